@@ -0,0 +1,60 @@
+// Criterion benchmarks of bus reads, the memory path of every instruction. Run them with:
+// cargo bench --bench bus
+//
+// Each iteration reads 4KB of a region, in order, like code and tables are read:
+//
+// | Benchmark | Region |
+// |---|---|
+// | nes/ram | Internal RAM, $0000-$0FFF (with the first mirror) |
+// | nes/rom | PRG ROM, $8000-$8FFF |
+// | flat/ram | `FlatBus`, $0000-$0FFF |
+//
+// The time of a read, when PRG ROM reads stopped dividing by its size (the banks are looked up instead), and
+// `FlatBus` stopped finding where every address is for the log (only when it's logged). Best of 5 runs, on a single
+// core VM, so only the difference means something:
+//
+// | Benchmark | Before | After |
+// |---|---|---|
+// | nes/ram | 2.0ns | 1.7ns (nothing changed, it's noise) |
+// | nes/rom | 4.7ns | 4.1ns |
+// | flat/ram | 3.7ns | 2.8ns |
+
+use criterion::{black_box, criterion_group, criterion_main, BenchmarkId, Criterion, Throughput};
+use rust_nes_emulator::{Bus, Cartridge, FlatBus, NesBus};
+
+/// Reads in every iteration.
+const READS: u16 = 0x1000;
+
+/// NROM with 32KB of PRG ROM, so every bank has something else.
+fn cartridge() -> Cartridge {
+	let prg: Vec<u8> = (0..0x8000).map(|i| (i * 7) as u8).collect();
+	let mut rom = vec![0x4E, 0x45, 0x53, 0x1A, 2, 1, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0];
+	rom.extend_from_slice(&prg);
+	rom.extend_from_slice(&[0; 0x2000]);
+	Cartridge::from_ines(&rom).unwrap()
+}
+
+fn read_region<B: Bus>(bus: &mut B, start: u16) -> u8 {
+	let mut sum: u8 = 0;
+	for addr in start..start + READS {
+		sum = sum.wrapping_add(bus.read(black_box(addr)));
+	}
+	sum
+}
+
+fn read(c: &mut Criterion) {
+	let mut group = c.benchmark_group("read");
+	group.throughput(Throughput::Elements(READS as u64));
+
+	let mut nes = NesBus::new(cartridge());
+	group.bench_function(BenchmarkId::new("nes", "ram"), |b| b.iter(|| read_region(&mut nes, 0x0000)));
+	group.bench_function(BenchmarkId::new("nes", "rom"), |b| b.iter(|| read_region(&mut nes, 0x8000)));
+
+	let mut flat = FlatBus::new(&[0x42; 65_536]);
+	group.bench_function(BenchmarkId::new("flat", "ram"), |b| b.iter(|| read_region(&mut flat, 0x0000)));
+
+	group.finish();
+}
+
+criterion_group!(benches, read);
+criterion_main!(benches);
@@ -0,0 +1,107 @@
+// Criterion benchmarks of the CPU hot path: fetch, decode, dispatch and execute. Run them with:
+// cargo bench --bench cpu
+//
+// They all run the same instruction mix: on a bare array (the CPU alone, for the dispatch and the instructions), on
+// `FlatBus` (the memory the demos run on), and in the console (through the NES bus, with the PPU and APU running
+// along). Compare with a baseline, to see what a change did:
+// cargo bench --bench cpu -- --save-baseline before
+// cargo bench --bench cpu -- --baseline before
+
+use criterion::{criterion_group, criterion_main, BenchmarkId, Criterion, Throughput};
+use rust_nes_emulator::{Bus, Cartridge, Emulator, FlatBus, CPU};
+
+/// Instructions in every iteration.
+const INSTRUCTIONS: u32 = 10_000;
+
+/// A mix of the common instructions: loads, stores, indexing, arithmetic, increments, compare and branch, and a PPU
+/// register read. The same as in src/bench.rs, at `origin`.
+fn instruction_mix(origin: u16) -> Vec<u8> {
+	/*
+	start:
+	LDX #$00
+	loop:
+	LDA $0200,X
+	CLC
+	ADC #$03
+	STA $0200,X
+	INC $10
+	INX
+	CPX #$80
+	BNE loop
+	BIT $2002
+	JMP start
+	*/
+	vec![
+		0xA2, 0x00, 0xBD, 0x00, 0x02, 0x18, 0x69, 0x03, 0x9D, 0x00, 0x02, 0xE6, 0x10, 0xE8, 0xE0, 0x80, 0xD0, 0xF0,
+		0x2C, 0x02, 0x20, 0x4C, origin as u8, (origin >> 8) as u8,
+	]
+}
+
+/// 64KB of RAM, and nothing else: no logging, no memory map.
+struct ArrayBus(Box<[u8; 65_536]>);
+
+impl Bus for ArrayBus {
+	fn read(&mut self, addr: u16) -> u8 {
+		self.0[addr as usize]
+	}
+
+	fn peek(&self, addr: u16) -> u8 {
+		self.0[addr as usize]
+	}
+
+	fn write(&mut self, addr: u16, data: u8) {
+		self.0[addr as usize] = data;
+	}
+}
+
+/// The program at $0600, where the reset vector points.
+fn memory() -> Box<[u8; 65_536]> {
+	let program = instruction_mix(0x0600);
+	let mut memory = Box::new([0; 65_536]);
+	memory[0x0600..0x0600 + program.len()].copy_from_slice(&program);
+	memory[0xFFFC] = 0x00;
+	memory[0xFFFD] = 0x06;
+	memory
+}
+
+fn cpu<B: Bus>(bus: B) -> CPU<B> {
+	let mut cpu = CPU::new(bus);
+	cpu.reset();
+	cpu
+}
+
+/// NROM: a 16KB PRG bank with the program at $8000, and 8KB of CHR.
+fn nes() -> Emulator {
+	let program = instruction_mix(0x8000);
+	let mut prg = vec![0xEA; 0x4000];
+	prg[..program.len()].copy_from_slice(&program);
+	prg[0x3FFC] = 0x00;
+	prg[0x3FFD] = 0x80;
+	let mut rom = vec![0x4E, 0x45, 0x53, 0x1A, 1, 1, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0];
+	rom.extend_from_slice(&prg);
+	rom.extend_from_slice(&[0; 0x2000]);
+	Emulator::new(Cartridge::from_ines(&rom).unwrap())
+}
+
+fn step(c: &mut Criterion) {
+	let mut group = c.benchmark_group("step");
+	group.throughput(Throughput::Elements(INSTRUCTIONS as u64));
+
+	let mut array = cpu(ArrayBus(memory()));
+	group.bench_function(BenchmarkId::new("instruction_mix", "array"), |b| b.iter(|| array.run(INSTRUCTIONS).unwrap()));
+
+	let mut flat = cpu(FlatBus::new(&memory()));
+	group.bench_function(BenchmarkId::new("instruction_mix", "flat"), |b| b.iter(|| flat.run(INSTRUCTIONS).unwrap()));
+
+	let mut emulator = nes();
+	group.bench_function(BenchmarkId::new("instruction_mix", "nes"), |b| b.iter(|| {
+		for _ in 0..INSTRUCTIONS {
+			emulator.step_instruction();
+		}
+	}));
+
+	group.finish();
+}
+
+criterion_group!(benches, step);
+criterion_main!(benches);
@@ -0,0 +1,23 @@
+// Generates include/nes.h, the C header of the `ffi` feature (src/ffi.rs). It's committed, so C frontends don't
+// need the Rust build for it.
+
+fn main() {
+	println!("cargo:rerun-if-changed=build.rs");
+
+	#[cfg(feature = "ffi")]
+	{
+		println!("cargo:rerun-if-changed=src/ffi.rs");
+		let crate_dir = std::env::var("CARGO_MANIFEST_DIR").unwrap();
+		cbindgen::Builder::new()
+			// First, it replaces the whole config.
+			.with_config(cbindgen::Config { usize_is_size_t: true, ..Default::default() })
+			// Only the C API, not every `pub const` of the crate.
+			.with_src(format!("{}/src/ffi.rs", crate_dir))
+			.with_language(cbindgen::Language::C)
+			.with_include_guard("NES_H")
+			.with_header("/* Generated by cbindgen from src/ffi.rs, don't edit. */")
+			.generate()
+			.expect("Can't generate the C header")
+			.write_to_file(format!("{}/include/nes.h", crate_dir));
+	}
+}
@@ -0,0 +1,79 @@
+/*
+ * Runs a ROM for some frames through the C API, and writes the last frame to a PPM image.
+ *
+ * cargo rustc --release --lib --features ffi --crate-type staticlib
+ * cc examples/c/render_frame.c -Iinclude target/release/librust_nes_emulator.a -lpthread -ldl -lm -o render_frame
+ * ./render_frame game.nes 60 frame.ppm
+ */
+
+#include <stdio.h>
+#include <stdlib.h>
+
+#include "nes.h"
+
+static uint8_t *read_file(const char *path, size_t *len) {
+	FILE *file = fopen(path, "rb");
+	if (!file) {
+		return NULL;
+	}
+	fseek(file, 0, SEEK_END);
+	long size = ftell(file);
+	fseek(file, 0, SEEK_SET);
+	uint8_t *bytes = malloc(size);
+	if (bytes && fread(bytes, 1, size, file) != (size_t)size) {
+		free(bytes);
+		bytes = NULL;
+	}
+	fclose(file);
+	*len = size;
+	return bytes;
+}
+
+int main(int argc, char **argv) {
+	if (argc != 4) {
+		fprintf(stderr, "Usage: %s <rom.nes> <frames> <out.ppm>\n", argv[0]);
+		return 1;
+	}
+
+	size_t rom_len;
+	uint8_t *rom = read_file(argv[1], &rom_len);
+	if (!rom) {
+		fprintf(stderr, "Can't read %s\n", argv[1]);
+		return 1;
+	}
+	NesHandle *nes = nes_create(rom, rom_len);
+	free(rom);
+	if (!nes) {
+		fprintf(stderr, "%s is not a valid iNES file\n", argv[1]);
+		return 1;
+	}
+
+	const uint32_t *pixels = NULL;
+	int frames = atoi(argv[2]);
+	for (int i = 0; i < frames; i++) {
+		/* Press Start for a frame, a second in. */
+		nes_set_input(nes, 0, i == 60 ? NES_BUTTON_START : 0);
+		pixels = nes_run_frame(nes);
+		if (!pixels) {
+			fprintf(stderr, "The emulator stopped at frame %d\n", i);
+			nes_destroy(nes);
+			return 1;
+		}
+	}
+
+	FILE *out = fopen(argv[3], "wb");
+	if (!out) {
+		fprintf(stderr, "Can't write %s\n", argv[3]);
+		nes_destroy(nes);
+		return 1;
+	}
+	fprintf(out, "P6\n%d %d\n255\n", NES_SCREEN_WIDTH, NES_SCREEN_HEIGHT);
+	for (int i = 0; pixels && i < NES_SCREEN_WIDTH * NES_SCREEN_HEIGHT; i++) {
+		uint8_t rgb[3] = { pixels[i] >> 16, pixels[i] >> 8, pixels[i] };
+		fwrite(rgb, 1, 3, out);
+	}
+	fclose(out);
+
+	nes_destroy(nes);
+	return 0;
+}
@@ -0,0 +1,102 @@
+// The CPU core without std, on a bus of its own: 2KB of RAM, and a program in ROM. No allocator, no logger.
+//
+// Build it for a Cortex-M0+ (like the RP2040):
+// rustup target add thumbv6m-none-eabi
+// cargo build --example no_std_cpu --no-default-features --target thumbv6m-none-eabi
+//
+// On the host it's a normal program, that prints what the 6502 computed:
+// cargo run --example no_std_cpu
+
+#![cfg_attr(target_os = "none", no_std)]
+#![cfg_attr(target_os = "none", no_main)]
+
+use rust_nes_emulator::{Bus, CPU};
+
+/*
+Fibonacci numbers to $00-$0C (the ones that fit in a byte).
+
+LDA #$01
+STA $00
+STA $01
+LDX #$00
+loop:
+LDA $00,X
+CLC
+ADC $01,X
+STA $02,X
+INX
+CPX #$0B
+BNE loop
+end:
+JMP end
+*/
+const PROGRAM: [u8; 23] = [
+	0xA9, 0x01, 0x85, 0x00, 0x85, 0x01, 0xA2, 0x00,
+	0xB5, 0x00, 0x18, 0x75, 0x01, 0x95, 0x02, 0xE8, 0xE0, 0x0B, 0xD0, 0xF4,
+	0x4C, 0x14, 0x80,
+];
+const ENTRY: u16 = 0x8000;
+const END: u16 = 0x8014;
+
+/// RAM at $0000-$07FF (mirrored up to $1FFF), the program at $8000, and the reset vector pointing to it.
+struct TinyBus {
+	ram: [u8; 0x800],
+	rom: &'static [u8],
+}
+
+impl Bus for TinyBus {
+	fn read(&mut self, addr: u16) -> u8 {
+		self.peek(addr)
+	}
+
+	fn peek(&self, addr: u16) -> u8 {
+		match addr {
+			0x0000..=0x1FFF => self.ram[(addr & 0x07FF) as usize],
+			0xFFFC => ENTRY as u8,
+			0xFFFD => (ENTRY >> 8) as u8,
+			// NOP after the end of the program.
+			0x8000..=0xFFFF => self.rom.get((addr - 0x8000) as usize).copied().unwrap_or(0xEA),
+			_ => 0,
+		}
+	}
+
+	fn write(&mut self, addr: u16, data: u8) {
+		if addr < 0x2000 {
+			self.ram[(addr & 0x07FF) as usize] = data;
+		}
+	}
+}
+
+/// Run the program to its end, and return the numbers.
+fn fibonacci() -> [u8; 13] {
+	let mut cpu = CPU::new(TinyBus { ram: [0; 0x800], rom: &PROGRAM });
+	cpu.reset();
+	while cpu.registers().PC != END {
+		cpu.clock_tick();
+	}
+
+	let mut numbers = [0; 13];
+	numbers.copy_from_slice(&cpu.bus().ram[..13]);
+	numbers
+}
+
+#[cfg(not(target_os = "none"))]
+fn main() {
+	println!("{:?}", fibonacci());
+}
+
+/// There is no runtime, so this is where the board starts. A real board would show the numbers on its display.
+#[cfg(target_os = "none")]
+#[no_mangle]
+pub extern "C" fn _start() -> ! {
+	let numbers = fibonacci();
+	loop {
+		core::hint::black_box(&numbers);
+	}
+}
+
+#[cfg(target_os = "none")]
+#[panic_handler]
+fn panic(_info: &core::panic::PanicInfo) -> ! {
+	loop {}
+}
@@ -0,0 +1,2 @@
+// Everything is in the emulator crate (src/wasm.rs), this only makes it a cdylib for wasm-pack.
+pub use rust_nes_emulator::wasm::WasmNes;
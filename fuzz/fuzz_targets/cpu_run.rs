@@ -0,0 +1,42 @@
+// Run any program on a flat 64KB bus, like `CPU::run`, but check the CPU after every instruction: it may stop on an
+// error (illegal or unimplemented opcode), and then it's left as it was. Otherwise every instruction takes 2 to 7
+// cycles. The program is at $0600 (like `write_rom`), and it's repeated in the zero page and the stack, so the indirect
+// addressing modes and RTI point anywhere.
+#![no_main]
+
+use libfuzzer_sys::fuzz_target;
+use rust_nes_emulator::memory::PROGRAM_START;
+use rust_nes_emulator::{FlatBus, CPU};
+
+const INSTRUCTIONS: u32 = 10_000;
+
+fuzz_target!(|data: &[u8]| {
+	if data.is_empty() {
+		return;
+	}
+	let mut image = [0; 65_536];
+	let start = PROGRAM_START as usize;
+	let program = &data[..data.len().min(0x10000 - start)];
+	image[start..start + program.len()].copy_from_slice(program);
+	for (i, byte) in data.iter().cycle().take(0x200).enumerate() {
+		image[i] = *byte;
+	}
+	image[0xFFFC] = PROGRAM_START as u8;
+	image[0xFFFD] = (PROGRAM_START >> 8) as u8;
+
+	let mut cpu = CPU::new(FlatBus::new(&image));
+	cpu.reset();
+	for _ in 0..INSTRUCTIONS {
+		let before = cpu.state();
+		match cpu.step() {
+			Ok(cycles) => {
+				assert!((2..=7).contains(&cycles), "{} cycles at {}", cycles, before);
+				assert_eq!(cpu.cycles(), before.cycles + cycles as u64);
+			}
+			Err(err) => {
+				assert_eq!(cpu.state(), before, "{}", err);
+				return;
+			}
+		}
+	}
+});
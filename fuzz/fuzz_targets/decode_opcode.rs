@@ -0,0 +1,33 @@
+// Decode and disassemble any memory. Illegal opcodes are None (and `.byte`), nothing panics, and the lengths agree.
+#![no_main]
+
+use libfuzzer_sys::fuzz_target;
+use rust_nes_emulator::cpu::disassembler::disassemble;
+use rust_nes_emulator::{decode_opcode, AddressingMode};
+
+fuzz_target!(|data: &[u8]| {
+	if data.is_empty() {
+		return;
+	}
+	// The data is the whole address space, repeated.
+	let peek = |addr: u16| data[addr as usize % data.len()];
+
+	let mut addr: u16 = 0;
+	for _ in 0..data.len().min(0x10000) {
+		let disassembly = disassemble(addr, peek);
+		match decode_opcode(peek(addr)) {
+			Some((_, mode, bytes, cycles, _)) => {
+				let expected = match mode {
+					AddressingMode::IMPLIED | AddressingMode::ACCUMULATOR => 1,
+					AddressingMode::ABSOLUTE | AddressingMode::ABSOLUTEX | AddressingMode::ABSOLUTEY | AddressingMode::INDIRECT => 3,
+					_ => 2,
+				};
+				assert_eq!(bytes, expected, "{}", disassembly);
+				assert!((2..=7).contains(&cycles), "{}", disassembly);
+				assert_eq!(disassembly.bytes.len(), bytes as usize);
+			}
+			None => assert!(disassembly.text.starts_with(".byte"), "{}", disassembly),
+		}
+		addr = disassembly.next_addr();
+	}
+});
@@ -0,0 +1,21 @@
+// Load any file as an iNES cartridge. It's either an error, or a cartridge that powers on, and whose state loads back.
+#![no_main]
+
+use libfuzzer_sys::fuzz_target;
+use rust_nes_emulator::{Cartridge, Emulator};
+
+fuzz_target!(|data: &[u8]| {
+	let Ok(cartridge) = Cartridge::from_ines(data) else {
+		return;
+	};
+	// The whole cartridge space reads, whatever the PRG ROM size.
+	for addr in 0x4020..=0xFFFF {
+		cartridge.cpu_read(addr);
+	}
+
+	let mut emulator = Emulator::new(cartridge);
+	let state = emulator.save_state();
+	emulator.load_state(&state).unwrap();
+	// And any state is either loaded, or an error.
+	let _ = emulator.load_state(data);
+});
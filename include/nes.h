@@ -0,0 +1,117 @@
+/* Generated by cbindgen from src/ffi.rs, don't edit. */
+
+#ifndef NES_H
+#define NES_H
+
+#include <stdarg.h>
+#include <stdbool.h>
+#include <stddef.h>
+#include <stdint.h>
+#include <stdlib.h>
+
+#define NES_OK 0
+
+/**
+ * A NULL handle or buffer.
+ */
+#define NES_ERROR_NULL -1
+
+/**
+ * The emulator panicked, in this call or before.
+ */
+#define NES_ERROR_PANIC -2
+
+/**
+ * There is no controller in the port.
+ */
+#define NES_ERROR_INVALID_PORT -3
+
+/**
+ * The state is not valid, or it's of another game.
+ */
+#define NES_ERROR_INVALID_STATE -4
+
+/**
+ * Buttons for `nes_set_input`.
+ */
+#define NES_BUTTON_A (1 << 0)
+
+#define NES_BUTTON_B (1 << 1)
+
+#define NES_BUTTON_SELECT (1 << 2)
+
+#define NES_BUTTON_START (1 << 3)
+
+#define NES_BUTTON_UP (1 << 4)
+
+#define NES_BUTTON_DOWN (1 << 5)
+
+#define NES_BUTTON_LEFT (1 << 6)
+
+#define NES_BUTTON_RIGHT (1 << 7)
+
+#define NES_SCREEN_WIDTH 256
+
+#define NES_SCREEN_HEIGHT 240
+
+/**
+ * The console, opaque to C.
+ */
+typedef struct NesHandle NesHandle;
+
+/**
+ * Insert the cartridge (the bytes of an iNES file) and power on. Returns NULL if the ROM is not valid.
+ *
+ * # Safety
+ * `rom` must point to `rom_len` readable bytes. They are copied, so they can be freed after the call.
+ */
+struct NesHandle *nes_create(const uint8_t *rom,
+                             size_t rom_len);
+
+/**
+ * Free the handle. NULL is ignored.
+ *
+ * # Safety
+ * `handle` must be NULL or from `nes_create`, and not used after the call.
+ */
+void nes_destroy(struct NesHandle *handle);
+
+/**
+ * Run a frame, and return its pixels: `NES_SCREEN_WIDTH * NES_SCREEN_HEIGHT` of 0x00RRGGBB, row by row. They are
+ * owned by the handle, and valid until the next call with it. Returns NULL on errors.
+ *
+ * # Safety
+ * `handle` must be NULL or from `nes_create`.
+ */
+const uint32_t *nes_run_frame(struct NesHandle *handle);
+
+/**
+ * Set the buttons (`NES_BUTTON_*`) of the controller in `port`, 0 for controller 1 and 1 for controller 2. Call it before every frame.
+ *
+ * # Safety
+ * `handle` must be NULL or from `nes_create`.
+ */
+int nes_set_input(struct NesHandle *handle,
+                  uint32_t port,
+                  uint8_t buttons);
+
+/**
+ * Save the state to `buffer`, and return its size. The state is written only if it fits in `len` bytes, so call it
+ * with NULL first for the size. Returns 0 on errors.
+ *
+ * # Safety
+ * `handle` must be NULL or from `nes_create`. `buffer` must be NULL, or point to `len` writable bytes.
+ */
+size_t nes_save_state(struct NesHandle *handle,
+                      uint8_t *buffer,
+                      size_t len);
+
+/**
+ * Load a state from `nes_save_state`. An invalid state leaves the emulator as it was.
+ *
+ * # Safety
+ * `handle` must be NULL or from `nes_create`. `buffer` must point to `len` readable bytes.
+ */
+int nes_load_state(struct NesHandle *handle, const uint8_t *buffer, size_t len);
+
+#endif  /* NES_H */
@@ -0,0 +1,233 @@
+// Memory access trace: a record of every read and write in the address ranges you ask for, with the instruction
+// that did it. For questions like "who is writing to $0200-$02FF?".
+//
+// | Field | Description |
+// |---|---|
+// | pc | Address of the instruction that accessed the memory |
+// | addr | The address read or written |
+// | value | The byte read or written |
+// | kind | Read or write |
+// | cycle | CPU cycles since power on, at the start of the instruction |
+//
+// The CPU tells the bus where each instruction starts (`Bus::instruction_start`), and the bus passes it on to the
+// `AccessTracer`, which then has the PC for the accesses that follow. `FlatBus` and `NesBus` have `trace_accesses`.
+// Only the accesses of the CPU are recorded, not the ones of the debugger (`peek`), DMA, or the PPU.
+//
+// The buses keep the tracer in an `Option`, which is None until the first range is added, so without a trace the
+// cost is a single check of None per access.
+//
+// Records go to an `AccessSink`: a `SharedAccessLog` (a Vec that you keep a handle to, for tests), or an
+// `AccessWriter` (a line per access, for long runs):
+//
+// 0602  W $0200 = $01  CYC:9
+
+use std::fmt;
+use std::io::{self, BufWriter, Write};
+use std::ops::RangeInclusive;
+use std::sync::{Arc, Mutex};
+
+use log::error;
+
+use crate::log_target::BUS;
+
+pub use crate::cpu::effects::AccessKind;
+
+/// Which kinds of access to record.
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub struct AccessKinds {
+	pub reads: bool,
+	pub writes: bool,
+}
+
+impl AccessKinds {
+	pub const READS: AccessKinds = AccessKinds { reads: true, writes: false };
+	pub const WRITES: AccessKinds = AccessKinds { reads: false, writes: true };
+	pub const ALL: AccessKinds = AccessKinds { reads: true, writes: true };
+
+	pub fn contains(&self, kind: AccessKind) -> bool {
+		match kind {
+			AccessKind::Read => self.reads,
+			AccessKind::Write => self.writes,
+		}
+	}
+}
+
+/// A single read or write.
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub struct Access {
+	/// The instruction that accessed the memory.
+	pub pc: u16,
+	pub addr: u16,
+	pub value: u8,
+	pub kind: AccessKind,
+	/// CPU cycles since power on, at the start of the instruction.
+	pub cycle: u64,
+}
+
+/// `0602  W $0200 = $01  CYC:9`
+impl fmt::Display for Access {
+	fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+		let kind = match self.kind {
+			AccessKind::Read => 'R',
+			AccessKind::Write => 'W',
+		};
+		write!(f, "{:04X}  {} ${:04X} = ${:02X}  CYC:{}", self.pc, kind, self.addr, self.value, self.cycle)
+	}
+}
+
+/// Where the records go. `Send`, so the bus can still move to another thread.
+pub trait AccessSink: Send {
+	fn record(&mut self, access: &Access);
+}
+
+/// The records in a Vec. It's shared: clone it before giving it to the bus, and read the records from the clone.
+#[derive(Clone, Default)]
+pub struct SharedAccessLog(Arc<Mutex<Vec<Access>>>);
+
+impl SharedAccessLog {
+	pub fn new() -> Self {
+		Self::default()
+	}
+
+	/// The records so far, oldest first.
+	pub fn accesses(&self) -> Vec<Access> {
+		self.0.lock().unwrap().clone()
+	}
+}
+
+impl AccessSink for SharedAccessLog {
+	fn record(&mut self, access: &Access) {
+		self.0.lock().unwrap().push(*access);
+	}
+}
+
+/// A line for each record (see `Access`'s `Display`). After the first error it stops writing, and logs it.
+pub struct AccessWriter {
+	out: BufWriter<Box<dyn Write + Send>>,
+	failed: bool,
+}
+
+impl AccessWriter {
+	pub fn new(out: Box<dyn Write + Send>) -> Self {
+		AccessWriter { out: BufWriter::new(out), failed: false }
+	}
+
+	pub fn flush(&mut self) -> io::Result<()> {
+		self.out.flush()
+	}
+}
+
+impl AccessSink for AccessWriter {
+	fn record(&mut self, access: &Access) {
+		if self.failed {
+			return;
+		}
+		if let Err(err) = writeln!(self.out, "{}", access) {
+			error!(target: BUS, "Failed to write the access trace, stopping it: {}", err);
+			self.failed = true;
+		}
+	}
+}
+
+impl Drop for AccessWriter {
+	fn drop(&mut self) {
+		// Nowhere to report it.
+		let _ = self.out.flush();
+	}
+}
+
+struct TracedRange {
+	range: RangeInclusive<u16>,
+	kinds: AccessKinds,
+	sink: Box<dyn AccessSink>,
+}
+
+/// The ranges to trace, and the instruction being executed. Owned by the bus.
+#[derive(Default)]
+pub struct AccessTracer {
+	ranges: Vec<TracedRange>,
+	pc: u16,
+	cycle: u64,
+}
+
+impl AccessTracer {
+	/// Send the accesses of `kinds` in `range` to `sink`. Ranges may overlap, then each sink gets the access.
+	pub fn add(&mut self, range: RangeInclusive<u16>, kinds: AccessKinds, sink: Box<dyn AccessSink>) {
+		self.ranges.push(TracedRange { range, kinds, sink });
+	}
+
+	/// The CPU starts executing the instruction at `pc`.
+	pub fn instruction_start(&mut self, pc: u16, cycle: u64) {
+		self.pc = pc;
+		self.cycle = cycle;
+	}
+
+	pub fn record(&mut self, addr: u16, value: u8, kind: AccessKind) {
+		let access = Access { pc: self.pc, addr, value, kind, cycle: self.cycle };
+		for traced in self.ranges.iter_mut() {
+			if traced.range.contains(&addr) && traced.kinds.contains(kind) {
+				traced.sink.record(&access);
+			}
+		}
+	}
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+	use crate::bus::{Bus, FlatBus};
+	use crate::cpu::cpu::CPU;
+	use crate::program_loader::load_program_helloworld;
+
+	#[test]
+	fn helloworld_test() {
+		let mut memory = [0; 65_536];
+		let instructions = load_program_helloworld(&mut memory);
+		let mut cpu = CPU::new(FlatBus::new(&memory));
+		cpu.reset();
+
+		let writes = SharedAccessLog::new();
+		let reads = SharedAccessLog::new();
+		cpu.bus_mut().trace_accesses(0x0200..=0x02FF, AccessKinds::ALL, Box::new(writes.clone()));
+		// Only the reads of the first instruction, LDA #$01.
+		cpu.bus_mut().trace_accesses(0x0600..=0x0601, AccessKinds::READS, Box::new(reads.clone()));
+		for _ in 0..instructions {
+			cpu.clock_tick();
+		}
+
+		let start = cpu.state().cycles - 18;
+		assert_eq!(writes.accesses(), [
+			Access { pc: 0x0602, addr: 0x0200, value: 0x01, kind: AccessKind::Write, cycle: start + 2 },
+			Access { pc: 0x0607, addr: 0x0201, value: 0x05, kind: AccessKind::Write, cycle: start + 8 },
+			Access { pc: 0x060C, addr: 0x0202, value: 0x08, kind: AccessKind::Write, cycle: start + 14 },
+		]);
+		let read_addrs: Vec<(u16, u16, u8)> = reads.accesses().iter().map(|access| (access.pc, access.addr, access.value)).collect();
+		assert_eq!(read_addrs, [(0x0600, 0x0600, 0xA9), (0x0600, 0x0601, 0x01)]);
+		assert_eq!(cpu.bus().peek(0x0202), 0x08);
+	}
+
+	#[test]
+	fn access_writer_test() {
+		#[derive(Clone, Default)]
+		struct Buffer(Arc<Mutex<Vec<u8>>>);
+		impl Write for Buffer {
+			fn write(&mut self, data: &[u8]) -> io::Result<usize> {
+				self.0.lock().unwrap().write(data)
+			}
+			fn flush(&mut self) -> io::Result<()> {
+				Ok(())
+			}
+		}
+
+		let buffer = Buffer::default();
+		let mut tracer = AccessTracer::default();
+		tracer.add(0x0200..=0x02FF, AccessKinds::WRITES, Box::new(AccessWriter::new(Box::new(buffer.clone()))));
+		tracer.instruction_start(0x0602, 9);
+		tracer.record(0x0200, 0x01, AccessKind::Write);
+		tracer.record(0x0200, 0x01, AccessKind::Read);
+		tracer.record(0x0300, 0x01, AccessKind::Write);
+		// Dropping the tracer flushes the writer.
+		drop(tracer);
+		assert_eq!(String::from_utf8(buffer.0.lock().unwrap().clone()).unwrap(), "0602  W $0200 = $01  CYC:9\n");
+	}
+}
@@ -0,0 +1,708 @@
+// APU registers: https://www.nesdev.org/wiki/APU
+//
+// | Address range | Channel |
+// |---|---|
+// | $4000 - $4003 | Pulse 1 |
+// | $4004 - $4007 | Pulse 2 |
+// | $4008 - $400B | Triangle |
+// | $400C - $400F | Noise |
+// | $4010 - $4013 | DMC |
+// | $4015 | Channel enable (write), status (read) |
+// | $4017 | Frame counter |
+//
+// Some Famicom cartridges have sound channels of their own (VRC6, and the FDS), which the console mixes with these:
+// the bus gives their level every cycle (`set_expansion`), and the mixer adds it, times the expansion volume.
+//
+// For debugging music, the channels can be left out of the mix (`set_channel_mask`): they keep running, their length
+// counters too, and $4015 sees them, only the sound is gone. And every channel can be mixed alone, into a buffer of
+// its own (`capture_channels`), to record one WAV per channel from a single run. Neither is in save states.
+
+use log::debug;
+
+use super::dmc::DMC;
+use super::frame_counter::{FrameClock, FrameCounter};
+use super::noise::Noise;
+use super::pulse::Pulse;
+use super::sweep::PulseChannel;
+use super::sample_buffer::SampleBuffer;
+use super::triangle::Triangle;
+use crate::irq::{IrqLine, IrqSource};
+use crate::log_target::APU;
+use crate::region::Region;
+use crate::save_state::{SaveState, StateReader, StateWriter};
+
+const DEFAULT_SAMPLE_RATE: u32 = 48_000;
+/// About a third of a second at 48KHz. The frontend should drain it every frame.
+const SAMPLE_BUFFER_CAPACITY: usize = 16 * 1024;
+
+/// The channels of the APU, a bit each in the channel mask (`APU::set_channel_mask`), like in $4015.
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub enum Channel {
+	Pulse1 = 0b00001,
+	Pulse2 = 0b00010,
+	Triangle = 0b00100,
+	Noise = 0b01000,
+	Dmc = 0b10000,
+}
+
+impl Channel {
+	pub const ALL: [Channel; 5] = [Channel::Pulse1, Channel::Pulse2, Channel::Triangle, Channel::Noise, Channel::Dmc];
+	/// The mask of all the channels, the default.
+	pub const ALL_MASK: u8 = 0b11111;
+
+	pub fn mask(self) -> u8 {
+		self as u8
+	}
+
+	/// Like `pulse1`, for file names.
+	pub fn name(self) -> &'static str {
+		match self {
+			Channel::Pulse1 => "pulse1",
+			Channel::Pulse2 => "pulse2",
+			Channel::Triangle => "triangle",
+			Channel::Noise => "noise",
+			Channel::Dmc => "dmc",
+		}
+	}
+
+	fn index(self) -> usize {
+		self.mask().trailing_zeros() as usize
+	}
+}
+
+/// The channels mixed alone, downsampled like the mix, see `APU::capture_channels`.
+struct ChannelCapture {
+	sums: [f32; 5],
+	buffers: [SampleBuffer; 5],
+}
+
+#[cfg(feature = "serde")]
+fn all_channels() -> u8 {
+	Channel::ALL_MASK
+}
+
+/// Audio processing unit.
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct APU {
+	region: Region,
+	pulse1: Pulse,
+	pulse2: Pulse,
+	triangle: Triangle,
+	noise: Noise,
+	dmc: DMC,
+	frame_counter: FrameCounter,
+	/// The channel timers are clocked every other CPU cycle.
+	odd_cycle: bool,
+	/// The level of the sound channels of the cartridge, not part of the state: the bus sets it before every cycle.
+	expansion: f32,
+	/// A setting, 1.0 by default.
+	expansion_volume: f32,
+	/// The channels in the mix, a setting: all by default.
+	#[cfg_attr(feature = "serde", serde(skip, default = "all_channels"))]
+	channel_mask: u8,
+	#[cfg_attr(feature = "serde", serde(skip))]
+	channel_capture: Option<Box<ChannelCapture>>,
+
+	/// Output sample rate, in Hz.
+	sample_rate: u32,
+	/// Not part of the state: a deserialized APU has a new, empty buffer, so the frontend needs `sample_buffer()` again.
+	#[cfg_attr(feature = "serde", serde(skip, default = "new_sample_buffer"))]
+	samples: SampleBuffer,
+	/// Downsampling: sum of the mixer output since the last sample.
+	sample_sum: f32,
+	sample_count: u32,
+	/// Goes up by the sample rate every CPU cycle. A sample is made every time it passes the CPU clock rate.
+	sample_clock: u64,
+}
+
+fn new_sample_buffer() -> SampleBuffer {
+	SampleBuffer::new(SAMPLE_BUFFER_CAPACITY)
+}
+
+impl Default for APU {
+	fn default() -> Self {
+		Self::new()
+	}
+}
+
+impl APU {
+	pub fn new() -> Self {
+		APU {
+			region: Region::Ntsc,
+			pulse1: Pulse::new(PulseChannel::One),
+			pulse2: Pulse::new(PulseChannel::Two),
+			triangle: Triangle::new(),
+			noise: Noise::new(),
+			dmc: DMC::new(),
+			frame_counter: FrameCounter::new(),
+			odd_cycle: false,
+			expansion: 0.0,
+			expansion_volume: 1.0,
+			channel_mask: Channel::ALL_MASK,
+			channel_capture: None,
+			sample_rate: DEFAULT_SAMPLE_RATE,
+			samples: new_sample_buffer(),
+			sample_sum: 0.0,
+			sample_count: 0,
+			sample_clock: 0,
+		}
+	}
+
+	/// Frame counter steps, noise and DMC periods, and the CPU clock rate (for the sample rate) depend on the region.
+	pub fn set_region(&mut self, region: Region) {
+		self.region = region;
+		self.noise.set_region(region);
+		self.dmc.set_region(region);
+		self.frame_counter.set_region(region);
+	}
+
+	/// The level of the sound channels of the cartridge, for the next cycles. See `Cartridge::audio_level`.
+	pub fn set_expansion(&mut self, level: f32) {
+		self.expansion = level;
+	}
+
+	/// How loud the sound channels of the cartridge are, 1.0 like on a Famicom. 0.0 mutes them.
+	pub fn set_expansion_volume(&mut self, volume: f32) {
+		self.expansion_volume = volume;
+	}
+
+	pub fn expansion_volume(&self) -> f32 {
+		self.expansion_volume
+	}
+
+	/// The channels in the mix, a bit each (see `Channel`). The others keep running, they are just not heard.
+	pub fn set_channel_mask(&mut self, mask: u8) {
+		self.channel_mask = mask & Channel::ALL_MASK;
+	}
+
+	pub fn channel_mask(&self) -> u8 {
+		self.channel_mask
+	}
+
+	/// Mix every channel alone too, into a buffer of its own, from now on: see `channel_sample_buffer`. Whether they
+	/// are muted or not.
+	pub fn capture_channels(&mut self, capture: bool) {
+		self.channel_capture = capture.then(|| Box::new(ChannelCapture { sums: [0.0; 5], buffers: core::array::from_fn(|_| new_sample_buffer()) }));
+	}
+
+	/// The samples of `channel` alone, like `sample_buffer`. None without `capture_channels`.
+	pub fn channel_sample_buffer(&self, channel: Channel) -> Option<SampleBuffer> {
+		self.channel_capture.as_ref().map(|capture| capture.buffers[channel.index()].clone())
+	}
+
+	/// Only $4015 is readable.
+	pub fn cpu_read(&mut self, addr: u16) -> u8 {
+		match addr {
+			0x4015 => {
+				let status = self.status();
+				// Reading acknowledges the frame interrupt, but not the DMC interrupt.
+				self.frame_counter.clear_irq();
+				status
+			}
+			_ => {
+				debug!(target: APU, "Reading from write only APU register, address: {:#X}", addr);
+				0
+			}
+		}
+	}
+
+	/// The value of $4015, without the side effects of reading it.
+	///
+	/// | Bit | Description |
+	/// |---|---|
+	/// | 7 | DMC interrupt |
+	/// | 6 | Frame interrupt |
+	/// | 5 | Open bus (always 0 here) |
+	/// | 4 | DMC bytes remaining > 0 |
+	/// | 3 - 0 | Length counter > 0: noise, triangle, pulse 2, pulse 1 |
+	pub fn status(&self) -> u8 {
+		(self.pulse1.active() as u8)
+			| (self.pulse2.active() as u8) << 1
+			| (self.triangle.active() as u8) << 2
+			| (self.noise.active() as u8) << 3
+			| (self.dmc.active() as u8) << 4
+			| (self.frame_counter.irq() as u8) << 6
+			| (self.dmc.irq() as u8) << 7
+	}
+
+	pub fn cpu_write(&mut self, addr: u16, data: u8) {
+		match addr {
+			0x4000..=0x4003 => self.pulse1.write(addr - 0x4000, data),
+			0x4004..=0x4007 => self.pulse2.write(addr - 0x4004, data),
+			0x4008..=0x400B => self.triangle.write(addr - 0x4008, data),
+			0x400C..=0x400F => self.noise.write(addr - 0x400C, data),
+			0x4010..=0x4013 => self.dmc.write(addr - 0x4010, data),
+			0x4015 => {
+				self.pulse1.set_enabled(data & 0x01 != 0);
+				self.pulse2.set_enabled(data & 0x02 != 0);
+				self.triangle.set_enabled(data & 0x04 != 0);
+				self.noise.set_enabled(data & 0x08 != 0);
+				self.dmc.set_enabled(data & 0x10 != 0);
+			}
+			0x4017 => self.frame_counter.write(data, self.odd_cycle),
+			_ => debug!(target: APU, "Writing to APU register is not implemented, address: {:#X}, data: {:#X}", addr, data),
+		}
+	}
+
+	/// Run the APU for `cycles` CPU cycles.
+	pub fn tick(&mut self, cycles: u8) {
+		for _ in 0..cycles {
+			self.clock();
+		}
+	}
+
+	fn clock(&mut self) {
+		self.triangle.clock_timer();
+		self.dmc.clock_timer();
+		if self.odd_cycle {
+			self.pulse1.clock_timer();
+			self.pulse2.clock_timer();
+			self.noise.clock_timer();
+		}
+		self.odd_cycle = !self.odd_cycle;
+
+		let clock = self.frame_counter.clock();
+		self.clock_frame(clock);
+		self.pulse1.end_cycle();
+		self.pulse2.end_cycle();
+		self.triangle.end_cycle();
+		self.noise.end_cycle();
+
+		self.clock_sample();
+	}
+
+	/// Downsample by averaging all the mixer outputs between two samples.
+	/// The APU produces a sample every CPU cycle, which is way more than the sound card wants.
+	fn clock_sample(&mut self) {
+		self.sample_sum += self.output();
+		self.sample_count += 1;
+		if let Some(capture) = self.channel_capture.as_mut() {
+			let levels = [self.pulse1.output(), self.pulse2.output(), self.triangle.output(), self.noise.output(), self.dmc.output()];
+			for (i, &level) in levels.iter().enumerate() {
+				let mut alone = [0; 5];
+				alone[i] = level;
+				capture.sums[i] += mix(alone);
+			}
+		}
+
+		self.sample_clock += self.sample_rate as u64;
+		let cpu_clock_rate = self.region.cpu_clock_rate();
+		if self.sample_clock >= cpu_clock_rate {
+			self.sample_clock -= cpu_clock_rate;
+			self.samples.push(self.sample_sum / self.sample_count as f32);
+			if let Some(capture) = self.channel_capture.as_mut() {
+				for (buffer, sum) in capture.buffers.iter().zip(capture.sums.iter_mut()) {
+					buffer.push(*sum / self.sample_count as f32);
+					*sum = 0.0;
+				}
+			}
+			self.sample_sum = 0.0;
+			self.sample_count = 0;
+		}
+	}
+
+	/// Set the output sample rate, in Hz. 44100 or 48000, usually.
+	pub fn set_sample_rate(&mut self, sample_rate: u32) {
+		self.sample_rate = sample_rate;
+		self.sample_sum = 0.0;
+		self.sample_count = 0;
+		self.sample_clock = 0;
+	}
+
+	pub fn sample_rate(&self) -> u32 {
+		self.sample_rate
+	}
+
+	/// Move all the samples made since the last call to the end of `out`.
+	pub fn take_samples(&mut self, out: &mut Vec<f32>) {
+		self.samples.take(out);
+	}
+
+	/// Another handle to the samples, for an audio callback running on another thread.
+	pub fn sample_buffer(&self) -> SampleBuffer {
+		self.samples.clone()
+	}
+
+	fn clock_frame(&mut self, clock: FrameClock) {
+		if clock.quarter {
+			self.pulse1.quarter_frame();
+			self.pulse2.quarter_frame();
+			self.triangle.quarter_frame();
+			self.noise.quarter_frame();
+		}
+		if clock.half {
+			self.pulse1.half_frame();
+			self.pulse2.half_frame();
+			self.triangle.half_frame();
+			self.noise.half_frame();
+		}
+	}
+
+	/// The frame counter and the DMC, on the IRQ line. Each lets go of it by itself.
+	pub fn irq_line(&self) -> IrqLine {
+		let mut line = IrqLine::default();
+		line.set(IrqSource::FRAME_COUNTER, self.frame_counter.irq());
+		line.set(IrqSource::DMC, self.dmc.irq());
+		line
+	}
+
+	/// The address the DMC wants to read from CPU memory, if any. See `DMC::dma_request`.
+	pub fn dmc_dma_request(&self) -> Option<u16> {
+		self.dmc.dma_request()
+	}
+
+	pub fn dmc_dma_complete(&mut self, data: u8) {
+		self.dmc.dma_complete(data);
+	}
+
+	pub fn pulse1_output(&self) -> u8 {
+		self.pulse1.output()
+	}
+
+	pub fn pulse2_output(&self) -> u8 {
+		self.pulse2.output()
+	}
+
+	pub fn triangle_output(&self) -> u8 {
+		self.triangle.output()
+	}
+
+	pub fn noise_output(&self) -> u8 {
+		self.noise.output()
+	}
+
+	pub fn dmc_output(&self) -> u8 {
+		self.dmc.output()
+	}
+
+	pub fn pulse1_period(&self) -> u16 {
+		self.pulse1.timer_period()
+	}
+
+	pub fn pulse2_period(&self) -> u16 {
+		self.pulse2.timer_period()
+	}
+
+	/// Mix the channels of the mask into a single sample, 0.0 - 1.0 (more with the channels of the cartridge, which
+	/// are added).
+	pub fn output(&self) -> f32 {
+		let levels = [self.pulse1.output(), self.pulse2.output(), self.triangle.output(), self.noise.output(), self.dmc.output()];
+		let levels = Channel::ALL.map(|channel| if self.channel_mask & channel.mask() != 0 { levels[channel.index()] } else { 0 });
+		mix(levels) + self.expansion * self.expansion_volume
+	}
+}
+
+/// The levels of the channels, in the order of `Channel::ALL`, into a sample.
+/// Uses the non linear formula from: https://www.nesdev.org/wiki/APU_Mixer
+pub fn mix(levels: [u8; 5]) -> f32 {
+	let [pulse1, pulse2, triangle, noise, dmc] = levels;
+	let pulse = (pulse1 + pulse2) as f32;
+	let pulse_out = if pulse == 0.0 { 0.0 } else { 95.88 / (8128.0 / pulse + 100.0) };
+
+	let tnd = triangle as f32 / 8227.0 + noise as f32 / 12241.0 + dmc as f32 / 22638.0;
+	let tnd_out = if tnd == 0.0 { 0.0 } else { 159.79 / (1.0 / tnd + 100.0) };
+
+	pulse_out + tnd_out
+}
+
+impl SaveState for APU {
+	fn save_state(&self, out: &mut StateWriter) {
+		self.pulse1.save_state(out);
+		self.pulse2.save_state(out);
+		self.triangle.save_state(out);
+		self.noise.save_state(out);
+		self.dmc.save_state(out);
+		self.frame_counter.save_state(out);
+		out.bool(self.odd_cycle);
+		out.f32(self.sample_sum);
+		out.u32(self.sample_count);
+		out.u64(self.sample_clock);
+	}
+
+	fn load_state(&mut self, input: &mut StateReader) -> Result<(), String> {
+		self.pulse1.load_state(input)?;
+		self.pulse2.load_state(input)?;
+		self.triangle.load_state(input)?;
+		self.noise.load_state(input)?;
+		self.dmc.load_state(input)?;
+		self.frame_counter.load_state(input)?;
+		self.odd_cycle = input.bool()?;
+		self.sample_sum = input.f32()?;
+		self.sample_count = input.u32()?;
+		self.sample_clock = input.u64()?;
+		Ok(())
+	}
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+	use crate::apu::length_counter::LENGTH_TABLE;
+
+	const FOUR_STEP_LENGTH: usize = 29830;
+
+	/// Tick and record the output of pulse 1 every CPU cycle.
+	fn record_pulse1(apu: &mut APU, cycles: usize) -> Vec<u8> {
+		(0..cycles).map(|_| {
+			apu.tick(1);
+			apu.pulse1_output()
+		}).collect()
+	}
+
+	#[test]
+	fn pulse_waveform_test() {
+		let mut apu = APU::new();
+		apu.cpu_write(0x4015, 0x01);
+		apu.cpu_write(0x4000, 0b1011_1111);	// 50% duty, halt length counter, constant volume 15
+		apu.cpu_write(0x4002, 99);			// Timer period 99: a step every 100 APU cycles = 200 CPU cycles
+		apu.cpu_write(0x4003, 0b0000_1000);	// Length counter load, period high bits 0
+
+		let samples = record_pulse1(&mut apu, 200 * 16);
+
+		// Find the cycles in which the output changed.
+		let toggles: Vec<usize> = (1..samples.len()).filter(|&i| samples[i] != samples[i - 1]).collect();
+		assert!(toggles.len() >= 3, "toggles: {:?}", toggles);
+
+		// 50% duty: 4 steps high, 4 steps low.
+		for pair in toggles.windows(2) {
+			assert_eq!(pair[1] - pair[0], 4 * 200);
+		}
+		assert!(samples.iter().all(|&sample| sample == 0 || sample == 15));
+	}
+
+	#[test]
+	fn pulse_duty_test() {
+		let mut apu = APU::new();
+		apu.cpu_write(0x4015, 0x01);
+		apu.cpu_write(0x4000, 0b0011_1111);	// 12.5% duty
+		apu.cpu_write(0x4002, 9);			// A step every 20 CPU cycles
+		apu.cpu_write(0x4003, 0b0000_1000);
+
+		// 8 steps = 160 CPU cycles, 1 of them high.
+		let samples = record_pulse1(&mut apu, 160 * 4);
+		let high = samples.iter().filter(|&&sample| sample != 0).count();
+		assert_eq!(high, 20 * 4);
+	}
+
+	#[test]
+	fn sweep_mute_test() {
+		let mut apu = APU::new();
+		apu.cpu_write(0x4015, 0x03);
+		apu.cpu_write(0x4000, 0b1011_1111);
+		apu.cpu_write(0x4002, 0xFF);
+		apu.cpu_write(0x4003, 0b0000_1101);	// Period 0x5FF
+		apu.cpu_write(0x4004, 0b1011_1111);
+		apu.cpu_write(0x4006, 0xFF);
+		apu.cpu_write(0x4007, 0b0000_1101);
+
+		// Target period of 0x5FF + (0x5FF >> 1) is over 0x7FF, so the channel is muted even when the sweep is disabled.
+		apu.cpu_write(0x4001, 0b0000_0001);
+		// Negated target period is fine.
+		apu.cpu_write(0x4005, 0b0000_1001);
+
+		let pulse1 = record_pulse1(&mut apu, 2000);
+		assert!(pulse1.iter().all(|&sample| sample == 0));
+		assert!((0..2000).any(|_| {
+			apu.tick(1);
+			apu.pulse2_output() != 0
+		}));
+
+		// Pulse 1 with a lower period: the sweep raises it every half frame, until it overflows and mutes.
+		apu.cpu_write(0x4001, 0b1000_0001);	// Enabled, divider period 0, shift 1
+		apu.cpu_write(0x4002, 0x00);
+		apu.cpu_write(0x4003, 0b0000_1001);	// Period 0x100
+		record_pulse1(&mut apu, FOUR_STEP_LENGTH);
+		assert_eq!(apu.pulse1_period(), 0x240);	// 0x100 -> 0x180 -> 0x240
+		record_pulse1(&mut apu, FOUR_STEP_LENGTH);
+		assert_eq!(apu.pulse1_period(), 0x510);	// -> 0x360 -> 0x510, target is 0x798
+		record_pulse1(&mut apu, FOUR_STEP_LENGTH);
+		// Target of 0x798 is 0xB64, so the period stops at 0x798 and the channel is muted.
+		assert_eq!(apu.pulse1_period(), 0x798);
+		let samples = record_pulse1(&mut apu, 2000);
+		assert!(samples.iter().all(|&sample| sample == 0));
+
+		// Pulse 2 with a negated sweep goes down, and never mutes because of the target.
+		apu.cpu_write(0x4005, 0b1000_1001);
+		record_pulse1(&mut apu, FOUR_STEP_LENGTH);
+		assert!(apu.pulse2_period() < 0x5FF);
+	}
+
+	#[test]
+	fn sample_output_test() {
+		let mut apu = APU::new();
+		apu.set_sample_rate(48_000);
+		apu.cpu_write(0x4015, 0x01);
+		apu.cpu_write(0x4000, 0b1011_1111);	// 50% duty, halt length counter, constant volume 15
+		apu.cpu_write(0x4002, 0xFD);			// Timer period 253: 1789773 / (16 * 254) = ~440Hz
+		apu.cpu_write(0x4003, 0b0000_1000);
+
+		// One emulated second, drained every frame like a frontend would.
+		let mut samples = vec![];
+		for _ in 0..60 {
+			for _ in 0..Region::Ntsc.cpu_clock_rate() / 60 {
+				apu.tick(1);
+			}
+			apu.take_samples(&mut samples);
+		}
+		assert!((47_990..=48_000).contains(&samples.len()), "samples: {}", samples.len());
+
+		// Count rising edges to find the period of the wave: 48000 / 440 = ~109 samples.
+		let middle = (samples.iter().cloned().fold(f32::MIN, f32::max) + samples.iter().cloned().fold(f32::MAX, f32::min)) / 2.0;
+		let rising_edges: Vec<usize> = (1..samples.len()).filter(|&i| samples[i - 1] < middle && samples[i] >= middle).collect();
+		let period = (rising_edges[rising_edges.len() - 1] - rising_edges[0]) as f32 / (rising_edges.len() - 1) as f32;
+		assert!((period - 48_000.0 / 440.4).abs() < 0.5, "period: {}", period);
+	}
+
+	#[test]
+	fn status_length_counter_test() {
+		let mut apu = APU::new();
+
+		// Length counters can't be loaded while the channel is disabled.
+		apu.cpu_write(0x4003, 0b0001_1000);
+		assert_eq!(apu.cpu_read(0x4015), 0x00);
+
+		apu.cpu_write(0x4015, 0x01);
+		apu.cpu_write(0x4000, 0b0001_1111);	// Length counter not halted
+		apu.cpu_write(0x4003, 0b0001_1000);	// Length 2
+
+		// The length counter is clocked by half frames: it reaches 0 at the second one.
+		let mut cycles = 0;
+		while apu.cpu_read(0x4015) & 0x01 != 0 {
+			apu.tick(1);
+			cycles += 1;
+		}
+		assert_eq!(cycles, 29_829);
+
+		// Disabling the channel clears the length counter immediately.
+		apu.cpu_write(0x4003, 0b0001_1000);
+		assert_eq!(apu.cpu_read(0x4015), 0x01);
+		apu.cpu_write(0x4015, 0x00);
+		assert_eq!(apu.cpu_read(0x4015), 0x00);
+	}
+
+	/// Pulse 1, pulse 2, triangle and noise playing, with their length counters halted.
+	fn play_all(apu: &mut APU) {
+		apu.cpu_write(0x4015, 0x0F);
+		apu.cpu_write(0x4000, 0b1011_1111);
+		apu.cpu_write(0x4002, 0xFD);
+		apu.cpu_write(0x4003, 0b0000_1000);
+		apu.cpu_write(0x4004, 0b0111_1010);
+		apu.cpu_write(0x4006, 0x80);
+		apu.cpu_write(0x4007, 0b0000_1001);
+		apu.cpu_write(0x4008, 0xFF);
+		apu.cpu_write(0x400A, 0x40);
+		apu.cpu_write(0x400B, 0b0000_1000);
+		apu.cpu_write(0x400C, 0b0011_1100);
+		apu.cpu_write(0x400E, 0x03);
+		apu.cpu_write(0x400F, 0b0000_1000);
+	}
+
+	#[test]
+	fn channel_mask_test() {
+		let mut apu = APU::new();
+		play_all(&mut apu);
+		assert_eq!(apu.channel_mask(), Channel::ALL_MASK);
+
+		// Pulse 1 alone is the mix of pulse 1 only, and the others are playing.
+		apu.set_channel_mask(Channel::Pulse1.mask());
+		let mut others = false;
+		for _ in 0..20_000 {
+			apu.tick(1);
+			assert_eq!(apu.output(), mix([apu.pulse1_output(), 0, 0, 0, 0]));
+			others |= apu.triangle_output() != 0 && apu.pulse2_output() != 0;
+		}
+		assert!(others);
+
+		// All muted: silence, but the channels still run.
+		apu.set_channel_mask(0);
+		for _ in 0..20_000 {
+			apu.tick(1);
+			assert_eq!(apu.output(), 0.0);
+		}
+		assert_eq!(apu.cpu_read(0x4015) & 0x0F, 0x0F);
+		apu.set_channel_mask(Channel::ALL_MASK);
+		assert_ne!((0..1000).map(|_| { apu.tick(1); apu.output() }).fold(0.0, f32::max), 0.0);
+	}
+
+	#[test]
+	fn capture_channels_test() {
+		// The capture of a channel is what the mix is with the channel alone.
+		let mut captured = APU::new();
+		captured.set_sample_rate(48_000);
+		captured.capture_channels(true);
+		play_all(&mut captured);
+		let mut solo = APU::new();
+		solo.set_sample_rate(48_000);
+		solo.set_channel_mask(Channel::Triangle.mask());
+		play_all(&mut solo);
+		for _ in 0..FOUR_STEP_LENGTH {
+			captured.tick(1);
+			solo.tick(1);
+		}
+
+		let (mut expected, mut triangle, mut noise) = (vec![], vec![], vec![]);
+		solo.take_samples(&mut expected);
+		captured.channel_sample_buffer(Channel::Triangle).unwrap().take(&mut triangle);
+		captured.channel_sample_buffer(Channel::Noise).unwrap().take(&mut noise);
+		assert!(expected.len() > 700);
+		assert_eq!(triangle, expected);
+		assert_eq!(noise.len(), expected.len());
+		assert_ne!(noise, expected);
+		assert!(APU::new().channel_sample_buffer(Channel::Triangle).is_none());
+	}
+
+	#[test]
+	fn note_length_test() {
+		// Pulse 1, pulse 2, triangle and noise: the first register (length counter not halted), the length counter
+		// load register, and the status bit.
+		let channels = [(0x4000, 0x1F, 0x4003, 0x01), (0x4004, 0x1F, 0x4007, 0x02), (0x4008, 0x7F, 0x400B, 0x04), (0x400C, 0x1F, 0x400F, 0x08)];
+		for (control, control_data, load, status) in channels {
+			for index in [0, 1, 3, 22] {
+				let mut apu = APU::new();
+				apu.cpu_write(0x4015, 0x0F);
+				apu.cpu_write(control, control_data);
+				apu.cpu_write(load, index << 3);
+
+				// 2 half frames a frame, in the 4-step mode.
+				let mut frames = 0;
+				while apu.cpu_read(0x4015) & status != 0 {
+					for _ in 0..FOUR_STEP_LENGTH {
+						apu.tick(1);
+					}
+					frames += 1;
+				}
+				assert_eq!(frames, LENGTH_TABLE[index as usize] as usize / 2, "register {:#X}, index {}", load, index);
+			}
+		}
+	}
+
+	#[test]
+	fn status_irq_test() {
+		let mut apu = APU::new();
+		apu.cpu_write(0x4010, 0x80);	// DMC IRQ enabled
+		apu.cpu_write(0x4013, 0x00);	// 1 byte
+		apu.cpu_write(0x4015, 0x10);
+		assert_eq!(apu.status(), 0x10);
+
+		// The bus does the DMA, so I do it here.
+		let addr = apu.dmc_dma_request().unwrap();
+		assert_eq!(addr, 0xC000);
+		apu.dmc_dma_complete(0x00);
+		assert_eq!(apu.status(), 0x80);
+
+		for _ in 0..FOUR_STEP_LENGTH {
+			apu.tick(1);
+		}
+		assert!(apu.irq_line().is_asserted());
+
+		// Reading clears the frame interrupt, but not the DMC interrupt.
+		assert_eq!(apu.cpu_read(0x4015), 0xC0);
+		assert_eq!(apu.cpu_read(0x4015), 0x80);
+		assert!(apu.irq_line().is_asserted());
+
+		// Writing clears the DMC interrupt.
+		apu.cpu_write(0x4015, 0x00);
+		assert_eq!(apu.cpu_read(0x4015), 0x00);
+		assert!(!apu.irq_line().is_asserted());
+	}
+}
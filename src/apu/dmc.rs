@@ -0,0 +1,269 @@
+// Delta modulation channel: https://www.nesdev.org/wiki/APU_DMC
+//
+// | Register | Bits | Description |
+// |---|---|---|
+// | $4010 | IL-- RRRR | IRQ enable (I), loop (L), rate index (R) |
+// | $4011 | -DDD DDDD | Direct load of the output level (D) |
+// | $4012 | AAAA AAAA | Sample address = $C000 + A * 64 |
+// | $4013 | LLLL LLLL | Sample length = L * 16 + 1 bytes |
+//
+// The DMC reads the sample bytes from CPU memory by itself (DMA). Every read stalls the CPU.
+
+use crate::region::Region;
+use crate::save_state::{SaveState, StateReader, StateWriter};
+
+#[derive(Clone)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct DMC {
+	irq_enabled: bool,
+	looping: bool,
+	/// The timer periods depend on the region.
+	region: Region,
+	timer_period: u16,
+	timer: u16,
+	/// 7 bit output level, 0-127.
+	output_level: u8,
+
+	sample_address: u16,
+	sample_length: u16,
+	current_address: u16,
+	bytes_remaining: u16,
+	/// The next byte to play. Empty if the memory reader should fetch the next byte.
+	sample_buffer: Option<u8>,
+
+	shift_register: u8,
+	bits_remaining: u8,
+	silence: bool,
+
+	irq_flag: bool,
+}
+
+impl Default for DMC {
+	fn default() -> Self {
+		Self::new()
+	}
+}
+
+impl DMC {
+	pub fn new() -> Self {
+		DMC {
+			irq_enabled: false,
+			looping: false,
+			region: Region::Ntsc,
+			timer_period: Region::Ntsc.dmc_rates()[0],
+			timer: 0,
+			output_level: 0,
+			sample_address: 0xC000,
+			sample_length: 1,
+			current_address: 0xC000,
+			bytes_remaining: 0,
+			sample_buffer: None,
+			shift_register: 0,
+			bits_remaining: 8,
+			silence: true,
+			irq_flag: false,
+		}
+	}
+
+	/// Takes effect at the next write of the rate.
+	pub fn set_region(&mut self, region: Region) {
+		self.region = region;
+	}
+
+	/// Write one of the 4 registers of the channel, `register` is 0-3.
+	pub fn write(&mut self, register: u16, data: u8) {
+		match register {
+			0 => {
+				self.irq_enabled = data & 0x80 != 0;
+				self.looping = data & 0x40 != 0;
+				self.timer_period = self.region.dmc_rates()[(data & 0x0F) as usize];
+				if !self.irq_enabled {
+					self.irq_flag = false;
+				}
+			}
+			1 => self.output_level = data & 0x7F,
+			2 => self.sample_address = 0xC000 + data as u16 * 64,
+			3 => self.sample_length = data as u16 * 16 + 1,
+			_ => unreachable!("DMC channel has only 4 registers"),
+		}
+	}
+
+	/// Enable/disable through $4015. Enabling restarts the sample only if it already ended.
+	/// Any write to $4015 clears the DMC interrupt flag.
+	pub fn set_enabled(&mut self, enabled: bool) {
+		self.irq_flag = false;
+		if !enabled {
+			self.bytes_remaining = 0;
+		} else if self.bytes_remaining == 0 {
+			self.restart();
+		}
+	}
+
+	fn restart(&mut self) {
+		self.current_address = self.sample_address;
+		self.bytes_remaining = self.sample_length;
+	}
+
+	/// Sample bytes remaining is not zero, used by $4015.
+	pub fn active(&self) -> bool {
+		self.bytes_remaining > 0
+	}
+
+	pub fn irq(&self) -> bool {
+		self.irq_flag
+	}
+
+	/// The address the memory reader wants to read, if the sample buffer is empty and there are bytes left.
+	/// The bus should read it (stalling the CPU), and give the byte back with `dma_complete`.
+	pub fn dma_request(&self) -> Option<u16> {
+		if self.sample_buffer.is_none() && self.bytes_remaining > 0 {
+			Some(self.current_address)
+		} else {
+			None
+		}
+	}
+
+	pub fn dma_complete(&mut self, data: u8) {
+		self.sample_buffer = Some(data);
+		// The address wraps around to $8000, not to $0000.
+		self.current_address = if self.current_address == 0xFFFF { 0x8000 } else { self.current_address + 1 };
+		self.bytes_remaining -= 1;
+
+		if self.bytes_remaining == 0 {
+			if self.looping {
+				self.restart();
+			} else if self.irq_enabled {
+				self.irq_flag = true;
+			}
+		}
+	}
+
+	/// Clocked every CPU cycle.
+	pub fn clock_timer(&mut self) {
+		if self.timer > 0 {
+			self.timer -= 1;
+			return;
+		}
+		self.timer = self.timer_period - 1;
+
+		// Each bit of the shift register moves the output level up or down by 2.
+		if !self.silence {
+			if self.shift_register & 1 == 1 {
+				if self.output_level <= 125 {
+					self.output_level += 2;
+				}
+			} else if self.output_level >= 2 {
+				self.output_level -= 2;
+			}
+		}
+		self.shift_register >>= 1;
+
+		self.bits_remaining -= 1;
+		if self.bits_remaining == 0 {
+			self.bits_remaining = 8;
+			match self.sample_buffer.take() {
+				Some(data) => {
+					self.silence = false;
+					self.shift_register = data;
+				}
+				None => self.silence = true,
+			}
+		}
+	}
+
+	/// Current amplitude, 0-127.
+	pub fn output(&self) -> u8 {
+		self.output_level
+	}
+}
+
+/// The rate table comes from the region, it's not saved.
+impl SaveState for DMC {
+	fn save_state(&self, out: &mut StateWriter) {
+		out.bool(self.irq_enabled);
+		out.bool(self.looping);
+		out.u16(self.timer_period);
+		out.u16(self.timer);
+		out.u8(self.output_level);
+		out.u16(self.sample_address);
+		out.u16(self.sample_length);
+		out.u16(self.current_address);
+		out.u16(self.bytes_remaining);
+		// 0x100 means empty.
+		out.u16(self.sample_buffer.map_or(0x100, |data| data as u16));
+		out.u8(self.shift_register);
+		out.u8(self.bits_remaining);
+		out.bool(self.silence);
+		out.bool(self.irq_flag);
+	}
+
+	fn load_state(&mut self, input: &mut StateReader) -> Result<(), String> {
+		self.irq_enabled = input.bool()?;
+		self.looping = input.bool()?;
+		self.timer_period = input.u16()?;
+		self.timer = input.u16()?;
+		self.output_level = input.u8()?;
+		self.sample_address = input.u16()?;
+		self.sample_length = input.u16()?;
+		self.current_address = input.u16()?;
+		self.bytes_remaining = input.u16()?;
+		self.sample_buffer = u8::try_from(input.u16()?).ok();
+		self.shift_register = input.u8()?;
+		self.bits_remaining = input.u8()?;
+		self.silence = input.bool()?;
+		self.irq_flag = input.bool()?;
+		Ok(())
+	}
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+
+	#[test]
+	fn dmc_output_test() {
+		let mut dmc = DMC::new();
+		dmc.write(0, 0x0F);		// Fastest rate, 54 cycles
+		dmc.write(1, 0x40);
+		dmc.write(3, 0x00);		// 1 byte
+		dmc.set_enabled(true);
+
+		assert_eq!(dmc.dma_request(), Some(0xC000));
+		dmc.dma_complete(0b0000_1111);
+		assert_eq!(dmc.dma_request(), None);
+		assert!(!dmc.active());
+
+		// The first 8 bits are silence (the shift register was empty), then the byte is played.
+		for _ in 0..54 * 8 {
+			dmc.clock_timer();
+		}
+		assert_eq!(dmc.output(), 0x40);
+		for _ in 0..54 * 4 {
+			dmc.clock_timer();
+		}
+		assert_eq!(dmc.output(), 0x40 + 8);
+		for _ in 0..54 * 4 {
+			dmc.clock_timer();
+		}
+		assert_eq!(dmc.output(), 0x40);
+	}
+
+	#[test]
+	fn dmc_loop_test() {
+		let mut dmc = DMC::new();
+		dmc.write(0, 0xC0);		// IRQ enabled, but looping samples never end
+		dmc.write(2, 0xFF);		// $FFC0
+		dmc.write(3, 0x04);		// 65 bytes
+		dmc.set_enabled(true);
+
+		for i in 0..65 * 2 {
+			// Wraps from $FFFF to $8000.
+			let expected = if i % 65 < 64 { 0xFFC0 + i % 65 } else { 0x8000 };
+			assert_eq!(dmc.dma_request(), Some(expected));
+			dmc.dma_complete(0);
+			dmc.sample_buffer = None;
+		}
+		assert!(!dmc.irq());
+		assert!(dmc.active());
+	}
+}
@@ -0,0 +1,149 @@
+// Envelope: https://www.nesdev.org/wiki/APU_Envelope
+//
+// Pulse and noise have one. It's the volume of the channel: either constant, or a saw that decays from 15 to 0.
+//
+// | Bits of $4000 / $4004 / $400C | Description |
+// |---|---|
+// | --L- ---- | Loop: after 0, start again from 15. It's also the length counter halt flag |
+// | ---C ---- | Constant volume: the volume is V. Otherwise it decays, and V is the period of the divider |
+// | ---- VVVV | Volume, or the divider period |
+//
+// Writing the channel's 4th register sets the start flag. The next quarter frame restarts the decay from 15, and
+// reloads the divider. After that, every quarter frame clocks the divider, and when it runs out (every V + 1 quarter
+// frames) the decay goes down by one.
+
+use crate::save_state::{SaveState, StateReader, StateWriter};
+
+/// Volume: either constant, or a decaying saw (15 down to 0). Clocked by the frame counter's quarter frames.
+#[derive(Default, Clone)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub(super) struct Envelope {
+	pub start: bool,
+	pub looping: bool,
+	pub constant_volume: bool,
+	/// Constant volume, or the period of the divider.
+	pub volume: u8,
+	divider: u8,
+	decay: u8,
+}
+
+impl Envelope {
+	/// Write the lower 6 bits of $4000/$4004/$400C.
+	pub fn write(&mut self, data: u8) {
+		self.looping = data & 0x20 != 0;
+		self.constant_volume = data & 0x10 != 0;
+		self.volume = data & 0x0F;
+	}
+
+	pub fn clock(&mut self) {
+		if self.start {
+			self.start = false;
+			self.decay = 15;
+			self.divider = self.volume;
+			return;
+		}
+
+		if self.divider > 0 {
+			self.divider -= 1;
+			return;
+		}
+
+		self.divider = self.volume;
+		if self.decay > 0 {
+			self.decay -= 1;
+		} else if self.looping {
+			self.decay = 15;
+		}
+	}
+
+	pub fn output(&self) -> u8 {
+		if self.constant_volume { self.volume } else { self.decay }
+	}
+}
+
+impl SaveState for Envelope {
+	fn save_state(&self, out: &mut StateWriter) {
+		out.bool(self.start);
+		out.bool(self.looping);
+		out.bool(self.constant_volume);
+		out.u8(self.volume);
+		out.u8(self.divider);
+		out.u8(self.decay);
+	}
+
+	fn load_state(&mut self, input: &mut StateReader) -> Result<(), String> {
+		self.start = input.bool()?;
+		self.looping = input.bool()?;
+		self.constant_volume = input.bool()?;
+		self.volume = input.u8()?;
+		self.divider = input.u8()?;
+		self.decay = input.u8()?;
+		Ok(())
+	}
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+
+	/// The output after each of `clocks` clocks.
+	fn outputs(envelope: &mut Envelope, clocks: usize) -> Vec<u8> {
+		(0..clocks).map(|_| {
+			envelope.clock();
+			envelope.output()
+		}).collect()
+	}
+
+	#[test]
+	fn envelope_test() {
+		let mut envelope = Envelope::default();
+		envelope.write(0x01); // Decay, period 1 (every 2 clocks)
+		envelope.start = true;
+
+		envelope.clock();
+		assert_eq!(envelope.output(), 15);
+		envelope.clock();
+		assert_eq!(envelope.output(), 15);
+		envelope.clock();
+		assert_eq!(envelope.output(), 14);
+
+		envelope.write(0x1A); // Constant volume
+		assert_eq!(envelope.output(), 0x0A);
+	}
+
+	#[test]
+	fn envelope_decay_test() {
+		// Period 0: one step every clock. 15 down to 0, and then it holds.
+		let mut envelope = Envelope::default();
+		envelope.write(0x00);
+		envelope.start = true;
+		let expected: Vec<u8> = (0..=15).rev().chain([0, 0, 0]).collect();
+		assert_eq!(outputs(&mut envelope, 19), expected);
+
+		// Looping: back to 15 after 0.
+		let mut envelope = Envelope::default();
+		envelope.write(0x20);
+		envelope.start = true;
+		let expected: Vec<u8> = (0..=15).rev().chain((13..=15).rev()).collect();
+		assert_eq!(outputs(&mut envelope, 19), expected);
+
+		// The start flag restarts it in the middle.
+		envelope.start = true;
+		assert_eq!(outputs(&mut envelope, 2), [15, 14]);
+	}
+
+	#[test]
+	fn envelope_divider_test() {
+		// Period 3: a step every 4 clocks, after the start.
+		let mut envelope = Envelope::default();
+		envelope.write(0x03);
+		envelope.start = true;
+		assert_eq!(outputs(&mut envelope, 9), [15, 15, 15, 15, 14, 14, 14, 14, 13]);
+
+		// The decay goes on behind a constant volume, and shows when it's turned off.
+		envelope.write(0x17);
+		assert_eq!(outputs(&mut envelope, 4), [7, 7, 7, 7]);
+		envelope.write(0x03);
+		assert_eq!(envelope.output(), 12);
+	}
+}
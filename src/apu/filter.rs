@@ -0,0 +1,48 @@
+// Filters for the mixed output. The console has a high-pass on its audio output, so the level of a silent APU (the
+// triangle stops on a step, and holds it) doesn't come out of the speaker. The samples of the APU keep it, like the
+// mixer makes them, and whoever plays or records them filters them.
+
+use std::f32::consts::PI;
+
+/// First order high-pass. It starts at the first sample, so a constant input comes out as exactly 0.0, from the
+/// first sample on.
+#[derive(Clone, Debug)]
+pub struct HighPass {
+	alpha: f32,
+	previous: Option<(f32, f32)>,
+}
+
+impl HighPass {
+	pub fn new(sample_rate: u32, cutoff: f32) -> Self {
+		let rc = 1.0 / (2.0 * PI * cutoff);
+		let dt = 1.0 / sample_rate as f32;
+		HighPass { alpha: rc / (rc + dt), previous: None }
+	}
+
+	pub fn apply(&mut self, input: f32) -> f32 {
+		let output = match self.previous {
+			Some((previous_input, previous_output)) => self.alpha * (previous_output + input - previous_input),
+			None => 0.0,
+		};
+		self.previous = Some((input, output));
+		output
+	}
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+
+	#[test]
+	fn high_pass_test() {
+		let mut filter = HighPass::new(48_000, 90.0);
+		// A level, and nothing else.
+		assert!((0..1000).all(|_| filter.apply(0.25) == 0.0));
+
+		// A step goes through, and fades out.
+		let step = filter.apply(0.75);
+		assert!(step > 0.45);
+		let later = (0..48_000).map(|_| filter.apply(0.75)).last().unwrap();
+		assert!(later.abs() < 0.001, "{}", later);
+	}
+}
@@ -0,0 +1,220 @@
+// Frame counter ($4017): https://www.nesdev.org/wiki/APU_Frame_Counter
+//
+// $4017: MI-- ----, Mode (M, 0 = 4-step, 1 = 5-step), IRQ inhibit flag (I).
+//
+// The sequencer steps, in CPU cycles (NTSC) since the sequence started. PAL steps are in `region.rs`.
+//
+// | 4-step | 5-step | Clocks |
+// |---|---|---|
+// | 7457 | 7457 | Quarter frame |
+// | 14913 | 14913 | Quarter frame, half frame |
+// | 22371 | 22371 | Quarter frame |
+// | 29828 - 29830 | - | Frame IRQ (if not inhibited) |
+// | 29829 | - | Quarter frame, half frame |
+// | - | 37281 | Quarter frame, half frame |
+
+use crate::region::Region;
+use crate::save_state::{SaveState, StateReader, StateWriter};
+
+/// Which units should be clocked in the current cycle.
+#[derive(Default, Clone, Copy, PartialEq, Debug)]
+pub struct FrameClock {
+	/// Envelopes and the triangle linear counter.
+	pub quarter: bool,
+	/// Length counters and sweeps.
+	pub half: bool,
+}
+
+#[derive(Clone)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct FrameCounter {
+	/// The steps depend on the region.
+	region: Region,
+	five_step_mode: bool,
+	irq_inhibit: bool,
+	irq_flag: bool,
+	/// CPU cycles since the start of the sequence.
+	cycle: u32,
+	/// The write to $4017 takes effect 3 or 4 CPU cycles after the write. Counts down to it.
+	reset_delay: u8,
+	/// The value written to $4017, applied when `reset_delay` reaches 0.
+	pending_write: u8,
+}
+
+impl Default for FrameCounter {
+	fn default() -> Self {
+		Self::new()
+	}
+}
+
+impl FrameCounter {
+	pub fn new() -> Self {
+		FrameCounter {
+			region: Region::Ntsc,
+			five_step_mode: false,
+			irq_inhibit: false,
+			irq_flag: false,
+			cycle: 0,
+			reset_delay: 0,
+			pending_write: 0,
+		}
+	}
+
+	pub fn set_region(&mut self, region: Region) {
+		self.region = region;
+	}
+
+	/// Write $4017. `odd_cycle` is whether the write happened between APU cycles.
+	/// The sequence restarts 3 or 4 cycles later, see `clock`.
+	pub fn write(&mut self, data: u8, odd_cycle: bool) {
+		self.irq_inhibit = data & 0x40 != 0;
+		if self.irq_inhibit {
+			self.irq_flag = false;
+		}
+
+		self.pending_write = data;
+		self.reset_delay = if odd_cycle { 4 } else { 3 };
+	}
+
+	/// Frame interrupt flag, read by $4015.
+	pub fn irq(&self) -> bool {
+		self.irq_flag
+	}
+
+	/// Reading $4015 acknowledges the interrupt.
+	pub fn clear_irq(&mut self) {
+		self.irq_flag = false;
+	}
+
+	/// Clocked every CPU cycle.
+	pub fn clock(&mut self) -> FrameClock {
+		let mut clock = FrameClock::default();
+		if self.reset_delay > 0 {
+			self.reset_delay -= 1;
+			if self.reset_delay == 0 {
+				self.five_step_mode = self.pending_write & 0x80 != 0;
+				self.cycle = 0;
+				// Restarting in 5-step mode clocks everything.
+				clock.quarter = self.five_step_mode;
+				clock.half = self.five_step_mode;
+			}
+		}
+
+		self.cycle += 1;
+
+		let steps = self.region.frame_counter_steps();
+		if self.cycle == steps.step1 || self.cycle == steps.step3 {
+			clock.quarter = true;
+		} else if self.cycle == steps.step2 {
+			clock.quarter = true;
+			clock.half = true;
+		}
+
+		if self.five_step_mode {
+			if self.cycle == steps.step5 {
+				clock.quarter = true;
+				clock.half = true;
+			}
+			if self.cycle == steps.five_step_length {
+				self.cycle = 0;
+			}
+		} else {
+			if self.cycle == steps.step4 {
+				clock.quarter = true;
+				clock.half = true;
+			}
+			if (steps.step4 - 1..=steps.four_step_length).contains(&self.cycle) && !self.irq_inhibit {
+				self.irq_flag = true;
+			}
+			if self.cycle == steps.four_step_length {
+				self.cycle = 0;
+			}
+		}
+
+		clock
+	}
+}
+
+/// The steps come from the region, they are not saved.
+impl SaveState for FrameCounter {
+	fn save_state(&self, out: &mut StateWriter) {
+		out.bool(self.five_step_mode);
+		out.bool(self.irq_inhibit);
+		out.bool(self.irq_flag);
+		out.u32(self.cycle);
+		out.u8(self.reset_delay);
+		out.u8(self.pending_write);
+	}
+
+	fn load_state(&mut self, input: &mut StateReader) -> Result<(), String> {
+		self.five_step_mode = input.bool()?;
+		self.irq_inhibit = input.bool()?;
+		self.irq_flag = input.bool()?;
+		self.cycle = input.u32()?;
+		self.reset_delay = input.u8()?;
+		self.pending_write = input.u8()?;
+		Ok(())
+	}
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+
+	/// Clock `cycles` times, and count the quarter and half frames.
+	fn count_clocks(frame_counter: &mut FrameCounter, cycles: u32) -> (u32, u32) {
+		(0..cycles).fold((0, 0), |(quarters, halves), _| {
+			let clock = frame_counter.clock();
+			(quarters + clock.quarter as u32, halves + clock.half as u32)
+		})
+	}
+
+	#[test]
+	fn four_step_test() {
+		let mut frame_counter = FrameCounter::new();
+		let steps = Region::Ntsc.frame_counter_steps();
+
+		assert_eq!(count_clocks(&mut frame_counter, steps.step4 - 2), (3, 1));
+		assert!(!frame_counter.irq());
+		assert_eq!(count_clocks(&mut frame_counter, 1), (0, 0));
+		assert!(frame_counter.irq());
+		assert_eq!(count_clocks(&mut frame_counter, 1), (1, 1));
+
+		frame_counter.clear_irq();
+		assert_eq!(count_clocks(&mut frame_counter, 1), (0, 0));
+		// Cleared, but still in the cycles that set it.
+		assert!(frame_counter.irq());
+		frame_counter.clear_irq();
+		assert_eq!(count_clocks(&mut frame_counter, steps.four_step_length), (4, 2));
+		assert!(frame_counter.irq());
+	}
+
+	#[test]
+	fn five_step_test() {
+		let mut frame_counter = FrameCounter::new();
+		let steps = Region::Ntsc.frame_counter_steps();
+
+		// Writing 5-step mode clocks everything, when the sequence restarts.
+		frame_counter.write(0x80, false);
+		assert_eq!(count_clocks(&mut frame_counter, 2), (0, 0));
+		assert_eq!(frame_counter.clock(), FrameClock { quarter: true, half: true });
+
+		assert_eq!(count_clocks(&mut frame_counter, steps.five_step_length - 1), (4, 2));
+		assert_eq!(count_clocks(&mut frame_counter, steps.five_step_length * 3), (12, 6));
+		assert!(!frame_counter.irq());
+	}
+
+	#[test]
+	fn irq_inhibit_test() {
+		let mut frame_counter = FrameCounter::new();
+		let steps = Region::Ntsc.frame_counter_steps();
+		count_clocks(&mut frame_counter, steps.four_step_length);
+		assert!(frame_counter.irq());
+
+		// Setting the inhibit flag clears the interrupt flag.
+		frame_counter.write(0x40, false);
+		assert!(!frame_counter.irq());
+		count_clocks(&mut frame_counter, steps.four_step_length * 2);
+		assert!(!frame_counter.irq());
+	}
+}
@@ -0,0 +1,169 @@
+// Length counter: https://www.nesdev.org/wiki/APU_Length_Counter
+//
+// Pulse, triangle and noise have one. It silences the channel after a while, so a note can end by itself without
+// the game writing to the channel again:
+//
+// * Writing the channel's 4th register ($4003, $4007, $400B, $400F) loads it from `LENGTH_TABLE`, with the top 5 bits
+//   of the write. Only while the channel is enabled in $4015.
+// * Half frames of the frame counter decrement it, unless it's halted. The halt flag is the same bit as the envelope
+//   loop flag (the triangle linear counter control flag): a looping envelope plays forever.
+// * At 0 the channel is silenced. Disabling the channel in $4015 sets it to 0 immediately.
+// * A write on the same cycle as a half frame: the load is ignored if the counter wasn't 0, and the clock still sees
+//   the halt flag from before the write. The APU ends every cycle with `end_cycle`, for that.
+
+use crate::save_state::{SaveState, StateReader, StateWriter};
+
+/// Length counter load values, in half frames, indexed by the 5 bits written to the length counter load register.
+pub(super) const LENGTH_TABLE: [u8; 32] = [
+	10, 254, 20, 2, 40, 4, 80, 6, 160, 8, 60, 10, 14, 12, 26, 14,
+	12, 16, 24, 18, 48, 20, 96, 22, 192, 24, 72, 26, 16, 28, 32, 30,
+];
+
+/// Counts down to silence the channel after a while. Clocked by the frame counter's half frames.
+#[derive(Default, Clone)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub(super) struct LengthCounter {
+	pub counter: u8,
+	pub halt: bool,
+	enabled: bool,
+	/// The counter before a load in this cycle. The write of a cycle is always followed by its APU clock in the same
+	/// CPU step, so neither is ever set between instructions, and they're not in save states.
+	#[cfg_attr(feature = "serde", serde(skip))]
+	loaded_from: Option<u8>,
+	/// The halt flag before a write in this cycle.
+	#[cfg_attr(feature = "serde", serde(skip))]
+	halt_before: Option<bool>,
+}
+
+impl LengthCounter {
+	/// Enable/disable through $4015. Disabling clears the counter immediately.
+	pub fn set_enabled(&mut self, enabled: bool) {
+		self.enabled = enabled;
+		if !enabled {
+			self.counter = 0;
+			self.loaded_from = None;
+		}
+	}
+
+	/// Load from the lookup table. Ignored while the channel is disabled.
+	pub fn load(&mut self, index: u8) {
+		if self.enabled {
+			self.loaded_from.get_or_insert(self.counter);
+			self.counter = LENGTH_TABLE[(index & 0x1F) as usize];
+		}
+	}
+
+	/// The halt flag, from the channel's first register.
+	pub fn set_halt(&mut self, halt: bool) {
+		self.halt_before.get_or_insert(self.halt);
+		self.halt = halt;
+	}
+
+	pub fn clock(&mut self) {
+		let halt = self.halt_before.unwrap_or(self.halt);
+		match self.loaded_from {
+			// The load comes after the clock, which had nothing to do.
+			Some(0) => {}
+			// The load is lost.
+			Some(counter) => self.counter = counter - !halt as u8,
+			None => {
+				if self.counter > 0 && !halt {
+					self.counter -= 1;
+				}
+			}
+		}
+	}
+
+	/// The writes of this cycle are done.
+	pub fn end_cycle(&mut self) {
+		self.loaded_from = None;
+		self.halt_before = None;
+	}
+
+	pub fn active(&self) -> bool {
+		self.counter > 0
+	}
+}
+
+impl SaveState for LengthCounter {
+	fn save_state(&self, out: &mut StateWriter) {
+		out.u8(self.counter);
+		out.bool(self.halt);
+		out.bool(self.enabled);
+	}
+
+	fn load_state(&mut self, input: &mut StateReader) -> Result<(), String> {
+		self.counter = input.u8()?;
+		self.halt = input.bool()?;
+		self.enabled = input.bool()?;
+		Ok(())
+	}
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+
+	#[test]
+	fn length_counter_test() {
+		let mut length_counter = LengthCounter::default();
+		// Disabled: loading does nothing.
+		length_counter.load(0b00011);
+		assert!(!length_counter.active());
+
+		length_counter.set_enabled(true);
+		length_counter.load(0b00011); // 2 half frames
+		assert_eq!(length_counter.counter, 2);
+		length_counter.end_cycle();
+		length_counter.clock();
+		assert!(length_counter.active());
+		length_counter.clock();
+		assert!(!length_counter.active());
+		// Stays at 0.
+		length_counter.clock();
+		assert_eq!(length_counter.counter, 0);
+
+		// Halted, it holds.
+		length_counter.load(0b00000);
+		length_counter.set_halt(true);
+		length_counter.end_cycle();
+		for _ in 0..100 {
+			length_counter.clock();
+		}
+		assert_eq!(length_counter.counter, 10);
+
+		// Disabling clears it.
+		length_counter.set_enabled(false);
+		assert_eq!(length_counter.counter, 0);
+	}
+
+	#[test]
+	fn write_on_clock_test() {
+		let mut length_counter = LengthCounter::default();
+		length_counter.set_enabled(true);
+
+		// From 0, the load wins.
+		length_counter.load(0b00011);
+		length_counter.clock();
+		length_counter.end_cycle();
+		assert_eq!(length_counter.counter, 2);
+
+		// Not from 0, the load is lost.
+		length_counter.load(0b00000);
+		length_counter.clock();
+		length_counter.end_cycle();
+		assert_eq!(length_counter.counter, 1);
+
+		// The clock sees the old halt flag.
+		length_counter.load(0b00000);
+		length_counter.end_cycle();
+		length_counter.set_halt(true);
+		length_counter.clock();
+		length_counter.end_cycle();
+		assert_eq!(length_counter.counter, 9);
+		length_counter.set_halt(false);
+		length_counter.clock();
+		length_counter.end_cycle();
+		assert_eq!(length_counter.counter, 9);
+	}
+}
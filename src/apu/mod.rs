@@ -0,0 +1,12 @@
+mod dmc;
+mod envelope;
+mod frame_counter;
+mod length_counter;
+mod noise;
+mod pulse;
+mod sweep;
+mod triangle;
+
+pub mod apu;
+pub mod filter;
+pub mod sample_buffer;
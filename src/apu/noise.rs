@@ -0,0 +1,227 @@
+// Noise channel: https://www.nesdev.org/wiki/APU_Noise
+//
+// | Register | Bits | Description |
+// |---|---|---|
+// | $400C | --LC VVVV | Envelope loop / length counter halt (L), constant volume (C), volume/envelope period (V) |
+// | $400E | M--- PPPP | Mode (M), timer period index (P) |
+// | $400F | LLLL L--- | Length counter load (L) |
+
+use super::envelope::Envelope;
+use super::length_counter::LengthCounter;
+use crate::region::Region;
+use crate::save_state::{SaveState, StateReader, StateWriter};
+
+#[derive(Clone)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct Noise {
+	/// 15 bit linear feedback shift register. Starts as 1 at power on.
+	shift_register: u16,
+	/// Short mode: feedback from bit 6 instead of bit 1, making a 93 (or 31) steps long sequence.
+	short_mode: bool,
+	/// The timer periods depend on the region.
+	region: Region,
+	/// Timer period, in APU cycles.
+	timer_period: u16,
+	timer: u16,
+	envelope: Envelope,
+	length_counter: LengthCounter,
+}
+
+impl Default for Noise {
+	fn default() -> Self {
+		Self::new()
+	}
+}
+
+impl Noise {
+	pub fn new() -> Self {
+		Noise {
+			shift_register: 1,
+			short_mode: false,
+			region: Region::Ntsc,
+			timer_period: Region::Ntsc.noise_periods()[0] / 2,
+			timer: 0,
+			envelope: Envelope::default(),
+			length_counter: LengthCounter::default(),
+		}
+	}
+
+	/// Takes effect at the next write of the period.
+	pub fn set_region(&mut self, region: Region) {
+		self.region = region;
+	}
+
+	/// Write one of the registers of the channel, `register` is 0-3 ($400D is unused).
+	pub fn write(&mut self, register: u16, data: u8) {
+		match register {
+			0 => {
+				self.length_counter.set_halt(data & 0x20 != 0);
+				self.envelope.write(data);
+			}
+			1 => {}
+			2 => {
+				self.short_mode = data & 0x80 != 0;
+				self.timer_period = self.region.noise_periods()[(data & 0x0F) as usize] / 2;
+			}
+			3 => {
+				self.length_counter.load(data >> 3);
+				self.envelope.start = true;
+			}
+			_ => unreachable!("Noise channel has only 4 registers"),
+		}
+	}
+
+	pub fn set_enabled(&mut self, enabled: bool) {
+		self.length_counter.set_enabled(enabled);
+	}
+
+	pub fn active(&self) -> bool {
+		self.length_counter.active()
+	}
+
+	/// Clocked every APU cycle (every other CPU cycle).
+	pub fn clock_timer(&mut self) {
+		if self.timer > 0 {
+			self.timer -= 1;
+			return;
+		}
+
+		self.timer = self.timer_period - 1;
+		self.shift();
+	}
+
+	fn shift(&mut self) {
+		let other_bit = if self.short_mode { 6 } else { 1 };
+		let feedback = (self.shift_register ^ (self.shift_register >> other_bit)) & 1;
+		self.shift_register = (self.shift_register >> 1) | (feedback << 14);
+	}
+
+	pub fn quarter_frame(&mut self) {
+		self.envelope.clock();
+	}
+
+	pub fn half_frame(&mut self) {
+		self.length_counter.clock();
+	}
+
+	/// The end of a CPU cycle, see `LengthCounter::end_cycle`.
+	pub fn end_cycle(&mut self) {
+		self.length_counter.end_cycle();
+	}
+
+	/// Current amplitude, 0-15.
+	pub fn output(&self) -> u8 {
+		if self.shift_register & 1 == 1 || !self.length_counter.active() {
+			return 0;
+		}
+		self.envelope.output()
+	}
+}
+
+/// The period table comes from the region, it's not saved.
+impl SaveState for Noise {
+	fn save_state(&self, out: &mut StateWriter) {
+		out.u16(self.shift_register);
+		out.bool(self.short_mode);
+		out.u16(self.timer_period);
+		out.u16(self.timer);
+		self.envelope.save_state(out);
+		self.length_counter.save_state(out);
+	}
+
+	fn load_state(&mut self, input: &mut StateReader) -> Result<(), String> {
+		self.shift_register = input.u16()?;
+		self.short_mode = input.bool()?;
+		self.timer_period = input.u16()?;
+		self.timer = input.u16()?;
+		self.envelope.load_state(input)?;
+		self.length_counter.load_state(input)
+	}
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+
+	/// Straightforward model of the LFSR, bit by bit, to compare against.
+	fn reference_lfsr(seed: u16, short_mode: bool, shifts: usize) -> Vec<u16> {
+		let mut bits: Vec<u16> = (0..15).map(|i| (seed >> i) & 1).collect();
+		let tap = if short_mode { 6 } else { 1 };
+		let mut states = vec![];
+		for _ in 0..shifts {
+			let feedback = bits[0] ^ bits[tap];
+			bits.remove(0);
+			bits.push(feedback);
+			states.push(bits.iter().enumerate().map(|(i, bit)| bit << i).sum());
+		}
+		states
+	}
+
+	fn lfsr_states(noise: &mut Noise, shifts: usize) -> Vec<u16> {
+		(0..shifts).map(|_| {
+			noise.shift();
+			noise.shift_register
+		}).collect()
+	}
+
+	#[test]
+	fn region_periods_test() {
+		let mut noise = Noise::new();
+		noise.write(2, 0x0F);
+		assert_eq!(noise.timer_period, 4068 / 2);
+
+		noise.set_region(Region::Pal);
+		noise.write(2, 0x0F);
+		assert_eq!(noise.timer_period, 3778 / 2);
+		noise.write(2, 0x02);
+		assert_eq!(noise.timer_period, 14 / 2);
+	}
+
+	#[test]
+	fn lfsr_long_mode_test() {
+		let mut noise = Noise::new();
+		assert_eq!(lfsr_states(&mut noise, 500), reference_lfsr(1, false, 500));
+
+		let mut noise = Noise::new();
+		noise.shift_register = 0x5A3C;
+		assert_eq!(lfsr_states(&mut noise, 500), reference_lfsr(0x5A3C, false, 500));
+
+		// The long sequence repeats every 32767 shifts.
+		let mut noise = Noise::new();
+		let states = lfsr_states(&mut noise, 32767);
+		assert_eq!(states[32766], 1);
+		assert!(states[..32766].iter().all(|&state| state != 1));
+	}
+
+	#[test]
+	fn lfsr_short_mode_test() {
+		let mut noise = Noise::new();
+		noise.write(2, 0x80);
+		assert_eq!(lfsr_states(&mut noise, 500), reference_lfsr(1, true, 500));
+
+		// From the power on state, the short sequence is 93 steps long.
+		let mut noise = Noise::new();
+		noise.write(2, 0x80);
+		let states = lfsr_states(&mut noise, 93);
+		assert_eq!(states[92], 1);
+		assert!(states[..92].iter().all(|&state| state != 1));
+	}
+
+	#[test]
+	fn timer_period_test() {
+		let mut noise = Noise::new();
+		noise.write(2, 0x03); // 32 CPU cycles = 16 APU cycles
+
+		let before = noise.shift_register;
+		noise.clock_timer();
+		assert_ne!(noise.shift_register, before);
+
+		let before = noise.shift_register;
+		for _ in 0..15 {
+			noise.clock_timer();
+		}
+		assert_eq!(noise.shift_register, before);
+		noise.clock_timer();
+		assert_ne!(noise.shift_register, before);
+	}
+}
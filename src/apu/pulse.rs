@@ -0,0 +1,137 @@
+// Pulse (square wave) channels: https://www.nesdev.org/wiki/APU_Pulse
+//
+// | Register | Bits | Description |
+// |---|---|---|
+// | $4000 / $4004 | DDLC VVVV | Duty (D), envelope loop / length counter halt (L), constant volume (C), volume/envelope period (V) |
+// | $4001 / $4005 | EPPP NSSS | Sweep unit: enabled (E), period (P), negate (N), shift (S) |
+// | $4002 / $4006 | TTTT TTTT | Timer low 8 bits |
+// | $4003 / $4007 | LLLL LTTT | Length counter load (L), timer high 3 bits |
+
+use super::envelope::Envelope;
+use super::length_counter::LengthCounter;
+use super::sweep::{PulseChannel, Sweep};
+use crate::save_state::{SaveState, StateReader, StateWriter};
+
+/// Each duty is 8 steps, output in this order.
+const DUTY_SEQUENCES: [[u8; 8]; 4] = [
+	[0, 1, 0, 0, 0, 0, 0, 0], // 12.5%
+	[0, 1, 1, 0, 0, 0, 0, 0], // 25%
+	[0, 1, 1, 1, 1, 0, 0, 0], // 50%
+	[1, 0, 0, 1, 1, 1, 1, 1], // 25% negated
+];
+
+#[derive(Clone)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct Pulse {
+	duty: u8,
+	sequence_step: u8,
+	timer_period: u16,
+	timer: u16,
+	envelope: Envelope,
+	sweep: Sweep,
+	length_counter: LengthCounter,
+}
+
+impl Pulse {
+	pub fn new(channel: PulseChannel) -> Self {
+		Pulse {
+			duty: 0,
+			sequence_step: 0,
+			timer_period: 0,
+			timer: 0,
+			envelope: Envelope::default(),
+			sweep: Sweep::new(channel),
+			length_counter: LengthCounter::default(),
+		}
+	}
+
+	/// Write one of the 4 registers of the channel, `register` is 0-3.
+	pub fn write(&mut self, register: u16, data: u8) {
+		match register {
+			0 => {
+				self.duty = data >> 6;
+				self.length_counter.set_halt(data & 0x20 != 0);
+				self.envelope.write(data);
+			}
+			1 => self.sweep.write(data),
+			2 => self.timer_period = (self.timer_period & 0x0700) | data as u16,
+			3 => {
+				self.timer_period = (self.timer_period & 0x00FF) | (((data & 0b111) as u16) << 8);
+				self.length_counter.load(data >> 3);
+				// Writing the high byte restarts the sequence and the envelope.
+				self.sequence_step = 0;
+				self.envelope.start = true;
+			}
+			_ => unreachable!("Pulse channel has only 4 registers"),
+		}
+	}
+
+	pub fn set_enabled(&mut self, enabled: bool) {
+		self.length_counter.set_enabled(enabled);
+	}
+
+	/// Length counter is not zero, used by $4015.
+	pub fn active(&self) -> bool {
+		self.length_counter.active()
+	}
+
+	pub fn timer_period(&self) -> u16 {
+		self.timer_period
+	}
+
+	/// Clocked every APU cycle (every other CPU cycle).
+	pub fn clock_timer(&mut self) {
+		if self.timer == 0 {
+			self.timer = self.timer_period;
+			self.sequence_step = (self.sequence_step + 1) % 8;
+		} else {
+			self.timer -= 1;
+		}
+	}
+
+	pub fn quarter_frame(&mut self) {
+		self.envelope.clock();
+	}
+
+	pub fn half_frame(&mut self) {
+		self.length_counter.clock();
+		self.timer_period = self.sweep.clock(self.timer_period);
+	}
+
+	/// The end of a CPU cycle, see `LengthCounter::end_cycle`.
+	pub fn end_cycle(&mut self) {
+		self.length_counter.end_cycle();
+	}
+
+	/// Current amplitude, 0-15.
+	pub fn output(&self) -> u8 {
+		if !self.length_counter.active()
+			|| self.sweep.mutes(self.timer_period)
+			|| DUTY_SEQUENCES[self.duty as usize][self.sequence_step as usize] == 0 {
+			return 0;
+		}
+		self.envelope.output()
+	}
+}
+
+impl SaveState for Pulse {
+	fn save_state(&self, out: &mut StateWriter) {
+		out.u8(self.duty);
+		out.u8(self.sequence_step);
+		out.u16(self.timer_period);
+		out.u16(self.timer);
+		self.envelope.save_state(out);
+		self.sweep.save_state(out);
+		self.length_counter.save_state(out);
+	}
+
+	fn load_state(&mut self, input: &mut StateReader) -> Result<(), String> {
+		self.duty = input.u8()?;
+		self.sequence_step = input.u8()?;
+		self.timer_period = input.u16()?;
+		self.timer = input.u16()?;
+		self.envelope.load_state(input)?;
+		self.sweep.load_state(input)?;
+		self.length_counter.load_state(input)
+	}
+}
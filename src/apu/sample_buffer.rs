@@ -0,0 +1,97 @@
+use std::collections::VecDeque;
+use std::sync::{Arc, Mutex};
+
+/// Audio samples, shared between the APU (the producer) and the audio output (the consumer).
+/// Cloning gives another handle to the same buffer, so the audio callback can run on its own thread.
+///
+/// The emulator and the sound card never run at exactly the same speed, so the buffer must survive both:
+/// * Overrun (the producer is faster): the oldest samples are dropped.
+/// * Underrun (the consumer is faster): `fill` pads with the last sample, which is silent, unlike padding with zeros.
+#[derive(Clone)]
+pub struct SampleBuffer {
+	inner: Arc<Mutex<Inner>>,
+}
+
+struct Inner {
+	samples: VecDeque<f32>,
+	capacity: usize,
+	last_sample: f32,
+}
+
+impl SampleBuffer {
+	pub fn new(capacity: usize) -> Self {
+		SampleBuffer {
+			inner: Arc::new(Mutex::new(Inner {
+				samples: VecDeque::with_capacity(capacity),
+				capacity,
+				last_sample: 0.0,
+			})),
+		}
+	}
+
+	pub fn push(&self, sample: f32) {
+		let mut inner = self.inner.lock().unwrap();
+		if inner.samples.len() == inner.capacity {
+			inner.samples.pop_front();
+		}
+		inner.samples.push_back(sample);
+	}
+
+	/// Move all the samples in the buffer to the end of `out`.
+	pub fn take(&self, out: &mut Vec<f32>) {
+		let mut inner = self.inner.lock().unwrap();
+		if let Some(&last) = inner.samples.back() {
+			inner.last_sample = last;
+		}
+		out.extend(inner.samples.drain(..));
+	}
+
+	/// Fill `out` completely, for audio callbacks that need an exact amount of samples.
+	pub fn fill(&self, out: &mut [f32]) {
+		let mut inner = self.inner.lock().unwrap();
+		for sample in out.iter_mut() {
+			if let Some(next) = inner.samples.pop_front() {
+				inner.last_sample = next;
+			}
+			*sample = inner.last_sample;
+		}
+	}
+
+	pub fn len(&self) -> usize {
+		self.inner.lock().unwrap().samples.len()
+	}
+
+	pub fn is_empty(&self) -> bool {
+		self.len() == 0
+	}
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+
+	#[test]
+	fn overrun_test() {
+		let buffer = SampleBuffer::new(4);
+		for i in 0..6 {
+			buffer.push(i as f32);
+		}
+
+		let mut samples = vec![];
+		buffer.take(&mut samples);
+		assert_eq!(samples, vec![2.0, 3.0, 4.0, 5.0]);
+		assert!(buffer.is_empty());
+	}
+
+	#[test]
+	fn underrun_test() {
+		let buffer = SampleBuffer::new(4);
+		let consumer = buffer.clone();
+		buffer.push(0.25);
+		buffer.push(0.5);
+
+		let mut samples = [0.0; 5];
+		consumer.fill(&mut samples);
+		assert_eq!(samples, [0.25, 0.5, 0.5, 0.5, 0.5]);
+	}
+}
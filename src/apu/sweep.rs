@@ -0,0 +1,178 @@
+// Sweep unit: https://www.nesdev.org/wiki/APU_Sweep
+//
+// Each pulse channel has one. It changes the timer period every few half frames, so the pitch slides up or down.
+//
+// | Bits of $4001 / $4005 | Description |
+// |---|---|
+// | E--- ---- | Enabled |
+// | -PPP ---- | Divider period: the period changes every P + 1 half frames |
+// | ---- N--- | Negate: the change is subtracted, so the pitch goes up |
+// | ---- -SSS | Shift: the change is the period shifted right by S |
+//
+// The target period (period ± change) is calculated all the time. If it's over $7FF, or the period is under 8, the
+// channel is muted, even when the sweep is disabled. Writing the register reloads the divider at the next half frame.
+//
+// The two pulse channels negate differently: pulse 1 with ones' complement (subtracting one more), and pulse 2 with
+// two's complement.
+
+use crate::save_state::{SaveState, StateReader, StateWriter};
+
+/// Which pulse channel this is. They differ only in how the sweep unit negates.
+#[derive(Clone, Copy, PartialEq, Debug)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub enum PulseChannel {
+	/// Negates with ones' complement: the change is subtracted, and then 1 more.
+	One,
+	/// Negates with two's complement.
+	Two,
+}
+
+/// Periodically changes the timer period, making the pitch go up or down.
+#[derive(Clone)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub(super) struct Sweep {
+	enabled: bool,
+	period: u8,
+	negate: bool,
+	shift: u8,
+	reload: bool,
+	divider: u8,
+	channel: PulseChannel,
+}
+
+impl Sweep {
+	pub fn new(channel: PulseChannel) -> Self {
+		Sweep {
+			enabled: false,
+			period: 0,
+			negate: false,
+			shift: 0,
+			reload: false,
+			divider: 0,
+			channel,
+		}
+	}
+
+	pub fn write(&mut self, data: u8) {
+		self.enabled = data & 0x80 != 0;
+		self.period = (data >> 4) & 0b111;
+		self.negate = data & 0x08 != 0;
+		self.shift = data & 0b111;
+		self.reload = true;
+	}
+
+	/// The sweep calculates the target period all the time, even when it's disabled.
+	pub fn target_period(&self, timer_period: u16) -> u16 {
+		let change = timer_period >> self.shift;
+		if !self.negate {
+			return timer_period + change;
+		}
+
+		match self.channel {
+			PulseChannel::One => timer_period.saturating_sub(change + 1),
+			PulseChannel::Two => timer_period.saturating_sub(change),
+		}
+	}
+
+	/// The channel is muted if the period is too low, or the target period overflows 11 bits.
+	pub fn mutes(&self, timer_period: u16) -> bool {
+		timer_period < 8 || self.target_period(timer_period) > 0x7FF
+	}
+
+	/// Clocked by half frames. Returns the new timer period.
+	pub fn clock(&mut self, timer_period: u16) -> u16 {
+		let mut new_period = timer_period;
+		if self.divider == 0 && self.enabled && self.shift > 0 && !self.mutes(timer_period) {
+			new_period = self.target_period(timer_period);
+		}
+
+		if self.divider == 0 || self.reload {
+			self.divider = self.period;
+			self.reload = false;
+		} else {
+			self.divider -= 1;
+		}
+
+		new_period
+	}
+}
+
+/// The channel is fixed, it's not saved.
+impl SaveState for Sweep {
+	fn save_state(&self, out: &mut StateWriter) {
+		out.bool(self.enabled);
+		out.u8(self.period);
+		out.bool(self.negate);
+		out.u8(self.shift);
+		out.bool(self.reload);
+		out.u8(self.divider);
+	}
+
+	fn load_state(&mut self, input: &mut StateReader) -> Result<(), String> {
+		self.enabled = input.bool()?;
+		self.period = input.u8()?;
+		self.negate = input.bool()?;
+		self.shift = input.u8()?;
+		self.reload = input.bool()?;
+		self.divider = input.u8()?;
+		Ok(())
+	}
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+
+	#[test]
+	fn sweep_negate_test() {
+		let mut pulse1 = Sweep::new(PulseChannel::One);
+		let mut pulse2 = Sweep::new(PulseChannel::Two);
+		pulse1.write(0b1000_1001); // Negate, shift 1
+		pulse2.write(0b1000_1001);
+
+		// Pulse 1 subtracts one more.
+		assert_eq!(pulse1.target_period(0x100), 0x7F);
+		assert_eq!(pulse2.target_period(0x100), 0x80);
+		// Shift 0 with negate: the change is the whole period.
+		pulse1.write(0b1000_1000);
+		pulse2.write(0b1000_1000);
+		assert_eq!(pulse1.target_period(0x100), 0);
+		assert_eq!(pulse2.target_period(0x100), 0);
+	}
+
+	#[test]
+	fn sweep_divider_test() {
+		// Divider period 2: the period changes every 3 half frames. Reloaded at the first clock after the write.
+		let mut sweep = Sweep::new(PulseChannel::Two);
+		sweep.write(0b1010_0010); // Enabled, period 2, shift 2
+		let mut period = 0x100;
+		let mut periods = vec![];
+		for _ in 0..7 {
+			period = sweep.clock(period);
+			periods.push(period);
+		}
+		assert_eq!(periods, [0x140, 0x140, 0x140, 0x190, 0x190, 0x190, 0x1F4]);
+
+		// Shift 0 or disabled: the divider runs, but the period stays.
+		sweep.write(0b1010_0000);
+		assert_eq!(sweep.clock(0x100), 0x100);
+		sweep.write(0b0010_0010);
+		assert_eq!(sweep.clock(0x100), 0x100);
+	}
+
+	#[test]
+	fn sweep_mute_test() {
+		let mut sweep = Sweep::new(PulseChannel::One);
+		// Disabled, shift 0: the target is twice the period.
+		assert!(!sweep.mutes(0x3FF));
+		assert!(sweep.mutes(0x400));
+		// Too low.
+		assert!(sweep.mutes(7));
+		assert!(!sweep.mutes(8));
+
+		// A muting sweep doesn't change the period.
+		sweep.write(0b1000_0001);
+		assert!(sweep.mutes(0x600));
+		assert_eq!(sweep.clock(0x600), 0x600);
+	}
+}
@@ -0,0 +1,178 @@
+// Triangle channel: https://www.nesdev.org/wiki/APU_Triangle
+//
+// | Register | Bits | Description |
+// |---|---|---|
+// | $4008 | CRRR RRRR | Length counter halt / linear counter control (C), linear counter reload value (R) |
+// | $400A | TTTT TTTT | Timer low 8 bits |
+// | $400B | LLLL LTTT | Length counter load (L), timer high 3 bits |
+
+use super::length_counter::LengthCounter;
+use crate::save_state::{SaveState, StateReader, StateWriter};
+
+const TRIANGLE_SEQUENCE: [u8; 32] = [
+	15, 14, 13, 12, 11, 10, 9, 8, 7, 6, 5, 4, 3, 2, 1, 0,
+	0, 1, 2, 3, 4, 5, 6, 7, 8, 9, 10, 11, 12, 13, 14, 15,
+];
+
+#[derive(Default, Clone)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct Triangle {
+	sequence_step: u8,
+	timer_period: u16,
+	timer: u16,
+	/// The control flag is also the length counter halt flag.
+	control: bool,
+	linear_counter: u8,
+	linear_counter_reload_value: u8,
+	linear_counter_reload: bool,
+	length_counter: LengthCounter,
+}
+
+impl Triangle {
+	pub fn new() -> Self {
+		Self::default()
+	}
+
+	/// Write one of the registers of the channel, `register` is 0-3 ($4009 is unused).
+	pub fn write(&mut self, register: u16, data: u8) {
+		match register {
+			0 => {
+				self.control = data & 0x80 != 0;
+				self.length_counter.set_halt(self.control);
+				self.linear_counter_reload_value = data & 0x7F;
+			}
+			1 => {}
+			2 => self.timer_period = (self.timer_period & 0x0700) | data as u16,
+			3 => {
+				self.timer_period = (self.timer_period & 0x00FF) | (((data & 0b111) as u16) << 8);
+				self.length_counter.load(data >> 3);
+				self.linear_counter_reload = true;
+			}
+			_ => unreachable!("Triangle channel has only 4 registers"),
+		}
+	}
+
+	pub fn set_enabled(&mut self, enabled: bool) {
+		self.length_counter.set_enabled(enabled);
+	}
+
+	pub fn active(&self) -> bool {
+		self.length_counter.active()
+	}
+
+	/// Unlike the other channels, the triangle timer is clocked every CPU cycle.
+	pub fn clock_timer(&mut self) {
+		if self.timer > 0 {
+			self.timer -= 1;
+			return;
+		}
+
+		self.timer = self.timer_period;
+		// The sequencer only moves while both counters are not zero. Silencing the channel freezes the output
+		// at its current level, instead of dropping to 0 (which would click).
+		// NOTE: Periods 0 and 1 are ultrasonic. Some emulators silence them, but the hardware plays them,
+		// and the averaged level causes audible "pops" that some games rely on. So I play them too.
+		if self.linear_counter > 0 && self.length_counter.active() {
+			self.sequence_step = (self.sequence_step + 1) % 32;
+		}
+	}
+
+	pub fn quarter_frame(&mut self) {
+		if self.linear_counter_reload {
+			self.linear_counter = self.linear_counter_reload_value;
+		} else if self.linear_counter > 0 {
+			self.linear_counter -= 1;
+		}
+
+		if !self.control {
+			self.linear_counter_reload = false;
+		}
+	}
+
+	pub fn half_frame(&mut self) {
+		self.length_counter.clock();
+	}
+
+	/// The end of a CPU cycle, see `LengthCounter::end_cycle`.
+	pub fn end_cycle(&mut self) {
+		self.length_counter.end_cycle();
+	}
+
+	/// Current amplitude, 0-15.
+	pub fn output(&self) -> u8 {
+		TRIANGLE_SEQUENCE[self.sequence_step as usize]
+	}
+}
+
+impl SaveState for Triangle {
+	fn save_state(&self, out: &mut StateWriter) {
+		out.u8(self.sequence_step);
+		out.u16(self.timer_period);
+		out.u16(self.timer);
+		out.bool(self.control);
+		out.u8(self.linear_counter);
+		out.u8(self.linear_counter_reload_value);
+		out.bool(self.linear_counter_reload);
+		self.length_counter.save_state(out);
+	}
+
+	fn load_state(&mut self, input: &mut StateReader) -> Result<(), String> {
+		self.sequence_step = input.u8()?;
+		self.timer_period = input.u16()?;
+		self.timer = input.u16()?;
+		self.control = input.bool()?;
+		self.linear_counter = input.u8()?;
+		self.linear_counter_reload_value = input.u8()?;
+		self.linear_counter_reload = input.bool()?;
+		self.length_counter.load_state(input)
+	}
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+
+	#[test]
+	fn triangle_freeze_test() {
+		let mut triangle = Triangle::new();
+		triangle.set_enabled(true);
+		triangle.write(0, 0x7F);		// Linear counter reload 127, not halted
+		triangle.write(2, 0x00);
+		triangle.write(3, 0b0001_1000);	// Length 2, period 0: a step every CPU cycle
+		triangle.end_cycle();
+		triangle.quarter_frame();
+
+		for _ in 0..5 {
+			triangle.clock_timer();
+		}
+		assert_eq!(triangle.output(), 10);
+
+		// Length counter reaches 0: the output freezes, instead of going to 0.
+		triangle.half_frame();
+		triangle.half_frame();
+		assert!(!triangle.active());
+		for _ in 0..100 {
+			triangle.clock_timer();
+			assert_eq!(triangle.output(), 10);
+		}
+	}
+
+	#[test]
+	fn linear_counter_test() {
+		let mut triangle = Triangle::new();
+		triangle.set_enabled(true);
+		triangle.write(0, 0x02);		// Linear counter reload 2
+		triangle.write(3, 0b1111_1000);
+		triangle.quarter_frame();
+		triangle.quarter_frame();
+		triangle.quarter_frame();
+
+		// Linear counter is 0, so the sequencer stopped.
+		let output = triangle.output();
+		for _ in 0..100 {
+			triangle.clock_timer();
+		}
+		assert_eq!(triangle.output(), output);
+		assert!(triangle.active());
+	}
+}
@@ -0,0 +1,49 @@
+// Base64 (RFC 4648, with padding), for binary data in text: the movie header, and states serialized to JSON.
+
+const ALPHABET: &[u8; 64] = b"ABCDEFGHIJKLMNOPQRSTUVWXYZabcdefghijklmnopqrstuvwxyz0123456789+/";
+
+pub fn encode(bytes: &[u8]) -> String {
+	let mut out = String::new();
+	for chunk in bytes.chunks(3) {
+		let value = (chunk[0] as u32) << 16 | (*chunk.get(1).unwrap_or(&0) as u32) << 8 | *chunk.get(2).unwrap_or(&0) as u32;
+		for i in 0..4 {
+			if i <= chunk.len() {
+				out.push(ALPHABET[(value >> (18 - 6 * i) & 0x3F) as usize] as char);
+			} else {
+				out.push('=');
+			}
+		}
+	}
+	out
+}
+
+pub fn decode(text: &str) -> Option<Vec<u8>> {
+	let digits: Vec<u32> = text.trim_end_matches('=').bytes()
+		.map(|c| ALPHABET.iter().position(|&a| a == c).map(|digit| digit as u32))
+		.collect::<Option<_>>()?;
+	let mut out = vec![];
+	for chunk in digits.chunks(4) {
+		if chunk.len() == 1 {
+			return None;
+		}
+		let value = chunk.iter().enumerate().fold(0, |value, (i, digit)| value | digit << (18 - 6 * i));
+		out.extend(value.to_be_bytes()[1..chunk.len()].iter());
+	}
+	Some(out)
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+
+	#[test]
+	fn encode_decode_test() {
+		for bytes in [&b""[..], b"f", b"fo", b"foo", b"foob", b"fooba", b"foobar"] {
+			assert_eq!(decode(&encode(bytes)).unwrap(), bytes);
+		}
+		assert_eq!(encode(b"foobar"), "Zm9vYmFy");
+		assert_eq!(encode(b"fo"), "Zm8=");
+		assert_eq!(decode("Zm8="), Some(b"fo".to_vec()));
+		assert_eq!(decode("Z!=="), None);
+	}
+}
@@ -0,0 +1,341 @@
+// Compatibility runs (--batch DIR): every .nes file of a directory runs headless for some frames, and the report has a
+// row for each, to follow the compatibility as the mappers and the PPU get better:
+//
+// | Column | Description |
+// |---|---|
+// | rom | The file name |
+// | loaded | yes, or no: not an iNES file, or a mapper that isn't supported (`detail` says which) |
+// | mapper | The iNES mapper number, when the file has the header |
+// | result | `ran` all the frames, `not loaded`, `jammed` (stuck in a loop no interrupt can leave, see stuck.rs), `cpu error` (an illegal opcode), or `panic` |
+// | frames | Frames it ran |
+// | rendered | yes when a frame had more than one color |
+// | frame_hash | `Emulator::frame_hash` of the last frame |
+// | seconds | Wall clock time of the run |
+// | speed | The multiple of real time, like in benchmark mode (bench.rs) |
+// | detail | The error, the PC of the loop, or the panic message |
+//
+// A ROM runs in `catch_unwind`, so a panic of the emulator is a row of the report, and the batch goes on. The default
+// panic hook still prints it to stderr.
+
+use std::fmt;
+use std::io::{self, Write};
+use std::panic::{self, AssertUnwindSafe};
+use std::path::{Path, PathBuf};
+use std::time::{Duration, Instant};
+
+use crate::cpu::cpu::CpuError;
+use crate::cpu::status::Flag;
+use crate::cpu::stuck::{StuckDetection, StuckDetector};
+use crate::emulator::Emulator;
+use crate::error::NesError;
+
+/// The columns of the report, see the top of the file.
+pub const COLUMNS: [&str; 10] = ["rom", "loaded", "mapper", "result", "frames", "rendered", "frame_hash", "seconds", "speed", "detail"];
+
+/// Makes the emulator of a ROM file, with the settings of the batch.
+pub type EmulatorLoader<'a> = &'a dyn Fn(&[u8]) -> Result<Emulator, NesError>;
+
+/// How a ROM ended, see the top of the file.
+#[derive(Clone, PartialEq, Eq, Debug)]
+pub enum BatchResult {
+	Ran,
+	NotLoaded(String),
+	Jammed { pc: u16 },
+	CpuError(CpuError),
+	Panic(String),
+}
+
+impl BatchResult {
+	/// The `result` column.
+	pub fn name(&self) -> &'static str {
+		match self {
+			BatchResult::Ran => "ran",
+			BatchResult::NotLoaded(_) => "not loaded",
+			BatchResult::Jammed { .. } => "jammed",
+			BatchResult::CpuError(_) => "cpu error",
+			BatchResult::Panic(_) => "panic",
+		}
+	}
+
+	/// The `detail` column.
+	pub fn detail(&self) -> String {
+		match self {
+			BatchResult::Ran => String::new(),
+			BatchResult::NotLoaded(message) | BatchResult::Panic(message) => message.clone(),
+			BatchResult::Jammed { pc } => format!("Stuck in a loop at ${:04X}", pc),
+			BatchResult::CpuError(err) => err.to_string(),
+		}
+	}
+}
+
+/// A row of the report.
+#[derive(Clone, PartialEq, Debug)]
+pub struct BatchRow {
+	pub rom: String,
+	pub mapper: Option<u8>,
+	pub result: BatchResult,
+	pub frames: u64,
+	pub rendered: bool,
+	/// None when it didn't load, or panicked.
+	pub frame_hash: Option<u64>,
+	pub elapsed: Duration,
+	/// None when it didn't run a frame.
+	pub speed: Option<f64>,
+}
+
+/// The .nes files of `dir` (any case), by name.
+pub fn rom_files(dir: &Path) -> Result<Vec<PathBuf>, String> {
+	let entries = std::fs::read_dir(dir).map_err(|err| format!("Can't read {}: {}", dir.display(), err))?;
+	let mut files: Vec<PathBuf> = entries
+		.filter_map(|entry| entry.ok().map(|entry| entry.path()))
+		.filter(|path| path.is_file() && path.extension().is_some_and(|extension| extension.eq_ignore_ascii_case("nes")))
+		.collect();
+	files.sort();
+	Ok(files)
+}
+
+/// Run every ROM of `dir` for `frames` frames, see `run_rom`. `on_row` gets every row as soon as it's done, to show
+/// the progress of a long batch.
+pub fn run_dir(dir: &Path, frames: u64, load: EmulatorLoader, mut on_row: impl FnMut(&BatchRow)) -> Result<Vec<BatchRow>, String> {
+	let mut rows = vec![];
+	for path in rom_files(dir)? {
+		let name = path.file_name().map_or(String::new(), |name| name.to_string_lossy().into_owned());
+		let row = match std::fs::read(&path) {
+			Ok(bytes) => run_rom(&name, &bytes, frames, load),
+			Err(err) => BatchRow::not_loaded(&name, None, NesError::io(&path, err).into()),
+		};
+		on_row(&row);
+		rows.push(row);
+	}
+	Ok(rows)
+}
+
+/// Load and run a ROM for `frames` frames, or until it jams or the CPU can't go on. A panic is caught, and is the
+/// result.
+pub fn run_rom(name: &str, bytes: &[u8], frames: u64, load: EmulatorLoader) -> BatchRow {
+	let mapper = ines_mapper(bytes);
+	let start = Instant::now();
+	let mut row = BatchRow::not_loaded(name, mapper, String::new());
+	let result = panic::catch_unwind(AssertUnwindSafe(|| {
+		let mut emulator = match load(bytes) {
+			Ok(emulator) => emulator,
+			Err(err) => return BatchResult::NotLoaded(err.into()),
+		};
+		row.mapper = Some(emulator.bus().cartridge().mapper());
+		let result = run(&mut emulator, frames, &mut row);
+		row.frame_hash = Some(emulator.frame_hash());
+		row.speed = (row.frames > 0).then(|| {
+			let emulated = emulator.region().frame_nanos() * row.frames as f64 / 1e9;
+			emulated / start.elapsed().as_secs_f64()
+		});
+		result
+	}));
+	row.result = result.unwrap_or_else(|payload| {
+		let message = payload.downcast_ref::<&str>().map(|message| message.to_string())
+			.or_else(|| payload.downcast_ref::<String>().cloned())
+			.unwrap_or_else(|| "A panic without a message".to_string());
+		// What it had before the panic is not to be trusted.
+		row.frame_hash = None;
+		row.speed = None;
+		BatchResult::Panic(message)
+	});
+	row.elapsed = start.elapsed();
+	row
+}
+
+/// The frames of `run_rom`, counting them and the ones with a picture in `row`.
+fn run(emulator: &mut Emulator, frames: u64, row: &mut BatchRow) -> BatchResult {
+	let mut detector = StuckDetector::new(StuckDetection::default());
+	while row.frames < frames {
+		let before = emulator.cpu_state();
+		if let Err(err) = emulator.try_step_instruction() {
+			return BatchResult::CpuError(err);
+		}
+		if emulator.take_frame_complete() {
+			row.frames += 1;
+			let pixels = emulator.framebuffer().pixels();
+			row.rendered |= pixels.iter().any(|&pixel| pixel != pixels[0]);
+		}
+		// Like `Harness::detect_stuck_loops`: a loop that waits for an interrupt that can come is not stuck.
+		let after = emulator.cpu_state();
+		let interruptible = emulator.bus().ppu().registers.ppuctrl.generate_nmi() != 0 || !after.flags().get(Flag::INTERRUPT_DISABLE);
+		if let Some(pc) = detector.check(&before, &after, emulator.wrote_memory(), interruptible) {
+			return BatchResult::Jammed { pc };
+		}
+	}
+	BatchResult::Ran
+}
+
+/// The mapper number in an iNES header, if `bytes` starts with one.
+fn ines_mapper(bytes: &[u8]) -> Option<u8> {
+	(bytes.len() >= 8 && bytes.starts_with(b"NES\x1A")).then(|| (bytes[6] >> 4) | (bytes[7] & 0xF0))
+}
+
+impl BatchRow {
+	pub fn loaded(&self) -> bool {
+		!matches!(self.result, BatchResult::NotLoaded(_))
+	}
+
+	fn not_loaded(rom: &str, mapper: Option<u8>, message: String) -> Self {
+		BatchRow {
+			rom: rom.to_string(),
+			mapper,
+			result: BatchResult::NotLoaded(message),
+			frames: 0,
+			rendered: false,
+			frame_hash: None,
+			elapsed: Duration::ZERO,
+			speed: None,
+		}
+	}
+
+	/// The values of the `COLUMNS`, as strings.
+	fn columns(&self) -> [String; 10] {
+		let yes_no = |yes: bool| if yes { "yes" } else { "no" }.to_string();
+		[
+			self.rom.clone(),
+			yes_no(self.loaded()),
+			self.mapper.map_or(String::new(), |mapper| mapper.to_string()),
+			self.result.name().to_string(),
+			self.frames.to_string(),
+			yes_no(self.rendered),
+			self.frame_hash.map_or(String::new(), |hash| format!("{:016X}", hash)),
+			format!("{:.3}", self.elapsed.as_secs_f64()),
+			self.speed.map_or(String::new(), |speed| format!("{:.2}", speed)),
+			self.result.detail(),
+		]
+	}
+}
+
+/// A CSV line of the row, like the ones of `write_csv`.
+impl fmt::Display for BatchRow {
+	fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+		let fields: Vec<String> = self.columns().iter().map(|value| csv_field(value)).collect();
+		write!(f, "{}", fields.join(","))
+	}
+}
+
+/// The report as CSV: a header line, then a line for each row.
+pub fn write_csv(rows: &[BatchRow], out: &mut impl Write) -> io::Result<()> {
+	writeln!(out, "{}", COLUMNS.join(","))?;
+	for row in rows {
+		writeln!(out, "{}", row)?;
+	}
+	Ok(())
+}
+
+/// The report as JSON: an array with an object for each row, with the names and values of the CSV. `mapper`, `frames`,
+/// `seconds` and `speed` are numbers (or null), `loaded` and `rendered` booleans, and the others strings.
+pub fn write_json(rows: &[BatchRow], out: &mut impl Write) -> io::Result<()> {
+	writeln!(out, "[")?;
+	for (i, row) in rows.iter().enumerate() {
+		let fields: Vec<String> = COLUMNS.iter().zip(row.columns()).map(|(&name, value)| {
+			let value = match name {
+				"loaded" | "rendered" => (value == "yes").to_string(),
+				"mapper" | "frames" | "seconds" | "speed" if value.is_empty() => "null".to_string(),
+				"mapper" | "frames" | "seconds" | "speed" => value,
+				_ => json_string(&value),
+			};
+			format!("\"{}\": {}", name, value)
+		}).collect();
+		let comma = if i + 1 < rows.len() { "," } else { "" };
+		writeln!(out, "  {{{}}}{}", fields.join(", "), comma)?;
+	}
+	writeln!(out, "]")
+}
+
+/// In quotes when it has a comma, a quote or a line break, with the quotes doubled.
+fn csv_field(value: &str) -> String {
+	if value.contains([',', '"', '\n', '\r']) {
+		format!("\"{}\"", value.replace('"', "\"\""))
+	} else {
+		value.to_string()
+	}
+}
+
+fn json_string(value: &str) -> String {
+	let mut json = String::from("\"");
+	for c in value.chars() {
+		match c {
+			'"' => json.push_str("\\\""),
+			'\\' => json.push_str("\\\\"),
+			'\n' => json.push_str("\\n"),
+			c if (c as u32) < 0x20 => json.push_str(&format!("\\u{:04x}", c as u32)),
+			c => json.push(c),
+		}
+	}
+	json.push('"');
+	json
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+	use crate::cartridge::{test_rom, Cartridge};
+
+	fn load(bytes: &[u8]) -> Result<Emulator, NesError> {
+		Ok(Emulator::new(Cartridge::from_ines(bytes)?))
+	}
+
+	#[test]
+	fn batch_test() {
+		let dir = std::env::temp_dir().join(format!("nes-batch-{}", std::process::id()));
+		std::fs::create_dir_all(&dir).unwrap();
+		// Counts in $10 forever: the writes make it a loop that isn't stuck.
+		/*
+		loop:
+		INC $10
+		JMP loop
+		*/
+		std::fs::write(dir.join("a-nrom.nes"), test_rom::nrom("E6 10 4C 00 80")).unwrap();
+		std::fs::write(dir.join("b-mapper9.NES"), test_rom::ines(9, &[0; 0x4000], &[])).unwrap();
+		std::fs::write(dir.join("c-illegal.nes"), test_rom::nrom("EA 02")).unwrap();
+		std::fs::write(dir.join("d-jam.nes"), test_rom::nrom("78 4C 01 80")).unwrap();
+		std::fs::write(dir.join("readme.txt"), "Not a ROM").unwrap();
+
+		let mut seen = vec![];
+		let mut rows = run_dir(&dir, 10, &load, |row| seen.push(row.rom.clone())).unwrap();
+		std::fs::remove_dir_all(&dir).unwrap();
+		assert_eq!(seen, ["a-nrom.nes", "b-mapper9.NES", "c-illegal.nes", "d-jam.nes"]);
+
+		let nrom = rows[0].clone();
+		assert!(nrom.loaded());
+		assert_eq!((nrom.mapper, &nrom.result, nrom.frames), (Some(0), &BatchResult::Ran, 10));
+		assert!(nrom.frame_hash.is_some() && nrom.speed.is_some());
+
+		let mapper9 = &rows[1];
+		assert!(!mapper9.loaded());
+		assert_eq!((mapper9.mapper, &mapper9.result, mapper9.frames), (Some(9), &BatchResult::NotLoaded("Mapper 9 is not supported".to_string()), 0));
+		assert_eq!(mapper9.frame_hash, None);
+
+		assert_eq!(rows[2].result, BatchResult::CpuError(CpuError::IllegalOpcode { pc: 0x8001, opcode: 0x02 }));
+		// With the I flag set and the NMI off, nothing can leave the loop.
+		assert_eq!(rows[3].result, BatchResult::Jammed { pc: 0x8001 });
+
+		// The times change from run to run.
+		for row in &mut rows {
+			row.elapsed = Duration::ZERO;
+		}
+		let mut csv = vec![];
+		write_csv(&rows, &mut csv).unwrap();
+		let csv = String::from_utf8(csv).unwrap();
+		let lines: Vec<&str> = csv.lines().collect();
+		assert_eq!(lines[0], "rom,loaded,mapper,result,frames,rendered,frame_hash,seconds,speed,detail");
+		assert!(lines[1].starts_with(&format!("a-nrom.nes,yes,0,ran,10,no,{:016X},", nrom.frame_hash.unwrap())), "{}", lines[1]);
+		assert_eq!(lines[2], "b-mapper9.NES,no,9,not loaded,0,no,,0.000,,Mapper 9 is not supported");
+		assert!(lines[4].ends_with(",Stuck in a loop at $8001"), "{}", lines[4]);
+
+		let mut json = vec![];
+		write_json(&rows[1..2], &mut json).unwrap();
+		assert_eq!(String::from_utf8(json).unwrap(), "[\n  {\"rom\": \"b-mapper9.NES\", \"loaded\": false, \"mapper\": 9, \"result\": \"not loaded\", \"frames\": 0, \"rendered\": false, \"frame_hash\": \"\", \"seconds\": 0.000, \"speed\": null, \"detail\": \"Mapper 9 is not supported\"}\n]\n");
+	}
+
+	#[test]
+	fn panic_test() {
+		let row = run_rom("panic.nes", &test_rom::nrom("EA"), 10, &|_| panic!("Broken mapper"));
+		assert_eq!(row.result, BatchResult::Panic("Broken mapper".to_string()));
+		assert_eq!(row.mapper, Some(0));
+		assert_eq!(row.to_string().split(',').nth(3), Some("panic"));
+		assert_eq!(csv_field("a,\"b\""), "\"a,\"\"b\"\"\"");
+	}
+}
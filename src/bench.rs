@@ -0,0 +1,180 @@
+// Benchmark mode (--bench): run headless as fast as possible, and report the emulated speed as a single
+// key=value line, so it can be compared across commits:
+//
+// bench program=game.nes region=NTSC seconds=10.000 frames=6012 cycles=179041237 cycles_per_second=17904123 fps=601.2 speed=10.00
+//
+// `speed` is the multiple of real time: 1.00 runs exactly as fast as the console.
+//
+// There is no trace or debugger in benchmark mode, and the log level is at most info, so they don't slow it down.
+//
+// For the CPU hot path itself there are criterion benchmarks in benches/cpu.rs, run them with:
+// cargo bench --bench cpu
+
+use std::fmt;
+use std::time::{Duration, Instant};
+
+use crate::bus::{Bus, FlatBus};
+use crate::cpu::cpu::CPU;
+use crate::emulator::Emulator;
+use crate::region::Region;
+
+/// How long to run.
+#[derive(Clone, Copy, PartialEq, Debug)]
+pub enum BenchBudget {
+	Seconds(f64),
+	Frames(u64),
+}
+
+/// A program to benchmark.
+pub trait BenchTarget {
+	/// Run a frame worth of emulation, and return the CPU cycles it took.
+	fn bench_frame(&mut self) -> u64;
+	fn region(&self) -> Region;
+}
+
+impl BenchTarget for Emulator {
+	fn bench_frame(&mut self) -> u64 {
+		let start = self.cycles();
+		self.run_frame();
+		self.cycles() - start
+	}
+
+	fn region(&self) -> Region {
+		Emulator::region(self)
+	}
+}
+
+/// A program on flat memory (demos, raw binaries). There is no PPU, so a frame is the CPU cycles of an NTSC frame.
+/// Programs end at a BRK, like in `run_flat`, and then they start again from the reset vector, so the CPU never idles.
+pub struct FlatBench {
+	cpu: CPU<FlatBus>,
+}
+
+impl FlatBench {
+	pub fn new(mut cpu: CPU<FlatBus>) -> Self {
+		cpu.reset();
+		FlatBench { cpu }
+	}
+}
+
+impl BenchTarget for FlatBench {
+	fn bench_frame(&mut self) -> u64 {
+		let start = self.cpu.cycles();
+		let end = start + self.region().cpu_cycles_per_frame() as u64;
+		while self.cpu.cycles() < end {
+			let pc = self.cpu.registers().PC;
+			if self.cpu.bus().peek(pc) == 0x00 {
+				self.cpu.reset();
+			}
+			self.cpu.clock_tick();
+		}
+		self.cpu.cycles() - start
+	}
+
+	fn region(&self) -> Region {
+		Region::default()
+	}
+}
+
+#[derive(Clone, PartialEq, Debug)]
+pub struct BenchResult {
+	pub program: String,
+	pub region: Region,
+	pub elapsed: Duration,
+	pub frames: u64,
+	pub cycles: u64,
+}
+
+impl BenchResult {
+	pub fn cycles_per_second(&self) -> f64 {
+		self.cycles as f64 / self.elapsed.as_secs_f64()
+	}
+
+	pub fn frames_per_second(&self) -> f64 {
+		self.frames as f64 / self.elapsed.as_secs_f64()
+	}
+
+	/// Multiple of real time.
+	pub fn speed(&self) -> f64 {
+		self.cycles_per_second() / self.region.cpu_clock_rate() as f64
+	}
+}
+
+impl fmt::Display for BenchResult {
+	fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+		write!(f, "bench program={} region={} seconds={:.3} frames={} cycles={} cycles_per_second={:.0} fps={:.1} speed={:.2}",
+			self.program, self.region, self.elapsed.as_secs_f64(), self.frames, self.cycles, self.cycles_per_second(), self.frames_per_second(), self.speed())
+	}
+}
+
+/// Run `target` frame after frame until the budget is used.
+pub fn run<T: BenchTarget>(target: &mut T, program: &str, budget: BenchBudget) -> BenchResult {
+	let start = Instant::now();
+	let mut frames = 0;
+	let mut cycles = 0;
+	loop {
+		let done = match budget {
+			BenchBudget::Seconds(seconds) => start.elapsed().as_secs_f64() >= seconds,
+			BenchBudget::Frames(max_frames) => frames >= max_frames,
+		};
+		if done {
+			break;
+		}
+		cycles += target.bench_frame();
+		frames += 1;
+	}
+
+	BenchResult {
+		program: program.to_string(),
+		region: target.region(),
+		elapsed: start.elapsed(),
+		frames,
+		cycles,
+	}
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+	use crate::cartridge::{test_rom, Cartridge};
+	use crate::program_loader::load_program_tolower;
+
+	/// A mix of the common instructions: loads, stores, indexing, arithmetic, increments, compare and branch,
+	/// and a PPU register read.
+	fn instruction_mix() -> Emulator {
+		/*
+		start:
+		LDX #$00
+		loop:
+		LDA $0200,X
+		CLC
+		ADC #$03
+		STA $0200,X
+		INC $10
+		INX
+		CPX #$80
+		BNE loop
+		BIT $2002
+		JMP start
+		*/
+		let program = "A2 00 BD 00 02 18 69 03 9D 00 02 E6 10 E8 E0 80 D0 F0 2C 02 20 4C 00 80";
+		Emulator::new(Cartridge::from_ines(&test_rom::nrom(program)).unwrap())
+	}
+
+	#[test]
+	fn frames_budget_test() {
+		let mut emulator = instruction_mix();
+		let result = run(&mut emulator, "mix", BenchBudget::Frames(3));
+		assert_eq!(result.frames, 3);
+		// The reset took 7 before the bench.
+		assert_eq!(result.cycles + 7, emulator.cycles());
+		assert!(result.to_string().starts_with("bench program=mix region=NTSC seconds="));
+
+		// Demos keep running after their BRK.
+		let mut memory = [0; 65_536];
+		load_program_tolower(&mut memory);
+		let mut demo = FlatBench::new(crate::machine::flat(&memory));
+		let result = run(&mut demo, "tolower", BenchBudget::Frames(2));
+		assert!(result.cycles >= 2 * 29_780, "cycles: {}", result.cycles);
+	}
+}
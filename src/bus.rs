@@ -1,16 +1,115 @@
-use crate::memory::{MemoryBus, ROM};
+#[cfg(feature = "std")]
+use core::ops::RangeInclusive;
+
+use crate::irq::IrqLine;
+#[cfg(feature = "std")]
+use crate::access_trace::{AccessKind, AccessKinds, AccessSink, AccessTracer};
+#[cfg(feature = "std")]
+use crate::memory::MemoryBus;
 
 /// Bus is like a container that glue every component together, like on the motherboard.
-pub struct Bus {
+/// The CPU only knows about addresses; what is actually behind each address (RAM, PPU registers, cartridge) is up to the bus.
+pub trait Bus {
+	/// Read a single byte. Reading can have side effects (for example, reading PPU status clears the vblank flag).
+	fn read(&mut self, addr: u16) -> u8;
+
+	/// Read a single byte without side effects, for tools (debugger, test harness).
+	/// Registers that can't be read without side effects return 0.
+	fn peek(&self, addr: u16) -> u8;
+
+	/// Write a single byte.
+	fn write(&mut self, addr: u16, data: u8);
+
+	/// Write a single byte for tools (the debugger's `poke`): memory the CPU can't write, like ROM, is written too.
+	/// Registers are written like `write` does, with its side effects.
+	fn poke(&mut self, addr: u16, data: u8) {
+		self.write(addr, data);
+	}
+
+	/// The CPU spent `cycles` cycles. Devices that run alongside the CPU (like the PPU) catch up here.
+	fn tick(&mut self, _cycles: u8) {}
+
+	/// The CPU is about to execute the instruction at `pc` (or take an interrupt there), `cycles` cycles after it
+	/// was created. For buses that want to know which instruction accessed memory (see access_trace.rs).
+	fn instruction_start(&mut self, _pc: u16, _cycles: u64) {}
+
+	/// The devices holding the IRQ line, see irq.rs.
+	fn irq_sources(&self) -> IrqLine {
+		IrqLine::default()
+	}
+
+	/// State of the IRQ line: any device holds it. The CPU takes an interrupt while it's set, unless interrupts are
+	/// disabled.
+	fn irq_pending(&self) -> bool {
+		self.irq_sources().is_asserted()
+	}
+
+	/// Whether the NMI line went down since the last call (it's edge triggered, unlike the IRQ): the CPU takes it
+	/// after the instruction. A machine without an NMI source never has one.
+	fn take_nmi(&mut self) -> bool {
+		false
+	}
+}
+
+/// The simplest 6502 machine: 64KB of RAM and nothing else. The demo programs run on this.
+#[cfg(feature = "std")]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct FlatBus {
 	pub memory: MemoryBus,
-	pub rom: ROM		// NOTE: The ROM can be as large as 8MB. For now, its 64kb just so I have MVP (minimal viable product).
+	/// None until `trace_accesses`, so there's no cost without it.
+	#[cfg_attr(feature = "serde", serde(skip))]
+	access_trace: Option<Box<AccessTracer>>,
 }
 
-impl Bus {
-	pub fn new(rom: ROM) -> Self {
-		Bus { 
-			memory: MemoryBus::new(), 
-			rom: rom
+#[cfg(feature = "std")]
+impl FlatBus {
+	/// Create bus whose memory is initialized with the given image (program, data, vectors).
+	pub fn new(image: &[u8; 65_536]) -> Self {
+		let mut memory = MemoryBus::new();
+		memory.load(image);
+		FlatBus { memory, access_trace: None }
+	}
+
+	/// Send the reads and/or writes in `range` to `sink`, see access_trace.rs.
+	pub fn trace_accesses(&mut self, range: RangeInclusive<u16>, kinds: AccessKinds, sink: Box<dyn AccessSink>) {
+		self.access_trace.get_or_insert_with(Default::default).add(range, kinds, sink);
+	}
+
+	/// Stop all the access traces.
+	pub fn stop_access_traces(&mut self) {
+		self.access_trace = None;
+	}
+}
+
+#[cfg(feature = "std")]
+impl Bus for FlatBus {
+	fn read(&mut self, addr: u16) -> u8 {
+		let value = self.memory.read(addr);
+		if let Some(trace) = &mut self.access_trace {
+			trace.record(addr, value, AccessKind::Read);
+		}
+		value
+	}
+
+	fn peek(&self, addr: u16) -> u8 {
+		self.memory.read(addr)
+	}
+
+	fn write(&mut self, addr: u16, data: u8) {
+		self.memory.write(addr, data);
+		if let Some(trace) = &mut self.access_trace {
+			trace.record(addr, data, AccessKind::Write);
+		}
+	}
+
+	/// Not an access of the program, so it's not traced.
+	fn poke(&mut self, addr: u16, data: u8) {
+		self.memory.write(addr, data);
+	}
+
+	fn instruction_start(&mut self, pc: u16, cycles: u64) {
+		if let Some(trace) = &mut self.access_trace {
+			trace.instruction_start(pc, cycles);
 		}
 	}
-}
\ No newline at end of file
+}
@@ -0,0 +1,1397 @@
+// iNES file format: https://www.nesdev.org/wiki/INES
+//
+// | Bytes | Description |
+// |---|---|
+// | 0-3 | Constant $4E $45 $53 $1A ("NES" followed by MS-DOS end-of-file) |
+// | 4 | Size of PRG ROM in 16 KB units |
+// | 5 | Size of CHR ROM in 8 KB units (0 means the board uses CHR RAM) |
+// | 6 | Flags 6: Mapper (lower nibble), mirroring, battery, trainer |
+// | 7 | Flags 7: Mapper (upper nibble), VS/Playchoice, NES 2.0 |
+// | 8-15 | Rarely used, or NES 2.0 extensions |
+//
+// If flags 6 bit 2 is set, a 512 bytes trainer comes after the header, before PRG ROM. It's loaded to PRG RAM at
+// $7000-$71FF (some dumps of games patched for copiers have it).
+//
+// NES 2.0 (flags 7 bits 2-3 = 10): https://www.nesdev.org/wiki/NES_2.0
+// `CartridgeHeader` has these fields:
+//
+// | Byte | Description |
+// |---|---|
+// | 8 | Mapper bits 8-11: bits 0-3. Submapper: bits 4-7 (for MMC3, which IRQ variant, see mmc3.rs) |
+// | 9 | PRG ROM size bits 8-11: bits 0-3, CHR ROM size bits 8-11: bits 4-7 (see `rom_size`) |
+// | 10 | PRG RAM size: bits 0-3, PRG NVRAM (battery backed) size: bits 4-7, 64 << n bytes (0 is none) |
+// | 11 | CHR RAM size: bits 0-3, CHR NVRAM size: bits 4-7, the same way |
+// | 12 | Timing: bits 0-1 (0 = NTSC, 1 = PAL, 2 = multiple regions, 3 = Dendy) |
+//
+// A cartridge without CHR ROM has CHR RAM instead, which the game fills through PPUDATA. iNES files don't say how
+// much, so it's 8KB, which is what the PPU sees of it without banks. They don't say how much PRG RAM either, so
+// they all have 8KB at $6000 (battery backed with flags 6 bit 1), which is what most games expect. A NES 2.0 file
+// has what it says: none, and $6000-$7FFF is open bus, or less than 8KB, mirrored.
+//
+// Some dumps have a wrong header. The ROM database (romdb.rs) knows the right mapper, mirroring and region of those,
+// and they win over the header. `header` has what the file says, and `mapper`, `mirroring` and `region` what is used.
+//
+// The mapper (mapper.rs) says where the banks are, the cartridge has the memory:
+//
+// | Mapper | Boards | Banks |
+// |---|---|---|
+// | 0 | NROM | None: 16KB or 32KB of PRG ROM, 8KB of CHR |
+// | 1 | MMC1 (SxROM) | 16KB or 32KB of PRG, 4KB or 8KB of CHR, and mirroring, from a serial port, see mmc1.rs |
+// | 2 | UxROM | A write to $8000-$FFFF selects the 16KB at $8000, and the last 16KB are at $C000. CHR RAM |
+// | 3 | CNROM | A write to $8000-$FFFF selects the 8KB of CHR |
+// | 4 | MMC3 (TxROM) | 8KB of PRG, 1KB of CHR, mirroring and a scanline IRQ, from its registers, see mmc3.rs |
+// | 5 | MMC5 (ExROM) | 8KB to 32KB of PRG, 1KB to 8KB of CHR, PRG RAM, ExRAM, nametables, a scanline IRQ, see mmc5.rs |
+// | 7 | AxROM | A write to $8000-$FFFF selects the 32KB of PRG and the nametable of the single screen. CHR RAM |
+// | 11 | Color Dreams | A write to $8000-$FFFF selects the 32KB of PRG and the 8KB of CHR |
+// | 20 | FDS RAM adapter | 32KB of PRG RAM at $6000-$DFFF, the BIOS at $E000, a timer IRQ, the disk drive and a sound channel, see fds/fds.rs |
+// | 24, 26 | VRC6 | 16KB and 8KB of PRG, 1KB of CHR, mirroring, an IRQ and 3 sound channels, see vrc6.rs |
+// | 66 | GxROM | Like Color Dreams, with the other bits |
+//
+// The Famicom Disk System isn't an iNES file: `from_fds` makes its cartridge from the BIOS of the RAM adapter (as
+// PRG ROM) and a disk image (see fds/disk.rs), which identifies the game like PRG ROM and CHR ROM do.
+//
+// On the boards of NES 2.0 submapper 2 of mappers 2, 3 and 7, and all the Color Dreams and GxROM ones, a write has
+// bus conflicts: the ROM drives the data bus too, and the register gets the AND (see `Mapper::bus_conflicts`).
+//
+// `debug_state` tells which banks are where, for the debugger (`banks`) and the top of the trace. The banks are shown
+// in the size the mapper switches them in, so they are the numbers the game writes (for MMC3's 2KB CHR banks, half).
+
+use std::fmt;
+use std::fs;
+use std::path::Path;
+
+use log::{info, warn};
+
+use crate::error::NesError;
+use crate::fds::disk;
+use crate::fds::fds::Fds;
+use crate::hash::{crc32, md5, sha1};
+use crate::irq::{IrqLine, IrqSource};
+use crate::log_target::MAPPER;
+use crate::mapper::{BackgroundFetch, BackgroundTile, Board, Nametable};
+use crate::mmc3::IrqVariant;
+use crate::ppu::ppu::Mirroring;
+use crate::ram_init::RamFiller;
+use crate::region::Region;
+use crate::romdb::RomDb;
+use crate::save_state::{SaveState, StateReader, StateWriter};
+
+const HEADER_SIZE: usize = 16;
+const TRAINER_SIZE: usize = 512;
+/// Where the trainer goes in PRG RAM ($7000).
+const TRAINER_OFFSET: usize = 0x1000;
+const PRG_ROM_UNIT: usize = 16 * 1024;
+const CHR_ROM_UNIT: usize = 8 * 1024;
+const PRG_RAM_SIZE: usize = 8 * 1024;
+const CHR_RAM_SIZE: usize = 8 * 1024;
+/// The BIOS of the FDS RAM adapter, at $E000.
+const FDS_BIOS_SIZE: usize = 8 * 1024;
+const FDS_PRG_RAM_SIZE: usize = 32 * 1024;
+/// The iNES number emulators give the FDS.
+const FDS_MAPPER: u8 = 20;
+/// $8000-$FFFF is mapped in 4 windows of 8KB, the smallest PRG bank of the common mappers.
+const PRG_BANK_SIZE: usize = 8 * 1024;
+/// And the pattern tables in 8 windows of 1KB.
+const CHR_BANK_SIZE: usize = 1024;
+
+/// What the header of the file says, or what the ROM database says instead.
+#[derive(Clone, Copy, PartialEq, Debug)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct HeaderValues {
+	pub mapper: u8,
+	pub mirroring: Mirroring,
+	pub region: Region,
+}
+
+/// iNES, or NES 2.0.
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub enum HeaderFormat {
+	Ines,
+	Nes2,
+}
+
+/// The CPU and PPU timing a NES 2.0 file is for. iNES files are all NTSC.
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub enum Timing {
+	Ntsc,
+	Pal,
+	/// The game runs on all of them.
+	Multiple,
+	Dendy,
+}
+
+/// Everything the header of an iNES or NES 2.0 file says, see the top of the file. The sizes are in bytes, and what
+/// iNES doesn't have is filled in like the cartridge uses it: 8KB of PRG RAM, and 8KB of CHR RAM without CHR ROM.
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct CartridgeHeader {
+	pub format: HeaderFormat,
+	/// 0-255 for iNES, 0-4095 for NES 2.0.
+	pub mapper: u16,
+	/// 0 for iNES.
+	pub submapper: u8,
+	pub prg_rom_size: usize,
+	pub chr_rom_size: usize,
+	pub prg_ram_size: usize,
+	/// Battery backed.
+	pub prg_nvram_size: usize,
+	pub chr_ram_size: usize,
+	pub chr_nvram_size: usize,
+	/// From flags 6 bit 0: vertical, or horizontal.
+	pub vertical_mirroring: bool,
+	pub battery: bool,
+	pub has_trainer: bool,
+	pub timing: Timing,
+}
+
+impl CartridgeHeader {
+	/// The first 16 bytes of `bytes`. Err when they aren't an iNES header.
+	pub fn parse(bytes: &[u8]) -> Result<Self, NesError> {
+		if !Cartridge::is_ines(bytes) {
+			return Err(NesError::Ines("Not an iNES file: missing 'NES' header".to_string()));
+		}
+		let flags6 = bytes[6];
+		let flags7 = bytes[7];
+		let battery = flags6 & 0x02 != 0;
+		let mapper = ((flags7 & 0xF0) | (flags6 >> 4)) as u16;
+		let mut header = CartridgeHeader {
+			format: HeaderFormat::Ines,
+			mapper,
+			submapper: 0,
+			prg_rom_size: bytes[4] as usize * PRG_ROM_UNIT,
+			chr_rom_size: bytes[5] as usize * CHR_ROM_UNIT,
+			prg_ram_size: if battery { 0 } else { PRG_RAM_SIZE },
+			prg_nvram_size: if battery { PRG_RAM_SIZE } else { 0 },
+			chr_ram_size: if bytes[5] == 0 { CHR_RAM_SIZE } else { 0 },
+			chr_nvram_size: 0,
+			vertical_mirroring: flags6 & 0x01 != 0,
+			battery,
+			has_trainer: flags6 & 0x04 != 0,
+			timing: Timing::Ntsc,
+		};
+		if flags7 & 0x0C != 0x08 {
+			return Ok(header);
+		}
+
+		let ram_size = |shift: u8| if shift == 0 { 0 } else { 64 << shift };
+		header.format = HeaderFormat::Nes2;
+		header.mapper |= ((bytes[8] & 0x0F) as u16) << 8;
+		header.submapper = bytes[8] >> 4;
+		header.prg_rom_size = rom_size(bytes[4], bytes[9] & 0x0F, PRG_ROM_UNIT);
+		header.chr_rom_size = rom_size(bytes[5], bytes[9] >> 4, CHR_ROM_UNIT);
+		header.prg_ram_size = ram_size(bytes[10] & 0x0F);
+		header.prg_nvram_size = ram_size(bytes[10] >> 4);
+		header.chr_ram_size = ram_size(bytes[11] & 0x0F);
+		header.chr_nvram_size = ram_size(bytes[11] >> 4);
+		header.timing = match bytes[12] & 0b11 {
+			0 => Timing::Ntsc,
+			1 => Timing::Pal,
+			2 => Timing::Multiple,
+			_ => Timing::Dendy,
+		};
+		Ok(header)
+	}
+
+	/// PRG RAM and PRG NVRAM: what's at $6000, mirrored when it's less than 8KB.
+	pub fn prg_ram_total(&self) -> usize {
+		self.prg_ram_size + self.prg_nvram_size
+	}
+}
+
+/// A NES 2.0 ROM size, from its low byte (bytes 4 and 5) and its high nibble (byte 9). A high nibble of $F is another
+/// notation, for sizes that aren't a multiple of the unit: 2^E * (M * 2 + 1) bytes, the low byte being EEEEEEMM.
+fn rom_size(low: u8, high: u8, unit: usize) -> usize {
+	if high == 0x0F {
+		1usize.checked_shl((low >> 2) as u32).map_or(usize::MAX, |size| size.saturating_mul((low & 0b11) as usize * 2 + 1))
+	} else {
+		((high as usize) << 8 | low as usize) * unit
+	}
+}
+
+/// A window of memory, and the bank in it, see `MapperDebugInfo`.
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub struct BankSlot {
+	/// The first address of the window, in CPU memory for PRG and PPU memory for CHR.
+	pub addr: u16,
+	pub size: usize,
+	/// In banks of `size`, from the start of PRG ROM or CHR.
+	pub bank: usize,
+}
+
+/// The IRQ counter of a mapper (MMC3 counts scanlines, VRC6 scanlines or CPU cycles).
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub struct IrqCounterInfo {
+	pub counter: u8,
+	/// What the counter is reloaded with.
+	pub latch: u8,
+	pub enabled: bool,
+	/// The counter fired, and the game didn't acknowledge it yet.
+	pub pending: bool,
+}
+
+/// What the mapper maps where, right now. Displayed, it's a few lines for the debugger, see the top of the file.
+#[derive(Clone, PartialEq, Debug)]
+pub struct MapperDebugInfo {
+	pub mapper: u8,
+	/// $8000-$FFFF, in order.
+	pub prg: Vec<BankSlot>,
+	/// The pattern tables, $0000-$1FFF, in order.
+	pub chr: Vec<BankSlot>,
+	pub mirroring: Mirroring,
+	/// There is PRG RAM at $6000 (see the top of the file), and the mapper didn't turn it off (VRC6).
+	pub prg_ram_enabled: bool,
+	pub irq: Option<IrqCounterInfo>,
+}
+
+impl fmt::Display for MapperDebugInfo {
+	fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+		let slots = |slots: &[BankSlot]| -> String {
+			let slots: Vec<String> = slots.iter().map(|slot| format!("${:04X} {}KB bank {}", slot.addr, slot.size / 1024, slot.bank)).collect();
+			slots.join(", ")
+		};
+		writeln!(f, "Mapper {}", self.mapper)?;
+		writeln!(f, "PRG: {}", slots(&self.prg))?;
+		writeln!(f, "CHR: {}", slots(&self.chr))?;
+		write!(f, "Mirroring: {:?}, PRG RAM {}", self.mirroring, if self.prg_ram_enabled { "on" } else { "off" })?;
+		if let Some(irq) = self.irq {
+			write!(f, "\nIRQ: counter {}, latch {}, {}{}", irq.counter, irq.latch, if irq.enabled { "enabled" } else { "disabled" }, if irq.pending { ", pending" } else { "" })?;
+		}
+		Ok(())
+	}
+}
+
+/// Where an address of $8000-$FFFF is in the ROM, see `Cartridge::prg_location`.
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub struct PrgLocation {
+	/// Like in `BankSlot`.
+	pub bank: usize,
+	/// In the .nes file, past the header and the trainer.
+	pub file_offset: usize,
+}
+
+/// Like `PRG bank 3, file offset $C010`.
+impl fmt::Display for PrgLocation {
+	fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+		write!(f, "PRG bank {}, file offset ${:X}", self.bank, self.file_offset)
+	}
+}
+
+/// The game cartridge: PRG ROM (program, mapped to CPU memory) and CHR (graphics, mapped to PPU memory).
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct Cartridge {
+	#[cfg_attr(feature = "serde", serde(with = "crate::serde_bytes_base64"))]
+	prg_rom: Vec<u8>,
+	#[cfg_attr(feature = "serde", serde(with = "crate::serde_bytes_base64"))]
+	chr: Vec<u8>,
+	/// CHR is RAM, and not ROM. Only then the PPU can write it.
+	chr_ram: bool,
+	#[cfg_attr(feature = "serde", serde(with = "crate::serde_bytes_base64"))]
+	prg_ram: Vec<u8>,
+	/// Where each 8KB window of $8000-$FFFF starts in PRG ROM. A read only adds and indexes, so the mapper sets them
+	/// when it switches banks, and not on every read. See `map_prg_banks`.
+	prg_banks: [usize; 4],
+	/// Where each 1KB window of $0000-$1FFF starts in CHR, like `prg_banks`. See `map_chr_banks`.
+	chr_banks: [usize; 8],
+	/// The same for the background, when the mapper has banks for it (MMC5).
+	background_chr_banks: [usize; 8],
+	/// Where $6000 starts in PRG RAM.
+	prg_ram_bank: usize,
+	/// There's PRG RAM, and the mapper has it on.
+	prg_ram_enabled: bool,
+	/// Where PRG RAM ends: $7FFF, or $DFFF for the FDS.
+	prg_ram_end: u16,
+	/// The nametables of the mapper, when they aren't one of the mirrorings (MMC5).
+	nametables: Option<[Nametable; 4]>,
+	/// The registers of the mapper.
+	board: Board,
+	mapper: u8,
+	/// From the NES 2.0 header, 0 for iNES files.
+	submapper: u8,
+	mirroring: Mirroring,
+	region: Region,
+	/// What the file says, before the ROM database.
+	header: HeaderValues,
+	/// All of it.
+	file_header: CartridgeHeader,
+	has_trainer: bool,
+	/// CRC32 of PRG ROM and CHR ROM, like ROM databases use.
+	hash: u32,
+	/// MD5 of PRG ROM and CHR ROM, like FCEUX uses.
+	#[cfg_attr(feature = "serde", serde(with = "crate::serde_bytes_base64"))]
+	md5: [u8; 16],
+}
+
+impl Cartridge {
+	/// Whether `bytes` start like an iNES file. Anything else is a raw binary, for the command line.
+	pub fn is_ines(bytes: &[u8]) -> bool {
+		bytes.len() >= HEADER_SIZE && bytes[0..4] == [0x4E, 0x45, 0x53, 0x1A]
+	}
+
+	/// Parse iNES file, and fix its header with the built-in ROM database.
+	pub fn from_ines(bytes: &[u8]) -> Result<Self, NesError> {
+		Self::from_ines_with_db(bytes, RomDb::builtin())
+	}
+
+	/// Read an iNES file, and parse it like `from_ines`.
+	pub fn from_file(path: &Path) -> Result<Self, NesError> {
+		let bytes = fs::read(path).map_err(|err| NesError::io(path, err))?;
+		Self::from_ines(&bytes)
+	}
+
+	/// Like `from_ines`, with another ROM database (`--romdb`).
+	pub fn from_ines_with_db(bytes: &[u8], db: &RomDb) -> Result<Self, NesError> {
+		let file_header = CartridgeHeader::parse(bytes)?;
+		let CartridgeHeader { prg_rom_size, chr_rom_size, submapper, has_trainer, .. } = file_header;
+		// Mappers past 255 are none of the ones here.
+		let mapper = u8::try_from(file_header.mapper).map_err(|_| NesError::Mapper(file_header.mapper))?;
+		let mirroring = if file_header.vertical_mirroring { Mirroring::Vertical } else { Mirroring::Horizontal };
+		let region = match file_header.timing {
+			Timing::Pal => Region::Pal,
+			Timing::Dendy => {
+				warn!(target: MAPPER, "Dendy is not supported, running as NTSC");
+				Region::Ntsc
+			}
+			// NTSC, or a game that runs on all regions.
+			Timing::Ntsc | Timing::Multiple => Region::Ntsc,
+		};
+
+		// The exponent notation of NES 2.0 has any size, but the banks here are in 16KB and 8KB.
+		if prg_rom_size % PRG_ROM_UNIT != 0 || chr_rom_size % CHR_ROM_UNIT != 0 {
+			return Err(NesError::Ines(format!(
+				"The header declares {} bytes of PRG ROM and {} bytes of CHR ROM, which are not in 16KB and 8KB banks",
+				prg_rom_size, chr_rom_size)));
+		}
+		let prg_start = if has_trainer { HEADER_SIZE + TRAINER_SIZE } else { HEADER_SIZE };
+		let chr_start = prg_start.saturating_add(prg_rom_size);
+		if prg_rom_size == 0 || bytes.len() < chr_start.saturating_add(chr_rom_size) {
+			return Err(NesError::Ines(format!(
+				"iNES file is too short: header declares {} bytes of PRG ROM and {} bytes of CHR ROM, but the file has {} bytes",
+				prg_rom_size, chr_rom_size, bytes.len())));
+		}
+
+		let hash = crc32(&bytes[prg_start..chr_start + chr_rom_size]);
+		let md5 = md5(&bytes[prg_start..chr_start + chr_rom_size]);
+
+		let header = HeaderValues { mapper, mirroring, region };
+		let HeaderValues { mapper, mirroring, region } = if db.is_empty() {
+			header
+		} else {
+			match db.lookup(hash, &sha1(&bytes[prg_start..chr_start + chr_rom_size])) {
+				Some(entry) => entry.fix(header),
+				None => header,
+			}
+		};
+		let board = Board::new(mapper, submapper).ok_or(NesError::Mapper(mapper as u16))?;
+
+		let prg_rom = bytes[prg_start..chr_start].to_vec();
+		let chr_ram = chr_rom_size == 0;
+		let chr = if chr_ram {
+			let size = file_header.chr_ram_size + file_header.chr_nvram_size;
+			// NES 2.0 can say "no CHR RAM", but a board without CHR at all doesn't exist, so it gets the default too.
+			vec![0; if size == 0 { CHR_RAM_SIZE } else { size }]
+		} else {
+			bytes[chr_start..chr_start + chr_rom_size].to_vec()
+		};
+		// The trainer needs the RAM it goes to.
+		let prg_ram_size = if has_trainer { PRG_RAM_SIZE.max(file_header.prg_ram_total()) } else { file_header.prg_ram_total() };
+		let mut prg_ram = vec![0; prg_ram_size];
+		if has_trainer {
+			prg_ram[TRAINER_OFFSET..TRAINER_OFFSET + TRAINER_SIZE].copy_from_slice(&bytes[HEADER_SIZE..prg_start]);
+		}
+
+		let mut cartridge = Cartridge {
+			prg_rom,
+			chr,
+			chr_ram,
+			prg_ram,
+			prg_banks: [0; 4],
+			chr_banks: [0; 8],
+			background_chr_banks: [0; 8],
+			prg_ram_bank: 0,
+			prg_ram_enabled: false,
+			prg_ram_end: 0x7FFF,
+			nametables: None,
+			board,
+			mapper,
+			submapper,
+			mirroring,
+			region,
+			header,
+			file_header,
+			has_trainer,
+			hash,
+			md5,
+		};
+		cartridge.map_prg_banks();
+		cartridge.map_chr_banks();
+		Ok(cartridge)
+	}
+
+	/// An NROM cartridge for a raw 6502 binary (no header): 32KB of PRG ROM with the binary at `load`, and 8KB of CHR
+	/// RAM. The reset vector points at `load`, unless the binary covers $FFFC-$FFFD and has its own. The rest of PRG ROM
+	/// is zeros (BRK), and so are the NMI and IRQ vectors, when the binary doesn't set them.
+	pub fn from_raw(bytes: &[u8], load: u16) -> Result<Self, NesError> {
+		const PRG_START: usize = 0x8000;
+		const RESET_VECTOR: usize = 0xFFFC;
+		let start = load as usize;
+		let end = start + bytes.len();
+		if start < PRG_START || end > 0x10000 {
+			return Err(NesError::Bus(format!("Binary is {} bytes, it doesn't fit in PRG ROM ($8000-$FFFF) at {:#06X}", bytes.len(), start)));
+		}
+
+		let mut ines = vec![0x4E, 0x45, 0x53, 0x1A, 2, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0];
+		let mut prg = vec![0; 2 * PRG_ROM_UNIT];
+		prg[start - PRG_START..end - PRG_START].copy_from_slice(bytes);
+		if !(start <= RESET_VECTOR && end >= RESET_VECTOR + 2) {
+			prg[RESET_VECTOR - PRG_START..RESET_VECTOR - PRG_START + 2].copy_from_slice(&load.to_le_bytes());
+		}
+		ines.extend_from_slice(&prg);
+		// Not a dump of a real cartridge, so not in any ROM database.
+		Self::from_ines_with_db(&ines, &RomDb::new())
+	}
+
+	/// Whether `bytes` start like an FDS disk image, see fds/disk.rs.
+	pub fn is_fds(bytes: &[u8]) -> bool {
+		disk::is_fds(bytes)
+	}
+
+	/// The FDS RAM adapter, with `bios` (disksys.rom, 8KB) and the disk image `image` in the drive, its first side in.
+	pub fn from_fds(bios: &[u8], image: &[u8]) -> Result<Self, NesError> {
+		if bios.len() != FDS_BIOS_SIZE {
+			return Err(NesError::Disk(format!("The FDS BIOS is {} bytes, this one has {} bytes", FDS_BIOS_SIZE, bios.len())));
+		}
+		let sides = disk::sides(image)?;
+		let fds = Fds::new(&sides);
+		info!(target: MAPPER, "FDS disk image: {} sides", fds.sides());
+
+		let file_header = CartridgeHeader {
+			format: HeaderFormat::Ines,
+			mapper: FDS_MAPPER as u16,
+			submapper: 0,
+			prg_rom_size: FDS_BIOS_SIZE,
+			chr_rom_size: 0,
+			prg_ram_size: FDS_PRG_RAM_SIZE,
+			prg_nvram_size: 0,
+			chr_ram_size: CHR_RAM_SIZE,
+			chr_nvram_size: 0,
+			vertical_mirroring: false,
+			battery: false,
+			has_trainer: false,
+			timing: Timing::Ntsc,
+		};
+		let header = HeaderValues { mapper: FDS_MAPPER, mirroring: Mirroring::Horizontal, region: Region::Ntsc };
+		let mut cartridge = Cartridge {
+			prg_rom: bios.to_vec(),
+			chr: vec![0; CHR_RAM_SIZE],
+			chr_ram: true,
+			prg_ram: vec![0; FDS_PRG_RAM_SIZE],
+			prg_banks: [0; 4],
+			chr_banks: [0; 8],
+			background_chr_banks: [0; 8],
+			prg_ram_bank: 0,
+			prg_ram_enabled: false,
+			prg_ram_end: 0x7FFF,
+			nametables: None,
+			board: Board::Fds(Box::new(fds)),
+			mapper: FDS_MAPPER,
+			submapper: 0,
+			mirroring: header.mirroring,
+			region: header.region,
+			header,
+			file_header,
+			has_trainer: false,
+			hash: crc32(disk::without_header(image)),
+			md5: md5(disk::without_header(image)),
+		};
+		cartridge.map_prg_banks();
+		cartridge.map_chr_banks();
+		Ok(cartridge)
+	}
+
+	/// Ask the mapper where its PRG banks are now, and the one of PRG RAM.
+	fn map_prg_banks(&mut self) {
+		let mapper = self.board.mapper();
+		self.prg_banks = mapper.prg_banks(self.prg_rom.len());
+		self.prg_ram_bank = if self.prg_ram.is_empty() { 0 } else { mapper.prg_ram_bank() * PRG_RAM_SIZE % self.prg_ram.len() };
+		self.prg_ram_enabled = !self.prg_ram.is_empty() && mapper.prg_ram_enabled();
+		self.prg_ram_end = mapper.prg_ram_end();
+	}
+
+	/// The same for CHR, and the nametables. Without CHR banks, the first 8KB (mirrored when there's less).
+	fn map_chr_banks(&mut self) {
+		let mapper = self.board.mapper();
+		self.chr_banks = mapper.chr_banks(self.chr.len());
+		self.background_chr_banks = mapper.background_chr_banks(self.chr.len());
+		self.nametables = mapper.nametables();
+	}
+
+	/// After the ROM database, see `header` for what the file says.
+	pub fn mapper(&self) -> u8 {
+		self.mapper
+	}
+
+	pub fn submapper(&self) -> u8 {
+		self.submapper
+	}
+
+	/// Set by the header or the ROM database, or by the game for MMC3 and VRC6 (which the PPU follows, see `NesBus`).
+	pub fn mirroring(&self) -> Mirroring {
+		self.board.mapper().mirroring().unwrap_or(self.mirroring)
+	}
+
+	/// Which banks are where, see the top of the file.
+	pub fn debug_state(&self) -> MapperDebugInfo {
+		let mapper = self.board.mapper();
+		MapperDebugInfo {
+			mapper: self.mapper,
+			prg: bank_slots(0x8000, &self.prg_banks, PRG_BANK_SIZE, mapper.prg_slots()),
+			chr: bank_slots(0x0000, &self.chr_banks, CHR_BANK_SIZE, mapper.chr_slots()),
+			mirroring: self.mirroring(),
+			prg_ram_enabled: self.prg_ram_enabled,
+			irq: mapper.irq_info(),
+		}
+	}
+
+	/// Where the byte at `addr` is in the ROM file, with the banks mapped now. None below $8000.
+	pub fn prg_location(&self, addr: u16) -> Option<PrgLocation> {
+		if addr < 0x8000 {
+			return None;
+		}
+		let slot = self.debug_state().prg.into_iter().rev().find(|slot| slot.addr <= addr)?;
+		let index = self.prg_banks[(addr as usize >> 13) & 0b11] + (addr as usize & (PRG_BANK_SIZE - 1));
+		let trainer = if self.has_trainer { TRAINER_SIZE } else { 0 };
+		Some(PrgLocation { bank: slot.bank, file_offset: HEADER_SIZE + trainer + index })
+	}
+
+	/// When the MMC3 counter fires: from the submapper, unless this changes it (`--mmc3-irq`). Nothing changes for
+	/// the other mappers.
+	pub fn set_mmc3_irq(&mut self, variant: IrqVariant) {
+		if let Board::Mmc3(mmc3) = &mut self.board {
+			mmc3.set_irq_variant(variant);
+		}
+	}
+
+	/// From the submapper for the other mappers, like MMC3 would have.
+	pub fn mmc3_irq(&self) -> IrqVariant {
+		match &self.board {
+			Board::Mmc3(mmc3) => mmc3.irq_variant(),
+			_ => IrqVariant::from_submapper(self.submapper),
+		}
+	}
+
+	/// The PPU got to the rise of A12 of a rendering scanline, which MMC3 counts. See mmc3.rs.
+	pub fn clock_scanline(&mut self) {
+		self.board.mapper_mut().clock_scanline();
+	}
+
+	/// The PPU started a scanline, with the fetches of rendering or without, which MMC5 counts. See mmc5.rs.
+	pub fn ppu_scanline(&mut self, fetching: bool) {
+		self.board.mapper_mut().ppu_scanline(fetching);
+	}
+
+	/// The CPU wrote a register of the PPU, which MMC5 watches for the size of the sprites.
+	pub fn ppu_register_write(&mut self, addr: u16, data: u8) {
+		if self.board.mapper_mut().ppu_register_write(addr, data) {
+			self.map_chr_banks();
+		}
+	}
+
+	/// A CPU cycle, which VRC6 and the FDS count for their IRQ and their sound channels, and the disk drive.
+	pub fn clock_cpu(&mut self) {
+		self.board.mapper_mut().clock_cpu();
+	}
+
+	/// The IRQ of the mapper, the counter of MMC3 or VRC6, or the timer and the disk of the FDS.
+	pub fn irq_line(&self) -> IrqLine {
+		let mut line = IrqLine::default();
+		line.set(IrqSource::MAPPER, self.board.mapper().irq_pending());
+		line
+	}
+
+	/// The sound channels of the cartridge (VRC6, FDS), at the level of the APU channels. 0 for the others.
+	pub fn audio_level(&self) -> f32 {
+		self.board.mapper().audio_level()
+	}
+
+	/// The sides of the disks of the FDS, 0 for a cartridge.
+	pub fn disk_sides(&self) -> usize {
+		match &self.board {
+			Board::Fds(fds) => fds.sides(),
+			_ => 0,
+		}
+	}
+
+	/// The side in the drive of the FDS, None when it's ejected, or for a cartridge.
+	pub fn disk_side(&self) -> Option<usize> {
+		match &self.board {
+			Board::Fds(fds) => fds.side(),
+			_ => None,
+		}
+	}
+
+	/// Ejects the disk of the FDS, and inserts `side` 2 seconds later (None only ejects). Err for a cartridge, or a side
+	/// the disks don't have.
+	pub fn insert_disk(&mut self, side: Option<usize>) -> Result<(), String> {
+		match &mut self.board {
+			Board::Fds(fds) => fds.insert(side),
+			_ => Err("The cartridge isn't a disk".to_string()),
+		}
+	}
+
+	/// From the NES 2.0 header. iNES files don't have it, so they are NTSC.
+	pub fn region(&self) -> Region {
+		self.region
+	}
+
+	/// What the header of the file says, before the ROM database fixed it.
+	pub fn header(&self) -> HeaderValues {
+		self.header
+	}
+
+	/// The ROM database changed the header values.
+	pub fn fixed_up(&self) -> bool {
+		self.header != HeaderValues { mapper: self.mapper, mirroring: self.mirroring, region: self.region }
+	}
+
+	/// Everything the header says, see `CartridgeHeader`. Unlike `header`, not fixed by the ROM database.
+	pub fn file_header(&self) -> &CartridgeHeader {
+		&self.file_header
+	}
+
+	/// The file has a trainer, loaded at $7000.
+	pub fn has_trainer(&self) -> bool {
+		self.has_trainer
+	}
+
+	/// Identifies the game: save states are only loaded into the game that saved them.
+	pub fn hash(&self) -> u32 {
+		self.hash
+	}
+
+	pub fn md5(&self) -> [u8; 16] {
+		self.md5
+	}
+
+	/// The RAM at $6000, battery backed or not: 8KB, or the size of the NES 2.0 header (maybe none), or the 32KB of the
+	/// FDS.
+	pub fn prg_ram(&self) -> &[u8] {
+		&self.prg_ram
+	}
+
+	/// There's PRG RAM, and the mapper didn't turn it off: $6000-$7FFF is open bus otherwise.
+	pub fn prg_ram_enabled(&self) -> bool {
+		self.prg_ram_enabled
+	}
+
+	/// PRG RAM, when a battery keeps it (flags 6 bit 1, or PRG NVRAM in a NES 2.0 header): what a .sav file has, to
+	/// be written where the frontend keeps them. None without a battery.
+	pub fn save_ram(&self) -> Option<&[u8]> {
+		let battery = self.file_header.battery || self.file_header.prg_nvram_size > 0;
+		(battery && !self.prg_ram.is_empty()).then_some(&self.prg_ram[..])
+	}
+
+	/// Put back what `save_ram` had, from a .sav file. Err without a battery, or when `data` isn't the size of PRG RAM.
+	pub fn load_ram(&mut self, data: &[u8]) -> Result<(), NesError> {
+		let Some(ram) = self.save_ram() else {
+			return Err(NesError::Battery("The cartridge has no battery backed PRG RAM".to_string()));
+		};
+		if data.len() != ram.len() {
+			return Err(NesError::Battery(format!("The battery backed PRG RAM is {} bytes, the save has {} bytes", ram.len(), data.len())));
+		}
+		self.prg_ram.copy_from_slice(data);
+		Ok(())
+	}
+
+	/// Pattern tables (CHR ROM, or CHR RAM if the cartridge has no CHR ROM).
+	pub fn chr(&self) -> &[u8] {
+		&self.chr
+	}
+
+	/// The cartridge has CHR RAM, and not CHR ROM.
+	pub fn has_chr_ram(&self) -> bool {
+		self.chr_ram
+	}
+
+	/// Fill PRG RAM and CHR RAM like at power on. The trainer stays.
+	pub fn power_on(&mut self, filler: &mut RamFiller) {
+		if self.has_trainer {
+			let (before, rest) = self.prg_ram.split_at_mut(TRAINER_OFFSET);
+			filler.fill(before);
+			filler.fill(&mut rest[TRAINER_SIZE..]);
+		} else {
+			filler.fill(&mut self.prg_ram);
+		}
+		if self.chr_ram {
+			filler.fill(&mut self.chr);
+		}
+	}
+
+	/// Read the pattern tables, $0000 - $1FFF in PPU memory. CHR RAM smaller than 8KB is mirrored.
+	pub fn ppu_read(&self, addr: u16) -> u8 {
+		self.chr[self.chr_index(addr)]
+	}
+
+	/// Like `ppu_read`, for the fetches of the background.
+	pub fn ppu_read_background(&self, addr: u16) -> u8 {
+		let addr = addr as usize & 0x1FFF;
+		self.chr[(self.background_chr_banks[addr >> 10] + (addr & (CHR_BANK_SIZE - 1))) % self.chr.len()]
+	}
+
+	/// The byte at `index` in CHR, wrapped, for `BackgroundTile::pattern`.
+	pub fn chr_byte(&self, index: usize) -> u8 {
+		self.chr[index % self.chr.len()]
+	}
+
+	fn chr_index(&self, addr: u16) -> usize {
+		let addr = addr as usize & 0x1FFF;
+		(self.chr_banks[addr >> 10] + (addr & (CHR_BANK_SIZE - 1))) % self.chr.len()
+	}
+
+	/// The nametables of the mapper, when they aren't one of the mirrorings. None for those, see `mirroring`.
+	pub fn nametables(&self) -> Option<[Nametable; 4]> {
+		self.nametables
+	}
+
+	/// Read a nametable of the cartridge, `Nametable::Cartridge`.
+	pub fn nametable_read(&self, addr: u16) -> u8 {
+		self.board.mapper().nametable_read(addr)
+	}
+
+	/// Write a nametable of the cartridge.
+	pub fn nametable_write(&mut self, addr: u16, data: u8) {
+		self.board.mapper_mut().nametable_write(addr, data);
+	}
+
+	/// A background tile of the mapper (MMC5's split screen and extended attributes). None for the one of the
+	/// nametable.
+	pub fn background_tile(&self, fetch: &BackgroundFetch) -> Option<BackgroundTile> {
+		self.board.mapper().background_tile(fetch)
+	}
+
+	/// Write the pattern tables, $0000 - $1FFF in PPU memory. Only CHR RAM is writable: returns false for CHR ROM,
+	/// which keeps its data.
+	pub fn ppu_write(&mut self, addr: u16, data: u8) -> bool {
+		if !self.chr_ram {
+			return false;
+		}
+		let index = self.chr_index(addr);
+		self.chr[index] = data;
+		true
+	}
+
+	/// Read cartridge space, $4020 - $FFFF in CPU memory. The registers of the expansion area ($4020-$5FFF) don't change.
+	pub fn cpu_read(&self, addr: u16) -> u8 {
+		match addr {
+			0x4020..=0x5FFF => self.board.mapper().expansion_peek(addr).unwrap_or(0),
+			0x6000..=0xFFFF if addr <= self.prg_ram_end && self.prg_ram_enabled => self.prg_ram[self.prg_ram_index(addr)],
+			0x8000..=0xFFFF => {
+				let addr = addr as usize;
+				self.prg_rom[self.prg_banks[(addr >> 13) & 0b11] + (addr & (PRG_BANK_SIZE - 1))]
+			}
+			_ => 0,
+		}
+	}
+
+	/// A read of the CPU of $4020-$5FFF, the registers of the mapper there. None where there's none: open bus.
+	pub fn expansion_read(&mut self, addr: u16) -> Option<u8> {
+		self.board.mapper_mut().expansion_read(addr)
+	}
+
+	fn prg_ram_index(&self, addr: u16) -> usize {
+		(self.prg_ram_bank + (addr - 0x6000) as usize) % self.prg_ram.len()
+	}
+
+	/// Write cartridge space like `cpu_write`, and PRG ROM too, for the debugger. The game is not the same after that,
+	/// so it's logged.
+	pub fn poke(&mut self, addr: u16, data: u8) {
+		if addr <= self.prg_ram_end {
+			self.cpu_write(addr, data);
+			return;
+		}
+		let addr = addr as usize;
+		let index = self.prg_banks[(addr >> 13) & 0b11] + (addr & (PRG_BANK_SIZE - 1));
+		info!(target: MAPPER, "PRG ROM changed: ${:04X} (offset {:#X}) = ${:02X}, was ${:02X}", addr, index, data, self.prg_rom[index]);
+		self.prg_rom[index] = data;
+	}
+
+	/// Write cartridge space, $4020 - $FFFF in CPU memory. PRG ROM never changes: writes to it go to the mapper
+	/// registers, and NROM has none. The mirroring may change with them, see `mirroring`. Returns false when the write went nowhere.
+	pub fn cpu_write(&mut self, addr: u16, data: u8) -> bool {
+		match addr {
+			0x4020..=0x5FFF => {
+				if !self.board.mapper_mut().expansion_write(addr, data) {
+					return false;
+				}
+				self.map_prg_banks();
+				self.map_chr_banks();
+				true
+			}
+			0x6000..=0xFFFF if addr <= self.prg_ram_end && self.prg_ram_enabled => {
+				if !self.board.mapper().prg_ram_writable() {
+					return false;
+				}
+				let index = self.prg_ram_index(addr);
+				self.prg_ram[index] = data;
+				true
+			}
+			0x8000..=0xFFFF => {
+				let data = if self.board.mapper().bus_conflicts() { data & self.cpu_read(addr) } else { data };
+				if !self.board.mapper_mut().cpu_write(addr, data) {
+					return false;
+				}
+				self.map_prg_banks();
+				self.map_chr_banks();
+				true
+			}
+			_ => false,
+		}
+	}
+}
+
+/// The slots of `sizes`, one after the other from `start`, with the banks in `windows` (where every window of
+/// `window_size` starts, like `prg_banks`).
+fn bank_slots(start: u16, windows: &[usize], window_size: usize, sizes: &[usize]) -> Vec<BankSlot> {
+	let mut window = 0;
+	sizes.iter().map(|&size| {
+		let slot = BankSlot { addr: start + (window * window_size) as u16, size, bank: windows[window] / size };
+		window += size / window_size;
+		slot
+	}).collect()
+}
+
+/// Only the RAM can change: PRG RAM, and CHR RAM when there's no CHR ROM. And the registers of the mapper. A state is
+/// only loaded into the game that saved it, so both sides agree on which mapper there is.
+impl SaveState for Cartridge {
+	fn save_state(&self, out: &mut StateWriter) {
+		out.bytes(&self.prg_ram);
+		if self.chr_ram {
+			out.bytes(&self.chr);
+		}
+		self.board.mapper().save_state(out);
+	}
+
+	fn load_state(&mut self, input: &mut StateReader) -> Result<(), String> {
+		input.bytes(&mut self.prg_ram)?;
+		if self.chr_ram {
+			input.bytes(&mut self.chr)?;
+		}
+		self.board.mapper_mut().load_state(input)?;
+		self.map_prg_banks();
+		self.map_chr_banks();
+		Ok(())
+	}
+}
+
+/// Build iNES files in memory, for tests.
+#[cfg(test)]
+pub mod test_rom {
+	use crate::memory::hex_to_bytes;
+
+	/// NROM cartridge with a single 16KB PRG bank (mirrored at $8000 and $C000) and 8KB of CHR.
+	/// The program is written at $8000, and the reset vector points to it.
+	pub fn nrom(program: &str) -> Vec<u8> {
+		nrom_with_vectors(program, 0x8000, 0x8000)
+	}
+
+	/// Like `nrom`, but also sets the NMI and IRQ vectors. They should point to handlers inside the program.
+	pub fn nrom_with_vectors(program: &str, nmi: u16, irq: u16) -> Vec<u8> {
+		let mut prg = vec![0xEA; 0x4000];
+		let program = hex_to_bytes(program).unwrap();
+		prg[..program.len()].copy_from_slice(&program);
+		prg[0x3FFA..].copy_from_slice(&[nmi as u8, (nmi >> 8) as u8, 0x00, 0x80, irq as u8, (irq >> 8) as u8]);
+		ines(0, &prg, &[0; 0x2000])
+	}
+
+	pub fn ines(mapper: u8, prg: &[u8], chr: &[u8]) -> Vec<u8> {
+		let mut rom = vec![0x4E, 0x45, 0x53, 0x1A, (prg.len() / 0x4000) as u8, (chr.len() / 0x2000) as u8, mapper << 4, mapper & 0xF0, 0, 0, 0, 0, 0, 0, 0, 0];
+		rom.extend_from_slice(prg);
+		rom.extend_from_slice(chr);
+		rom
+	}
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+	use crate::emulator::Emulator;
+	use crate::memory::hex_to_bytes;
+
+	#[test]
+	fn raw_test() {
+		let cartridge = Cartridge::from_raw(&[0xA9, 0x01, 0xEA], 0xC000).unwrap();
+		assert_eq!(cartridge.mapper(), 0);
+		assert_eq!(cartridge.cpu_read(0xC000), 0xA9);
+		assert_eq!(cartridge.cpu_read(0x8000), 0x00);
+		assert_eq!((cartridge.cpu_read(0xFFFC), cartridge.cpu_read(0xFFFD)), (0x00, 0xC0));
+		assert!(cartridge.has_chr_ram());
+
+		// Its own vectors.
+		let mut binary = vec![0xEA; 0x1000];
+		binary[0xFFC..].copy_from_slice(&[0x34, 0xF1, 0x00, 0x00]);
+		let cartridge = Cartridge::from_raw(&binary, 0xF000).unwrap();
+		assert_eq!((cartridge.cpu_read(0xFFFC), cartridge.cpu_read(0xFFFD)), (0x34, 0xF1));
+
+		assert!(Cartridge::from_raw(&[0xEA], 0x0600).is_err());
+		assert!(Cartridge::from_raw(&[0xEA; 3], 0xFFFE).is_err());
+	}
+
+	#[test]
+	fn ines_test() {
+		let rom = test_rom::nrom("A9 01 EA");
+		let cartridge = Cartridge::from_ines(&rom).unwrap();
+
+		assert_eq!(cartridge.mapper(), 0);
+		assert_eq!(cartridge.mirroring(), Mirroring::Horizontal);
+		assert_eq!(cartridge.cpu_read(0x8000), 0xA9);
+		assert_eq!(cartridge.cpu_read(0xC001), 0x01);
+		assert_eq!(cartridge.cpu_read(0xFFFD), 0x80);
+		assert_eq!(cartridge.region(), Region::Ntsc);
+	}
+
+	#[test]
+	fn prg_banks_test() {
+		// NROM-256: 32KB, a different byte in each 8KB bank.
+		let prg: Vec<u8> = (0..0x8000).map(|addr| (addr / 0x2000) as u8 + 1).collect();
+		let cartridge = Cartridge::from_ines(&test_rom::ines(0, &prg, &[0; 0x2000])).unwrap();
+		for (addr, bank) in [(0x8000, 1), (0x9FFF, 1), (0xA000, 2), (0xC000, 3), (0xE000, 4), (0xFFFF, 4)] {
+			assert_eq!(cartridge.cpu_read(addr), bank, "address {:#X}", addr);
+		}
+
+		// NROM-128: 16KB, mirrored.
+		let cartridge = Cartridge::from_ines(&test_rom::ines(0, &prg[..0x4000], &[0; 0x2000])).unwrap();
+		for (addr, bank) in [(0x8000, 1), (0xA000, 2), (0xC000, 1), (0xFFFF, 2)] {
+			assert_eq!(cartridge.cpu_read(addr), bank, "address {:#X}", addr);
+		}
+	}
+
+	#[test]
+	fn hash_test() {
+		let first = Cartridge::from_ines(&test_rom::nrom("A9 01")).unwrap();
+		let second = Cartridge::from_ines(&test_rom::nrom("A9 02")).unwrap();
+		assert_ne!(first.hash(), second.hash());
+
+		// The header is not part of the hash.
+		let mut rom = test_rom::nrom("A9 01");
+		rom[6] |= 1;
+		assert_eq!(Cartridge::from_ines(&rom).unwrap().hash(), first.hash());
+	}
+
+	#[test]
+	fn nes2_region_test() {
+		let mut rom = test_rom::nrom("EA");
+		rom[12] = 1;
+		// Byte 12 means nothing in iNES.
+		assert_eq!(Cartridge::from_ines(&rom).unwrap().region(), Region::Ntsc);
+
+		rom[7] |= 0x08;
+		assert_eq!(Cartridge::from_ines(&rom).unwrap().region(), Region::Pal);
+		rom[12] = 2;
+		assert_eq!(Cartridge::from_ines(&rom).unwrap().region(), Region::Ntsc);
+	}
+
+	#[test]
+	fn nes2_header_test() {
+		// iNES: the sizes the cartridge uses, battery backed PRG RAM with flags 6 bit 1.
+		let mut rom = test_rom::ines(4, &[0xEA; 0x8000], &[]);
+		rom[6] |= 0x03;
+		let header = CartridgeHeader::parse(&rom).unwrap();
+		assert_eq!(header, CartridgeHeader {
+			format: HeaderFormat::Ines,
+			mapper: 4,
+			submapper: 0,
+			prg_rom_size: 0x8000,
+			chr_rom_size: 0,
+			prg_ram_size: 0,
+			prg_nvram_size: 0x2000,
+			chr_ram_size: 0x2000,
+			chr_nvram_size: 0,
+			vertical_mirroring: true,
+			battery: true,
+			has_trainer: false,
+			timing: Timing::Ntsc,
+		});
+
+		// NES 2.0: submapper 1, 2KB of PRG RAM and 8KB of PRG NVRAM, 32KB of CHR RAM, multiple regions.
+		rom[7] |= 0x08;
+		rom[8] = 0x10;
+		rom[10] = 0x75;
+		rom[11] = 0x09;
+		rom[12] = 2;
+		let header = CartridgeHeader::parse(&rom).unwrap();
+		assert_eq!(header.format, HeaderFormat::Nes2);
+		assert_eq!((header.mapper, header.submapper), (4, 1));
+		assert_eq!((header.prg_ram_size, header.prg_nvram_size, header.prg_ram_total()), (0x800, 0x2000, 0x2800));
+		assert_eq!((header.chr_ram_size, header.chr_nvram_size), (0x8000, 0));
+		assert_eq!(header.timing, Timing::Multiple);
+		let cartridge = Cartridge::from_ines(&rom).unwrap();
+		assert_eq!(cartridge.file_header(), &header);
+		assert_eq!((cartridge.prg_ram().len(), cartridge.chr().len()), (0x2800, 0x8000));
+
+		// The high bits of the mapper, and of the ROM sizes.
+		let mut header_bytes = rom[..16].to_vec();
+		header_bytes[8] = 0x21;
+		header_bytes[9] = 0x21;
+		let header = CartridgeHeader::parse(&header_bytes).unwrap();
+		assert_eq!((header.mapper, header.submapper), (0x104, 2));
+		assert_eq!((header.prg_rom_size, header.chr_rom_size), (0x102 * 0x4000, 0x200 * 0x2000));
+		assert!(matches!(Cartridge::from_ines(&header_bytes), Err(NesError::Mapper(0x104))));
+		// The exponent notation: 2^3 * 3 bytes.
+		header_bytes[4] = 0b0000_1101;
+		header_bytes[9] = 0x0F;
+		assert_eq!(CartridgeHeader::parse(&header_bytes).unwrap().prg_rom_size, 24);
+		assert!(CartridgeHeader::parse(&header_bytes[..8]).is_err());
+		assert!(Cartridge::from_ines(&header_bytes).is_err());
+		header_bytes[4] = 0xFF;
+		assert!(Cartridge::from_ines(&header_bytes).is_err());
+	}
+
+	#[test]
+	fn nes2_prg_ram_test() {
+		// iNES doesn't say, so 8KB.
+		assert_eq!(Cartridge::from_ines(&test_rom::nrom("EA")).unwrap().prg_ram().len(), 0x2000);
+
+		// NES 2.0 without PRG RAM: writes go nowhere, and LDA $6000 reads open bus, the high byte of the address.
+		let mut rom = test_rom::nrom("AD 00 60");
+		rom[7] |= 0x08;
+		let mut cartridge = Cartridge::from_ines(&rom).unwrap();
+		assert!(cartridge.prg_ram().is_empty());
+		assert!(!cartridge.cpu_write(0x6000, 0x12));
+		assert!(!cartridge.debug_state().prg_ram_enabled);
+		let mut emulator = Emulator::new(cartridge);
+		emulator.step_instruction();
+		assert_eq!(emulator.cpu_state().a, 0x60);
+
+		// 2KB, mirrored.
+		rom[10] = 0x05;
+		let mut cartridge = Cartridge::from_ines(&rom).unwrap();
+		assert!(cartridge.cpu_write(0x6001, 0x34));
+		assert_eq!((cartridge.cpu_read(0x6801), cartridge.cpu_read(0x7801)), (0x34, 0x34));
+	}
+
+	#[test]
+	fn battery_test() {
+		let mut rom = test_rom::nrom("EA");
+		assert_eq!(Cartridge::from_ines(&rom).unwrap().save_ram(), None);
+		assert!(matches!(Cartridge::from_ines(&rom).unwrap().load_ram(&[0; 0x2000]), Err(NesError::Battery(_))));
+
+		// Flags 6 bit 1: the 8KB at $6000.
+		rom[6] |= 0x02;
+		let mut cartridge = Cartridge::from_ines(&rom).unwrap();
+		cartridge.cpu_write(0x6000, 0x12);
+		let save = cartridge.save_ram().unwrap().to_vec();
+		assert_eq!((save.len(), save[0]), (0x2000, 0x12));
+		let mut cartridge = Cartridge::from_ines(&rom).unwrap();
+		cartridge.load_ram(&save).unwrap();
+		assert_eq!(cartridge.cpu_read(0x6000), 0x12);
+		assert!(matches!(cartridge.load_ram(&save[..0x1000]), Err(NesError::Battery(_))));
+
+		// NES 2.0: 2KB of PRG NVRAM.
+		rom[7] |= 0x08;
+		rom[10] = 0x50;
+		assert_eq!(Cartridge::from_ines(&rom).unwrap().save_ram().map(<[u8]>::len), Some(0x800));
+	}
+
+	#[test]
+	fn chr_ram_size_test() {
+		let mut rom = test_rom::ines(0, &[0xEA; 0x4000], &[]);
+		rom[11] = 0x07;
+		// iNES has no size, so it's 8KB.
+		let cartridge = Cartridge::from_ines(&rom).unwrap();
+		assert!(cartridge.has_chr_ram());
+		assert_eq!(cartridge.chr().len(), 0x2000);
+
+		// 64 << 7 is 8KB, 64 << 9 is 32KB.
+		rom[7] |= 0x08;
+		rom[11] = 0x09;
+		let mut cartridge = Cartridge::from_ines(&rom).unwrap();
+		assert_eq!(cartridge.chr().len(), 0x8000);
+		assert!(cartridge.ppu_write(0x1FFF, 0x12));
+		assert_eq!(cartridge.ppu_read(0x1FFF), 0x12);
+
+		// Less than 8KB is mirrored.
+		rom[11] = 0x06;
+		let mut cartridge = Cartridge::from_ines(&rom).unwrap();
+		assert!(cartridge.ppu_write(0x0010, 0x34));
+		assert_eq!(cartridge.ppu_read(0x1010), 0x34);
+
+		// CHR ROM keeps its data.
+		let mut cartridge = Cartridge::from_ines(&test_rom::nrom("EA")).unwrap();
+		assert!(!cartridge.has_chr_ram());
+		assert!(!cartridge.ppu_write(0x0010, 0x34));
+		assert_eq!(cartridge.ppu_read(0x0010), 0x00);
+	}
+
+	#[test]
+	fn trainer_test() {
+		let mut prg = vec![0xEA; 0x4000];
+		prg[..4].copy_from_slice(&[0xDE, 0xAD, 0xBE, 0xEF]);
+		let plain = test_rom::ines(0, &prg, &[0; 0x2000]);
+		assert!(!Cartridge::from_ines(&plain).unwrap().has_trainer());
+
+		// The trainer goes between the header and PRG ROM.
+		let mut rom = plain[..16].to_vec();
+		rom[6] |= 0x04;
+		rom.extend((0..512).map(|i| i as u8));
+		rom.extend_from_slice(&plain[16..]);
+		let cartridge = Cartridge::from_ines(&rom).unwrap();
+
+		assert!(cartridge.has_trainer());
+		assert_eq!([0x8000, 0x8001, 0x8002, 0x8003].map(|addr| cartridge.cpu_read(addr)), [0xDE, 0xAD, 0xBE, 0xEF]);
+		assert_eq!(cartridge.cpu_read(0x6FFF), 0x00);
+		assert_eq!(cartridge.cpu_read(0x7000), 0x00);
+		assert_eq!(cartridge.cpu_read(0x7001), 0x01);
+		assert_eq!(cartridge.cpu_read(0x71FF), 0xFF);
+		assert_eq!(cartridge.cpu_read(0x7200), 0x00);
+		// The trainer is not part of the game.
+		assert_eq!(cartridge.hash(), Cartridge::from_ines(&plain).unwrap().hash());
+
+		// Without room for PRG ROM after the trainer.
+		rom.truncate(rom.len() - 512);
+		assert!(Cartridge::from_ines(&rom).is_err());
+	}
+
+	#[test]
+	fn ines_error_test() {
+		assert!(Cartridge::from_ines(&[0x4E, 0x45, 0x53]).is_err());
+
+		let mut rom = test_rom::nrom("EA");
+		rom.truncate(100);
+		assert!(Cartridge::from_ines(&rom).is_err());
+
+		let rom = test_rom::ines(9, &[0; 0x4000], &[]);
+		assert!(Cartridge::from_ines(&rom).is_err());
+	}
+
+	#[test]
+	fn uxrom_test() {
+		// 4 banks of 16KB, each filled with its number.
+		let prg: Vec<u8> = (0..0x10000).map(|addr| (addr / 0x4000) as u8).collect();
+		let mut cartridge = Cartridge::from_ines(&test_rom::ines(2, &prg, &[])).unwrap();
+		assert_eq!((cartridge.cpu_read(0x8000), cartridge.cpu_read(0xC000), cartridge.cpu_read(0xFFFF)), (0, 3, 3));
+		assert!(cartridge.cpu_write(0x8000, 2));
+		assert_eq!((cartridge.cpu_read(0x8000), cartridge.cpu_read(0xBFFF), cartridge.cpu_read(0xC000)), (2, 2, 3));
+
+		// The bank is in the state.
+		let mut out = StateWriter::default();
+		cartridge.save_state(&mut out);
+		let state = out.into_bytes();
+		cartridge.cpu_write(0xFFFF, 1);
+		assert_eq!(cartridge.cpu_read(0x8000), 1);
+		let mut input = StateReader::new(&state);
+		cartridge.load_state(&mut input).unwrap();
+		input.finish().unwrap();
+		assert_eq!(cartridge.cpu_read(0x8000), 2);
+	}
+
+	#[test]
+	fn cnrom_test() {
+		// 4 banks of 8KB of CHR, each filled with its number. The PRG ROM is all $FF but the first byte.
+		let mut prg = vec![0xFF; 0x8000];
+		prg[0] = 0x01;
+		let chr: Vec<u8> = (0..0x8000).map(|addr| (addr / 0x2000) as u8).collect();
+		let mut rom = test_rom::ines(3, &prg, &chr);
+		let mut cartridge = Cartridge::from_ines(&rom).unwrap();
+		assert_eq!(cartridge.ppu_read(0x0000), 0);
+		assert!(cartridge.cpu_write(0x8000, 3));
+		assert_eq!((cartridge.ppu_read(0x0000), cartridge.ppu_read(0x1FFF)), (3, 3));
+
+		// NES 2.0 submapper 2: the ROM drives the bus too, 3 & $01 selects bank 1.
+		rom[7] |= 0x08;
+		rom[8] = 0x20;
+		let mut cartridge = Cartridge::from_ines(&rom).unwrap();
+		cartridge.cpu_write(0x8000, 3);
+		assert_eq!(cartridge.ppu_read(0x0000), 1);
+		cartridge.cpu_write(0x8001, 2);
+		assert_eq!(cartridge.ppu_read(0x0000), 2);
+	}
+
+	#[test]
+	fn mmc3_test() {
+		// 8 banks of 8KB of PRG, and 16 of 1KB of CHR, each filled with its number.
+		let prg: Vec<u8> = (0..0x10000).map(|addr| (addr / 0x2000) as u8).collect();
+		let chr: Vec<u8> = (0..0x4000).map(|addr| (addr / 0x400) as u8).collect();
+		let mut rom = test_rom::ines(4, &prg, &chr);
+		let mut cartridge = Cartridge::from_ines(&rom).unwrap();
+		assert_eq!((cartridge.submapper(), cartridge.mmc3_irq()), (0, IrqVariant::New));
+		assert_eq!([0x8000, 0xA000, 0xC000, 0xE000].map(|addr| cartridge.cpu_read(addr)), [0, 0, 6, 7]);
+
+		for (register, bank) in [(6, 3), (7, 4), (2, 9)] {
+			assert!(cartridge.cpu_write(0x8000, register));
+			assert!(cartridge.cpu_write(0x8001, bank));
+		}
+		assert_eq!([0x8000, 0xA000, 0xC000, 0xE000].map(|addr| cartridge.cpu_read(addr)), [3, 4, 6, 7]);
+		assert_eq!(cartridge.ppu_read(0x1000), 9);
+		assert_eq!(cartridge.mirroring(), Mirroring::Horizontal);
+		cartridge.cpu_write(0xA000, 0);
+		assert_eq!(cartridge.mirroring(), Mirroring::Vertical);
+		// The game chose it, the header didn't change.
+		assert!(!cartridge.fixed_up());
+
+		// The registers are in the state.
+		let mut out = StateWriter::default();
+		cartridge.save_state(&mut out);
+		let state = out.into_bytes();
+		cartridge.cpu_write(0x8000, 0x46);
+		cartridge.cpu_write(0x8001, 1);
+		cartridge.cpu_write(0xA000, 1);
+		assert_eq!(cartridge.cpu_read(0x8000), 6);
+		let mut input = StateReader::new(&state);
+		cartridge.load_state(&mut input).unwrap();
+		input.finish().unwrap();
+		assert_eq!((cartridge.cpu_read(0x8000), cartridge.ppu_read(0x1000)), (3, 9));
+		assert_eq!(cartridge.mirroring(), Mirroring::Vertical);
+
+		// NES 2.0 submapper 4: the old IRQ.
+		rom[7] |= 0x08;
+		rom[8] = 0x40;
+		let mut cartridge = Cartridge::from_ines(&rom).unwrap();
+		assert_eq!((cartridge.submapper(), cartridge.mmc3_irq()), (4, IrqVariant::Old));
+		cartridge.set_mmc3_irq(IrqVariant::New);
+		assert_eq!(cartridge.mmc3_irq(), IrqVariant::New);
+	}
+
+	/// `prg` with `program` at the start of the last 8KB ($E000), where the reset vector points.
+	fn with_program(mut prg: Vec<u8>, program: &str) -> Vec<u8> {
+		let program = hex_to_bytes(program).unwrap();
+		let start = prg.len() - 0x2000;
+		prg[start..start + program.len()].copy_from_slice(&program);
+		let len = prg.len();
+		prg[len - 4..len - 2].copy_from_slice(&[0x00, 0xE0]);
+		prg
+	}
+
+	#[test]
+	fn debug_state_test() {
+		/*
+		LDA #$02
+		STA $8000
+		*/
+		let prg = with_program(vec![0; 0x10000], "A9 02 8D 00 80");
+		let mut emulator = Emulator::new(Cartridge::from_ines(&test_rom::ines(2, &prg, &[])).unwrap());
+		let slots = |info: &MapperDebugInfo| info.prg.iter().map(|slot| (slot.addr, slot.size / 1024, slot.bank)).collect::<Vec<_>>();
+		let info = emulator.bus().cartridge().debug_state();
+		assert_eq!(slots(&info), [(0x8000, 16, 0), (0xC000, 16, 3)]);
+		assert_eq!(info.chr, [BankSlot { addr: 0, size: 0x2000, bank: 0 }]);
+		assert_eq!(info.irq, None);
+		emulator.step_instruction();
+		emulator.step_instruction();
+		assert_eq!(slots(&emulator.bus().cartridge().debug_state()), [(0x8000, 16, 2), (0xC000, 16, 3)]);
+		let location = emulator.bus().cartridge().prg_location(0x8010).unwrap();
+		assert_eq!(location, PrgLocation { bank: 2, file_offset: 16 + 0x8010 });
+		assert_eq!(location.to_string(), "PRG bank 2, file offset $8020");
+		assert_eq!(emulator.bus().cartridge().prg_location(0x6000), None);
+
+		/*
+		LDA #$C6    R6, the CHR inversion and the PRG mode
+		STA $8000
+		LDA #$03
+		STA $8001
+		LDA #$05    IRQ latch
+		STA $C000
+		*/
+		let prg = with_program(vec![0; 0x10000], "A9 C6 8D 00 80 A9 03 8D 01 80 A9 05 8D 00 C0");
+		let mut emulator = Emulator::new(Cartridge::from_ines(&test_rom::ines(4, &prg, &[0; 0x4000])).unwrap());
+		assert_eq!(slots(&emulator.bus().cartridge().debug_state()), [(0x8000, 8, 0), (0xA000, 8, 0), (0xC000, 8, 6), (0xE000, 8, 7)]);
+		for _ in 0..6 {
+			emulator.step_instruction();
+		}
+		let info = emulator.bus().cartridge().debug_state();
+		assert_eq!(slots(&info), [(0x8000, 8, 6), (0xA000, 8, 0), (0xC000, 8, 3), (0xE000, 8, 7)]);
+		assert_eq!(info.chr.iter().map(|slot| (slot.addr, slot.size / 1024)).collect::<Vec<_>>(), [(0, 1), (0x400, 1), (0x800, 1), (0xC00, 1), (0x1000, 2), (0x1800, 2)]);
+		assert_eq!(info.irq, Some(IrqCounterInfo { counter: 0, latch: 5, enabled: false, pending: false }));
+		assert_eq!(info.to_string(), "Mapper 4\n\
+			PRG: $8000 8KB bank 6, $A000 8KB bank 0, $C000 8KB bank 3, $E000 8KB bank 7\n\
+			CHR: $0000 1KB bank 0, $0400 1KB bank 0, $0800 1KB bank 0, $0C00 1KB bank 0, $1000 2KB bank 0, $1800 2KB bank 0\n\
+			Mirroring: Horizontal, PRG RAM on\n\
+			IRQ: counter 0, latch 5, disabled");
+	}
+
+	#[test]
+	fn vrc6_test() {
+		// 16 banks of 8KB of PRG, and 32 of 1KB of CHR, each filled with its number.
+		let prg: Vec<u8> = (0..0x20000).map(|addr| (addr / 0x2000) as u8).collect();
+		let chr: Vec<u8> = (0..0x8000).map(|addr| (addr / 0x400) as u8).collect();
+		let mut cartridge = Cartridge::from_ines(&test_rom::ines(26, &prg, &chr)).unwrap();
+		assert_eq!([0x8000, 0xA000, 0xC000, 0xE000].map(|addr| cartridge.cpu_read(addr)), [0, 1, 0, 15]);
+
+		assert!(cartridge.cpu_write(0x8000, 2));
+		assert!(cartridge.cpu_write(0xC000, 11));
+		// $D002 on mapper 26 is the register of $D001 on mapper 24, the second 1KB.
+		assert!(cartridge.cpu_write(0xD002, 21));
+		assert!(cartridge.cpu_write(0xB003, 0x8C));
+		assert_eq!([0x8000, 0xA000, 0xC000, 0xE000].map(|addr| cartridge.cpu_read(addr)), [4, 5, 11, 15]);
+		assert_eq!((cartridge.ppu_read(0x0400), cartridge.ppu_read(0x0800)), (21, 0));
+		assert_eq!(cartridge.mirroring(), Mirroring::SingleScreenUpper);
+
+		// The registers are in the state.
+		let mut out = StateWriter::default();
+		cartridge.save_state(&mut out);
+		let state = out.into_bytes();
+		cartridge.cpu_write(0x8000, 0);
+		cartridge.cpu_write(0xB003, 0x80);
+		let mut input = StateReader::new(&state);
+		cartridge.load_state(&mut input).unwrap();
+		input.finish().unwrap();
+		assert_eq!((cartridge.cpu_read(0x8000), cartridge.ppu_read(0x0400)), (4, 21));
+		assert_eq!(cartridge.mirroring(), Mirroring::SingleScreenUpper);
+	}
+
+	#[test]
+	fn fds_test() {
+		use crate::fds::disk::test_disk;
+
+		let mut bios = vec![0xEA; 0x2000];
+		bios[0x1FFC..0x1FFE].copy_from_slice(&[0x24, 0xEE]);
+		let side = test_disk::side(&[0xEA], 0x6000);
+		let image = test_disk::image(&[side.clone(), side.clone()]);
+		let mut cartridge = Cartridge::from_fds(&bios, &image).unwrap();
+		assert_eq!((cartridge.mapper(), cartridge.prg_ram().len(), cartridge.has_chr_ram()), (20, 0x8000, true));
+		assert_eq!((cartridge.cpu_read(0xFFFC), cartridge.cpu_read(0xFFFD)), (0x24, 0xEE));
+		// PRG RAM up to $DFFF.
+		assert!(cartridge.cpu_write(0x8000, 0x42));
+		assert_eq!(cartridge.cpu_read(0x8000), 0x42);
+		assert!(!cartridge.cpu_write(0xE000, 0x42));
+		assert_eq!(cartridge.prg_location(0xE000), None);
+		// The image, without the header, identifies the game.
+		assert_eq!(cartridge.hash(), Cartridge::from_fds(&bios, &[&side[..], &side[..]].concat()).unwrap().hash());
+
+		assert_eq!((cartridge.disk_sides(), cartridge.disk_side()), (2, Some(0)));
+		cartridge.insert_disk(Some(1)).unwrap();
+		assert_eq!(cartridge.disk_side(), Some(1));
+		assert!(cartridge.insert_disk(Some(2)).is_err());
+
+		assert!(matches!(Cartridge::from_fds(&bios[..0x1000], &image), Err(NesError::Disk(_))));
+		assert!(matches!(Cartridge::from_fds(&bios, &image[..100]), Err(NesError::Disk(_))));
+		let mut nrom = Cartridge::from_ines(&test_rom::nrom("EA")).unwrap();
+		assert_eq!((nrom.disk_sides(), nrom.disk_side()), (0, None));
+		assert!(nrom.insert_disk(Some(0)).is_err());
+	}
+
+	#[test]
+	fn rom_database_test() {
+		// The header says NROM, but it's UxROM with vertical mirroring.
+		let prg: Vec<u8> = (0..0x8000).map(|addr| (addr / 0x4000) as u8).collect();
+		let rom = test_rom::ines(0, &prg, &[]);
+		let header = Cartridge::from_ines(&rom).unwrap();
+		assert_eq!(header.mapper(), 0);
+		assert!(!header.fixed_up());
+
+		let db = RomDb::parse(&format!("{:08X},,2,vertical,,Test UxROM", header.hash())).unwrap();
+		let mut cartridge = Cartridge::from_ines_with_db(&rom, &db).unwrap();
+		assert_eq!(cartridge.mapper(), 2);
+		assert_eq!(cartridge.mirroring(), Mirroring::Vertical);
+		assert_eq!(cartridge.header(), HeaderValues { mapper: 0, mirroring: Mirroring::Horizontal, region: Region::Ntsc });
+		assert!(cartridge.fixed_up());
+
+		// UxROM: the write selects the bank at $8000, NROM would drop it.
+		assert_eq!(cartridge.cpu_read(0x8000), 0);
+		assert!(cartridge.cpu_write(0x8000, 1));
+		assert_eq!(cartridge.cpu_read(0x8000), 1);
+
+		// Another ROM isn't touched.
+		let other = Cartridge::from_ines_with_db(&test_rom::nrom("EA"), &db).unwrap();
+		assert_eq!(other.mapper(), 0);
+	}
+}
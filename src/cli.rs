@@ -0,0 +1,920 @@
+use std::path::PathBuf;
+
+use log::LevelFilter;
+
+use rust_nes_emulator::bench::BenchBudget;
+use rust_nes_emulator::harness::{Condition, Verdict};
+use rust_nes_emulator::log_target;
+use rust_nes_emulator::ram_init::RamInitPattern;
+use rust_nes_emulator::mmc3::IrqVariant;
+use rust_nes_emulator::region::Region;
+use rust_nes_emulator::rewind::{DEFAULT_REWIND_INTERVAL, DEFAULT_REWIND_MEMORY};
+use rust_nes_emulator::state_slots::{DEFAULT_STATE_DIR, SLOTS};
+use rust_nes_emulator::trace::TraceFilter;
+
+pub const USAGE: &str = "\
+Usage: rust-nes-emulator [OPTIONS] <ROM>
+       rust-nes-emulator [OPTIONS] --demo <adc|tolower|helloworld|snake>
+       rust-nes-emulator [OPTIONS] --batch <DIR> [--report <FILE>]
+
+Arguments:
+  <ROM>                  iNES file (.nes), FDS disk image (.fds, with --fds-bios), or a raw 6502 binary (any file
+                         without the iNES header)
+
+Options:
+  --demo <NAME>          Run one of the built-in demo programs instead of a ROM (snake runs on easy6502, with W A S D)
+  --machine <MACHINE>    What demos and raw binaries run on: flat (64KB of RAM, the default), or easy6502 (random
+                         numbers at $FE, the last key at $FF, and a 32x32 display at $0200, in the window or the terminal)
+  --seed <N>             Seed of the easy6502 random numbers, to repeat a run (default: from the clock)
+  --batch <DIR>          Run every .nes file of DIR headless for --frames frames, and write a report with a row for
+                         each: whether it loaded (and its mapper), jammed or hit an illegal opcode, rendered a picture,
+                         the hash of the last frame, and the speed. A panic is a row too, the batch goes on
+  --report <FILE>        Write the --batch report to FILE: CSV, or JSON for a .json file (default: CSV on stdout)
+  --trace-file <FILE>    Write every executed instruction to FILE, like nestest.log (--trace is the same)
+  --trace-pc <START-END> Only trace instructions in the range (like $C000-$C0FF)
+  --trace-from <ADDRESS> Start tracing when the instruction at ADDRESS runs for the first time
+  --trace-last <N>       Keep only the last N lines, written when the run ends (or crashes)
+  --trace-penalties      Add the oops cycles of an instruction (page crossed, branch taken) to its line, like
+                         CYC:9 +1. The trace no longer diffs with nestest.log
+  --symbols <FILE>       Names for addresses in the trace and the debugger: FCEUX .nl, cc65 .dbg, or VICE .sym/.lbl
+                         labels (from ld65 -Ln). Can be repeated
+  --headless             Don't open a window
+  --bench <TIME|FRAMES>  Run headless as fast as possible for TIME seconds (10 or 10s) or FRAMES frames (600f),
+                         and print the speed as a key=value line
+  --debug                Run in the debugger: step, breakpoints, watchpoints (type 'h' at the prompt). No window
+  --frames <N>           Run N frames and exit (default: 60 when headless)
+  --raw <FILE>           Run FILE as a raw 6502 binary, on 64KB of flat memory, even if it has an iNES header
+  --load <ADDRESS>       Load the raw binary at ADDRESS (default: --entry, or so that it ends at $FFFF)
+  --entry <ADDRESS>      Start the raw binary at ADDRESS, like 0x8000 (default: the binary's reset vector if it has
+                         one at $FFFC, or --load)
+  --scale <N>            Window scale (default: 3)
+  --region <REGION>      ntsc or pal (default: from the NES 2.0 header, NTSC for iNES files)
+  --romdb <FILE>         More ROM database lines (crc32,sha1,mapper,mirroring,region,name), to fix wrong headers.
+                         They win over the built-in ones
+  --fds-bios <FILE>      The BIOS of the Famicom Disk System (disksys.rom, 8KB), to run .fds disk images. F6 flips
+                         the disk to the next side
+  --mmc3-irq <VARIANT>   When the IRQ of MMC3 games fires: new (MMC3B/C) or old (MMC3A), for the games that need the
+                         one the header doesn't say (default: from the NES 2.0 submapper, new for iNES files)
+  --ram-init <PATTERN>   RAM at power on: zero (the default), ff, alternating (4 bytes of $00, 4 of $FF), or
+                         random:SEED
+  --warn-ram             Log every read of RAM (internal or PRG RAM) that was never written, once for every byte:
+                         what it reads is what the RAM had at power on, which differs between consoles
+  --cycle-accurate       Do the dummy reads and writes of the real CPU (a store indexed across a page reads the wrong
+                         address first, INC writes back what it read before the result), that registers like $2002,
+                         $2007 and MMC1 can tell. Slower
+  --overclock <N>        Give the CPU N more scanlines of time every frame (at most 1000), at the end of VBlank, for
+                         games that slow down. The PPU and the APU wait meanwhile, so the picture and the sound keep
+                         their timing (default: 0, off)
+  --expansion-volume <P> Volume of the sound channels of the cartridge (VRC6, FDS), in percent of the Famicom's, at most
+                         400 (default: 100)
+  --speed <X>            Run X times as fast as the console, like 2.0 or 0.5 (default: 1.0). Tab toggles turbo (as fast as possible)
+  --crop-overscan        Hide the top and bottom 8 lines, like most TVs did (in the window and in screenshots)
+  --palette <NAME|FILE>  The colors: a preset (default, fceux) or a .pal file of 64 or 512 colors. F9 switches
+                         between them in the window
+  --compare <FILE>       Compare the frames with a reference PNG, 256x240 or 256x224 (without the overscan lines, which
+                         are left out then, and with --crop-overscan). The window shows a heatmap of the pixels that
+                         differ instead of the picture. Headless, the last frame is compared, and the run fails when
+                         more pixels differ than --compare-threshold
+  --compare-threshold <N>
+                         Pixels that may differ in a headless run (default: 0)
+  --compare-verbose      Print every pixel that differs, with its color and the one in the reference (headless)
+  --state-dir <DIR>      Save states directory (default: states). F5 saves, F8 loads, 0-9 select the slot
+  --load-slot <N>        Load save state slot N at start
+  --record <FILE>        Record the controller input to an FM2 movie (from power on, or from --load-slot)
+  --play <FILE>          Play an FM2 movie, ignoring the keyboard until it ends
+  --rewind-interval <N>  Frames between rewind states, 0 disables rewind (default: 3). Hold Backspace to rewind
+  --rewind-memory <MB>   Memory for rewind states (default: 64)
+  --zapper               Plug a Zapper light gun in port 2: aim with the mouse, and click to pull the trigger
+  --watch                Reload the ROM when its file changes, and power on (in the window, where R reloads it too, and
+                         in the debugger, keeping the breakpoints, watchpoints and symbols)
+  --keymap <FILE>        Keyboard mapping, lines of 'Button = Key', or 'P2 Button = Key' for player 2 (default: arrows, Z/X = B/A,
+                         Enter = Start, Right Shift = Select; player 2: WASD, F/G = B/A, E = Start, Q = Select)
+  --log-level <LEVEL>    off, error, warn, info, debug or trace (default: info)
+  --log <SPEC>           Log level of each part of the console, like cpu=debug,ppu=warn (parts: cpu, bus, ppu, apu,
+                         mapper, input, emulator). A level alone is the default, like --log-level
+  -h, --help             Print this help
+
+Headless test options (for test ROMs, implies --headless):
+  --pass-pc <ADDRESS>    Pass when PC gets to ADDRESS
+  --fail-pc <ADDRESS>    Fail when PC gets to ADDRESS
+  --pass-mem <ADDR=VAL>  Pass when the byte at ADDR equals VAL (like $6000=0x00)
+  --fail-mem <ADDR=VAL>  Fail when the byte at ADDR equals VAL
+  --cycles <N>           Stop after N CPU cycles, in addition to --frames
+  --dump <START-END>     Print the memory from START to END when stopped (like $6000-$60FF)
+  --strict-rom           Fail at the first write to ROM ($8000-$FFFF), and print the instruction that wrote
+  --strict-ram           Fail at the first read of RAM that was never written, like --warn-ram, and print the
+                         instruction that read
+  --strict-stack         Fail at the first push with SP at $00, or pull with SP at $FF, and print the instruction
+  --hash-after <N>       Run N frames, and print the hashes of the frame and of the state (RAM and registers), to
+                         compare with the ones of another run
+  --screenshot-after <N> Run N frames, and write the screen to --screenshot-out, a PNG of the 256x240 picture of the
+                         console (F12 takes one in the window, <ROM>-<N>.png in the current directory)
+  --screenshot-out <FILE>
+  --wav-out <FILE>       Write the audio to FILE, a 16-bit mono WAV (48000 Hz). It's complete after every frame, so
+                         a run stopped with Ctrl-C has the audio up to there
+  --wav-channels         With --wav-out, write every APU channel alone too, next to it: game-pulse1.wav,
+                         game-pulse2.wav, game-triangle.wav, game-noise.wav and game-dmc.wav for game.wav
+  --profile              Count the executions, cycles and penalty cycles (page crossed, branch taken) of every
+                         opcode, and print them when stopped, the most cycles first
+  --blargg               Run a blargg test ROM until it reports its result at $6000, pressing reset when it asks,
+                         and print its message (default: 3600 frames, one minute)
+
+Exit codes: 0 passed (or ran to the end, without conditions), 1 failed or the CPU jammed, 2 bad arguments,
+3 no condition was met (or the blargg ROM was not done) before the frames/cycles ran out.";
+
+/// The built-in demo programs, from `program_loader`.
+#[derive(Clone, Copy, PartialEq, Debug)]
+pub enum Demo {
+	Adc,
+	ToLower,
+	HelloWorld,
+	Snake,
+}
+
+/// The memory map of demos and raw binaries. iNES ROMs always run on the console.
+#[derive(Clone, Copy, PartialEq, Debug)]
+pub enum Machine {
+	/// `FlatBus`: 64KB of RAM.
+	Flat,
+	/// `Easy6502Bus`: RAM, and random numbers, a key and a display.
+	Easy6502,
+}
+
+/// What to run.
+#[derive(Clone, PartialEq, Debug)]
+pub enum Program {
+	Rom(PathBuf),
+	Demo(Demo),
+	/// Every ROM of a directory, see batch.rs.
+	Batch(PathBuf),
+}
+
+#[derive(Clone, PartialEq, Debug)]
+pub struct Options {
+	pub program: Program,
+	pub trace: Option<PathBuf>,
+	pub trace_filter: TraceFilter,
+	/// See `Tracer::with_penalties`.
+	pub trace_penalties: bool,
+	/// Symbol files, for the trace and the debugger.
+	pub symbols: Vec<PathBuf>,
+	pub headless: bool,
+	pub debug: bool,
+	pub bench: Option<BenchBudget>,
+	pub frames: Option<u32>,
+	/// Load address of a raw binary. The ROM is treated as iNES if not set.
+	/// Run the file as a raw binary, see `program_loader::load_raw`. Also with `load` or `entry`, or without an
+	/// iNES header.
+	pub raw: bool,
+	pub load: Option<u16>,
+	pub entry: Option<u16>,
+	pub scale: u32,
+	pub speed: f64,
+	/// Overrides the region of the cartridge header.
+	pub region: Option<Region>,
+	/// ROM database on top of the built-in one, see romdb.rs.
+	pub romdb: Option<PathBuf>,
+	/// The BIOS of the FDS RAM adapter, for disk images, see `Cartridge::from_fds`.
+	pub fds_bios: Option<PathBuf>,
+	/// Overrides the MMC3 IRQ variant of the header, see mmc3.rs.
+	pub mmc3_irq: Option<IrqVariant>,
+	pub ram_init: RamInitPattern,
+	/// Log the reads of RAM that was never written, see `NesBus::set_strict_ram`.
+	pub warn_ram: bool,
+	/// See `CPU::set_cycle_accurate`.
+	pub cycle_accurate: bool,
+	/// Extra scanlines for the CPU every frame, see `NesBus::set_overclock`.
+	pub overclock: u16,
+	/// Percent, see `APU::set_expansion_volume`. None is 100.
+	pub expansion_volume: Option<u16>,
+	pub crop_overscan: bool,
+	/// A preset or a .pal file, see `MasterPalette::preset_or_file`. None is the default colors.
+	pub palette: Option<String>,
+	pub keymap: Option<PathBuf>,
+	/// The mouse is a Zapper in port 2.
+	pub zapper: bool,
+	/// Reload the ROM when the file changes, see `rom_watch.rs`.
+	pub watch: bool,
+	pub state_dir: PathBuf,
+	pub load_slot: Option<u8>,
+	pub record: Option<PathBuf>,
+	pub play: Option<PathBuf>,
+	/// 0 disables rewind.
+	pub rewind_interval: u32,
+	/// In bytes.
+	pub rewind_memory: usize,
+	pub log_level: LevelFilter,
+	/// Log levels of the targets of the library (see log_target.rs), on top of `log_level`.
+	pub log_targets: Vec<(String, LevelFilter)>,
+	/// Exit conditions for the test harness, in the order they were given.
+	pub conditions: Vec<(Condition, Verdict)>,
+	pub cycles: Option<u64>,
+	/// Memory range to print when the harness stops, inclusive.
+	pub dump: Option<(u16, u16)>,
+	/// Run a blargg test ROM, see `Harness::run_blargg`.
+	pub blargg: bool,
+	/// Fail at the first write to ROM, see `Harness::stop_on_rom_write`.
+	pub strict_rom: bool,
+	/// Fail at the first read of RAM that was never written, see `Harness::stop_on_uninitialized_read`.
+	pub strict_ram: bool,
+	/// Fail at the first stack overflow or underflow, see `Harness::stop_on_stack_fault`.
+	pub strict_stack: bool,
+	/// Frames to run before printing the hashes, see `Emulator::frame_hash`.
+	pub hash_after: Option<u32>,
+	/// Frames to run before writing the screen to `screenshot_out`, a PNG (see png.rs).
+	pub screenshot_after: Option<u32>,
+	pub screenshot_out: Option<PathBuf>,
+	/// Write the audio of a headless run, see `wav::WavRecorder`.
+	pub wav_out: Option<PathBuf>,
+	/// Write every APU channel of `wav_out` alone too, see `APU::capture_channels`.
+	pub wav_channels: bool,
+	/// Print the profile of a headless run, see profile.rs.
+	pub profile: bool,
+	/// Where the report of `Program::Batch` goes.
+	pub report: Option<PathBuf>,
+	/// Reference image of the frames, see frame_diff.rs.
+	pub compare: Option<PathBuf>,
+	pub compare_threshold: u32,
+	pub compare_verbose: bool,
+	pub machine: Machine,
+	/// Seed of the easy6502 random numbers.
+	pub seed: Option<u64>,
+}
+
+/// Why the arguments could not be used.
+#[derive(Clone, PartialEq, Debug)]
+pub enum CliError {
+	/// -h or --help. Not really an error, but there is nothing to run.
+	Help,
+	Invalid(String),
+}
+
+/// Parse the command line arguments, without the program name.
+pub fn parse_args<I: IntoIterator<Item = String>>(args: I) -> Result<Options, CliError> {
+	let mut args = args.into_iter();
+
+	let mut program = None;
+	let mut trace = None;
+	let mut trace_filter = TraceFilter::default();
+	let mut trace_penalties = false;
+	let mut symbols = vec![];
+	let mut headless = false;
+	let mut debug = false;
+	let mut bench = None;
+	let mut frames = None;
+	let mut raw = false;
+	let mut load = None;
+	let mut entry = None;
+	let mut scale = 3;
+	let mut speed: f64 = 1.0;
+	let mut region = None;
+	let mut romdb = None;
+	let mut fds_bios = None;
+	let mut mmc3_irq = None;
+	let mut ram_init = RamInitPattern::default();
+	let mut warn_ram = false;
+	let mut cycle_accurate = false;
+	let mut overclock = 0;
+	let mut expansion_volume = None;
+	let mut crop_overscan = false;
+	let mut palette = None;
+	let mut keymap = None;
+	let mut zapper = false;
+	let mut watch = false;
+	let mut state_dir = PathBuf::from(DEFAULT_STATE_DIR);
+	let mut load_slot = None;
+	let mut record = None;
+	let mut play = None;
+	let mut rewind_interval = DEFAULT_REWIND_INTERVAL;
+	let mut rewind_memory = DEFAULT_REWIND_MEMORY;
+	let mut log_level = LevelFilter::Info;
+	let mut log_targets = vec![];
+	let mut conditions = vec![];
+	let mut cycles = None;
+	let mut dump = None;
+	let mut blargg = false;
+	let mut strict_rom = false;
+	let mut strict_ram = false;
+	let mut strict_stack = false;
+	let mut hash_after = None;
+	let mut wav_out = None;
+	let mut wav_channels = false;
+	let mut profile = false;
+	let mut report = None;
+	let mut compare = None;
+	let mut compare_threshold = None;
+	let mut compare_verbose = false;
+	let mut screenshot_after = None;
+	let mut screenshot_out = None;
+	let mut machine = None;
+	let mut seed = None;
+
+	while let Some(arg) = args.next() {
+		let mut value = |name: &str| args.next().ok_or_else(|| CliError::Invalid(format!("{} needs a value", name)));
+
+		match arg.as_str() {
+			"-h" | "--help" => return Err(CliError::Help),
+			"--demo" => {
+				let demo = match value("--demo")?.to_lowercase().as_str() {
+					"adc" => Demo::Adc,
+					"tolower" => Demo::ToLower,
+					"helloworld" => Demo::HelloWorld,
+					"snake" => Demo::Snake,
+					other => return Err(CliError::Invalid(format!("Unknown demo '{}', expected adc, tolower, helloworld or snake", other))),
+				};
+				set_program(&mut program, Program::Demo(demo))?;
+			}
+			"--batch" => set_program(&mut program, Program::Batch(PathBuf::from(value("--batch")?)))?,
+			"--report" => report = Some(PathBuf::from(value("--report")?)),
+			"--trace" | "--trace-file" => trace = Some(PathBuf::from(value(&arg)?)),
+			"--trace-pc" => trace_filter.pc_range = Some(parse_range(&value("--trace-pc")?, "--trace-pc")?),
+			"--trace-from" => trace_filter.start_at = Some(parse_address(&value("--trace-from")?, "--trace-from")?),
+			"--trace-last" => trace_filter.last = Some(parse_number(&value("--trace-last")?, "--trace-last")? as usize),
+			"--trace-penalties" => trace_penalties = true,
+			"--symbols" => symbols.push(PathBuf::from(value("--symbols")?)),
+			"--headless" => headless = true,
+			"--debug" => debug = true,
+			"--bench" => bench = Some(parse_bench_budget(&value("--bench")?)?),
+			"--frames" => frames = Some(parse_number(&value("--frames")?, "--frames")?),
+			"--raw" => {
+				raw = true;
+				set_program(&mut program, Program::Rom(PathBuf::from(value("--raw")?)))?;
+			}
+			"--load" => load = Some(parse_address(&value("--load")?, "--load")?),
+			"--entry" => entry = Some(parse_address(&value("--entry")?, "--entry")?),
+			"--scale" => {
+				scale = parse_number(&value("--scale")?, "--scale")?;
+				if scale == 0 {
+					return Err(CliError::Invalid("--scale must be at least 1".to_string()));
+				}
+			}
+			"--speed" => {
+				let value = value("--speed")?;
+				speed = value.parse().map_err(|_| CliError::Invalid(format!("--speed expects a number, got '{}'", value)))?;
+				if !(speed > 0.0 && speed.is_finite()) {
+					return Err(CliError::Invalid(format!("--speed must be positive, got '{}'", value)));
+				}
+			}
+			"--region" => {
+				region = match value("--region")?.to_lowercase().as_str() {
+					"ntsc" => Some(Region::Ntsc),
+					"pal" => Some(Region::Pal),
+					other => return Err(CliError::Invalid(format!("Unknown region '{}', expected ntsc or pal", other))),
+				};
+			}
+			"--romdb" => romdb = Some(PathBuf::from(value("--romdb")?)),
+			"--fds-bios" => fds_bios = Some(PathBuf::from(value("--fds-bios")?)),
+			"--mmc3-irq" => {
+				mmc3_irq = match value("--mmc3-irq")?.to_lowercase().as_str() {
+					"new" => Some(IrqVariant::New),
+					"old" => Some(IrqVariant::Old),
+					other => return Err(CliError::Invalid(format!("Unknown MMC3 IRQ variant '{}', expected new or old", other))),
+				};
+			}
+			"--ram-init" => ram_init = value("--ram-init")?.parse().map_err(CliError::Invalid)?,
+			"--warn-ram" => warn_ram = true,
+			"--crop-overscan" => crop_overscan = true,
+			"--cycle-accurate" => cycle_accurate = true,
+			"--overclock" => {
+				let scanlines = parse_number(&value("--overclock")?, "--overclock")?;
+				if scanlines > 1000 {
+					return Err(CliError::Invalid(format!("--overclock is at most 1000 scanlines, got {}", scanlines)));
+				}
+				overclock = scanlines as u16;
+			}
+			"--expansion-volume" => {
+				let percent = parse_number(&value("--expansion-volume")?, "--expansion-volume")?;
+				if percent > 400 {
+					return Err(CliError::Invalid(format!("--expansion-volume is at most 400 percent, got {}", percent)));
+				}
+				expansion_volume = Some(percent as u16);
+			}
+			"--palette" => palette = Some(value("--palette")?),
+			"--keymap" => keymap = Some(PathBuf::from(value("--keymap")?)),
+			"--zapper" => zapper = true,
+			"--watch" => watch = true,
+			"--state-dir" => state_dir = PathBuf::from(value("--state-dir")?),
+			"--load-slot" => {
+				let slot = parse_number(&value("--load-slot")?, "--load-slot")?;
+				if slot >= SLOTS as u32 {
+					return Err(CliError::Invalid(format!("--load-slot must be 0-{}, got {}", SLOTS - 1, slot)));
+				}
+				load_slot = Some(slot as u8);
+			}
+			"--record" => record = Some(PathBuf::from(value("--record")?)),
+			"--play" => play = Some(PathBuf::from(value("--play")?)),
+			"--rewind-interval" => rewind_interval = parse_number(&value("--rewind-interval")?, "--rewind-interval")?,
+			"--rewind-memory" => {
+				let megabytes = parse_number(&value("--rewind-memory")?, "--rewind-memory")?;
+				rewind_memory = megabytes as usize * 1024 * 1024;
+			}
+			"--log-level" => {
+				let level = value("--log-level")?;
+				log_level = parse_log_level(&level)?;
+			}
+			"--log" => {
+				for part in value("--log")?.split(',') {
+					match part.split_once('=') {
+						Some((target, level)) => {
+							let target = target.trim().to_lowercase();
+							if !log_target::ALL.contains(&target.as_str()) {
+								return Err(CliError::Invalid(format!("Unknown log target '{}', expected one of {}", target, log_target::ALL.join(", "))));
+							}
+							log_targets.push((target, parse_log_level(level.trim())?));
+						}
+						None => log_level = parse_log_level(part.trim())?,
+					}
+				}
+			}
+			"--pass-pc" => conditions.push((Condition::PcEquals(parse_address(&value("--pass-pc")?, "--pass-pc")?), Verdict::Pass)),
+			"--fail-pc" => conditions.push((Condition::PcEquals(parse_address(&value("--fail-pc")?, "--fail-pc")?), Verdict::Fail)),
+			"--pass-mem" => conditions.push((parse_memory_condition(&value("--pass-mem")?, "--pass-mem")?, Verdict::Pass)),
+			"--fail-mem" => conditions.push((parse_memory_condition(&value("--fail-mem")?, "--fail-mem")?, Verdict::Fail)),
+			"--cycles" => cycles = Some(parse_number(&value("--cycles")?, "--cycles")? as u64),
+			"--dump" => dump = Some(parse_range(&value("--dump")?, "--dump")?),
+			"--blargg" => blargg = true,
+			"--strict-rom" => strict_rom = true,
+			"--strict-ram" => strict_ram = true,
+			"--strict-stack" => strict_stack = true,
+			"--wav-out" => wav_out = Some(PathBuf::from(value("--wav-out")?)),
+			"--wav-channels" => wav_channels = true,
+			"--profile" => profile = true,
+			"--compare" => compare = Some(PathBuf::from(value("--compare")?)),
+			"--compare-threshold" => compare_threshold = Some(parse_number(&value("--compare-threshold")?, "--compare-threshold")?),
+			"--compare-verbose" => compare_verbose = true,
+			"--screenshot-after" => screenshot_after = Some(parse_number(&value("--screenshot-after")?, "--screenshot-after")?),
+			"--screenshot-out" => screenshot_out = Some(PathBuf::from(value("--screenshot-out")?)),
+			"--hash-after" => hash_after = Some(parse_number(&value("--hash-after")?, "--hash-after")?),
+			"--machine" => {
+				machine = match value("--machine")?.to_lowercase().as_str() {
+					"flat" => Some(Machine::Flat),
+					"easy6502" => Some(Machine::Easy6502),
+					other => return Err(CliError::Invalid(format!("Unknown machine '{}', expected flat or easy6502", other))),
+				};
+			}
+			"--seed" => {
+				let value = value("--seed")?;
+				seed = Some(value.parse().map_err(|_| CliError::Invalid(format!("--seed expects a number, got '{}'", value)))?);
+			}
+			_ if arg.starts_with('-') => return Err(CliError::Invalid(format!("Unknown option '{}'", arg))),
+			_ => set_program(&mut program, Program::Rom(PathBuf::from(arg)))?,
+		}
+	}
+
+	if trace.is_none() && (trace_filter != TraceFilter::default() || trace_penalties) {
+		return Err(CliError::Invalid("Trace filters need --trace-file".to_string()));
+	}
+	if !symbols.is_empty() && trace.is_none() && !debug {
+		return Err(CliError::Invalid("--symbols is for --trace-file and --debug".to_string()));
+	}
+
+	let program = program.ok_or_else(|| CliError::Invalid("Nothing to run: give a ROM file, or --demo".to_string()))?;
+	// Raw for sure. A file without any of them is raw when it has no iNES header, see main.rs.
+	let raw = raw || load.is_some() || entry.is_some();
+
+	// Only the options of the console: the rest is for a single ROM.
+	let batch = matches!(program, Program::Batch(_));
+	if batch && (debug || bench.is_some() || raw || trace.is_some() || !symbols.is_empty() || !conditions.is_empty() || cycles.is_some() || dump.is_some() || blargg || strict_rom || strict_ram || strict_stack
+		|| hash_after.is_some() || screenshot_after.is_some() || wav_out.is_some() || profile || compare.is_some() || record.is_some() || play.is_some() || load_slot.is_some() || watch || zapper || palette.is_some() || machine.is_some()) {
+		return Err(CliError::Invalid("--batch runs every ROM for --frames frames, with the options of the console only (like --region or --ram-init)".to_string()));
+	}
+	if report.is_some() && !batch {
+		return Err(CliError::Invalid("--report is the report of --batch".to_string()));
+	}
+	if raw && matches!(program, Program::Demo(_)) {
+		return Err(CliError::Invalid("--load and --entry are for raw binaries, not demos".to_string()));
+	}
+
+	if blargg && !conditions.is_empty() {
+		return Err(CliError::Invalid("--blargg has its own conditions, it can't be used with --pass-* and --fail-*".to_string()));
+	}
+	if blargg && (raw || matches!(program, Program::Demo(_))) {
+		return Err(CliError::Invalid("--blargg needs an iNES ROM".to_string()));
+	}
+	if strict_rom && (blargg || raw || matches!(program, Program::Demo(_))) {
+		return Err(CliError::Invalid("--strict-rom needs an iNES ROM, and can't be used with --blargg".to_string()));
+	}
+	if strict_ram && (blargg || raw || matches!(program, Program::Demo(_))) {
+		return Err(CliError::Invalid("--strict-ram needs an iNES ROM, and can't be used with --blargg".to_string()));
+	}
+	if warn_ram && (raw || matches!(program, Program::Demo(_))) {
+		return Err(CliError::Invalid("--warn-ram is for iNES ROMs: raw binaries and demos have flat memory, without RAM to check".to_string()));
+	}
+	if strict_stack && (blargg || raw || matches!(program, Program::Demo(_))) {
+		return Err(CliError::Invalid("--strict-stack needs an iNES ROM, and can't be used with --blargg".to_string()));
+	}
+
+	if romdb.is_some() && (raw || matches!(program, Program::Demo(_))) {
+		return Err(CliError::Invalid("--romdb fixes the headers of iNES ROMs, it's not for raw binaries or demos".to_string()));
+	}
+	if fds_bios.is_some() && (raw || matches!(program, Program::Demo(_))) {
+		return Err(CliError::Invalid("--fds-bios runs FDS disk images, not raw binaries or demos".to_string()));
+	}
+	if overclock > 0 && (raw || matches!(program, Program::Demo(_))) {
+		return Err(CliError::Invalid("--overclock is for iNES ROMs: raw binaries and demos have no PPU to wait".to_string()));
+	}
+	if expansion_volume.is_some() && (raw || matches!(program, Program::Demo(_))) {
+		return Err(CliError::Invalid("--expansion-volume is for iNES ROMs, not raw binaries or demos".to_string()));
+	}
+	if palette.is_some() && (raw || matches!(program, Program::Demo(_))) {
+		return Err(CliError::Invalid("--palette is for iNES ROMs: raw binaries and demos have no PPU".to_string()));
+	}
+	if mmc3_irq.is_some() && (raw || matches!(program, Program::Demo(_))) {
+		return Err(CliError::Invalid("--mmc3-irq is for iNES ROMs, not raw binaries or demos".to_string()));
+	}
+
+	if hash_after.is_some() && (blargg || !conditions.is_empty() || frames.is_some() || raw || matches!(program, Program::Demo(_))) {
+		return Err(CliError::Invalid("--hash-after needs an iNES ROM, and sets the frames itself (no --frames, --blargg, --pass-* or --fail-*)".to_string()));
+	}
+
+	if wav_out.is_some() && (hash_after.is_some() || debug || bench.is_some() || raw || matches!(program, Program::Demo(_))) {
+		return Err(CliError::Invalid("--wav-out records the APU of an iNES ROM in a headless run (not with --hash-after, --debug or --bench)".to_string()));
+	}
+
+	if profile && (hash_after.is_some() || screenshot_after.is_some() || debug || bench.is_some() || raw || matches!(program, Program::Demo(_))) {
+		return Err(CliError::Invalid("--profile counts the instructions of an iNES ROM in a headless run (not with --hash-after, --screenshot-after, --debug or --bench)".to_string()));
+	}
+
+	if compare.is_some() && (hash_after.is_some() || screenshot_after.is_some() || blargg || debug || bench.is_some() || raw || matches!(program, Program::Demo(_))) {
+		return Err(CliError::Invalid("--compare needs an iNES ROM, in the window or a headless run (not with --hash-after, --screenshot-after, --blargg, --debug or --bench)".to_string()));
+	}
+	if compare.is_none() && (compare_threshold.is_some() || compare_verbose) {
+		return Err(CliError::Invalid("--compare-threshold and --compare-verbose need --compare".to_string()));
+	}
+	let compare_threshold = compare_threshold.unwrap_or(0);
+
+	if screenshot_after.is_some() != screenshot_out.is_some() {
+		return Err(CliError::Invalid("--screenshot-after and --screenshot-out go together".to_string()));
+	}
+	if wav_channels && wav_out.is_none() {
+		return Err(CliError::Invalid("--wav-channels writes the channels next to the --wav-out file, and needs it".to_string()));
+	}
+	if screenshot_after.is_some() && (hash_after.is_some() || wav_out.is_some() || blargg || !conditions.is_empty() || frames.is_some() || debug || bench.is_some() || raw || matches!(program, Program::Demo(_))) {
+		return Err(CliError::Invalid("--screenshot-after needs an iNES ROM, and sets the frames itself (no --frames, --hash-after, --wav-out, --blargg, --pass-* or --fail-*)".to_string()));
+	}
+
+	// Snake needs the keys and the display of easy6502.
+	let machine = machine.unwrap_or(if program == Program::Demo(Demo::Snake) { Machine::Easy6502 } else { Machine::Flat });
+	if machine == Machine::Easy6502 && matches!(program, Program::Rom(_)) && !raw {
+		return Err(CliError::Invalid("--machine easy6502 runs demos and raw binaries (with --raw, --load or --entry), not iNES ROMs".to_string()));
+	}
+	if machine == Machine::Easy6502 && bench.is_some() {
+		return Err(CliError::Invalid("--bench runs on flat memory, it can't be used with --machine easy6502".to_string()));
+	}
+	if seed.is_some() && machine != Machine::Easy6502 {
+		return Err(CliError::Invalid("--seed is for --machine easy6502".to_string()));
+	}
+
+	// There is no one to look at the window of a test.
+	let headless = headless || batch || !conditions.is_empty() || blargg || strict_rom || strict_ram || strict_stack || hash_after.is_some() || wav_out.is_some() || screenshot_after.is_some() || profile;
+
+	if record.is_some() && play.is_some() {
+		return Err(CliError::Invalid("--record and --play can't be used together".to_string()));
+	}
+	if play.is_some() && load_slot.is_some() {
+		return Err(CliError::Invalid("--play starts from the movie's own state, it can't be used with --load-slot".to_string()));
+	}
+	if watch && (raw || matches!(program, Program::Demo(_)) || (headless && !debug) || bench.is_some() || record.is_some() || play.is_some()) {
+		return Err(CliError::Invalid("--watch reloads an iNES ROM in the window or the debugger, without movies".to_string()));
+	}
+	// Headless has no input to record.
+	if record.is_some() && headless {
+		return Err(CliError::Invalid("--record needs the window, it can't be used headless".to_string()));
+	}
+
+	Ok(Options { program, trace, trace_filter, trace_penalties, symbols, headless, debug, bench, frames, raw, load, entry, scale, speed, region, romdb, fds_bios, mmc3_irq, ram_init, warn_ram, cycle_accurate, overclock, expansion_volume, crop_overscan, palette, keymap, zapper, watch, state_dir, load_slot, record, play, rewind_interval, rewind_memory, log_level, log_targets, conditions, cycles, dump, blargg, strict_rom, strict_ram, strict_stack, hash_after, screenshot_after, screenshot_out, wav_out, wav_channels, profile, report, compare, compare_threshold, compare_verbose, machine, seed })
+}
+
+fn set_program(program: &mut Option<Program>, new: Program) -> Result<(), CliError> {
+	if program.is_some() {
+		return Err(CliError::Invalid("Only a single ROM or demo can be run".to_string()));
+	}
+	*program = Some(new);
+	Ok(())
+}
+
+/// Decimal, or hex with 0x or $ prefix.
+fn parse_number(value: &str, name: &str) -> Result<u32, CliError> {
+	let parsed = if let Some(hex) = value.strip_prefix("0x").or_else(|| value.strip_prefix('$')) {
+		u32::from_str_radix(hex, 16)
+	} else {
+		value.parse()
+	};
+	parsed.map_err(|_| CliError::Invalid(format!("{} expects a number, got '{}'", name, value)))
+}
+
+fn parse_address(value: &str, name: &str) -> Result<u16, CliError> {
+	let address = parse_number(value, name)?;
+	u16::try_from(address).map_err(|_| CliError::Invalid(format!("{} {:#X} is not a 16 bit address", name, address)))
+}
+
+/// Seconds (10 or 10s), or frames (600f).
+fn parse_bench_budget(value: &str) -> Result<BenchBudget, CliError> {
+	let invalid = || CliError::Invalid(format!("--bench expects seconds (like 10s) or frames (like 600f), got '{}'", value));
+	let budget = if let Some(frames) = value.strip_suffix('f') {
+		BenchBudget::Frames(frames.parse().map_err(|_| invalid())?)
+	} else {
+		let seconds: f64 = value.strip_suffix('s').unwrap_or(value).parse().map_err(|_| invalid())?;
+		if !(seconds > 0.0 && seconds.is_finite()) {
+			return Err(invalid());
+		}
+		BenchBudget::Seconds(seconds)
+	};
+	Ok(budget)
+}
+
+fn parse_log_level(level: &str) -> Result<LevelFilter, CliError> {
+	level.parse().map_err(|_| CliError::Invalid(format!("Unknown log level '{}'", level)))
+}
+
+/// START-END, inclusive.
+fn parse_range(value: &str, name: &str) -> Result<(u16, u16), CliError> {
+	let (start, end) = value.split_once('-').ok_or_else(|| CliError::Invalid(format!("{} expects START-END, got '{}'", name, value)))?;
+	let (start, end) = (parse_address(start, name)?, parse_address(end, name)?);
+	if start > end {
+		return Err(CliError::Invalid(format!("{} range '{}' ends before it starts", name, value)));
+	}
+	Ok((start, end))
+}
+
+/// ADDR=VALUE
+fn parse_memory_condition(value: &str, name: &str) -> Result<Condition, CliError> {
+	let (addr, expected) = value.split_once('=').ok_or_else(|| CliError::Invalid(format!("{} expects ADDR=VALUE, got '{}'", name, value)))?;
+	let addr = parse_address(addr, name)?;
+	let expected = parse_number(expected, name)?;
+	let value = u8::try_from(expected).map_err(|_| CliError::Invalid(format!("{} {:#X} is not a byte", name, expected)))?;
+	Ok(Condition::MemoryEquals { addr, value })
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+
+	fn parse(args: &str) -> Result<Options, CliError> {
+		parse_args(args.split_whitespace().map(String::from))
+	}
+
+	#[test]
+	fn parse_rom_test() {
+		let options = parse("game.nes --headless --frames 120 --trace trace.log --scale 2 --speed 2.5 --region PAL --crop-overscan --log-level debug").unwrap();
+		assert_eq!(options.program, Program::Rom(PathBuf::from("game.nes")));
+		assert!(options.headless);
+		assert_eq!(options.frames, Some(120));
+		assert_eq!(options.trace, Some(PathBuf::from("trace.log")));
+		assert_eq!(options.scale, 2);
+		assert_eq!(options.speed, 2.5);
+		assert_eq!(options.region, Some(Region::Pal));
+		assert!(options.crop_overscan);
+		assert_eq!(options.log_level, LevelFilter::Debug);
+		assert!(options.log_targets.is_empty());
+		let options = parse("game.nes --log cpu=debug,PPU=warn,error").unwrap();
+		assert_eq!(options.log_targets, vec![("cpu".to_string(), LevelFilter::Debug), ("ppu".to_string(), LevelFilter::Warn)]);
+		assert_eq!(options.log_level, LevelFilter::Error);
+		assert!(parse("game.nes --log gpu=debug").is_err());
+		assert!(parse("game.nes --log cpu=loud").is_err());
+		assert_eq!(options.entry, None);
+		assert!(!options.zapper);
+		assert!(parse("duckhunt.nes --zapper").unwrap().zapper);
+		assert!(parse("game.nes --watch").unwrap().watch);
+		assert!(parse("game.nes --watch --debug").unwrap().watch);
+		assert!(parse("game.nes --watch --headless").is_err());
+		assert!(parse("game.nes --watch --record game.fm2").is_err());
+		assert!(parse("--demo adc --watch").is_err());
+		assert_eq!(parse("game.nes --romdb fixes.csv").unwrap().romdb, Some(PathBuf::from("fixes.csv")));
+		assert!(parse("game.bin --raw game.bin --romdb fixes.csv").is_err());
+		assert_eq!(parse("zelda.fds --fds-bios disksys.rom").unwrap().fds_bios, Some(PathBuf::from("disksys.rom")));
+		assert!(parse("--demo adc --fds-bios disksys.rom").is_err());
+		assert_eq!(parse("game.nes --mmc3-irq OLD").unwrap().mmc3_irq, Some(IrqVariant::Old));
+		assert_eq!(parse("game.nes --overclock 20").unwrap().overclock, 20);
+		assert!(parse("game.nes --overclock 1001").is_err());
+		assert!(parse("--demo adc --overclock 20").is_err());
+		assert_eq!(parse("vrc6.nes --expansion-volume 50").unwrap().expansion_volume, Some(50));
+		assert!(parse("vrc6.nes --expansion-volume 401").is_err());
+		assert!(parse("--demo adc --expansion-volume 50").is_err());
+		assert_eq!(parse("game.nes --palette fceux").unwrap().palette.as_deref(), Some("fceux"));
+		assert_eq!(parse("game.nes --palette my.pal --headless --frames 60").unwrap().palette.as_deref(), Some("my.pal"));
+		assert!(parse("--demo adc --palette fceux").is_err());
+		assert!(parse("game.nes --palette").is_err());
+		assert!(parse("game.nes --mmc3-irq nec").is_err());
+		assert!(parse("--demo snake --mmc3-irq new").is_err());
+		assert_eq!(options.ram_init, RamInitPattern::AllZero);
+		assert_eq!(parse("game.nes --ram-init random:42").unwrap().ram_init, RamInitPattern::Random { seed: 42 });
+		assert!(parse("game.nes --ram-init random").is_err());
+		assert!(parse("game.nes --cycle-accurate").unwrap().cycle_accurate);
+		assert!(!options.cycle_accurate);
+	}
+
+	#[test]
+	fn parse_demo_test() {
+		let options = parse("--demo ToLower").unwrap();
+		assert_eq!(options.program, Program::Demo(Demo::ToLower));
+		assert!(!options.headless);
+		assert!(!options.debug);
+		assert_eq!(options.scale, 3);
+		assert_eq!(options.speed, 1.0);
+		assert_eq!(options.region, None);
+		assert_eq!(options.romdb, None);
+		assert_eq!(options.mmc3_irq, None);
+		assert_eq!(options.overclock, 0);
+		assert_eq!(options.expansion_volume, None);
+		assert_eq!(options.palette, None);
+		assert_eq!(options.state_dir, PathBuf::from("states"));
+		assert_eq!(options.rewind_interval, 3);
+		assert_eq!(options.rewind_memory, 64 * 1024 * 1024);
+		assert_eq!(options.log_level, LevelFilter::Info);
+
+		assert!(parse("--demo tolower --debug").unwrap().debug);
+		assert_eq!(parse("raw.bin --entry 0x8000").unwrap().entry, Some(0x8000));
+		assert_eq!(parse("raw.bin --entry $C000").unwrap().entry, Some(0xC000));
+	}
+
+	#[test]
+	fn parse_raw_test() {
+		let options = parse("--raw test.bin --load 0x0600 --entry $0610 --pass-pc $0700").unwrap();
+		assert_eq!(options.program, Program::Rom(PathBuf::from("test.bin")));
+		assert!(options.raw && options.headless);
+		assert_eq!((options.load, options.entry), (Some(0x0600), Some(0x0610)));
+
+		assert!(parse("test.bin --load 0x0600").unwrap().raw);
+		assert!(parse("test.bin --entry 0x0600").unwrap().raw);
+		// Without them, main.rs looks at the file.
+		assert!(!parse("test.bin").unwrap().raw);
+
+		assert!(parse("--demo adc --load 0x0600").is_err());
+		assert!(parse("--raw a.bin b.bin").is_err());
+		assert!(parse("--raw test.bin --blargg").is_err());
+		assert_eq!(parse("--raw snake.bin --machine easy6502").unwrap().machine, Machine::Easy6502);
+	}
+
+	#[test]
+	fn parse_harness_test() {
+		let options = parse("test.nes --pass-mem $6000=0 --fail-pc 0xE000 --cycles 100000 --dump $6000-$600F").unwrap();
+		assert!(options.headless);
+		assert_eq!(options.conditions, vec![
+			(Condition::MemoryEquals { addr: 0x6000, value: 0 }, Verdict::Pass),
+			(Condition::PcEquals(0xE000), Verdict::Fail),
+		]);
+		assert_eq!(options.cycles, Some(100_000));
+		assert_eq!(options.dump, Some((0x6000, 0x600F)));
+
+		assert!(parse("test.nes --pass-mem $6000").is_err());
+		assert!(parse("test.nes --pass-mem $6000=0x100").is_err());
+		assert!(parse("test.nes --dump $6010-$6000").is_err());
+
+		let options = parse("test.nes --strict-rom").unwrap();
+		assert!(options.strict_rom && options.headless);
+		assert!(!parse("test.nes").unwrap().strict_rom);
+		assert!(parse("--demo adc --strict-rom").is_err());
+		assert!(parse("test.nes --blargg --strict-rom").is_err());
+
+		let options = parse("test.nes --strict-stack").unwrap();
+		assert!(options.strict_stack && options.headless && !options.strict_rom);
+		assert!(parse("--demo tolower --strict-stack").is_err());
+		assert!(parse("test.nes --blargg --strict-stack").is_err());
+
+		let options = parse("test.nes --strict-ram").unwrap();
+		assert!(options.strict_ram && options.headless && !options.warn_ram);
+		assert!(parse("--demo tolower --strict-ram").is_err());
+		assert!(parse("test.nes --blargg --strict-ram").is_err());
+		let options = parse("game.nes --warn-ram").unwrap();
+		assert!(options.warn_ram && !options.headless);
+		assert!(parse("--demo tolower --warn-ram").is_err());
+
+		let options = parse("game.nes --hash-after 120").unwrap();
+		assert_eq!(options.hash_after, Some(120));
+		assert!(options.headless);
+		assert_eq!(parse("game.nes").unwrap().hash_after, None);
+		assert!(parse("game.nes --hash-after 120 --frames 60").is_err());
+		assert!(parse("--demo adc --hash-after 10").is_err());
+
+		let options = parse("game.nes --wav-out game.wav --frames 600").unwrap();
+		assert_eq!(options.wav_out, Some(PathBuf::from("game.wav")));
+		assert!(options.headless);
+		assert!(parse("game.nes --wav-out game.wav --hash-after 10").is_err());
+		assert!(parse("--demo adc --wav-out adc.wav").is_err());
+		assert!(!options.wav_channels);
+		assert!(parse("game.nes --wav-out game.wav --wav-channels").unwrap().wav_channels);
+		assert!(parse("game.nes --wav-channels --frames 600").is_err());
+
+		let options = parse("game.nes --profile --frames 600").unwrap();
+		assert!(options.profile && options.headless);
+		assert!(parse("game.nes --profile --bench 10").is_err());
+		assert!(parse("--demo adc --profile").is_err());
+
+		let options = parse("--batch roms --frames 600 --report out.csv --region PAL").unwrap();
+		assert_eq!(options.program, Program::Batch(PathBuf::from("roms")));
+		assert_eq!(options.report, Some(PathBuf::from("out.csv")));
+		assert!(options.headless);
+		assert!(parse("--batch roms game.nes").is_err());
+		assert!(parse("--batch roms --debug").is_err());
+		assert!(parse("--batch roms --palette fceux").is_err());
+		assert!(parse("game.nes --report out.csv").is_err());
+
+		let options = parse("game.nes --compare golden.png --headless --compare-threshold 10 --compare-verbose").unwrap();
+		assert_eq!(options.compare, Some(PathBuf::from("golden.png")));
+		assert_eq!(options.compare_threshold, 10);
+		assert!(options.compare_verbose && options.headless);
+		assert_eq!(parse("game.nes --compare golden.png").unwrap().compare_threshold, 0);
+		assert!(parse("game.nes --compare-threshold 10").is_err());
+		assert!(parse("game.nes --compare golden.png --hash-after 10").is_err());
+		assert!(parse("--demo adc --compare golden.png").is_err());
+
+		let options = parse("game.nes --screenshot-after 30 --screenshot-out game.png --crop-overscan").unwrap();
+		assert_eq!(options.screenshot_after, Some(30));
+		assert_eq!(options.screenshot_out, Some(PathBuf::from("game.png")));
+		assert!(options.headless && options.crop_overscan);
+		assert!(parse("game.nes --screenshot-after 30").is_err());
+		assert!(parse("game.nes --screenshot-out game.png").is_err());
+		assert!(parse("game.nes --screenshot-after 30 --screenshot-out game.png --frames 60").is_err());
+		assert!(parse("--demo adc --screenshot-after 30 --screenshot-out adc.png").is_err());
+	}
+
+	#[test]
+	fn parse_blargg_test() {
+		let options = parse("cpu_timing_test.nes --blargg --frames 600").unwrap();
+		assert!(options.blargg);
+		assert!(options.headless);
+		assert_eq!(options.frames, Some(600));
+		assert!(!parse("game.nes").unwrap().blargg);
+
+		assert!(parse("test.nes --blargg --pass-mem $6000=0").is_err());
+		assert!(parse("test.bin --blargg --entry 0x8000").is_err());
+		assert!(parse("--demo adc --blargg").is_err());
+	}
+
+	#[test]
+	fn parse_machine_test() {
+		assert_eq!(parse("--demo adc").unwrap().machine, Machine::Flat);
+		// Snake only runs on easy6502.
+		let options = parse("--demo snake --seed 7").unwrap();
+		assert_eq!((options.machine, options.seed), (Machine::Easy6502, Some(7)));
+		assert_eq!(parse("--demo helloworld --machine EASY6502").unwrap().machine, Machine::Easy6502);
+		assert_eq!(parse("snake.bin --entry 0x0600 --machine easy6502").unwrap().machine, Machine::Easy6502);
+
+		assert!(parse("game.nes --machine easy6502").is_err());
+		assert!(parse("--demo snake --bench 10").is_err());
+		assert!(parse("--demo adc --seed 7").is_err());
+		assert!(parse("--demo snake --seed -1").is_err());
+		assert!(parse("--demo adc --machine c64").is_err());
+	}
+
+	#[test]
+	fn parse_bench_test() {
+		assert_eq!(parse("game.nes --bench 10").unwrap().bench, Some(BenchBudget::Seconds(10.0)));
+		assert_eq!(parse("game.nes --bench 2.5s").unwrap().bench, Some(BenchBudget::Seconds(2.5)));
+		assert_eq!(parse("--demo adc --bench 600f").unwrap().bench, Some(BenchBudget::Frames(600)));
+		assert_eq!(parse("game.nes").unwrap().bench, None);
+
+		assert!(parse("game.nes --bench 0").is_err());
+		assert!(parse("game.nes --bench tenf").is_err());
+	}
+
+	#[test]
+	fn parse_trace_test() {
+		let options = parse("game.nes --trace-file trace.log --trace-pc $C000-$C0FF --trace-from 0xC004 --trace-last 1000").unwrap();
+		assert_eq!(options.trace, Some(PathBuf::from("trace.log")));
+		assert_eq!(options.trace_filter, TraceFilter { pc_range: Some((0xC000, 0xC0FF)), start_at: Some(0xC004), last: Some(1000) });
+
+		assert!(parse("game.nes --trace-pc $C000-$C0FF").is_err());
+		assert!(parse("game.nes --trace-file trace.log --trace-penalties").unwrap().trace_penalties);
+		assert!(parse("game.nes --trace-penalties").is_err());
+		assert!(parse("game.nes --trace-file trace.log --trace-pc $C0FF-$C000").is_err());
+
+		let options = parse("game.nes --debug --symbols game.0.nl --symbols game.ram.nl").unwrap();
+		assert_eq!(options.symbols, [PathBuf::from("game.0.nl"), PathBuf::from("game.ram.nl")]);
+		assert!(parse("game.nes --symbols game.nl").is_err());
+	}
+
+	#[test]
+	fn parse_movie_test() {
+		let options = parse("game.nes --record run.fm2 --load-slot 2").unwrap();
+		assert_eq!(options.record, Some(PathBuf::from("run.fm2")));
+		assert_eq!(options.load_slot, Some(2));
+		assert_eq!(options.play, None);
+
+		let options = parse("game.nes --play run.fm2 --headless").unwrap();
+		assert_eq!(options.play, Some(PathBuf::from("run.fm2")));
+		assert_eq!(options.record, None);
+	}
+
+	#[test]
+	fn parse_error_test() {
+		assert_eq!(parse("--help"), Err(CliError::Help));
+		assert!(parse("").is_err());
+		assert!(parse("--demo pong").is_err());
+		assert!(parse("game.nes --frames").is_err());
+		assert!(parse("game.nes --frames ten").is_err());
+		assert!(parse("game.nes --entry 0x10000").is_err());
+		assert!(parse("game.nes --scale 0").is_err());
+		assert!(parse("game.nes --speed 0").is_err());
+		assert!(parse("game.nes --speed fast").is_err());
+		assert!(parse("game.nes --region dendy").is_err());
+		assert!(parse("game.nes --turbo").is_err());
+		assert!(parse("game.nes other.nes").is_err());
+		assert!(parse("game.nes --demo adc").is_err());
+		assert!(parse("game.nes --load-slot 10").is_err());
+		assert!(parse("game.nes --record a.fm2 --play b.fm2").is_err());
+		assert!(parse("game.nes --play a.fm2 --load-slot 1").is_err());
+		assert!(parse("game.nes --record a.fm2 --headless").is_err());
+	}
+}
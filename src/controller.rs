@@ -0,0 +1,231 @@
+// Standard controller: https://www.nesdev.org/wiki/Standard_controller
+//
+// Writing 1 to $4016 (strobe) makes the controller keep reloading its shift register with the buttons state.
+// Writing 0 stops reloading, and then each read of $4016 returns the next button, in this order:
+//
+// | Read | Button |
+// |---|---|
+// | 1 | A |
+// | 2 | B |
+// | 3 | Select |
+// | 4 | Start |
+// | 5 | Up |
+// | 6 | Down |
+// | 7 | Left |
+// | 8 | Right |
+//
+// After 8 reads, official controllers return 1.
+//
+// Controller 2 is read at $4017 the same way. There is a single strobe for both, at $4016 (writes to $4017 are
+// the APU frame counter).
+
+use crate::save_state::{SaveState, StateReader, StateWriter};
+
+/// Buttons, in the order the controller reports them.
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub enum Button {
+	A,
+	B,
+	Select,
+	Start,
+	Up,
+	Down,
+	Left,
+	Right,
+}
+
+impl Button {
+	pub const ALL: [Button; 8] = [Button::A, Button::B, Button::Select, Button::Start, Button::Up, Button::Down, Button::Left, Button::Right];
+
+	fn mask(self) -> u8 {
+		1 << self as u8
+	}
+}
+
+/// Which buttons are pressed. Bit 0 is A, bit 7 is Right (the report order).
+#[derive(Default, Clone, Copy, PartialEq, Eq, Debug)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct ButtonState(pub u8);
+
+impl ButtonState {
+	pub fn set(&mut self, button: Button, pressed: bool) {
+		if pressed {
+			self.0 |= button.mask();
+		} else {
+			self.0 &= !button.mask();
+		}
+	}
+
+	pub fn pressed(&self, button: Button) -> bool {
+		self.0 & button.mask() != 0
+	}
+
+	/// A real d-pad can't press opposing directions together, and some games break if they see it
+	/// (Zelda 2, for example). So pressing both Up and Down (or Left and Right) releases both of them.
+	pub fn without_opposing_directions(mut self) -> Self {
+		for (first, second) in [(Button::Up, Button::Down), (Button::Left, Button::Right)] {
+			if self.pressed(first) && self.pressed(second) {
+				self.set(first, false);
+				self.set(second, false);
+			}
+		}
+		self
+	}
+}
+
+#[derive(Default, Clone)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct Joypad {
+	buttons: ButtonState,
+	shift_register: u8,
+	/// Reads after the 8th return 1.
+	reads: u8,
+	strobe: bool,
+}
+
+impl Joypad {
+	pub fn new() -> Self {
+		Self::default()
+	}
+
+	/// The frontend sets the buttons state. The game sees it only when it strobes the controller.
+	pub fn set_buttons(&mut self, buttons: ButtonState) {
+		self.buttons = buttons;
+		if self.strobe {
+			self.reload();
+		}
+	}
+
+	/// Press or release a single button, for frontends that get them one at a time (key down and key up events). Like
+	/// `set_buttons`.
+	pub fn set_button(&mut self, button: Button, pressed: bool) {
+		let mut buttons = self.buttons;
+		buttons.set(button, pressed);
+		self.set_buttons(buttons);
+	}
+
+	pub fn buttons(&self) -> ButtonState {
+		self.buttons
+	}
+
+	/// Write to $4016. Only bit 0 (strobe) matters.
+	pub fn write(&mut self, data: u8) {
+		self.strobe = data & 1 == 1;
+		if self.strobe {
+			self.reload();
+		}
+	}
+
+	/// Read the next button. Only bit 0 is returned; the caller should fill the rest (open bus).
+	pub fn read(&mut self) -> u8 {
+		if self.strobe {
+			// While strobe is high, the controller keeps reloading, so the game always gets A.
+			return self.buttons.0 & 1;
+		}
+
+		if self.reads >= 8 {
+			return 1;
+		}
+		let bit = self.shift_register & 1;
+		self.shift_register >>= 1;
+		self.reads += 1;
+		bit
+	}
+
+	fn reload(&mut self) {
+		self.shift_register = self.buttons.0;
+		self.reads = 0;
+	}
+}
+
+impl SaveState for Joypad {
+	fn save_state(&self, out: &mut StateWriter) {
+		out.u8(self.buttons.0);
+		out.u8(self.shift_register);
+		out.u8(self.reads);
+		out.bool(self.strobe);
+	}
+
+	fn load_state(&mut self, input: &mut StateReader) -> Result<(), String> {
+		self.buttons = ButtonState(input.u8()?);
+		self.shift_register = input.u8()?;
+		self.reads = input.u8()?;
+		self.strobe = input.bool()?;
+		Ok(())
+	}
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+
+	#[test]
+	fn joypad_read_test() {
+		let mut joypad = Joypad::new();
+		let mut buttons = ButtonState::default();
+		buttons.set(Button::A, true);
+		buttons.set(Button::Start, true);
+		buttons.set(Button::Right, true);
+		joypad.set_buttons(buttons);
+
+		joypad.write(1);
+		joypad.write(0);
+		let bits: Vec<u8> = (0..10).map(|_| joypad.read()).collect();
+		assert_eq!(bits, vec![1, 0, 0, 1, 0, 0, 0, 1, 1, 1]);
+
+		// Changing the buttons after the strobe is not seen until the next strobe.
+		joypad.write(1);
+		joypad.write(0);
+		joypad.set_buttons(ButtonState::default());
+		assert_eq!(joypad.read(), 1);
+	}
+
+	#[test]
+	fn joypad_strobe_high_test() {
+		let mut joypad = Joypad::new();
+		joypad.write(1);
+
+		let mut buttons = ButtonState::default();
+		buttons.set(Button::A, true);
+		joypad.set_buttons(buttons);
+		assert_eq!(joypad.read(), 1);
+		assert_eq!(joypad.read(), 1);
+
+		joypad.set_buttons(ButtonState::default());
+		assert_eq!(joypad.read(), 0);
+	}
+
+	#[test]
+	fn set_button_test() {
+		let mut joypad = Joypad::new();
+		joypad.set_button(Button::B, true);
+		joypad.set_button(Button::Up, true);
+		joypad.set_button(Button::Up, false);
+		assert_eq!(joypad.buttons(), ButtonState(0b10));
+
+		joypad.write(1);
+		joypad.write(0);
+		let bits: Vec<u8> = (0..3).map(|_| joypad.read()).collect();
+		assert_eq!(bits, vec![0, 1, 0]);
+
+		// While strobe is high, the game sees A change right away.
+		joypad.write(1);
+		joypad.set_button(Button::A, true);
+		assert_eq!(joypad.read(), 1);
+	}
+
+	#[test]
+	fn opposing_directions_test() {
+		let mut buttons = ButtonState::default();
+		buttons.set(Button::Up, true);
+		buttons.set(Button::Down, true);
+		buttons.set(Button::Left, true);
+		buttons.set(Button::A, true);
+
+		let filtered = buttons.without_opposing_directions();
+		assert!(!filtered.pressed(Button::Up));
+		assert!(!filtered.pressed(Button::Down));
+		assert!(filtered.pressed(Button::Left));
+		assert!(filtered.pressed(Button::A));
+	}
+}
@@ -1,391 +1,735 @@
-use core::panic;
+use core::fmt;
 use log::{debug, error, warn};
 
-use crate::cpu::registers::{Registers, ProcessorStatusRegisterBits};
+use crate::cpu::registers::{Register, Registers};
+use crate::cpu::status::{Flag, StatusFlags};
+use crate::cpu::stuck::{StuckDetection, StuckDetector};
 use crate::cpu::decoder::{OopsCycle, Instructions, AddressingMode, decode_opcode};
+use crate::cpu::effects::{AccessLog, BusAccess, StepEffects};
 use crate::bus::Bus;
+use crate::log_target::CPU;
+#[cfg(feature = "std")]
+use crate::save_state::{SaveState, StateReader, StateWriter};
+
+const NMI_VECTOR: u16 = 0xFFFA;
+const RESET_VECTOR: u16 = 0xFFFC;
+const IRQ_VECTOR: u16 = 0xFFFE;
+
+/// Snapshot of the CPU registers, for tools (test harness, debugger, trace).
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct CpuState {
+	pub pc: u16,
+	pub a: u8,
+	pub x: u8,
+	pub y: u8,
+	pub p: u8,
+	pub s: u8,
+	pub cycles: u64,
+}
+
+impl CpuState {
+	/// P, as flags: prints like `nv-BdIZc`.
+	pub fn flags(&self) -> StatusFlags {
+		StatusFlags::from_byte(self.p)
+	}
+
+	/// Same registers, ignoring the cycles.
+	pub fn same_registers(&self, other: &CpuState) -> bool {
+		CpuState { cycles: 0, ..*self } == CpuState { cycles: 0, ..*other }
+	}
+}
 
-use hex::FromHex;
+impl fmt::Display for CpuState {
+	fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+		write!(f, "{:04X}  A:{:02X} X:{:02X} Y:{:02X} P:{:02X} {} SP:{:02X} CYC:{}", self.pc, self.a, self.x, self.y, self.p, self.flags(), self.s, self.cycles)
+	}
+}
 
-pub struct CPU {
+/// Why the CPU couldn't execute the instruction at `pc`. The CPU is left as it was before the instruction.
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub enum CpuError {
+	/// An opcode the 6502 doesn't have, like $02.
+	IllegalOpcode { pc: u16, opcode: u8 },
+	/// A legal opcode, that the emulator doesn't execute yet.
+	Unimplemented { pc: u16, opcode: u8 },
+}
+
+impl fmt::Display for CpuError {
+	fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+		match *self {
+			CpuError::IllegalOpcode { pc, opcode } => write!(f, "Illegal opcode {:02X} at {:04X}", opcode, pc),
+			CpuError::Unimplemented { pc, opcode } => write!(f, "Opcode {:02X} at {:04X} is not implemented, yet", opcode, pc),
+		}
+	}
+}
+
+#[cfg(feature = "std")]
+impl std::error::Error for CpuError {}
+
+/// Why `CPU::run_until_jam` or `CPU::run_until_stuck` stopped.
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub enum RunEnd {
+	/// An instruction didn't change the registers, like `JMP *`, so the CPU will run it forever. Test programs end
+	/// like this, when they pass and when they fail, and the PC tells which.
+	/// NOTE: A loop that waits for an interrupt looks the same.
+	Jammed,
+	/// The CPU ran `max_cycles` cycles (or a little more, to finish the last instruction).
+	BudgetExhausted,
+	/// `run_until_stuck` found the CPU in a loop that never ends, see `stuck.rs`. `pc` is in the loop.
+	StuckLoop { pc: u16 },
+}
+
+/// What went wrong with the stack, see `CPU::set_strict_stack`. Programs don't do it on purpose: it's usually a JSR
+/// without its RTS (or the other way around), or a loop that pushes more than it pulls.
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub enum StackFault {
+	/// A push with SP at $00, so SP wrapped around to $FF, and the next push overwrites the bottom of the stack.
+	Overflow,
+	/// A pull with SP at $FF: there was nothing on the stack.
+	Underflow,
+}
+
+/// The bytes on the stack, for a stack pointer `s`: from $01FF down to the last one pushed, so in the order they were
+/// pushed. Read with `peek`, for tools.
+#[cfg(feature = "std")]
+pub fn stack_contents(s: u8, peek: impl Fn(u16) -> u8) -> Vec<u8> {
+	(s as u16 + 1..=0xFF).rev().map(|offset| peek(0x100 + offset)).collect()
+}
+
+/// Executes an instruction, with its addressing mode.
+type Handler<B> = fn(&mut CPU<B>, AddressingMode);
+
+/// An opcode, decoded at compile time, with the function that executes it. `step` looks it up in `CPU::DISPATCH`, so
+/// there is no decoding and no match on the instruction in the hot path. Tools decode with `decode_opcode`.
+struct Dispatch<B: Bus> {
+	instr: Instructions,		// Only for the log.
+	handler: Handler<B>,
+	addrmode: AddressingMode,
+	bytes: u8,
+	cycles: u8,
+	oops_cycle: OopsCycle,
+	changes_pc: bool,			// The instruction sets the PC by itself, see `changes_pc`.
+}
+
+// Not derived, because derive would want B: Copy. The fields are all Copy.
+impl<B: Bus> Clone for Dispatch<B> {
+	fn clone(&self) -> Self {
+		*self
+	}
+}
+
+impl<B: Bus> Copy for Dispatch<B> {}
+
+/// The 6502 core. It owns the bus, and doesn't allocate, so it also runs without std (see the `std` feature).
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct CPU<B: Bus> {
 	registers: Registers,
-	bus: Box<Bus>,
-	cycles: u64
+	bus: B,
+	cycles: u64,
+	page_crossed: bool,		// Set by the current instruction if indexing/branching crossed a page. Used for oops cycles.
+	branch_taken: bool,		// Set by the current instruction if it was a branch, and the branch was taken.
+	last_opcode: Option<u8>,	// The opcode of the last step, None if it was an interrupt.
+	penalty_cycles: u8,		// The oops cycles the last instruction took, see `penalty_cycles`.
+	memory_written: bool,	// Set by the current instruction (or interrupt) if it wrote memory. For the stuck loop detection.
+	nmi_requested: bool,	// The NMI came before the last cycle of the instruction: it's taken before the next one.
+	#[cfg_attr(feature = "serde", serde(skip))]
+	access_log: AccessLog,	// The reads and writes of the current step, when `step_with_effects` runs it.
+	#[cfg_attr(feature = "serde", serde(skip))]
+	cycle_accurate: bool,	// Do the dummy reads of the real CPU, see `set_cycle_accurate`.
+	#[cfg_attr(feature = "serde", serde(skip))]
+	strict_stack: bool,		// Check the stack pointer on every push and pull, see `set_strict_stack`.
+	#[cfg_attr(feature = "serde", serde(skip))]
+	stack_fault: Option<StackFault>,	// Set by the current instruction (or interrupt), when `strict_stack` is on.
 }
 
-impl CPU {
-	pub fn new(bus: Box<Bus>) -> Self {
-		let mut registers: Registers = Registers::default();
-		registers.S = 0xFF; //TODO: Remove. The original NES does not initialize the stack register; Its random at startup. But I need this to debug my programs for now.
+impl<B: Bus> CPU<B> {
+	/// The decoded opcodes, by opcode. None for illegal opcodes, and for instructions that are not implemented yet.
+	const DISPATCH: [Option<Dispatch<B>>; 256] = Self::dispatch_table();
+
+	pub fn new(bus: B) -> Self {
+		let registers: Registers = Registers {
+			S: 0xFF, //TODO: Remove. The original NES does not initialize the stack register; Its random at startup. But I need this to debug my programs for now.
+			..Default::default()
+		};
 		CPU {
 			registers,
 			bus,
-			cycles: 0
+			cycles: 0,
+			page_crossed: false,
+			branch_taken: false,
+			last_opcode: None,
+			penalty_cycles: 0,
+			memory_written: false,
+			nmi_requested: false,
+			access_log: AccessLog::default(),
+			cycle_accurate: false,
+			strict_stack: false,
+			stack_fault: None,
 		}
 	}
 
-	/// A single clock cycle is executed here.
-	/// Original NES CPU needs multiple cycles to execute instruction.
-	/// Emulation does not do that; Its much simpler to do everything at once, and emulate the cycles.
-	pub fn clock_tick(&mut self) {
-		debug!("Tick, cycle: {}", self.cycles);
-		debug!("{}", self.registers);
+	pub fn bus(&self) -> &B {
+		&self.bus
+	}
 
-		// Read next instruction.
-		let opcode = self.bus.rom.read(self.registers.PC); // Read at address of Program Counter (duh!)
-		let instruction = decode_opcode(opcode);
+	pub fn bus_mut(&mut self) -> &mut B {
+		&mut self.bus
+	}
 
-		let instr = instruction.0;
-		let addrmode = instruction.1;
-		let bytes = instruction.2;
-		let cycles = instruction.3;
-		let oops_cycle = instruction.4;
+	/// Amount of cycles executed since the CPU was created.
+	pub fn cycles(&self) -> u64 {
+		self.cycles
+	}
 
-		debug!("{:#X}: {:?}\t{:?}\tBytes: {}, Cycles: {}, Oops cycle: {}", opcode, instr, addrmode, bytes, cycles, oops_cycle);
+	pub fn registers(&self) -> &Registers {
+		&self.registers
+	}
 
-		//The main brains of the CPU. Execute instruction.
-		match instr {
-			Instructions::LDX => {
-				// Load Index X with Memory
-				// M -> X
-				let fetched_memory = self.fetch_memory(&addrmode);
-				self.registers.X = fetched_memory;
-
-				self.registers.P.modify_n(fetched_memory);
-				self.registers.P.modify_z(fetched_memory);
-			}
-			Instructions::LDY => {
-				// Load Index Y with Memory
-				// M -> Y
-				let fetched_memory = self.fetch_memory(&addrmode);
-				self.registers.Y = fetched_memory;
-
-				self.registers.P.modify_n(fetched_memory);
-				self.registers.P.modify_z(fetched_memory);
-			}
-			Instructions::LDA => {
-				// Load Accumulator with Memory
-				// M -> A
-				let fetched_memory = self.fetch_memory(&addrmode);
-				self.registers.A = fetched_memory;
-
-				self.registers.P.modify_n(fetched_memory);
-				self.registers.P.modify_z(fetched_memory);
-			}
-			Instructions::PHA => {
-				// Push Accumulator on Stack
-				// push A
-				self.push_stack(self.registers.A);
-			}
-			Instructions::NOP => {
-				// No Operation
-			}
-			Instructions::PLA => {
-				// Pull Accumulator from Stack
-				// pull A
-				let fetched_memory = self.pop_stack();
-				self.registers.A = fetched_memory;
-
-				self.registers.P.modify_n(fetched_memory);
-				self.registers.P.modify_z(fetched_memory);
-			}
-			Instructions::SEC => {
-				// Set Carry Flag
-				self.registers.P.set(ProcessorStatusRegisterBits::CARRY, true);
-			}
-			Instructions::CLC => {
-				// Clear Carry Flag
-				self.registers.P.set(ProcessorStatusRegisterBits::CARRY, false);
-			}
-			Instructions::SED => {
-				// Set Decimal Flag
-				self.registers.P.set(ProcessorStatusRegisterBits::DECIMAL, true);
-			}
-			Instructions::CLD => {
-				// Clear Decimal Mode
-				self.registers.P.set(ProcessorStatusRegisterBits::DECIMAL, false);
-			}
-			Instructions::SEI => {
-				// Set Interrupt Disable Status
-				self.registers.P.set(ProcessorStatusRegisterBits::INTERRUPT_DISABLE, true);
-			}
-			Instructions::CLI => {
-				// Clear Interrupt Disable Bit
-				self.registers.P.set(ProcessorStatusRegisterBits::INTERRUPT_DISABLE, false);
-			}
-			Instructions::CLV => {
-				// Clear Overflow Flag
-				self.registers.P.set(ProcessorStatusRegisterBits::OVERFLOW, false);
-			}
-			Instructions::ADC => {
-				// Add Memory to Accumulator with Carry
-				// A + M + C -> A, C
-				// NOTE: This is the first instruction that actually does 'complex' arithmetic
-				// After reading a lot of forums, its actually the most complex thing to emulate, I must understand this
+	pub fn state(&self) -> CpuState {
+		CpuState {
+			pc: self.registers.PC,
+			a: self.registers.A,
+			x: self.registers.X,
+			y: self.registers.Y,
+			p: self.registers.P.to_byte(),
+			s: self.registers.S,
+			cycles: self.cycles,
+		}
+	}
+
+	/// Set the registers and the cycle counter, for tools (test harness, debugger). B and the unused bit of P are
+	/// ignored, like when P is pulled from the stack.
+	pub fn set_state(&mut self, state: &CpuState) {
+		self.registers.PC = state.pc;
+		self.registers.A = state.a;
+		self.registers.X = state.x;
+		self.registers.Y = state.y;
+		self.registers.P = StatusFlags::from_stack(state.p);
+		self.registers.S = state.s;
+		self.cycles = state.cycles;
+	}
+
+	/// Set a single register, for tools (the debugger's `set`). The 8 bit registers take the low byte of `value`, and
+	/// P ignores B, like `set_state`. The next instruction runs with it.
+	pub fn set_register(&mut self, register: Register, value: u16) {
+		match register {
+			Register::A => self.registers.A = value as u8,
+			Register::X => self.registers.X = value as u8,
+			Register::Y => self.registers.Y = value as u8,
+			Register::SP => self.registers.S = value as u8,
+			Register::PC => self.registers.PC = value,
+			Register::P => self.registers.P = StatusFlags::from_stack(value as u8),
+		}
+	}
+
+	/// Set or clear a single flag of P, for tools. Like `set_register`, B stays clear.
+	pub fn set_flag(&mut self, flag: Flag, value: bool) {
+		if flag != Flag::BREAK && flag != Flag::UNUSED {
+			self.registers.P.set(flag, value);
+		}
+	}
 
-				let fetched_memory = self.fetch_memory(&addrmode);
+	/// Disable interrupts, and jump to the address stored in the reset vector ($FFFC, $FFFD). Takes 7 cycles, like an
+	/// interrupt, but nothing is pushed.
+	///
+	/// Unlike the real 6502, S is left as it is instead of being decremented by 3: the test programs expect S to be
+	/// $FF after a reset.
+	pub fn reset(&mut self) {
+		self.nmi_requested = false;
+		self.registers.P.set(Flag::INTERRUPT_DISABLE, true);
+		let lsb = self.read(RESET_VECTOR) as u16;
+		let msb = self.read(RESET_VECTOR + 1) as u16;
+		self.registers.PC = (msb << 8) | lsb;
+		self.bus.tick(7);
+		self.cycles += 7;
+	}
 
-				let a = self.registers.A;
-				let m = fetched_memory;
-				let carry: u8 = self.registers.P.get(ProcessorStatusRegisterBits::CARRY) as u8;
+	/// Take an NMI now: push PC and P, disable interrupts, and jump to the address in $FFFA. Takes 7 cycles.
+	/// `step` calls it by itself when the bus signals an NMI (see `Bus::take_nmi`).
+	pub fn nmi(&mut self) -> u8 {
+		debug!(target: CPU, "NMI");
+		self.nmi_requested = false;
+		let pc = self.registers.PC;
+		self.interrupt(pc, NMI_VECTOR, false);
+		self.bus.tick(7);
+		self.cycles += 7;
+		7
+	}
 
-				// Carry flag: Only for unsigned. If result is > 255, carry is set.
-				// Overflow flag: Only if (Positive+Positive=Negative) or (Negative+Negative=Positive)
+	/// Take an IRQ, like `nmi` but through $FFFE, unless the I flag masks it. Returns if it was taken.
+	pub fn irq(&mut self) -> bool {
+		if self.registers.P.get(Flag::INTERRUPT_DISABLE) {
+			return false;
+		}
+		debug!(target: CPU, "IRQ");
+		let pc = self.registers.PC;
+		self.interrupt(pc, IRQ_VECTOR, false);
+		self.bus.tick(7);
+		self.cycles += 7;
+		true
+	}
 
-				// Perform regular unsigned addition, allowing arithmetic overflow.
-				let first_addition = a.overflowing_add(m);
-				let second_addition = first_addition.0.overflowing_add(carry);
-				let mut result = second_addition.0;
+	/// Like `step`, but panics if the CPU can't execute the instruction. For programs that are known to be fine.
+	pub fn clock_tick(&mut self) -> u8 {
+		match self.step() {
+			Ok(cycles) => cycles,
+			Err(err) => panic!("{}, registers: {}", err, self.state()),
+		}
+	}
 
-				// Set A register.
+	/// Execute up to `instructions` instructions, and stop at the first error. Returns the amount of cycles they took.
+	/// It always returns, whatever the program does (a JMP to itself, for example), so any program can run with it.
+	pub fn run(&mut self, instructions: u32) -> Result<u64, CpuError> {
+		let mut cycles = 0;
+		for _ in 0..instructions {
+			cycles += self.step()? as u64;
+		}
+		Ok(cycles)
+	}
 
-				// Check decimal mode, check if CPU is in binary/decimal coded mode
-				// TODO: I read that NES doesn't use this mode. Maybe remove it so I don't have any problems?
-				if self.registers.P.get(ProcessorStatusRegisterBits::DECIMAL) {
-					result = self.decimal_mode(result);
-				}
-				self.registers.A = result;
-
-				// Set carry accordingly.
-				let new_carry = first_addition.1 || second_addition.1;
-
-				// Set overflow accordingly.
-				let is_a_negative = (a >> 7) == 1;
-				let is_m_negative = (m >> 7) == 1;
-				let is_result_negative = (result >> 7) == 1;
-				let new_overflow = 
-					(is_a_negative 				&& is_m_negative 			&& is_result_negative == false 	) ||
-					(is_a_negative == false 	&& is_m_negative == false 	&& is_result_negative 			);
-				
-				self.registers.P.modify_n(self.registers.A);
-				self.registers.P.modify_z(self.registers.A);
-				self.registers.P.set(ProcessorStatusRegisterBits::CARRY, new_carry);
-				self.registers.P.set(ProcessorStatusRegisterBits::OVERFLOW, new_overflow);
-			}
-			Instructions::STX => {
-				// Store Index X in Memory
-				// X -> M
-				let addr = self.fetch_instruction_address(addrmode);
-				self.bus.memory.write(addr, self.registers.X);
-			}
-			Instructions::STY => {
-				// Store Index Y in Memory
-				// Y -> M
-				let addr = self.fetch_instruction_address(addrmode);
-				self.bus.memory.write(addr, self.registers.Y);
-			}
-			Instructions::STA => {
-				// Store Accumulator in Memory
-				// A -> M
-				let addr = self.fetch_instruction_address(addrmode);
-				self.bus.memory.write(addr, self.registers.A);
+	/// Run until the CPU jams, or `max_cycles` cycles from now run out, or an instruction can't be executed.
+	pub fn run_until_jam(&mut self, max_cycles: u64) -> Result<RunEnd, CpuError> {
+		let last_cycle = self.cycles + max_cycles;
+		while self.cycles < last_cycle {
+			let before = self.state();
+			self.step()?;
+			if self.state().same_registers(&before) {
+				return Ok(RunEnd::Jammed);
 			}
-			Instructions::INX => {
-				// Increment Index X by One
-				// X + 1 -> X
-				self.registers.X = self.registers.X.wrapping_add(1);
-				self.registers.P.modify_n(self.registers.X);
-				self.registers.P.modify_z(self.registers.X);
-			}
-			Instructions::INY => {
-				// Increment Index Y by One
-				// Y + 1 -> Y
-				self.registers.Y = self.registers.Y.wrapping_add(1);
-				self.registers.P.modify_n(self.registers.Y);
-				self.registers.P.modify_z(self.registers.Y);
+		}
+		Ok(RunEnd::BudgetExhausted)
+	}
+
+	/// Run until the CPU is stuck in a loop (see `stuck.rs`), or `max_cycles` cycles from now run out, or an
+	/// instruction can't be executed. Interrupts can come when the I flag is clear, so then loops wait for them.
+	pub fn run_until_stuck(&mut self, max_cycles: u64, detection: StuckDetection) -> Result<RunEnd, CpuError> {
+		let last_cycle = self.cycles + max_cycles;
+		let mut detector = StuckDetector::new(detection);
+		while self.cycles < last_cycle {
+			let before = self.state();
+			self.step()?;
+			let interruptible = !self.registers.P.get(Flag::INTERRUPT_DISABLE);
+			if let Some(pc) = detector.check(&before, &self.state(), self.memory_written, interruptible) {
+				return Ok(RunEnd::StuckLoop { pc });
 			}
-			Instructions::INC => {
-				// Increment Memory by One
-				// M + 1 -> M
-				let fetched_memory = self.fetch_memory(&addrmode);
-				let new_memory = fetched_memory.wrapping_add(1);
+		}
+		Ok(RunEnd::BudgetExhausted)
+	}
 
-				let addr = self.fetch_instruction_address(addrmode);
-				self.bus.memory.write(addr, new_memory);
+	/// Whether the last instruction (or interrupt) wrote memory.
+	pub fn wrote_memory(&self) -> bool {
+		self.memory_written
+	}
 
-				self.registers.P.modify_n(new_memory);
-				self.registers.P.modify_z(new_memory);
-			}
-			Instructions::CMP => {
-				// Compare Memory with Accumulator
-				// A - M
+	/// The opcode of the last step, None if it was an interrupt (or nothing ran yet).
+	pub fn last_opcode(&self) -> Option<u8> {
+		self.last_opcode
+	}
 
-				self.exec_cmp(addrmode, self.registers.A);
-			}
-			Instructions::JMP => {
-				// Jump to New Location
-				// (PC+1) -> PCL
-				// (PC+2) -> PCH
-				let addr = self.fetch_instruction_address(addrmode);
-				self.registers.PC = addr;
-			}
-			Instructions::CPX => {
-				// Compare Memory and Index X
-				// X - M
+	/// The cycles the last instruction took over its base count: 1 for an indexed read that crossed a page, 1 for a
+	/// branch taken, 2 for a branch taken to another page. `OopsCycle` says which instructions can, this says whether
+	/// it did. 0 after an interrupt.
+	pub fn penalty_cycles(&self) -> u8 {
+		self.penalty_cycles
+	}
 
-				self.exec_cmp(addrmode, self.registers.X);
-			}
-			Instructions::CPY => {
-				// Compare Memory and Index Y
-				// Y - M
+	/// Do the bus accesses of the real CPU that don't change the result: a store indexed across a page (`STA $20F0,X`
+	/// with X = $20) first reads the address before the carry got to the high byte ($2010), and then writes the right
+	/// one ($2110). A read-modify-write instruction (INC, DEC, ASL, LSR, ROL, ROR on memory) writes back what it read
+	/// before the result. Only registers with side effects ($2002, $2007, the controllers, MMC1) can tell. Off by
+	/// default.
+	pub fn set_cycle_accurate(&mut self, accurate: bool) {
+		self.cycle_accurate = accurate;
+	}
 
-				self.exec_cmp(addrmode, self.registers.Y);
-			}
-			Instructions::BIT => {
-				// Test Bits in Memory with Accumulator
+	pub fn cycle_accurate(&self) -> bool {
+		self.cycle_accurate
+	}
 
-				// bits 7 and 6 of operand are transfered to bit 7 and 6 of SR (N,V);
-				// the zero-flag is set to the result of operand AND accumulator.
+	/// Check the stack pointer on every push and pull: a push that wraps SP from $00 to $FF, or a pull with SP at $FF,
+	/// is a `StackFault` of the instruction. The CPU goes on like the real one (SP wraps around); `stack_fault` tells.
+	pub fn set_strict_stack(&mut self, strict: bool) {
+		self.strict_stack = strict;
+		self.stack_fault = None;
+	}
 
-				// A AND M, M7 -> N, M6 -> V
+	/// The stack fault of the last instruction (or interrupt), with `set_strict_stack`.
+	pub fn stack_fault(&self) -> Option<StackFault> {
+		self.stack_fault
+	}
 
-				todo!();
-			}
-			_ => {
-				error!("Could not execute instruction: {:?}, not implimented, yet", instr);
-				panic!();
-			}
+	/// The bytes on the stack, in the order they were pushed (see `stack_contents`). Read with `peek`.
+	#[cfg(feature = "std")]
+	pub fn stack_slice(&self) -> Vec<u8> {
+		stack_contents(self.registers.S, |addr| self.bus.peek(addr))
+	}
+
+	/// Like `step`, but also returns what the step did: the registers before and after, and every read and write, in
+	/// order (see `effects.rs`). For running in lockstep with another 6502, and comparing.
+	pub fn step_with_effects(&mut self) -> Result<StepEffects, CpuError> {
+		let before = self.record_effects();
+		let result = self.step();
+		self.recorded_effects(before, result)
+	}
+
+	/// Record the accesses of the next step, until `recorded_effects`. Returns the state before it. For steps that do
+	/// more than `step` around it (`Emulator::step_with_effects`).
+	pub(crate) fn record_effects(&mut self) -> CpuState {
+		self.access_log = AccessLog::recording();
+		self.state()
+	}
+
+	/// What the step since `record_effects` did, and stop recording. `result` is what the step returned.
+	pub(crate) fn recorded_effects(&mut self, before: CpuState, result: Result<u8, CpuError>) -> Result<StepEffects, CpuError> {
+		let log = core::mem::take(&mut self.access_log);
+		let cycles = result?;
+		Ok(StepEffects::new(before, self.state(), cycles, self.penalty_cycles, &log))
+	}
+
+	/// A single clock cycle is executed here.
+	/// Original NES CPU needs multiple cycles to execute instruction.
+	/// Emulation does not do that; Its much simpler to do everything at once, and emulate the cycles.
+	/// Returns the amount of cycles the instruction took, or why it can't be executed (see `CpuError`).
+	pub fn step(&mut self) -> Result<u8, CpuError> {
+		debug!(target: CPU, "Tick, cycle: {}", self.cycles);
+		debug!(target: CPU, "{}", self.registers);
+
+		self.bus.instruction_start(self.registers.PC, self.cycles);
+		self.memory_written = false;
+		self.stack_fault = None;
+		self.last_opcode = None;
+		self.penalty_cycles = 0;
+
+		// The CPU checks for interrupts between instructions. The NMI comes first, and can't be disabled.
+		if self.nmi_requested {
+			return Ok(self.nmi());
 		}
+		if self.bus.irq_pending() && self.irq() {
+			return Ok(7);
+		}
+
+		// Read next instruction.
+		let pc = self.registers.PC;
+		let opcode = self.read(pc); // Read at address of Program Counter (duh!)
+		let Some(dispatch) = Self::DISPATCH[opcode as usize] else {
+			return Err(Self::dispatch_error(pc, opcode));
+		};
+		self.last_opcode = Some(opcode);
+
+		debug!(target: CPU, "{:#X}: {:?}\t{:?}\tBytes: {}, Cycles: {}, Oops cycle: {}", opcode, dispatch.instr, dispatch.addrmode, dispatch.bytes, dispatch.cycles, dispatch.oops_cycle);
+
+		// Most instructions access memory at their last cycle. So we let the rest of the machine run until then.
+		self.bus.tick(dispatch.cycles - 1);
+		// The NMI is polled before the last cycle: one that comes on it (like from the write of this instruction to
+		// PPUCTRL) waits for the end of the next instruction.
+		self.nmi_requested = self.bus.take_nmi();
+		self.page_crossed = false;
+		self.branch_taken = false;
+
+		//The main brains of the CPU. Execute instruction.
+		(dispatch.handler)(self, dispatch.addrmode);
 
 		// Increment PC by amount of bytes needed for the instruction, other than opcode (which is 1 byte).
 		// We do this at the end of the execution, because we need to access the PC (for the current instruction) before we increment it.
 		// For example, when we have LDA, we load A with immediate memory at the next byte of PC. So we access PC + 1.
 		// We also don't want to change PC if the instruction changes the PC.
-		if instr != Instructions::JMP {
-			self.registers.PC += bytes as u16;
+		if !dispatch.changes_pc {
+			self.registers.PC = self.registers.PC.wrapping_add(dispatch.bytes as u16);
 		}
 
-		self.cycles += cycles as u64;
-
-		match oops_cycle {
+		let extra_cycles = match dispatch.oops_cycle {
 			OopsCycle::NONE => { 
 				// don't change amount of cycles.
+				0
 			},
 			OopsCycle::PageBoundryCrossed => { 
-				//TODO: Impliment. For now, I don't change amount of cycles.
-
 				//add 1 to cycles if page boundary is crossed
+				self.page_crossed as u8
 			},
 			OopsCycle::BranchOccursOn => {
-				//TODO: Impliment. For now, I don't change amount of cycles.
-
 				//add 1 to cycles if branch occurs on same page
 				//add 2 to cycles if branch occurs to different page
+				if self.branch_taken { 1 + self.page_crossed as u8 } else { 0 }
 			}
+		};
+		let cycles = dispatch.cycles + extra_cycles;
+		self.penalty_cycles = extra_cycles;
+
+		// The last cycle of the instruction (and the oops cycles).
+		self.bus.tick(1 + extra_cycles);
+		self.cycles += cycles as u64;
+
+		Ok(cycles)
+	}
+
+	/// Build `DISPATCH`, at compile time.
+	const fn dispatch_table() -> [Option<Dispatch<B>>; 256] {
+		let mut table = [None; 256];
+		let mut opcode = 0;
+		while opcode < 256 {
+			if let Some((instr, addrmode, bytes, cycles, oops_cycle)) = decode_opcode(opcode as u8) {
+				if let Some(handler) = Self::handler(&instr) {
+					let changes_pc = Self::changes_pc(&instr);
+					table[opcode] = Some(Dispatch { instr, handler, addrmode, bytes, cycles, oops_cycle, changes_pc });
+				}
+			}
+			opcode += 1;
 		}
+		table
 	}
 
-	// /// Relative addressing is PC + offset.
-	// /// The offset is the next byte after opcode.
-	// /// So we fetch the next byte (after opcode) and add it with PC.
-	// /// IMPORTANT: The offset is SIGNED. Which means, the offset can be -128 to 127.
-	// fn fetch_relative(&self) -> u16 {
-	// 	let pc = self.registers.PC;
-	// 	let offset = self.bus.rom.read(self.registers.PC + 1) as i8;
-	// 	// Here we need a way to add 'u16' type with 'i8' type.
-	// 	// IMPORTANT NOTE: We need the "mixed_integer_ops" feature, which is in nightly rust.
-	// 	// Its very complex to do this manually, without this feature. So what the hell.
-	// 	let res = pc.wrapping_add_signed(offset as i16);
-	// 	debug!("Fetched relative: {}", res);
-	// 	res
-	// }
+	/// The function that executes the instruction. None for instructions that are not implemented yet.
+	const fn handler(instr: &Instructions) -> Option<Handler<B>> {
+		let handler: Handler<B> = match instr {
+			Instructions::LDX => Self::ldx,
+			Instructions::LDY => Self::ldy,
+			Instructions::LDA => Self::lda,
+			Instructions::PHA => Self::pha,
+			Instructions::PHP => Self::php,
+			Instructions::PLP => Self::plp,
+			Instructions::BRK => Self::brk,
+			Instructions::RTI => Self::rti,
+			Instructions::NOP => Self::nop,
+			Instructions::PLA => Self::pla,
+			Instructions::SEC => Self::sec,
+			Instructions::CLC => Self::clc,
+			Instructions::SED => Self::sed,
+			Instructions::CLD => Self::cld,
+			Instructions::SEI => Self::sei,
+			Instructions::CLI => Self::cli,
+			Instructions::CLV => Self::clv,
+			Instructions::ADC => Self::adc,
+			Instructions::STX => Self::stx,
+			Instructions::STY => Self::sty,
+			Instructions::STA => Self::sta,
+			Instructions::INX => Self::inx,
+			Instructions::INY => Self::iny,
+			Instructions::INC => Self::inc,
+			Instructions::CMP => Self::cmp,
+			Instructions::JMP => Self::jmp,
+			Instructions::CPX => Self::cpx,
+			Instructions::CPY => Self::cpy,
+			Instructions::BIT => Self::bit,
+			Instructions::BCC => Self::bcc,
+			Instructions::BCS => Self::bcs,
+			Instructions::BNE => Self::bne,
+			Instructions::BEQ => Self::beq,
+			Instructions::BPL => Self::bpl,
+			Instructions::BMI => Self::bmi,
+			Instructions::BVC => Self::bvc,
+			Instructions::BVS => Self::bvs,
+			Instructions::JSR => Self::jsr,
+			Instructions::RTS => Self::rts,
+			Instructions::AND => Self::and,
+			Instructions::SBC => Self::sbc,
+			Instructions::LSR => Self::lsr,
+			Instructions::ASL => Self::asl,
+			Instructions::ROL => Self::rol,
+			Instructions::ROR => Self::ror,
+			Instructions::DEC => Self::dec,
+			Instructions::DEX => Self::dex,
+			Instructions::TXA => Self::txa,
+			_ => return None,
+		};
+		Some(handler)
+	}
 
-	/// $0xFFFA, $0xFFFB
-	// fn nmi_interrupt(&self)
+	/// Why the opcode is not in `DISPATCH`. Only for errors, so it can decode the opcode again.
+	#[cold]
+	fn dispatch_error(pc: u16, opcode: u8) -> CpuError {
+		match decode_opcode(opcode) {
+			None => {
+				error!(target: CPU, "Could not decode instruction, opcode: {:#X}", opcode);
+				CpuError::IllegalOpcode { pc, opcode }
+			}
+			Some(instruction) => {
+				error!(target: CPU, "Could not execute instruction: {:?}, not implimented, yet", instruction.0);
+				CpuError::Unimplemented { pc, opcode }
+			}
+		}
+	}
+
+	/// Instructions that set the PC by themselves, so we don't increment it after execution.
+	const fn changes_pc(instr: &Instructions) -> bool {
+		matches!(instr,
+			Instructions::JMP | Instructions::JSR | Instructions::RTS | Instructions::BRK | Instructions::RTI |
+			Instructions::BCC | Instructions::BCS | Instructions::BNE | Instructions::BEQ |
+			Instructions::BPL | Instructions::BMI | Instructions::BVC | Instructions::BVS)
+	}
+
+	/// Relative addressing is PC + offset.
+	/// The offset is the next byte after opcode.
+	/// IMPORTANT: The offset is SIGNED. Which means, the offset can be -128 to 127.
+	/// The offset is relative to the next instruction (PC + 2), not to the branch itself.
+	fn exec_branch(&mut self, condition: bool) {
+		let next_instruction = self.registers.PC.wrapping_add(2);
+		if !condition {
+			self.registers.PC = next_instruction;
+			return;
+		}
+
+		// Sign extend the offset, and wrap around the 64KB address space, like the real CPU.
+		let offset = self.read(self.registers.PC.wrapping_add(1)) as i8;
+		let target = next_instruction.wrapping_add_signed(offset as i16);
+		debug!(target: CPU, "Branch taken to: {:#X}", target);
+
+		self.branch_taken = true;
+		self.page_crossed = (next_instruction & 0xFF00) != (target & 0xFF00);
+		self.registers.PC = target;
+	}
+
+	/// Push PC and P, disable interrupts, and jump to the address in the vector.
+	/// The B flag is only set in the pushed P when it's a BRK instruction.
+	fn interrupt(&mut self, return_addr: u16, vector: u16, brk: bool) {
+		self.push_stack((return_addr >> 8) as u8);
+		self.push_stack(return_addr as u8);
+		let status = self.registers.P.to_stack(brk);
+		self.push_stack(status);
+
+		self.registers.P.set(Flag::INTERRUPT_DISABLE, true);
+		let lsb = self.read(vector) as u16;
+		let msb = self.read(vector + 1) as u16;
+		self.registers.PC = (msb << 8) | lsb;
+	}
 
-	/// $0xFFFC, $0xFFFD
-	// fn res_interrupt(&self)
+	/// All the reads of the CPU go through here.
+	fn read(&mut self, addr: u16) -> u8 {
+		let value = self.bus.read(addr);
+		self.access_log.record(BusAccess::read(addr, value));
+		value
+	}
+
+	/// All the writes of the CPU go through here.
+	fn write(&mut self, addr: u16, data: u8) {
+		self.memory_written = true;
+		if self.access_log.is_recording() {
+			let old = self.bus.peek(addr);
+			self.access_log.record_write(BusAccess::write(addr, data), old);
+		}
+		self.bus.write(addr, data);
+	}
 
-	/// $0xFFFE, $0xFFFF
-	// fn irq_interrupt(&self)
+	/// The write of a read-modify-write instruction. The real CPU writes back what it read, and the result on the next
+	/// cycle: with `cycle_accurate`, so does this.
+	fn write_modified(&mut self, addr: u16, old: u8, new: u8) {
+		if self.cycle_accurate {
+			self.write(addr, old);
+		}
+		self.write(addr, new);
+	}
 
 	fn push_stack(&mut self, data: u8) {
-		self.bus.memory.write(0x100 + self.registers.S as u16, data);
-		self.registers.S -= 1;
-		debug!("Pushed to stack: \t{:#X}", data);
+		if self.strict_stack && self.registers.S == 0x00 {
+			warn!(target: CPU, "Stack push: stack pointer is at the end, overflowing stack pointer");
+			self.stack_fault = Some(StackFault::Overflow);
+		}
+		self.write(0x100 + self.registers.S as u16, data);
+		self.registers.S = self.registers.S.wrapping_sub(1);
+		debug!(target: CPU, "Pushed to stack: \t{:#X}", data);
 	}
 
 	fn pop_stack(&mut self) -> u8 {
 		if self.registers.S == 0xFF {
-			warn!("Stack pop: stack pointer is at beginning, overflowing stack pointer");
+			warn!(target: CPU, "Stack pop: stack pointer is at beginning, overflowing stack pointer");
+			if self.strict_stack {
+				self.stack_fault = Some(StackFault::Underflow);
+			}
 		}
 		let head_addr: u16 = 0x100 + (self.registers.S as u16) + 1;  // we add 1 before the current SP points to get the head (the stack is down going)
-		let res = self.bus.memory.read(head_addr);
+		let res = self.read(head_addr);
 		self.registers.S = self.registers.S.wrapping_add(1);  // NOTE: We allow the programmer to overflow SP.
 		//self.registers.S += 1;
-		debug!("Poped stack: \t{:#X}", res);
+		debug!(target: CPU, "Poped stack: \t{:#X}", res);
 		res
 	}
 
 	/// Convert data from hex (example: 0x0B) to another hex (0x11), but is represented in 'decimal hex' form.
-	fn decimal_mode(&self, data: u8) -> u8 {
-		let hex_str = data.to_string();
-		let decoded = <[u8; 1]>::from_hex(hex_str).expect("Could not convert decimal");
-		decoded[0]
+	/// Above 99 it wraps around (0x64 to 0x00), and carries, like adding decimal digits.
+	fn decimal_mode(&self, data: u8) -> (u8, bool) {
+		let carry = data > 99;
+		let data = data % 100;
+		(((data / 10) << 4) | (data % 10), carry)
 	}
 
-	fn fetch_absolute_indexed(&self, index: u8) -> u8 {
-		let addr = self.read_instruction_absolute_address() + index as u16;
-		self.bus.memory.read(addr)
+	fn fetch_absolute_indexed(&mut self, index: u8) -> u8 {
+		let base = self.read_instruction_absolute_address();
+		let addr = base.wrapping_add(index as u16);
+		self.page_crossed = (base & 0xFF00) != (addr & 0xFF00);
+		self.read(addr)
 	}
 
-	fn fetch_zero_page_indexed(&self, index: u8) -> u8 {
+	fn fetch_zero_page_indexed(&mut self, index: u8) -> u8 {
 		let instr_addr = self.read_instruction_zero_page_address();
 		let addr = instr_addr.wrapping_add(index);
-		self.bus.memory.read(addr as u16)
+		self.read(addr as u16)
 	}
 
 	/// Read memory. This can be in ROM (immediate, for example) or in RAM (absolute, for example).
 	/// All load instructions use this.
-	fn fetch_memory(&self, addrmode: &AddressingMode) -> u8 {
+	fn fetch_memory(&mut self, addrmode: &AddressingMode) -> u8 {
 		match addrmode {
 			AddressingMode::IMPLIED => {
 				panic!("Instruction with implied addressing mode should never ask to fetch memory.");
 			}
 			AddressingMode::IMMEDIATE => {
-				let addr = self.registers.PC + 1;
-				let res = self.bus.rom.read(addr);
-				debug!("Fetched immediate: {:#X}", res);
+				let addr = self.registers.PC.wrapping_add(1);
+				let res = self.read(addr);
+				debug!(target: CPU, "Fetched immediate: {:#X}", res);
 				res
 			}
 			AddressingMode::ACCUMULATOR => {
 				let res = self.registers.A;
-				debug!("Fetched accumulator: {}", res);
+				debug!(target: CPU, "Fetched accumulator: {}", res);
 				res
 			},
 			AddressingMode::ZEROPAGE => {
 				let addr = self.read_instruction_zero_page_address();
-				let res = self.bus.memory.read(addr as u16);
-				debug!("Fetched from zero page: {:#X}", res);
+				let res = self.read(addr as u16);
+				debug!(target: CPU, "Fetched from zero page: {:#X}", res);
 				res
 			},
 			AddressingMode::ZEROPAGEX => {
 				let res = self.fetch_zero_page_indexed(self.registers.X);
-				debug!("Fetched zeropage,x: {:#X}", res);
+				debug!(target: CPU, "Fetched zeropage,x: {:#X}", res);
 				res
 			}
 			AddressingMode::ZEROPAGEY => {
 				let res = self.fetch_zero_page_indexed(self.registers.Y);
-				debug!("Fetched zeropage,y: {:#X}", res);
+				debug!(target: CPU, "Fetched zeropage,y: {:#X}", res);
 				res
 			},
 			AddressingMode::ABSOLUTE => {
 				let res = self.fetch_absolute_indexed(0);
-				debug!("Fetched absolute: {:#X}", res);
+				debug!(target: CPU, "Fetched absolute: {:#X}", res);
 				res
 			},
 			AddressingMode::ABSOLUTEX => {
 				let res = self.fetch_absolute_indexed(self.registers.X);
-				debug!("Fetched absolute,X: {:#X}", res);
+				debug!(target: CPU, "Fetched absolute,X: {:#X}", res);
 				res
 			}
 			AddressingMode::ABSOLUTEY => {
 				let res = self.fetch_absolute_indexed(self.registers.Y);
-				debug!("Fetched absolute,Y: {:#X}", res);
+				debug!(target: CPU, "Fetched absolute,Y: {:#X}", res);
+				res
+			}
+			AddressingMode::INDIRECTX => {
+				let addr = self.read_instruction_indirect_x_address();
+				let res = self.read(addr);
+				debug!(target: CPU, "Fetched (indirect,X): {:#X}", res);
+				res
+			}
+			AddressingMode::INDIRECTY => {
+				let base = self.read_instruction_indirect_y_base();
+				let addr = base.wrapping_add(self.registers.Y as u16);
+				self.page_crossed = (base & 0xFF00) != (addr & 0xFF00);
+				let res = self.read(addr);
+				debug!(target: CPU, "Fetched (indirect),Y: {:#X}", res);
 				res
 			}
 			_ => {
-				error!("The instruction doesn't support addressing mode: {:?}, panic", addrmode);
+				error!(target: CPU, "The instruction doesn't support addressing mode: {:?}, panic", addrmode);
 				panic!();
 			}
 		}
@@ -393,37 +737,82 @@ impl CPU {
 
 	/// Extract the address from instruction. This function will access ROM and RAM, aswell as indirect addressing.
 	/// All store instructions use this.
-	fn fetch_instruction_address(&self, addrmode: AddressingMode) -> u16 {
+	fn fetch_instruction_address(&mut self, addrmode: AddressingMode) -> u16 {
 		match addrmode {
 			AddressingMode::IMMEDIATE => {
-				let res = self.bus.rom.read(self.registers.PC + 1) as u16;
-				debug!("Fetched immediate address: {:#X}", res);
+				let res = self.read(self.registers.PC.wrapping_add(1)) as u16;
+				debug!(target: CPU, "Fetched immediate address: {:#X}", res);
 				res
 			}
 			AddressingMode::ABSOLUTE => 	self.read_instruction_absolute_address(),
+			AddressingMode::ABSOLUTEX => 	self.read_instruction_absolute_address().wrapping_add(self.registers.X as u16),
+			AddressingMode::ABSOLUTEY => 	self.read_instruction_absolute_address().wrapping_add(self.registers.Y as u16),
 			AddressingMode::ZEROPAGE => 	self.read_instruction_zero_page_address() as u16,
+			AddressingMode::ZEROPAGEX => 	self.read_instruction_zero_page_address().wrapping_add(self.registers.X) as u16,
+			AddressingMode::ZEROPAGEY => 	self.read_instruction_zero_page_address().wrapping_add(self.registers.Y) as u16,
 			AddressingMode::INDIRECT => 	self.read_instruction_indirect_address(),
-			_ => todo!()
+			AddressingMode::INDIRECTX => 	self.read_instruction_indirect_x_address(),
+			AddressingMode::INDIRECTY => {
+				let base = self.read_instruction_indirect_y_base();
+				base.wrapping_add(self.registers.Y as u16)
+			}
+			_ => unreachable!("No instruction stores with addressing mode: {:?}", addrmode)
 		}
 	}
 
+	/// Like `fetch_instruction_address`, for stores. Indexed across a page, the real CPU reads the address with the
+	/// old high byte, before it writes: with `cycle_accurate`, so does this.
+	fn fetch_store_address(&mut self, addrmode: AddressingMode) -> u16 {
+		let (base, index) = match addrmode {
+			AddressingMode::ABSOLUTEX => (self.read_instruction_absolute_address(), self.registers.X),
+			AddressingMode::ABSOLUTEY => (self.read_instruction_absolute_address(), self.registers.Y),
+			AddressingMode::INDIRECTY => (self.read_instruction_indirect_y_base(), self.registers.Y),
+			_ => return self.fetch_instruction_address(addrmode),
+		};
+		let addr = base.wrapping_add(index as u16);
+		if self.cycle_accurate && (base & 0xFF00) != (addr & 0xFF00) {
+			self.read((base & 0xFF00) | (addr & 0x00FF));
+		}
+		addr
+	}
+
 	/// Reads address stored in ROM at the current PC.
-	fn read_instruction_absolute_address(&self) -> u16 {
-		let lsb = self.bus.rom.read(self.registers.PC + 1) as u16;
-		let msb = self.bus.rom.read(self.registers.PC + 2) as u16;
+	fn read_instruction_absolute_address(&mut self) -> u16 {
+		let lsb = self.read(self.registers.PC.wrapping_add(1)) as u16;
+		let msb = self.read(self.registers.PC.wrapping_add(2)) as u16;
 		(msb << 8) | lsb
 	}
 
 	/// Reads zero-page address stored in ROM at the current PC.
-	fn read_instruction_zero_page_address(&self) -> u8 {
-		self.bus.rom.read(self.registers.PC + 1)
+	fn read_instruction_zero_page_address(&mut self) -> u8 {
+		self.read(self.registers.PC.wrapping_add(1))
 	}
 
 	/// Returns address stored in memory, from the absolute address in ROM, at the current PC.
-	fn read_instruction_indirect_address(&self) -> u16 {
+	/// The 6502 doesn't carry to the high byte of the pointer: JMP ($10FF) reads the MSB from $1000, not $1100.
+	fn read_instruction_indirect_address(&mut self) -> u16 {
 		let indirect_addr = self.read_instruction_absolute_address();
-		let lsb = self.bus.memory.read(indirect_addr) as u16;
-		let msb = self.bus.memory.read(indirect_addr + 1) as u16;
+		let lsb = self.read(indirect_addr) as u16;
+		let msb = self.read((indirect_addr & 0xFF00) | (indirect_addr as u8).wrapping_add(1) as u16) as u16;
+		(msb << 8) | lsb
+	}
+
+	/// Returns address stored in the zero page, at the address in ROM plus X: `LDA ($10,X)`.
+	fn read_instruction_indirect_x_address(&mut self) -> u16 {
+		let pointer = self.read_instruction_zero_page_address().wrapping_add(self.registers.X);
+		self.read_zero_page_pointer(pointer)
+	}
+
+	/// Returns address stored in the zero page, at the address in ROM: `LDA ($10),Y`. Y is added to it by the caller.
+	fn read_instruction_indirect_y_base(&mut self) -> u16 {
+		let pointer = self.read_instruction_zero_page_address();
+		self.read_zero_page_pointer(pointer)
+	}
+
+	/// Read an address from the zero page. The pointer wraps around the zero page: $FF reads $FF and $00.
+	fn read_zero_page_pointer(&mut self, pointer: u8) -> u16 {
+		let lsb = self.read(pointer as u16) as u16;
+		let msb = self.read(pointer.wrapping_add(1) as u16) as u16;
 		(msb << 8) | lsb
 	}
 
@@ -454,28 +843,469 @@ impl CPU {
 				(last_bit, false, true)
 			};
 
-		self.registers.P.set(ProcessorStatusRegisterBits::NEGATIVE, new_n);
-		self.registers.P.set(ProcessorStatusRegisterBits::ZERO, new_z);
-		self.registers.P.set(ProcessorStatusRegisterBits::CARRY, new_c);
+		self.registers.P.set(Flag::NEGATIVE, new_n);
+		self.registers.P.set(Flag::ZERO, new_z);
+		self.registers.P.set(Flag::CARRY, new_c);
+	}
+
+	/// Execute adc instruction: A + M + C -> A, and the flags.
+	/// Possible instructions: ADC, and SBC (with the data inverted).
+	fn exec_adc(&mut self, m: u8, decimal: bool) {
+		let a = self.registers.A;
+		let carry: u8 = self.registers.P.get(Flag::CARRY) as u8;
+
+		// Carry flag: Only for unsigned. If result is > 255, carry is set.
+		// Overflow flag: Only if (Positive+Positive=Negative) or (Negative+Negative=Positive)
+
+		// Perform regular unsigned addition, allowing arithmetic overflow.
+		let first_addition = a.overflowing_add(m);
+		let second_addition = first_addition.0.overflowing_add(carry);
+		let mut result = second_addition.0;
+		let mut decimal_carry = false;
+
+		// Set A register.
+
+		// Check decimal mode, check if CPU is in binary/decimal coded mode
+		// TODO: I read that NES doesn't use this mode. Maybe remove it so I don't have any problems?
+		if decimal {
+			(result, decimal_carry) = self.decimal_mode(result);
+		}
+		self.registers.A = result;
+
+		// Set carry accordingly.
+		let new_carry = first_addition.1 || second_addition.1 || decimal_carry;
+
+		// Set overflow accordingly.
+		let is_a_negative = (a >> 7) == 1;
+		let is_m_negative = (m >> 7) == 1;
+		let is_result_negative = (result >> 7) == 1;
+		let new_overflow = 
+			( is_a_negative 	&&  is_m_negative 	&& !is_result_negative 	) ||
+			(!is_a_negative 	&& !is_m_negative 	&&  is_result_negative 	);
+		
+		self.registers.P.modify_nz(self.registers.A);
+		self.registers.P.set(Flag::CARRY, new_carry);
+		self.registers.P.set(Flag::OVERFLOW, new_overflow);
 	}
 
 }
 
-#[cfg(test)]
+// The instructions, one function each. `DISPATCH` points at them, by opcode.
+impl<B: Bus> CPU<B> {
+	fn ldx(&mut self, addrmode: AddressingMode) {
+		// Load Index X with Memory
+		// M -> X
+		let fetched_memory = self.fetch_memory(&addrmode);
+		self.registers.X = fetched_memory;
+
+		self.registers.P.modify_nz(fetched_memory);
+	}
+
+	fn ldy(&mut self, addrmode: AddressingMode) {
+		// Load Index Y with Memory
+		// M -> Y
+		let fetched_memory = self.fetch_memory(&addrmode);
+		self.registers.Y = fetched_memory;
+
+		self.registers.P.modify_nz(fetched_memory);
+	}
+
+	fn lda(&mut self, addrmode: AddressingMode) {
+		// Load Accumulator with Memory
+		// M -> A
+		let fetched_memory = self.fetch_memory(&addrmode);
+		self.registers.A = fetched_memory;
+
+		self.registers.P.modify_nz(fetched_memory);
+	}
+
+	fn pha(&mut self, _addrmode: AddressingMode) {
+		// Push Accumulator on Stack
+		// push A
+		self.push_stack(self.registers.A);
+	}
+
+	fn php(&mut self, _addrmode: AddressingMode) {
+		// Push Processor Status on Stack
+		// The status register will be pushed with the break flag and bit 5 set to 1.
+		self.push_stack(self.registers.P.to_stack(true));
+	}
+
+	fn plp(&mut self, _addrmode: AddressingMode) {
+		// Pull Processor Status from Stack
+		// The status register will be pulled with the break flag and bit 5 ignored.
+		let fetched_memory = self.pop_stack();
+		self.registers.P = StatusFlags::from_stack(fetched_memory);
+	}
+
+	fn brk(&mut self, _addrmode: AddressingMode) {
+		// Force Break
+		// BRK initiates a software interrupt similar to a hardware interrupt (IRQ).
+		// The return address pushed to the stack is PC+2, providing an extra byte of spacing for a break mark.
+		let return_addr = self.registers.PC.wrapping_add(2);
+		self.interrupt(return_addr, IRQ_VECTOR, true);
+	}
+
+	fn rti(&mut self, _addrmode: AddressingMode) {
+		// Return from Interrupt
+		// pull SR, pull PC
+		let status = self.pop_stack();
+		self.registers.P = StatusFlags::from_stack(status);
+		let lsb = self.pop_stack() as u16;
+		let msb = self.pop_stack() as u16;
+		self.registers.PC = (msb << 8) | lsb;
+	}
+
+	fn nop(&mut self, _addrmode: AddressingMode) {
+		// No Operation
+	}
+
+	fn pla(&mut self, _addrmode: AddressingMode) {
+		// Pull Accumulator from Stack
+		// pull A
+		let fetched_memory = self.pop_stack();
+		self.registers.A = fetched_memory;
+
+		self.registers.P.modify_nz(fetched_memory);
+	}
+
+	fn sec(&mut self, _addrmode: AddressingMode) {
+		// Set Carry Flag
+		self.registers.P.set(Flag::CARRY, true);
+	}
+
+	fn clc(&mut self, _addrmode: AddressingMode) {
+		// Clear Carry Flag
+		self.registers.P.set(Flag::CARRY, false);
+	}
+
+	fn sed(&mut self, _addrmode: AddressingMode) {
+		// Set Decimal Flag
+		self.registers.P.set(Flag::DECIMAL, true);
+	}
+
+	fn cld(&mut self, _addrmode: AddressingMode) {
+		// Clear Decimal Mode
+		self.registers.P.set(Flag::DECIMAL, false);
+	}
+
+	fn sei(&mut self, _addrmode: AddressingMode) {
+		// Set Interrupt Disable Status
+		self.registers.P.set(Flag::INTERRUPT_DISABLE, true);
+	}
+
+	fn cli(&mut self, _addrmode: AddressingMode) {
+		// Clear Interrupt Disable Bit
+		self.registers.P.set(Flag::INTERRUPT_DISABLE, false);
+	}
+
+	fn clv(&mut self, _addrmode: AddressingMode) {
+		// Clear Overflow Flag
+		self.registers.P.set(Flag::OVERFLOW, false);
+	}
+
+	fn adc(&mut self, addrmode: AddressingMode) {
+		// Add Memory to Accumulator with Carry
+		// A + M + C -> A, C
+		// NOTE: This is the first instruction that actually does 'complex' arithmetic
+		// After reading a lot of forums, its actually the most complex thing to emulate, I must understand this
+
+		let fetched_memory = self.fetch_memory(&addrmode);
+		self.exec_adc(fetched_memory, self.registers.P.get(Flag::DECIMAL));
+	}
+
+	fn stx(&mut self, addrmode: AddressingMode) {
+		// Store Index X in Memory
+		// X -> M
+		let addr = self.fetch_instruction_address(addrmode);
+		self.write(addr, self.registers.X);
+	}
+
+	fn sty(&mut self, addrmode: AddressingMode) {
+		// Store Index Y in Memory
+		// Y -> M
+		let addr = self.fetch_instruction_address(addrmode);
+		self.write(addr, self.registers.Y);
+	}
+
+	fn sta(&mut self, addrmode: AddressingMode) {
+		// Store Accumulator in Memory
+		// A -> M
+		let addr = self.fetch_store_address(addrmode);
+		self.write(addr, self.registers.A);
+	}
+
+	fn inx(&mut self, _addrmode: AddressingMode) {
+		// Increment Index X by One
+		// X + 1 -> X
+		self.registers.X = self.registers.X.wrapping_add(1);
+		self.registers.P.modify_nz(self.registers.X);
+	}
+
+	fn iny(&mut self, _addrmode: AddressingMode) {
+		// Increment Index Y by One
+		// Y + 1 -> Y
+		self.registers.Y = self.registers.Y.wrapping_add(1);
+		self.registers.P.modify_nz(self.registers.Y);
+	}
+
+	fn inc(&mut self, addrmode: AddressingMode) {
+		// Increment Memory by One
+		// M + 1 -> M
+		// Read, modify, write: the operand is read once.
+		let addr = self.fetch_instruction_address(addrmode);
+		let fetched_memory = self.read(addr);
+		let new_memory = fetched_memory.wrapping_add(1);
+		self.write_modified(addr, fetched_memory, new_memory);
+
+		self.registers.P.modify_nz(new_memory);
+	}
+
+	fn cmp(&mut self, addrmode: AddressingMode) {
+		// Compare Memory with Accumulator
+		// A - M
+
+		self.exec_cmp(addrmode, self.registers.A);
+	}
+
+	fn jmp(&mut self, addrmode: AddressingMode) {
+		// Jump to New Location
+		// (PC+1) -> PCL
+		// (PC+2) -> PCH
+		let addr = self.fetch_instruction_address(addrmode);
+		self.registers.PC = addr;
+	}
+
+	fn cpx(&mut self, addrmode: AddressingMode) {
+		// Compare Memory and Index X
+		// X - M
+
+		self.exec_cmp(addrmode, self.registers.X);
+	}
+
+	fn cpy(&mut self, addrmode: AddressingMode) {
+		// Compare Memory and Index Y
+		// Y - M
+
+		self.exec_cmp(addrmode, self.registers.Y);
+	}
+
+	fn bit(&mut self, addrmode: AddressingMode) {
+		// Test Bits in Memory with Accumulator
+
+		// bits 7 and 6 of operand are transfered to bit 7 and 6 of SR (N,V);
+		// the zero-flag is set to the result of operand AND accumulator.
+
+		// A AND M, M7 -> N, M6 -> V
+		let fetched_memory = self.fetch_memory(&addrmode);
+
+		self.registers.P.modify_n(fetched_memory);
+		self.registers.P.set(Flag::OVERFLOW, (fetched_memory >> 6) & 1 == 1);
+		self.registers.P.modify_z(self.registers.A & fetched_memory);
+	}
+
+	fn bcc(&mut self, _addrmode: AddressingMode) {
+		// Branch on Carry Clear
+		// branch on C = 0
+		self.exec_branch(!self.registers.P.get(Flag::CARRY));
+	}
+
+	fn bcs(&mut self, _addrmode: AddressingMode) {
+		// Branch on Carry Set
+		// branch on C = 1
+		self.exec_branch(self.registers.P.get(Flag::CARRY));
+	}
+
+	fn bne(&mut self, _addrmode: AddressingMode) {
+		// Branch on Result not Zero
+		// branch on Z = 0
+		self.exec_branch(!self.registers.P.get(Flag::ZERO));
+	}
+
+	fn beq(&mut self, _addrmode: AddressingMode) {
+		// Branch on Result Zero
+		// branch on Z = 1
+		self.exec_branch(self.registers.P.get(Flag::ZERO));
+	}
+
+	fn bpl(&mut self, _addrmode: AddressingMode) {
+		// Branch on Result Plus
+		// branch on N = 0
+		self.exec_branch(!self.registers.P.get(Flag::NEGATIVE));
+	}
+
+	fn bmi(&mut self, _addrmode: AddressingMode) {
+		// Branch on Result Minus
+		// branch on N = 1
+		self.exec_branch(self.registers.P.get(Flag::NEGATIVE));
+	}
+
+	fn bvc(&mut self, _addrmode: AddressingMode) {
+		// Branch on Overflow Clear
+		// branch on V = 0
+		self.exec_branch(!self.registers.P.get(Flag::OVERFLOW));
+	}
+
+	fn bvs(&mut self, _addrmode: AddressingMode) {
+		// Branch on Overflow Set
+		// branch on V = 1
+		self.exec_branch(self.registers.P.get(Flag::OVERFLOW));
+	}
+
+	fn jsr(&mut self, addrmode: AddressingMode) {
+		// Jump to New Location Saving Return Address
+		// push (PC+2),
+		// (PC+1) -> PCL
+		// (PC+2) -> PCH
+		// The pushed address is the last byte of the JSR, not the next instruction. RTS adds the 1.
+		let addr = self.fetch_instruction_address(addrmode);
+		let return_addr = self.registers.PC.wrapping_add(2);
+		self.push_stack((return_addr >> 8) as u8);
+		self.push_stack(return_addr as u8);
+		self.registers.PC = addr;
+	}
+
+	fn rts(&mut self, _addrmode: AddressingMode) {
+		// Return from Subroutine
+		// pull PC, PC+1 -> PC
+		let lsb = self.pop_stack() as u16;
+		let msb = self.pop_stack() as u16;
+		self.registers.PC = ((msb << 8) | lsb).wrapping_add(1);
+	}
+
+	fn and(&mut self, addrmode: AddressingMode) {
+		// AND Memory with Accumulator
+		// A AND M -> A
+		let fetched_memory = self.fetch_memory(&addrmode);
+		self.registers.A &= fetched_memory;
+		self.registers.P.modify_nz(self.registers.A);
+	}
+
+	fn sbc(&mut self, addrmode: AddressingMode) {
+		// Subtract Memory from Accumulator with Borrow
+		// A - M - (1 - C) -> A
+		// The borrow is the inverted carry, so this is A + (NOT M) + C: an ADC of the inverted memory, flags and all.
+		// Binary only, the NES has no decimal mode (see the TODO in `exec_adc`).
+		let fetched_memory = self.fetch_memory(&addrmode);
+		self.exec_adc(!fetched_memory, false);
+	}
+
+	fn lsr(&mut self, addrmode: AddressingMode) {
+		// Shift One Bit Right (Memory or Accumulator)
+		// 0 -> [76543210] -> C
+		let fetched_memory = self.modify(addrmode, |value| value >> 1);
+		self.registers.P.set(Flag::CARRY, fetched_memory & 1 == 1);
+	}
+
+	fn asl(&mut self, addrmode: AddressingMode) {
+		// Shift Left One Bit (Memory or Accumulator)
+		// C <- [76543210] <- 0
+		let fetched_memory = self.modify(addrmode, |value| value << 1);
+		self.registers.P.set(Flag::CARRY, fetched_memory & 0x80 != 0);
+	}
+
+	fn rol(&mut self, addrmode: AddressingMode) {
+		// Rotate One Bit Left (Memory or Accumulator)
+		// C <- [76543210] <- C
+		let carry = self.registers.P.get(Flag::CARRY) as u8;
+		let fetched_memory = self.modify(addrmode, |value| (value << 1) | carry);
+		self.registers.P.set(Flag::CARRY, fetched_memory & 0x80 != 0);
+	}
+
+	fn ror(&mut self, addrmode: AddressingMode) {
+		// Rotate One Bit Right (Memory or Accumulator)
+		// C -> [76543210] -> C
+		let carry = self.registers.P.get(Flag::CARRY) as u8;
+		let fetched_memory = self.modify(addrmode, |value| (value >> 1) | (carry << 7));
+		self.registers.P.set(Flag::CARRY, fetched_memory & 1 == 1);
+	}
+
+	/// The accumulator or the memory operand of a shift or a rotate, through `operation`. Sets N and Z from the result,
+	/// and returns the operand, for the carry.
+	fn modify(&mut self, addrmode: AddressingMode, operation: impl Fn(u8) -> u8) -> u8 {
+		let fetched_memory;
+		let result;
+		if addrmode == AddressingMode::ACCUMULATOR {
+			fetched_memory = self.registers.A;
+			result = operation(fetched_memory);
+			self.registers.A = result;
+		} else {
+			let addr = self.fetch_instruction_address(addrmode);
+			fetched_memory = self.read(addr);
+			result = operation(fetched_memory);
+			self.write_modified(addr, fetched_memory, result);
+		}
+		self.registers.P.modify_nz(result);
+		fetched_memory
+	}
+
+	fn dec(&mut self, addrmode: AddressingMode) {
+		// Decrement Memory by One
+		// M - 1 -> M
+		// Read, modify, write: the operand is read once.
+		let addr = self.fetch_instruction_address(addrmode);
+		let fetched_memory = self.read(addr);
+		let new_memory = fetched_memory.wrapping_sub(1);
+		self.write_modified(addr, fetched_memory, new_memory);
+
+		self.registers.P.modify_nz(new_memory);
+	}
+
+	fn dex(&mut self, _addrmode: AddressingMode) {
+		// Decrement Index X by One
+		// X - 1 -> X
+		self.registers.X = self.registers.X.wrapping_sub(1);
+		self.registers.P.modify_nz(self.registers.X);
+	}
+
+	fn txa(&mut self, _addrmode: AddressingMode) {
+		// Transfer Index X to Accumulator
+		// X -> A
+		self.registers.A = self.registers.X;
+		self.registers.P.modify_nz(self.registers.A);
+	}
+}
+
+#[cfg(feature = "std")]
+impl<B: Bus + SaveState> SaveState for CPU<B> {
+	fn save_state(&self, out: &mut StateWriter) {
+		out.u8(self.registers.A);
+		out.u8(self.registers.X);
+		out.u8(self.registers.Y);
+		out.u8(self.registers.P.to_byte());
+		out.u8(self.registers.S);
+		out.u16(self.registers.PC);
+		out.u64(self.cycles);
+		out.bool(self.nmi_requested);
+		self.bus.save_state(out);
+	}
+
+	fn load_state(&mut self, input: &mut StateReader) -> Result<(), String> {
+		self.registers.A = input.u8()?;
+		self.registers.X = input.u8()?;
+		self.registers.Y = input.u8()?;
+		self.registers.P = StatusFlags::from_stack(input.u8()?);
+		self.registers.S = input.u8()?;
+		self.registers.PC = input.u16()?;
+		self.cycles = input.u64()?;
+		self.nmi_requested = input.bool()?;
+		self.bus.load_state(input)
+	}
+}
+
+#[cfg(all(test, feature = "std"))]
 mod tests {
-    use crate::{bus::Bus, program_loader::*, memory::ROM, cpu::registers::ProcessorStatusRegisterBits};
+    use crate::{bus::FlatBus, program_loader::*, cpu::status::Flag};
 
-    use super::CPU;
+    use crate::access_trace::{AccessKind, AccessKinds, SharedAccessLog};
+    use super::{decode_opcode, AddressingMode, BusAccess, CpuError, CpuState, Instructions, Register, RunEnd, StackFault, CPU};
 
-	fn initialize(f: fn(&mut [u8;65_536]) -> u8) -> CPU {
-		// Create ROM and load it with any program, for testing.
+	fn initialize(f: fn(&mut [u8;65_536]) -> u8) -> CPU<FlatBus> {
+		// Create memory image and load it with any program, for testing.
 		let mut rom_memory: [u8; 65_536] = [0;65_536];
 		f(&mut rom_memory);  // call f - load program
-		let rom: ROM = ROM {
-			rom: Box::new(rom_memory)
-		};
-		let bus = Box::new(Bus::new(rom));
-		let cpu = CPU::new(bus);
+		let mut cpu = CPU::new(FlatBus::new(&rom_memory));
+		cpu.reset();
 
 		cpu
 	}
@@ -494,6 +1324,7 @@ mod tests {
 		assert_eq!(cpu.registers.A, 0xAB);
 		cpu.clock_tick();
 		assert_eq!(cpu.bus.memory.read(0x1FE), 0xAB);
+		assert_eq!(cpu.stack_slice(), [0x8C, 0xAB]);
 		cpu.clock_tick();
 		assert_eq!(cpu.registers.A, 0xAB);
 		cpu.clock_tick();
@@ -501,18 +1332,51 @@ mod tests {
 		assert_eq!(cpu.registers.S, 0xFF);
 		cpu.clock_tick();
 		assert_eq!(cpu.registers.S, 0x00);
+		assert_eq!(cpu.stack_slice().len(), 0xFF);
 		cpu.clock_tick();
 	}
 
+	#[test]
+	fn strict_stack_test() {
+		// The third PLA pulls with nothing on the stack.
+		let mut cpu = initialize(load_program_stack);
+		cpu.set_strict_stack(true);
+		for _ in 0..6 {
+			cpu.clock_tick();
+			assert_eq!(cpu.stack_fault(), None);
+		}
+		assert!(cpu.stack_slice().is_empty());
+		cpu.clock_tick();
+		assert_eq!(cpu.stack_fault(), Some(StackFault::Underflow));
+		// Only that instruction.
+		cpu.clock_tick();
+		assert_eq!(cpu.stack_fault(), None);
+
+		// PHA, PHA, with SP at $00: the first push wraps SP around.
+		let mut cpu = initialize_at(0x0600, &[0x48, 0x48]);
+		cpu.set_strict_stack(true);
+		cpu.set_register(Register::SP, 0x00);
+		cpu.clock_tick();
+		assert_eq!(cpu.stack_fault(), Some(StackFault::Overflow));
+		assert_eq!(cpu.registers.S, 0xFF);
+		cpu.clock_tick();
+		assert_eq!(cpu.stack_fault(), None);
+
+		// Not strict: the same program, no faults.
+		let mut cpu = initialize(load_program_stack);
+		cpu.run(7).unwrap();
+		assert_eq!(cpu.stack_fault(), None);
+	}
+
 	#[test]
 	fn lda_test() {
 		let mut cpu = initialize(load_program_lda);
 
 		cpu.clock_tick();
 		assert_eq!(cpu.registers.A, 0xFF);
-		assert_eq!(cpu.registers.P.get(ProcessorStatusRegisterBits::NEGATIVE), true);
+		assert_eq!(cpu.registers.P.get(Flag::NEGATIVE), true);
 		cpu.clock_tick();
-		assert_eq!(cpu.registers.P.get(ProcessorStatusRegisterBits::ZERO), true);
+		assert_eq!(cpu.registers.P.get(Flag::ZERO), true);
 		cpu.clock_tick();
 	}
 
@@ -521,46 +1385,46 @@ mod tests {
 		let mut cpu = initialize(load_program_adc);
 
 		cpu.clock_tick();
-		assert_eq!(cpu.registers.P.get(ProcessorStatusRegisterBits::DECIMAL), false);
+		assert_eq!(cpu.registers.P.get(Flag::DECIMAL), false);
 		cpu.clock_tick();
 		assert_eq!(cpu.registers.A, 0x09);
 		cpu.clock_tick();
-		assert_eq!(cpu.registers.P.get(ProcessorStatusRegisterBits::CARRY), false);
+		assert_eq!(cpu.registers.P.get(Flag::CARRY), false);
 		cpu.clock_tick();
 		assert_eq!(cpu.registers.A, 0x0B);
 		
 		cpu.clock_tick();
-		assert_eq!(cpu.registers.P.get(ProcessorStatusRegisterBits::DECIMAL), true);
+		assert_eq!(cpu.registers.P.get(Flag::DECIMAL), true);
 		cpu.clock_tick();
 		cpu.clock_tick();
 		cpu.clock_tick();
 		assert_eq!(cpu.registers.A, 0x11);
 
 		cpu.clock_tick();
-		assert_eq!(cpu.registers.P.get(ProcessorStatusRegisterBits::DECIMAL), false);
-		assert_eq!(cpu.registers.P.get(ProcessorStatusRegisterBits::CARRY), false);
+		assert_eq!(cpu.registers.P.get(Flag::DECIMAL), false);
+		assert_eq!(cpu.registers.P.get(Flag::CARRY), false);
 		cpu.clock_tick();
 		cpu.clock_tick();
-		assert_eq!(cpu.registers.P.get(ProcessorStatusRegisterBits::CARRY), true);
+		assert_eq!(cpu.registers.P.get(Flag::CARRY), true);
 		assert_eq!(cpu.registers.A, 0x80);
 
 		cpu.clock_tick();
-		assert_eq!(cpu.registers.P.get(ProcessorStatusRegisterBits::CARRY), false);
+		assert_eq!(cpu.registers.P.get(Flag::CARRY), false);
 		cpu.clock_tick();
 		cpu.clock_tick();
-		assert_eq!(cpu.registers.P.get(ProcessorStatusRegisterBits::OVERFLOW), true);
-		assert_eq!(cpu.registers.P.get(ProcessorStatusRegisterBits::CARRY), true);
+		assert_eq!(cpu.registers.P.get(Flag::OVERFLOW), true);
+		assert_eq!(cpu.registers.P.get(Flag::CARRY), true);
 		assert_eq!(cpu.registers.A, 0x7F);
 
 
 		cpu.clock_tick();
-		assert_eq!(cpu.registers.P.get(ProcessorStatusRegisterBits::OVERFLOW), false);
+		assert_eq!(cpu.registers.P.get(Flag::OVERFLOW), false);
 		cpu.clock_tick();
 		cpu.clock_tick();
 		cpu.clock_tick();
-		assert_eq!(cpu.registers.P.get(ProcessorStatusRegisterBits::OVERFLOW), true);
-		assert_eq!(cpu.registers.P.get(ProcessorStatusRegisterBits::NEGATIVE), true);
-		assert_eq!(cpu.registers.P.get(ProcessorStatusRegisterBits::CARRY), false);
+		assert_eq!(cpu.registers.P.get(Flag::OVERFLOW), true);
+		assert_eq!(cpu.registers.P.get(Flag::NEGATIVE), true);
+		assert_eq!(cpu.registers.P.get(Flag::CARRY), false);
 		assert_eq!(cpu.registers.A, 0x80);
 
 		cpu.clock_tick();
@@ -589,14 +1453,14 @@ mod tests {
 
 		cpu.clock_tick();
 		assert_eq!(cpu.registers.X, 0xFE);
-		assert_eq!(cpu.registers.P.get(ProcessorStatusRegisterBits::NEGATIVE), true);
+		assert_eq!(cpu.registers.P.get(Flag::NEGATIVE), true);
 		cpu.clock_tick();
 		assert_eq!(cpu.registers.X, 0xFF);
-		assert_eq!(cpu.registers.P.get(ProcessorStatusRegisterBits::NEGATIVE), true);
+		assert_eq!(cpu.registers.P.get(Flag::NEGATIVE), true);
 		cpu.clock_tick();
 		assert_eq!(cpu.registers.X, 0x00);
-		assert_eq!(cpu.registers.P.get(ProcessorStatusRegisterBits::NEGATIVE), false);
-		assert_eq!(cpu.registers.P.get(ProcessorStatusRegisterBits::ZERO), true);
+		assert_eq!(cpu.registers.P.get(Flag::NEGATIVE), false);
+		assert_eq!(cpu.registers.P.get(Flag::ZERO), true);
 
 		cpu.clock_tick();
 	}
@@ -613,9 +1477,9 @@ mod tests {
 
 		cpu.clock_tick();
 		assert_eq!(cpu.bus.memory.read(0x0A), 0xFF);
-		assert_eq!(cpu.registers.P.get(ProcessorStatusRegisterBits::ZERO), false);
+		assert_eq!(cpu.registers.P.get(Flag::ZERO), false);
 		cpu.clock_tick();
-		assert_eq!(cpu.registers.P.get(ProcessorStatusRegisterBits::ZERO), true);
+		assert_eq!(cpu.registers.P.get(Flag::ZERO), true);
 		assert_eq!(cpu.bus.memory.read(0x0A), 0x00);
 
 		cpu.clock_tick();
@@ -640,7 +1504,7 @@ mod tests {
 		assert_eq!(cpu.registers.X, 0x0B);
 		cpu.clock_tick();
 		assert_eq!(cpu.registers.A, 0xFC);
-		assert_eq!(cpu.registers.P.get(ProcessorStatusRegisterBits::CARRY), true);
+		assert_eq!(cpu.registers.P.get(Flag::CARRY), true);
 
 		cpu.clock_tick();
 	}
@@ -676,10 +1540,10 @@ mod tests {
 		cpu.clock_tick();
 		assert_eq!(cpu.registers.PC, 0x0001);  // PC is at 0x0001
 
-		assert_eq!(cpu.registers.P.get(ProcessorStatusRegisterBits::DECIMAL), false);
+		assert_eq!(cpu.registers.P.get(Flag::DECIMAL), false);
 		// Execute instruction stored in 0x0001
 		cpu.clock_tick();
-		assert_eq!(cpu.registers.P.get(ProcessorStatusRegisterBits::DECIMAL), true);
+		assert_eq!(cpu.registers.P.get(Flag::DECIMAL), true);
 	}
 
 	#[test]
@@ -704,30 +1568,30 @@ mod tests {
 
 		cpu.clock_tick();
 		
-		assert_eq!(cpu.registers.P.get(ProcessorStatusRegisterBits::CARRY), false);
+		assert_eq!(cpu.registers.P.get(Flag::CARRY), false);
 		cpu.clock_tick();
-		assert_eq!(cpu.registers.P.get(ProcessorStatusRegisterBits::CARRY), true);
+		assert_eq!(cpu.registers.P.get(Flag::CARRY), true);
 
-		assert_eq!(cpu.registers.P.get(ProcessorStatusRegisterBits::ZERO), false);
+		assert_eq!(cpu.registers.P.get(Flag::ZERO), false);
 		cpu.clock_tick();
-		assert_eq!(cpu.registers.P.get(ProcessorStatusRegisterBits::ZERO), true);
+		assert_eq!(cpu.registers.P.get(Flag::ZERO), true);
 
 		cpu.clock_tick();
-		assert_eq!(cpu.registers.P.get(ProcessorStatusRegisterBits::NEGATIVE), true);
-		assert_eq!(cpu.registers.P.get(ProcessorStatusRegisterBits::ZERO), false);
-		assert_eq!(cpu.registers.P.get(ProcessorStatusRegisterBits::CARRY), false);
+		assert_eq!(cpu.registers.P.get(Flag::NEGATIVE), true);
+		assert_eq!(cpu.registers.P.get(Flag::ZERO), false);
+		assert_eq!(cpu.registers.P.get(Flag::CARRY), false);
 
 		cpu.clock_tick(); // LDA 0xAA: N=1, Z=C=0
 		cpu.clock_tick();
-		assert_eq!(cpu.registers.P.get(ProcessorStatusRegisterBits::NEGATIVE), true);
-		assert_eq!(cpu.registers.P.get(ProcessorStatusRegisterBits::ZERO), false);
-		assert_eq!(cpu.registers.P.get(ProcessorStatusRegisterBits::CARRY), true);
+		assert_eq!(cpu.registers.P.get(Flag::NEGATIVE), true);
+		assert_eq!(cpu.registers.P.get(Flag::ZERO), false);
+		assert_eq!(cpu.registers.P.get(Flag::CARRY), true);
 
 		cpu.clock_tick(); // LDA 0x00
 		cpu.clock_tick();
-		assert_eq!(cpu.registers.P.get(ProcessorStatusRegisterBits::NEGATIVE), false);
-		assert_eq!(cpu.registers.P.get(ProcessorStatusRegisterBits::ZERO), false);
-		assert_eq!(cpu.registers.P.get(ProcessorStatusRegisterBits::CARRY), false);
+		assert_eq!(cpu.registers.P.get(Flag::NEGATIVE), false);
+		assert_eq!(cpu.registers.P.get(Flag::ZERO), false);
+		assert_eq!(cpu.registers.P.get(Flag::CARRY), false);
 
 		cpu.clock_tick();
 	}
@@ -741,27 +1605,543 @@ mod tests {
 		cpu.clock_tick();
 
 		cpu.clock_tick();
-		assert_eq!(cpu.registers.P.get(ProcessorStatusRegisterBits::CARRY), false);
-		assert_eq!(cpu.registers.P.get(ProcessorStatusRegisterBits::ZERO), false);
-		assert_eq!(cpu.registers.P.get(ProcessorStatusRegisterBits::NEGATIVE), false);
+		assert_eq!(cpu.registers.P.get(Flag::CARRY), false);
+		assert_eq!(cpu.registers.P.get(Flag::ZERO), false);
+		assert_eq!(cpu.registers.P.get(Flag::NEGATIVE), false);
+		cpu.clock_tick();
+		assert_eq!(cpu.registers.P.get(Flag::CARRY), false);
+		assert_eq!(cpu.registers.P.get(Flag::ZERO), false);
+		assert_eq!(cpu.registers.P.get(Flag::NEGATIVE), true);
+
+		cpu.clock_tick();
+		cpu.clock_tick();
+		assert_eq!(cpu.registers.P.get(Flag::CARRY), true);
+		assert_eq!(cpu.registers.P.get(Flag::ZERO), false);
+		assert_eq!(cpu.registers.P.get(Flag::NEGATIVE), true);
+
+		cpu.clock_tick();
+		cpu.clock_tick();
+		assert_eq!(cpu.registers.P.get(Flag::CARRY), true);
+		assert_eq!(cpu.registers.P.get(Flag::ZERO), true);
+		assert_eq!(cpu.registers.P.get(Flag::NEGATIVE), false);
+
+		cpu.clock_tick();
+	}
+
+	#[test]
+	fn test_branch() {
+		let mut cpu = initialize(load_program_branch);
+
+		cpu.clock_tick();
+		cpu.clock_tick();
+		assert_eq!(cpu.registers.X, 0xFE);
+
+		// Branch taken on the same page: 2 cycles + 1.
+		assert_eq!(cpu.clock_tick(), 3);
+		assert_eq!(cpu.registers.PC, 0x0602);
+
+		cpu.clock_tick();
+		cpu.clock_tick();
+		cpu.clock_tick();
+		assert_eq!(cpu.registers.X, 0x00);
+
+		// Branch not taken: 2 cycles, and PC moves to the next instruction.
+		assert_eq!(cpu.clock_tick(), 2);
+		assert_eq!(cpu.registers.PC, 0x0605);
+
+		cpu.clock_tick();
+	}
+
+	/// A CPU with `program` at `addr`, and the reset vector pointing to it.
+	fn initialize_at(addr: u16, program: &[u8]) -> CPU<FlatBus> {
+		let mut rom_memory: [u8; 65_536] = [0;65_536];
+		for (i, byte) in program.iter().enumerate() {
+			rom_memory[addr.wrapping_add(i as u16) as usize] = *byte;
+		}
+		rom_memory[0xFFFC] = addr as u8;
+		rom_memory[0xFFFD] = (addr >> 8) as u8;
+		let mut cpu = CPU::new(FlatBus::new(&rom_memory));
+		cpu.reset();
+		cpu
+	}
+
+	#[test]
+	fn interrupts_test() {
+		let mut cpu = initialize_at(0x8000, &[0xEA]);
+		assert_eq!(cpu.registers.PC, 0x8000);
+		assert_eq!(cpu.cycles, 7);
+		// NMI vector $9000, IRQ vector $A000.
+		cpu.bus.memory.write(0xFFFA, 0x00);
+		cpu.bus.memory.write(0xFFFB, 0x90);
+		cpu.bus.memory.write(0xFFFE, 0x00);
+		cpu.bus.memory.write(0xFFFF, 0xA0);
+
+		// The I flag is set by the reset, so the IRQ waits.
+		assert!(!cpu.irq());
+		assert_eq!(cpu.registers.PC, 0x8000);
+		assert_eq!(cpu.cycles, 7);
+
+		cpu.registers.P.set(Flag::INTERRUPT_DISABLE, false);
+		cpu.registers.P.set(Flag::CARRY, true);
+		assert!(cpu.irq());
+		assert_eq!(cpu.registers.PC, 0xA000);
+		assert_eq!(cpu.cycles, 14);
+		assert!(cpu.registers.P.get(Flag::INTERRUPT_DISABLE));
+		// Return address high, low, then P with B clear (bit 4) and the unused bit set (bit 5).
+		assert_eq!(cpu.stack_slice(), [0x80, 0x00, 0x21]);
+
+		// The NMI doesn't care about the I flag.
+		assert_eq!(cpu.nmi(), 7);
+		assert_eq!(cpu.registers.PC, 0x9000);
+		assert_eq!(cpu.cycles, 21);
+		assert_eq!(cpu.stack_slice(), [0x80, 0x00, 0x21, 0xA0, 0x00, 0x25]);
+	}
+
+	#[test]
+	fn test_branch_offsets() {
+		// (branch address, offset, target, cycles). Z is clear after reset, so BNE is always taken.
+		// The offset is from the next instruction (branch + 2), and the target wraps around the address space.
+		let cases = [
+			(0x8010, 0x7F, 0x8091, 3),	// +127, same page
+			(0x80F0, 0x7F, 0x8171, 4),	// +127, next page
+			(0x8090, 0x80, 0x8012, 3),	// -128, same page
+			(0x8010, 0x80, 0x7F92, 4),	// -128, previous page
+			(0x80FE, 0x00, 0x8100, 3),	// +0, the next instruction is already on the next page
+			(0x80FD, 0xFF, 0x80FE, 3),	// -1, back to the operand
+			(0xFFF0, 0x7F, 0x0071, 4),	// +127, wraps past $FFFF
+			(0x0002, 0x80, 0xFF84, 4),	// -128, wraps below $0000
+		];
+		for (addr, offset, target, cycles) in cases {
+			let mut cpu = initialize_at(addr, &[0xD0, offset]);
+			assert_eq!(cpu.clock_tick(), cycles, "BNE at {:#06X} with offset {:#04X}", addr, offset);
+			assert_eq!(cpu.registers.PC, target, "BNE at {:#06X} with offset {:#04X}", addr, offset);
+		}
+
+		// Not taken at the end of the address space: the next instruction wraps to $0000.
+		let mut cpu = initialize_at(0xFFFE, &[0xF0, 0x10]);
+		assert_eq!(cpu.clock_tick(), 2);
+		assert_eq!(cpu.registers.PC, 0x0000);
+	}
+
+	#[test]
+	fn test_jmp_indirect_page_wrap() {
+		// JMP ($02FF): the MSB is read from $0200, not $0300.
+		let mut cpu = initialize_at(0x8000, &[0x6C, 0xFF, 0x02]);
+		cpu.bus.memory.write(0x02FF, 0x34);
+		cpu.bus.memory.write(0x0200, 0x12);
+		cpu.bus.memory.write(0x0300, 0x56);
+		cpu.clock_tick();
+		assert_eq!(cpu.registers.PC, 0x1234);
+	}
+
+	#[test]
+	fn test_tolower() {
+		let mut cpu = initialize(load_program_tolower);
+
+		// The program ends at the first empty byte (BRK).
+		while cpu.bus.memory.read(cpu.registers.PC) != 0x00 {
+			cpu.clock_tick();
+		}
+
+		let output: Vec<u8> = (TOLOWER_OUTPUT..TOLOWER_OUTPUT + 19).map(|addr| cpu.bus.memory.read(addr)).collect();
+		assert_eq!(output, b"hello, world! 6502\0");
+	}
+
+	#[test]
+	fn test_helloworld() {
+		let mut cpu = initialize(load_program_helloworld);
+
+		for _ in 0..6 {
+			cpu.clock_tick();
+		}
+		assert_eq!(cpu.bus.memory.read(0x0200), 0x01);
+		assert_eq!(cpu.bus.memory.read(0x0201), 0x05);
+		assert_eq!(cpu.bus.memory.read(0x0202), 0x08);
+	}
+
+	#[test]
+	fn test_indirect_x_y() {
+		// LDX #$04, LDA ($0C,X), LDY #$10, STA ($FF),Y
+		let mut cpu = initialize_at(0x8000, &[0xA2, 0x04, 0xA1, 0x0C, 0xA0, 0x10, 0x91, 0xFF]);
+		cpu.bus.memory.write(0x0010, 0x34);
+		cpu.bus.memory.write(0x0011, 0x12);
+		cpu.bus.memory.write(0x1234, 0xAB);
+		// The pointer at $FF wraps around the zero page: its MSB is at $00.
+		cpu.bus.memory.write(0x00FF, 0xF8);
+		cpu.bus.memory.write(0x0000, 0x20);
+
+		cpu.clock_tick();
+		assert_eq!(cpu.clock_tick(), 6);
+		assert_eq!(cpu.registers.A, 0xAB);
+		cpu.clock_tick();
+		assert_eq!(cpu.clock_tick(), 6);
+		assert_eq!(cpu.bus.memory.read(0x2108), 0xAB);
+
+		// LDA ($10),Y crosses a page: $1234 + $F0 = $1324, an oops cycle.
+		let mut cpu = initialize_at(0x8000, &[0xA0, 0xF0, 0xB1, 0x10]);
+		cpu.bus.memory.write(0x0010, 0x34);
+		cpu.bus.memory.write(0x0011, 0x12);
+		cpu.bus.memory.write(0x1324, 0xCD);
+		cpu.clock_tick();
+		assert_eq!(cpu.clock_tick(), 6);
+		assert_eq!(cpu.registers.A, 0xCD);
+	}
+
+	#[test]
+	fn test_jsr_rts() {
+		// JSR $8010, INX, ... $8010: INX, RTS
+		let mut program = [0xEA; 0x12];
+		program[..4].copy_from_slice(&[0x20, 0x10, 0x80, 0xE8]);
+		program[0x10..].copy_from_slice(&[0xE8, 0x60]);
+		let mut cpu = initialize_at(0x8000, &program);
+
+		assert_eq!(cpu.clock_tick(), 6);
+		assert_eq!(cpu.registers.PC, 0x8010);
+		// The return address minus 1: the last byte of the JSR.
+		assert_eq!(cpu.registers.S, 0xFD);
+		assert_eq!(cpu.bus.memory.read(0x01FF), 0x80);
+		assert_eq!(cpu.bus.memory.read(0x01FE), 0x02);
+
+		cpu.clock_tick();
+		assert_eq!(cpu.clock_tick(), 6);
+		assert_eq!(cpu.registers.PC, 0x8003);
+		assert_eq!(cpu.registers.S, 0xFF);
+		cpu.clock_tick();
+		assert_eq!(cpu.registers.X, 2);
+	}
+
+	#[test]
+	fn test_sbc() {
+		// SEC, LDA #$50, SBC #$F0: 0x50 - 0xF0 borrows, C=0. No signed overflow.
+		let mut cpu = initialize_at(0x8000, &[0x38, 0xA9, 0x50, 0xE9, 0xF0, 0xE9, 0xB0, 0x38, 0xE9, 0x60]);
+		cpu.clock_tick();
+		cpu.clock_tick();
+		cpu.clock_tick();
+		assert_eq!(cpu.registers.A, 0x60);
+		assert_eq!(cpu.registers.P.get(Flag::CARRY), false);
+		assert_eq!(cpu.registers.P.get(Flag::OVERFLOW), false);
+
+		// SBC #$B0 with the borrow: 0x60 - 0xB0 - 1 = 0xAF. Positive - negative = negative, V=1.
+		cpu.clock_tick();
+		assert_eq!(cpu.registers.A, 0xAF);
+		assert_eq!(cpu.registers.P.get(Flag::OVERFLOW), true);
+		assert_eq!(cpu.registers.P.get(Flag::NEGATIVE), true);
+
+		// SEC, SBC #$60: 0xAF - 0x60 = 0x4F, no borrow, C=1. Negative - positive = positive, V=1.
+		cpu.clock_tick();
 		cpu.clock_tick();
-		assert_eq!(cpu.registers.P.get(ProcessorStatusRegisterBits::CARRY), false);
-		assert_eq!(cpu.registers.P.get(ProcessorStatusRegisterBits::ZERO), false);
-		assert_eq!(cpu.registers.P.get(ProcessorStatusRegisterBits::NEGATIVE), true);
+		assert_eq!(cpu.registers.A, 0x4F);
+		assert_eq!(cpu.registers.P.get(Flag::CARRY), true);
+		assert_eq!(cpu.registers.P.get(Flag::OVERFLOW), true);
+	}
 
+	#[test]
+	fn test_and_lsr_dec() {
+		// LDA #$F3, AND #$0F, LSR A, LSR $10, DEC $11, LDX #$01, DEX, TXA
+		let mut cpu = initialize_at(0x8000, &[0xA9, 0xF3, 0x29, 0x0F, 0x4A, 0x46, 0x10, 0xC6, 0x11, 0xA2, 0x01, 0xCA, 0x8A]);
+		cpu.bus.memory.write(0x0010, 0x80);
+
+		cpu.clock_tick();
 		cpu.clock_tick();
+		assert_eq!(cpu.registers.A, 0x03);
+
+		assert_eq!(cpu.clock_tick(), 2);
+		assert_eq!(cpu.registers.A, 0x01);
+		assert_eq!(cpu.registers.P.get(Flag::CARRY), true);
+
+		assert_eq!(cpu.clock_tick(), 5);
+		assert_eq!(cpu.bus.memory.read(0x0010), 0x40);
+		assert_eq!(cpu.registers.A, 0x01);
+		assert_eq!(cpu.registers.P.get(Flag::CARRY), false);
+
+		cpu.clock_tick();
+		assert_eq!(cpu.bus.memory.read(0x0011), 0xFF);
+		assert_eq!(cpu.registers.P.get(Flag::NEGATIVE), true);
+
+		cpu.clock_tick();
+		cpu.clock_tick();
+		assert_eq!(cpu.registers.X, 0);
+		assert_eq!(cpu.registers.P.get(Flag::ZERO), true);
+		cpu.clock_tick();
+		assert_eq!(cpu.registers.A, 0);
+	}
+
+	#[test]
+	fn test_shifts_and_rotates() {
+		// SEC, LDA #$81, ASL A, ROL A, ROR $10, ASL $10, ROL $11, ROR A
+		let mut cpu = initialize_at(0x8000, &[0x38, 0xA9, 0x81, 0x0A, 0x2A, 0x66, 0x10, 0x06, 0x10, 0x26, 0x11, 0x6A]);
+		cpu.bus.memory.write(0x0010, 0x02);
+		cpu.bus.memory.write(0x0011, 0x40);
+		cpu.clock_tick();
+		cpu.clock_tick();
+
+		assert_eq!(cpu.clock_tick(), 2);
+		assert_eq!((cpu.registers.A, cpu.registers.P.get(Flag::CARRY)), (0x02, true));
 		cpu.clock_tick();
-		assert_eq!(cpu.registers.P.get(ProcessorStatusRegisterBits::CARRY), true);
-		assert_eq!(cpu.registers.P.get(ProcessorStatusRegisterBits::ZERO), false);
-		assert_eq!(cpu.registers.P.get(ProcessorStatusRegisterBits::NEGATIVE), true);
+		assert_eq!((cpu.registers.A, cpu.registers.P.get(Flag::CARRY)), (0x05, false));
 
+		assert_eq!(cpu.clock_tick(), 5);
+		assert_eq!((cpu.bus.memory.read(0x0010), cpu.registers.P.get(Flag::CARRY)), (0x01, false));
 		cpu.clock_tick();
+		assert_eq!((cpu.bus.memory.read(0x0010), cpu.registers.P.get(Flag::CARRY)), (0x02, false));
 		cpu.clock_tick();
-		assert_eq!(cpu.registers.P.get(ProcessorStatusRegisterBits::CARRY), true);
-		assert_eq!(cpu.registers.P.get(ProcessorStatusRegisterBits::ZERO), true);
-		assert_eq!(cpu.registers.P.get(ProcessorStatusRegisterBits::NEGATIVE), false);
+		assert_eq!(cpu.bus.memory.read(0x0011), 0x80);
+		assert_eq!(cpu.registers.P.get(Flag::NEGATIVE), true);
 
 		cpu.clock_tick();
+		assert_eq!((cpu.registers.A, cpu.registers.P.get(Flag::CARRY)), (0x02, true));
+		assert_eq!(cpu.registers.P.get(Flag::NEGATIVE), false);
 	}
 
-}
\ No newline at end of file
+	#[test]
+	fn test_set_state() {
+		let mut cpu = initialize_at(0x8000, &[0xE8]);
+		cpu.set_state(&CpuState { pc: 0x8000, a: 1, x: 0x41, y: 3, p: 0xFF, s: 0xF0, cycles: 100 });
+		assert_eq!(cpu.state(), CpuState { pc: 0x8000, a: 1, x: 0x41, y: 3, p: 0xEF, s: 0xF0, cycles: 100 });
+		cpu.clock_tick();
+		assert_eq!(cpu.state().x, 0x42);
+		assert_eq!(cpu.cycles(), 102);
+	}
+
+	#[test]
+	fn test_set_register_and_flag() {
+		/*
+		LDA #$00 	; Z = 1
+		BEQ taken
+		LDX #$01
+		taken:
+		LDX #$02
+		*/
+		let program = [0xA9, 0x00, 0xF0, 0x02, 0xA2, 0x01, 0xA2, 0x02];
+		let mut cpu = initialize_at(0x8000, &program);
+		cpu.clock_tick();
+		cpu.clock_tick();
+		assert_eq!(cpu.state().pc, 0x8006);
+
+		// Stopped before the BEQ, Z cleared: the branch is not taken.
+		let mut cpu = initialize_at(0x8000, &program);
+		cpu.clock_tick();
+		assert_eq!(cpu.state().pc, 0x8002);
+		cpu.set_flag(Flag::ZERO, false);
+		cpu.clock_tick();
+		assert_eq!(cpu.state().pc, 0x8004);
+
+		// Back to the BEQ, with A = 0 but Z still clear: A doesn't change the flags.
+		cpu.set_register(Register::PC, 0x8002);
+		cpu.set_register(Register::A, 0x100);
+		assert_eq!(cpu.state().a, 0);
+		cpu.clock_tick();
+		assert_eq!(cpu.state().pc, 0x8004);
+
+		// P ignores B.
+		cpu.set_register(Register::P, 0xFF);
+		assert_eq!(cpu.state().p, 0xEF);
+		cpu.set_flag(Flag::BREAK, true);
+		assert_eq!(cpu.state().p, 0xEF);
+	}
+
+	#[test]
+	fn test_dispatch_table() {
+		// The table is decode_opcode, done at compile time. Only the unimplemented instructions are missing.
+		let mut missing = 0;
+		for opcode in 0..=255u8 {
+			match (&CPU::<FlatBus>::DISPATCH[opcode as usize], decode_opcode(opcode)) {
+				(Some(dispatch), Some((instr, addrmode, bytes, cycles, oops_cycle))) => {
+					assert_eq!((dispatch.instr, dispatch.addrmode, dispatch.bytes, dispatch.cycles, dispatch.oops_cycle), (instr, addrmode, bytes, cycles, oops_cycle), "opcode {:#04X}", opcode);
+					assert_eq!(dispatch.changes_pc, CPU::<FlatBus>::changes_pc(&instr));
+				}
+				(Some(_), None) => panic!("Illegal opcode {:#04X} is in the table", opcode),
+				(None, Some((instr, _, _, _, _))) => {
+					assert!(CPU::<FlatBus>::handler(&instr).is_none(), "opcode {:#04X}", opcode);
+					missing += 1;
+				}
+				(None, None) => {}
+			}
+		}
+		assert_eq!(missing, 22);
+	}
+
+	#[test]
+	fn test_errors() {
+		// LDA #$01, then the illegal $02.
+		let mut cpu = initialize_at(0x8000, &[0xA9, 0x01, 0x02]);
+		assert_eq!(cpu.run(10), Err(CpuError::IllegalOpcode { pc: 0x8002, opcode: 0x02 }));
+		// The CPU stops before the instruction.
+		assert_eq!(cpu.state(), CpuState { pc: 0x8002, a: 0x01, x: 0, y: 0, p: cpu.state().p, s: 0xFF, cycles: 7 + 2 });
+		assert_eq!(cpu.step(), Err(CpuError::IllegalOpcode { pc: 0x8002, opcode: 0x02 }));
+
+		// TAX
+		let mut cpu = initialize_at(0x8000, &[0xAA]);
+		assert_eq!(cpu.step(), Err(CpuError::Unimplemented { pc: 0x8000, opcode: 0xAA }));
+		// Only the reset's.
+		assert_eq!(cpu.cycles(), 7);
+
+		// JMP $8000 never ends, but `run` does.
+		let mut cpu = initialize_at(0x8000, &[0x4C, 0x00, 0x80]);
+		assert_eq!(cpu.run(1000), Ok(3000));
+	}
+
+	#[test]
+	fn test_run_until_jam() {
+		// INC $10, INC $10, JMP *
+		let mut cpu = initialize_at(0x8000, &[0xE6, 0x10, 0xE6, 0x10, 0x4C, 0x04, 0x80]);
+		assert_eq!(cpu.run_until_jam(1000), Ok(RunEnd::Jammed));
+		assert_eq!(cpu.registers.PC, 0x8004);
+		assert_eq!(cpu.bus.memory.read(0x10), 2);
+		// The jam is detected after it runs once. The reset took 7.
+		assert_eq!(cpu.cycles(), 7 + 13);
+
+		// loop: INC $10, JMP loop. Memory changes, so it's not jammed.
+		let mut cpu = initialize_at(0x8000, &[0xE6, 0x10, 0x4C, 0x00, 0x80]);
+		assert_eq!(cpu.run_until_jam(100), Ok(RunEnd::BudgetExhausted));
+		// The last INC starts at cycle 96 of the budget, which starts after the reset.
+		assert_eq!(cpu.cycles(), 7 + 101);
+
+		// BRK to $0000, then the illegal $02.
+		let mut cpu = initialize_at(0x8000, &[0x00]);
+		cpu.bus.memory.write(0x0000, 0x02);
+		assert_eq!(cpu.run_until_jam(100), Err(CpuError::IllegalOpcode { pc: 0x0000, opcode: 0x02 }));
+	}
+
+	#[test]
+	fn test_random_programs() {
+		// Memory full of random bytes, so every opcode runs with all kinds of operands and registers. The CPU may
+		// stop with an error, but it never panics, and every instruction takes 2 to 7 cycles.
+		let mut seed: u32 = 0x1234_5678;
+		for _ in 0..200 {
+			let mut rom_memory: [u8; 65_536] = [0; 65_536];
+			for byte in rom_memory.iter_mut() {
+				// xorshift32
+				seed ^= seed << 13;
+				seed ^= seed >> 17;
+				seed ^= seed << 5;
+				*byte = seed as u8;
+			}
+			let mut cpu = CPU::new(FlatBus::new(&rom_memory));
+			cpu.reset();
+			for _ in 0..1000 {
+				let before = cpu.state();
+				match cpu.step() {
+					Ok(cycles) => assert!((2..=7).contains(&cycles), "{} cycles at {}", cycles, before),
+					Err(_) => {
+						assert_eq!(cpu.state(), before);
+						break;
+					}
+				}
+			}
+		}
+	}
+
+	#[test]
+	fn test_step_with_effects() {
+		// LDX #$02, INC $10,X
+		let mut cpu = initialize_at(0x8000, &[0xA2, 0x02, 0xF6, 0x10]);
+		cpu.step_with_effects().unwrap();
+		let effects = cpu.step_with_effects().unwrap();
+		assert_eq!(effects.opcode, Some(0xF6));
+		assert_eq!(effects.instruction(), Some((Instructions::INC, AddressingMode::ZEROPAGEX)));
+		assert_eq!(effects.before.pc, 0x8002);
+		assert_eq!(effects.after.pc, 0x8004);
+		assert_eq!(effects.cycles, 6);
+		assert_eq!(effects.after.cycles - effects.before.cycles, 6);
+		// The operand is read once, and written back.
+		assert_eq!(effects.accesses(), &[
+			BusAccess::read(0x8002, 0xF6),
+			BusAccess::read(0x8003, 0x10),
+			BusAccess::read(0x0012, 0x00),
+			BusAccess::write(0x0012, 0x01),
+		]);
+		// Nothing is recorded outside of `step_with_effects`.
+		cpu.reset();
+		cpu.step().unwrap();
+		assert_eq!(cpu.step_with_effects().unwrap().accesses().len(), 4);
+	}
+
+	#[test]
+	fn dummy_read_test() {
+		/*
+		LDX #$20
+		LDY #$01
+		STA $20F0,X 	; Crosses to $2110
+		STA $2000,Y 	; Doesn't cross
+		STA ($10),Y 	; $10 points at $20FF, crosses to $2100
+		*/
+		let program = [0xA2, 0x20, 0xA0, 0x01, 0x9D, 0xF0, 0x20, 0x99, 0x00, 0x20, 0x91, 0x10];
+		let run = |accurate: bool| {
+			let mut cpu = initialize_at(0x8000, &program);
+			cpu.bus.memory.write(0x10, 0xFF);
+			cpu.bus.memory.write(0x11, 0x20);
+			cpu.set_cycle_accurate(accurate);
+			let log = SharedAccessLog::new();
+			cpu.bus_mut().trace_accesses(0x2000..=0x21FF, AccessKinds::ALL, Box::new(log.clone()));
+			cpu.run(5).unwrap();
+			log.accesses().iter().map(|access| (access.pc, access.kind, access.addr)).collect::<Vec<_>>()
+		};
+
+		assert_eq!(run(true), [
+			(0x8004, AccessKind::Read, 0x2010),
+			(0x8004, AccessKind::Write, 0x2110),
+			(0x8007, AccessKind::Write, 0x2001),
+			(0x800A, AccessKind::Read, 0x2000),
+			(0x800A, AccessKind::Write, 0x2100),
+		]);
+		assert_eq!(run(false), [
+			(0x8004, AccessKind::Write, 0x2110),
+			(0x8007, AccessKind::Write, 0x2001),
+			(0x800A, AccessKind::Write, 0x2100),
+		]);
+	}
+
+	#[test]
+	fn dummy_write_test() {
+		/*
+		INC $2000
+		ROR $2001
+		*/
+		let program = [0xEE, 0x00, 0x20, 0x6E, 0x01, 0x20];
+		let run = |accurate: bool| {
+			let mut cpu = initialize_at(0x8000, &program);
+			cpu.bus.memory.write(0x2000, 0x10);
+			cpu.bus.memory.write(0x2001, 0x81);
+			cpu.set_cycle_accurate(accurate);
+			let log = SharedAccessLog::new();
+			cpu.bus_mut().trace_accesses(0x2000..=0x2001, AccessKinds::WRITES, Box::new(log.clone()));
+			cpu.run(2).unwrap();
+			log.accesses().iter().map(|access| (access.addr, access.value)).collect::<Vec<_>>()
+		};
+
+		// What was read, then the result.
+		assert_eq!(run(true), [(0x2000, 0x10), (0x2000, 0x11), (0x2001, 0x81), (0x2001, 0x40)]);
+		assert_eq!(run(false), [(0x2000, 0x11), (0x2001, 0x40)]);
+	}
+
+	#[test]
+	fn test_step_with_effects_jsr_rts() {
+		// JSR $8010, ... $8010: RTS
+		let mut program = [0xEA; 0x11];
+		program[..3].copy_from_slice(&[0x20, 0x10, 0x80]);
+		program[0x10] = 0x60;
+		let mut cpu = initialize_at(0x8000, &program);
+
+		let jsr = cpu.step_with_effects().unwrap();
+		assert_eq!(jsr.opcode, Some(0x20));
+		assert_eq!(jsr.after.pc, 0x8010);
+		assert_eq!(jsr.accesses(), &[
+			BusAccess::read(0x8000, 0x20),
+			BusAccess::read(0x8001, 0x10),
+			BusAccess::read(0x8002, 0x80),
+			BusAccess::write(0x01FF, 0x80),
+			BusAccess::write(0x01FE, 0x02),
+		]);
+
+		let rts = cpu.step_with_effects().unwrap();
+		assert_eq!(rts.opcode, Some(0x60));
+		assert_eq!(rts.after.pc, 0x8003);
+		assert_eq!(rts.cycles, 6);
+		assert_eq!(rts.accesses(), &[
+			BusAccess::read(0x8010, 0x60),
+			BusAccess::read(0x01FE, 0x02),
+			BusAccess::read(0x01FF, 0x80),
+		]);
+	}
+}
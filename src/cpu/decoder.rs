@@ -1,11 +1,10 @@
-/// The decoder's purpose is to take OPCODE and translate it to the appropriate instruction.
+// The decoder's purpose is to take OPCODE and translate it to the appropriate instruction.
 // https://www.masswerk.at/6502/6502_instruction_set.html
 
-use log::error;
-use std::fmt;
+use core::fmt;
 
 /// All possible CPU instructions. This is written like in 6502 assembler.
-#[derive(PartialEq, Debug)]
+#[derive(Clone, Copy, PartialEq, Debug)]
 pub enum Instructions {
 	ADC, // add with carry
 	AND, // and (with accumulator)
@@ -80,7 +79,7 @@ pub enum Instructions {
 /// | INDIRECTX |  |
 /// | INDIRECTY |  |
 /// | IMMEDIATE | Data defined in next byte after opcode |
-#[derive(PartialEq, Debug)]
+#[derive(Clone, Copy, PartialEq, Debug)]
 pub enum AddressingMode {
 	IMPLIED, 		// 1 byte
 	ABSOLUTE, 		// 3 bytes
@@ -110,6 +109,7 @@ pub enum AddressingMode {
 /// | BranchOccursOn     | add 2 to cycles if branch occurs on same page <br> or add 2 to cycles if branch occurs to different page |
 /// 
 /// 
+#[derive(Clone, Copy, PartialEq, Debug)]
 pub enum OopsCycle {
 	NONE,
 	PageBoundryCrossed,
@@ -126,165 +126,161 @@ impl fmt::Display for OopsCycle {
 }
 
 /// Decode CPU instruction, probably from ROM or something. \
-/// Returns the Instruction (like in assembly), Addressing Mode, Bytes, Cycles.
-pub fn decode_opcode(opcode: u8) -> (Instructions, AddressingMode, u8, u8, OopsCycle) {
+/// Returns the Instruction (like in assembly), Addressing Mode, Bytes, Cycles. None for illegal opcodes.
+/// It's const, so the CPU builds its dispatch table from it at compile time. The CPU itself doesn't decode.
+pub const fn decode_opcode(opcode: u8) -> Option<(Instructions, AddressingMode, u8, u8, OopsCycle)> {
 	match opcode {
-		0x00 => (Instructions::BRK, AddressingMode::IMPLIED, 		1, 2, OopsCycle::NONE),
-		0x01 => (Instructions::ORA, AddressingMode::INDIRECTX, 		2, 6, OopsCycle::NONE),
-		0x05 => (Instructions::ORA, AddressingMode::ZEROPAGE, 		2, 3, OopsCycle::NONE),
-		0x06 => (Instructions::ASL, AddressingMode::ZEROPAGE, 		2, 5, OopsCycle::NONE),
-		0x08 => (Instructions::PHP, AddressingMode::IMPLIED, 		1, 3, OopsCycle::NONE),
-		0x09 => (Instructions::ORA, AddressingMode::IMMEDIATE, 		2, 2, OopsCycle::NONE),
-		0x0A => (Instructions::ASL, AddressingMode::ACCUMULATOR, 	1, 2, OopsCycle::NONE),
-		0x0D => (Instructions::ORA, AddressingMode::ABSOLUTE, 		3, 4, OopsCycle::NONE),
-		0x0E => (Instructions::ASL, AddressingMode::ABSOLUTE, 		3, 6, OopsCycle::NONE),
-		0x10 => (Instructions::BPL, AddressingMode::RELATIVE, 		2, 2, OopsCycle::BranchOccursOn),
-		0x11 => (Instructions::ORA, AddressingMode::INDIRECTY, 		2, 5, OopsCycle::PageBoundryCrossed),
-		0x15 => (Instructions::ORA, AddressingMode::ZEROPAGEX, 		2, 4, OopsCycle::NONE),
-		0x16 => (Instructions::ASL, AddressingMode::ZEROPAGEX, 		2, 6, OopsCycle::NONE),
-		0x18 => (Instructions::CLC, AddressingMode::IMPLIED, 		1, 2, OopsCycle::NONE),
-		0x19 => (Instructions::ORA, AddressingMode::ABSOLUTEY, 		3, 4, OopsCycle::PageBoundryCrossed),
-		0x1D => (Instructions::ORA, AddressingMode::ABSOLUTEX, 		3, 4, OopsCycle::PageBoundryCrossed),
-		0x1E => (Instructions::ASL, AddressingMode::ABSOLUTEX, 		3, 7, OopsCycle::NONE),
-		0x20 => (Instructions::JSR, AddressingMode::ABSOLUTE, 		3, 6, OopsCycle::NONE),
-		0x21 => (Instructions::AND, AddressingMode::INDIRECTX, 		2, 6, OopsCycle::NONE),
-		0x24 => (Instructions::BIT, AddressingMode::ZEROPAGE, 		2, 3, OopsCycle::NONE),
-		0x25 => (Instructions::AND, AddressingMode::ZEROPAGE, 		2, 3, OopsCycle::NONE),
-		0x26 => (Instructions::ROL, AddressingMode::ZEROPAGE, 		2, 5, OopsCycle::NONE),
-		0x28 => (Instructions::PLP, AddressingMode::IMPLIED, 		1, 4, OopsCycle::NONE),
-		0x29 => (Instructions::AND, AddressingMode::IMMEDIATE, 		2, 2, OopsCycle::NONE),
-		0x2A => (Instructions::ROL, AddressingMode::ACCUMULATOR, 	1, 2, OopsCycle::NONE),
-		0x2C => (Instructions::BIT, AddressingMode::ABSOLUTE, 		3, 4, OopsCycle::NONE),
-		0x2D => (Instructions::AND, AddressingMode::ABSOLUTE, 		3, 4, OopsCycle::NONE),
-		0x2E => (Instructions::ROL, AddressingMode::ABSOLUTE, 		3, 6, OopsCycle::NONE),
-		0x30 => (Instructions::BMI, AddressingMode::RELATIVE, 		2, 2, OopsCycle::BranchOccursOn),
-		0x31 => (Instructions::AND, AddressingMode::INDIRECTY, 		2, 5, OopsCycle::PageBoundryCrossed),
-		0x35 => (Instructions::AND, AddressingMode::ZEROPAGEX, 		2, 4, OopsCycle::NONE),
-		0x36 => (Instructions::ROL, AddressingMode::ZEROPAGEX, 		2, 6, OopsCycle::NONE),
-		0x38 => (Instructions::SEC, AddressingMode::IMPLIED, 		1, 2, OopsCycle::NONE),
-		0x39 => (Instructions::AND, AddressingMode::ABSOLUTEY, 		3, 4, OopsCycle::PageBoundryCrossed),
-		0x3D => (Instructions::AND, AddressingMode::ABSOLUTEX, 		3, 4, OopsCycle::PageBoundryCrossed),
-		0x3E => (Instructions::ROL, AddressingMode::ABSOLUTEX, 		3, 7, OopsCycle::NONE),
-		0x40 => (Instructions::RTI, AddressingMode::IMMEDIATE, 		1, 6, OopsCycle::NONE),
-		0x41 => (Instructions::EOR, AddressingMode::INDIRECTX, 		2, 6, OopsCycle::NONE),
-		0x45 => (Instructions::EOR, AddressingMode::ZEROPAGE, 		2, 3, OopsCycle::NONE),
-		0x46 => (Instructions::LSR, AddressingMode::ZEROPAGE, 		2, 5, OopsCycle::NONE),
-		0x48 => (Instructions::PHA, AddressingMode::IMPLIED, 		1, 3, OopsCycle::NONE),
-		0x49 => (Instructions::EOR, AddressingMode::IMMEDIATE, 		2, 2, OopsCycle::NONE),
-		0x4A => (Instructions::LSR, AddressingMode::ACCUMULATOR, 	1, 2, OopsCycle::NONE),
-		0x4C => (Instructions::JMP, AddressingMode::ABSOLUTE, 		3, 3, OopsCycle::NONE),
-		0x4D => (Instructions::EOR, AddressingMode::ABSOLUTE, 		3, 4, OopsCycle::NONE),
-		0x4E => (Instructions::LSR, AddressingMode::ABSOLUTE, 		3, 6, OopsCycle::NONE),
-		0x50 => (Instructions::BVC, AddressingMode::RELATIVE, 		2, 2, OopsCycle::BranchOccursOn),
-		0x51 => (Instructions::EOR, AddressingMode::INDIRECTY, 		2, 5, OopsCycle::BranchOccursOn),
-		0x55 => (Instructions::EOR, AddressingMode::ZEROPAGEX, 		2, 4, OopsCycle::NONE),
-		0x56 => (Instructions::LSR, AddressingMode::ZEROPAGEX, 		2, 6, OopsCycle::NONE),
-		0x58 => (Instructions::CLI, AddressingMode::IMPLIED, 		1, 2, OopsCycle::NONE),
-		0x59 => (Instructions::EOR, AddressingMode::ABSOLUTEY, 		3, 4, OopsCycle::PageBoundryCrossed),
-		0x5D => (Instructions::EOR, AddressingMode::ABSOLUTEX, 		3, 4, OopsCycle::PageBoundryCrossed),
-		0x5E => (Instructions::LSR, AddressingMode::ABSOLUTEX, 		3, 7, OopsCycle::NONE),
-		0x60 => (Instructions::RTS, AddressingMode::IMPLIED, 		1, 6, OopsCycle::NONE),
-		0x61 => (Instructions::ADC, AddressingMode::INDIRECTX, 		2, 6, OopsCycle::NONE),
-		0x65 => (Instructions::ADC, AddressingMode::ZEROPAGE, 		2, 3, OopsCycle::NONE),
-		0x66 => (Instructions::ROR, AddressingMode::ZEROPAGE, 		2, 5, OopsCycle::NONE),
-		0x68 => (Instructions::PLA, AddressingMode::IMPLIED, 		1, 4, OopsCycle::NONE),
-		0x69 => (Instructions::ADC, AddressingMode::IMMEDIATE, 		2, 2, OopsCycle::NONE),
-		0x6A => (Instructions::ROR, AddressingMode::ACCUMULATOR, 	1, 2, OopsCycle::NONE),
-		0x6C => (Instructions::JMP, AddressingMode::INDIRECT, 		3, 5, OopsCycle::NONE),
-		0x6D => (Instructions::ADC, AddressingMode::ABSOLUTE, 		3, 4, OopsCycle::NONE),
-		0x6E => (Instructions::ROR, AddressingMode::ABSOLUTE, 		3, 6, OopsCycle::NONE),
-		0x70 => (Instructions::BVS, AddressingMode::RELATIVE, 		2, 2, OopsCycle::BranchOccursOn),
-		0x71 => (Instructions::ADC, AddressingMode::INDIRECTY, 		2, 5, OopsCycle::PageBoundryCrossed),
-		0x75 => (Instructions::ADC, AddressingMode::ZEROPAGEX, 		2, 4, OopsCycle::NONE),
-		0x76 => (Instructions::ROR, AddressingMode::ZEROPAGEX, 		2, 6, OopsCycle::NONE),
-		0x78 => (Instructions::SEI, AddressingMode::IMPLIED, 		1, 2, OopsCycle::NONE),
-		0x79 => (Instructions::ADC, AddressingMode::ABSOLUTEY, 		3, 4, OopsCycle::PageBoundryCrossed),
-		0x7D => (Instructions::ADC, AddressingMode::ABSOLUTEX, 		3, 4, OopsCycle::PageBoundryCrossed),
-		0x7E => (Instructions::ROR, AddressingMode::ABSOLUTEX, 		3, 7, OopsCycle::NONE),
-		0x81 => (Instructions::STA, AddressingMode::INDIRECTX, 		2, 6, OopsCycle::NONE),
-		0x84 => (Instructions::STY, AddressingMode::ZEROPAGE, 		2, 3, OopsCycle::NONE),
-		0x85 => (Instructions::STA, AddressingMode::ZEROPAGE, 		2, 3, OopsCycle::NONE),
-		0x86 => (Instructions::STX, AddressingMode::ZEROPAGE, 		2, 3, OopsCycle::NONE),
-		0x88 => (Instructions::DEY, AddressingMode::IMPLIED, 		1, 2, OopsCycle::NONE),
-		0x8A => (Instructions::TXA, AddressingMode::IMPLIED, 		1, 2, OopsCycle::NONE),
-		0x8C => (Instructions::STY, AddressingMode::ABSOLUTE, 		3, 4, OopsCycle::NONE),
-		0x8D => (Instructions::STA, AddressingMode::ABSOLUTE, 		3, 4, OopsCycle::NONE),
-		0x8E => (Instructions::STX, AddressingMode::ABSOLUTE, 		3, 4, OopsCycle::NONE),
-		0x90 => (Instructions::BCC, AddressingMode::RELATIVE, 		2, 2, OopsCycle::BranchOccursOn),
-		0x91 => (Instructions::STA, AddressingMode::INDIRECTY, 		2, 6, OopsCycle::NONE),
-		0x94 => (Instructions::STY, AddressingMode::ZEROPAGEX, 		2, 4, OopsCycle::NONE),
-		0x95 => (Instructions::STA, AddressingMode::ZEROPAGEX, 		2, 4, OopsCycle::NONE),
-		0x96 => (Instructions::STX, AddressingMode::ZEROPAGEY, 		2, 4, OopsCycle::NONE),
-		0x98 => (Instructions::TYA, AddressingMode::IMPLIED, 		1, 2, OopsCycle::NONE),
-		0x99 => (Instructions::STA, AddressingMode::ABSOLUTEY, 		3, 5, OopsCycle::NONE),
-		0x9A => (Instructions::TXS, AddressingMode::IMPLIED, 		1, 2, OopsCycle::NONE),
-		0x9D => (Instructions::STA, AddressingMode::ABSOLUTEX, 		3, 5, OopsCycle::NONE),
-		0xA0 => (Instructions::LDY, AddressingMode::IMMEDIATE, 		2, 2, OopsCycle::NONE),
-		0xA1 => (Instructions::LDA, AddressingMode::INDIRECTX, 		2, 6, OopsCycle::NONE),
-		0xA2 => (Instructions::LDX, AddressingMode::IMMEDIATE, 		2, 2, OopsCycle::NONE),
-		0xA4 => (Instructions::LDY, AddressingMode::ZEROPAGE, 		2, 3, OopsCycle::NONE),
-		0xA5 => (Instructions::LDA, AddressingMode::ZEROPAGE, 		2, 3, OopsCycle::NONE),
-		0xA6 => (Instructions::LDX, AddressingMode::ZEROPAGE, 		2, 3, OopsCycle::NONE),
-		0xA8 => (Instructions::TAY, AddressingMode::IMPLIED, 		1, 2, OopsCycle::NONE),
-		0xA9 => (Instructions::LDA, AddressingMode::IMMEDIATE, 		2, 2, OopsCycle::NONE),
-		0xAA => (Instructions::TAX, AddressingMode::IMPLIED, 		1, 2, OopsCycle::NONE),
-		0xAC => (Instructions::LDY, AddressingMode::ABSOLUTE, 		3, 4, OopsCycle::NONE),
-		0xAD => (Instructions::LDA, AddressingMode::ABSOLUTE, 		3, 4, OopsCycle::NONE),
-		0xAE => (Instructions::LDX, AddressingMode::ABSOLUTE, 		3, 4, OopsCycle::NONE),
-		0xB0 => (Instructions::BCS, AddressingMode::RELATIVE, 		2, 2, OopsCycle::BranchOccursOn),
-		0xB1 => (Instructions::LDA, AddressingMode::INDIRECTY, 		2, 5, OopsCycle::PageBoundryCrossed),
-		0xB4 => (Instructions::LDY, AddressingMode::ZEROPAGEX, 		2, 4, OopsCycle::NONE),
-		0xB5 => (Instructions::LDA, AddressingMode::ZEROPAGEX, 		2, 4, OopsCycle::NONE),
-		0xB6 => (Instructions::LDX, AddressingMode::ZEROPAGEY, 		2, 4, OopsCycle::NONE),
-		0xB8 => (Instructions::CLV, AddressingMode::IMPLIED, 		1, 2, OopsCycle::NONE),
-		0xB9 => (Instructions::LDA, AddressingMode::ABSOLUTEY, 		3, 4, OopsCycle::PageBoundryCrossed),
-		0xBA => (Instructions::TSX, AddressingMode::IMPLIED, 		1, 2, OopsCycle::NONE),
-		0xBC => (Instructions::LDY, AddressingMode::ABSOLUTEX, 		3, 4, OopsCycle::PageBoundryCrossed),
-		0xBD => (Instructions::LDA, AddressingMode::ABSOLUTEX, 		3, 4, OopsCycle::PageBoundryCrossed),
-		0xBE => (Instructions::LDX, AddressingMode::ABSOLUTEY, 		3, 4, OopsCycle::PageBoundryCrossed),
-		0xC0 => (Instructions::CPY, AddressingMode::IMMEDIATE, 		2, 2, OopsCycle::NONE),
-		0xC1 => (Instructions::CMP, AddressingMode::INDIRECTX, 		2, 6, OopsCycle::NONE),
-		0xC4 => (Instructions::CPY, AddressingMode::ZEROPAGE, 		2, 3, OopsCycle::NONE),
-		0xC5 => (Instructions::CMP, AddressingMode::ZEROPAGE, 		2, 3, OopsCycle::NONE),
-		0xC6 => (Instructions::DEC, AddressingMode::ZEROPAGE, 		2, 5, OopsCycle::NONE),
-		0xC8 => (Instructions::INY, AddressingMode::IMPLIED, 		1, 2, OopsCycle::NONE),
-		0xC9 => (Instructions::CMP, AddressingMode::IMMEDIATE, 		2, 2, OopsCycle::NONE),
-		0xCA => (Instructions::DEX, AddressingMode::IMPLIED, 		1, 2, OopsCycle::NONE),
-		0xCC => (Instructions::CPY, AddressingMode::ABSOLUTE, 		3, 4, OopsCycle::NONE),
-		0xCD => (Instructions::CMP, AddressingMode::ABSOLUTE, 		3, 4, OopsCycle::NONE),
-		0xCE => (Instructions::DEC, AddressingMode::ABSOLUTE, 		3, 6, OopsCycle::NONE),
-		0xD0 => (Instructions::BNE, AddressingMode::RELATIVE, 		2, 2, OopsCycle::BranchOccursOn),
-		0xD1 => (Instructions::CMP, AddressingMode::INDIRECTY, 		2, 5, OopsCycle::PageBoundryCrossed),
-		0xD5 => (Instructions::CMP, AddressingMode::ZEROPAGEX, 		2, 4, OopsCycle::NONE),
-		0xD6 => (Instructions::DEC, AddressingMode::ZEROPAGEX, 		2, 6, OopsCycle::NONE),
-		0xD8 => (Instructions::CLD, AddressingMode::IMPLIED, 		1, 2, OopsCycle::NONE),
-		0xD9 => (Instructions::CMP, AddressingMode::ABSOLUTEY, 		3, 4, OopsCycle::PageBoundryCrossed),
-		0xDD => (Instructions::CMP, AddressingMode::ABSOLUTEX, 		3, 4, OopsCycle::PageBoundryCrossed),
-		0xDE => (Instructions::DEC, AddressingMode::ABSOLUTEX, 		3, 7, OopsCycle::NONE),
-		0xE0 => (Instructions::CPX, AddressingMode::IMMEDIATE, 		2, 2, OopsCycle::NONE),
-		0xE1 => (Instructions::SBC, AddressingMode::INDIRECTX, 		2, 6, OopsCycle::NONE),
-		0xE4 => (Instructions::CPX, AddressingMode::ZEROPAGE, 		2, 3, OopsCycle::NONE),
-		0xE5 => (Instructions::SBC, AddressingMode::ZEROPAGE, 		2, 3, OopsCycle::NONE),
-		0xE6 => (Instructions::INC, AddressingMode::ZEROPAGE, 		2, 5, OopsCycle::NONE),
-		0xE8 => (Instructions::INX, AddressingMode::IMPLIED, 		1, 2, OopsCycle::NONE),
-		0xE9 => (Instructions::SBC, AddressingMode::IMMEDIATE, 		2, 2, OopsCycle::NONE),
-		0xEA => (Instructions::NOP, AddressingMode::IMPLIED, 		1, 2, OopsCycle::NONE),
-		0xEC => (Instructions::CPX, AddressingMode::ABSOLUTE, 		3, 4, OopsCycle::NONE),
-		0xED => (Instructions::SBC, AddressingMode::ABSOLUTE, 		3, 4, OopsCycle::NONE),
-		0xEE => (Instructions::INC, AddressingMode::ABSOLUTE, 		3, 6, OopsCycle::NONE),
-		0xF0 => (Instructions::BEQ, AddressingMode::RELATIVE, 		2, 2, OopsCycle::BranchOccursOn),
-		0xF1 => (Instructions::SBC, AddressingMode::INDIRECTY, 		2, 5, OopsCycle::PageBoundryCrossed),
-		0xF5 => (Instructions::SBC, AddressingMode::ZEROPAGEX, 		2, 4, OopsCycle::NONE),
-		0xF6 => (Instructions::INC, AddressingMode::ZEROPAGEX, 		2, 6, OopsCycle::NONE),
-		0xF8 => (Instructions::SED, AddressingMode::IMPLIED, 		1, 2, OopsCycle::NONE),
-		0xF9 => (Instructions::SBC, AddressingMode::ABSOLUTEY, 		3, 4, OopsCycle::PageBoundryCrossed),
-		0xFD => (Instructions::SBC, AddressingMode::ABSOLUTEX, 		3, 4, OopsCycle::PageBoundryCrossed),
-		0xFE => (Instructions::INC, AddressingMode::ABSOLUTEX, 		3, 7, OopsCycle::NONE),
-		_ => {
-			//TODO: For now we panic, but we must handle this later. What happens when illegal instruction is called in real NES?
-			error!("Could not decode instruction, opcode: {:#X}", opcode);
-			panic!();
-		}
-
+		0x00 => Some((Instructions::BRK, AddressingMode::IMPLIED, 		1, 7, OopsCycle::NONE)),
+		0x01 => Some((Instructions::ORA, AddressingMode::INDIRECTX, 		2, 6, OopsCycle::NONE)),
+		0x05 => Some((Instructions::ORA, AddressingMode::ZEROPAGE, 		2, 3, OopsCycle::NONE)),
+		0x06 => Some((Instructions::ASL, AddressingMode::ZEROPAGE, 		2, 5, OopsCycle::NONE)),
+		0x08 => Some((Instructions::PHP, AddressingMode::IMPLIED, 		1, 3, OopsCycle::NONE)),
+		0x09 => Some((Instructions::ORA, AddressingMode::IMMEDIATE, 		2, 2, OopsCycle::NONE)),
+		0x0A => Some((Instructions::ASL, AddressingMode::ACCUMULATOR, 	1, 2, OopsCycle::NONE)),
+		0x0D => Some((Instructions::ORA, AddressingMode::ABSOLUTE, 		3, 4, OopsCycle::NONE)),
+		0x0E => Some((Instructions::ASL, AddressingMode::ABSOLUTE, 		3, 6, OopsCycle::NONE)),
+		0x10 => Some((Instructions::BPL, AddressingMode::RELATIVE, 		2, 2, OopsCycle::BranchOccursOn)),
+		0x11 => Some((Instructions::ORA, AddressingMode::INDIRECTY, 		2, 5, OopsCycle::PageBoundryCrossed)),
+		0x15 => Some((Instructions::ORA, AddressingMode::ZEROPAGEX, 		2, 4, OopsCycle::NONE)),
+		0x16 => Some((Instructions::ASL, AddressingMode::ZEROPAGEX, 		2, 6, OopsCycle::NONE)),
+		0x18 => Some((Instructions::CLC, AddressingMode::IMPLIED, 		1, 2, OopsCycle::NONE)),
+		0x19 => Some((Instructions::ORA, AddressingMode::ABSOLUTEY, 		3, 4, OopsCycle::PageBoundryCrossed)),
+		0x1D => Some((Instructions::ORA, AddressingMode::ABSOLUTEX, 		3, 4, OopsCycle::PageBoundryCrossed)),
+		0x1E => Some((Instructions::ASL, AddressingMode::ABSOLUTEX, 		3, 7, OopsCycle::NONE)),
+		0x20 => Some((Instructions::JSR, AddressingMode::ABSOLUTE, 		3, 6, OopsCycle::NONE)),
+		0x21 => Some((Instructions::AND, AddressingMode::INDIRECTX, 		2, 6, OopsCycle::NONE)),
+		0x24 => Some((Instructions::BIT, AddressingMode::ZEROPAGE, 		2, 3, OopsCycle::NONE)),
+		0x25 => Some((Instructions::AND, AddressingMode::ZEROPAGE, 		2, 3, OopsCycle::NONE)),
+		0x26 => Some((Instructions::ROL, AddressingMode::ZEROPAGE, 		2, 5, OopsCycle::NONE)),
+		0x28 => Some((Instructions::PLP, AddressingMode::IMPLIED, 		1, 4, OopsCycle::NONE)),
+		0x29 => Some((Instructions::AND, AddressingMode::IMMEDIATE, 		2, 2, OopsCycle::NONE)),
+		0x2A => Some((Instructions::ROL, AddressingMode::ACCUMULATOR, 	1, 2, OopsCycle::NONE)),
+		0x2C => Some((Instructions::BIT, AddressingMode::ABSOLUTE, 		3, 4, OopsCycle::NONE)),
+		0x2D => Some((Instructions::AND, AddressingMode::ABSOLUTE, 		3, 4, OopsCycle::NONE)),
+		0x2E => Some((Instructions::ROL, AddressingMode::ABSOLUTE, 		3, 6, OopsCycle::NONE)),
+		0x30 => Some((Instructions::BMI, AddressingMode::RELATIVE, 		2, 2, OopsCycle::BranchOccursOn)),
+		0x31 => Some((Instructions::AND, AddressingMode::INDIRECTY, 		2, 5, OopsCycle::PageBoundryCrossed)),
+		0x35 => Some((Instructions::AND, AddressingMode::ZEROPAGEX, 		2, 4, OopsCycle::NONE)),
+		0x36 => Some((Instructions::ROL, AddressingMode::ZEROPAGEX, 		2, 6, OopsCycle::NONE)),
+		0x38 => Some((Instructions::SEC, AddressingMode::IMPLIED, 		1, 2, OopsCycle::NONE)),
+		0x39 => Some((Instructions::AND, AddressingMode::ABSOLUTEY, 		3, 4, OopsCycle::PageBoundryCrossed)),
+		0x3D => Some((Instructions::AND, AddressingMode::ABSOLUTEX, 		3, 4, OopsCycle::PageBoundryCrossed)),
+		0x3E => Some((Instructions::ROL, AddressingMode::ABSOLUTEX, 		3, 7, OopsCycle::NONE)),
+		0x40 => Some((Instructions::RTI, AddressingMode::IMPLIED, 		1, 6, OopsCycle::NONE)),
+		0x41 => Some((Instructions::EOR, AddressingMode::INDIRECTX, 		2, 6, OopsCycle::NONE)),
+		0x45 => Some((Instructions::EOR, AddressingMode::ZEROPAGE, 		2, 3, OopsCycle::NONE)),
+		0x46 => Some((Instructions::LSR, AddressingMode::ZEROPAGE, 		2, 5, OopsCycle::NONE)),
+		0x48 => Some((Instructions::PHA, AddressingMode::IMPLIED, 		1, 3, OopsCycle::NONE)),
+		0x49 => Some((Instructions::EOR, AddressingMode::IMMEDIATE, 		2, 2, OopsCycle::NONE)),
+		0x4A => Some((Instructions::LSR, AddressingMode::ACCUMULATOR, 	1, 2, OopsCycle::NONE)),
+		0x4C => Some((Instructions::JMP, AddressingMode::ABSOLUTE, 		3, 3, OopsCycle::NONE)),
+		0x4D => Some((Instructions::EOR, AddressingMode::ABSOLUTE, 		3, 4, OopsCycle::NONE)),
+		0x4E => Some((Instructions::LSR, AddressingMode::ABSOLUTE, 		3, 6, OopsCycle::NONE)),
+		0x50 => Some((Instructions::BVC, AddressingMode::RELATIVE, 		2, 2, OopsCycle::BranchOccursOn)),
+		0x51 => Some((Instructions::EOR, AddressingMode::INDIRECTY, 		2, 5, OopsCycle::PageBoundryCrossed)),
+		0x55 => Some((Instructions::EOR, AddressingMode::ZEROPAGEX, 		2, 4, OopsCycle::NONE)),
+		0x56 => Some((Instructions::LSR, AddressingMode::ZEROPAGEX, 		2, 6, OopsCycle::NONE)),
+		0x58 => Some((Instructions::CLI, AddressingMode::IMPLIED, 		1, 2, OopsCycle::NONE)),
+		0x59 => Some((Instructions::EOR, AddressingMode::ABSOLUTEY, 		3, 4, OopsCycle::PageBoundryCrossed)),
+		0x5D => Some((Instructions::EOR, AddressingMode::ABSOLUTEX, 		3, 4, OopsCycle::PageBoundryCrossed)),
+		0x5E => Some((Instructions::LSR, AddressingMode::ABSOLUTEX, 		3, 7, OopsCycle::NONE)),
+		0x60 => Some((Instructions::RTS, AddressingMode::IMPLIED, 		1, 6, OopsCycle::NONE)),
+		0x61 => Some((Instructions::ADC, AddressingMode::INDIRECTX, 		2, 6, OopsCycle::NONE)),
+		0x65 => Some((Instructions::ADC, AddressingMode::ZEROPAGE, 		2, 3, OopsCycle::NONE)),
+		0x66 => Some((Instructions::ROR, AddressingMode::ZEROPAGE, 		2, 5, OopsCycle::NONE)),
+		0x68 => Some((Instructions::PLA, AddressingMode::IMPLIED, 		1, 4, OopsCycle::NONE)),
+		0x69 => Some((Instructions::ADC, AddressingMode::IMMEDIATE, 		2, 2, OopsCycle::NONE)),
+		0x6A => Some((Instructions::ROR, AddressingMode::ACCUMULATOR, 	1, 2, OopsCycle::NONE)),
+		0x6C => Some((Instructions::JMP, AddressingMode::INDIRECT, 		3, 5, OopsCycle::NONE)),
+		0x6D => Some((Instructions::ADC, AddressingMode::ABSOLUTE, 		3, 4, OopsCycle::NONE)),
+		0x6E => Some((Instructions::ROR, AddressingMode::ABSOLUTE, 		3, 6, OopsCycle::NONE)),
+		0x70 => Some((Instructions::BVS, AddressingMode::RELATIVE, 		2, 2, OopsCycle::BranchOccursOn)),
+		0x71 => Some((Instructions::ADC, AddressingMode::INDIRECTY, 		2, 5, OopsCycle::PageBoundryCrossed)),
+		0x75 => Some((Instructions::ADC, AddressingMode::ZEROPAGEX, 		2, 4, OopsCycle::NONE)),
+		0x76 => Some((Instructions::ROR, AddressingMode::ZEROPAGEX, 		2, 6, OopsCycle::NONE)),
+		0x78 => Some((Instructions::SEI, AddressingMode::IMPLIED, 		1, 2, OopsCycle::NONE)),
+		0x79 => Some((Instructions::ADC, AddressingMode::ABSOLUTEY, 		3, 4, OopsCycle::PageBoundryCrossed)),
+		0x7D => Some((Instructions::ADC, AddressingMode::ABSOLUTEX, 		3, 4, OopsCycle::PageBoundryCrossed)),
+		0x7E => Some((Instructions::ROR, AddressingMode::ABSOLUTEX, 		3, 7, OopsCycle::NONE)),
+		0x81 => Some((Instructions::STA, AddressingMode::INDIRECTX, 		2, 6, OopsCycle::NONE)),
+		0x84 => Some((Instructions::STY, AddressingMode::ZEROPAGE, 		2, 3, OopsCycle::NONE)),
+		0x85 => Some((Instructions::STA, AddressingMode::ZEROPAGE, 		2, 3, OopsCycle::NONE)),
+		0x86 => Some((Instructions::STX, AddressingMode::ZEROPAGE, 		2, 3, OopsCycle::NONE)),
+		0x88 => Some((Instructions::DEY, AddressingMode::IMPLIED, 		1, 2, OopsCycle::NONE)),
+		0x8A => Some((Instructions::TXA, AddressingMode::IMPLIED, 		1, 2, OopsCycle::NONE)),
+		0x8C => Some((Instructions::STY, AddressingMode::ABSOLUTE, 		3, 4, OopsCycle::NONE)),
+		0x8D => Some((Instructions::STA, AddressingMode::ABSOLUTE, 		3, 4, OopsCycle::NONE)),
+		0x8E => Some((Instructions::STX, AddressingMode::ABSOLUTE, 		3, 4, OopsCycle::NONE)),
+		0x90 => Some((Instructions::BCC, AddressingMode::RELATIVE, 		2, 2, OopsCycle::BranchOccursOn)),
+		0x91 => Some((Instructions::STA, AddressingMode::INDIRECTY, 		2, 6, OopsCycle::NONE)),
+		0x94 => Some((Instructions::STY, AddressingMode::ZEROPAGEX, 		2, 4, OopsCycle::NONE)),
+		0x95 => Some((Instructions::STA, AddressingMode::ZEROPAGEX, 		2, 4, OopsCycle::NONE)),
+		0x96 => Some((Instructions::STX, AddressingMode::ZEROPAGEY, 		2, 4, OopsCycle::NONE)),
+		0x98 => Some((Instructions::TYA, AddressingMode::IMPLIED, 		1, 2, OopsCycle::NONE)),
+		0x99 => Some((Instructions::STA, AddressingMode::ABSOLUTEY, 		3, 5, OopsCycle::NONE)),
+		0x9A => Some((Instructions::TXS, AddressingMode::IMPLIED, 		1, 2, OopsCycle::NONE)),
+		0x9D => Some((Instructions::STA, AddressingMode::ABSOLUTEX, 		3, 5, OopsCycle::NONE)),
+		0xA0 => Some((Instructions::LDY, AddressingMode::IMMEDIATE, 		2, 2, OopsCycle::NONE)),
+		0xA1 => Some((Instructions::LDA, AddressingMode::INDIRECTX, 		2, 6, OopsCycle::NONE)),
+		0xA2 => Some((Instructions::LDX, AddressingMode::IMMEDIATE, 		2, 2, OopsCycle::NONE)),
+		0xA4 => Some((Instructions::LDY, AddressingMode::ZEROPAGE, 		2, 3, OopsCycle::NONE)),
+		0xA5 => Some((Instructions::LDA, AddressingMode::ZEROPAGE, 		2, 3, OopsCycle::NONE)),
+		0xA6 => Some((Instructions::LDX, AddressingMode::ZEROPAGE, 		2, 3, OopsCycle::NONE)),
+		0xA8 => Some((Instructions::TAY, AddressingMode::IMPLIED, 		1, 2, OopsCycle::NONE)),
+		0xA9 => Some((Instructions::LDA, AddressingMode::IMMEDIATE, 		2, 2, OopsCycle::NONE)),
+		0xAA => Some((Instructions::TAX, AddressingMode::IMPLIED, 		1, 2, OopsCycle::NONE)),
+		0xAC => Some((Instructions::LDY, AddressingMode::ABSOLUTE, 		3, 4, OopsCycle::NONE)),
+		0xAD => Some((Instructions::LDA, AddressingMode::ABSOLUTE, 		3, 4, OopsCycle::NONE)),
+		0xAE => Some((Instructions::LDX, AddressingMode::ABSOLUTE, 		3, 4, OopsCycle::NONE)),
+		0xB0 => Some((Instructions::BCS, AddressingMode::RELATIVE, 		2, 2, OopsCycle::BranchOccursOn)),
+		0xB1 => Some((Instructions::LDA, AddressingMode::INDIRECTY, 		2, 5, OopsCycle::PageBoundryCrossed)),
+		0xB4 => Some((Instructions::LDY, AddressingMode::ZEROPAGEX, 		2, 4, OopsCycle::NONE)),
+		0xB5 => Some((Instructions::LDA, AddressingMode::ZEROPAGEX, 		2, 4, OopsCycle::NONE)),
+		0xB6 => Some((Instructions::LDX, AddressingMode::ZEROPAGEY, 		2, 4, OopsCycle::NONE)),
+		0xB8 => Some((Instructions::CLV, AddressingMode::IMPLIED, 		1, 2, OopsCycle::NONE)),
+		0xB9 => Some((Instructions::LDA, AddressingMode::ABSOLUTEY, 		3, 4, OopsCycle::PageBoundryCrossed)),
+		0xBA => Some((Instructions::TSX, AddressingMode::IMPLIED, 		1, 2, OopsCycle::NONE)),
+		0xBC => Some((Instructions::LDY, AddressingMode::ABSOLUTEX, 		3, 4, OopsCycle::PageBoundryCrossed)),
+		0xBD => Some((Instructions::LDA, AddressingMode::ABSOLUTEX, 		3, 4, OopsCycle::PageBoundryCrossed)),
+		0xBE => Some((Instructions::LDX, AddressingMode::ABSOLUTEY, 		3, 4, OopsCycle::PageBoundryCrossed)),
+		0xC0 => Some((Instructions::CPY, AddressingMode::IMMEDIATE, 		2, 2, OopsCycle::NONE)),
+		0xC1 => Some((Instructions::CMP, AddressingMode::INDIRECTX, 		2, 6, OopsCycle::NONE)),
+		0xC4 => Some((Instructions::CPY, AddressingMode::ZEROPAGE, 		2, 3, OopsCycle::NONE)),
+		0xC5 => Some((Instructions::CMP, AddressingMode::ZEROPAGE, 		2, 3, OopsCycle::NONE)),
+		0xC6 => Some((Instructions::DEC, AddressingMode::ZEROPAGE, 		2, 5, OopsCycle::NONE)),
+		0xC8 => Some((Instructions::INY, AddressingMode::IMPLIED, 		1, 2, OopsCycle::NONE)),
+		0xC9 => Some((Instructions::CMP, AddressingMode::IMMEDIATE, 		2, 2, OopsCycle::NONE)),
+		0xCA => Some((Instructions::DEX, AddressingMode::IMPLIED, 		1, 2, OopsCycle::NONE)),
+		0xCC => Some((Instructions::CPY, AddressingMode::ABSOLUTE, 		3, 4, OopsCycle::NONE)),
+		0xCD => Some((Instructions::CMP, AddressingMode::ABSOLUTE, 		3, 4, OopsCycle::NONE)),
+		0xCE => Some((Instructions::DEC, AddressingMode::ABSOLUTE, 		3, 6, OopsCycle::NONE)),
+		0xD0 => Some((Instructions::BNE, AddressingMode::RELATIVE, 		2, 2, OopsCycle::BranchOccursOn)),
+		0xD1 => Some((Instructions::CMP, AddressingMode::INDIRECTY, 		2, 5, OopsCycle::PageBoundryCrossed)),
+		0xD5 => Some((Instructions::CMP, AddressingMode::ZEROPAGEX, 		2, 4, OopsCycle::NONE)),
+		0xD6 => Some((Instructions::DEC, AddressingMode::ZEROPAGEX, 		2, 6, OopsCycle::NONE)),
+		0xD8 => Some((Instructions::CLD, AddressingMode::IMPLIED, 		1, 2, OopsCycle::NONE)),
+		0xD9 => Some((Instructions::CMP, AddressingMode::ABSOLUTEY, 		3, 4, OopsCycle::PageBoundryCrossed)),
+		0xDD => Some((Instructions::CMP, AddressingMode::ABSOLUTEX, 		3, 4, OopsCycle::PageBoundryCrossed)),
+		0xDE => Some((Instructions::DEC, AddressingMode::ABSOLUTEX, 		3, 7, OopsCycle::NONE)),
+		0xE0 => Some((Instructions::CPX, AddressingMode::IMMEDIATE, 		2, 2, OopsCycle::NONE)),
+		0xE1 => Some((Instructions::SBC, AddressingMode::INDIRECTX, 		2, 6, OopsCycle::NONE)),
+		0xE4 => Some((Instructions::CPX, AddressingMode::ZEROPAGE, 		2, 3, OopsCycle::NONE)),
+		0xE5 => Some((Instructions::SBC, AddressingMode::ZEROPAGE, 		2, 3, OopsCycle::NONE)),
+		0xE6 => Some((Instructions::INC, AddressingMode::ZEROPAGE, 		2, 5, OopsCycle::NONE)),
+		0xE8 => Some((Instructions::INX, AddressingMode::IMPLIED, 		1, 2, OopsCycle::NONE)),
+		0xE9 => Some((Instructions::SBC, AddressingMode::IMMEDIATE, 		2, 2, OopsCycle::NONE)),
+		0xEA => Some((Instructions::NOP, AddressingMode::IMPLIED, 		1, 2, OopsCycle::NONE)),
+		0xEC => Some((Instructions::CPX, AddressingMode::ABSOLUTE, 		3, 4, OopsCycle::NONE)),
+		0xED => Some((Instructions::SBC, AddressingMode::ABSOLUTE, 		3, 4, OopsCycle::NONE)),
+		0xEE => Some((Instructions::INC, AddressingMode::ABSOLUTE, 		3, 6, OopsCycle::NONE)),
+		0xF0 => Some((Instructions::BEQ, AddressingMode::RELATIVE, 		2, 2, OopsCycle::BranchOccursOn)),
+		0xF1 => Some((Instructions::SBC, AddressingMode::INDIRECTY, 		2, 5, OopsCycle::PageBoundryCrossed)),
+		0xF5 => Some((Instructions::SBC, AddressingMode::ZEROPAGEX, 		2, 4, OopsCycle::NONE)),
+		0xF6 => Some((Instructions::INC, AddressingMode::ZEROPAGEX, 		2, 6, OopsCycle::NONE)),
+		0xF8 => Some((Instructions::SED, AddressingMode::IMPLIED, 		1, 2, OopsCycle::NONE)),
+		0xF9 => Some((Instructions::SBC, AddressingMode::ABSOLUTEY, 		3, 4, OopsCycle::PageBoundryCrossed)),
+		0xFD => Some((Instructions::SBC, AddressingMode::ABSOLUTEX, 		3, 4, OopsCycle::PageBoundryCrossed)),
+		0xFE => Some((Instructions::INC, AddressingMode::ABSOLUTEX, 		3, 7, OopsCycle::NONE)),
+		_ => None,
 	}
 }	
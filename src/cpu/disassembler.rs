@@ -0,0 +1,149 @@
+// Disassembler: memory back to 6502 assembly, for the debugger.
+//
+// | Addressing mode | Syntax |
+// |---|---|
+// | IMPLIED | `CLC` |
+// | ACCUMULATOR | `ASL A` |
+// | IMMEDIATE | `LDA #$01` |
+// | ZEROPAGE, ZEROPAGEX, ZEROPAGEY | `LDA $10`, `LDA $10,X`, `LDX $10,Y` |
+// | ABSOLUTE, ABSOLUTEX, ABSOLUTEY | `LDA $1234`, `LDA $1234,X`, `LDA $1234,Y` |
+// | INDIRECT | `JMP ($1234)` |
+// | INDIRECTX, INDIRECTY | `LDA ($10,X)`, `LDA ($10),Y` |
+// | RELATIVE | `BNE $8010`, the target address instead of the offset |
+//
+// Illegal opcodes are shown as `.byte $02`.
+//
+// With a symbol table, addresses with a name are shown as the name (`JSR Reset`, `STA Buffer+2,X`). Immediate values
+// are never addresses, so they stay numbers.
+
+use std::fmt;
+
+use crate::cpu::decoder::{decode_opcode, AddressingMode};
+use crate::symbols::SymbolTable;
+
+/// A single disassembled instruction.
+#[derive(Clone, PartialEq, Eq, Debug)]
+pub struct Disassembly {
+	pub addr: u16,
+	/// The opcode and the operand bytes.
+	pub bytes: Vec<u8>,
+	pub text: String,
+}
+
+impl Disassembly {
+	/// Address of the next instruction.
+	pub fn next_addr(&self) -> u16 {
+		self.addr.wrapping_add(self.bytes.len() as u16)
+	}
+}
+
+impl fmt::Display for Disassembly {
+	fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+		let bytes: Vec<String> = self.bytes.iter().map(|byte| format!("{:02X}", byte)).collect();
+		write!(f, "${:04X}  {:<8}  {}", self.addr, bytes.join(" "), self.text)
+	}
+}
+
+/// Disassemble the instruction at `addr`. `peek` reads memory, and should have no side effects (see `Bus::peek`).
+pub fn disassemble(addr: u16, peek: impl Fn(u16) -> u8) -> Disassembly {
+	disassemble_with_symbols(addr, peek, None)
+}
+
+/// Like `disassemble`, with the names of the addresses in `symbols`.
+pub fn disassemble_with_symbols(addr: u16, peek: impl Fn(u16) -> u8, symbols: Option<&SymbolTable>) -> Disassembly {
+	let mut text = String::new();
+	let length = write_text(&mut text, addr, &peek, symbols).expect("Writing to a String can't fail");
+	let bytes = (0..length as u16).map(|i| peek(addr.wrapping_add(i))).collect();
+	Disassembly { addr, bytes, text }
+}
+
+/// Write just the assembly of the instruction at `addr` (like `LDA #$01`) to `out`, and return the length of the
+/// instruction in bytes. It doesn't allocate, so the trace can call it for every instruction.
+pub fn write_text(out: &mut impl fmt::Write, addr: u16, peek: &impl Fn(u16) -> u8, symbols: Option<&SymbolTable>) -> Result<u8, fmt::Error> {
+	let opcode = peek(addr);
+	let Some((instruction, mode, length, _, _)) = decode_opcode(opcode) else {
+		write!(out, ".byte ${:02X}", opcode)?;
+		return Ok(1);
+	};
+
+	let byte = if length > 1 { peek(addr.wrapping_add(1)) } else { 0 };
+	let word = if length > 2 { u16::from_le_bytes([byte, peek(addr.wrapping_add(2))]) } else { byte as u16 };
+	write!(out, "{:?}", instruction)?;
+	let (prefix, suffix) = match mode {
+		AddressingMode::IMPLIED => return Ok(length),
+		AddressingMode::ACCUMULATOR => {
+			write!(out, " A")?;
+			return Ok(length);
+		}
+		AddressingMode::IMMEDIATE => {
+			write!(out, " #${:02X}", byte)?;
+			return Ok(length);
+		}
+		AddressingMode::ZEROPAGE | AddressingMode::ABSOLUTE | AddressingMode::RELATIVE => (" ", ""),
+		AddressingMode::ZEROPAGEX | AddressingMode::ABSOLUTEX => (" ", ",X"),
+		AddressingMode::ZEROPAGEY | AddressingMode::ABSOLUTEY => (" ", ",Y"),
+		AddressingMode::INDIRECT => (" (", ")"),
+		AddressingMode::INDIRECTX => (" (", ",X)"),
+		AddressingMode::INDIRECTY => (" (", "),Y"),
+	};
+	let operand = match mode {
+		// The offset is from the next instruction.
+		AddressingMode::RELATIVE => addr.wrapping_add(2).wrapping_add_signed(byte as i8 as i16),
+		_ => word,
+	};
+
+	out.write_str(prefix)?;
+	let named = match symbols {
+		Some(symbols) => symbols.write_name(out, operand)?,
+		None => false,
+	};
+	if !named {
+		// Zero page operands are a single byte, the rest are addresses.
+		match mode {
+			AddressingMode::ZEROPAGE | AddressingMode::ZEROPAGEX | AddressingMode::ZEROPAGEY
+				| AddressingMode::INDIRECTX | AddressingMode::INDIRECTY => write!(out, "${:02X}", operand),
+			_ => write!(out, "${:04X}", operand),
+		}?;
+	}
+	out.write_str(suffix)?;
+	Ok(length)
+}
+
+/// Length in bytes of the instruction with `opcode`. Illegal opcodes are shown as a single byte.
+pub fn instruction_length(opcode: u8) -> u8 {
+	decode_opcode(opcode).map_or(1, |(_, _, length, _, _)| length)
+}
+
+/// Disassemble `count` instructions, one after the other, starting at `addr`.
+/// `symbols` names the addresses, like in `disassemble_with_symbols`.
+pub fn disassemble_range(addr: u16, count: usize, peek: impl Fn(u16) -> u8, symbols: Option<&SymbolTable>) -> Vec<Disassembly> {
+	let mut next = addr;
+	(0..count).map(|_| {
+		let disassembly = disassemble_with_symbols(next, &peek, symbols);
+		next = disassembly.next_addr();
+		disassembly
+	}).collect()
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+
+	fn memory(program: &str) -> impl Fn(u16) -> u8 {
+		let bytes = hex::decode(program.replace(' ', "")).unwrap();
+		move |addr| bytes.get(addr.wrapping_sub(0x8000) as usize).copied().unwrap_or(0)
+	}
+
+	#[test]
+	fn disassemble_test() {
+		let peek = memory("A9 01 0A B5 10 BD 34 12 6C 00 02 B1 20 D0 FC 02");
+		let lines: Vec<String> = disassemble_range(0x8000, 8, &peek, None).iter().map(|line| line.text.clone()).collect();
+		assert_eq!(lines, ["LDA #$01", "ASL A", "LDA $10,X", "LDA $1234,X", "JMP ($0200)", "LDA ($20),Y", "BNE $800B", ".byte $02"]);
+
+		assert_eq!(disassemble(0x8000, &peek).to_string(), "$8000  A9 01     LDA #$01");
+		assert_eq!(disassemble(0x8005, &peek).next_addr(), 0x8008);
+
+		// Branch targets wrap around the address space.
+		assert_eq!(disassemble(0xFFF0, |addr| if addr == 0xFFF0 { 0xD0 } else { 0x7F }).text, "BNE $0071");
+	}
+}
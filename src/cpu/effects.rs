@@ -0,0 +1,127 @@
+// What an instruction did, for comparing the CPU with another 6502 in lockstep (`CPU::step_with_effects`): a trace
+// line, as a struct.
+//
+// | Field | Description |
+// |---|---|
+// | `opcode` | The opcode, None when the step took an interrupt instead |
+// | `before`, `after` | The registers (and the cycle counter) before and after |
+// | `accesses()` | Every read and write of the CPU, in order: address, value, and which |
+// | `overwritten()` | The writes, with the value each address had before (what undoing the step writes back) |
+// | `cycles` | Cycles the step took |
+// | `penalty_cycles` | The oops cycles in `cycles`: a page crossed by an indexed read, or a branch taken (see `CPU::penalty_cycles`) |
+//
+// The accesses are recorded where all the reads and writes of the CPU go through (`CPU::read` and `CPU::write`), and
+// nowhere else, so they are the accesses the bus sees from the CPU. Not DMA, and not the debugger's `peek`.
+// They go in a fixed array, so recording doesn't allocate (and works without std): no instruction gets close to
+// `MAX_ACCESSES`, the most is an interrupt, with 5. The values before the writes are read with `peek`, so for registers
+// they are whatever `peek` returns for them.
+
+use crate::cpu::cpu::CpuState;
+use crate::cpu::decoder::{decode_opcode, AddressingMode, Instructions};
+
+/// More than any instruction (or interrupt) does.
+pub const MAX_ACCESSES: usize = 16;
+
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub enum AccessKind {
+	Read,
+	Write,
+}
+
+/// A read or write of the CPU.
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub struct BusAccess {
+	pub addr: u16,
+	pub value: u8,
+	pub kind: AccessKind,
+}
+
+impl BusAccess {
+	pub fn read(addr: u16, value: u8) -> Self {
+		BusAccess { addr, value, kind: AccessKind::Read }
+	}
+
+	pub fn write(addr: u16, value: u8) -> Self {
+		BusAccess { addr, value, kind: AccessKind::Write }
+	}
+}
+
+/// The accesses of the current step, while `CPU::step_with_effects` records them.
+#[derive(Clone, Copy, Default, Debug)]
+pub(crate) struct AccessLog {
+	recording: bool,
+	accesses: [Option<BusAccess>; MAX_ACCESSES],
+	/// For the writes in `accesses`, the value before.
+	overwritten: [u8; MAX_ACCESSES],
+	count: u8,
+}
+
+impl AccessLog {
+	pub(crate) fn recording() -> Self {
+		AccessLog { recording: true, ..Default::default() }
+	}
+
+	#[inline]
+	pub(crate) fn is_recording(&self) -> bool {
+		self.recording
+	}
+
+	#[inline]
+	pub(crate) fn record(&mut self, access: BusAccess) {
+		self.record_write(access, 0);
+	}
+
+	/// `access`, and for a write, the value it overwrote.
+	#[inline]
+	pub(crate) fn record_write(&mut self, access: BusAccess, overwritten: u8) {
+		if self.recording && (self.count as usize) < MAX_ACCESSES {
+			self.accesses[self.count as usize] = Some(access);
+			self.overwritten[self.count as usize] = overwritten;
+			self.count += 1;
+		}
+	}
+}
+
+/// What `CPU::step_with_effects` did, see the top of the file.
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub struct StepEffects {
+	pub opcode: Option<u8>,
+	pub before: CpuState,
+	pub after: CpuState,
+	pub cycles: u8,
+	pub penalty_cycles: u8,
+	accesses: [BusAccess; MAX_ACCESSES],
+	overwritten: [u8; MAX_ACCESSES],
+	access_count: u8,
+}
+
+impl StepEffects {
+	pub(crate) fn new(before: CpuState, after: CpuState, cycles: u8, penalty_cycles: u8, log: &AccessLog) -> Self {
+		let mut accesses = [BusAccess::read(0, 0); MAX_ACCESSES];
+		for (access, recorded) in accesses.iter_mut().zip(log.accesses.iter().flatten()) {
+			*access = *recorded;
+		}
+		// An instruction starts with reading its opcode at PC. An interrupt starts with pushing PC.
+		let opcode = match log.accesses[0] {
+			Some(BusAccess { addr, value, kind: AccessKind::Read }) if addr == before.pc => Some(value),
+			_ => None,
+		};
+		StepEffects { opcode, before, after, cycles, penalty_cycles, accesses, overwritten: log.overwritten, access_count: log.count }
+	}
+
+	pub fn accesses(&self) -> &[BusAccess] {
+		&self.accesses[..self.access_count as usize]
+	}
+
+	/// The writes, in order: the address, and the value it had before the write.
+	pub fn overwritten(&self) -> impl Iterator<Item = (u16, u8)> + '_ {
+		self.accesses().iter().zip(self.overwritten).filter(|(access, _)| access.kind == AccessKind::Write).map(|(access, old)| (access.addr, old))
+	}
+
+	/// The decoded instruction, None for an interrupt.
+	pub fn instruction(&self) -> Option<(Instructions, AddressingMode)> {
+		let (instruction, addrmode, ..) = decode_opcode(self.opcode?)?;
+		Some((instruction, addrmode))
+	}
+}
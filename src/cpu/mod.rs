@@ -1,4 +1,9 @@
-mod registers;
-mod decoder;
+pub mod registers;
+pub mod decoder;
 
-pub mod cpu;
\ No newline at end of file
+pub mod cpu;
+pub mod effects;
+pub mod status;
+pub mod stuck;
+#[cfg(feature = "std")]
+pub mod disassembler;
@@ -0,0 +1,219 @@
+use core::fmt;
+use core::str::FromStr;
+
+/// # Processor Status Register
+/// The P register contains 7 bit flags, and 1 bit unused (bit 5)
+///
+/// | Bit | Symbol | Description |
+/// |---|---|---|
+/// | 7 | N | Negative |
+/// | 6 | V | Overflow |
+/// | 5 | - | Not used |
+/// | 4 | B | Break |
+/// | 3 | D | Decimal |
+/// | 2 | I | Interrupt disable |
+/// | 1 | Z | Zero |
+/// | 0 | C | Carry |
+///
+/// B and bit 5 don't really exist in the register, they only show in the copies of P pushed to the stack: bit 5 is
+/// always 1, and B is 1 when PHP or BRK pushed it (0 for interrupts). The CPU keeps B at 0, but `StatusFlags` can
+/// hold it too, so a pushed byte can be shown.
+#[allow(non_camel_case_types)]
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub enum Flag {
+	CARRY = 0b0000_0001,
+	ZERO = 0b0000_0010,
+	INTERRUPT_DISABLE = 0b0000_0100,
+	DECIMAL = 0b0000_1000,
+	BREAK = 0b0001_0000,
+	UNUSED = 0b0010_0000,		// By the datasheet it looks like its always 1.
+	OVERFLOW = 0b0100_0000,
+	NEGATIVE = 0b1000_0000,
+}
+
+impl Flag {
+	pub fn mask(self) -> u8 {
+		self as u8
+	}
+
+	/// The flag of a letter of `NV-BDIZC`, in any case.
+	pub fn from_letter(letter: char) -> Option<Flag> {
+		match letter.to_ascii_uppercase() {
+			'N' => Some(Flag::NEGATIVE),
+			'V' => Some(Flag::OVERFLOW),
+			'B' => Some(Flag::BREAK),
+			'D' => Some(Flag::DECIMAL),
+			'I' => Some(Flag::INTERRUPT_DISABLE),
+			'Z' => Some(Flag::ZERO),
+			'C' => Some(Flag::CARRY),
+			_ => None,
+		}
+	}
+}
+
+/// The flags, from bit 7 to bit 0, as shown: `NV-BDIZC`.
+const LETTERS: &[u8; 8] = b"NV-BDIZC";
+
+/// The P register. Displayed as the flags from bit 7 to bit 0, uppercase when set and lowercase when clear, like
+/// `nv-BdIZc` (bit 5 is always `-`).
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct StatusFlags {
+	flags: u8
+}
+
+impl Default for StatusFlags {
+	fn default() -> Self {
+		// Set 'UNUSED' flag to 1. Its the standard.
+		Self { flags: Flag::UNUSED.mask() }
+	}
+}
+
+impl StatusFlags {
+	/// All the bits of `value`, and bit 5, which is always set.
+	pub fn from_byte(value: u8) -> Self {
+		StatusFlags { flags: value | Flag::UNUSED.mask() }
+	}
+
+	pub fn to_byte(self) -> u8 {
+		self.flags
+	}
+
+	/// P as it's pulled from the stack (PLP, RTI): the B bit doesn't exist in the register, so it's ignored.
+	pub fn from_stack(value: u8) -> Self {
+		Self::from_byte(value & !Flag::BREAK.mask())
+	}
+
+	/// P as it's pushed to the stack: B is set by PHP and BRK (`brk`), and clear for interrupts.
+	pub fn to_stack(self, brk: bool) -> u8 {
+		let flags = self.flags | Flag::UNUSED.mask();
+		if brk { flags | Flag::BREAK.mask() } else { flags & !Flag::BREAK.mask() }
+	}
+
+	pub fn set(&mut self, flag: Flag, value: bool) {
+		if value {
+			self.flags |= flag.mask();
+		} else {
+			self.flags &= !flag.mask();
+		}
+	}
+
+	pub fn get(&self, flag: Flag) -> bool {
+		self.flags & flag.mask() != 0
+	}
+
+	/// Sets the N bitflag, depending on arithmetic result. Its common for all the instructions.
+	pub fn modify_n(&mut self, value: u8) {
+		// If last bit (7) is 1, its negative
+		self.set(Flag::NEGATIVE, (value >> 7) == 1);
+	}
+
+	/// Sets the Z bitflag, depending on arithmetic result. Its common for all the instructions.
+	pub fn modify_z(&mut self, value: u8) {
+		// If value is 0, zero flag is 1
+		self.set(Flag::ZERO, value == 0);
+	}
+
+	/// N and Z of the result, what most instructions set.
+	pub fn modify_nz(&mut self, value: u8) {
+		self.modify_n(value);
+		self.modify_z(value);
+	}
+}
+
+impl fmt::Display for StatusFlags {
+	fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+		for (i, &letter) in LETTERS.iter().enumerate() {
+			let set = self.flags & (0x80 >> i) != 0;
+			let letter = if set || letter == b'-' { letter } else { letter.to_ascii_lowercase() };
+			write!(f, "{}", letter as char)?;
+		}
+		Ok(())
+	}
+}
+
+/// Parses what `Display` writes, like `nv-BdIZc`. The letters must be in their places, in either case.
+impl FromStr for StatusFlags {
+	type Err = InvalidStatusFlags;
+
+	fn from_str(text: &str) -> Result<Self, Self::Err> {
+		if text.len() != LETTERS.len() {
+			return Err(InvalidStatusFlags);
+		}
+		let mut flags = 0;
+		for (i, (&letter, &expected)) in text.as_bytes().iter().zip(LETTERS).enumerate() {
+			if letter == expected {
+				flags |= 0x80 >> i;
+			} else if letter != expected.to_ascii_lowercase() {
+				return Err(InvalidStatusFlags);
+			}
+		}
+		Ok(Self::from_byte(flags))
+	}
+}
+
+/// The text isn't 8 flags like `nv-BdIZc`.
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub struct InvalidStatusFlags;
+
+impl fmt::Display for InvalidStatusFlags {
+	fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+		write!(f, "Expected the flags like nv-BdIZc")
+	}
+}
+
+#[cfg(all(test, feature = "std"))]
+mod tests {
+	use super::*;
+
+	#[test]
+	fn status_flags_test() {
+		let mut p = StatusFlags::default();
+
+		assert!(!p.get(Flag::CARRY));
+		p.set(Flag::CARRY, true);
+		assert!(p.get(Flag::CARRY));
+
+		assert!(!p.get(Flag::NEGATIVE));
+		p.set(Flag::NEGATIVE, true);
+		assert!(p.get(Flag::NEGATIVE));
+		p.set(Flag::NEGATIVE, false);
+		assert!(!p.get(Flag::NEGATIVE));
+		p.set(Flag::NEGATIVE, false);
+		assert!(!p.get(Flag::NEGATIVE));
+
+		// Like LDA #$80, then LDA #$00.
+		p.modify_nz(0x80);
+		assert_eq!(p.to_byte(), 0b1010_0001);
+		p.modify_nz(0x00);
+		assert_eq!(p.to_byte(), 0b0010_0011);
+	}
+
+	#[test]
+	fn stack_test() {
+		// B only exists on the stack.
+		let p = StatusFlags::from_byte(0b1100_0011);
+		assert_eq!(p.to_stack(true), 0b1111_0011);
+		assert_eq!(p.to_stack(false), 0b1110_0011);
+		assert_eq!(StatusFlags::from_stack(0b1111_0011), p);
+		assert_eq!(StatusFlags::from_stack(0b0000_0000).to_byte(), 0b0010_0000);
+	}
+
+	#[test]
+	fn format_test() {
+		assert_eq!(StatusFlags::from_byte(0b0011_0110).to_string(), "nv-BdIZc");
+		assert_eq!(StatusFlags::from_byte(0xFF).to_string(), "NV-BDIZC");
+		assert_eq!(StatusFlags::default().to_string(), "nv-bdizc");
+
+		// Every byte, through the text and back. Bit 5 is always set.
+		for byte in 0..=255u8 {
+			let p = StatusFlags::from_byte(byte);
+			assert_eq!(p.to_byte(), byte | 0x20);
+			assert_eq!(p.to_string().parse::<StatusFlags>(), Ok(p), "{:08b}", byte);
+		}
+
+		for bad in ["", "nv-bdiz", "nv_bdizc", "nvxbdizc", "NV-BDIZCC", "cv-bdizc"] {
+			assert_eq!(bad.parse::<StatusFlags>(), Err(InvalidStatusFlags), "{}", bad);
+		}
+	}
+}
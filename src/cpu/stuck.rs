@@ -0,0 +1,182 @@
+// Stuck loop detection: test ROMs and broken programs often end in `JMP *` or in a tight loop, and a headless run
+// would spin there forever. `StuckDetector` watches the instructions, and tells when the CPU will never leave.
+//
+// | Detection | Stuck when |
+// |---|---|
+// | `SelfJump` | An instruction jumps to itself (`JMP *`, or a branch to itself): no register changes, nothing is written |
+// | `Repeated` | The CPU comes back to the same PC with the same registers `iterations` times, without writing memory |
+//
+// `SelfJump` only catches the loops of a single instruction, and is stuck right away. `Repeated` catches longer
+// loops too, like polling a flag in RAM, but a loop that polls the hardware ($2002, for example) looks the same
+// until the hardware changes: `iterations` must be long enough for that, see `DEFAULT_ITERATIONS`.
+//
+// A loop that waits for an interrupt (`JMP *` until the NMI comes) is not stuck. So when an interrupt can come (the
+// caller knows: the I flag is clear, or the PPU NMI is on), the loop must also go on for `INTERRUPT_WAIT_CYCLES`.
+// An interrupt that arrives pushes to the stack, and any write starts the detection over.
+
+use crate::cpu::cpu::CpuState;
+
+/// A loop polling $2002 (`BIT $2002`, `BPL`) is 7 cycles, so 20000 iterations are more than two frames.
+pub const DEFAULT_ITERATIONS: u32 = 20_000;
+/// Over 3 NTSC frames: an NMI would have come by then.
+pub const INTERRUPT_WAIT_CYCLES: u64 = 100_000;
+/// `Repeated` looks for a loop at a PC it saw. If the CPU doesn't come back to it in this many instructions, it's not
+/// a loop (or a too long one), and it looks at the current PC instead.
+const MAX_LOOP_INSTRUCTIONS: u32 = 64;
+
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub enum StuckDetection {
+	/// An instruction that jumps to itself.
+	SelfJump,
+	/// The same PC with the same registers, and no memory writes, for `iterations` iterations of the loop.
+	Repeated { iterations: u32 },
+}
+
+impl Default for StuckDetection {
+	fn default() -> Self {
+		StuckDetection::Repeated { iterations: DEFAULT_ITERATIONS }
+	}
+}
+
+/// Give it every instruction with `check`.
+#[derive(Clone, Debug)]
+pub struct StuckDetector {
+	detection: StuckDetection,
+	/// The state the loop started with: at the start of the self jump, or of the instruction `Repeated` waits to see
+	/// again.
+	start: Option<CpuState>,
+	/// Times the CPU came back to `start`.
+	iterations: u32,
+	/// Instructions since the CPU was last at `start`.
+	instructions: u32,
+}
+
+impl StuckDetector {
+	pub fn new(detection: StuckDetection) -> Self {
+		StuckDetector { detection, start: None, iterations: 0, instructions: 0 }
+	}
+
+	/// Call after every instruction, with the CPU state before and after it, whether it wrote memory, and whether an
+	/// interrupt can come. Returns the PC of the loop when the CPU is stuck in it.
+	pub fn check(&mut self, before: &CpuState, after: &CpuState, wrote: bool, interruptible: bool) -> Option<u16> {
+		if wrote {
+			self.start = None;
+			return None;
+		}
+		match self.detection {
+			StuckDetection::SelfJump => {
+				if !after.same_registers(before) {
+					self.start = None;
+					return None;
+				}
+				let start = *self.start.get_or_insert(*before);
+				Self::stuck(&start, after, interruptible)
+			}
+			StuckDetection::Repeated { iterations } => {
+				let Some(start) = self.start else {
+					self.restart(before);
+					return None;
+				};
+				if before.same_registers(&start) {
+					self.iterations += 1;
+					self.instructions = 0;
+					if self.iterations < iterations {
+						return None;
+					}
+					return Self::stuck(&start, before, interruptible);
+				}
+				self.instructions += 1;
+				// Back at the PC with something else changed, or never back: a loop, if any, starts over from here.
+				if before.pc == start.pc || self.instructions > MAX_LOOP_INSTRUCTIONS {
+					self.restart(before);
+				}
+				None
+			}
+		}
+	}
+
+	/// Start over, waiting to see `state` again.
+	fn restart(&mut self, state: &CpuState) {
+		self.start = Some(*state);
+		self.iterations = 0;
+		self.instructions = 0;
+	}
+
+	/// The loop from `start` to `now` ran enough. It's stuck, unless an interrupt can still come.
+	fn stuck(start: &CpuState, now: &CpuState, interruptible: bool) -> Option<u16> {
+		let waited = now.cycles - start.cycles >= INTERRUPT_WAIT_CYCLES;
+		(!interruptible || waited).then_some(start.pc)
+	}
+}
+
+#[cfg(all(test, feature = "std"))]
+mod tests {
+	use super::*;
+	use crate::bus::FlatBus;
+	use crate::cpu::cpu::{RunEnd, CPU};
+
+	/// `program` at $0600, with interrupts disabled.
+	fn cpu_with(program: &[u8]) -> CPU<FlatBus> {
+		let mut memory = [0; 65_536];
+		memory[0x0600..0x0600 + program.len()].copy_from_slice(program);
+		memory[0xFFFC] = 0x00;
+		memory[0xFFFD] = 0x06;
+		let mut cpu = CPU::new(FlatBus::new(&memory));
+		cpu.reset();
+		cpu
+	}
+
+	#[test]
+	fn self_jump_test() {
+		// JMP $0600
+		let mut cpu = cpu_with(&[0x4C, 0x00, 0x06]);
+		assert_eq!(cpu.run_until_stuck(1000, StuckDetection::SelfJump), Ok(RunEnd::StuckLoop { pc: 0x0600 }));
+		// After the 7 of the reset.
+		assert_eq!(cpu.cycles(), 7 + 3);
+
+		let mut cpu = cpu_with(&[0x4C, 0x00, 0x06]);
+		assert_eq!(cpu.run_until_stuck(INTERRUPT_WAIT_CYCLES, StuckDetection::default()), Ok(RunEnd::StuckLoop { pc: 0x0600 }));
+		assert_eq!(cpu.cycles(), 7 + 3 * (DEFAULT_ITERATIONS as u64 + 1));
+
+		// CLI, then JMP *: an interrupt could still come.
+		let mut cpu = cpu_with(&[0x58, 0x4C, 0x01, 0x06]);
+		assert_eq!(cpu.run_until_stuck(1000, StuckDetection::SelfJump), Ok(RunEnd::BudgetExhausted));
+		assert_eq!(cpu.run_until_stuck(INTERRUPT_WAIT_CYCLES, StuckDetection::SelfJump), Ok(RunEnd::StuckLoop { pc: 0x0601 }));
+	}
+
+	#[test]
+	fn repeated_test() {
+		/*
+		loop:
+		LDA $10
+		BEQ loop
+		*/
+		let mut cpu = cpu_with(&[0xA5, 0x10, 0xF0, 0xFC]);
+		let detection = StuckDetection::Repeated { iterations: 100 };
+		assert_eq!(cpu.run_until_stuck(10_000, detection), Ok(RunEnd::StuckLoop { pc: 0x0600 }));
+		assert!(cpu.cycles() < 1000, "cycles: {}", cpu.cycles());
+
+		// Not a self jump.
+		let mut cpu = cpu_with(&[0xA5, 0x10, 0xF0, 0xFC]);
+		assert_eq!(cpu.run_until_stuck(10_000, StuckDetection::SelfJump), Ok(RunEnd::BudgetExhausted));
+
+		/*
+		loop:
+		INC $10
+		JMP loop
+		*/
+		let mut cpu = cpu_with(&[0xE6, 0x10, 0x4C, 0x00, 0x06]);
+		assert_eq!(cpu.run_until_stuck(10_000, detection), Ok(RunEnd::BudgetExhausted));
+
+		/*
+		loop:
+		INX
+		BNE loop
+		done:
+		JMP done
+		*/
+		let mut cpu = cpu_with(&[0xE8, 0xD0, 0xFD, 0x4C, 0x03, 0x06]);
+		assert_eq!(cpu.run_until_stuck(10_000, detection), Ok(RunEnd::StuckLoop { pc: 0x0603 }));
+		assert_eq!(cpu.state().x, 0);
+	}
+}
@@ -0,0 +1,1066 @@
+// Interactive debugger, for --debug. It stops before the first instruction, and then reads commands:
+//
+// | Command | Description |
+// |---|---|
+// | `s [N]` | Step N instructions (default 1) |
+// | `rs [N]` | Step back N instructions (default 1), with the journal on |
+// | `journal on\|off` | Record every instruction from now on, to step back over (see journal.rs) |
+// | `c` | Continue until a breakpoint, a watchpoint, or the end of the program |
+// | `until ADDR` | Continue until PC gets to ADDR (or a breakpoint/watchpoint stops it before) |
+// | `b [ADDR]` | Add a breakpoint at ADDR. Without an address, list the breakpoints and watchpoints |
+// | `b ADDR if COND` | Add a breakpoint that only stops when COND is true, like `A == 0x20 && [$10] > 5` (see expression.rs) |
+// | `w ADDR` | Add a watchpoint: stop when the byte at ADDR changes |
+// | `watch NAME = EXPR` | Print EXPR (see expression.rs) every time the program stops, like `watch scroll = [$FD]:[$FC]` (see watch_list.rs) |
+// | `unwatch NAME` | Remove a watch |
+// | `watches` | List the watches |
+// | `d ID` | Delete breakpoint or watchpoint ID |
+// | `r` | Print the registers and the flags, and who holds the IRQ line when someone does |
+// | `m ADDR [LEN]` | Hex dump LEN bytes (default 64) from ADDR |
+// | `stack` | The bytes on the stack, from the last one pushed, with the return addresses of JSRs |
+// | `set REG VALUE` | Set a register: `A`, `X`, `Y`, `SP`, `P` (a number), or `PC` (an address) |
+// | `set flag F 0\|1` | Clear or set a flag of P: `N`, `V`, `D`, `I`, `Z` or `C` |
+// | `poke ADDR VALUE` | Write a byte, or hex bytes in quotes from ADDR on (`poke $0300 "DE AD BE EF"`) |
+// | `render N [FILE]` | Run until the PPU gets to scanline N, print its scroll, registers and sprites, and write the frame so far to FILE (a PPM image) |
+// | `u [ADDR] [N]` | Disassemble N instructions (default 10) from ADDR (default PC), with where they are in the ROM file |
+// | `banks` | The PRG and CHR banks the mapper has where, the mirroring and the IRQ counter (see `Cartridge::debug_state`) |
+// | `h` | Help |
+// | `q` | Quit |
+//
+// Addresses are hex, with or without `$` (`$8000`, `8000` or `0x8000`), or the names of symbols (`b Reset`, with
+// `--symbols`). A name wins over hex, so a label called `Add` is the label. Counts are decimal, or hex with `$`.
+// The disassembly shows the names too, like `JSR Reset`.
+// An empty line repeats the last command, so stepping is just pressing Enter.
+//
+// Watchpoints compare the byte before and after every instruction, so writing the same value doesn't stop.
+// Memory is read with `peek`, so the debugger never changes the state (no register reads with side effects). Only
+// `set` and `poke` do, and the next instruction runs with the change. `render` is for raster effects (split screens):
+// it runs to the middle of the frame, and breakpoints and watchpoints don't stop it. `poke` writes ROM too (see `Bus::poke`), and
+// registers like the CPU would, with their side effects.
+//
+// The journal keeps the last 100000 instructions. `set`, `poke` and `render` change the state without it, so they empty
+// it, and so does a reload. Instructions that accessed the PPU, the APU, the controllers or the mapper can't be stepped
+// back over (see journal.rs).
+//
+// `stack` can't know which bytes are return addresses, so it guesses: two bytes that point at the last byte of a JSR
+// are one, and show on a line of their own, with the JSR. Pushed data that happens to look like one is shown like one.
+
+use std::io::{self, BufRead, Write};
+
+use crate::bus::Bus;
+use crate::cartridge::{MapperDebugInfo, PrgLocation};
+use crate::cpu::cpu::{stack_contents, CpuState, CPU};
+use crate::cpu::decoder::{decode_opcode, Instructions};
+use crate::cpu::effects::{AccessKind, BusAccess, StepEffects};
+use crate::cpu::registers::Register;
+use crate::cpu::status::Flag;
+use crate::cpu::disassembler::{disassemble_range, disassemble_with_symbols, Disassembly};
+use crate::emulator::Emulator;
+use crate::expression::Expression;
+use crate::harness::hex_dump;
+use crate::irq::IrqLine;
+use crate::journal::{Journal, DEFAULT_JOURNAL_CAPACITY};
+use crate::memory::hex_to_bytes;
+use crate::ppu::framebuffer::Framebuffer;
+use crate::ppu::scanline::ScanlineState;
+use crate::symbols::SymbolTable;
+use crate::watch_list::WatchList;
+
+pub const HELP: &str = "\
+s [N]           step N instructions (default 1)
+rs [N]          step back N instructions (default 1), with the journal on
+journal on|off  record the instructions, to step back over
+c               continue
+until ADDR      continue until PC is ADDR
+b [ADDR]        add a breakpoint, or list the breakpoints and watchpoints
+b ADDR if COND  add a breakpoint that stops only when COND is true, like A == 0x20 && [$10] > 5
+w ADDR          add a watchpoint, stops when the byte at ADDR changes
+watch NAME = EXPR  print EXPR at every stop, like watch lives = [$075A] or [$FD]:[$FC] (16 bits)
+unwatch NAME    remove a watch
+watches         list the watches
+d ID            delete a breakpoint or watchpoint
+r               registers, flags and IRQ sources
+m ADDR [LEN]    hex dump LEN bytes (default 64)
+stack           the stack, with the return addresses of JSRs
+set REG VALUE   set a register: A, X, Y, SP, P, or PC (an address)
+set flag F 0|1  clear or set a flag: N, V, D, I, Z or C
+poke ADDR VAL   write a byte, or hex bytes in quotes (poke $0300 \"DE AD\"), ROM too
+render N [FILE] run until scanline N, and write the frame so far to FILE (PPM)
+u [ADDR] [N]    disassemble N instructions (default 10) from ADDR (default PC)
+banks           the PRG and CHR banks mapped now, the mirroring and the IRQ counter
+h               this help
+q               quit
+Addresses are hex ($8000) or symbol names. An empty line repeats the last command.";
+
+const DEFAULT_DUMP_LENGTH: u32 = 64;
+const DEFAULT_DISASSEMBLE_COUNT: usize = 10;
+
+/// What the debugger runs: the whole console, or a CPU on a bus of its own (demos and raw binaries, on flat memory or easy6502).
+pub trait DebugTarget {
+	fn cpu_state(&self) -> CpuState;
+	/// Read memory without side effects, see `Bus::peek`.
+	fn peek(&self, addr: u16) -> u8;
+	/// Execute a single instruction.
+	fn step(&mut self);
+	/// Like `step`, and return what it did, for the journal (see journal.rs).
+	fn step_with_effects(&mut self) -> StepEffects;
+	/// Whether `access` is to a device (a register), rather than memory. The journal can't step back over it.
+	fn is_device(&self, _access: &BusAccess) -> bool {
+		false
+	}
+	/// Why the program can't go on, if it can't.
+	fn halted(&self) -> Option<String> {
+		None
+	}
+	/// The devices holding the IRQ line, see `Bus::irq_sources`.
+	fn irq_sources(&self) -> IrqLine {
+		IrqLine::default()
+	}
+	fn set_cpu_state(&mut self, state: &CpuState);
+	fn set_register(&mut self, register: Register, value: u16);
+	fn set_flag(&mut self, flag: Flag, value: bool);
+	/// Write memory, ROM too, see `Bus::poke`.
+	fn poke(&mut self, addr: u16, data: u8);
+	/// Run until the PPU gets to `scanline`, see `Emulator::render_until_scanline`. Returns the state of the scanline,
+	/// and the frame so far.
+	fn render_until_scanline(&mut self, _scanline: u16) -> Result<(ScanlineState, &Framebuffer), String> {
+		Err("There is no PPU here, only the CPU".to_string())
+	}
+	/// The banks of the cartridge, see `Cartridge::debug_state`. None without one.
+	fn mapper_state(&self) -> Option<MapperDebugInfo> {
+		None
+	}
+	/// Where `addr` is in the ROM file, see `Cartridge::prg_location`.
+	fn prg_location(&self, _addr: u16) -> Option<PrgLocation> {
+		None
+	}
+	/// Called before every command of the REPL. A target that watches its ROM file (`rom_watch::WatchedEmulator`)
+	/// loads it again if it changed, and says what happened.
+	fn poll_reload(&mut self) -> Option<String> {
+		None
+	}
+}
+
+impl DebugTarget for Emulator {
+	fn cpu_state(&self) -> CpuState {
+		Emulator::cpu_state(self)
+	}
+
+	fn peek(&self, addr: u16) -> u8 {
+		Emulator::peek(self, addr)
+	}
+
+	fn step(&mut self) {
+		self.step_instruction();
+	}
+
+	fn step_with_effects(&mut self) -> StepEffects {
+		match Emulator::step_with_effects(self) {
+			Ok(effects) => effects,
+			Err(err) => panic!("{}, registers: {}", err, self.cpu_state()),
+		}
+	}
+
+	/// The PPU, APU and controller registers, and writes to the cartridge's ROM (the mapper's registers). PRG RAM is
+	/// memory.
+	fn is_device(&self, access: &BusAccess) -> bool {
+		(0x2000..0x6000).contains(&access.addr) || (access.kind == AccessKind::Write && access.addr >= 0x8000)
+	}
+
+	fn irq_sources(&self) -> IrqLine {
+		self.bus().irq_sources()
+	}
+
+	fn set_cpu_state(&mut self, state: &CpuState) {
+		Emulator::set_cpu_state(self, state);
+	}
+
+	fn set_register(&mut self, register: Register, value: u16) {
+		Emulator::set_register(self, register, value);
+	}
+
+	fn set_flag(&mut self, flag: Flag, value: bool) {
+		Emulator::set_flag(self, flag, value);
+	}
+
+	fn poke(&mut self, addr: u16, data: u8) {
+		Emulator::poke(self, addr, data);
+	}
+
+	fn render_until_scanline(&mut self, scanline: u16) -> Result<(ScanlineState, &Framebuffer), String> {
+		Emulator::render_until_scanline(self, scanline)?;
+		Ok((self.bus().ppu().scanline_state(), self.framebuffer()))
+	}
+
+	fn mapper_state(&self) -> Option<MapperDebugInfo> {
+		Some(self.bus().cartridge().debug_state())
+	}
+
+	fn prg_location(&self, addr: u16) -> Option<PrgLocation> {
+		self.bus().cartridge().prg_location(addr)
+	}
+}
+
+impl<B: Bus> DebugTarget for CPU<B> {
+	fn cpu_state(&self) -> CpuState {
+		self.state()
+	}
+
+	fn peek(&self, addr: u16) -> u8 {
+		self.bus().peek(addr)
+	}
+
+	fn step(&mut self) {
+		self.clock_tick();
+	}
+
+	fn step_with_effects(&mut self) -> StepEffects {
+		match CPU::step_with_effects(self) {
+			Ok(effects) => effects,
+			Err(err) => panic!("{}, registers: {}", err, self.state()),
+		}
+	}
+
+	/// Like `run_flat` in main: programs without the console end at a BRK (usually empty memory).
+	fn halted(&self) -> Option<String> {
+		let pc = self.state().pc;
+		(self.bus().peek(pc) == 0x00).then(|| format!("Program ended: BRK at ${:04X}", pc))
+	}
+
+	fn irq_sources(&self) -> IrqLine {
+		self.bus().irq_sources()
+	}
+
+	fn set_cpu_state(&mut self, state: &CpuState) {
+		self.set_state(state);
+	}
+
+	fn set_register(&mut self, register: Register, value: u16) {
+		CPU::set_register(self, register, value);
+	}
+
+	fn set_flag(&mut self, flag: Flag, value: bool) {
+		CPU::set_flag(self, flag, value);
+	}
+
+	fn poke(&mut self, addr: u16, data: u8) {
+		self.bus_mut().poke(addr, data);
+	}
+}
+
+/// A breakpoint condition: the text, to list it, and the parsed expression.
+#[derive(Clone, PartialEq, Eq, Debug)]
+struct Condition {
+	text: String,
+	expression: Expression,
+}
+
+#[derive(Clone, PartialEq, Eq, Debug)]
+enum Point {
+	/// Stops only when the condition (if there is one) is true.
+	Breakpoint(u16, Option<Condition>),
+	/// The address, and the value it had after the last instruction.
+	Watchpoint(u16, u8),
+}
+
+/// Why running stopped.
+#[derive(Clone, PartialEq, Eq, Debug)]
+pub enum Stop {
+	/// Executed all the steps it was asked to.
+	Stepped,
+	Breakpoint { id: u32, addr: u16 },
+	Watchpoint { id: u32, addr: u16, old: u8, new: u8 },
+	Until(u16),
+	Halted(String),
+	/// The instruction didn't change any register, so it will never get anywhere (like `JMP` to itself).
+	Jammed,
+}
+
+/// What a command wants the REPL to do.
+#[derive(Clone, PartialEq, Eq, Debug)]
+pub enum Reply {
+	Print(String),
+	Quit,
+}
+
+#[derive(Default)]
+pub struct Debugger {
+	points: Vec<(u32, Point)>,
+	next_id: u32,
+	last_command: String,
+	symbols: SymbolTable,
+	watches: WatchList,
+	/// None until `journal on`.
+	journal: Option<Journal>,
+}
+
+impl Debugger {
+	pub fn new() -> Self {
+		Debugger { next_id: 1, ..Default::default() }
+	}
+
+	/// Names for addresses: shown in the disassembly, and accepted instead of addresses.
+	pub fn set_symbols(&mut self, symbols: SymbolTable) {
+		self.symbols = symbols;
+	}
+
+	/// Returns the ID of the breakpoint.
+	pub fn add_breakpoint(&mut self, addr: u16) -> u32 {
+		self.add(Point::Breakpoint(addr, None))
+	}
+
+	/// Add a breakpoint that stops only when `condition` is true. Returns the ID, or why the condition can't be parsed.
+	pub fn add_conditional_breakpoint(&mut self, addr: u16, condition: &str) -> Result<u32, String> {
+		let expression = Expression::parse(condition, &self.symbols)?;
+		Ok(self.add(Point::Breakpoint(addr, Some(Condition { text: condition.to_string(), expression }))))
+	}
+
+	/// Returns the ID of the watchpoint.
+	pub fn add_watchpoint<T: DebugTarget>(&mut self, target: &T, addr: u16) -> u32 {
+		self.add(Point::Watchpoint(addr, target.peek(addr)))
+	}
+
+	fn add(&mut self, point: Point) -> u32 {
+		let id = self.next_id;
+		self.next_id += 1;
+		self.points.push((id, point));
+		id
+	}
+
+	/// Returns false if there is no breakpoint or watchpoint `id`.
+	pub fn delete(&mut self, id: u32) -> bool {
+		let count = self.points.len();
+		self.points.retain(|&(point_id, _)| point_id != id);
+		self.points.len() != count
+	}
+
+	/// Run until a breakpoint or a watchpoint, PC gets to `until`, or `max_steps` instructions were executed.
+	/// At least one instruction is executed (if the program didn't end), so continuing from a breakpoint works.
+	pub fn run<T: DebugTarget>(&mut self, target: &mut T, max_steps: Option<u64>, until: Option<u16>) -> Stop {
+		let mut steps = 0;
+		loop {
+			if let Some(reason) = target.halted() {
+				return Stop::Halted(reason);
+			}
+
+			let before = target.cpu_state();
+			match self.journal.as_mut() {
+				Some(journal) => {
+					let effects = target.step_with_effects();
+					journal.record(&effects, |access| target.is_device(access));
+				}
+				None => target.step(),
+			}
+			steps += 1;
+
+			if let Some(stop) = self.check_watchpoints(target) {
+				return stop;
+			}
+			let pc = target.cpu_state().pc;
+			if until == Some(pc) {
+				return Stop::Until(pc);
+			}
+			if let Some(id) = self.breakpoint_at(target, pc) {
+				return Stop::Breakpoint { id, addr: pc };
+			}
+			if target.cpu_state().same_registers(&before) {
+				return Stop::Jammed;
+			}
+			if max_steps == Some(steps) {
+				return Stop::Stepped;
+			}
+		}
+	}
+
+	/// The first breakpoint at `pc` that stops: without a condition, or with a condition that is true now.
+	fn breakpoint_at<T: DebugTarget>(&self, target: &T, pc: u16) -> Option<u32> {
+		let state = target.cpu_state();
+		let peek = |addr| target.peek(addr);
+		self.points.iter().find_map(|(id, point)| match point {
+			Point::Breakpoint(addr, None) if *addr == pc => Some(*id),
+			Point::Breakpoint(addr, Some(condition)) if *addr == pc && condition.expression.is_true(&state, &peek) => Some(*id),
+			_ => None,
+		})
+	}
+
+	/// After the program was replaced (`Emulator::insert_cartridge`): the breakpoints, watchpoints, watches and symbols
+	/// stay, and the watchpoints and watches start from the values of the new program, so the reload doesn't stop them
+	/// (or mark them changed).
+	pub fn program_reloaded<T: DebugTarget>(&mut self, target: &T) {
+		self.forget_journal();
+		self.check_watchpoints(target);
+		self.watches.refresh(&target.cpu_state(), &|addr| target.peek(addr));
+	}
+
+	/// Record the instructions from now on, to step back over (see journal.rs).
+	pub fn enable_journal(&mut self, capacity: usize) {
+		self.journal = Some(Journal::new(capacity));
+	}
+
+	/// Step back up to `count` instructions. Returns how many, and why it stopped before `count` if it did.
+	pub fn step_back<T: DebugTarget>(&mut self, target: &mut T, count: u32) -> Result<(u32, Option<String>), String> {
+		let journal = self.journal.as_mut().ok_or("The journal is off: 'journal on' records the instructions to step back over")?;
+		let mut steps = 0;
+		let mut stop = None;
+		while steps < count {
+			if let Err(reason) = journal.step_back(target) {
+				stop = Some(reason);
+				break;
+			}
+			steps += 1;
+		}
+		// Going back is not a change for the watchpoints to stop at.
+		self.check_watchpoints(target);
+		Ok((steps, stop))
+	}
+
+	/// The state changed without the journal: it can't step back over that.
+	fn forget_journal(&mut self) {
+		if let Some(journal) = self.journal.as_mut() {
+			journal.clear();
+		}
+	}
+
+	/// Update the values of all the watchpoints, and return the first one that changed.
+	fn check_watchpoints<T: DebugTarget>(&mut self, target: &T) -> Option<Stop> {
+		let mut stop = None;
+		for (id, point) in self.points.iter_mut() {
+			if let Point::Watchpoint(addr, old) = point {
+				let new = target.peek(*addr);
+				if new != *old && stop.is_none() {
+					stop = Some(Stop::Watchpoint { id: *id, addr: *addr, old: *old, new });
+				}
+				*old = new;
+			}
+		}
+		stop
+	}
+
+	/// Execute a single command line. Errors are bad input, the debugger can go on after them.
+	pub fn execute<T: DebugTarget>(&mut self, target: &mut T, line: &str) -> Result<Reply, String> {
+		let line = if line.trim().is_empty() { self.last_command.clone() } else { line.trim().to_string() };
+		self.last_command = line.clone();
+
+		let mut words = line.split_whitespace();
+		let Some(command) = words.next() else {
+			return Ok(Reply::Print(String::new()));
+		};
+		let args: Vec<&str> = words.collect();
+
+		let output = match (command, args.as_slice()) {
+			("s" | "step", []) => self.step_message(target, 1),
+			("s" | "step", [count]) => {
+				let count = parse_count(count)?;
+				if count == 0 {
+					return Err("Step count must be at least 1".to_string());
+				}
+				self.step_message(target, count as u64)
+			}
+			("rs" | "reverse-step", args) if args.len() <= 1 => {
+				let count = args.first().map_or(Ok(1), |count| parse_count(count))?;
+				if count == 0 {
+					return Err("Step count must be at least 1".to_string());
+				}
+				let text = match self.step_back(target, count)? {
+					(_, None) => self.current(target),
+					(0, Some(reason)) => return Err(reason),
+					(steps, Some(reason)) => format!("Stepped back {} of {}: {}\n{}", steps, count, reason, self.current(target)),
+				};
+				self.stopped(target, text)
+			}
+			("journal", ["on"]) => {
+				if self.journal.is_none() {
+					self.enable_journal(DEFAULT_JOURNAL_CAPACITY);
+				}
+				format!("Journal on: 'rs' steps back up to {} instructions", DEFAULT_JOURNAL_CAPACITY)
+			}
+			("journal", ["off"]) => {
+				self.journal = None;
+				"Journal off".to_string()
+			}
+			("c" | "continue", []) => {
+				let stop = self.run(target, None, None);
+				let text = format!("{}\n{}", describe(&stop), self.current(target));
+				self.stopped(target, text)
+			}
+			("until", [addr]) => {
+				let addr = self.parse_address(addr)?;
+				let stop = self.run(target, None, Some(addr));
+				let text = format!("{}\n{}", describe(&stop), self.current(target));
+				self.stopped(target, text)
+			}
+			("b" | "break", []) => self.list(),
+			("b" | "break", [addr]) => {
+				let addr = self.parse_address(addr)?;
+				format!("Breakpoint {} at ${:04X}", self.add_breakpoint(addr), addr)
+			}
+			("b" | "break", [addr, "if", condition @ ..]) if !condition.is_empty() => {
+				let addr = self.parse_address(addr)?;
+				let condition = condition.join(" ");
+				format!("Breakpoint {} at ${:04X} if {}", self.add_conditional_breakpoint(addr, &condition)?, addr, condition)
+			}
+			("w" | "watch", args) if args.iter().any(|arg| arg.contains('=')) => {
+				let definition = args.join(" ");
+				let (name, text) = definition.split_once('=').expect("There is a '='");
+				let state = target.cpu_state();
+				self.watches.add(name.trim(), text.trim(), &self.symbols, &state, &|addr| target.peek(addr))?;
+				self.watches.to_string()
+			}
+			("unwatch", [name]) => {
+				if !self.watches.remove(name) {
+					return Err(format!("There is no watch called '{}'", name));
+				}
+				format!("Removed {}", name)
+			}
+			("watches", []) if self.watches.is_empty() => "No watches".to_string(),
+			("watches", []) => self.watches.definitions(),
+			("w" | "watch", [addr]) => {
+				let addr = self.parse_address(addr)?;
+				format!("Watchpoint {} at ${:04X} (now ${:02X})", self.add_watchpoint(target, addr), addr, target.peek(addr))
+			}
+			("d" | "delete", [id]) => {
+				let id = id.parse().map_err(|_| format!("Expected a breakpoint or watchpoint ID, got '{}'", id))?;
+				if !self.delete(id) {
+					return Err(format!("There is no breakpoint or watchpoint {}", id));
+				}
+				format!("Deleted {}", id)
+			}
+			("r" | "registers", []) => registers(&target.cpu_state(), target.irq_sources()),
+			("m" | "memory", [addr, rest @ ..]) if rest.len() <= 1 => {
+				let start = self.parse_address(addr)?;
+				let length = rest.first().map_or(Ok(DEFAULT_DUMP_LENGTH), |length| parse_count(length))?;
+				if length == 0 {
+					return Err("Length must be at least 1".to_string());
+				}
+				let end = (start as u32 + length - 1).min(0xFFFF) as u16;
+				hex_dump(start, end, |addr| target.peek(addr)).trim_end().to_string()
+			}
+			("stack", []) => self.stack(target),
+			("u" | "disassemble", args) if args.len() <= 2 => {
+				let addr = args.first().map_or(Ok(target.cpu_state().pc), |addr| self.parse_address(addr))?;
+				let count = args.get(1).map_or(Ok(DEFAULT_DISASSEMBLE_COUNT as u32), |count| parse_count(count))? as usize;
+				let lines: Vec<String> = disassemble_range(addr, count, |addr| target.peek(addr), Some(&self.symbols)).iter().map(|line| {
+					let text = line.to_string();
+					match target.prg_location(line.addr) {
+						Some(location) => format!("{:<40}; {}", text, location),
+						None => text,
+					}
+				}).collect();
+				lines.join("\n")
+			}
+			("banks", []) => target.mapper_state().ok_or("There is no cartridge here, only the CPU")?.to_string(),
+			("set", ["flag", letter, value]) => {
+				let flag = parse_flag(letter)?;
+				let value = match *value {
+					"0" => false,
+					"1" => true,
+					_ => return Err(format!("A flag is 0 or 1, got '{}'", value)),
+				};
+				target.set_flag(flag, value);
+				self.forget_journal();
+				registers(&target.cpu_state(), target.irq_sources())
+			}
+			("set", [register, value]) => {
+				let register: Register = register.parse()
+					.map_err(|_| format!("Unknown register '{}', expected A, X, Y, SP, P or PC", register))?;
+				let value = match register {
+					Register::PC => self.parse_address(value)?,
+					_ => parse_byte(value)? as u16,
+				};
+				target.set_register(register, value);
+				self.forget_journal();
+				registers(&target.cpu_state(), target.irq_sources())
+			}
+			("poke", [addr, value @ ..]) if !value.is_empty() => {
+				let start = self.parse_address(addr)?;
+				let value = value.join(" ");
+				let data = match value.strip_prefix('"').and_then(|value| value.strip_suffix('"')) {
+					Some(bytes) => hex_to_bytes(bytes)?,
+					None => vec![parse_byte(&value)?],
+				};
+				if data.is_empty() || start as usize + data.len() > 0x10000 {
+					return Err(format!("Expected bytes from ${:04X} up to $FFFF", start));
+				}
+				for (addr, &byte) in (start..=0xFFFF).zip(&data) {
+					target.poke(addr, byte);
+				}
+				// The poke is not a change for the watchpoints to stop at.
+				self.check_watchpoints(target);
+				self.forget_journal();
+				let end = start + (data.len() - 1) as u16;
+				hex_dump(start, end, |addr| target.peek(addr)).trim_end().to_string()
+			}
+			("render" | "render_until_scanline", [scanline, file @ ..]) if file.len() <= 1 => {
+				let scanline = parse_count(scanline)?;
+				let scanline = u16::try_from(scanline).map_err(|_| format!("Scanline {} is not in a frame", scanline))?;
+				self.forget_journal();
+				let (state, frame) = target.render_until_scanline(scanline)?;
+				let mut text = state.to_string();
+				if let Some(path) = file.first() {
+					std::fs::write(path, frame.to_ppm()).map_err(|err| format!("Can't write {}: {}", path, err))?;
+					text += &format!("\nThe frame so far is in {}", path);
+				}
+				self.check_watchpoints(target);
+				let text = format!("{}\n{}", text, self.current(target));
+				self.stopped(target, text)
+			}
+			("h" | "help", []) => HELP.to_string(),
+			("q" | "quit", []) => return Ok(Reply::Quit),
+			("s" | "step" | "rs" | "reverse-step" | "journal" | "c" | "continue" | "until" | "b" | "break" | "w" | "watch" | "unwatch" | "watches" | "d" | "delete" | "r" | "registers"
+				| "m" | "memory" | "stack" | "u" | "disassemble" | "banks" | "set" | "poke" | "render" | "render_until_scanline" | "h" | "help" | "q" | "quit", _) => {
+				return Err(format!("Wrong arguments for '{}', type 'h' for help", command));
+			}
+			_ => return Err(format!("Unknown command '{}', type 'h' for help", command)),
+		};
+		Ok(Reply::Print(output))
+	}
+
+	/// Step `count` instructions, and describe where it stopped.
+	fn step_message<T: DebugTarget>(&mut self, target: &mut T, count: u64) -> String {
+		let text = match self.run(target, Some(count), None) {
+			Stop::Stepped => self.current(target),
+			stop => format!("{}\n{}", describe(&stop), self.current(target)),
+		};
+		self.stopped(target, text)
+	}
+
+	/// `text` about where the program stopped, and then the watches, evaluated again.
+	fn stopped<T: DebugTarget>(&mut self, target: &T, text: String) -> String {
+		if self.watches.is_empty() {
+			return text;
+		}
+		self.watches.update(&target.cpu_state(), &|addr| target.peek(addr));
+		format!("{}\n{}", text, self.watches)
+	}
+
+	fn list(&self) -> String {
+		if self.points.is_empty() {
+			return "No breakpoints or watchpoints".to_string();
+		}
+		let lines: Vec<String> = self.points.iter().map(|(id, point)| match point {
+			Point::Breakpoint(addr, None) => format!("{}: breakpoint at ${:04X}", id, addr),
+			Point::Breakpoint(addr, Some(condition)) => format!("{}: breakpoint at ${:04X} if {}", id, addr, condition.text),
+			Point::Watchpoint(addr, value) => format!("{}: watchpoint at ${:04X} (now ${:02X})", id, addr, value),
+		}).collect();
+		lines.join("\n")
+	}
+
+	/// The stack, from the last byte pushed up to $01FF, a line per byte. Return addresses take a line for both bytes.
+	fn stack<T: DebugTarget>(&self, target: &T) -> String {
+		let s = target.cpu_state().s;
+		let mut bytes = stack_contents(s, |addr| target.peek(addr));
+		if bytes.is_empty() {
+			return format!("The stack is empty (SP:{:02X})", s);
+		}
+		// From the top.
+		bytes.reverse();
+		let mut lines = vec![format!("SP:{:02X}", s)];
+		let mut addr = 0x100 + s as u16 + 1;
+		let mut rest = bytes.as_slice();
+		while let Some((&low, after)) = rest.split_first() {
+			let frame = after.first().and_then(|&high| Some((high, self.return_from(target, u16::from_le_bytes([low, high]))?)));
+			match frame {
+				Some((high, jsr)) => {
+					let pushed = u16::from_le_bytes([low, high]);
+					lines.push(format!("${:04X}: {:02X} {:02X}  return to ${:04X}, after {} at ${:04X}", addr, low, high, pushed.wrapping_add(1), jsr.text, jsr.addr));
+					addr += 2;
+					rest = &after[1..];
+				}
+				None => {
+					lines.push(format!("${:04X}: {:02X}", addr, low));
+					addr += 1;
+					rest = after;
+				}
+			}
+		}
+		lines.join("\n")
+	}
+
+	/// The JSR that pushed `pushed`, if there is one: a JSR pushes the address of its last byte.
+	fn return_from<T: DebugTarget>(&self, target: &T, pushed: u16) -> Option<Disassembly> {
+		let addr = pushed.wrapping_sub(2);
+		let (instr, ..) = decode_opcode(target.peek(addr))?;
+		(instr == Instructions::JSR).then(|| disassemble_with_symbols(addr, |addr| target.peek(addr), Some(&self.symbols)))
+	}
+
+	/// The next instruction, and the registers.
+	fn current<T: DebugTarget>(&self, target: &T) -> String {
+		let state = target.cpu_state();
+		let instruction = disassemble_with_symbols(state.pc, |addr| target.peek(addr), Some(&self.symbols));
+		format!("{:<32}A:{:02X} X:{:02X} Y:{:02X} P:{:02X} SP:{:02X}", instruction.to_string(), state.a, state.x, state.y, state.p, state.s)
+	}
+
+	/// A symbol, or hex with or without `$`/`0x`.
+	fn parse_address(&self, value: &str) -> Result<u16, String> {
+		if let Some(addr) = self.symbols.resolve(value) {
+			return Ok(addr);
+		}
+		let hex = value.strip_prefix('$').or_else(|| value.strip_prefix("0x")).unwrap_or(value);
+		u16::from_str_radix(hex, 16).map_err(|_| format!("Expected a hex address like $8000 or a symbol, got '{}'", value))
+	}
+
+	/// Read commands from `input` until `q` or the end of the input, and write the replies to `output`.
+	pub fn repl<T: DebugTarget>(&mut self, target: &mut T, input: impl BufRead, mut output: impl Write) -> io::Result<()> {
+		writeln!(output, "Type 'h' for help")?;
+		writeln!(output, "{}", self.current(target))?;
+		write!(output, "(nes) ")?;
+		output.flush()?;
+
+		for line in input.lines() {
+			let line = line?;
+			if let Some(message) = target.poll_reload() {
+				self.program_reloaded(target);
+				writeln!(output, "{}", message)?;
+			}
+			match self.execute(target, &line) {
+				Ok(Reply::Print(text)) if text.is_empty() => {}
+				Ok(Reply::Print(text)) => writeln!(output, "{}", text)?,
+				Ok(Reply::Quit) => return Ok(()),
+				Err(message) => writeln!(output, "error: {}", message)?,
+			}
+			write!(output, "(nes) ")?;
+			output.flush()?;
+		}
+		writeln!(output)
+	}
+}
+
+fn describe(stop: &Stop) -> String {
+	match stop {
+		Stop::Stepped => "Stepped".to_string(),
+		Stop::Breakpoint { id, addr } => format!("Breakpoint {} at ${:04X}", id, addr),
+		Stop::Watchpoint { id, addr, old, new } => format!("Watchpoint {}: ${:04X} changed from ${:02X} to ${:02X}", id, addr, old, new),
+		Stop::Until(addr) => format!("Got to ${:04X}", addr),
+		Stop::Halted(reason) => reason.clone(),
+		Stop::Jammed => "CPU jammed: the last instruction didn't change anything".to_string(),
+	}
+}
+
+/// Registers, and the flags as letters: uppercase is set, like `NV-bdIZc`. Then who holds the IRQ line, if anyone.
+fn registers(state: &CpuState, irq: IrqLine) -> String {
+	let mut text = format!("PC:{:04X} A:{:02X} X:{:02X} Y:{:02X} SP:{:02X} P:{:02X} {}\nCycles: {}", state.pc, state.a, state.x, state.y, state.s, state.p, state.flags(), state.cycles);
+	if irq.is_asserted() {
+		text += &format!("\nIRQ: {}", irq);
+	}
+	text
+}
+
+/// Decimal, or hex with `$`/`0x`.
+fn parse_count(value: &str) -> Result<u32, String> {
+	let parsed = match value.strip_prefix('$').or_else(|| value.strip_prefix("0x")) {
+		Some(hex) => u32::from_str_radix(hex, 16),
+		None => value.parse(),
+	};
+	parsed.map_err(|_| format!("Expected a number, got '{}'", value))
+}
+
+/// A byte: decimal, or hex with `$`/`0x`.
+fn parse_byte(value: &str) -> Result<u8, String> {
+	let number = parse_count(value)?;
+	u8::try_from(number).map_err(|_| format!("Expected a byte, got {}", value))
+}
+
+/// A flag of P, by its letter. B is not in the register, only in its copies on the stack.
+fn parse_flag(letter: &str) -> Result<Flag, String> {
+	let mut chars = letter.chars();
+	match (chars.next().and_then(Flag::from_letter), chars.next()) {
+		(Some(flag), None) if flag != Flag::BREAK => Ok(flag),
+		_ => Err(format!("Unknown flag '{}', expected N, V, D, I, Z or C", letter)),
+	}
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+	use crate::bus::FlatBus;
+	use crate::cartridge::{test_rom, Cartridge};
+	use crate::program_loader::{load_program_adc, load_program_stack, load_program_tolower};
+
+	fn tolower() -> CPU<FlatBus> {
+		let mut memory = [0; 65_536];
+		load_program_tolower(&mut memory);
+		let mut cpu = CPU::new(FlatBus::new(&memory));
+		cpu.reset();
+		cpu
+	}
+
+	fn print(reply: Result<Reply, String>) -> String {
+		match reply {
+			Ok(Reply::Print(text)) => text,
+			other => panic!("Expected text, got {:?}", other),
+		}
+	}
+
+	#[test]
+	fn step_test() {
+		let mut cpu = tolower();
+		let mut debugger = Debugger::new();
+
+		assert!(print(debugger.execute(&mut cpu, "s")).starts_with("$0602  BD 40 06  LDA $0640,X"));
+		// An empty line repeats the step.
+		assert!(print(debugger.execute(&mut cpu, "")).starts_with("$0605  F0 12     BEQ $0619"));
+		print(debugger.execute(&mut cpu, "s 3"));
+		assert_eq!(cpu.state().pc, 0x060B);
+	}
+
+	#[test]
+	fn set_and_poke_test() {
+		let mut cpu = tolower();
+		let mut debugger = Debugger::new();
+
+		assert_eq!(print(debugger.execute(&mut cpu, "set a 0x10")), "PC:0600 A:10 X:00 Y:00 SP:FF P:24 nv-bdIzc\nCycles: 7");
+		print(debugger.execute(&mut cpu, "set pc $0602"));
+		print(debugger.execute(&mut cpu, "set X 3"));
+		assert_eq!(print(debugger.execute(&mut cpu, "set flag c 1")), "PC:0602 A:10 X:03 Y:00 SP:FF P:25 nv-bdIzC\nCycles: 7");
+		assert!(debugger.execute(&mut cpu, "set q 1").is_err());
+		assert!(debugger.execute(&mut cpu, "set a 256").is_err());
+		assert!(debugger.execute(&mut cpu, "set flag b 1").is_err());
+		assert!(debugger.execute(&mut cpu, "set flag z 2").is_err());
+
+		// The next step reads the poked byte: LDA $0640,X.
+		print(debugger.execute(&mut cpu, "w $0643"));
+		assert_eq!(print(debugger.execute(&mut cpu, "poke $0643 0xFF")), "$0643: FF");
+		assert_eq!(print(debugger.execute(&mut cpu, "poke $0300 \"DE AD BE EF\"")), "$0300: DE AD BE EF");
+		assert_eq!(cpu.bus().peek(0x0303), 0xEF);
+		// The watchpoint doesn't stop at the poke.
+		assert!(print(debugger.execute(&mut cpu, "s")).starts_with("$0605"));
+		assert_eq!(cpu.state().a, 0xFF);
+
+		assert!(debugger.execute(&mut cpu, "poke $0300 0x100").is_err());
+		assert!(debugger.execute(&mut cpu, "poke $FFFF \"01 02\"").is_err());
+		assert!(debugger.execute(&mut cpu, "poke $0300 \"XY\"").is_err());
+	}
+
+	#[test]
+	fn breakpoint_test() {
+		let mut cpu = tolower();
+		let mut debugger = Debugger::new();
+
+		// Every time around the loop, X goes up by one.
+		assert_eq!(print(debugger.execute(&mut cpu, "b $0616")), "Breakpoint 1 at $0616");
+		for x in 1..=3 {
+			let text = print(debugger.execute(&mut cpu, "c"));
+			assert!(text.starts_with("Breakpoint 1 at $0616\n$0616  4C 02 06  JMP $0602"), "{}", text);
+			assert_eq!(cpu.state().x, x);
+		}
+
+		// Until stops at its address, unless a breakpoint is first.
+		print(debugger.execute(&mut cpu, "until 0612"));
+		assert_eq!(cpu.state().pc, 0x0612);
+		assert_eq!(print(debugger.execute(&mut cpu, "d 1")), "Deleted 1");
+		assert!(debugger.execute(&mut cpu, "d 1").is_err());
+
+		// Without breakpoints it runs to the end of the string.
+		assert!(print(debugger.execute(&mut cpu, "c")).starts_with("Program ended: BRK at $061C"));
+		let output: Vec<u8> = (0x0680..0x0692).map(|addr| cpu.bus().peek(addr)).collect();
+		assert_eq!(output, b"hello, world! 6502");
+	}
+
+	#[test]
+	fn watchpoint_test() {
+		let mut cpu = tolower();
+		let mut debugger = Debugger::new();
+
+		debugger.execute(&mut cpu, "w $0681").unwrap();
+		let text = print(debugger.execute(&mut cpu, "c"));
+		assert!(text.starts_with("Watchpoint 1: $0681 changed from $00 to $65"), "{}", text);
+		assert_eq!(cpu.state().pc, 0x0615);
+		assert_eq!(print(debugger.execute(&mut cpu, "b")), "1: watchpoint at $0681 (now $65)");
+	}
+
+	#[test]
+	fn inspect_test() {
+		let mut cpu = tolower();
+		let mut debugger = Debugger::new();
+
+		assert_eq!(print(debugger.execute(&mut cpu, "m 640 5")), "$0640: 48 65 6C 6C 6F");
+		assert_eq!(print(debugger.execute(&mut cpu, "u $0600 2")), "$0600  A2 00     LDX #$00\n$0602  BD 40 06  LDA $0640,X");
+		assert_eq!(print(debugger.execute(&mut cpu, "r")), "PC:0600 A:00 X:00 Y:00 SP:FF P:24 nv-bdIzc\nCycles: 7");
+	}
+
+	#[test]
+	fn mapper_banks_test() {
+		// NROM-128: the 16KB are at $C000 too.
+		let mut emulator = Emulator::new(Cartridge::from_ines(&test_rom::nrom("A9 01 EA")).unwrap());
+		let mut debugger = Debugger::new();
+		assert_eq!(print(debugger.execute(&mut emulator, "u $8000 2")), "\
+			$8000  A9 01     LDA #$01               ; PRG bank 0, file offset $10\n\
+			$8002  EA        NOP                    ; PRG bank 0, file offset $12");
+		assert!(print(debugger.execute(&mut emulator, "banks")).starts_with("Mapper 0\nPRG: $8000 16KB bank 0, $C000 16KB bank 0\n"));
+		assert_eq!(print(debugger.execute(&mut emulator, "u $0200 1")), "$0200  00        BRK");
+
+		assert!(debugger.execute(&mut tolower(), "banks").is_err());
+	}
+
+	#[test]
+	fn stack_test() {
+		let mut memory = [0; 65_536];
+		load_program_stack(&mut memory);
+		let mut cpu = CPU::new(FlatBus::new(&memory));
+		cpu.reset();
+		let mut debugger = Debugger::new();
+
+		assert_eq!(print(debugger.execute(&mut cpu, "stack")), "The stack is empty (SP:FF)");
+		// LDA, PHA, LDA, PHA: data, that doesn't point after a JSR.
+		debugger.execute(&mut cpu, "s 4").unwrap();
+		assert_eq!(print(debugger.execute(&mut cpu, "stack")), "SP:FD\n$01FE: AB\n$01FF: 8C");
+
+		/*
+		LDA #$42
+		JSR sub
+		BRK
+		...
+		sub:
+		PHA
+		PLA
+		RTS
+		*/
+		let mut memory = [0; 65_536];
+		memory[0x0600..0x0606].copy_from_slice(&[0xA9, 0x42, 0x20, 0x10, 0x06, 0x00]);
+		memory[0x0610..0x0613].copy_from_slice(&[0x48, 0x68, 0x60]);
+		memory[0xFFFC..0xFFFE].copy_from_slice(&[0x00, 0x06]);
+		let mut cpu = CPU::new(FlatBus::new(&memory));
+		cpu.reset();
+		debugger.set_symbols(SymbolTable::parse_nl("$0610#sub#").unwrap());
+		debugger.execute(&mut cpu, "s 3").unwrap();
+		assert_eq!(print(debugger.execute(&mut cpu, "stack")), "SP:FC\n$01FD: 42\n$01FE: 04 06  return to $0605, after JSR sub at $0602");
+		debugger.execute(&mut cpu, "s 2").unwrap();
+		assert_eq!(cpu.state().pc, 0x0605);
+		assert_eq!(print(debugger.execute(&mut cpu, "stack")), "The stack is empty (SP:FF)");
+		assert!(debugger.execute(&mut cpu, "stack 1").is_err());
+	}
+
+	#[test]
+	fn conditional_breakpoint_test() {
+		// loop: CLC, ADC #$01, INC $10, JMP loop
+		let mut memory = [0; 65_536];
+		memory[0x0600..0x060A].copy_from_slice(&[0x18, 0x69, 0x01, 0xE6, 0x10, 0x4C, 0x00, 0x06, 0x00, 0x00]);
+		memory[0xFFFC..].copy_from_slice(&[0x00, 0x06, 0x00, 0x00]);
+		let mut cpu = CPU::new(FlatBus::new(&memory));
+		cpu.reset();
+		let mut debugger = Debugger::new();
+
+		// Stops only in the iteration where A gets to $20, not in the 31 before.
+		assert_eq!(print(debugger.execute(&mut cpu, "b $0603 if A == 0x20")), "Breakpoint 1 at $0603 if A == 0x20");
+		let text = print(debugger.execute(&mut cpu, "c"));
+		assert!(text.starts_with("Breakpoint 1 at $0603"), "{}", text);
+		assert_eq!(cpu.state().a, 0x20);
+		assert_eq!(cpu.bus().peek(0x10), 0x1F);
+
+		// Memory, and && with a flag. The old breakpoint doesn't stop again until A wraps around.
+		debugger.execute(&mut cpu, "b $0605 if [$10] >= 0x30 && !C").unwrap_err();
+		debugger.execute(&mut cpu, "b $0605 if [$10] >= 0x30 && C == 0").unwrap();
+		let text = print(debugger.execute(&mut cpu, "c"));
+		assert!(text.starts_with("Breakpoint 2 at $0605"), "{}", text);
+		assert_eq!(cpu.bus().peek(0x10), 0x30);
+		assert_eq!(print(debugger.execute(&mut cpu, "b")), "1: breakpoint at $0603 if A == 0x20\n2: breakpoint at $0605 if [$10] >= 0x30 && C == 0");
+
+		// Bad conditions are errors when the breakpoint is set, and no breakpoint is added.
+		assert!(debugger.execute(&mut cpu, "b $0600 if A ==").unwrap_err().contains("Expected a value"));
+		assert!(debugger.execute(&mut cpu, "b $0600 if").is_err());
+		assert!(debugger.execute(&mut cpu, "b $0600 if Q > 1").is_err());
+		assert_eq!(debugger.points.len(), 2);
+	}
+
+	#[test]
+	fn watch_list_test() {
+		// loop: CLC, ADC #$01, INC $10, JMP loop
+		let mut memory = [0; 65_536];
+		memory[0x0600..0x0608].copy_from_slice(&[0x18, 0x69, 0x01, 0xE6, 0x10, 0x4C, 0x00, 0x06]);
+		memory[0xFFFC..0xFFFE].copy_from_slice(&[0x00, 0x06]);
+		let mut cpu = CPU::new(FlatBus::new(&memory));
+		cpu.reset();
+		let mut debugger = Debugger::new();
+
+		assert_eq!(print(debugger.execute(&mut cpu, "watches")), "No watches");
+		assert_eq!(print(debugger.execute(&mut cpu, "watch count = [$10]")), "  count = $00 (0)");
+		assert_eq!(print(debugger.execute(&mut cpu, "watch sum = A:[$10]")), "  count = $00 (0)\n  sum = $00 (0)");
+		// CLC: nothing changed. ADC: only the sum.
+		assert!(print(debugger.execute(&mut cpu, "s")).ends_with("\n  count = $00 (0)\n  sum = $00 (0)"));
+		assert!(print(debugger.execute(&mut cpu, "s")).ends_with("\n  count = $00 (0)\n* sum = $0100 (256), was $00"));
+		assert_eq!(debugger.watches.changed("count"), Some(false));
+		// INC $10: both.
+		let text = print(debugger.execute(&mut cpu, "s"));
+		assert!(text.ends_with("\n* count = $01 (1), was $00\n* sum = $0101 (257), was $0100"), "{}", text);
+		assert_eq!(debugger.watches.changed("count"), Some(true));
+		// JMP: neither, anymore.
+		debugger.execute(&mut cpu, "s").unwrap();
+		assert_eq!(debugger.watches.changed("count"), Some(false));
+
+		// A breakpoint stop prints them too.
+		debugger.execute(&mut cpu, "b $0605").unwrap();
+		let text = print(debugger.execute(&mut cpu, "c"));
+		assert!(text.starts_with("Breakpoint 1 at $0605") && text.contains("* count = $02 (2), was $01"), "{}", text);
+
+		assert_eq!(print(debugger.execute(&mut cpu, "watches")), "count = [$10]\nsum = A:[$10]");
+		assert_eq!(print(debugger.execute(&mut cpu, "unwatch sum")), "Removed sum");
+		assert!(debugger.execute(&mut cpu, "unwatch sum").is_err());
+		assert!(debugger.execute(&mut cpu, "watch bad = [$10").is_err());
+		assert!(debugger.execute(&mut cpu, "watch = [$10]").is_err());
+		assert_eq!(print(debugger.execute(&mut cpu, "watches")), "count = [$10]");
+		// Watchpoints are still `w ADDR`.
+		assert_eq!(print(debugger.execute(&mut cpu, "w $10")), "Watchpoint 2 at $0010 (now $02)");
+	}
+
+	#[test]
+	fn symbols_test() {
+		let mut cpu = tolower();
+		let mut debugger = Debugger::new();
+		debugger.set_symbols(SymbolTable::parse_nl("$0600#Reset#\n$0602#loop#\n$0616#next#").unwrap());
+
+		assert_eq!(print(debugger.execute(&mut cpu, "b Reset")), "Breakpoint 1 at $0600");
+		assert_eq!(print(debugger.execute(&mut cpu, "b next")), "Breakpoint 2 at $0616");
+		let text = print(debugger.execute(&mut cpu, "c"));
+		assert!(text.starts_with("Breakpoint 2 at $0616\n$0616  4C 02 06  JMP loop"), "{}", text);
+		assert_eq!(print(debugger.execute(&mut cpu, "u next 1")), "$0616  4C 02 06  JMP loop");
+		assert!(debugger.execute(&mut cpu, "b Nowhere").is_err());
+	}
+
+	#[test]
+	fn bad_input_test() {
+		let mut cpu = tolower();
+		let mut debugger = Debugger::new();
+
+		assert!(debugger.execute(&mut cpu, "x").unwrap_err().contains("Unknown command"));
+		assert!(debugger.execute(&mut cpu, "b").is_ok());
+		assert!(debugger.execute(&mut cpu, "b $zz").is_err());
+		assert!(debugger.execute(&mut cpu, "s 0").is_err());
+		assert!(debugger.execute(&mut cpu, "m").is_err());
+		assert!(debugger.execute(&mut cpu, "r 1").is_err());
+		assert_eq!(cpu.state().pc, 0x0600);
+
+		// The REPL goes on after errors, and stops at 'q'.
+		let mut output = vec![];
+		debugger.repl(&mut cpu, "x\ns\nq\ns\n".as_bytes(), &mut output).unwrap();
+		let output = String::from_utf8(output).unwrap();
+		assert!(output.contains("error: Unknown command 'x'"));
+		assert_eq!(cpu.state().pc, 0x0602);
+	}
+
+	#[test]
+	fn step_back_test() {
+		let mut memory = [0; 65_536];
+		load_program_adc(&mut memory);
+		let mut cpu = CPU::new(FlatBus::new(&memory));
+		cpu.reset();
+		let mut debugger = Debugger::new();
+		let initial = cpu.state();
+		assert!(debugger.execute(&mut cpu, "rs").is_err());
+
+		print(debugger.execute(&mut cpu, "journal on"));
+		print(debugger.execute(&mut cpu, "s 10"));
+		assert_ne!(cpu.state(), initial);
+		print(debugger.execute(&mut cpu, "rs 9"));
+		assert!(print(debugger.execute(&mut cpu, "rs")).starts_with("$0600  D8        CLD"));
+		assert_eq!(cpu.state(), initial);
+		assert_eq!(debugger.execute(&mut cpu, "rs"), Err("Nothing to step back to: the journal is empty".to_string()));
+
+		// Back past the start of the journal, and after a change it doesn't know about.
+		print(debugger.execute(&mut cpu, "s 3"));
+		assert!(print(debugger.execute(&mut cpu, "rs 5")).starts_with("Stepped back 3 of 5: Nothing to step back to"));
+		print(debugger.execute(&mut cpu, "s 3"));
+		print(debugger.execute(&mut cpu, "set a 1"));
+		assert!(debugger.execute(&mut cpu, "rs").is_err());
+		print(debugger.execute(&mut cpu, "journal off"));
+		assert!(debugger.execute(&mut cpu, "rs").is_err());
+	}
+}
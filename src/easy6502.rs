@@ -0,0 +1,255 @@
+// The easy6502 virtual machine (https://skilldrick.github.io/easy6502/), where the demo programs come from. It's
+// flat memory, like `FlatBus`, with a few addresses that do something:
+//
+// | Address | What's there |
+// |---|---|
+// | $FE | A new random byte on every read |
+// | $FF | ASCII code of the last key pressed (lowercase, so W A S D are $77 $61 $73 $64) |
+// | $0200-$05FF | 32x32 display, a byte per pixel, left to right and top to bottom. The low 4 bits are the color |
+//
+// Programs are loaded at $0600 (`memory::PROGRAM_START`), and end with a BRK. The random numbers come from a seed, so
+// a run can be repeated.
+
+use std::fmt::Write;
+
+use crate::bus::Bus;
+use crate::memory::MemoryBus;
+
+/// Reading it returns a new random byte.
+pub const RANDOM: u16 = 0x00FE;
+/// The last key pressed.
+pub const LAST_KEY: u16 = 0x00FF;
+/// The first pixel of the display, the top left.
+pub const DISPLAY_START: u16 = 0x0200;
+/// Pixels in a row, and rows.
+pub const DISPLAY_SIZE: usize = 32;
+
+/// easy6502 has no clock, it runs as fast as the browser lets it. At this speed snake moves about 10 times a second,
+/// like it does there.
+pub const CYCLES_PER_FRAME: u64 = 400;
+
+/// The 16 colors of easy6502, as RGB.
+pub const PALETTE: [(u8, u8, u8); 16] = [
+	(0x00, 0x00, 0x00), // Black
+	(0xFF, 0xFF, 0xFF), // White
+	(0x88, 0x00, 0x00), // Red
+	(0xAA, 0xFF, 0xEE), // Cyan
+	(0xCC, 0x44, 0xCC), // Purple
+	(0x00, 0xCC, 0x55), // Green
+	(0x00, 0x00, 0xAA), // Blue
+	(0xEE, 0xEE, 0x77), // Yellow
+	(0xDD, 0x88, 0x55), // Orange
+	(0x66, 0x44, 0x00), // Brown
+	(0xFF, 0x77, 0x77), // Light red
+	(0x33, 0x33, 0x33), // Dark grey
+	(0x77, 0x77, 0x77), // Grey
+	(0xAA, 0xFF, 0x66), // Light green
+	(0x00, 0x88, 0xFF), // Light blue
+	(0xBB, 0xBB, 0xBB), // Light grey
+];
+
+/// 64KB of RAM, with the random numbers, the key and the display of easy6502.
+pub struct Easy6502Bus {
+	pub memory: MemoryBus,
+	random: u64,
+}
+
+impl Easy6502Bus {
+	/// Create bus whose memory is initialized with the given image, and random numbers from `seed`.
+	pub fn new(image: &[u8; 65_536], seed: u64) -> Self {
+		let mut memory = MemoryBus::new();
+		memory.load(image);
+		Easy6502Bus { memory, random: seed }
+	}
+
+	/// A key was pressed. Programs see it at $FF, until the next one.
+	pub fn set_key(&mut self, key: u8) {
+		self.memory.write(LAST_KEY, key.to_ascii_lowercase());
+	}
+
+	/// Color of the pixel, 0-15 (an index to `PALETTE`).
+	pub fn pixel(&self, x: usize, y: usize) -> u8 {
+		self.memory.read(DISPLAY_START + (y * DISPLAY_SIZE + x) as u16) & 0x0F
+	}
+
+	/// The colors of the whole display, row by row.
+	pub fn display(&self) -> [u8; DISPLAY_SIZE * DISPLAY_SIZE] {
+		core::array::from_fn(|i| self.pixel(i % DISPLAY_SIZE, i / DISPLAY_SIZE))
+	}
+
+	/// Write the display as RGB24 (3 bytes per pixel, row by row), for a texture.
+	pub fn write_rgb24(&self, out: &mut [u8]) {
+		for (pixel, rgb) in self.display().iter().zip(out.chunks_exact_mut(3)) {
+			let (r, g, b) = PALETTE[*pixel as usize];
+			rgb.copy_from_slice(&[r, g, b]);
+		}
+	}
+
+	/// Draw the display in a terminal with 24-bit color, from the top left corner. A character is 2 pixels: the top one
+	/// is the color of '▀', and the bottom one is the background.
+	pub fn render_ansi(&self) -> String {
+		let mut out = String::from("\x1b[H");
+		for y in (0..DISPLAY_SIZE).step_by(2) {
+			for x in 0..DISPLAY_SIZE {
+				let (r, g, b) = PALETTE[self.pixel(x, y) as usize];
+				let (br, bg, bb) = PALETTE[self.pixel(x, y + 1) as usize];
+				// Writing to a String never fails.
+				let _ = write!(out, "\x1b[38;2;{};{};{}m\x1b[48;2;{};{};{}m▀", r, g, b, br, bg, bb);
+			}
+			out.push_str("\x1b[0m\r\n");
+		}
+		out
+	}
+
+	/// The next random byte. SplitMix64: a fine generator from any seed, 0 too.
+	fn next_random(&mut self) -> u8 {
+		self.random = self.random.wrapping_add(0x9E37_79B9_7F4A_7C15);
+		let mut z = self.random;
+		z = (z ^ (z >> 30)).wrapping_mul(0xBF58_476D_1CE4_E5B9);
+		z = (z ^ (z >> 27)).wrapping_mul(0x94D0_49BB_1331_11EB);
+		(z ^ (z >> 31)) as u8
+	}
+}
+
+impl Bus for Easy6502Bus {
+	fn read(&mut self, addr: u16) -> u8 {
+		if addr == RANDOM {
+			// Kept in memory, so `peek` (and the debugger) sees the last one.
+			let random = self.next_random();
+			self.memory.write(RANDOM, random);
+			return random;
+		}
+		self.memory.read(addr)
+	}
+
+	fn peek(&self, addr: u16) -> u8 {
+		self.memory.read(addr)
+	}
+
+	fn write(&mut self, addr: u16, data: u8) {
+		self.memory.write(addr, data);
+	}
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+	use crate::cpu::cpu::CPU;
+	use crate::program_loader::*;
+
+	#[test]
+	fn random_test() {
+		let mut bus = Easy6502Bus::new(&[0; 65_536], 42);
+		let numbers: Vec<u8> = (0..16).map(|_| bus.read(RANDOM)).collect();
+		// Peeking doesn't make a new one.
+		assert_eq!(bus.peek(RANDOM), numbers[15]);
+		assert_eq!(bus.peek(RANDOM), numbers[15]);
+		// Not all the same.
+		assert!(numbers.iter().any(|&n| n != numbers[0]));
+
+		// The same seed, the same numbers.
+		let mut again = Easy6502Bus::new(&[0; 65_536], 42);
+		assert_eq!((0..16).map(|_| again.read(RANDOM)).collect::<Vec<u8>>(), numbers);
+		let mut other = Easy6502Bus::new(&[0; 65_536], 43);
+		assert_ne!((0..16).map(|_| other.read(RANDOM)).collect::<Vec<u8>>(), numbers);
+	}
+
+	#[test]
+	fn display_test() {
+		let mut rom = [0; 65_536];
+		load_program_helloworld(&mut rom);
+		let mut cpu = CPU::new(Easy6502Bus::new(&rom, 0));
+		cpu.reset();
+		cpu.run(6).unwrap();
+
+		let bus = cpu.bus_mut();
+		assert_eq!((bus.pixel(0, 0), bus.pixel(1, 0), bus.pixel(2, 0), bus.pixel(3, 0)), (0x01, 0x05, 0x08, 0x00));
+		// Only the low 4 bits are the color.
+		bus.write(0x05FF, 0xF3);
+		assert_eq!(bus.pixel(31, 31), 0x03);
+
+		let mut rgb = vec![0; DISPLAY_SIZE * DISPLAY_SIZE * 3];
+		bus.write_rgb24(&mut rgb);
+		assert_eq!(rgb[..9], [0xFF, 0xFF, 0xFF, 0x00, 0xCC, 0x55, 0xDD, 0x88, 0x55]);
+		assert_eq!(rgb[rgb.len() - 3..], [0xAA, 0xFF, 0xEE]);
+
+		// 16 lines of 32 characters. The first is white on black.
+		let ansi = bus.render_ansi();
+		assert_eq!(ansi.matches("\r\n").count(), 16);
+		assert_eq!(ansi.matches('▀').count(), 16 * 32);
+		assert!(ansi.starts_with("\x1b[H\x1b[38;2;255;255;255m\x1b[48;2;0;0;0m▀"));
+
+		bus.set_key(b'W');
+		assert_eq!(bus.peek(LAST_KEY), b'w');
+	}
+
+	/// Run a frame of snake, and return false when the game is over (a BRK, after the program).
+	fn snake_frame(cpu: &mut CPU<Easy6502Bus>) -> bool {
+		let end = cpu.cycles() + CYCLES_PER_FRAME;
+		while cpu.cycles() < end {
+			if cpu.bus().peek(cpu.registers().PC) == 0x00 {
+				return false;
+			}
+			cpu.clock_tick();
+		}
+		true
+	}
+
+	fn snake(seed: u64) -> CPU<Easy6502Bus> {
+		let mut rom = [0; 65_536];
+		load_program_snake(&mut rom);
+		let mut cpu = CPU::new(Easy6502Bus::new(&rom, seed));
+		cpu.reset();
+		cpu
+	}
+
+	/// Column and row on the display of the pointer at `addr`.
+	fn position(bus: &Easy6502Bus, addr: u16) -> (i32, i32) {
+		let offset = (bus.peek(addr) as u16 | (bus.peek(addr + 1) as u16) << 8).wrapping_sub(DISPLAY_START) as i32;
+		(offset % DISPLAY_SIZE as i32, offset / DISPLAY_SIZE as i32)
+	}
+
+	#[test]
+	fn snake_test() {
+		// Steer to the apple, like a player would, and eat 3 of them.
+		let mut cpu = snake(2024);
+		for _ in 0..5000 {
+			assert!(snake_frame(&mut cpu), "Game over, the snake is {} bytes long", cpu.bus().peek(SNAKE_LENGTH));
+			if cpu.bus().peek(SNAKE_LENGTH) >= 4 + 2 * 3 {
+				break;
+			}
+
+			let bus = cpu.bus_mut();
+			let (head_x, head_y) = position(bus, SNAKE_HEAD);
+			let (apple_x, apple_y) = position(bus, SNAKE_APPLE);
+			// The snake can't turn back, so turn to the side first.
+			let reverse = match bus.peek(SNAKE_DIRECTION) { 1 => b's', 2 => b'a', 4 => b'w', _ => b'd' };
+			let wanted = [
+				(apple_x > head_x, b'd'),
+				(apple_x < head_x, b'a'),
+				(apple_y > head_y, b's'),
+				(apple_y < head_y, b'w'),
+			];
+			let side = if head_y < DISPLAY_SIZE as i32 / 2 { b's' } else { b'w' };
+			let key = wanted.iter().find(|&&(want, key)| want && key != reverse).map_or(side, |&(_, key)| key);
+			bus.set_key(key);
+		}
+		assert_eq!(cpu.bus().peek(SNAKE_LENGTH), 10);
+		// The head is white, where the snake is.
+		let (head_x, head_y) = position(cpu.bus(), SNAKE_HEAD);
+		assert_eq!(cpu.bus().pixel(head_x as usize, head_y as usize), 0x01);
+	}
+
+	#[test]
+	fn snake_game_over_test() {
+		// Without a key, the snake goes right, into the wall. It starts at column 17.
+		let mut cpu = snake(0);
+		let mut frames = 0;
+		while snake_frame(&mut cpu) {
+			frames += 1;
+			assert!(frames < 1000, "The snake didn't hit the wall");
+		}
+		assert_eq!(cpu.registers().PC, 0x0735);
+		assert_eq!(position(cpu.bus(), SNAKE_HEAD).0, 0);
+	}
+}
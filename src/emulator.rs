@@ -0,0 +1,1147 @@
+use std::fmt;
+use std::ops::RangeInclusive;
+
+use log::{debug, error};
+
+use crate::access_trace::{AccessKinds, AccessSink};
+use crate::cartridge::Cartridge;
+use crate::bus::Bus;
+use crate::controller::ButtonState;
+use crate::cpu::cpu::{CpuError, CpuState, StackFault, CPU};
+use crate::cpu::effects::StepEffects;
+use crate::error::NesError;
+use crate::cpu::registers::Register;
+use crate::cpu::status::Flag;
+use crate::hash::Fnv1a;
+use crate::hooks::{self, EmulatorView, Hooks};
+use crate::log_target::{CPU, EMULATOR};
+use crate::nes_bus::NesBus;
+use crate::profile::Profile;
+use crate::ppu::colors::MasterPalette;
+use crate::ppu::framebuffer::{Framebuffer, HEIGHT};
+use crate::ppu::scanline::ScanlineState;
+use crate::ram_init::RamInitPattern;
+use crate::region::Region;
+use crate::save_state::{SaveState, StateReader, StateWriter};
+use crate::trace::Tracer;
+use crate::zapper::Zapper;
+
+pub type FrameCallback = Box<dyn FnMut(&Framebuffer)>;
+
+/// What `Emulator::run_budget` did.
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub struct BudgetResult {
+	/// CPU cycles it ran. It only stops between instructions, so it can be a few cycles more than the budget (up to
+	/// 7, or 513 with an OAM DMA): take them off the next budget.
+	pub cycles_run: u64,
+	/// A frame finished, it stopped there. The frame callback got it, like from `run_frame`.
+	pub frame_completed: bool,
+	/// Why it stopped before using the budget, and the frame didn't finish (a hook may ask to stop as it finishes).
+	pub stop: Option<StopReason>,
+}
+
+/// Why `Emulator::run_budget` stopped early.
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub enum StopReason {
+	/// The CPU can't execute the next instruction. It stays on it, so the next call stops again, right away.
+	CpuError(CpuError),
+	/// A hook asked to stop, after the instruction that called it (or the frame), see hooks.rs.
+	Requested,
+}
+
+impl fmt::Display for StopReason {
+	fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+		match self {
+			StopReason::CpuError(err) => write!(f, "{}", err),
+			StopReason::Requested => write!(f, "Stopped by a hook"),
+		}
+	}
+}
+
+/// The whole console: CPU, and everything connected to it through the bus.
+/// The emulator is deterministic: the same cartridge and the same calls always produce the same state.
+/// Nothing depends on the host (time, random numbers), and the power on state (RAM, registers) is always the same,
+/// for the same `RamInitPattern` (random RAM comes from its seed, which is in save states). Movies depend on it.
+/// Host time is only in the frontends (frame pacing, `--watch`) and the movie GUID, never in a step, and nothing
+/// a step does iterates a hash container. The `determinism_check` test runs two emulators for 1000 frames.
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct Emulator {
+	cpu: CPU<NesBus>,
+	/// What RAM holds at power on.
+	ram_init: RamInitPattern,
+	// Not part of the state: a deserialized emulator has none.
+	#[cfg_attr(feature = "serde", serde(skip))]
+	frame_callback: Option<FrameCallback>,
+	#[cfg_attr(feature = "serde", serde(skip))]
+	trace: Option<Tracer>,
+	#[cfg_attr(feature = "serde", serde(skip))]
+	profile: Option<Profile>,
+	#[cfg_attr(feature = "serde", serde(skip))]
+	hooks: Hooks,
+}
+
+impl Emulator {
+	/// Insert the cartridge and power on the console. The region comes from the cartridge header.
+	pub fn new(cartridge: Cartridge) -> Self {
+		let region = cartridge.region();
+		Self::with_region(cartridge, region)
+	}
+
+	/// Like `new`, but ignore the region in the cartridge header.
+	pub fn with_region(cartridge: Cartridge, region: Region) -> Self {
+		let mut emulator = Emulator {
+			cpu: CPU::new(NesBus::with_region(cartridge, region)),
+			ram_init: RamInitPattern::default(),
+			frame_callback: None,
+			trace: None,
+			profile: None,
+			hooks: Hooks::default(),
+		};
+		emulator.power_on();
+		emulator
+	}
+
+	/// A console running a raw 6502 binary (no iNES header) from PRG ROM: the binary is at `load` ($8000-$FFFF) of an
+	/// NROM cartridge, see `Cartridge::from_raw`. For programs assembled without a header, that still want the PPU and
+	/// the APU. Raw binaries on flat memory, without the console, are in machine.rs.
+	pub fn with_raw_program(bytes: &[u8], load: u16) -> Result<Self, NesError> {
+		Ok(Self::new(Cartridge::from_raw(bytes, load)?))
+	}
+
+	/// Power on with `pattern` in RAM, instead of zeros. See `ram_init.rs`.
+	pub fn with_ram_init(mut self, pattern: RamInitPattern) -> Self {
+		self.ram_init = pattern;
+		self.power_on();
+		self
+	}
+
+	pub fn ram_init(&self) -> RamInitPattern {
+		self.ram_init
+	}
+
+	/// Fill the internal RAM, PRG RAM and CHR RAM with the `RamInitPattern`, and start from the reset vector.
+	/// NOTE: Like `reset`, the PPU and APU keep their state.
+	pub fn power_on(&mut self) {
+		debug!(target: EMULATOR, "Power on, RAM: {}", self.ram_init);
+		self.cpu.bus_mut().power_on(self.ram_init);
+		self.cpu.reset();
+	}
+
+	/// Take the cartridge out, insert `cartridge`, and power on: the console is like a new one, with the same region and
+	/// `RamInitPattern`, cycle accurate and overclocked if it was, with the same palette and the same channels muted. The frame callback, the hooks, the trace and the profile stay. What was set on the bus and the CPU (strict modes, access traces,
+	/// the Zapper, the scanline callback) doesn't. For reloading a ROM while it's being developed, see rom_watch.rs.
+	pub fn insert_cartridge(&mut self, cartridge: Cartridge) {
+		let region = self.region();
+		let accurate = self.cpu.cycle_accurate();
+		let overclock = self.cpu.bus().overclock();
+		let expansion_volume = self.cpu.bus().apu().expansion_volume();
+		let channel_mask = self.cpu.bus().apu().channel_mask();
+		let palette = self.framebuffer().palette().clone();
+		self.cpu = CPU::new(NesBus::with_region(cartridge, region));
+		self.set_master_palette(palette);
+		self.cpu.set_cycle_accurate(accurate);
+		self.cpu.bus_mut().set_overclock(overclock);
+		self.cpu.bus_mut().set_expansion_volume(expansion_volume);
+		self.cpu.bus_mut().set_channel_mask(channel_mask);
+		self.power_on();
+	}
+
+	/// Call `callback` with every finished frame.
+	pub fn set_frame_callback<F: FnMut(&Framebuffer) + 'static>(&mut self, callback: F) {
+		self.frame_callback = Some(Box::new(callback));
+	}
+
+	/// Call `hook` after every finished frame, after the frame callback. See hooks.rs.
+	pub fn on_frame<F: FnMut(&mut EmulatorView) + 'static>(&mut self, hook: F) {
+		self.hooks.add_frame(Box::new(hook));
+	}
+
+	/// Call `hook` after the instruction that made the byte at `addr` equal `value` (read with `peek`). Not again
+	/// until it changes to something else, and back. See hooks.rs.
+	pub fn on_memory_equals<F: FnMut(&mut EmulatorView) + 'static>(&mut self, addr: u16, value: u8, hook: F) {
+		let met = self.peek(addr) == value;
+		self.hooks.add_memory(addr, value, met, Box::new(hook));
+	}
+
+	/// Whether a hook asked to stop since the last call. `run_budget` stops by itself, `run_frame` finishes the frame.
+	pub fn take_stop_request(&mut self) -> bool {
+		std::mem::take(&mut self.hooks.stop)
+	}
+
+	pub(crate) fn hooks_mut(&mut self) -> &mut Hooks {
+		&mut self.hooks
+	}
+
+	/// Trace every instruction, see `trace.rs`. The trace is finished when the emulator is dropped.
+	pub fn set_trace(&mut self, trace: Tracer) {
+		self.trace = Some(trace);
+	}
+
+	/// Count the executions, cycles and penalty cycles of every opcode from now on, in `profile`. See profile.rs.
+	pub fn set_profile(&mut self, profile: Profile) {
+		self.profile = Some(profile);
+	}
+
+	pub fn profile(&self) -> Option<&Profile> {
+		self.profile.as_ref()
+	}
+
+	/// Execute a single CPU instruction, and let the rest of the console catch up.
+	/// Returns the amount of CPU cycles it took. Panics if the CPU can't execute it, see `try_step_instruction`.
+	pub fn step_instruction(&mut self) -> u8 {
+		match self.try_step_instruction() {
+			Ok(cycles) => cycles,
+			Err(err) => panic!("{}, registers: {}", err, self.cpu_state()),
+		}
+	}
+
+	/// Like `step_instruction`, but returns why the CPU can't execute the instruction (an illegal or unimplemented
+	/// opcode), and leaves the CPU as it was. For test ROMs, that may run into anything.
+	pub fn try_step_instruction(&mut self) -> Result<u8, CpuError> {
+		if let Some(trace) = self.trace.as_mut() {
+			let bus = self.cpu.bus();
+			if let Err(err) = trace.instruction(&self.cpu.state(), Some((bus.ppu().scanline(), bus.ppu().dot())), |addr| bus.peek(addr)) {
+				error!(target: CPU, "Failed to write trace, stopping it: {}", err);
+				self.trace = None;
+			}
+		}
+
+		// The CPU ticks the bus (and the PPU) by itself.
+		let cycles = self.cpu.step()?;
+		if let Some(trace) = self.trace.as_mut() {
+			if let Err(err) = trace.executed(self.cpu.penalty_cycles()) {
+				error!(target: CPU, "Failed to write trace, stopping it: {}", err);
+				self.trace = None;
+			}
+		}
+		if let (Some(profile), Some(opcode)) = (self.profile.as_mut(), self.cpu.last_opcode()) {
+			profile.record(opcode, cycles, self.cpu.penalty_cycles());
+		}
+		if self.hooks.has_memory() {
+			hooks::run_memory_hooks(self);
+		}
+		Ok(cycles)
+	}
+
+	/// Like `try_step_instruction`, but also returns what the step did, see `CPU::step_with_effects`.
+	pub fn step_with_effects(&mut self) -> Result<StepEffects, CpuError> {
+		let before = self.cpu.record_effects();
+		let result = self.try_step_instruction();
+		self.cpu.recorded_effects(before, result)
+	}
+
+	/// Whether the last instruction (or interrupt) wrote memory.
+	pub fn wrote_memory(&self) -> bool {
+		self.cpu.wrote_memory()
+	}
+
+	/// Press the reset button: the CPU jumps to the reset vector, and everything else keeps its state (RAM too, unlike
+	/// `power_on`). Test ROMs ask for it (blargg's, see `harness::BlarggResult`).
+	/// NOTE: The real reset also silences the APU and turns the PPU off for a frame, this doesn't yet.
+	pub fn reset(&mut self) {
+		self.cpu.reset();
+	}
+
+	/// Run until the PPU finished drawing a frame, and return it.
+	pub fn run_frame(&mut self) -> &Framebuffer {
+		loop {
+			self.step_instruction();
+			if self.take_frame_complete() {
+				break;
+			}
+		}
+
+		self.frame_finished();
+		self.cpu.bus().ppu().framebuffer()
+	}
+
+	/// Give the frame to the frame callback, then to the frame hooks.
+	fn frame_finished(&mut self) {
+		if let Some(callback) = self.frame_callback.as_mut() {
+			callback(self.cpu.bus().ppu().framebuffer());
+		}
+		hooks::run_frame_hooks(self);
+	}
+
+	/// Call `callback` at the start of every visible scanline, with its scroll, registers and sprites, see
+	/// `ppu/scanline.rs`.
+	pub fn set_scanline_callback<F: FnMut(u16, &ScanlineState) + 'static>(&mut self, callback: F) {
+		self.cpu.bus_mut().ppu_mut().set_scanline_callback(callback);
+	}
+
+	/// Run until the PPU gets to `scanline`, and return the frame so far: the scanlines above it are from this frame,
+	/// the ones below from the last. It stops after the instruction that got there, so the PPU is a few dots into
+	/// the scanline. Like `step_instruction`, it doesn't call the frame callback.
+	pub fn render_until_scanline(&mut self, scanline: u16) -> Result<&Framebuffer, String> {
+		let scanlines = self.region().scanlines_per_frame();
+		if scanline >= scanlines {
+			return Err(format!("Scanline {} is not in a frame, they are 0-{}", scanline, scanlines - 1));
+		}
+		while self.cpu.bus().ppu().scanline() != scanline {
+			self.try_step_instruction().map_err(|err| err.to_string())?;
+		}
+		Ok(self.framebuffer())
+	}
+
+	/// Run at most `cpu_cycles` (give or take an instruction, see `BudgetResult::cycles_run`), or until the frame
+	/// finishes, and return. For frontends with their own event loop, that can't block on `run_frame`: run a slice
+	/// every time the loop comes around, and the next call continues exactly where this one stopped. It's the same
+	/// as `run_frame`, however the frame is sliced: the same frames and the same audio.
+	pub fn run_budget(&mut self, cpu_cycles: u64) -> BudgetResult {
+		let mut result = BudgetResult { cycles_run: 0, frame_completed: false, stop: None };
+		while result.cycles_run < cpu_cycles {
+			match self.try_step_instruction() {
+				Ok(cycles) => result.cycles_run += cycles as u64,
+				Err(err) => {
+					result.stop = Some(StopReason::CpuError(err));
+					break;
+				}
+			}
+			let frame_completed = self.take_frame_complete();
+			if frame_completed {
+				result.frame_completed = true;
+				self.frame_finished();
+			}
+			if self.take_stop_request() {
+				result.stop = Some(StopReason::Requested);
+				break;
+			}
+			if frame_completed {
+				break;
+			}
+		}
+		result
+	}
+
+	/// Whether a frame finished (VBlank started) since the last call. `run_frame` uses it, so it's only
+	/// useful when running with `step_instruction`.
+	pub fn take_frame_complete(&mut self) -> bool {
+		self.cpu.bus_mut().ppu_mut().take_frame_complete()
+	}
+
+	/// Set the buttons pressed on controller 1. The frontend should call it once per frame, before `run_frame`.
+	pub fn set_controller1(&mut self, buttons: ButtonState) {
+		self.cpu.bus_mut().controller1_mut().set_buttons(buttons);
+	}
+
+	/// Set the buttons pressed on controller 2, like `set_controller1`. A Zapper in port 2 replaces it.
+	pub fn set_controller2(&mut self, buttons: ButtonState) {
+		self.cpu.bus_mut().controller2_mut().set_buttons(buttons);
+	}
+
+	/// Set the buttons of both controllers, port 1 first. Movies record it for every frame.
+	pub fn set_controllers(&mut self, [buttons1, buttons2]: [ButtonState; 2]) {
+		self.set_controller1(buttons1);
+		self.set_controller2(buttons2);
+	}
+
+	/// Aim the Zapper at pixel (x, y), and pull the trigger or not. The first call plugs it in port 2. The frontend
+	/// should call it once per frame, before `run_frame`. Out of the screen (x >= 256 or y >= 240) is away from it.
+	pub fn set_zapper(&mut self, x: u16, y: u16, trigger: bool) {
+		self.cpu.bus_mut().set_zapper(Some(Zapper::new(x, y, trigger)));
+	}
+
+	/// Record writes to ROM, see `NesBus::set_strict_rom`. They are in `bus().rom_write_violations()`.
+	pub fn set_strict_rom(&mut self, strict: bool) {
+		self.cpu.bus_mut().set_strict_rom(strict);
+	}
+
+	/// Record reads of RAM that was never written, see `NesBus::set_strict_ram`. They are in
+	/// `bus().uninitialized_reads()`.
+	pub fn set_strict_ram(&mut self, strict: bool) {
+		self.cpu.bus_mut().set_strict_ram(strict);
+	}
+
+	/// Record accesses to the CPU test registers, see `NesBus::set_strict_io`. They are in
+	/// `bus().unmapped_accesses()`.
+	pub fn set_strict_io(&mut self, strict: bool) {
+		self.cpu.bus_mut().set_strict_io(strict);
+	}
+
+	/// Do the dummy reads and writes of the real CPU, that registers with side effects can tell, see
+	/// `CPU::set_cycle_accurate`.
+	pub fn set_cycle_accurate(&mut self, accurate: bool) {
+		self.cpu.set_cycle_accurate(accurate);
+	}
+
+	/// Give the CPU `scanlines` more scanlines of time every frame, at the end of VBlank. 0 is off. See
+	/// `NesBus::set_overclock`.
+	pub fn set_overclock(&mut self, scanlines: u16) {
+		self.cpu.bus_mut().set_overclock(scanlines);
+	}
+
+	/// How loud the sound channels of the cartridge (VRC6, FDS) are, 1.0 like on a Famicom. See `APU::set_expansion_volume`.
+	pub fn set_expansion_volume(&mut self, volume: f32) {
+		self.cpu.bus_mut().set_expansion_volume(volume);
+	}
+
+	/// The battery backed PRG RAM, for a .sav file. None without a battery. See `Cartridge::save_ram`.
+	pub fn save_ram(&self) -> Option<&[u8]> {
+		self.cpu.bus().cartridge().save_ram()
+	}
+
+	/// Put back the battery backed PRG RAM, from a .sav file. Err without a battery, or for another size.
+	pub fn load_ram(&mut self, data: &[u8]) -> Result<(), NesError> {
+		self.cpu.bus_mut().load_ram(data)
+	}
+
+	/// Ejects the disk of the FDS, and inserts `side` 2 seconds later (None only ejects). Err for a cartridge. See
+	/// `Cartridge::insert_disk`.
+	pub fn insert_disk(&mut self, side: Option<usize>) -> Result<(), String> {
+		self.cpu.bus_mut().insert_disk(side)
+	}
+
+	/// The APU channels heard, a bit each (see `Channel`): the others are muted. See `APU::set_channel_mask`.
+	pub fn set_channel_mask(&mut self, mask: u8) {
+		self.cpu.bus_mut().set_channel_mask(mask);
+	}
+
+	/// Mix every APU channel alone too, for recording them apart, see `APU::capture_channels`.
+	pub fn capture_channels(&mut self, capture: bool) {
+		self.cpu.bus_mut().capture_channels(capture);
+	}
+
+	/// The colors the frames are shown with, from now on (the frame drawn so far too). See `PPU::set_master_palette`.
+	pub fn set_master_palette(&mut self, palette: MasterPalette) {
+		self.cpu.bus_mut().ppu_mut().set_master_palette(palette);
+	}
+
+	/// Check the stack pointer on every push and pull, see `CPU::set_strict_stack`.
+	pub fn set_strict_stack(&mut self, strict: bool) {
+		self.cpu.set_strict_stack(strict);
+	}
+
+	/// The stack overflow or underflow of the last instruction, with `set_strict_stack`.
+	pub fn stack_fault(&self) -> Option<StackFault> {
+		self.cpu.stack_fault()
+	}
+
+	/// The bytes on the stack, in the order they were pushed.
+	pub fn stack_slice(&self) -> Vec<u8> {
+		self.cpu.stack_slice()
+	}
+
+	/// Send the CPU's reads and/or writes in `range` to `sink`, see `NesBus::trace_accesses`.
+	pub fn trace_accesses(&mut self, range: RangeInclusive<u16>, kinds: AccessKinds, sink: Box<dyn AccessSink>) {
+		self.cpu.bus_mut().trace_accesses(range, kinds, sink);
+	}
+
+	/// The whole state of the console, see `save_state.rs` for the format.
+	pub fn save_state(&self) -> Vec<u8> {
+		let mut out = StateWriter::with_header(self.rom_hash());
+		// Not needed to go on from the state, but to replay the run from power on.
+		let (kind, seed) = self.ram_init.to_parts();
+		out.u8(kind);
+		out.u64(seed);
+		self.cpu.save_state(&mut out);
+		out.into_bytes()
+	}
+
+	/// Load a state from `save_state`. On error (another game, corrupt state...) the emulator is not changed.
+	pub fn load_state(&mut self, state: &[u8]) -> Result<(), NesError> {
+		let mut input = StateReader::with_header(state, self.rom_hash()).map_err(NesError::State)?;
+		let backup = self.save_state();
+		let result = self.load_state_from(&mut input).and_then(|_| input.finish());
+		if result.is_err() {
+			let mut backup_input = StateReader::with_header(&backup, self.rom_hash()).unwrap();
+			self.load_state_from(&mut backup_input).expect("Failed to restore the state before loading");
+		}
+		result.map_err(NesError::State)
+	}
+
+	fn load_state_from(&mut self, input: &mut StateReader) -> Result<(), String> {
+		let kind = input.u8()?;
+		self.ram_init = RamInitPattern::from_parts(kind, input.u64()?)?;
+		self.cpu.load_state(input)
+	}
+
+	pub fn rom_hash(&self) -> u32 {
+		self.cpu.bus().cartridge().hash()
+	}
+
+	/// MD5 of the ROM, for movies.
+	pub fn rom_md5(&self) -> [u8; 16] {
+		self.cpu.bus().cartridge().md5()
+	}
+
+	pub fn region(&self) -> Region {
+		self.cpu.bus().region()
+	}
+
+	/// Amount of CPU cycles executed since power on.
+	pub fn cycles(&self) -> u64 {
+		self.cpu.bus().cycles()
+	}
+
+	/// Frames the PPU finished since power on.
+	pub fn frame(&self) -> u64 {
+		self.cpu.bus().ppu().frame()
+	}
+
+	pub fn framebuffer(&self) -> &Framebuffer {
+		self.cpu.bus().ppu().framebuffer()
+	}
+
+	pub fn cpu_state(&self) -> CpuState {
+		self.cpu.state()
+	}
+
+	/// Set the CPU registers, like a test ROM's automation entry point: nestest runs without a PPU from $C000.
+	/// The rest of the console is not changed.
+	pub fn set_cpu_state(&mut self, state: &CpuState) {
+		self.cpu.set_state(state);
+	}
+
+	/// Set a single register, see `CPU::set_register`.
+	pub fn set_register(&mut self, register: Register, value: u16) {
+		self.cpu.set_register(register, value);
+	}
+
+	pub fn set_flag(&mut self, flag: Flag, value: bool) {
+		self.cpu.set_flag(flag, value);
+	}
+
+	/// Write memory for tools, ROM too, see `Bus::poke`.
+	pub fn poke(&mut self, addr: u16, data: u8) {
+		self.cpu.bus_mut().poke(addr, data);
+	}
+
+	/// The rest of the console, for inspection (RAM, PPU, cartridge). Changes go through the emulator.
+	pub fn bus(&self) -> &NesBus {
+		self.cpu.bus()
+	}
+
+	/// Read CPU memory without side effects, see `Bus::peek`.
+	pub fn peek(&self, addr: u16) -> u8 {
+		self.cpu.bus().peek(addr)
+	}
+
+	/// Hash of the last frame, to compare runs without keeping their frames (regression tests, `--hash-after`).
+	/// FNV-1a (64 bits) of the palette indexes (`Framebuffer::pixels`, a byte per pixel, row by row), then the color
+	/// emphasis of every scanline (240 bytes). It's not RGB, so changing the colors of the palette doesn't change it.
+	pub fn frame_hash(&self) -> u64 {
+		hash_frame(self.framebuffer())
+	}
+
+	/// Hash of the memory and the registers, like `frame_hash`. FNV-1a (64 bits) of the internal RAM (2KB), the PRG
+	/// RAM (8KB), then PC (low byte first), A, X, Y, SP and P. Not the cycles, nor the PPU and APU.
+	pub fn state_hash(&self) -> u64 {
+		let bus = self.cpu.bus();
+		let state = self.cpu.state();
+		let mut hash = Fnv1a::default();
+		hash.write(bus.ram());
+		hash.write(bus.cartridge().prg_ram());
+		hash.write(&state.pc.to_le_bytes());
+		hash.write(&[state.a, state.x, state.y, state.s, state.p]);
+		hash.finish()
+	}
+}
+
+/// See `Emulator::frame_hash`.
+fn hash_frame(framebuffer: &Framebuffer) -> u64 {
+	let mut hash = Fnv1a::default();
+	hash.write(framebuffer.pixels());
+	for y in 0..HEIGHT {
+		hash.write(&[framebuffer.emphasis(y)]);
+	}
+	hash.finish()
+}
+
+#[cfg(test)]
+mod tests {
+	use std::cell::RefCell;
+	use std::rc::Rc;
+
+	use super::*;
+	use crate::cartridge::test_rom;
+	use crate::access_trace::AccessKind;
+	use crate::controller::Button;
+	use crate::irq::IrqSource;
+	use crate::nes_bus::UnmappedAccess;
+
+	/// Keep changing the background color, so every frame looks different.
+	fn color_cycle_rom() -> Cartridge {
+		/*
+		loop:
+		LDA #$3F
+		STA $2006
+		LDA #$00
+		STA $2006 	; PPU address = $3F00, background color
+		INX
+		STX $2007 	; Background color = X
+		JMP loop
+		*/
+		let rom = test_rom::nrom("A9 3F 8D 06 20 A9 00 8D 06 20 E8 8E 07 20 4C 00 80");
+		Cartridge::from_ines(&rom).unwrap()
+	}
+
+	#[test]
+	fn try_step_instruction_test() {
+		// LDA #$01, then the illegal $02.
+		let mut emulator = Emulator::new(Cartridge::from_ines(&test_rom::nrom("A9 01 02")).unwrap());
+		emulator.set_cpu_state(&CpuState { pc: 0x8000, ..emulator.cpu_state() });
+		assert_eq!(emulator.try_step_instruction(), Ok(2));
+		assert_eq!(emulator.try_step_instruction(), Err(CpuError::IllegalOpcode { pc: 0x8002, opcode: 0x02 }));
+		assert_eq!(emulator.cpu_state().pc, 0x8002);
+		assert_eq!(emulator.cpu_state().a, 0x01);
+	}
+
+	#[test]
+	fn strict_io_test() {
+		/*
+		LDA $4018
+		STA $401F
+		*/
+		let mut emulator = Emulator::new(Cartridge::from_ines(&test_rom::nrom("AD 18 40 8D 1F 40")).unwrap());
+		emulator.set_cpu_state(&CpuState { pc: 0x8000, ..emulator.cpu_state() });
+		emulator.set_strict_io(true);
+		emulator.step_instruction();
+		emulator.step_instruction();
+		// Open bus is the high byte of the address, the last byte of the instruction.
+		assert_eq!(emulator.cpu_state().a, 0x40);
+		let accesses = emulator.bus().unmapped_accesses();
+		assert_eq!(accesses, [
+			UnmappedAccess { pc: 0x8000, addr: 0x4018, value: 0x40, kind: AccessKind::Read },
+			UnmappedAccess { pc: 0x8003, addr: 0x401F, value: 0x40, kind: AccessKind::Write },
+		]);
+		assert_eq!(accesses[1].to_string(), "Write of $40 to the disabled register $401F, by the instruction at $8003");
+	}
+
+	#[test]
+	fn ram_init_test() {
+		// A cartridge with CHR RAM.
+		let cartridge = || Cartridge::from_ines(&test_rom::ines(0, &[0xEA; 0x4000], &[])).unwrap();
+		let random = RamInitPattern::Random { seed: 1234 };
+		let first = Emulator::new(cartridge()).with_ram_init(random);
+		let second = Emulator::new(cartridge()).with_ram_init(random);
+		assert_eq!(first.bus().ram(), second.bus().ram());
+		assert_eq!(first.bus().cartridge().chr(), second.bus().cartridge().chr());
+		assert!(first.bus().ram().iter().any(|&byte| byte != 0));
+		let other = Emulator::new(cartridge()).with_ram_init(RamInitPattern::Random { seed: 1235 });
+		assert_ne!(first.bus().ram(), other.bus().ram());
+
+		// Before anything runs: internal RAM, and PRG RAM.
+		let mut emulator = Emulator::new(cartridge()).with_ram_init(RamInitPattern::AllFF);
+		assert_eq!(emulator.peek(0x0000), 0xFF);
+		assert_eq!(emulator.peek(0x07FF), 0xFF);
+		assert_eq!(emulator.peek(0x6000), 0xFF);
+		assert!(emulator.bus().cartridge().chr().iter().all(|&byte| byte == 0xFF));
+		assert_eq!(Emulator::new(cartridge()).peek(0x0000), 0x00);
+
+		// Reset keeps the RAM.
+		emulator.step_instruction();
+		emulator.reset();
+		assert_eq!(emulator.peek(0x0000), 0xFF);
+
+		// The pattern is in save states.
+		let state = first.save_state();
+		let mut emulator = Emulator::new(cartridge());
+		emulator.load_state(&state).unwrap();
+		assert_eq!(emulator.ram_init(), random);
+		emulator.power_on();
+		assert_eq!(emulator.bus().ram(), first.bus().ram());
+	}
+
+	#[test]
+	fn run_frame_deterministic_test() {
+		let mut first = Emulator::new(color_cycle_rom());
+		let mut second = Emulator::new(color_cycle_rom());
+
+		for _ in 0..10 {
+			first.run_frame();
+			second.run_frame();
+		}
+
+		assert!(first.framebuffer().pixels() == second.framebuffer().pixels());
+		assert_eq!(first.cycles(), second.cycles());
+
+		// Make sure the test actually tests something: the frame is not a single color.
+		let pixels = first.framebuffer().pixels();
+		assert!(pixels.iter().any(|&pixel| pixel != pixels[0]));
+	}
+
+	#[test]
+	fn run_budget_test() {
+		let mut whole = Emulator::new(color_cycle_rom());
+		let mut hashes = Vec::new();
+		let mut samples = Vec::new();
+		for _ in 0..3 {
+			whole.run_frame();
+			hashes.push(whole.frame_hash());
+		}
+		whole.bus().apu().sample_buffer().take(&mut samples);
+		assert!(!samples.is_empty());
+
+		for budget in [1, 7, 1000] {
+			let mut sliced = Emulator::new(color_cycle_rom());
+			let mut sliced_hashes = Vec::new();
+			let mut cycles = 0;
+			while sliced_hashes.len() < 3 {
+				let result = sliced.run_budget(budget);
+				assert_eq!(result.stop, None);
+				assert!(result.cycles_run >= budget || result.frame_completed, "Budget {}: {:?}", budget, result);
+				cycles += result.cycles_run;
+				if result.frame_completed {
+					sliced_hashes.push(sliced.frame_hash());
+				}
+			}
+			assert_eq!(sliced_hashes, hashes, "Budget {}", budget);
+			assert_eq!(sliced.state_hash(), whole.state_hash(), "Budget {}", budget);
+			// Plus the 7 of the reset.
+			assert_eq!(cycles + 7, sliced.cycles());
+			assert_eq!(sliced.cycles(), whole.cycles());
+			let mut sliced_samples = Vec::new();
+			sliced.bus().apu().sample_buffer().take(&mut sliced_samples);
+			assert!(sliced_samples == samples, "Budget {}", budget);
+		}
+	}
+
+	#[test]
+	fn frame_hash_test() {
+		let mut first = Emulator::new(color_cycle_rom());
+		let mut second = Emulator::new(color_cycle_rom());
+		for _ in 0..5 {
+			first.run_frame();
+			second.run_frame();
+		}
+		assert_eq!(first.frame_hash(), second.frame_hash());
+		assert_eq!(first.state_hash(), second.state_hash());
+
+		// A single pixel.
+		let mut frame = first.framebuffer().clone();
+		frame.set(100, 100, frame.get(100, 100) ^ 1);
+		assert_ne!(hash_frame(&frame), first.frame_hash());
+
+		// The next frame has other colors, and X changed.
+		first.run_frame();
+		assert_ne!(first.frame_hash(), second.frame_hash());
+		assert_ne!(first.state_hash(), second.state_hash());
+	}
+
+	/// Two runs of the same ROM, with random RAM and the same inputs, from the same seed: they must not diverge.
+	/// Halfway, the first one is saved and loaded on a third emulator (with another pattern), which must go on the same.
+	#[test]
+	fn determinism_check() {
+		/*
+		loop:
+		LDA #$01
+		STA $4016
+		LDA #$00
+		STA $4016 	; Strobe
+		LDA $4016
+		AND #$01 	; A button
+		ADC $10,X 	; Random at power on
+		STA $10,X
+		STA $4000 	; Pulse 1
+		STA $2007
+		INX
+		JMP loop
+		*/
+		let rom = test_rom::nrom("A9 01 8D 16 40 A9 00 8D 16 40 AD 16 40 29 01 75 10 95 10 8D 00 40 8D 07 20 E8 4C 00 80");
+		let new = || Emulator::new(Cartridge::from_ines(&rom).unwrap()).with_ram_init(RamInitPattern::Random { seed: 643 });
+		let mut first = new();
+		let mut second = new();
+		let mut loaded: Option<Emulator> = None;
+		for frame in 0..1000u64 {
+			let mut buttons = ButtonState::default();
+			buttons.set(Button::A, frame % 3 == 0);
+			for emulator in [&mut first, &mut second].into_iter().chain(loaded.as_mut()) {
+				emulator.set_controller1(buttons);
+				emulator.run_frame();
+			}
+			debug_assert_eq!(first.state_hash(), second.state_hash(), "Diverged at frame {}", frame);
+			debug_assert_eq!(first.frame_hash(), second.frame_hash(), "Diverged at frame {}", frame);
+			if let Some(loaded) = &loaded {
+				debug_assert_eq!(first.state_hash(), loaded.state_hash(), "Loaded state diverged at frame {}", frame);
+				debug_assert_eq!(first.frame_hash(), loaded.frame_hash(), "Loaded state diverged at frame {}", frame);
+			}
+			if frame == 500 {
+				let mut third = Emulator::new(Cartridge::from_ines(&rom).unwrap());
+				third.load_state(&first.save_state()).unwrap();
+				assert_eq!(third.ram_init(), first.ram_init());
+				loaded = Some(third);
+			}
+		}
+
+		// The seed is in the state, so the power on is the same too.
+		let mut loaded = loaded.unwrap();
+		first.power_on();
+		loaded.power_on();
+		assert_eq!(first.state_hash(), loaded.state_hash());
+	}
+
+	#[test]
+	fn overclock_test() {
+		/*
+		LDA #$3F
+		STA $2006
+		LDA #$00
+		STA $2006
+		LDA #$16
+		STA $2007 	; Background color = red
+		loop:
+		INC $10
+		JMP loop
+		*/
+		let rom = test_rom::nrom("A9 3F 8D 06 20 A9 00 8D 06 20 A9 16 8D 07 20 E6 10 4C 0F 80");
+		let mut plain = Emulator::new(Cartridge::from_ines(&rom).unwrap());
+		let mut overclocked = Emulator::new(Cartridge::from_ines(&rom).unwrap());
+		overclocked.set_overclock(10);
+		for _ in 0..4 {
+			plain.run_frame();
+			overclocked.run_frame();
+			assert_eq!(plain.frame_hash(), overclocked.frame_hash());
+		}
+
+		// 3 VBlanks ended: 10 scanlines of 113.67 cycles, 3 times. Frames end between instructions, give or take one.
+		assert_eq!(overclocked.bus().overclock_cycles(), 3 * 10 * 341 / 3);
+		assert_eq!(plain.bus().overclock_cycles(), 0);
+		let extra = overclocked.cycles() as i64 - plain.cycles() as i64;
+		assert!((extra - 3410).abs() < 8, "{} extra cycles", extra);
+		// The game got them.
+		assert_ne!(plain.peek(0x10), overclocked.peek(0x10));
+		assert_eq!(plain.frame(), overclocked.frame());
+	}
+
+	#[test]
+	fn frame_callback_test() {
+		let mut emulator = Emulator::new(color_cycle_rom());
+
+		let frames = Rc::new(RefCell::new(vec![]));
+		let frames_clone = frames.clone();
+		emulator.set_frame_callback(move |framebuffer| frames_clone.borrow_mut().push(framebuffer.clone()));
+
+		emulator.run_frame();
+		emulator.run_frame();
+
+		assert_eq!(frames.borrow().len(), 2);
+		assert!(frames.borrow()[1] == *emulator.framebuffer());
+	}
+
+	#[test]
+	fn cycles_per_frame_test() {
+		/*
+		LDA #$08
+		STA $2001 	; Enable background rendering
+		loop:
+		JMP loop
+		*/
+		let rom = test_rom::nrom("A9 08 8D 01 20 4C 05 80");
+		let mut emulator = Emulator::new(Cartridge::from_ines(&rom).unwrap());
+
+		emulator.run_frame();
+		let start = emulator.cycles();
+		for _ in 0..10 {
+			emulator.run_frame();
+		}
+
+		// 341 * 262 = 89342 dots, but every odd frame is 1 dot shorter. That's 29780.5 CPU cycles on average.
+		let cycles = (emulator.cycles() - start) as i64;
+		assert!((cycles - 297_805).abs() <= 3, "cycles: {}", cycles);
+	}
+
+	#[test]
+	fn pal_cycles_per_frame_test() {
+		// Same program as `cycles_per_frame_test`.
+		let rom = test_rom::nrom("A9 08 8D 01 20 4C 05 80");
+		let mut emulator = Emulator::with_region(Cartridge::from_ines(&rom).unwrap(), Region::Pal);
+
+		emulator.run_frame();
+		let start = emulator.cycles();
+		for _ in 0..10 {
+			emulator.run_frame();
+		}
+
+		// 341 * 312 = 106392 dots, and PAL doesn't skip a dot on odd frames. At 3.2 dots per cycle, that's 33247.5 CPU cycles.
+		let cycles = (emulator.cycles() - start) as i64;
+		assert!((cycles - 332_475).abs() <= 3, "cycles: {}", cycles);
+	}
+
+	#[test]
+	fn vblank_polling_test() {
+		/*
+		wait:
+		BIT $2002
+		BPL wait 	; Wait for VBlank
+		LDA #$01
+		STA $00 	; Flag for the test
+		loop:
+		JMP loop
+		*/
+		let rom = test_rom::nrom("2C 02 20 10 FB A9 01 85 00 4C 09 80");
+		let mut emulator = Emulator::new(Cartridge::from_ines(&rom).unwrap());
+
+		while emulator.cpu.bus_mut().read(0x0000) == 0 {
+			emulator.step_instruction();
+		}
+
+		// VBlank is set at dot 1 of scanline 241: (241 * 341 + 1) / 3 = 27394 CPU cycles. A read lands on that very dot,
+		// which reads the flag clear and keeps it clear (see ppu.rs), so the first VBlank seen is the next frame's:
+		// 27394 + 89342 / 3 = ~57175 CPU cycles.
+		// BIT reads $2002 at its last cycle. After that read there are 8 more cycles: the end of BIT (1), BPL not taken (2), LDA (2) and STA (3).
+		// The loop is 7 cycles long, so the read that sees VBlank can be up to 7 cycles late.
+		let read_cycle = emulator.cycles() as i64 - 8;
+		assert!((57_175..=57_175 + 7).contains(&read_cycle), "read cycle: {}", read_cycle);
+	}
+
+	/// Step until the RAM at `addr` is not 0, and return the cycles at that point.
+	fn run_until_flag(emulator: &mut Emulator, addr: u16, max_cycles: u64) -> Option<u64> {
+		while emulator.cycles() < max_cycles {
+			emulator.step_instruction();
+			if emulator.cpu.bus_mut().read(addr) != 0 {
+				return Some(emulator.cycles());
+			}
+		}
+		None
+	}
+
+	#[test]
+	fn frame_irq_test() {
+		/*
+		$8000:
+		CLI
+		loop:
+		JMP loop
+
+		$8010, IRQ handler:
+		INC $10
+		LDA $4015 	; Acknowledge the frame interrupt
+		RTI
+		*/
+		let program = "58 4C 01 80 EA EA EA EA EA EA EA EA EA EA EA EA EE 10 00 AD 15 40 40";
+		let rom = test_rom::nrom_with_vectors(program, 0x8000, 0x8010);
+		let mut emulator = Emulator::new(Cartridge::from_ines(&rom).unwrap());
+
+		// The frame interrupt flag is set 29828 cycles after the frame counter started (power on).
+		// The handler starts at the next instruction boundary (up to 3 cycles later), and it takes 7 cycles to enter it, and 6 for INC.
+		let cycles = run_until_flag(&mut emulator, 0x10, 100_000).expect("IRQ handler did not run") as i64;
+		let irq_cycle = cycles - 7 - 6;
+		assert!((29_828..=29_831).contains(&irq_cycle), "IRQ cycle: {}", irq_cycle);
+
+		// The handler acknowledged the interrupt, so it runs once every sequence, and not in an endless loop.
+		while emulator.cycles() < 29_830 * 3 + 100 {
+			emulator.step_instruction();
+		}
+		assert_eq!(emulator.cpu.bus_mut().read(0x10), 3);
+	}
+
+	#[test]
+	fn frame_irq_inhibit_test() {
+		/*
+		LDA #$40
+		STA $4017 	; Inhibit the frame interrupt
+		CLI
+		loop:
+		JMP loop
+
+		$8010, IRQ handler:
+		INC $10
+		LDA $4015
+		RTI
+		*/
+		let program = "A9 40 8D 17 40 58 4C 06 80 EA EA EA EA EA EA EA EE 10 00 AD 15 40 40";
+		let rom = test_rom::nrom_with_vectors(program, 0x8000, 0x8010);
+		let mut emulator = Emulator::new(Cartridge::from_ines(&rom).unwrap());
+
+		assert_eq!(run_until_flag(&mut emulator, 0x10, 29_830 * 3), None);
+	}
+
+	#[test]
+	fn irq_sources_test() {
+		/*
+		wait:
+		LDA $11
+		BEQ wait 	; Wait for the test to set $11
+		CLI
+		loop:
+		JMP loop
+
+		$8010, IRQ handler:
+		INC $10
+		LDA $4015 	; Acknowledge the frame interrupt, but not the DMC one
+		RTI
+		*/
+		let program = "A5 11 F0 FC 58 4C 05 80 EA EA EA EA EA EA EA EA EE 10 00 AD 15 40 40";
+		let rom = test_rom::nrom_with_vectors(program, 0x8000, 0x8010);
+		let mut emulator = Emulator::new(Cartridge::from_ines(&rom).unwrap());
+
+		// A DMC sample of a byte, with its IRQ, and the frame IRQ after it.
+		emulator.cpu.bus_mut().write(0x4010, 0x8F);
+		emulator.cpu.bus_mut().write(0x4013, 0x00);
+		emulator.cpu.bus_mut().write(0x4015, 0x10);
+		while emulator.bus().irq_sources().bits() != IrqSource::FRAME_COUNTER.mask() | IrqSource::DMC.mask() {
+			assert!(emulator.cycles() < 40_000, "IRQs: {}", emulator.bus().irq_sources());
+			emulator.step_instruction();
+		}
+		assert_eq!(emulator.bus().irq_sources().to_string(), "frame counter, DMC");
+
+		// The handler acknowledges the frame counter, and the DMC still holds the line: the CPU takes it again and again.
+		emulator.cpu.bus_mut().write(0x11, 1);
+		for _ in 0..100 {
+			emulator.step_instruction();
+		}
+		assert_eq!(emulator.bus().irq_sources().to_string(), "DMC");
+		assert!(emulator.cpu.bus_mut().read(0x10) > 3);
+
+		// Acknowledge the DMC too: no more interrupts.
+		emulator.cpu.bus_mut().write(0x4015, 0x00);
+		emulator.step_instruction();
+		let handled = emulator.cpu.bus_mut().read(0x10);
+		for _ in 0..100 {
+			emulator.step_instruction();
+		}
+		assert!(!emulator.bus().irq_pending());
+		assert_eq!(emulator.cpu.bus_mut().read(0x10), handled);
+	}
+
+	#[test]
+	fn nmi_test() {
+		/*
+		LDA #$80
+		STA $2000 	; NMI on
+		loop:
+		JMP loop
+
+		$8020, NMI handler:
+		INC $10
+		RTI
+		*/
+		let program = "A9 80 8D 00 20 4C 05 80 EA EA EA EA EA EA EA EA EA EA EA EA EA EA EA EA EA EA EA EA EA EA EA EA E6 10 40";
+		let rom = test_rom::nrom_with_vectors(program, 0x8020, 0x8000);
+		let mut emulator = Emulator::new(Cartridge::from_ines(&rom).unwrap());
+		for frame in 1..=3 {
+			emulator.run_frame();
+			for _ in 0..10 {
+				emulator.step_instruction();
+			}
+			assert_eq!(emulator.cpu.bus_mut().read(0x10), frame);
+		}
+	}
+
+	#[test]
+	fn nmi_enable_in_vblank_test() {
+		/*
+		$8000:
+		JMP $8000
+
+		$8010:
+		LDA #$80
+		STA $2000 	; NMI on, in VBlank
+		NOP
+		NOP
+		loop:
+		JMP loop
+
+		$8020, NMI handler:
+		RTI
+		*/
+		let program = "4C 00 80 EA EA EA EA EA EA EA EA EA EA EA EA EA A9 80 8D 00 20 EA EA 4C 17 80 EA EA EA EA EA EA 40";
+		let rom = test_rom::nrom_with_vectors(program, 0x8020, 0x8000);
+		let mut emulator = Emulator::new(Cartridge::from_ines(&rom).unwrap());
+		while emulator.bus().ppu().registers.ppustatus.vertical_blank_started() == 0 {
+			emulator.step_instruction();
+		}
+
+		// The NMI comes after the instruction that follows the write: the first NOP.
+		emulator.cpu.set_register(Register::PC, 0x8010);
+		for _ in 0..3 {
+			emulator.step_instruction();
+		}
+		assert_eq!(emulator.cpu_state().pc, 0x8016);
+		emulator.step_instruction();
+		assert_eq!(emulator.cpu_state().pc, 0x8020);
+		let s = emulator.cpu_state().s as u16;
+		assert_eq!((emulator.peek(0x0100 + s + 3), emulator.peek(0x0100 + s + 2)), (0x80, 0x16));
+		emulator.step_instruction();
+
+		// Still in VBlank: off and on again is another NMI.
+		emulator.cpu.bus_mut().write(0x2000, 0x00);
+		emulator.cpu.bus_mut().write(0x2000, 0x80);
+		emulator.step_instruction();
+		emulator.step_instruction();
+		assert_eq!(emulator.cpu_state().pc, 0x8020);
+		emulator.step_instruction();
+
+		// Not after PPUSTATUS was read.
+		emulator.cpu.bus_mut().read(0x2002);
+		emulator.cpu.bus_mut().write(0x2000, 0x00);
+		emulator.cpu.bus_mut().write(0x2000, 0x80);
+		for _ in 0..10 {
+			emulator.step_instruction();
+			assert_ne!(emulator.cpu_state().pc, 0x8020);
+		}
+	}
+
+	#[test]
+	fn scanline_callback_test() {
+		/*
+		LDA #$08
+		STA $2001 	; Show the background
+		wait:
+		BIT $2002
+		BPL wait 	; Wait for VBlank, the scroll is 0,0
+		STA $10 	; Still 8
+		outer:
+		LDX #0
+		inner:
+		DEX
+		BNE inner
+		DEC $10
+		BNE outer 	; About 10000 cycles, to the middle of the next frame
+		LDA #$40
+		STA $2005 	; Scroll 64 pixels right
+		loop:
+		JMP loop
+		*/
+		let program = "A9 08 8D 01 20 2C 02 20 10 FB 85 10 A2 00 CA D0 FD C6 10 D0 F7 A9 40 8D 05 20 4C 1A 80";
+		let mut emulator = Emulator::new(Cartridge::from_ines(&test_rom::nrom(program)).unwrap());
+		let scrolls = Rc::new(RefCell::new(Vec::new()));
+		let recorded = scrolls.clone();
+		emulator.set_scanline_callback(move |scanline, state| {
+			assert_eq!(scanline, state.scanline);
+			recorded.borrow_mut().push((scanline, state.scroll_x(), state.ppumask));
+		});
+		while !emulator.take_frame_complete() {
+			emulator.step_instruction();
+		}
+		scrolls.borrow_mut().clear();
+
+		emulator.render_until_scanline(240).unwrap();
+		let scrolls = scrolls.borrow();
+		assert_eq!(scrolls.len(), 240);
+		assert!(scrolls.iter().enumerate().all(|(index, &(scanline, _, ppumask))| scanline as usize == index && ppumask == 0x08));
+		// The write is somewhere in the middle: the scanlines above it are at 0, and the ones below at 64.
+		let split = scrolls.iter().position(|&(_, scroll, _)| scroll != 0).unwrap();
+		assert!((20..200).contains(&split), "Split at scanline {}", split);
+		assert!(scrolls[split..].iter().all(|&(_, scroll, _)| scroll == 64), "{:?}", &scrolls[split..]);
+		assert_eq!(emulator.bus().ppu().scanline(), 240);
+		assert!(emulator.render_until_scanline(262).is_err());
+	}
+
+	#[cfg(feature = "serde")]
+	#[test]
+	fn serde_test() {
+		let mut original = Emulator::new(color_cycle_rom());
+		for _ in 0..3 {
+			original.run_frame();
+		}
+
+		// The copy continues exactly like the original.
+		let mut copy: Emulator = bincode::deserialize(&bincode::serialize(&original).unwrap()).unwrap();
+		assert_eq!(copy.cpu_state(), original.cpu_state());
+		for _ in 0..3 {
+			original.run_frame();
+			copy.run_frame();
+			assert!(copy.framebuffer() == original.framebuffer());
+		}
+		assert_eq!(copy.cpu_state(), original.cpu_state());
+		assert_eq!(copy.save_state(), original.save_state());
+
+		// In JSON, the byte arrays are base64.
+		let json = serde_json::to_value(&copy).unwrap();
+		assert!(json["cpu"]["bus"]["ram"].is_string());
+		assert_eq!(json["cpu"]["registers"]["PC"], copy.cpu_state().pc);
+		let from_json: Emulator = serde_json::from_value(json).unwrap();
+		assert_eq!(from_json.save_state(), copy.save_state());
+	}
+}
@@ -0,0 +1,125 @@
+// The errors of the library APIs that take data from outside: ROMs, raw binaries, hex programs, .sav files and save
+// states. The tools on top (the debugger, symbols, movies, the ROM database) have their own messages, as strings.
+//
+// | Variant | From |
+// |---|---|
+// | `Decode` | A hex program that isn't hex (`memory::hex_to_bytes`), or an instruction the CPU can't decode (`CpuError`) |
+// | `Ines` | A file that isn't an iNES file, or is shorter than its header says (`Cartridge::from_ines`) |
+// | `Mapper` | An iNES file for a mapper that isn't supported |
+// | `Disk` | An FDS disk image that isn't whole sides, or a BIOS that isn't 8KB (`Cartridge::from_fds`) |
+// | `Io` | A file that can't be read (`Cartridge::from_file`), with its path |
+// | `Battery` | A .sav file of another size than the battery backed PRG RAM, or for a cartridge without one (`Cartridge::load_ram`) |
+// | `State` | A save state for another ROM or region, of another version, or corrupt (`Emulator::load_state`) |
+// | `Bus` | A program or binary that doesn't fit where it goes in memory (`program_loader::load_raw`, `Cartridge::from_raw`) |
+//
+// An error with a cause (`Io`) has it as its `source`. `String::from` an error is its whole chain, like "Can't read
+// game.nes: No such file or directory (os error 2)", which is what the command line prints.
+
+use std::error::Error;
+use std::fmt;
+use std::io;
+use std::path::{Path, PathBuf};
+
+use crate::cpu::cpu::CpuError;
+
+#[derive(Debug)]
+pub enum NesError {
+	Decode(String),
+	Ines(String),
+	Mapper(u16),
+	Disk(String),
+	Io { path: PathBuf, source: io::Error },
+	Battery(String),
+	State(String),
+	Bus(String),
+}
+
+impl NesError {
+	/// `path` couldn't be read.
+	pub fn io(path: &Path, source: io::Error) -> Self {
+		NesError::Io { path: path.to_path_buf(), source }
+	}
+}
+
+impl fmt::Display for NesError {
+	fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+		match self {
+			NesError::Decode(message) | NesError::Ines(message) | NesError::State(message) | NesError::Bus(message) | NesError::Disk(message) | NesError::Battery(message) => write!(f, "{}", message),
+			NesError::Mapper(mapper) => write!(f, "Mapper {} is not supported", mapper),
+			NesError::Io { path, .. } => write!(f, "Can't read {}", path.display()),
+		}
+	}
+}
+
+impl Error for NesError {
+	fn source(&self) -> Option<&(dyn Error + 'static)> {
+		match self {
+			NesError::Io { source, .. } => Some(source),
+			_ => None,
+		}
+	}
+}
+
+impl From<CpuError> for NesError {
+	fn from(err: CpuError) -> Self {
+		NesError::Decode(err.to_string())
+	}
+}
+
+/// The message, then the message of every cause, joined with ": ". So code that reports errors as strings keeps
+/// all of it.
+impl From<NesError> for String {
+	fn from(err: NesError) -> Self {
+		let mut message = err.to_string();
+		let mut source = err.source();
+		while let Some(cause) = source {
+			message.push_str(&format!(": {}", cause));
+			source = cause.source();
+		}
+		message
+	}
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+	use crate::cartridge::{test_rom, Cartridge};
+	use crate::emulator::Emulator;
+	use crate::memory::{hex_to_bytes, write_rom};
+	use crate::program_loader::load_raw;
+
+	#[test]
+	fn error_test() {
+		let err = Cartridge::from_file(Path::new("no/such/game.nes")).err().unwrap();
+		assert!(matches!(&err, NesError::Io { path, source } if path == Path::new("no/such/game.nes") && source.kind() == io::ErrorKind::NotFound));
+		assert!(err.source().is_some());
+		let chain = String::from(err);
+		assert!(chain.starts_with("Can't read no/such/game.nes: "), "{}", chain);
+
+		let mut rom = test_rom::nrom("EA");
+		rom[0] = b'X';
+		let err = Cartridge::from_ines(&rom).err().unwrap();
+		assert!(matches!(err, NesError::Ines(_)));
+		assert_eq!(err.to_string(), "Not an iNES file: missing 'NES' header");
+		rom[0] = b'N';
+		rom.truncate(1000);
+		assert!(matches!(Cartridge::from_ines(&rom), Err(NesError::Ines(message)) if message.contains("too short")));
+
+		let err = Cartridge::from_ines(&test_rom::ines(9, &[0; 0x4000], &[])).err().unwrap();
+		assert!(matches!(err, NesError::Mapper(9)));
+		assert_eq!(String::from(err), "Mapper 9 is not supported");
+
+		let mut emulator = Emulator::new(Cartridge::from_ines(&test_rom::nrom("EA")).unwrap());
+		let state = emulator.save_state();
+		assert!(matches!(emulator.load_state(&state[..state.len() - 1]), Err(NesError::State(_))));
+		let other = Emulator::new(Cartridge::from_ines(&test_rom::nrom("E8")).unwrap());
+		assert!(matches!(emulator.load_state(&other.save_state()), Err(NesError::State(message)) if message.starts_with("Save state is for another ROM")));
+
+		assert!(matches!(hex_to_bytes("A9 GG"), Err(NesError::Decode(message)) if message == "Not a hex byte: 'GG'"));
+		let mut memory = [0; 65_536];
+		assert!(matches!(write_rom(&mut memory, "A9 0"), Err(NesError::Decode(_))));
+		assert!(matches!(write_rom(&mut memory, &"EA ".repeat(0xFA00)), Err(NesError::Bus(message)) if message.starts_with("Program is too long")));
+		assert!(matches!(load_raw(&[0xEA; 16], Some(0xFFF8), None), Err(NesError::Bus(_))));
+		assert!(matches!(Cartridge::from_raw(&[0xEA], 0x6000), Err(NesError::Bus(_))));
+	}
+}
@@ -0,0 +1,303 @@
+// Expressions, for the conditions of breakpoints (`b $C123 if A == 0x20 && [$10] > 5`), and the watch list of the
+// debugger (`watch scroll = [$FD]:[$FC]`).
+//
+// | Syntax | Value |
+// |---|---|
+// | `A`, `X`, `Y`, `SP`, `PC`, `P` | The register |
+// | `N`, `V`, `B`, `D`, `I`, `Z`, `C` | The flag, 0 or 1 |
+// | `32`, `0x20`, `$20` | Numbers: decimal, or hex with `0x` or `$` |
+// | `Reset` | The address of a symbol (see symbols.rs) |
+// | `[$0042]`, `[$0300+X]` | The byte at the address |
+// | `[$FD]:[$FC]` | 16 bits, from the high byte and the low byte: the low bytes of both sides |
+// | `+`, `-` | Addition and subtraction |
+// | `==`, `!=`, `<`, `<=`, `>`, `>=` | Comparisons: 1 if true, 0 if false |
+// | `&&`, `\|\|` | Logical and/or: anything but 0 is true |
+// | `( )` | Grouping |
+//
+// From the loosest: `||`, then `&&`, then the comparisons, then `+` and `-`, then `:`. Names are case insensitive, except for
+// symbols. Registers and flags win over symbols with the same name.
+//
+// Expressions are parsed once, when the breakpoint is set, so a typo is reported right away and not when the
+// breakpoint is hit. Evaluating reads memory with `peek`, so checking a condition never changes the machine.
+
+use std::fmt;
+
+use crate::cpu::cpu::CpuState;
+pub use crate::cpu::registers::Register;
+use crate::symbols::SymbolTable;
+
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub enum Operator {
+	Add,
+	Subtract,
+	Equal,
+	NotEqual,
+	Less,
+	LessOrEqual,
+	Greater,
+	GreaterOrEqual,
+	And,
+	Or,
+	/// High byte and low byte.
+	Pair,
+}
+
+#[derive(Clone, PartialEq, Eq, Debug)]
+pub enum Expression {
+	Number(i64),
+	Register(Register),
+	/// The mask of the flag in P.
+	Flag(u8),
+	/// The byte at the address.
+	Memory(Box<Expression>),
+	Binary(Box<Expression>, Operator, Box<Expression>),
+}
+
+/// Operators, from the loosest to the tightest, and the tokens that are them.
+const PRECEDENCE: [&[(&str, Operator)]; 5] = [
+	&[("||", Operator::Or)],
+	&[("&&", Operator::And)],
+	&[("==", Operator::Equal), ("!=", Operator::NotEqual), ("<=", Operator::LessOrEqual), ("<", Operator::Less),
+		(">=", Operator::GreaterOrEqual), (">", Operator::Greater)],
+	&[("+", Operator::Add), ("-", Operator::Subtract)],
+	&[(":", Operator::Pair)],
+];
+
+/// Flag names, and their masks in P.
+const FLAGS: [(&str, u8); 7] = [("N", 0x80), ("V", 0x40), ("B", 0x10), ("D", 0x08), ("I", 0x04), ("Z", 0x02), ("C", 0x01)];
+
+impl Expression {
+	/// Parse `text`. Symbols are replaced with their addresses.
+	pub fn parse(text: &str, symbols: &SymbolTable) -> Result<Expression, String> {
+		let tokens = tokenize(text)?;
+		let mut parser = Parser { tokens, position: 0, symbols };
+		let expression = parser.binary(0)?;
+		match parser.tokens.get(parser.position) {
+			None => Ok(expression),
+			Some(token) => Err(format!("Unexpected '{}' in '{}'", token, text)),
+		}
+	}
+
+	/// The value with the registers in `state`, and the memory read with `peek`.
+	pub fn evaluate(&self, state: &CpuState, peek: &dyn Fn(u16) -> u8) -> i64 {
+		match self {
+			Expression::Number(value) => *value,
+			Expression::Register(register) => match register {
+				Register::A => state.a as i64,
+				Register::X => state.x as i64,
+				Register::Y => state.y as i64,
+				Register::SP => state.s as i64,
+				Register::PC => state.pc as i64,
+				Register::P => state.p as i64,
+			},
+			Expression::Flag(mask) => (state.p & mask != 0) as i64,
+			Expression::Memory(addr) => peek(addr.evaluate(state, peek) as u16) as i64,
+			Expression::Binary(left, operator, right) => {
+				let left = left.evaluate(state, peek);
+				// && and || don't evaluate the right side when the left decides. Nothing has side effects, it's just faster.
+				match operator {
+					Operator::And if left == 0 => return 0,
+					Operator::Or if left != 0 => return 1,
+					_ => {}
+				}
+				let right = right.evaluate(state, peek);
+				match operator {
+					Operator::Add => left.wrapping_add(right),
+					Operator::Subtract => left.wrapping_sub(right),
+					Operator::Equal => (left == right) as i64,
+					Operator::NotEqual => (left != right) as i64,
+					Operator::Less => (left < right) as i64,
+					Operator::LessOrEqual => (left <= right) as i64,
+					Operator::Greater => (left > right) as i64,
+					Operator::GreaterOrEqual => (left >= right) as i64,
+					Operator::And | Operator::Or => (right != 0) as i64,
+					Operator::Pair => ((left & 0xFF) << 8) | (right & 0xFF),
+				}
+			}
+		}
+	}
+
+	/// Not 0.
+	pub fn is_true(&self, state: &CpuState, peek: &dyn Fn(u16) -> u8) -> bool {
+		self.evaluate(state, peek) != 0
+	}
+}
+
+#[derive(Clone, PartialEq, Eq, Debug)]
+enum Token {
+	Number(i64),
+	Name(String),
+	/// Operators and brackets.
+	Symbol(&'static str),
+}
+
+impl fmt::Display for Token {
+	fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+		match self {
+			Token::Number(value) => write!(f, "{}", value),
+			Token::Name(name) => write!(f, "{}", name),
+			Token::Symbol(symbol) => write!(f, "{}", symbol),
+		}
+	}
+}
+
+/// Longer first, so `<=` isn't `<` and `=`.
+const SYMBOLS: [&str; 15] = ["||", "&&", "==", "!=", "<=", ">=", "<", ">", "+", "-", ":", "[", "]", "(", ")"];
+
+fn tokenize(text: &str) -> Result<Vec<Token>, String> {
+	let mut tokens = vec![];
+	let mut rest = text.trim_start();
+	while !rest.is_empty() {
+		if let Some(symbol) = SYMBOLS.iter().find(|symbol| rest.starts_with(*symbol)) {
+			tokens.push(Token::Symbol(symbol));
+			rest = &rest[symbol.len()..];
+		} else {
+			// A word: a number or a name. `$` only starts hex numbers.
+			let length = rest.char_indices().skip(1)
+				.find(|(_, c)| !(c.is_ascii_alphanumeric() || *c == '_' || *c == '@'))
+				.map_or(rest.len(), |(i, _)| i);
+			let word = &rest[..length];
+			tokens.push(word_token(word).ok_or_else(|| format!("Unexpected '{}' in '{}'", word, text))?);
+			rest = &rest[length..];
+		}
+		rest = rest.trim_start();
+	}
+	Ok(tokens)
+}
+
+fn word_token(word: &str) -> Option<Token> {
+	let first = word.chars().next()?;
+	let hex = word.strip_prefix('$').or_else(|| word.strip_prefix("0x")).or_else(|| word.strip_prefix("0X"));
+	match hex {
+		Some(hex) => i64::from_str_radix(hex, 16).ok().map(Token::Number),
+		None if first.is_ascii_digit() => word.parse().ok().map(Token::Number),
+		None if first.is_ascii_alphabetic() || first == '_' || first == '@' => Some(Token::Name(word.to_string())),
+		None => None,
+	}
+}
+
+struct Parser<'a> {
+	tokens: Vec<Token>,
+	position: usize,
+	symbols: &'a SymbolTable,
+}
+
+impl Parser<'_> {
+	/// Operators of `PRECEDENCE[level]` and tighter, left to right.
+	fn binary(&mut self, level: usize) -> Result<Expression, String> {
+		if level == PRECEDENCE.len() {
+			return self.operand();
+		}
+		let mut left = self.binary(level + 1)?;
+		while let Some(&(_, operator)) = PRECEDENCE[level].iter().find(|(token, _)| self.peek() == Some(&Token::Symbol(token))) {
+			self.position += 1;
+			let right = self.binary(level + 1)?;
+			left = Expression::Binary(Box::new(left), operator, Box::new(right));
+		}
+		Ok(left)
+	}
+
+	fn operand(&mut self) -> Result<Expression, String> {
+		let token = self.peek().cloned().ok_or("Expected a value at the end")?;
+		self.position += 1;
+		match token {
+			Token::Number(value) => Ok(Expression::Number(value)),
+			Token::Name(name) => self.name(&name),
+			Token::Symbol("[") => {
+				let addr = self.binary(0)?;
+				self.expect("]")?;
+				Ok(Expression::Memory(Box::new(addr)))
+			}
+			Token::Symbol("(") => {
+				let inner = self.binary(0)?;
+				self.expect(")")?;
+				Ok(inner)
+			}
+			token => Err(format!("Expected a value, got '{}'", token)),
+		}
+	}
+
+	fn name(&self, name: &str) -> Result<Expression, String> {
+		if let Ok(register) = name.parse() {
+			return Ok(Expression::Register(register));
+		}
+		let upper = name.to_ascii_uppercase();
+		if let Some(&(_, mask)) = FLAGS.iter().find(|(flag, _)| *flag == upper) {
+			return Ok(Expression::Flag(mask));
+		}
+		self.symbols.resolve(name).map(|addr| Expression::Number(addr as i64))
+			.ok_or_else(|| format!("Unknown name '{}': expected a register, a flag or a symbol", name))
+	}
+
+	fn expect(&mut self, symbol: &'static str) -> Result<(), String> {
+		match self.peek() {
+			Some(Token::Symbol(found)) if *found == symbol => {
+				self.position += 1;
+				Ok(())
+			}
+			Some(token) => Err(format!("Expected '{}', got '{}'", symbol, token)),
+			None => Err(format!("Expected '{}' at the end", symbol)),
+		}
+	}
+
+	fn peek(&self) -> Option<&Token> {
+		self.tokens.get(self.position)
+	}
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+
+	fn evaluate(text: &str, state: &CpuState) -> i64 {
+		let memory = |addr: u16| (addr & 0xFF) as u8;
+		Expression::parse(text, &SymbolTable::new()).unwrap().evaluate(state, &memory)
+	}
+
+	#[test]
+	fn parse_test() {
+		let expression = Expression::parse("A == 0x20 && [$10] > 5", &SymbolTable::new()).unwrap();
+		assert_eq!(expression, Expression::Binary(
+			Box::new(Expression::Binary(Box::new(Expression::Register(Register::A)), Operator::Equal, Box::new(Expression::Number(0x20)))),
+			Operator::And,
+			Box::new(Expression::Binary(
+				Box::new(Expression::Memory(Box::new(Expression::Number(0x10)))),
+				Operator::Greater,
+				Box::new(Expression::Number(5)),
+			)),
+		));
+
+		let symbols = SymbolTable::parse_nl("$0300#buffer#").unwrap();
+		assert_eq!(Expression::parse("[buffer]", &symbols).unwrap(), Expression::Memory(Box::new(Expression::Number(0x0300))));
+
+		// `:` is tighter than `+`.
+		assert_eq!(Expression::parse("[$FD]:[$FC] + 1", &symbols).unwrap(), Expression::Binary(
+			Box::new(Expression::Binary(
+				Box::new(Expression::Memory(Box::new(Expression::Number(0xFD)))),
+				Operator::Pair,
+				Box::new(Expression::Memory(Box::new(Expression::Number(0xFC)))),
+			)),
+			Operator::Add,
+			Box::new(Expression::Number(1)),
+		));
+
+		for bad in ["", "A ==", "A = 1", "[$10", "(A", "A B", "foo > 1", "$zz", "A == 1)", "[$FD]:", ":[$FC]"] {
+			assert!(Expression::parse(bad, &symbols).is_err(), "{}", bad);
+		}
+	}
+
+	#[test]
+	fn evaluate_test() {
+		// The memory is the low byte of the address.
+		let state = CpuState { pc: 0xC123, a: 0x20, x: 3, y: 0, p: 0b1000_0011, s: 0xFD, cycles: 0 };
+		assert_eq!(evaluate("[$0300+X]", &state), 3);
+		assert_eq!(evaluate("[$10] - 1", &state), 0x0F);
+		assert_eq!(evaluate("pc == $C123 && sp == 0xFD", &state), 1);
+		assert_eq!(evaluate("N + Z + C + V", &state), 3);
+		assert_eq!(evaluate("A < 32 || Y >= 0", &state), 1);
+		assert_eq!(evaluate("(A == 1 || X == 3) && Y != 0", &state), 0);
+		assert_eq!(evaluate("[$FD]:[$FC]", &state), 0xFDFC);
+		assert_eq!(evaluate("[$10+X]:A", &state), 0x1320);
+		assert_eq!(evaluate("$1234:$5678", &state), 0x3478);
+	}
+}
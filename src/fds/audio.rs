@@ -0,0 +1,446 @@
+// FDS sound: https://www.nesdev.org/wiki/FDS_audio
+// A wavetable channel: 64 steps of 6 bits, that the game writes, played at the pitch of $4082-$4083. A modulator bends
+// the pitch, from a table of 64 steps of 3 bits, and both have an envelope for their gain.
+//
+// | Address | Register |
+// |---|---|
+// | $4040-$407F | The wavetable: writable with bit 7 of $4089. A read is the step played, or the one at the address while writable |
+// | $4080 | Volume envelope: off (bit 7, and the gain is bits 0-5), up (bit 6) or down, speed (bits 0-5) |
+// | $4082 / $4083 | Pitch: low 8 bits / high 4 bits, halt the wave (bit 7), halt the envelopes (bit 6) |
+// | $4084 | Modulator envelope, like $4080 |
+// | $4085 | Modulator counter, 7 bits signed |
+// | $4086 / $4087 | Modulator pitch: low 8 bits / high 4 bits, halt the modulator (bit 7) |
+// | $4088 | Modulator table: while it's halted, a write fills the next 2 steps |
+// | $4089 | Wavetable writable (bit 7), master volume (bits 0-1): 2/2, 2/3, 2/4 or 2/5 |
+// | $408A | Envelope speed: the envelopes step every 8 * (their speed + 1) * this CPU cycles |
+// | $4090 / $4092 | Read: the gain of the volume / modulator envelope |
+//
+// Every CPU cycle, the pitch (bent by the modulator) is added to a 16 bit accumulator, and the wave goes to its next
+// step when it overflows. The modulator does the same with its own pitch, and adds its step to its counter: 0, 1, 2,
+// 4, -4, -2, -1, or 4 is 0 again. The counter times the modulator gain bends the pitch, see `pitch_offset`.
+//
+// The output is the step, times the volume gain (at most 32), times the master volume. The low-pass filter of the RAM
+// adapter isn't here.
+
+use crate::save_state::{SaveState, StateReader, StateWriter};
+
+/// The high byte of $40xx, which is what the bus has on the bits without a register, after an absolute read.
+pub(super) const OPEN_BUS: u8 = 0x40;
+/// What the 8 steps of the modulator table add to its counter. 4 resets it.
+const MODULATOR_STEPS: [i8; 8] = [0, 1, 2, 4, 0, -4, -2, -1];
+const MODULATOR_RESET: u8 = 4;
+/// 2/2, 2/3, 2/4 and 2/5, of 36.
+const MASTER_VOLUMES: [u32; 4] = [36, 24, 17, 14];
+/// The output at full volume is 2.4 times an APU pulse at full volume, see `APU::output`.
+const STEP_LEVEL: f32 = 2.4 * 95.88 / (8128.0 / 15.0 + 100.0) / 63.0;
+
+/// An envelope of a gain: the one of the volume, or of the modulator.
+#[derive(Clone, Debug, Default)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+struct Envelope {
+	/// $4080 or $4084.
+	control: u8,
+	gain: u8,
+	/// CPU cycles to the next step.
+	timer: u32,
+}
+
+impl Envelope {
+	/// Restarts the timer, and sets the gain when the envelope is off.
+	fn write(&mut self, data: u8, speed: u8) {
+		self.control = data;
+		self.restart(speed);
+		if self.off() {
+			self.gain = data & 0x3F;
+		}
+	}
+
+	fn off(&self) -> bool {
+		self.control & 0x80 != 0
+	}
+
+	fn restart(&mut self, speed: u8) {
+		self.timer = 8 * ((self.control & 0x3F) as u32 + 1) * speed as u32;
+	}
+
+	/// A CPU cycle: the gain goes up or down, between 0 and 32, when the timer gets to 0.
+	fn clock(&mut self, speed: u8) {
+		if self.off() || speed == 0 {
+			return;
+		}
+		self.timer = self.timer.saturating_sub(1);
+		if self.timer == 0 {
+			self.restart(speed);
+			self.gain = if self.control & 0x40 != 0 { (self.gain + 1).min(32) } else { self.gain.saturating_sub(1) };
+		}
+	}
+}
+
+#[derive(Clone, Debug)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub(super) struct FdsAudio {
+	#[cfg_attr(feature = "serde", serde(with = "crate::serde_bytes_base64"))]
+	wave: [u8; 64],
+	wave_step: u8,
+	wave_accumulator: u16,
+	/// $4082-$4083, 12 bits.
+	pitch: u16,
+	/// $4083 bits 6-7.
+	halt: u8,
+	volume: Envelope,
+	modulator: Envelope,
+	/// 7 bits signed, -64 to 63.
+	modulator_counter: i8,
+	modulator_pitch: u16,
+	modulator_halted: bool,
+	#[cfg_attr(feature = "serde", serde(with = "crate::serde_bytes_base64"))]
+	modulator_table: [u8; 64],
+	modulator_step: u8,
+	modulator_accumulator: u16,
+	/// $4089.
+	master: u8,
+	/// $408A.
+	envelope_speed: u8,
+	output: u8,
+}
+
+impl Default for FdsAudio {
+	fn default() -> Self {
+		Self::new()
+	}
+}
+
+impl FdsAudio {
+	/// Silent, with the envelope speed the BIOS sets.
+	pub(super) fn new() -> Self {
+		FdsAudio {
+			wave: [0; 64],
+			wave_step: 0,
+			wave_accumulator: 0,
+			pitch: 0,
+			halt: 0,
+			volume: Envelope::default(),
+			modulator: Envelope::default(),
+			modulator_counter: 0,
+			modulator_pitch: 0,
+			modulator_halted: false,
+			modulator_table: [0; 64],
+			modulator_step: 0,
+			modulator_accumulator: 0,
+			master: 0,
+			envelope_speed: 0xE8,
+			output: 0,
+		}
+	}
+
+	fn wave_writable(&self) -> bool {
+		self.master & 0x80 != 0
+	}
+
+	/// A write to $4040-$408A. Returns false when there's no register at `addr`.
+	pub(super) fn write(&mut self, addr: u16, data: u8) -> bool {
+		match addr {
+			0x4040..=0x407F => {
+				if self.wave_writable() {
+					self.wave[(addr & 0x3F) as usize] = data & 0x3F;
+				}
+			}
+			0x4080 => self.volume.write(data, self.envelope_speed),
+			0x4082 => self.pitch = self.pitch & 0x0F00 | data as u16,
+			0x4083 => {
+				self.pitch = self.pitch & 0x00FF | ((data & 0x0F) as u16) << 8;
+				self.halt = data & 0xC0;
+				if self.halt & 0x80 != 0 {
+					self.wave_step = 0;
+					self.wave_accumulator = 0;
+				}
+				if self.halt & 0x40 != 0 {
+					self.volume.restart(self.envelope_speed);
+					self.modulator.restart(self.envelope_speed);
+				}
+			}
+			0x4084 => self.modulator.write(data, self.envelope_speed),
+			0x4085 => self.modulator_counter = ((data << 1) as i8) >> 1,
+			0x4086 => self.modulator_pitch = self.modulator_pitch & 0x0F00 | data as u16,
+			0x4087 => {
+				self.modulator_pitch = self.modulator_pitch & 0x00FF | ((data & 0x0F) as u16) << 8;
+				self.modulator_halted = data & 0x80 != 0;
+				if self.modulator_halted {
+					self.modulator_accumulator = 0;
+				}
+			}
+			0x4088 => {
+				if self.modulator_halted {
+					for step in [self.modulator_step, self.modulator_step + 1] {
+						self.modulator_table[(step & 0x3F) as usize] = data & 0b111;
+					}
+					self.modulator_step = (self.modulator_step + 2) & 0x3F;
+				}
+			}
+			0x4089 => self.master = data,
+			0x408A => self.envelope_speed = data,
+			_ => return false,
+		}
+		true
+	}
+
+	/// A read of $4040-$4092. None where there's no register.
+	pub(super) fn read(&self, addr: u16) -> Option<u8> {
+		let value = match addr {
+			0x4040..=0x407F => self.wave[if self.wave_writable() { (addr & 0x3F) as usize } else { self.wave_step as usize }],
+			0x4090 => self.volume.gain,
+			0x4092 => self.modulator.gain,
+			_ => return None,
+		};
+		Some(OPEN_BUS | value)
+	}
+
+	/// How much the modulator bends the pitch now: the counter times the gain, in 1/16 (rounded the way the chip does,
+	/// and wrapped), times the pitch, in 1/64.
+	fn pitch_offset(&self) -> i32 {
+		if self.modulator_halted || self.modulator_pitch == 0 {
+			return 0;
+		}
+		let counter = self.modulator_counter as i32;
+		let product = counter * self.modulator.gain as i32;
+		let mut offset = product >> 4;
+		if product & 0x0F != 0 && offset & 0x80 == 0 {
+			offset += if counter < 0 { -1 } else { 2 };
+		}
+		if offset >= 192 {
+			offset -= 256;
+		} else if offset < -64 {
+			offset += 256;
+		}
+		let bent = self.pitch as i32 * offset;
+		(bent >> 6) + (bent & 0x3F >= 32) as i32
+	}
+
+	/// A CPU cycle.
+	pub(super) fn clock(&mut self) {
+		if self.halt == 0 {
+			self.volume.clock(self.envelope_speed);
+			self.modulator.clock(self.envelope_speed);
+		}
+
+		if !self.modulator_halted && self.modulator_pitch > 0 {
+			let (accumulator, overflow) = self.modulator_accumulator.overflowing_add(self.modulator_pitch);
+			self.modulator_accumulator = accumulator;
+			if overflow {
+				let step = self.modulator_table[self.modulator_step as usize];
+				let counter = if step == MODULATOR_RESET { 0 } else { self.modulator_counter + MODULATOR_STEPS[step as usize] };
+				// Back to 7 bits.
+				self.modulator_counter = ((counter as u8) << 1) as i8 >> 1;
+				self.modulator_step = (self.modulator_step + 1) & 0x3F;
+			}
+		}
+
+		// The output holds while the game writes the wavetable.
+		if self.wave_writable() {
+			return;
+		}
+		let pitch = self.pitch as i32 + self.pitch_offset();
+		if self.halt & 0x80 == 0 && pitch > 0 {
+			let (accumulator, overflow) = self.wave_accumulator.overflowing_add(pitch as u16);
+			self.wave_accumulator = accumulator;
+			if overflow {
+				self.wave_step = (self.wave_step + 1) & 0x3F;
+			}
+		}
+		let gain = self.volume.gain.min(32) as u32;
+		self.output = (self.wave[self.wave_step as usize] as u32 * gain * MASTER_VOLUMES[(self.master & 0b11) as usize] / 1152) as u8;
+	}
+
+	/// 0-63.
+	pub(super) fn output(&self) -> u8 {
+		self.output
+	}
+
+	/// `output`, at the level of the APU channels.
+	pub(super) fn level(&self) -> f32 {
+		self.output() as f32 * STEP_LEVEL
+	}
+}
+
+impl SaveState for Envelope {
+	fn save_state(&self, out: &mut StateWriter) {
+		out.u8(self.control);
+		out.u8(self.gain);
+		out.u32(self.timer);
+	}
+
+	fn load_state(&mut self, input: &mut StateReader) -> Result<(), String> {
+		self.control = input.u8()?;
+		self.gain = input.u8()?;
+		self.timer = input.u32()?;
+		Ok(())
+	}
+}
+
+impl SaveState for FdsAudio {
+	fn save_state(&self, out: &mut StateWriter) {
+		out.bytes(&self.wave);
+		out.u8(self.wave_step);
+		out.u16(self.wave_accumulator);
+		out.u16(self.pitch);
+		out.u8(self.halt);
+		self.volume.save_state(out);
+		self.modulator.save_state(out);
+		out.u8(self.modulator_counter as u8);
+		out.u16(self.modulator_pitch);
+		out.bool(self.modulator_halted);
+		out.bytes(&self.modulator_table);
+		out.u8(self.modulator_step);
+		out.u16(self.modulator_accumulator);
+		out.u8(self.master);
+		out.u8(self.envelope_speed);
+		out.u8(self.output);
+	}
+
+	fn load_state(&mut self, input: &mut StateReader) -> Result<(), String> {
+		input.bytes(&mut self.wave)?;
+		self.wave_step = input.u8()?;
+		self.wave_accumulator = input.u16()?;
+		self.pitch = input.u16()?;
+		self.halt = input.u8()?;
+		self.volume.load_state(input)?;
+		self.modulator.load_state(input)?;
+		self.modulator_counter = input.u8()? as i8;
+		self.modulator_pitch = input.u16()?;
+		self.modulator_halted = input.bool()?;
+		input.bytes(&mut self.modulator_table)?;
+		self.modulator_step = input.u8()?;
+		self.modulator_accumulator = input.u16()?;
+		self.master = input.u8()?;
+		self.envelope_speed = input.u8()?;
+		self.output = input.u8()?;
+		Ok(())
+	}
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+
+	/// A ramp in the wavetable, the volume envelope off at `gain`, and the pitch.
+	fn ramp(gain: u8, pitch: u16) -> FdsAudio {
+		let mut audio = FdsAudio::new();
+		audio.write(0x4089, 0x80);
+		for step in 0..64 {
+			audio.write(0x4040 + step, step as u8);
+		}
+		audio.write(0x4089, 0);
+		audio.write(0x4080, 0x80 | gain);
+		audio.write(0x4082, pitch as u8);
+		audio.write(0x4083, (pitch >> 8) as u8);
+		audio
+	}
+
+	#[test]
+	fn wave_test() {
+		// The highest pitch: the accumulator overflows every 65536 / 4095 cycles, a bit more than 16.
+		let mut audio = ramp(32, 0xFFF);
+		let mut steps = vec![];
+		for _ in 0..64 {
+			audio.clock();
+			steps.push(audio.output());
+		}
+		// The ramp, at full volume.
+		assert_eq!(steps[..16], [0; 16]);
+		assert_eq!(steps[16], 1);
+		assert_eq!(audio.read(0x4040), Some(OPEN_BUS | 3));
+		assert_eq!(steps[63], 3);
+
+		// The master volume: 2/5 of it.
+		audio.write(0x4089, 3);
+		audio.clock();
+		assert_eq!(audio.output(), (3 * 32 * 14 / 1152) as u8);
+
+		// Halted: back to the first step, and it stays there.
+		audio.write(0x4083, 0x8F);
+		for _ in 0..100 {
+			audio.clock();
+		}
+		assert_eq!(audio.read(0x4040), Some(OPEN_BUS));
+
+		// While writable, the output holds, and a read is the step at the address.
+		let mut audio = ramp(32, 0x800);
+		for _ in 0..1000 {
+			audio.clock();
+		}
+		let output = audio.output();
+		assert!(output > 0);
+		audio.write(0x4089, 0x80);
+		audio.write(0x407F, 0);
+		audio.clock();
+		assert_eq!((audio.output(), audio.read(0x407F)), (output, Some(OPEN_BUS)));
+	}
+
+	#[test]
+	fn envelope_test() {
+		// Up, speed 0: a step every 8 * 1 * $E8 cycles, up to 32.
+		let mut audio = ramp(0, 0);
+		audio.write(0x4080, 0x40);
+		assert_eq!(audio.read(0x4090), Some(OPEN_BUS));
+		for _ in 0..8 * 0xE8 - 1 {
+			audio.clock();
+		}
+		assert_eq!(audio.read(0x4090), Some(OPEN_BUS));
+		audio.clock();
+		assert_eq!(audio.read(0x4090), Some(OPEN_BUS | 1));
+		for _ in 0..40 * 8 * 0xE8 {
+			audio.clock();
+		}
+		assert_eq!(audio.read(0x4090), Some(OPEN_BUS | 32));
+
+		// Halted by $4083 bit 6.
+		audio.write(0x4080, 0x00);
+		audio.write(0x4083, 0x40);
+		for _ in 0..10 * 8 * 0xE8 {
+			audio.clock();
+		}
+		assert_eq!(audio.read(0x4090), Some(OPEN_BUS | 32));
+
+		// Off: the gain is the speed, even past 32 (the output stops at 32).
+		audio.write(0x4080, 0xBF);
+		assert_eq!(audio.read(0x4090), Some(OPEN_BUS | 63));
+	}
+
+	#[test]
+	fn modulator_test() {
+		// Writes to the table only while halted, 2 steps each.
+		let mut audio = ramp(32, 0x100);
+		audio.write(0x4087, 0x80);
+		for step in [1, 1, 7, 4] {
+			audio.write(0x4088, step);
+		}
+		assert_eq!(audio.modulator_table[..10], [1, 1, 1, 1, 7, 7, 4, 4, 0, 0]);
+		audio.write(0x4087, 0);
+		audio.write(0x4088, 2);
+		assert_eq!(audio.modulator_table[8], 0);
+
+		// The counter: +1 +1 +1 +1 -1 -1, then 4 resets it.
+		audio.write(0x4085, 0x7E);
+		assert_eq!(audio.modulator_counter, -2);
+		audio.write(0x4084, 0x80 | 16);
+		audio.modulator_step = 0;
+		audio.write(0x4086, 0);
+		audio.write(0x4087, 0x08);
+		let mut counters = vec![];
+		for _ in 0..8 * 32 {
+			audio.clock();
+			if counters.last() != Some(&audio.modulator_counter) {
+				counters.push(audio.modulator_counter);
+			}
+		}
+		assert_eq!(counters, [-2, -1, 0, 1, 2, 1, 0]);
+
+		// A counter of 2 with a gain of 16: 2 / 64 of the pitch more, 8.
+		audio.modulator_counter = 2;
+		assert_eq!(audio.pitch_offset(), 8);
+		audio.modulator_counter = -2;
+		assert_eq!(audio.pitch_offset(), -8);
+		audio.write(0x4087, 0x88);
+		assert_eq!(audio.pitch_offset(), 0);
+	}
+}
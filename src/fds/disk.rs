@@ -0,0 +1,171 @@
+// FDS disk images (.fds): https://www.nesdev.org/wiki/FDS_file_format
+// The sides of the disks, 65500 bytes each (side A of the first disk, its side B, then the next disk), after an
+// optional 16 byte header: "FDS" $1A, and the number of sides, which isn't used here (the size of the file says it).
+//
+// A side is blocks, one after the other, and zeros after the last one:
+//
+// | Block | Size | Description |
+// |---|---|---|
+// | 1 | 56 | Disk info: "*NINTENDO-HVC*", the game, the side |
+// | 2 | 2 | The number of files |
+// | 3 | 16 | File header: number, name, where it's loaded, and its size (bytes 13-14) |
+// | 4 | 1 + size | File data, of the file header before it |
+//
+// The image has only the blocks. On the disk, a block comes after a gap of 0 bits ended by a 1 (the byte $80 for the
+// drive), and has a CRC after it. `raw_side` puts them back, so the drive (fds.rs) reads what it would on a disk.
+
+use crate::error::NesError;
+
+pub const SIDE_SIZE: usize = 65500;
+const HEADER: &[u8] = b"FDS\x1A";
+const HEADER_SIZE: usize = 16;
+/// The start of every side.
+const DISK_INFO: &[u8] = b"\x01*NINTENDO-HVC*";
+/// 28300 bits before the first block.
+const FIRST_GAP: usize = 28300 / 8;
+/// 976 bits after every block.
+const GAP: usize = 976 / 8;
+/// The 1 bit at the end of a gap, and the 7 last 0s.
+pub const BLOCK_START: u8 = 0x80;
+
+/// `bytes` start like an FDS disk image: with the header, or with the disk info block of the first side.
+pub fn is_fds(bytes: &[u8]) -> bool {
+	bytes.starts_with(HEADER) || bytes.starts_with(DISK_INFO)
+}
+
+/// The sides of `bytes`, without the header. Err when they aren't whole sides, or one doesn't start with the disk info
+/// block.
+pub fn sides(bytes: &[u8]) -> Result<Vec<&[u8]>, NesError> {
+	let data = without_header(bytes);
+	if data.is_empty() || !data.len().is_multiple_of(SIDE_SIZE) {
+		return Err(NesError::Disk(format!("An FDS disk image has sides of {} bytes, this one has {} bytes", SIDE_SIZE, data.len())));
+	}
+	let sides: Vec<&[u8]> = data.chunks(SIDE_SIZE).collect();
+	if let Some(side) = sides.iter().position(|side| !side.starts_with(DISK_INFO)) {
+		return Err(NesError::Disk(format!("Side {} of the disk image doesn't start with the disk info block", side + 1)));
+	}
+	Ok(sides)
+}
+
+/// The sides, past the header if there's one. What identifies the game, like PRG and CHR ROM for a cartridge.
+pub fn without_header(bytes: &[u8]) -> &[u8] {
+	if bytes.starts_with(HEADER) { bytes.get(HEADER_SIZE..).unwrap_or(&[]) } else { bytes }
+}
+
+/// A side as the drive reads it: the first gap, then every block with $80 before it, and its CRC and a gap after it.
+/// It ends at the first byte that isn't one of the 4 blocks (the zeros after the last one), or a block that doesn't fit
+/// in the side.
+pub fn raw_side(side: &[u8]) -> Vec<u8> {
+	let mut raw = vec![0; FIRST_GAP];
+	let mut start = 0;
+	let mut file_size = 0;
+	while start < side.len() {
+		let size = match side[start] {
+			1 => 56,
+			2 => 2,
+			3 => 16,
+			4 => 1 + file_size,
+			_ => break,
+		};
+		let Some(block) = side.get(start..start + size) else {
+			break;
+		};
+		if block[0] == 3 {
+			file_size = u16::from_le_bytes([block[13], block[14]]) as usize;
+		}
+		raw.push(BLOCK_START);
+		raw.extend_from_slice(block);
+		let crc = [BLOCK_START].iter().chain(block).chain(&[0, 0]).fold(0, |crc, &byte| crc_update(crc, byte));
+		raw.extend_from_slice(&crc.to_le_bytes());
+		raw.resize(raw.len() + GAP, 0);
+		start += size;
+	}
+	raw
+}
+
+/// `crc` with the bits of `byte`, the low one first (CRC-16 with the polynomial $8408, the way the RAM adapter computes
+/// it). The CRC of a block is the one of $80, the block and 2 zeros, written low byte first after the block.
+pub fn crc_update(crc: u16, byte: u8) -> u16 {
+	(0..8).fold(crc, |crc, bit| {
+		let crc = if crc & 1 != 0 { crc >> 1 ^ 0x8408 } else { crc >> 1 };
+		if byte >> bit & 1 != 0 { crc ^ 0x8000 } else { crc }
+	})
+}
+
+/// Build disk images in memory, for tests.
+#[cfg(test)]
+pub mod test_disk {
+	use super::*;
+
+	/// A side with a single file of `data`, loaded at `load` in PRG RAM.
+	pub fn side(data: &[u8], load: u16) -> Vec<u8> {
+		let mut side = DISK_INFO.to_vec();
+		side.resize(56, 0);
+		side.extend_from_slice(&[2, 1]);
+		let mut header = vec![3, 0, 0];
+		header.extend_from_slice(b"TESTFILE");
+		header.extend_from_slice(&load.to_le_bytes());
+		header.extend_from_slice(&(data.len() as u16).to_le_bytes());
+		header.push(0);
+		side.extend_from_slice(&header);
+		side.push(4);
+		side.extend_from_slice(data);
+		side.resize(SIDE_SIZE, 0);
+		side
+	}
+
+	/// An image of `sides`, with the header.
+	pub fn image(sides: &[Vec<u8>]) -> Vec<u8> {
+		let mut image = HEADER.to_vec();
+		image.push(sides.len() as u8);
+		image.resize(HEADER_SIZE, 0);
+		for side in sides {
+			image.extend_from_slice(side);
+		}
+		image
+	}
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+
+	#[test]
+	fn sides_test() {
+		let side = test_disk::side(&[0xEA; 3], 0x6000);
+		let image = test_disk::image(&[side.clone(), side.clone()]);
+		assert!(is_fds(&image));
+		assert_eq!(sides(&image).unwrap(), [&side[..], &side[..]]);
+		// Without the header.
+		assert!(is_fds(&side));
+		assert_eq!(sides(&side).unwrap().len(), 1);
+		assert_eq!(without_header(&image), [&side[..], &side[..]].concat());
+
+		assert!(!is_fds(b"NES\x1A"));
+		assert!(matches!(sides(&image[..image.len() - 1]), Err(NesError::Disk(message)) if message.contains("130999 bytes")));
+		let mut image = image;
+		image[16 + SIDE_SIZE + 1] = b'X';
+		assert!(matches!(sides(&image), Err(NesError::Disk(message)) if message.starts_with("Side 2 ")));
+	}
+
+	#[test]
+	fn raw_side_test() {
+		let raw = raw_side(&test_disk::side(&[0x11, 0x22, 0x33], 0x6000));
+		// The first gap, and the disk info block after $80.
+		assert!(raw[..FIRST_GAP].iter().all(|&byte| byte == 0));
+		assert_eq!(&raw[FIRST_GAP..FIRST_GAP + 16], b"\x80\x01*NINTENDO-HVC*");
+
+		// The 4 blocks, each with $80 before it, and its CRC and a gap after it: the CRC of a block and its CRC is 0.
+		let mut start = FIRST_GAP;
+		for size in [56, 2, 16, 4] {
+			assert_eq!(raw[start], BLOCK_START);
+			let block = &raw[start..start + 1 + size + 2];
+			assert_eq!(block.iter().fold(0, |crc, &byte| crc_update(crc, byte)), 0);
+			start += 1 + size + 2;
+			assert!(raw[start..start + GAP].iter().all(|&byte| byte == 0));
+			start += GAP;
+		}
+		assert_eq!(&raw[start - GAP - 6..start - GAP - 2], [4, 0x11, 0x22, 0x33]);
+		assert_eq!(raw.len(), start);
+	}
+}
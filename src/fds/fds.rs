@@ -0,0 +1,538 @@
+// The RAM adapter of the Famicom Disk System: https://www.nesdev.org/wiki/Family_Computer_Disk_System
+// It goes in the cartridge slot, with 32KB of PRG RAM at $6000-$DFFF, the 8KB BIOS at $E000 (disksys.rom, which isn't
+// included), 8KB of CHR RAM, a timer IRQ, the port of the disk drive, and a sound channel (audio.rs). The games are on
+// disks, and the BIOS loads them into RAM. It's mapper 20 for emulators, which no iNES file uses: the cartridge is
+// made from the BIOS and a disk image instead, see `Cartridge::from_fds`.
+//
+// | Address | Register |
+// |---|---|
+// | $4020 / $4021 | Timer reload value: low / high byte |
+// | $4022 | Timer: repeat (bit 0), enabled (bit 1). Reloads the counter, and acknowledges the IRQ |
+// | $4023 | Disk registers on (bit 0), sound registers on (bit 1) |
+// | $4024 | Data to write to the disk |
+// | $4025 | Control: motor on (bit 0), transfer reset (bit 1), read mode (bit 2), horizontal mirroring (bit 3), CRC (bit 4), transfer start (bit 6), IRQ on transfer (bit 7) |
+// | $4030 | Read: timer IRQ (bit 0), byte transferred (bit 1), end of the head (bit 6). Acknowledges the IRQs |
+// | $4031 | Read: the byte read from the disk |
+// | $4032 | Read: no disk (bit 0), not ready (bit 1), write protected (bit 2) |
+// | $4033 | Read: the battery is good (bit 7) |
+// | $4040-$4092 | Sound, see audio.rs |
+//
+// The timer counts down every CPU cycle while it's enabled, and at 0 holds the IRQ and reloads, and stays enabled only
+// when it repeats.
+//
+// The drive goes over a side from the start, in raw bytes (see `disk::raw_side`): 50000 CPU cycles to rewind, then a
+// byte every 150 cycles, which holds the IRQ when it's on. In read mode, the gap before a block is skipped up to the
+// $80 that ends it. CRC errors aren't reported: the blocks of an image are all right. At the end of the side, the
+// motor stops.
+//
+// Another side is inserted 2 seconds after the disk is ejected, so the BIOS sees the change. What the game writes
+// stays on the disk here, and in save states, not in the image file.
+
+use crate::fds::audio::{FdsAudio, OPEN_BUS};
+use crate::fds::disk::{self, BLOCK_START, SIDE_SIZE};
+use crate::mapper::Mapper;
+use crate::ppu::ppu::Mirroring;
+use crate::save_state::{SaveState, StateReader, StateWriter};
+
+/// CPU cycles from the start of the side to its first byte.
+const REWIND_CYCLES: u32 = 50_000;
+/// CPU cycles between 2 bytes, about 96.4 kbit/s.
+const BYTE_CYCLES: u32 = 150;
+/// 2 seconds of CPU cycles, without a disk.
+const INSERT_CYCLES: u32 = 2 * 1_789_773;
+
+/// The bits of $4025.
+const MOTOR_ON: u8 = 0x01;
+const TRANSFER_RESET: u8 = 0x02;
+const READ_MODE: u8 = 0x04;
+const HORIZONTAL: u8 = 0x08;
+const CRC_CONTROL: u8 = 0x10;
+const TRANSFER_START: u8 = 0x40;
+const IRQ_ENABLED: u8 = 0x80;
+
+/// The RAM adapter, and the disk in the drive.
+#[derive(Clone, Debug)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct Fds {
+	/// The raw sides, each padded to `side_size`, one after the other.
+	#[cfg_attr(feature = "serde", serde(with = "crate::serde_bytes_base64"))]
+	disk: Vec<u8>,
+	side_size: usize,
+	/// The side in the drive, None when it's ejected.
+	side: Option<usize>,
+	/// CPU cycles until `side` is in the drive.
+	insert_delay: u32,
+
+	/// $4020-$4021.
+	timer_reload: u16,
+	timer: u16,
+	timer_enabled: bool,
+	timer_repeat: bool,
+	timer_irq: bool,
+	/// $4023.
+	disk_registers: bool,
+	sound_registers: bool,
+
+	/// $4025.
+	control: u8,
+	/// $4024.
+	write_data: u8,
+	/// $4031.
+	read_data: u8,
+	/// A byte was read or written, since the game last read $4030 or $4031.
+	transferred: bool,
+	disk_irq: bool,
+
+	/// The byte under the head, in the raw side.
+	position: usize,
+	/// CPU cycles to the next byte.
+	delay: u32,
+	/// The drive is reading or writing the side, past the rewind.
+	scanning: bool,
+	/// The head is at the start of the side (it rewinds at the next cycle with the motor on).
+	end_of_head: bool,
+	/// In read mode, the $80 of the block was read.
+	gap_ended: bool,
+	crc: u16,
+	/// The byte before was the CRC, in write mode.
+	previous_crc: bool,
+
+	audio: FdsAudio,
+}
+
+impl Fds {
+	/// A disk of `sides` (of an image, see `disk::sides`), with the first one inserted.
+	pub fn new(sides: &[&[u8]]) -> Self {
+		let raw: Vec<Vec<u8>> = sides.iter().map(|side| disk::raw_side(side)).collect();
+		let side_size = raw.iter().map(Vec::len).max().unwrap_or(0).max(SIDE_SIZE);
+		let mut disk = Vec::with_capacity(side_size * raw.len());
+		for side in &raw {
+			disk.extend_from_slice(side);
+			disk.resize(disk.len() + side_size - side.len(), 0);
+		}
+		Fds {
+			disk,
+			side_size,
+			side: Some(0),
+			insert_delay: 0,
+			timer_reload: 0,
+			timer: 0,
+			timer_enabled: false,
+			timer_repeat: false,
+			timer_irq: false,
+			disk_registers: true,
+			sound_registers: true,
+			control: 0,
+			write_data: 0,
+			read_data: 0,
+			transferred: false,
+			disk_irq: false,
+			position: 0,
+			delay: 0,
+			scanning: false,
+			end_of_head: true,
+			gap_ended: false,
+			crc: 0,
+			previous_crc: false,
+			audio: FdsAudio::new(),
+		}
+	}
+
+	/// The number of sides of the disks.
+	pub fn sides(&self) -> usize {
+		self.disk.len() / self.side_size
+	}
+
+	/// The side in the drive, or going in. None when it's ejected.
+	pub fn side(&self) -> Option<usize> {
+		self.side
+	}
+
+	/// Ejects the disk, and inserts `side` 2 seconds later. None only ejects. Err when there's no such side.
+	pub fn insert(&mut self, side: Option<usize>) -> Result<(), String> {
+		if let Some(side) = side.filter(|&side| side >= self.sides()) {
+			return Err(format!("The disk image has {} sides, there's no side {}", self.sides(), side + 1));
+		}
+		self.side = side;
+		self.insert_delay = INSERT_CYCLES;
+		Ok(())
+	}
+
+	fn inserted(&self) -> bool {
+		self.side.is_some() && self.insert_delay == 0
+	}
+
+	fn irq_on_transfer(&self) -> bool {
+		self.control & IRQ_ENABLED != 0
+	}
+
+	/// A CPU cycle of the drive.
+	fn clock_drive(&mut self) {
+		if !self.inserted() || self.control & MOTOR_ON == 0 {
+			self.end_of_head = true;
+			self.scanning = false;
+			return;
+		}
+		if self.control & TRANSFER_RESET != 0 && !self.scanning {
+			return;
+		}
+		if self.end_of_head {
+			self.delay = REWIND_CYCLES;
+			self.end_of_head = false;
+			self.position = 0;
+			self.gap_ended = false;
+			return;
+		}
+		if self.delay > 0 {
+			self.delay -= 1;
+			return;
+		}
+
+		self.scanning = true;
+		let index = self.side.unwrap_or(0) * self.side_size + self.position;
+		if self.control & READ_MODE != 0 {
+			self.read_byte(self.disk[index]);
+		} else {
+			self.disk[index] = self.write_byte();
+			self.gap_ended = false;
+		}
+		self.previous_crc = self.control & CRC_CONTROL != 0;
+		self.position += 1;
+		if self.position >= self.side_size {
+			self.control &= !MOTOR_ON;
+			self.disk_irq |= self.irq_on_transfer();
+		} else {
+			self.delay = BYTE_CYCLES;
+		}
+	}
+
+	/// The gap is skipped: the first byte the game gets is the one after $80.
+	fn read_byte(&mut self, byte: u8) {
+		let mut irq = self.irq_on_transfer();
+		if self.control & TRANSFER_START == 0 {
+			self.gap_ended = false;
+		} else if byte == BLOCK_START && !self.gap_ended {
+			self.gap_ended = true;
+			irq = false;
+		}
+		if self.gap_ended {
+			self.transferred = true;
+			self.read_data = byte;
+			self.disk_irq |= irq;
+		}
+	}
+
+	/// The byte the game wrote, or zeros before the transfer starts, or the CRC of what it wrote.
+	fn write_byte(&mut self) -> u8 {
+		if self.control & CRC_CONTROL == 0 {
+			self.transferred = true;
+			self.disk_irq |= self.irq_on_transfer();
+			// The gap before the block: the CRC starts at its $80.
+			if self.control & TRANSFER_START == 0 {
+				self.crc = 0;
+				return 0;
+			}
+			self.crc = disk::crc_update(self.crc, self.write_data);
+			return self.write_data;
+		}
+		if !self.previous_crc {
+			self.crc = disk::crc_update(disk::crc_update(self.crc, 0), 0);
+		}
+		let data = self.crc as u8;
+		self.crc >>= 8;
+		data
+	}
+
+	/// $4030-$4033 and the sound registers, without the side effects of a read.
+	fn register(&self, addr: u16) -> Option<u8> {
+		match addr {
+			0x4030..=0x4033 if self.disk_registers => Some(match addr {
+				0x4030 => self.timer_irq as u8 | (self.transferred as u8) << 1 | (self.end_of_head as u8) << 6,
+				0x4031 => self.read_data,
+				0x4032 => {
+					if !self.inserted() {
+						OPEN_BUS | 0b111
+					} else {
+						OPEN_BUS | (!self.scanning as u8) << 1
+					}
+				}
+				_ => 0x80,
+			}),
+			0x4040..=0x4092 if self.sound_registers => self.audio.read(addr),
+			_ => None,
+		}
+	}
+}
+
+impl Mapper for Fds {
+	/// The BIOS is ROM, and there's no register at $8000-$FFFF.
+	fn cpu_write(&mut self, _addr: u16, _data: u8) -> bool {
+		false
+	}
+
+	/// The BIOS, at $E000. $8000-$DFFF is PRG RAM, see `prg_ram_end`.
+	fn prg_banks(&self, _prg_rom_size: usize) -> [usize; 4] {
+		[0; 4]
+	}
+
+	fn prg_ram_end(&self) -> u16 {
+		0xDFFF
+	}
+
+	fn expansion_write(&mut self, addr: u16, data: u8) -> bool {
+		match addr {
+			0x4020 => self.timer_reload = self.timer_reload & 0xFF00 | data as u16,
+			0x4021 => self.timer_reload = self.timer_reload & 0x00FF | (data as u16) << 8,
+			0x4022 => {
+				self.timer_repeat = data & 0x01 != 0;
+				self.timer_enabled = data & 0x02 != 0 && self.disk_registers;
+				self.timer_irq = false;
+				if self.timer_enabled {
+					self.timer = self.timer_reload;
+				}
+			}
+			0x4023 => {
+				self.disk_registers = data & 0x01 != 0;
+				self.sound_registers = data & 0x02 != 0;
+				if !self.disk_registers {
+					self.timer_enabled = false;
+					self.timer_irq = false;
+					self.disk_irq = false;
+				}
+			}
+			0x4024 if self.disk_registers => {
+				self.write_data = data;
+				self.transferred = false;
+				self.disk_irq = false;
+			}
+			0x4025 if self.disk_registers => {
+				self.control = data;
+				self.disk_irq = false;
+			}
+			// The expansion port, with nothing on it.
+			0x4026 => {}
+			0x4040..=0x408A if self.sound_registers => return self.audio.write(addr, data),
+			_ => return false,
+		}
+		true
+	}
+
+	fn expansion_peek(&self, addr: u16) -> Option<u8> {
+		self.register(addr)
+	}
+
+	/// $4030 acknowledges both IRQs, $4031 the one of the disk.
+	fn expansion_read(&mut self, addr: u16) -> Option<u8> {
+		let value = self.register(addr)?;
+		match addr {
+			0x4030 => {
+				self.transferred = false;
+				self.timer_irq = false;
+				self.disk_irq = false;
+			}
+			0x4031 => {
+				self.transferred = false;
+				self.disk_irq = false;
+			}
+			_ => {}
+		}
+		Some(value)
+	}
+
+	fn mirroring(&self) -> Option<Mirroring> {
+		Some(if self.control & HORIZONTAL != 0 { Mirroring::Horizontal } else { Mirroring::Vertical })
+	}
+
+	/// The BIOS isn't in banks.
+	fn prg_slots(&self) -> &'static [usize] {
+		&[]
+	}
+
+	fn irq_pending(&self) -> bool {
+		self.timer_irq || self.disk_irq
+	}
+
+	fn clock_cpu(&mut self) {
+		if self.timer_enabled {
+			if self.timer == 0 {
+				self.timer_irq = true;
+				self.timer = self.timer_reload;
+				self.timer_enabled = self.timer_repeat;
+			} else {
+				self.timer -= 1;
+			}
+		}
+		self.audio.clock();
+		self.insert_delay = self.insert_delay.saturating_sub(1);
+		self.clock_drive();
+	}
+
+	fn audio_level(&self) -> f32 {
+		self.audio.level()
+	}
+}
+
+/// The disk too, with what the game wrote on it.
+impl SaveState for Fds {
+	fn save_state(&self, out: &mut StateWriter) {
+		out.bytes(&self.disk);
+		out.bool(self.side.is_some());
+		out.u32(self.side.unwrap_or(0) as u32);
+		out.u32(self.insert_delay);
+		out.u16(self.timer_reload);
+		out.u16(self.timer);
+		out.bool(self.timer_enabled);
+		out.bool(self.timer_repeat);
+		out.bool(self.timer_irq);
+		out.bool(self.disk_registers);
+		out.bool(self.sound_registers);
+		out.u8(self.control);
+		out.u8(self.write_data);
+		out.u8(self.read_data);
+		out.bool(self.transferred);
+		out.bool(self.disk_irq);
+		out.u32(self.position as u32);
+		out.u32(self.delay);
+		out.bool(self.scanning);
+		out.bool(self.end_of_head);
+		out.bool(self.gap_ended);
+		out.u16(self.crc);
+		out.bool(self.previous_crc);
+		self.audio.save_state(out);
+	}
+
+	fn load_state(&mut self, input: &mut StateReader) -> Result<(), String> {
+		input.bytes(&mut self.disk)?;
+		let inserted = input.bool()?;
+		let side = input.u32()? as usize;
+		if side >= self.sides() {
+			return Err(format!("Side {} of the disk in the state, which has {} sides", side + 1, self.sides()));
+		}
+		self.side = inserted.then_some(side);
+		self.insert_delay = input.u32()?;
+		self.timer_reload = input.u16()?;
+		self.timer = input.u16()?;
+		self.timer_enabled = input.bool()?;
+		self.timer_repeat = input.bool()?;
+		self.timer_irq = input.bool()?;
+		self.disk_registers = input.bool()?;
+		self.sound_registers = input.bool()?;
+		self.control = input.u8()?;
+		self.write_data = input.u8()?;
+		self.read_data = input.u8()?;
+		self.transferred = input.bool()?;
+		self.disk_irq = input.bool()?;
+		let position = input.u32()? as usize;
+		if position >= self.side_size {
+			return Err(format!("Disk position {} in the state, past the side of {} bytes", position, self.side_size));
+		}
+		self.position = position;
+		self.delay = input.u32()?;
+		self.scanning = input.bool()?;
+		self.end_of_head = input.bool()?;
+		self.gap_ended = input.bool()?;
+		self.crc = input.u16()?;
+		self.previous_crc = input.bool()?;
+		self.audio.load_state(input)
+	}
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+	use crate::fds::disk::test_disk;
+
+	fn fds() -> Fds {
+		let side = test_disk::side(&[0x11, 0x22, 0x33], 0x6000);
+		Fds::new(&[&side, &side])
+	}
+
+	#[test]
+	fn timer_test() {
+		let mut fds = fds();
+		fds.expansion_write(0x4020, 3);
+		fds.expansion_write(0x4021, 0);
+		fds.expansion_write(0x4022, 0b11);
+		// 3, 2, 1, 0: the IRQ, and the counter is reloaded.
+		for _ in 0..3 {
+			fds.clock_cpu();
+		}
+		assert!(!fds.irq_pending());
+		fds.clock_cpu();
+		assert!(fds.irq_pending());
+		assert_eq!(fds.expansion_read(0x4030), Some(0x41));
+		assert!(!fds.irq_pending());
+		// It repeats.
+		for _ in 0..4 {
+			fds.clock_cpu();
+		}
+		assert!(fds.irq_pending());
+
+		// Once, without the repeat.
+		fds.expansion_write(0x4022, 0b10);
+		assert!(!fds.irq_pending());
+		for _ in 0..4 {
+			fds.clock_cpu();
+		}
+		assert!(fds.expansion_read(0x4030).unwrap() & 1 != 0);
+		for _ in 0..8 {
+			fds.clock_cpu();
+		}
+		assert!(!fds.irq_pending());
+
+		// The disk registers off stop it.
+		fds.expansion_write(0x4022, 0b11);
+		fds.expansion_write(0x4023, 0);
+		for _ in 0..8 {
+			fds.clock_cpu();
+		}
+		assert!(!fds.irq_pending());
+		assert_eq!(fds.expansion_read(0x4030), None);
+	}
+
+	#[test]
+	fn read_test() {
+		let mut fds = fds();
+		assert_eq!(fds.expansion_peek(0x4032), Some(0x42));
+		// Motor on, read mode, the IRQ on every byte.
+		fds.expansion_write(0x4025, MOTOR_ON | READ_MODE | TRANSFER_START | IRQ_ENABLED);
+		assert_eq!(fds.mirroring(), Some(Mirroring::Vertical));
+		let mut bytes = vec![];
+		while bytes.len() < 15 {
+			fds.clock_cpu();
+			if fds.irq_pending() {
+				bytes.push(fds.expansion_read(0x4031).unwrap());
+			}
+		}
+		// The $80 that ends the gap is skipped.
+		assert_eq!(&bytes[..], b"\x01*NINTENDO-HVC*");
+		assert_eq!(fds.expansion_peek(0x4032), Some(0x40));
+		assert_eq!(fds.expansion_peek(0x4030).unwrap() & 0x40, 0);
+	}
+
+	#[test]
+	fn insert_test() {
+		let mut fds = fds();
+		assert_eq!((fds.sides(), fds.side()), (2, Some(0)));
+		assert!(fds.insert(Some(2)).is_err());
+		fds.insert(Some(1)).unwrap();
+		// Ejected for 2 seconds.
+		assert_eq!(fds.side(), Some(1));
+		assert_eq!(fds.expansion_peek(0x4032), Some(0x47));
+		for _ in 0..INSERT_CYCLES {
+			fds.clock_cpu();
+		}
+		assert_eq!(fds.expansion_peek(0x4032), Some(0x42));
+		fds.insert(None).unwrap();
+		assert_eq!(fds.side(), None);
+
+		// With what's on the disk.
+		fds.disk[0] = 0x42;
+		let mut out = StateWriter::default();
+		fds.save_state(&mut out);
+		let state = out.into_bytes();
+		let mut loaded = self::fds();
+		loaded.load_state(&mut StateReader::new(&state)).unwrap();
+		assert_eq!((loaded.side(), loaded.disk[0]), (None, 0x42));
+	}
+}
@@ -0,0 +1,4 @@
+mod audio;
+
+pub mod disk;
+pub mod fds;
@@ -0,0 +1,247 @@
+// C API (feature `ffi`), for frontends in other languages. The header is include/nes.h, generated by the build
+// (build.rs), and examples/c/render_frame.c shows how to use it:
+//
+// NesHandle *nes = nes_create(rom, rom_len);        // NULL if it's not a valid iNES file
+// nes_set_input(nes, 0, NES_BUTTON_A);              // Controller 1
+// nes_set_input(nes, 1, NES_BUTTON_START);          // Controller 2
+// const uint32_t *pixels = nes_run_frame(nes);      // 256x240, 0x00RRGGBB, valid until the next call
+// size_t size = nes_save_state(nes, NULL, 0);       // The size of the state
+// nes_save_state(nes, buffer, size);
+// nes_load_state(nes, buffer, size);
+// nes_destroy(nes);
+//
+// A panic can't unwind into C, so every function catches it, and returns an error instead (NULL, 0 or
+// NES_ERROR_PANIC). The emulator may be half way through an instruction after a panic, so from then on the handle
+// returns errors for everything, except `nes_destroy`.
+
+use std::ffi::c_int;
+use std::panic::{self, AssertUnwindSafe};
+use std::ptr;
+use std::slice;
+
+use log::error;
+
+use crate::cartridge::Cartridge;
+use crate::controller::ButtonState;
+use crate::emulator::Emulator;
+use crate::log_target::EMULATOR;
+use crate::ppu::framebuffer::{HEIGHT, WIDTH};
+
+pub const NES_OK: c_int = 0;
+/// A NULL handle or buffer.
+pub const NES_ERROR_NULL: c_int = -1;
+/// The emulator panicked, in this call or before.
+pub const NES_ERROR_PANIC: c_int = -2;
+/// There is no controller in the port.
+pub const NES_ERROR_INVALID_PORT: c_int = -3;
+/// The state is not valid, or it's of another game.
+pub const NES_ERROR_INVALID_STATE: c_int = -4;
+
+/// Buttons for `nes_set_input`.
+pub const NES_BUTTON_A: u8 = 1 << 0;
+pub const NES_BUTTON_B: u8 = 1 << 1;
+pub const NES_BUTTON_SELECT: u8 = 1 << 2;
+pub const NES_BUTTON_START: u8 = 1 << 3;
+pub const NES_BUTTON_UP: u8 = 1 << 4;
+pub const NES_BUTTON_DOWN: u8 = 1 << 5;
+pub const NES_BUTTON_LEFT: u8 = 1 << 6;
+pub const NES_BUTTON_RIGHT: u8 = 1 << 7;
+
+// Numbers, not WIDTH and HEIGHT, so they're defined in the header.
+pub const NES_SCREEN_WIDTH: usize = 256;
+pub const NES_SCREEN_HEIGHT: usize = 240;
+const _: () = assert!(NES_SCREEN_WIDTH == WIDTH && NES_SCREEN_HEIGHT == HEIGHT);
+
+/// The console, opaque to C.
+pub struct NesHandle {
+	emulator: Emulator,
+	/// The last frame, in 0x00RRGGBB.
+	pixels: Vec<u32>,
+	poisoned: bool,
+}
+
+/// Run `f` on the handle, and turn a panic into `NES_ERROR_PANIC`.
+fn call<T>(handle: *mut NesHandle, f: impl FnOnce(&mut NesHandle) -> Result<T, c_int>) -> Result<T, c_int> {
+	// SAFETY: the caller promises the handle is NULL or from `nes_create`, and not used from another thread.
+	let Some(handle) = (unsafe { handle.as_mut() }) else {
+		return Err(NES_ERROR_NULL);
+	};
+	if handle.poisoned {
+		return Err(NES_ERROR_PANIC);
+	}
+	match panic::catch_unwind(AssertUnwindSafe(|| f(&mut *handle))) {
+		Ok(result) => result,
+		Err(_) => {
+			handle.poisoned = true;
+			Err(NES_ERROR_PANIC)
+		}
+	}
+}
+
+/// Insert the cartridge (the bytes of an iNES file) and power on. Returns NULL if the ROM is not valid.
+///
+/// # Safety
+/// `rom` must point to `rom_len` readable bytes. They are copied, so they can be freed after the call.
+#[no_mangle]
+pub unsafe extern "C" fn nes_create(rom: *const u8, rom_len: usize) -> *mut NesHandle {
+	if rom.is_null() {
+		return ptr::null_mut();
+	}
+	let bytes = slice::from_raw_parts(rom, rom_len);
+	let created = panic::catch_unwind(|| {
+		Cartridge::from_ines(bytes).map(|cartridge| NesHandle {
+			emulator: Emulator::new(cartridge),
+			pixels: vec![0; WIDTH * HEIGHT],
+			poisoned: false,
+		})
+	});
+	match created {
+		Ok(Ok(handle)) => Box::into_raw(Box::new(handle)),
+		Ok(Err(err)) => {
+			error!(target: EMULATOR, "nes_create: {}", err);
+			ptr::null_mut()
+		}
+		Err(_) => ptr::null_mut(),
+	}
+}
+
+/// Free the handle. NULL is ignored.
+///
+/// # Safety
+/// `handle` must be NULL or from `nes_create`, and not used after the call.
+#[no_mangle]
+pub unsafe extern "C" fn nes_destroy(handle: *mut NesHandle) {
+	if !handle.is_null() {
+		let handle = Box::from_raw(handle);
+		// Nothing to report, and nowhere to report it.
+		let _ = panic::catch_unwind(AssertUnwindSafe(|| drop(handle)));
+	}
+}
+
+/// Run a frame, and return its pixels: `NES_SCREEN_WIDTH * NES_SCREEN_HEIGHT` of 0x00RRGGBB, row by row. They are
+/// owned by the handle, and valid until the next call with it. Returns NULL on errors.
+///
+/// # Safety
+/// `handle` must be NULL or from `nes_create`.
+#[no_mangle]
+pub unsafe extern "C" fn nes_run_frame(handle: *mut NesHandle) -> *const u32 {
+	call(handle, |nes| {
+		nes.emulator.run_frame().write_xrgb32(&mut nes.pixels);
+		Ok(nes.pixels.as_ptr())
+	}).unwrap_or(ptr::null())
+}
+
+/// Set the buttons (`NES_BUTTON_*`) of the controller in `port`, 0 for controller 1 and 1 for controller 2. Call it before every frame.
+///
+/// # Safety
+/// `handle` must be NULL or from `nes_create`.
+#[no_mangle]
+pub unsafe extern "C" fn nes_set_input(handle: *mut NesHandle, port: u32, buttons: u8) -> c_int {
+	call(handle, |nes| {
+		match port {
+			0 => nes.emulator.set_controller1(ButtonState(buttons).without_opposing_directions()),
+			1 => nes.emulator.set_controller2(ButtonState(buttons).without_opposing_directions()),
+			_ => return Err(NES_ERROR_INVALID_PORT),
+		}
+		Ok(NES_OK)
+	}).unwrap_or_else(|err| err)
+}
+
+/// Save the state to `buffer`, and return its size. The state is written only if it fits in `len` bytes, so call it
+/// with NULL first for the size. Returns 0 on errors.
+///
+/// # Safety
+/// `handle` must be NULL or from `nes_create`. `buffer` must be NULL, or point to `len` writable bytes.
+#[no_mangle]
+pub unsafe extern "C" fn nes_save_state(handle: *mut NesHandle, buffer: *mut u8, len: usize) -> usize {
+	call(handle, |nes| {
+		let state = nes.emulator.save_state();
+		if !buffer.is_null() && len >= state.len() {
+			ptr::copy_nonoverlapping(state.as_ptr(), buffer, state.len());
+		}
+		Ok(state.len())
+	}).unwrap_or(0)
+}
+
+/// Load a state from `nes_save_state`. An invalid state leaves the emulator as it was.
+///
+/// # Safety
+/// `handle` must be NULL or from `nes_create`. `buffer` must point to `len` readable bytes.
+#[no_mangle]
+pub unsafe extern "C" fn nes_load_state(handle: *mut NesHandle, buffer: *const u8, len: usize) -> c_int {
+	if buffer.is_null() {
+		return NES_ERROR_NULL;
+	}
+	let state = slice::from_raw_parts(buffer, len);
+	call(handle, |nes| {
+		nes.emulator.load_state(state).map_err(|err| {
+			error!(target: EMULATOR, "nes_load_state: {}", err);
+			NES_ERROR_INVALID_STATE
+		})?;
+		Ok(NES_OK)
+	}).unwrap_or_else(|err| err)
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+	use crate::cartridge::test_rom;
+
+	fn create(program: &str) -> *mut NesHandle {
+		let rom = test_rom::nrom(program);
+		unsafe { nes_create(rom.as_ptr(), rom.len()) }
+	}
+
+	#[test]
+	fn frame_and_state_test() {
+		// INC $10, JMP $8000
+		let nes = create("E6 10 4C 00 80");
+		assert!(!nes.is_null());
+		unsafe {
+			assert_eq!(nes_set_input(nes, 0, NES_BUTTON_A | NES_BUTTON_START), NES_OK);
+			assert_eq!(nes_set_input(nes, 1, NES_BUTTON_A), NES_OK);
+			assert_eq!(nes_set_input(nes, 2, NES_BUTTON_A), NES_ERROR_INVALID_PORT);
+
+			let pixels = nes_run_frame(nes);
+			assert!(!pixels.is_null());
+			let pixels = slice::from_raw_parts(pixels, NES_SCREEN_WIDTH * NES_SCREEN_HEIGHT);
+			assert!(pixels.iter().all(|pixel| pixel >> 24 == 0));
+
+			let size = nes_save_state(nes, ptr::null_mut(), 0);
+			assert!(size > 0);
+			let mut state = vec![0; size];
+			assert_eq!(nes_save_state(nes, state.as_mut_ptr(), state.len()), size);
+			let counter = (*nes).emulator.peek(0x10);
+
+			nes_run_frame(nes);
+			assert_ne!((*nes).emulator.peek(0x10), counter);
+			assert_eq!(nes_load_state(nes, state.as_ptr(), state.len()), NES_OK);
+			assert_eq!((*nes).emulator.peek(0x10), counter);
+			assert_eq!(nes_load_state(nes, state.as_ptr(), 10), NES_ERROR_INVALID_STATE);
+
+			nes_destroy(nes);
+		}
+	}
+
+	#[test]
+	fn errors_test() {
+		unsafe {
+			assert!(nes_create(b"not a rom".as_ptr(), 9).is_null());
+			assert!(nes_create(ptr::null(), 0).is_null());
+			assert!(nes_run_frame(ptr::null_mut()).is_null());
+			assert_eq!(nes_set_input(ptr::null_mut(), 0, 0), NES_ERROR_NULL);
+			assert_eq!(nes_save_state(ptr::null_mut(), ptr::null_mut(), 0), 0);
+			nes_destroy(ptr::null_mut());
+		}
+
+		// $02 is an illegal opcode, and the CPU panics.
+		let nes = create("02");
+		unsafe {
+			assert!(nes_run_frame(nes).is_null());
+			// The handle is poisoned.
+			assert_eq!(nes_set_input(nes, 0, 0), NES_ERROR_PANIC);
+			assert_eq!(nes_save_state(nes, ptr::null_mut(), 0), 0);
+			nes_destroy(nes);
+		}
+	}
+}
@@ -0,0 +1,205 @@
+// Frame comparison with a reference image, for visual regression hunting (`--compare`): which pixels of the frame are
+// not the color they are in the reference, and a heatmap of them to show instead of the frame.
+//
+// The comparison is of the 256x240 picture of the console, in RGB. A reference of 256x224 is one without the overscan
+// lines (`--crop-overscan` screenshots): it's lined up with the visible lines, and the overscan lines aren't compared.
+// `crop_overscan` leaves them out of a 256x240 reference too, for games with garbage there. Coordinates are always the
+// ones of the whole picture, so the first visible line is y = 8 either way.
+
+use std::fmt;
+use std::ops::Range;
+use std::path::Path;
+
+use crate::png;
+use crate::ppu::framebuffer::{Framebuffer, HEIGHT, OVERSCAN_LINES, WIDTH};
+
+/// An image to compare frames with.
+pub struct Reference {
+	/// The lines of the picture it has.
+	lines: Range<usize>,
+	/// RGB, row by row, of `lines` only.
+	rgb: Vec<u8>,
+}
+
+impl Reference {
+	/// `rgb` has 3 bytes per pixel, row by row, like `png::decode_rgb` returns it.
+	pub fn from_rgb(width: usize, height: usize, rgb: Vec<u8>) -> Result<Self, String> {
+		let lines = match (width, height) {
+			(WIDTH, HEIGHT) => 0..HEIGHT,
+			(WIDTH, visible) if visible == HEIGHT - 2 * OVERSCAN_LINES => OVERSCAN_LINES..HEIGHT - OVERSCAN_LINES,
+			_ => return Err(format!("The reference is {}x{}, not 256x240 (or 256x224 without the overscan lines)", width, height)),
+		};
+		assert_eq!(rgb.len(), width * height * 3, "{}x{} pixels need {} bytes of RGB", width, height, width * height * 3);
+		Ok(Reference { lines, rgb })
+	}
+
+	pub fn from_png(png: &[u8]) -> Result<Self, String> {
+		let (width, height, rgb) = png::decode_rgb(png)?;
+		Self::from_rgb(width, height, rgb)
+	}
+
+	pub fn load(path: &Path) -> Result<Self, String> {
+		let bytes = std::fs::read(path).map_err(|err| format!("Can't read {}: {}", path.display(), err))?;
+		Self::from_png(&bytes).map_err(|err| format!("{}: {}", path.display(), err))
+	}
+
+	/// The lines of the picture in the reference: all of them, or the visible ones.
+	pub fn lines(&self) -> Range<usize> {
+		self.lines.clone()
+	}
+
+	fn pixel(&self, x: usize, y: usize) -> [u8; 3] {
+		let i = ((y - self.lines.start) * WIDTH + x) * 3;
+		[self.rgb[i], self.rgb[i + 1], self.rgb[i + 2]]
+	}
+}
+
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub struct PixelDiff {
+	pub x: usize,
+	pub y: usize,
+	/// The color in the reference.
+	pub expected: [u8; 3],
+	/// The color in the frame.
+	pub actual: [u8; 3],
+}
+
+impl PixelDiff {
+	/// How far apart the colors are: the most any channel differs, 1-255.
+	pub fn distance(&self) -> u8 {
+		(0..3).map(|i| self.expected[i].abs_diff(self.actual[i])).max().unwrap()
+	}
+}
+
+/// The pixels that differ, row by row. Displayed, it's a line for each, like `(12, 100): #FFFFFF, expected #000000`.
+#[derive(Clone, PartialEq, Eq, Debug, Default)]
+pub struct FrameDiff {
+	pub pixels: Vec<PixelDiff>,
+}
+
+impl FrameDiff {
+	/// Compare `framebuffer` with `reference`, on the lines they both have (and not the overscan lines, with
+	/// `crop_overscan`).
+	pub fn new(framebuffer: &Framebuffer, reference: &Reference, crop_overscan: bool) -> Self {
+		let mut rgb = vec![0; WIDTH * HEIGHT * 3];
+		framebuffer.write_rgb24(&mut rgb);
+		let mut lines = reference.lines();
+		if crop_overscan {
+			lines = lines.start.max(OVERSCAN_LINES)..lines.end.min(HEIGHT - OVERSCAN_LINES);
+		}
+
+		let mut pixels = vec![];
+		for y in lines {
+			for x in 0..WIDTH {
+				let i = (y * WIDTH + x) * 3;
+				let actual = [rgb[i], rgb[i + 1], rgb[i + 2]];
+				let expected = reference.pixel(x, y);
+				if actual != expected {
+					pixels.push(PixelDiff { x, y, expected, actual });
+				}
+			}
+		}
+		FrameDiff { pixels }
+	}
+
+	pub fn count(&self) -> usize {
+		self.pixels.len()
+	}
+
+	/// Turn `rgb`, the frame the diff is of (as `Framebuffer::write_rgb24` writes it), into a heatmap: the pixels that
+	/// match are dimmed to gray, so the picture is still there to find your way, and the ones that differ are red for
+	/// a small difference, up to yellow for a big one.
+	pub fn write_heatmap(&self, rgb: &mut [u8]) {
+		for pixel in rgb.chunks_exact_mut(3) {
+			let gray = ((pixel[0] as u16 * 3 + pixel[1] as u16 * 6 + pixel[2] as u16) / 10 / 4) as u8;
+			pixel.copy_from_slice(&[gray; 3]);
+		}
+		for diff in &self.pixels {
+			let i = (diff.y * WIDTH + diff.x) * 3;
+			rgb[i..i + 3].copy_from_slice(&[0xFF, diff.distance(), 0]);
+		}
+	}
+}
+
+impl fmt::Display for FrameDiff {
+	fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+		for pixel in &self.pixels {
+			let [r, g, b] = pixel.actual;
+			let [er, eg, eb] = pixel.expected;
+			writeln!(f, "({}, {}): #{:02X}{:02X}{:02X}, expected #{:02X}{:02X}{:02X}", pixel.x, pixel.y, r, g, b, er, eg, eb)?;
+		}
+		Ok(())
+	}
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+
+	fn pattern() -> Framebuffer {
+		let mut framebuffer = Framebuffer::new();
+		for y in 0..HEIGHT {
+			for x in 0..WIDTH {
+				framebuffer.set(x, y, ((x / 8 + y / 8) % 64) as u8);
+			}
+		}
+		framebuffer
+	}
+
+	#[test]
+	fn compare_test() {
+		let framebuffer = pattern();
+		let reference = Reference::from_png(&framebuffer.to_png(false)).unwrap();
+		assert_eq!(reference.lines(), 0..HEIGHT);
+		let diff = FrameDiff::new(&framebuffer, &reference, false);
+		assert_eq!(diff.count(), 0);
+		assert_eq!(diff.to_string(), "");
+
+		// 10 pixels of another color, the first 2 and the last 2 in the overscan lines.
+		let mut rgb = vec![0; WIDTH * HEIGHT * 3];
+		framebuffer.write_rgb24(&mut rgb);
+		let changed: Vec<(usize, usize)> = vec![(0, 0), (255, 3), (7, 8), (100, 100), (101, 100), (0, 120), (200, 150), (17, 200), (30, 232), (255, 239)];
+		for &(x, y) in &changed {
+			let i = (y * WIDTH + x) * 3;
+			rgb[i] ^= 0x80;
+		}
+		let reference = Reference::from_rgb(WIDTH, HEIGHT, rgb.clone()).unwrap();
+		let diff = FrameDiff::new(&framebuffer, &reference, false);
+		assert_eq!(diff.count(), 10);
+		assert_eq!(diff.pixels.iter().map(|pixel| (pixel.x, pixel.y)).collect::<Vec<_>>(), changed);
+		assert!(diff.pixels.iter().all(|pixel| pixel.distance() == 0x80));
+		let listing: Vec<String> = diff.to_string().lines().map(str::to_string).collect();
+		assert_eq!(listing.len(), 10);
+		let (x, y) = changed[3];
+		let i = (y * WIDTH + x) * 3;
+		assert_eq!(listing[3], format!("(100, 100): #{:02X}{:02X}{:02X}, expected #{:02X}{:02X}{:02X}", rgb[i] ^ 0x80, rgb[i + 1], rgb[i + 2], rgb[i], rgb[i + 1], rgb[i + 2]));
+
+		// The overscan lines left out, of the same reference, and of one without them.
+		assert_eq!(FrameDiff::new(&framebuffer, &reference, true).count(), 6);
+		let visible = rgb[OVERSCAN_LINES * WIDTH * 3..(HEIGHT - OVERSCAN_LINES) * WIDTH * 3].to_vec();
+		let cropped = Reference::from_rgb(WIDTH, HEIGHT - 2 * OVERSCAN_LINES, visible).unwrap();
+		assert_eq!(cropped.lines(), OVERSCAN_LINES..HEIGHT - OVERSCAN_LINES);
+		let diff = FrameDiff::new(&framebuffer, &cropped, false);
+		assert_eq!(diff.pixels.iter().map(|pixel| (pixel.x, pixel.y)).collect::<Vec<_>>(), changed[2..8]);
+		assert_eq!(FrameDiff::new(&framebuffer, &Reference::from_png(&framebuffer.to_png(true)).unwrap(), false).count(), 0);
+
+		assert!(Reference::from_rgb(320, 240, vec![0; 320 * 240 * 3]).is_err());
+	}
+
+	#[test]
+	fn heatmap_test() {
+		let mut framebuffer = Framebuffer::new();
+		framebuffer.set(5, 5, 0x30);
+		let reference = Reference::from_png(&Framebuffer::new().to_png(false)).unwrap();
+		let diff = FrameDiff::new(&framebuffer, &reference, false);
+		assert_eq!(diff.count(), 1);
+
+		let mut rgb = vec![0; WIDTH * HEIGHT * 3];
+		framebuffer.write_rgb24(&mut rgb);
+		diff.write_heatmap(&mut rgb);
+		let i = (5 * WIDTH + 5) * 3;
+		assert_eq!(rgb[i..i + 3], [0xFF, diff.pixels[0].distance(), 0]);
+		// The rest is gray, and dim.
+		assert!(rgb[..i].chunks_exact(3).all(|pixel| pixel[0] == pixel[1] && pixel[1] == pixel[2] && pixel[0] < 0x40));
+	}
+}
@@ -0,0 +1,272 @@
+// Keeps the emulator running at the speed of the real console.
+//
+// The frontend runs a frame, and then waits for the pacer. Deadlines are absolute: frame N is due at
+// start + N * frame duration, so a sleep that took too long makes the next wait shorter, and the errors of
+// coarse sleeps never add up. The long term speed is exact.
+
+use std::thread;
+use std::time::{Duration, Instant};
+
+/// When the emulator is this many frames late (slow host, the window was dragged...), forget about the missed
+/// deadlines, instead of running as fast as possible until it catches up.
+const MAX_LAG_FRAMES: f64 = 4.0;
+
+/// How much audio feedback can change the speed. 0.5% is not audible as pitch, and it's enough to fix the
+/// difference between the sound card clock and the system clock.
+const MAX_AUDIO_ADJUSTMENT: f64 = 0.005;
+
+/// Time source of the pacer, so tests can run it against a mocked clock.
+pub trait Clock {
+	/// Time since some fixed point, like the creation of the clock.
+	fn now(&self) -> Duration;
+	/// Wait until `now() >= deadline`. It may wait longer.
+	fn sleep_until(&mut self, deadline: Duration);
+}
+
+pub struct SystemClock {
+	start: Instant,
+}
+
+/// `thread::sleep` can oversleep by a millisecond or more on some systems, so it sleeps until this much
+/// before the deadline, and spins the rest.
+const SPIN_DURATION: Duration = Duration::from_millis(1);
+
+impl Default for SystemClock {
+	fn default() -> Self {
+		SystemClock { start: Instant::now() }
+	}
+}
+
+impl Clock for SystemClock {
+	fn now(&self) -> Duration {
+		self.start.elapsed()
+	}
+
+	fn sleep_until(&mut self, deadline: Duration) {
+		let now = self.now();
+		if deadline > now + SPIN_DURATION {
+			thread::sleep(deadline - now - SPIN_DURATION);
+		}
+		while self.now() < deadline {
+			std::hint::spin_loop();
+		}
+	}
+}
+
+pub struct FramePacer<C: Clock = SystemClock> {
+	clock: C,
+	frame_nanos: f64,
+	speed: f64,
+	turbo: bool,
+	/// Speed factor from the audio buffer fill level, around 1.0.
+	audio_adjustment: f64,
+	/// Deadline of the next frame, in nanoseconds of the clock. None until the first frame.
+	next_deadline: Option<f64>,
+}
+
+impl FramePacer<SystemClock> {
+	pub fn new(frame_nanos: f64) -> Self {
+		Self::with_clock(SystemClock::default(), frame_nanos)
+	}
+}
+
+impl<C: Clock> FramePacer<C> {
+	pub fn with_clock(clock: C, frame_nanos: f64) -> Self {
+		FramePacer {
+			clock,
+			frame_nanos,
+			speed: 1.0,
+			turbo: false,
+			audio_adjustment: 1.0,
+			next_deadline: None,
+		}
+	}
+
+	/// 2.0 runs twice as fast as the console, 0.5 at half the speed.
+	pub fn set_speed(&mut self, speed: f64) {
+		assert!(speed > 0.0, "Speed must be positive, got {}", speed);
+		self.speed = speed;
+		self.next_deadline = None;
+	}
+
+	pub fn speed(&self) -> f64 {
+		self.speed
+	}
+
+	/// In turbo mode the pacer doesn't wait at all.
+	pub fn set_turbo(&mut self, turbo: bool) {
+		self.turbo = turbo;
+		self.next_deadline = None;
+	}
+
+	pub fn turbo(&self) -> bool {
+		self.turbo
+	}
+
+	/// Optional feedback from the audio output: `buffered` samples are waiting to be played, and the output
+	/// wants about `target` of them. A buffer that fills up means the emulator is faster than the sound card,
+	/// so it slows down a bit, and the other way around. This keeps the buffer from overrunning (dropped samples)
+	/// or underrunning (silence), both of which crackle.
+	pub fn set_audio_fill(&mut self, buffered: usize, target: usize) {
+		if target == 0 {
+			self.audio_adjustment = 1.0;
+			return;
+		}
+		let error = (buffered as f64 - target as f64) / target as f64;
+		self.audio_adjustment = 1.0 - error.clamp(-1.0, 1.0) * MAX_AUDIO_ADJUSTMENT;
+	}
+
+	/// Duration of a frame right now, with the speed and the audio feedback.
+	fn frame_duration(&self) -> f64 {
+		self.frame_nanos / (self.speed * self.audio_adjustment)
+	}
+
+	/// Forget the deadlines, the next frame starts the count again. After a pause, so the frames after it don't run
+	/// as fast as possible to catch up.
+	pub fn restart(&mut self) {
+		self.next_deadline = None;
+	}
+
+	/// Call after every frame. Waits until the next frame is due.
+	pub fn wait_for_next_frame(&mut self) {
+		if self.turbo {
+			return;
+		}
+
+		let now = self.clock.now().as_nanos() as f64;
+		let frame_duration = self.frame_duration();
+		let deadline = match self.next_deadline {
+			Some(deadline) if now - deadline < MAX_LAG_FRAMES * frame_duration => deadline,
+			// First frame, or too late to catch up: start counting from now.
+			_ => now,
+		};
+
+		if deadline > now {
+			self.clock.sleep_until(Duration::from_nanos(deadline as u64));
+		}
+		self.next_deadline = Some(deadline + frame_duration);
+	}
+
+	pub fn clock(&self) -> &C {
+		&self.clock
+	}
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+	use crate::region::Region;
+
+	/// Sleeps take longer than asked, up to 2ms, like a coarse OS timer.
+	struct MockClock {
+		now: Duration,
+		sleeps: u64,
+	}
+
+	impl MockClock {
+		fn new() -> Self {
+			MockClock { now: Duration::ZERO, sleeps: 0 }
+		}
+
+		/// Emulating a frame takes some time too.
+		fn work(&mut self, duration: Duration) {
+			self.now += duration;
+		}
+	}
+
+	impl Clock for MockClock {
+		fn now(&self) -> Duration {
+			self.now
+		}
+
+		fn sleep_until(&mut self, deadline: Duration) {
+			self.sleeps += 1;
+			let oversleep = Duration::from_micros(self.sleeps * 7919 % 2000);
+			self.now = self.now.max(deadline) + oversleep;
+		}
+	}
+
+	#[test]
+	fn no_drift_test() {
+		let mut pacer = FramePacer::with_clock(MockClock::new(), Region::Ntsc.frame_nanos());
+		// The first frame doesn't wait, it starts the count.
+		pacer.wait_for_next_frame();
+		let start = pacer.clock().now().as_nanos() as f64;
+
+		for frame in 1..=6000 {
+			pacer.clock.work(Duration::from_millis(3));
+			pacer.wait_for_next_frame();
+
+			// Frame N starts at N * frame duration (plus the oversleep), no matter how many sleeps were too long.
+			let expected = start + frame as f64 * Region::Ntsc.frame_nanos();
+			let error = pacer.clock().now().as_nanos() as f64 - expected;
+			assert!((-1.0..2_000_000.0).contains(&error), "frame {}: off by {}ns", frame, error);
+		}
+
+		// 6000 frames is 99.8 seconds.
+		let seconds = pacer.clock().now().as_secs_f64();
+		assert!((seconds - 6000.0 / 60.0988).abs() < 0.01, "{}s", seconds);
+	}
+
+	#[test]
+	fn speed_and_turbo_test() {
+		let mut pacer = FramePacer::with_clock(MockClock::new(), Region::Ntsc.frame_nanos());
+		pacer.set_speed(2.0);
+		for _ in 0..601 {
+			pacer.wait_for_next_frame();
+		}
+		let seconds = pacer.clock().now().as_secs_f64();
+		assert!((seconds - 600.0 / 60.0988 / 2.0).abs() < 0.01, "{}s", seconds);
+
+		pacer.set_turbo(true);
+		let before = pacer.clock().now();
+		for _ in 0..100 {
+			pacer.wait_for_next_frame();
+		}
+		assert_eq!(pacer.clock().now(), before);
+	}
+
+	#[test]
+	fn lag_test() {
+		let mut pacer = FramePacer::with_clock(MockClock::new(), Region::Ntsc.frame_nanos());
+		pacer.wait_for_next_frame();
+
+		// A long stall (the window was dragged): the pacer doesn't try to run the missed frames as fast as possible.
+		pacer.clock.work(Duration::from_secs(1));
+		pacer.wait_for_next_frame();
+		let after_stall = pacer.clock().now();
+		pacer.wait_for_next_frame();
+		let waited = (pacer.clock().now() - after_stall).as_nanos() as f64;
+		assert!(waited >= Region::Ntsc.frame_nanos(), "waited {}ns", waited);
+	}
+
+	#[test]
+	fn restart_test() {
+		let mut pacer = FramePacer::with_clock(MockClock::new(), Region::Ntsc.frame_nanos());
+		pacer.wait_for_next_frame();
+
+		// Paused for 3 frames, less than the lag that is forgotten anyway.
+		pacer.clock.work(Duration::from_millis(50));
+		pacer.restart();
+		pacer.wait_for_next_frame();
+		let resumed = pacer.clock().now();
+		pacer.wait_for_next_frame();
+		let waited = (pacer.clock().now() - resumed).as_nanos() as f64;
+		assert!(waited >= Region::Ntsc.frame_nanos(), "waited {}ns", waited);
+	}
+
+	#[test]
+	fn audio_feedback_test() {
+		let mut pacer = FramePacer::with_clock(MockClock::new(), Region::Ntsc.frame_nanos());
+
+		// Too many samples buffered, the emulator runs a bit slower.
+		pacer.set_audio_fill(4096, 2048);
+		assert!((pacer.frame_duration() / Region::Ntsc.frame_nanos() - 1.005).abs() < 0.0001);
+
+		pacer.set_audio_fill(1024, 2048);
+		assert!(pacer.frame_duration() < Region::Ntsc.frame_nanos());
+
+		pacer.set_audio_fill(2048, 2048);
+		assert_eq!(pacer.frame_duration(), Region::Ntsc.frame_nanos());
+	}
+}
@@ -0,0 +1,674 @@
+// Headless test runner, for test ROMs in CI.
+//
+// The harness runs the emulator without a window until one of the exit conditions is met, or until the budget
+// (frames and/or cycles) runs out. Test ROMs usually report their result in memory (blargg's tests write the
+// status to $6000, for example), or by jumping to a known address, and then loop forever.
+//
+// Blargg's test ROMs (https://github.com/christopherpow/nes-test-roms) all report the same way, in PRG RAM:
+//
+// | Address | Description |
+// |---|---|
+// | $6000 | Status: $80 running, $81 needs the reset button pressed, below $80 done ($00 passed, else the error code) |
+// | $6001 - $6003 | $DE $B0 $61, so it's not some other ROM's data |
+// | $6004 | The message, zero terminated: what the console would show |
+//
+// `run_blargg` runs them until they are done, and presses reset when they ask for it.
+
+use std::fmt;
+
+use crate::bus::Bus;
+use crate::controller::ButtonState;
+use crate::cpu::cpu::{CpuState, StackFault};
+use crate::cpu::status::Flag;
+use crate::cpu::stuck::{StuckDetection, StuckDetector};
+use crate::emulator::Emulator;
+use crate::nes_bus::{RomWriteViolation, UninitializedRead};
+
+const BLARGG_STATUS: u16 = 0x6000;
+const BLARGG_SIGNATURE: [u8; 3] = [0xDE, 0xB0, 0x61];
+const BLARGG_MESSAGE: u16 = 0x6004;
+const BLARGG_RUNNING: u8 = 0x80;
+const BLARGG_NEEDS_RESET: u8 = 0x81;
+/// The ROMs want the button held for at least 100ms, so they wait for it. 6 frames are 100ms.
+const BLARGG_RESET_DELAY_FRAMES: u64 = 6;
+
+/// What a met condition means.
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub enum Verdict {
+	Pass,
+	Fail,
+}
+
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub enum Condition {
+	/// PC is at `addr`, before executing the instruction there.
+	PcEquals(u16),
+	/// The byte at `addr` equals `value`. Read with `Emulator::peek`, so registers with side effects are never read.
+	MemoryEquals { addr: u16, value: u8 },
+}
+
+impl Condition {
+	fn met(&self, emulator: &Emulator) -> bool {
+		self.met_on(emulator.cpu_state().pc, |addr| emulator.peek(addr))
+	}
+
+	/// For programs without the console (raw binaries on flat memory): the PC, and memory read with `peek`.
+	pub fn met_on(&self, pc: u16, peek: impl Fn(u16) -> u8) -> bool {
+		match *self {
+			Condition::PcEquals(addr) => pc == addr,
+			Condition::MemoryEquals { addr, value } => peek(addr) == value,
+		}
+	}
+}
+
+impl fmt::Display for Condition {
+	fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+		match self {
+			Condition::PcEquals(addr) => write!(f, "PC = ${:04X}", addr),
+			Condition::MemoryEquals { addr, value } => write!(f, "[${:04X}] = ${:02X}", addr, value),
+		}
+	}
+}
+
+/// Why the harness stopped.
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub enum StopReason {
+	Condition(Condition, Verdict),
+	/// The CPU executed an instruction that didn't change its state, like `JMP *`, so it will never do anything else.
+	/// NOTE: A loop that waits for an interrupt looks the same, but the harness can't know an interrupt will come.
+	/// `Harness::detect_stuck_loops` knows better.
+	Jammed,
+	/// The CPU is in a loop it will never leave, see `Harness::detect_stuck_loops`. `pc` is in the loop.
+	StuckLoop { pc: u16 },
+	/// Neither a condition was met nor the CPU jammed, before the budget ran out.
+	BudgetExhausted,
+	/// The program wrote to ROM, see `Harness::stop_on_rom_write`.
+	RomWrite(RomWriteViolation),
+	/// The program read RAM it never wrote, see `Harness::stop_on_uninitialized_read`.
+	UninitializedRead(UninitializedRead),
+	/// The instruction at `pc` pushed with SP at $00, see `Harness::stop_on_stack_fault`.
+	StackOverflow { pc: u16 },
+	/// The instruction at `pc` pulled with SP at $FF, see `Harness::stop_on_stack_fault`.
+	StackUnderflow { pc: u16 },
+}
+
+impl fmt::Display for StopReason {
+	fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+		match self {
+			StopReason::Condition(condition, verdict) => write!(f, "{:?}: {}", verdict, condition),
+			StopReason::Jammed => write!(f, "CPU jammed"),
+			StopReason::StuckLoop { pc } => write!(f, "CPU stuck in a loop at ${:04X}", pc),
+			StopReason::BudgetExhausted => write!(f, "Budget exhausted"),
+			StopReason::RomWrite(violation) => write!(f, "{}", violation),
+			StopReason::UninitializedRead(read) => write!(f, "{}", read),
+			StopReason::StackOverflow { pc } => write!(f, "Stack overflow: the instruction at ${:04X} pushed with SP at $00", pc),
+			StopReason::StackUnderflow { pc } => write!(f, "Stack underflow: the instruction at ${:04X} pulled with SP at $FF", pc),
+		}
+	}
+}
+
+/// What a blargg test ROM reported, see the top of the file.
+#[derive(Clone, PartialEq, Eq, Debug)]
+pub struct BlarggResult {
+	pub status: u8,
+	pub message: String,
+}
+
+impl BlarggResult {
+	/// Read the result with `peek`. None if the signature is not there: the ROM didn't start yet, or it's not a
+	/// blargg ROM.
+	pub fn poll<B: Bus>(bus: &B) -> Option<BlarggResult> {
+		let status = Self::status(bus)?;
+		// Up to the end of PRG RAM, in case the terminator is missing.
+		let message: Vec<u8> = (BLARGG_MESSAGE..=0x7FFF).map(|addr| bus.peek(addr)).take_while(|&c| c != 0).collect();
+		Some(BlarggResult { status, message: String::from_utf8_lossy(&message).into_owned() })
+	}
+
+	/// Like `poll`, without the message. This is cheap enough for every instruction.
+	fn status<B: Bus>(bus: &B) -> Option<u8> {
+		let signed = (0..3).all(|i| bus.peek(BLARGG_STATUS + 1 + i) == BLARGG_SIGNATURE[i as usize]);
+		signed.then(|| bus.peek(BLARGG_STATUS))
+	}
+
+	pub fn done(&self) -> bool {
+		self.status < BLARGG_RUNNING
+	}
+
+	pub fn passed(&self) -> bool {
+		self.status == 0
+	}
+
+	pub fn needs_reset(&self) -> bool {
+		self.status == BLARGG_NEEDS_RESET
+	}
+}
+
+impl fmt::Display for BlarggResult {
+	fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+		match self.status {
+			0 => write!(f, "Passed")?,
+			BLARGG_RUNNING => write!(f, "Running")?,
+			BLARGG_NEEDS_RESET => write!(f, "Needs reset")?,
+			status => write!(f, "Failed with code {}", status)?,
+		}
+		if !self.message.is_empty() {
+			write!(f, ": {}", self.message.trim_end())?;
+		}
+		Ok(())
+	}
+}
+
+/// How `run_blargg` stopped.
+#[derive(Clone, PartialEq, Eq, Debug)]
+pub enum BlarggStop {
+	/// The ROM is done, and this is its result.
+	Done(BlarggResult),
+	/// The CPU jammed (or got stuck) or the budget ran out before the ROM was done. The last result, if the ROM wrote one.
+	Stopped(StopReason, Option<BlarggResult>),
+}
+
+pub type OnFrame = Box<dyn FnMut(&Emulator)>;
+
+pub struct Harness {
+	emulator: Emulator,
+	conditions: Vec<(Condition, Verdict)>,
+	max_frames: Option<u64>,
+	max_cycles: Option<u64>,
+	/// Frames finished since the harness was created. Counted like `Emulator::run_frame` does: a frame ends when VBlank starts.
+	frames: u64,
+	/// Buttons of both controllers for every frame, and the frame the first one is for.
+	inputs: Vec<[ButtonState; 2]>,
+	first_input_frame: u64,
+	stop_on_rom_write: bool,
+	/// ROM writes `run` already stopped at.
+	rom_writes_seen: usize,
+	stop_on_uninitialized_read: bool,
+	/// Uninitialized reads `run` already stopped at.
+	uninitialized_reads_seen: usize,
+	stop_on_stack_fault: bool,
+	/// None stops at the first instruction that changes nothing (`StopReason::Jammed`).
+	stuck_detector: Option<StuckDetector>,
+	on_frame: Option<OnFrame>,
+}
+
+impl Harness {
+	/// Without a budget, `run` runs until a condition is met or the CPU jams.
+	pub fn new(emulator: Emulator) -> Self {
+		Harness {
+			emulator,
+			conditions: vec![],
+			max_frames: None,
+			max_cycles: None,
+			frames: 0,
+			inputs: vec![],
+			first_input_frame: 0,
+			stop_on_rom_write: false,
+			rom_writes_seen: 0,
+			stop_on_uninitialized_read: false,
+			uninitialized_reads_seen: 0,
+			stop_on_stack_fault: false,
+			stuck_detector: None,
+			on_frame: None,
+		}
+	}
+
+	/// Stop after `frames` frames from now.
+	pub fn max_frames(mut self, frames: u64) -> Self {
+		self.max_frames = Some(frames);
+		self
+	}
+
+	/// Stop after `cycles` CPU cycles from now.
+	pub fn max_cycles(mut self, cycles: u64) -> Self {
+		self.max_cycles = Some(cycles);
+		self
+	}
+
+	/// Stop `run` at the first write to ROM, after the instruction that wrote. Turns on the bus strict mode.
+	pub fn stop_on_rom_write(mut self) -> Self {
+		self.stop_on_rom_write = true;
+		self.emulator.set_strict_rom(true);
+		self
+	}
+
+	/// Stop `run` at the first read of RAM that was never written, after the instruction that read. Turns on the bus
+	/// strict mode for RAM, from now: the RAM is as good as uninitialized.
+	pub fn stop_on_uninitialized_read(mut self) -> Self {
+		self.stop_on_uninitialized_read = true;
+		self.emulator.set_strict_ram(true);
+		self
+	}
+
+	/// Stop `run` after an instruction (or interrupt) that overflows or underflows the stack. Turns on the CPU strict
+	/// stack checks.
+	pub fn stop_on_stack_fault(mut self) -> Self {
+		self.stop_on_stack_fault = true;
+		self.emulator.set_strict_stack(true);
+		self
+	}
+
+	/// Stop when the CPU is stuck in a loop (`StopReason::StuckLoop`), see `stuck.rs`, instead of at the first
+	/// instruction that changes nothing. Loops that wait for the NMI or an IRQ, when they can come, are not stuck.
+	pub fn detect_stuck_loops(mut self, detection: StuckDetection) -> Self {
+		self.stuck_detector = Some(StuckDetector::new(detection));
+		self
+	}
+
+	/// Call `callback` after every frame, like the frame callback of `Emulator::run_frame`. For the audio of headless
+	/// runs (`wav::WavRecorder`).
+	pub fn on_frame<F: FnMut(&Emulator) + 'static>(mut self, callback: F) -> Self {
+		self.on_frame = Some(Box::new(callback));
+		self
+	}
+
+	/// Conditions are checked in the order they were added, before every instruction.
+	pub fn add_condition(&mut self, condition: Condition, verdict: Verdict) {
+		self.conditions.push((condition, verdict));
+	}
+
+	/// Press `inputs[i]` on controllers 1 and 2 in the i-th frame from now, like a movie. After the last one, the buttons stay as they are.
+	pub fn set_inputs(&mut self, inputs: Vec<[ButtonState; 2]>) {
+		self.inputs = inputs;
+		self.first_input_frame = self.frames;
+	}
+
+	pub fn emulator(&self) -> &Emulator {
+		&self.emulator
+	}
+
+	pub fn emulator_mut(&mut self) -> &mut Emulator {
+		&mut self.emulator
+	}
+
+	pub fn into_emulator(self) -> Emulator {
+		self.emulator
+	}
+
+	pub fn run(&mut self) -> StopReason {
+		let budget = self.budget();
+
+		loop {
+			if let Some(&(condition, verdict)) = self.conditions.iter().find(|(condition, _)| condition.met(&self.emulator)) {
+				return StopReason::Condition(condition, verdict);
+			}
+			if self.budget_exhausted(budget) {
+				return StopReason::BudgetExhausted;
+			}
+			let pc = self.emulator.cpu_state().pc;
+			let stuck = self.step();
+			if self.stop_on_stack_fault {
+				match self.emulator.stack_fault() {
+					Some(StackFault::Overflow) => return StopReason::StackOverflow { pc },
+					Some(StackFault::Underflow) => return StopReason::StackUnderflow { pc },
+					None => {}
+				}
+			}
+			if self.stop_on_rom_write {
+				if let Some(&violation) = self.emulator.bus().rom_write_violations().get(self.rom_writes_seen) {
+					self.rom_writes_seen += 1;
+					return StopReason::RomWrite(violation);
+				}
+			}
+			if self.stop_on_uninitialized_read {
+				if let Some(&read) = self.emulator.bus().uninitialized_reads().get(self.uninitialized_reads_seen) {
+					self.uninitialized_reads_seen += 1;
+					return StopReason::UninitializedRead(read);
+				}
+			}
+			if let Some(reason) = stuck {
+				return reason;
+			}
+		}
+	}
+
+	/// Run a blargg test ROM until it's done (see the top of the file), instead of the conditions. When it asks for
+	/// the reset button, it's pressed, and the ROM goes on. The ROM waits for it in a loop, so that's not a jam (or a
+	/// stuck loop).
+	pub fn run_blargg(&mut self) -> BlarggStop {
+		let budget = self.budget();
+		// The frame to press reset at. The status stays "needs reset" until the ROM is running again.
+		let mut reset_frame = None;
+		let mut reset_pressed = false;
+
+		loop {
+			if self.budget_exhausted(budget) {
+				return BlarggStop::Stopped(StopReason::BudgetExhausted, BlarggResult::poll(self.emulator.bus()));
+			}
+			let stuck = self.step();
+
+			match BlarggResult::status(self.emulator.bus()) {
+				Some(status) if status < BLARGG_RUNNING => {
+					let result = BlarggResult::poll(self.emulator.bus()).expect("The signature was just there");
+					return BlarggStop::Done(result);
+				}
+				Some(BLARGG_NEEDS_RESET) => {
+					if !reset_pressed && reset_frame.is_none() {
+						reset_frame = Some(self.frames + BLARGG_RESET_DELAY_FRAMES);
+					}
+				}
+				_ => reset_pressed = false,
+			}
+			if reset_frame.is_some_and(|frame| self.frames >= frame) {
+				self.emulator.reset();
+				reset_frame = None;
+				reset_pressed = true;
+			} else if let Some(reason) = stuck.filter(|_| reset_frame.is_none()) {
+				return BlarggStop::Stopped(reason, BlarggResult::poll(self.emulator.bus()));
+			}
+		}
+	}
+
+	/// The frame and the cycle the budget ends at, from now.
+	fn budget(&self) -> (Option<u64>, Option<u64>) {
+		(self.max_frames.map(|frames| self.frames + frames), self.max_cycles.map(|cycles| self.emulator.cycles() + cycles))
+	}
+
+	fn budget_exhausted(&self, (last_frame, last_cycle): (Option<u64>, Option<u64>)) -> bool {
+		last_frame.is_some_and(|frame| self.frames >= frame) || last_cycle.is_some_and(|cycle| self.emulator.cycles() >= cycle)
+	}
+
+	/// Execute an instruction, with the buttons of the frame. Returns `Jammed` or `StuckLoop` when the CPU won't
+	/// leave where it is.
+	fn step(&mut self) -> Option<StopReason> {
+		// Same as setting the buttons before every `run_frame`.
+		if let Some(&buttons) = self.inputs.get((self.frames - self.first_input_frame) as usize) {
+			self.emulator.set_controllers(buttons);
+		}
+
+		let before = self.emulator.cpu_state();
+		self.emulator.step_instruction();
+		if self.emulator.take_frame_complete() {
+			self.frames += 1;
+			if let Some(callback) = self.on_frame.as_mut() {
+				callback(&self.emulator);
+			}
+		}
+		let after = self.emulator.cpu_state();
+		match &mut self.stuck_detector {
+			Some(detector) => {
+				// The NMI is on, or an IRQ can come.
+				let interruptible = self.emulator.bus().ppu().registers.ppuctrl.generate_nmi() != 0
+					|| !after.flags().get(Flag::INTERRUPT_DISABLE);
+				detector.check(&before, &after, self.emulator.wrote_memory(), interruptible)
+					.map(|pc| StopReason::StuckLoop { pc })
+			}
+			None => after.same_registers(&before).then_some(StopReason::Jammed),
+		}
+	}
+
+	pub fn cpu_state(&self) -> CpuState {
+		self.emulator.cpu_state()
+	}
+
+	/// Hex dump of `start..=end`, see `hex_dump`.
+	pub fn dump_memory(&self, start: u16, end: u16) -> String {
+		hex_dump(start, end, |addr| self.emulator.peek(addr))
+	}
+}
+
+/// Hex dump of `start..=end`, 16 bytes a line, like `$0000: 00 01 ...`. `peek` reads memory without side effects.
+pub fn hex_dump(start: u16, end: u16, peek: impl Fn(u16) -> u8) -> String {
+	let mut dump = String::new();
+	for line_start in (start as u32..=end as u32).step_by(16) {
+		let line_end = (line_start + 15).min(end as u32);
+		let bytes: Vec<String> = (line_start..=line_end).map(|addr| format!("{:02X}", peek(addr as u16))).collect();
+		dump += &format!("${:04X}: {}\n", line_start, bytes.join(" "));
+	}
+	dump
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+	use crate::cartridge::{test_rom, Cartridge};
+	use crate::cpu::registers::Register;
+
+	fn nrom_harness(program: &str) -> Harness {
+		let rom = test_rom::nrom(program);
+		Harness::new(Emulator::new(Cartridge::from_ines(&rom).unwrap()))
+	}
+
+	#[test]
+	fn memory_condition_test() {
+		/*
+		LDA #$01
+		STA $10
+		loop:
+		JMP loop
+		*/
+		let mut harness = nrom_harness("A9 01 85 10 4C 04 80");
+		harness.add_condition(Condition::MemoryEquals { addr: 0x10, value: 0x01 }, Verdict::Pass);
+		harness.add_condition(Condition::PcEquals(0x8004), Verdict::Fail);
+
+		assert_eq!(harness.run(), StopReason::Condition(Condition::MemoryEquals { addr: 0x10, value: 0x01 }, Verdict::Pass));
+		assert_eq!(harness.cpu_state().pc, 0x8004);
+		assert_eq!(harness.dump_memory(0x0010, 0x0012), "$0010: 01 00 00\n");
+	}
+
+	#[test]
+	fn pc_condition_test() {
+		let mut harness = nrom_harness("A9 01 85 10 4C 04 80");
+		harness.add_condition(Condition::PcEquals(0x8002), Verdict::Fail);
+
+		assert_eq!(harness.run(), StopReason::Condition(Condition::PcEquals(0x8002), Verdict::Fail));
+		assert_eq!(harness.cpu_state().a, 0x01);
+	}
+
+	#[test]
+	fn jammed_test() {
+		let mut harness = nrom_harness("A9 01 85 10 4C 04 80");
+		assert_eq!(harness.run(), StopReason::Jammed);
+		assert_eq!(harness.cpu_state().pc, 0x8004);
+	}
+
+	#[test]
+	fn stuck_loop_test() {
+		/*
+		vblank:
+		BIT $2002
+		BPL vblank
+		loop:
+		JMP loop
+		*/
+		let mut harness = nrom_harness("2C 02 20 10 FB 4C 05 80").detect_stuck_loops(StuckDetection::default());
+		assert_eq!(harness.run(), StopReason::StuckLoop { pc: 0x8005 });
+		// The polling loop waited for VBlank, at scanline 241.
+		assert!(harness.emulator().cycles() > 241 * 113, "cycles: {}", harness.emulator().cycles());
+		assert_eq!(StopReason::StuckLoop { pc: 0x8005 }.to_string(), "CPU stuck in a loop at $8005");
+
+		/*
+		LDA #$80
+		STA $2000		; NMI on
+		loop:
+		JMP loop
+		*/
+		let mut harness = nrom_harness("A9 80 8D 00 20 4C 05 80").detect_stuck_loops(StuckDetection::SelfJump).max_frames(2);
+		assert_eq!(harness.run(), StopReason::BudgetExhausted);
+	}
+
+	/// A blargg style ROM: status $80, the signature, the message "OK", and then `program`, at $8023.
+	fn blargg_harness(program: &str) -> Harness {
+		/*
+		LDA #$80
+		STA $6000
+		LDA #$DE
+		STA $6001
+		LDA #$B0
+		STA $6002
+		LDA #$61
+		STA $6003
+		LDA #'O'
+		STA $6004
+		LDA #'K'
+		STA $6005
+		LDA #$00
+		STA $6006
+		*/
+		nrom_harness(&format!("A9 80 8D 00 60 A9 DE 8D 01 60 A9 B0 8D 02 60 A9 61 8D 03 60 A9 4F 8D 04 60 A9 4B 8D 05 60 A9 00 8D 06 60 {}", program))
+	}
+
+	#[test]
+	fn blargg_test() {
+		/*
+		LDA $10
+		BNE second_run
+		INC $10
+		LDA #$81
+		STA $6000		; Press reset
+		loop:
+		JMP loop
+		second_run:
+		LDA #$00
+		STA $6000		; Passed
+		done:
+		JMP done
+		*/
+		let mut harness = blargg_harness("A5 10 D0 0A E6 10 A9 81 8D 00 60 4C 2E 80 A9 00 8D 00 60 4C 36 80").max_frames(60);
+		assert_eq!(BlarggResult::poll(harness.emulator().bus()), None);
+
+		let result = BlarggResult { status: 0, message: "OK".to_string() };
+		assert_eq!(harness.run_blargg(), BlarggStop::Done(result.clone()));
+		assert!(result.passed());
+		assert_eq!(result.to_string(), "Passed: OK");
+		// It waited for the reset.
+		assert!(harness.frames >= BLARGG_RESET_DELAY_FRAMES, "frames: {}", harness.frames);
+	}
+
+	#[test]
+	fn blargg_failed_test() {
+		/*
+		LDA #$02
+		STA $6000
+		done:
+		JMP done
+		*/
+		let mut harness = blargg_harness("A9 02 8D 00 60 4C 28 80");
+		let BlarggStop::Done(result) = harness.run_blargg() else {
+			panic!("The ROM is done");
+		};
+		assert!(result.done() && !result.passed());
+		assert_eq!(result.to_string(), "Failed with code 2: OK");
+
+		// Still running when it jams.
+		let mut harness = blargg_harness("4C 23 80");
+		let running = BlarggResult { status: 0x80, message: "OK".to_string() };
+		assert_eq!(harness.run_blargg(), BlarggStop::Stopped(StopReason::Jammed, Some(running)));
+	}
+
+	#[test]
+	fn rom_write_test() {
+		/*
+		LDA #$42
+		STA $10
+		STA $C000
+		loop:
+		JMP loop
+		*/
+		let program = "A9 42 85 10 8D 00 C0 4C 07 80";
+		let mut harness = nrom_harness(program).stop_on_rom_write();
+		let violation = RomWriteViolation { pc: 0x8004, addr: 0xC000, value: 0x42 };
+		assert_eq!(harness.run(), StopReason::RomWrite(violation));
+		assert_eq!(harness.emulator().bus().rom_write_violations(), [violation]);
+		assert_eq!(StopReason::RomWrite(violation).to_string(), "Write of $42 to ROM at $C000, by the instruction at $8004");
+		// The ROM didn't change, and the RAM write went through.
+		assert_eq!(harness.emulator().peek(0xC000), 0xA9);
+		assert_eq!(harness.emulator().peek(0x0010), 0x42);
+		// It goes on from there.
+		assert_eq!(harness.run(), StopReason::Jammed);
+
+		// Not strict: the write is just dropped.
+		let mut harness = nrom_harness(program);
+		assert_eq!(harness.run(), StopReason::Jammed);
+		assert!(harness.emulator().bus().rom_write_violations().is_empty());
+		assert_eq!(harness.emulator().peek(0xC000), 0xA9);
+	}
+
+	#[test]
+	fn uninitialized_read_test() {
+		/*
+		LDA $10
+		loop:
+		JMP loop
+		*/
+		let mut harness = nrom_harness("A5 10 4C 02 80").stop_on_uninitialized_read();
+		let read = UninitializedRead { pc: 0x8000, addr: 0x0010 };
+		assert_eq!(harness.run(), StopReason::UninitializedRead(read));
+		assert_eq!(StopReason::UninitializedRead(read).to_string(), "Read of $0010, which was never written, by the instruction at $8000");
+		assert_eq!(harness.run(), StopReason::Jammed);
+
+		/*
+		STA $10
+		LDA $10
+		JSR sub		; The stack was pushed, so RTS reads it fine
+		LDA $6123	; PRG RAM too
+		loop:
+		JMP loop
+		sub:
+		RTS
+		*/
+		let program = "85 10 A5 10 20 0D 80 AD 23 61 4C 0A 80 60";
+		let mut harness = nrom_harness(program).stop_on_uninitialized_read();
+		assert_eq!(harness.run(), StopReason::UninitializedRead(UninitializedRead { pc: 0x8007, addr: 0x6123 }));
+		assert_eq!(harness.run(), StopReason::Jammed);
+		assert_eq!(harness.emulator().bus().uninitialized_reads().len(), 1);
+		// Not asked for: nothing is recorded.
+		let mut harness = nrom_harness(program);
+		assert_eq!(harness.run(), StopReason::Jammed);
+		assert!(harness.emulator().bus().uninitialized_reads().is_empty());
+	}
+
+	#[test]
+	fn stack_fault_test() {
+		/*
+		JSR sub
+		PLA 	; Nothing left to pull
+		loop:
+		JMP loop
+		sub:
+		RTS
+		*/
+		let program = "20 07 80 68 4C 04 80 60";
+		let mut harness = nrom_harness(program).stop_on_stack_fault();
+		assert_eq!(harness.run(), StopReason::StackUnderflow { pc: 0x8003 });
+		assert_eq!(StopReason::StackUnderflow { pc: 0x8003 }.to_string(), "Stack underflow: the instruction at $8003 pulled with SP at $FF");
+		assert_eq!(harness.run(), StopReason::Jammed);
+		// Not asked for: it runs to the end.
+		assert_eq!(nrom_harness(program).run(), StopReason::Jammed);
+
+		/*
+		JSR sub 	; With SP at $02, pushes at $0102 and $0101
+		loop:
+		JMP loop
+		sub:
+		PHA 	; SP wraps around to $FF
+		RTS
+		*/
+		let mut harness = nrom_harness("20 06 80 4C 03 80 48 60").stop_on_stack_fault();
+		harness.emulator_mut().set_register(Register::SP, 0x02);
+		assert_eq!(harness.run(), StopReason::StackOverflow { pc: 0x8006 });
+		assert_eq!(harness.cpu_state().s, 0xFF);
+	}
+
+	#[test]
+	fn budget_test() {
+		/*
+		loop:
+		INX
+		JMP loop
+		*/
+		let mut harness = nrom_harness("E8 4C 00 80").max_cycles(1000);
+		let start = harness.emulator().cycles();
+		assert_eq!(harness.run(), StopReason::BudgetExhausted);
+		// Stops at the first instruction boundary after the budget.
+		let cycles = harness.emulator().cycles() - start;
+		assert!((1000..1000 + 3).contains(&cycles), "cycles: {}", cycles);
+
+		// Frames end where `run_frame` ends them.
+		let mut harness = nrom_harness("E8 4C 00 80").max_frames(2);
+		assert_eq!(harness.run(), StopReason::BudgetExhausted);
+		let mut emulator = Emulator::new(Cartridge::from_ines(&test_rom::nrom("E8 4C 00 80")).unwrap());
+		emulator.run_frame();
+		emulator.run_frame();
+		assert_eq!(harness.emulator().cycles(), emulator.cycles());
+	}
+}
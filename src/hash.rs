@@ -0,0 +1,179 @@
+// Hashes of ROMs and states. They run once per ROM (or once per test), so they are written for clarity, not speed.
+//
+// * CRC32 identifies the game for save states, like ROM databases do.
+// * MD5 is what FCEUX puts in movie files (`romChecksum`), so movies can be shared with it.
+// * SHA-1 is the other key of ROM databases (romdb.rs), with CRC32: No-Intro and NesCartDB list both.
+// * FNV-1a hashes frames and states (`Emulator::frame_hash`), for regression tests. It's simple, and the same on every
+//   host: it reads bytes, never wider words.
+
+/// CRC-32 (IEEE), bit by bit.
+pub fn crc32(bytes: &[u8]) -> u32 {
+	let mut crc = !0u32;
+	for &byte in bytes {
+		crc ^= byte as u32;
+		for _ in 0..8 {
+			crc = if crc & 1 == 1 { (crc >> 1) ^ 0xEDB8_8320 } else { crc >> 1 };
+		}
+	}
+	!crc
+}
+
+/// Shift amounts of every MD5 round.
+const MD5_SHIFTS: [u32; 64] = [
+	7, 12, 17, 22, 7, 12, 17, 22, 7, 12, 17, 22, 7, 12, 17, 22,
+	5, 9, 14, 20, 5, 9, 14, 20, 5, 9, 14, 20, 5, 9, 14, 20,
+	4, 11, 16, 23, 4, 11, 16, 23, 4, 11, 16, 23, 4, 11, 16, 23,
+	6, 10, 15, 21, 6, 10, 15, 21, 6, 10, 15, 21, 6, 10, 15, 21,
+];
+
+/// MD5: https://www.ietf.org/rfc/rfc1321.txt
+pub fn md5(bytes: &[u8]) -> [u8; 16] {
+	// K[i] = floor(abs(sin(i + 1)) * 2^32).
+	let constants: Vec<u32> = (0..64).map(|i| ((i as f64 + 1.0).sin().abs() * 4_294_967_296.0) as u32).collect();
+
+	// Pad with a 1 bit, zeros, and the length in bits, to a multiple of 64 bytes.
+	let mut message = bytes.to_vec();
+	message.push(0x80);
+	while message.len() % 64 != 56 {
+		message.push(0);
+	}
+	message.extend_from_slice(&((bytes.len() as u64).wrapping_mul(8)).to_le_bytes());
+
+	let mut state: [u32; 4] = [0x6745_2301, 0xEFCD_AB89, 0x98BA_DCFE, 0x1032_5476];
+	for chunk in message.chunks(64) {
+		let words: Vec<u32> = chunk.chunks(4).map(|word| u32::from_le_bytes([word[0], word[1], word[2], word[3]])).collect();
+		let [mut a, mut b, mut c, mut d] = state;
+		for i in 0..64 {
+			let (f, g) = match i / 16 {
+				0 => ((b & c) | (!b & d), i),
+				1 => ((d & b) | (!d & c), (5 * i + 1) % 16),
+				2 => (b ^ c ^ d, (3 * i + 5) % 16),
+				_ => (c ^ (b | !d), (7 * i) % 16),
+			};
+			let rotated = a.wrapping_add(f).wrapping_add(constants[i]).wrapping_add(words[g]).rotate_left(MD5_SHIFTS[i]);
+			a = d;
+			d = c;
+			c = b;
+			b = b.wrapping_add(rotated);
+		}
+		state = [state[0].wrapping_add(a), state[1].wrapping_add(b), state[2].wrapping_add(c), state[3].wrapping_add(d)];
+	}
+
+	let mut digest = [0; 16];
+	for (i, word) in state.iter().enumerate() {
+		digest[i * 4..i * 4 + 4].copy_from_slice(&word.to_le_bytes());
+	}
+	digest
+}
+
+/// SHA-1: https://www.ietf.org/rfc/rfc3174.txt
+pub fn sha1(bytes: &[u8]) -> [u8; 20] {
+	// Padded like MD5, but the length is big endian.
+	let mut message = bytes.to_vec();
+	message.push(0x80);
+	while message.len() % 64 != 56 {
+		message.push(0);
+	}
+	message.extend_from_slice(&((bytes.len() as u64).wrapping_mul(8)).to_be_bytes());
+
+	let mut state: [u32; 5] = [0x6745_2301, 0xEFCD_AB89, 0x98BA_DCFE, 0x1032_5476, 0xC3D2_E1F0];
+	for chunk in message.chunks(64) {
+		let mut words = [0u32; 80];
+		for (i, word) in chunk.chunks(4).enumerate() {
+			words[i] = u32::from_be_bytes([word[0], word[1], word[2], word[3]]);
+		}
+		for i in 16..80 {
+			words[i] = (words[i - 3] ^ words[i - 8] ^ words[i - 14] ^ words[i - 16]).rotate_left(1);
+		}
+		let [mut a, mut b, mut c, mut d, mut e] = state;
+		for (i, word) in words.iter().enumerate() {
+			let (f, k) = match i / 20 {
+				0 => ((b & c) | (!b & d), 0x5A82_7999),
+				1 => (b ^ c ^ d, 0x6ED9_EBA1),
+				2 => ((b & c) | (b & d) | (c & d), 0x8F1B_BCDC),
+				_ => (b ^ c ^ d, 0xCA62_C1D6),
+			};
+			let temp = a.rotate_left(5).wrapping_add(f).wrapping_add(e).wrapping_add(k).wrapping_add(*word);
+			e = d;
+			d = c;
+			c = b.rotate_left(30);
+			b = a;
+			a = temp;
+		}
+		for (value, add) in state.iter_mut().zip([a, b, c, d, e]) {
+			*value = value.wrapping_add(add);
+		}
+	}
+
+	let mut digest = [0; 20];
+	for (i, word) in state.iter().enumerate() {
+		digest[i * 4..i * 4 + 4].copy_from_slice(&word.to_be_bytes());
+	}
+	digest
+}
+
+/// FNV-1a, 64 bits, fed a part at a time.
+pub struct Fnv1a(u64);
+
+impl Default for Fnv1a {
+	fn default() -> Self {
+		Fnv1a(0xCBF2_9CE4_8422_2325)
+	}
+}
+
+impl Fnv1a {
+	pub fn write(&mut self, bytes: &[u8]) {
+		for &byte in bytes {
+			self.0 = (self.0 ^ byte as u64).wrapping_mul(0x0000_0100_0000_01B3);
+		}
+	}
+
+	pub fn finish(&self) -> u64 {
+		self.0
+	}
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+
+	#[test]
+	fn crc32_test() {
+		assert_eq!(crc32(b"123456789"), 0xCBF4_3926);
+		assert_eq!(crc32(b""), 0);
+	}
+
+	#[test]
+	fn fnv1a_test() {
+		let hash = |bytes: &[u8]| {
+			let mut fnv = Fnv1a::default();
+			fnv.write(bytes);
+			fnv.finish()
+		};
+		assert_eq!(hash(b""), 0xCBF2_9CE4_8422_2325);
+		assert_eq!(hash(b"a"), 0xAF63_DC4C_8601_EC8C);
+		assert_eq!(hash(b"foobar"), 0x8594_4171_F739_67E8);
+
+		// In parts, or at once.
+		let mut fnv = Fnv1a::default();
+		fnv.write(b"foo");
+		fnv.write(b"bar");
+		assert_eq!(fnv.finish(), hash(b"foobar"));
+	}
+
+	#[test]
+	fn md5_test() {
+		assert_eq!(hex::encode(md5(b"")), "d41d8cd98f00b204e9800998ecf8427e");
+		assert_eq!(hex::encode(md5(b"The quick brown fox jumps over the lazy dog")), "9e107d9d372bb6826bd81d3542a419d6");
+		// More than one chunk.
+		assert_eq!(hex::encode(md5(&[b'a'; 100])), "36a92cc94a9e0fa21f625f8bfb007adf");
+	}
+
+	#[test]
+	fn sha1_test() {
+		assert_eq!(hex::encode(sha1(b"")), "da39a3ee5e6b4b0d3255bfef95601890afd80709");
+		assert_eq!(hex::encode(sha1(b"The quick brown fox jumps over the lazy dog")), "2fd4e1c67a2d28fced849ee1bb76e7391b93eb12");
+		// More than one chunk.
+		assert_eq!(hex::encode(sha1(b"abcdbcdecdefdefgefghfghighijhijkijkljklmklmnlmnomnopnopq")), "84983e441c3bd26ebaae4aa1f95129e5e54670f1");
+	}
+}
@@ -0,0 +1,220 @@
+// Hooks: Rust closures that the emulator calls while it runs, for bots and gameplay tests without a scripting
+// language. `Emulator::on_frame` calls one after every finished frame, and `Emulator::on_memory_equals` when a byte
+// becomes a value (checked after every instruction, like the watchpoints of the debugger, so it's the change that
+// calls it, not every instruction while the byte stays the same).
+//
+// A hook gets an `EmulatorView`: it reads memory, sets the controllers, saves and loads states, and asks to stop.
+// It can't run the emulator: the view has no method for it, and the emulator is borrowed while the hook runs, so
+// a hook that captured it doesn't compile. A stop shows in `Emulator::run_budget` (`StopReason::Requested`), and in
+// `Emulator::take_stop_request` after `run_frame`, which always finishes its frame.
+//
+// Hooks are not part of the state: save states and serde leave them out, like the frame callback.
+
+use crate::controller::ButtonState;
+use crate::cpu::cpu::CpuState;
+use crate::emulator::Emulator;
+use crate::error::NesError;
+use crate::harness::Condition;
+
+pub type Hook = Box<dyn FnMut(&mut EmulatorView)>;
+
+/// What a hook can do with the emulator, see the top of the file.
+pub struct EmulatorView<'a> {
+	emulator: &'a mut Emulator,
+	stop: &'a mut bool,
+}
+
+impl<'a> EmulatorView<'a> {
+	pub(crate) fn new(emulator: &'a mut Emulator, stop: &'a mut bool) -> Self {
+		EmulatorView { emulator, stop }
+	}
+
+	/// Read memory without side effects, see `Emulator::peek`.
+	pub fn peek(&self, addr: u16) -> u8 {
+		self.emulator.peek(addr)
+	}
+
+	pub fn cpu_state(&self) -> CpuState {
+		self.emulator.cpu_state()
+	}
+
+	/// Frames the PPU finished since power on.
+	pub fn frame(&self) -> u64 {
+		self.emulator.frame()
+	}
+
+	/// The buttons of controller 1 from now on: the next frame, for a frame hook.
+	pub fn set_controller1(&mut self, buttons: ButtonState) {
+		self.emulator.set_controller1(buttons);
+	}
+
+	pub fn set_controller2(&mut self, buttons: ButtonState) {
+		self.emulator.set_controller2(buttons);
+	}
+
+	pub fn save_state(&self) -> Vec<u8> {
+		self.emulator.save_state()
+	}
+
+	/// The emulator goes on from `state`, once the hooks return.
+	pub fn load_state(&mut self, state: &[u8]) -> Result<(), NesError> {
+		self.emulator.load_state(state)
+	}
+
+	/// Ask the emulator to stop: `run_budget` returns, and `take_stop_request` is true.
+	pub fn stop(&mut self) {
+		*self.stop = true;
+	}
+}
+
+struct MemoryHook {
+	condition: Condition,
+	/// The condition was met after the last instruction.
+	met: bool,
+	hook: Hook,
+}
+
+/// The hooks of an emulator.
+#[derive(Default)]
+pub(crate) struct Hooks {
+	frame: Vec<Hook>,
+	memory: Vec<MemoryHook>,
+	pub(crate) stop: bool,
+}
+
+impl Hooks {
+	pub(crate) fn add_frame(&mut self, hook: Hook) {
+		self.frame.push(hook);
+	}
+
+	/// `met` is whether the byte has the value already: it only calls the hook when it changes to it.
+	pub(crate) fn add_memory(&mut self, addr: u16, value: u8, met: bool, hook: Hook) {
+		self.memory.push(MemoryHook { condition: Condition::MemoryEquals { addr, value }, met, hook });
+	}
+
+	pub(crate) fn has_memory(&self) -> bool {
+		!self.memory.is_empty()
+	}
+}
+
+/// Call the frame hooks. They are taken out of the emulator while they run, since the view borrows all of it.
+pub(crate) fn run_frame_hooks(emulator: &mut Emulator) {
+	let mut hooks = std::mem::take(&mut emulator.hooks_mut().frame);
+	if hooks.is_empty() {
+		return;
+	}
+	let mut stop = false;
+	for hook in &mut hooks {
+		hook(&mut EmulatorView::new(emulator, &mut stop));
+	}
+	let state = emulator.hooks_mut();
+	state.frame = hooks;
+	state.stop |= stop;
+}
+
+/// Call the memory hooks whose byte just became their value.
+pub(crate) fn run_memory_hooks(emulator: &mut Emulator) {
+	let mut hooks = std::mem::take(&mut emulator.hooks_mut().memory);
+	let mut stop = false;
+	for memory in &mut hooks {
+		let met = memory.condition.met_on(0, |addr| emulator.peek(addr));
+		if met && !memory.met {
+			(memory.hook)(&mut EmulatorView::new(emulator, &mut stop));
+		}
+		memory.met = met;
+	}
+	let state = emulator.hooks_mut();
+	state.memory = hooks;
+	state.stop |= stop;
+}
+
+#[cfg(test)]
+mod tests {
+	use std::cell::RefCell;
+	use std::rc::Rc;
+
+	use super::*;
+	use crate::cartridge::{test_rom, Cartridge};
+	use crate::controller::Button;
+	use crate::emulator::StopReason;
+
+	/// Reads controller 1 over and over, and sets $10 to 1 when Start is pressed.
+	fn start_rom() -> Cartridge {
+		/*
+		loop:
+		LDA #$01
+		STA $4016
+		LDA #$00
+		STA $4016 	; Strobe
+		LDA $4016 	; A
+		LDA $4016 	; B
+		LDA $4016 	; Select
+		LDA $4016 	; Start
+		AND #$01
+		BEQ loop
+		STA $10
+		JMP loop
+		*/
+		let rom = test_rom::nrom("A9 01 8D 16 40 A9 00 8D 16 40 AD 16 40 AD 16 40 AD 16 40 AD 16 40 29 01 F0 E6 85 10 4C 00 80");
+		Cartridge::from_ines(&rom).unwrap()
+	}
+
+	#[test]
+	fn hooks_test() {
+		let mut emulator = Emulator::new(start_rom());
+		let log = Rc::new(RefCell::new(Vec::new()));
+
+		// Start for 10 frames, from the end of frame 4, and what $10 was at every frame.
+		let frames = log.clone();
+		emulator.on_frame(move |view| {
+			frames.borrow_mut().push(view.peek(0x10));
+			let mut buttons = ButtonState::default();
+			buttons.set(Button::Start, (4..14).contains(&view.frame()));
+			view.set_controller1(buttons);
+		});
+		let changes = Rc::new(RefCell::new(Vec::new()));
+		let saw = changes.clone();
+		emulator.on_memory_equals(0x10, 0x01, move |view| {
+			saw.borrow_mut().push((view.frame(), view.save_state()));
+			view.stop();
+		});
+
+		// run_frame finishes the frame, and keeps the stop for later.
+		for _ in 0..5 {
+			emulator.run_frame();
+			assert!(!emulator.take_stop_request());
+		}
+		assert_eq!(*log.borrow(), [0; 5]);
+		emulator.run_frame();
+		assert!(emulator.take_stop_request());
+		assert!(!emulator.take_stop_request());
+		assert_eq!(emulator.peek(0x10), 0x01);
+		assert_eq!(changes.borrow().len(), 1);
+		// The program sees Start as soon as the hook returns, in the VBlank of frame 4.
+		assert_eq!(changes.borrow()[0].0, 4);
+
+		// Once: the byte stays 1.
+		for _ in 0..20 {
+			let result = emulator.run_budget(u64::MAX);
+			assert!(result.frame_completed);
+			assert_eq!(result.stop, None);
+		}
+		assert_eq!(changes.borrow().len(), 1);
+		assert_eq!(log.borrow().len(), 26);
+
+		// It saved the state with the byte at 1.
+		let state = changes.borrow()[0].1.clone();
+		emulator.poke(0x10, 0x00);
+		emulator.load_state(&state).unwrap();
+		assert_eq!(emulator.peek(0x10), 0x01);
+
+		// A byte that changes to the value again calls it again, and `run_budget` stops after the instruction.
+		emulator.poke(0x10, 0x00);
+		assert_eq!(emulator.run_budget(1).stop, None);
+		emulator.poke(0x10, 0x01);
+		let result = emulator.run_budget(u64::MAX);
+		assert_eq!(result.stop, Some(StopReason::Requested));
+		assert!(!result.frame_completed);
+		assert_eq!(changes.borrow().len(), 2);
+	}
+}
@@ -0,0 +1,240 @@
+// Inflate: the decompressor of deflate (RFC 1951), for the PNG files of other programs (png.rs decodes them, for
+// `--compare`). It's written for short images, not speed: the Huffman codes are decoded a bit at a time, like zlib's
+// puff.c.
+//
+// A deflate stream is a list of blocks, each of them stored (not compressed), or compressed with the fixed Huffman
+// codes, or with codes at the start of the block (dynamic). The codes are sent as their lengths only: the codes of
+// every length are consecutive numbers, in the order of the symbols.
+
+/// The longest code.
+const MAX_BITS: usize = 15;
+
+/// Length symbols 257-285: the least length, and the extra bits on top.
+const LENGTH_BASE: [u16; 29] = [3, 4, 5, 6, 7, 8, 9, 10, 11, 13, 15, 17, 19, 23, 27, 31, 35, 43, 51, 59, 67, 83, 99, 115, 131, 163, 195, 227, 258];
+const LENGTH_EXTRA: [u8; 29] = [0, 0, 0, 0, 0, 0, 0, 0, 1, 1, 1, 1, 2, 2, 2, 2, 3, 3, 3, 3, 4, 4, 4, 4, 5, 5, 5, 5, 0];
+/// Distance symbols 0-29.
+const DIST_BASE: [u16; 30] = [1, 2, 3, 4, 5, 7, 9, 13, 17, 25, 33, 49, 65, 97, 129, 193, 257, 385, 513, 769, 1025, 1537, 2049, 3073, 4097, 6145, 8193, 12289, 16385, 24577];
+const DIST_EXTRA: [u8; 30] = [0, 0, 0, 0, 1, 1, 2, 2, 3, 3, 4, 4, 5, 5, 6, 6, 7, 7, 8, 8, 9, 9, 10, 10, 11, 11, 12, 12, 13, 13];
+/// The order of the code length code lengths, in a dynamic block.
+const CODE_LENGTH_ORDER: [usize; 19] = [16, 17, 18, 0, 8, 7, 9, 6, 10, 5, 11, 4, 12, 3, 13, 2, 14, 1, 15];
+
+struct Bits<'a> {
+	data: &'a [u8],
+	pos: usize,
+	buffer: u32,
+	count: u32,
+}
+
+impl<'a> Bits<'a> {
+	/// `n` bits (up to 16), the first one in the lowest bit.
+	fn read(&mut self, n: u32) -> Result<u32, String> {
+		while self.count < n {
+			let byte = *self.data.get(self.pos).ok_or("The deflate stream ends too soon")?;
+			self.buffer |= (byte as u32) << self.count;
+			self.pos += 1;
+			self.count += 8;
+		}
+		let bits = self.buffer & ((1 << n) - 1);
+		self.buffer >>= n;
+		self.count -= n;
+		Ok(bits)
+	}
+
+	/// Drop the bits left in the byte, for a stored block.
+	fn align(&mut self) {
+		self.buffer = 0;
+		self.count = 0;
+	}
+}
+
+/// A canonical Huffman code: how many codes have every length, and the symbols in the order of their codes.
+struct Huffman {
+	counts: [u16; MAX_BITS + 1],
+	symbols: Vec<u16>,
+}
+
+impl Huffman {
+	fn new(lengths: &[u8]) -> Result<Self, String> {
+		let mut counts = [0u16; MAX_BITS + 1];
+		for &length in lengths {
+			counts[length as usize] += 1;
+		}
+		counts[0] = 0;
+
+		// More codes of a length than there are left is not a code.
+		let mut left = 1i32;
+		for &count in &counts[1..] {
+			left = left * 2 - count as i32;
+			if left < 0 {
+				return Err("A Huffman code with too many codes".to_string());
+			}
+		}
+
+		let mut offsets = [0u16; MAX_BITS + 1];
+		for length in 1..MAX_BITS {
+			offsets[length + 1] = offsets[length] + counts[length];
+		}
+		let mut symbols = vec![0; lengths.len()];
+		for (symbol, &length) in lengths.iter().enumerate() {
+			if length != 0 {
+				symbols[offsets[length as usize] as usize] = symbol as u16;
+				offsets[length as usize] += 1;
+			}
+		}
+		Ok(Huffman { counts, symbols })
+	}
+
+	fn decode(&self, bits: &mut Bits) -> Result<u16, String> {
+		// The codes of a length are from `first`, and `index` is where their symbols start.
+		let (mut code, mut first, mut index) = (0i32, 0i32, 0i32);
+		for &count in &self.counts[1..] {
+			code |= bits.read(1)? as i32;
+			let count = count as i32;
+			if code - first < count {
+				return Ok(self.symbols[(index + code - first) as usize]);
+			}
+			index += count;
+			first = (first + count) << 1;
+			code <<= 1;
+		}
+		Err("Not a code of the Huffman table".to_string())
+	}
+}
+
+/// The uncompressed data of a deflate stream, and how many bytes the stream took (zlib has its checksum after it).
+pub(crate) fn inflate(data: &[u8]) -> Result<(Vec<u8>, usize), String> {
+	let mut bits = Bits { data, pos: 0, buffer: 0, count: 0 };
+	let mut out = vec![];
+	loop {
+		let last = bits.read(1)? == 1;
+		match bits.read(2)? {
+			0 => stored(&mut bits, &mut out)?,
+			1 => {
+				let (lengths, distances) = fixed();
+				codes(&mut bits, &mut out, &Huffman::new(&lengths)?, &Huffman::new(&distances)?)?;
+			}
+			2 => {
+				let (lengths, distances) = dynamic(&mut bits)?;
+				codes(&mut bits, &mut out, &lengths, &distances)?;
+			}
+			_ => return Err("A deflate block of type 3".to_string()),
+		}
+		if last {
+			return Ok((out, bits.pos));
+		}
+	}
+}
+
+fn stored(bits: &mut Bits, out: &mut Vec<u8>) -> Result<(), String> {
+	bits.align();
+	let header = bits.data.get(bits.pos..bits.pos + 4).ok_or("The deflate stream ends too soon")?;
+	let len = u16::from_le_bytes([header[0], header[1]]);
+	if !len != u16::from_le_bytes([header[2], header[3]]) {
+		return Err("A stored deflate block with a wrong length".to_string());
+	}
+	let start = bits.pos + 4;
+	let block = bits.data.get(start..start + len as usize).ok_or("The deflate stream ends too soon")?;
+	out.extend_from_slice(block);
+	bits.pos = start + len as usize;
+	Ok(())
+}
+
+/// The code lengths of the fixed Huffman codes, for the literals and lengths, and for the distances.
+fn fixed() -> (Vec<u8>, Vec<u8>) {
+	let mut lengths = vec![8; 288];
+	lengths[144..256].fill(9);
+	lengths[256..280].fill(7);
+	(lengths, vec![5; 30])
+}
+
+/// The Huffman codes at the start of a dynamic block.
+fn dynamic(bits: &mut Bits) -> Result<(Huffman, Huffman), String> {
+	let literals = bits.read(5)? as usize + 257;
+	let distances = bits.read(5)? as usize + 1;
+	let code_lengths = bits.read(4)? as usize + 4;
+	if literals > 286 || distances > 30 {
+		return Err("A dynamic deflate block with too many codes".to_string());
+	}
+
+	let mut lengths = [0u8; 19];
+	for &symbol in &CODE_LENGTH_ORDER[..code_lengths] {
+		lengths[symbol] = bits.read(3)? as u8;
+	}
+	let code_length_code = Huffman::new(&lengths)?;
+
+	// The lengths of both codes, in a row: 0-15 is a length, and 16-18 repeat one.
+	let mut lengths = Vec::with_capacity(literals + distances);
+	while lengths.len() < literals + distances {
+		let (length, repeat) = match code_length_code.decode(bits)? {
+			length @ 0..=15 => (length as u8, 1),
+			16 => (*lengths.last().ok_or("A repeat of no code length")?, 3 + bits.read(2)?),
+			17 => (0, 3 + bits.read(3)?),
+			_ => (0, 11 + bits.read(7)?),
+		};
+		lengths.extend(std::iter::repeat_n(length, repeat as usize));
+	}
+	if lengths.len() > literals + distances {
+		return Err("Code lengths repeated past the end".to_string());
+	}
+	if lengths[256] == 0 {
+		return Err("A dynamic deflate block without an end code".to_string());
+	}
+	Ok((Huffman::new(&lengths[..literals])?, Huffman::new(&lengths[literals..])?))
+}
+
+/// The literals and copies of a compressed block, to its end code.
+fn codes(bits: &mut Bits, out: &mut Vec<u8>, lengths: &Huffman, distances: &Huffman) -> Result<(), String> {
+	loop {
+		let symbol = lengths.decode(bits)? as usize;
+		match symbol {
+			0..=255 => out.push(symbol as u8),
+			256 => return Ok(()),
+			_ => {
+				let index = symbol - 257;
+				if index >= LENGTH_BASE.len() {
+					return Err(format!("Length code {}", symbol));
+				}
+				let length = LENGTH_BASE[index] as usize + bits.read(LENGTH_EXTRA[index] as u32)? as usize;
+				let index = distances.decode(bits)? as usize;
+				if index >= DIST_BASE.len() {
+					return Err(format!("Distance code {}", index));
+				}
+				let distance = DIST_BASE[index] as usize + bits.read(DIST_EXTRA[index] as u32)? as usize;
+				if distance > out.len() {
+					return Err("A copy from before the start".to_string());
+				}
+				// Byte by byte: the copy may overlap what it writes.
+				let start = out.len() - distance;
+				for i in 0..length {
+					out.push(out[start + i]);
+				}
+			}
+		}
+	}
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+
+	fn bytes(hex: &str) -> Vec<u8> {
+		(0..hex.len()).step_by(2).map(|i| u8::from_str_radix(&hex[i..i + 2], 16).unwrap()).collect()
+	}
+
+	#[test]
+	fn inflate_test() {
+		// zlib.compress(data, 9) in Python, without its 2 byte header: a dynamic block.
+		let data: Vec<u8> = (0..600).map(|i: usize| (i * i / 3 % 11) as u8 + b'a').collect();
+		let zlib = bytes("78daedcab10d00300803b05b134144e0ff9dbed09dd532c090a9112a396df7300b0f6805810b17fec302c088ee3e");
+		assert_eq!((zlib[2] >> 1) & 3, 2);
+		assert_eq!(inflate(&zlib[2..]).unwrap(), (data, zlib.len() - 2 - 4));
+
+		// Fixed codes ("abc" and a copy of it, overlapping), an empty stored block, and a last stored block of "xy".
+		let deflate = bytes("4a4c4a4e042300000000ffff010200fdff7879");
+		assert_eq!(inflate(&deflate).unwrap(), (b"abcabcabcxy".to_vec(), deflate.len()));
+
+		// Block type 3, and a stream that ends too soon.
+		assert!(inflate(&[0x07]).is_err());
+		assert!(inflate(&deflate[..4]).is_err());
+	}
+}
@@ -0,0 +1,128 @@
+// The IRQ line of the CPU. It's a single wire, but many devices can pull it: the APU frame counter, the DMC, and
+// mappers like MMC3. The CPU sees an interrupt while any of them holds it, and each device lets go on its own terms
+// (reading $4015 acknowledges the frame counter, but not the DMC). So the line keeps who holds it, a bit each:
+//
+// | Bit | Source |
+// |---|---|
+// | 0 | APU frame counter |
+// | 1 | APU DMC |
+// | 2 | Mapper (the cartridge) |
+//
+// Buses return it from `Bus::irq_sources`, and the CPU polls `Bus::irq_pending`. The debugger shows who holds it.
+
+use core::fmt;
+
+#[allow(non_camel_case_types)]
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub enum IrqSource {
+	FRAME_COUNTER = 0b001,
+	DMC = 0b010,
+	MAPPER = 0b100,
+}
+
+impl IrqSource {
+	pub const ALL: [IrqSource; 3] = [IrqSource::FRAME_COUNTER, IrqSource::DMC, IrqSource::MAPPER];
+
+	pub fn mask(self) -> u8 {
+		self as u8
+	}
+
+	pub fn name(self) -> &'static str {
+		match self {
+			IrqSource::FRAME_COUNTER => "frame counter",
+			IrqSource::DMC => "DMC",
+			IrqSource::MAPPER => "mapper",
+		}
+	}
+}
+
+/// The sources holding the line. Displayed as their names, like `frame counter, DMC`, or `none`.
+#[derive(Clone, Copy, PartialEq, Eq, Debug, Default)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct IrqLine {
+	sources: u8,
+}
+
+impl IrqLine {
+	/// `source` pulls the line. Other sources are not changed.
+	pub fn assert(&mut self, source: IrqSource) {
+		self.sources |= source.mask();
+	}
+
+	/// `source` lets go of the line. It stays asserted if other sources hold it.
+	pub fn deassert(&mut self, source: IrqSource) {
+		self.sources &= !source.mask();
+	}
+
+	pub fn set(&mut self, source: IrqSource, asserted: bool) {
+		if asserted {
+			self.assert(source);
+		} else {
+			self.deassert(source);
+		}
+	}
+
+	/// Any source holds the line.
+	pub fn is_asserted(&self) -> bool {
+		self.sources != 0
+	}
+
+	pub fn holds(&self, source: IrqSource) -> bool {
+		self.sources & source.mask() != 0
+	}
+
+	/// The bits of the sources, see the top of the file.
+	pub fn bits(&self) -> u8 {
+		self.sources
+	}
+
+	/// Both lines: a bus with many devices joins theirs.
+	pub fn join(self, other: IrqLine) -> IrqLine {
+		IrqLine { sources: self.sources | other.sources }
+	}
+}
+
+impl fmt::Display for IrqLine {
+	fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+		if !self.is_asserted() {
+			return write!(f, "none");
+		}
+		let mut first = true;
+		for source in IrqSource::ALL.iter().filter(|source| self.holds(**source)) {
+			if !first {
+				write!(f, ", ")?;
+			}
+			write!(f, "{}", source.name())?;
+			first = false;
+		}
+		Ok(())
+	}
+}
+
+#[cfg(all(test, feature = "std"))]
+mod tests {
+	use super::*;
+
+	#[test]
+	fn irq_line_test() {
+		let mut line = IrqLine::default();
+		assert!(!line.is_asserted());
+		assert_eq!(line.to_string(), "none");
+
+		line.assert(IrqSource::FRAME_COUNTER);
+		line.assert(IrqSource::DMC);
+		assert_eq!(line.bits(), 0b011);
+		assert_eq!(line.to_string(), "frame counter, DMC");
+
+		// Letting go of one keeps the other.
+		line.deassert(IrqSource::FRAME_COUNTER);
+		assert!(line.is_asserted());
+		assert!(line.holds(IrqSource::DMC) && !line.holds(IrqSource::FRAME_COUNTER));
+		line.set(IrqSource::DMC, false);
+		assert!(!line.is_asserted());
+
+		let mut mapper = IrqLine::default();
+		mapper.assert(IrqSource::MAPPER);
+		assert_eq!(line.join(mapper).to_string(), "mapper");
+	}
+}
@@ -0,0 +1,148 @@
+// Instruction journal, for stepping backwards in the debugger (`journal on`, then `rs [N]`). Every instruction (and
+// interrupt) it runs leaves an undo record: the registers before it, and the bytes it wrote, with their old values
+// (see `StepEffects::overwritten`). Stepping back writes the old values back, last write first, and sets the
+// registers. The records are in a ring of `capacity`, the oldest dropped first.
+//
+// Only the CPU and memory go back. The devices (the PPU, the APU, the controllers, the mapper) don't, so an instruction
+// that accessed one can't be stepped back over: its record stays, and stepping back stops there. Time doesn't go
+// back either: the PPU stays at the dot it got to.
+
+use std::collections::VecDeque;
+
+use crate::cpu::cpu::CpuState;
+use crate::cpu::effects::{BusAccess, StepEffects};
+use crate::debugger::DebugTarget;
+
+pub const DEFAULT_JOURNAL_CAPACITY: usize = 100_000;
+
+/// How to undo an instruction.
+#[derive(Clone, PartialEq, Eq, Debug)]
+pub struct UndoRecord {
+	pub before: CpuState,
+	/// The addresses written, and the values before, in the order of the writes.
+	pub overwritten: Vec<(u16, u8)>,
+	/// The first access to a device, if there was one: then the instruction can't be undone.
+	pub device_access: Option<BusAccess>,
+}
+
+/// See the top of the file.
+pub struct Journal {
+	records: VecDeque<UndoRecord>,
+	capacity: usize,
+}
+
+impl Journal {
+	pub fn new(capacity: usize) -> Self {
+		assert!(capacity > 0, "The journal needs room for at least 1 instruction");
+		Journal { records: VecDeque::new(), capacity }
+	}
+
+	/// The step just ran, with these effects. `is_device` tells which accesses are to devices, see
+	/// `DebugTarget::is_device`.
+	pub fn record(&mut self, effects: &StepEffects, is_device: impl Fn(&BusAccess) -> bool) {
+		if self.records.len() == self.capacity {
+			self.records.pop_front();
+		}
+		self.records.push_back(UndoRecord {
+			before: effects.before,
+			overwritten: effects.overwritten().collect(),
+			device_access: effects.accesses().iter().find(|access| is_device(access)).copied(),
+		});
+	}
+
+	/// The records kept, the ones that accessed a device too.
+	pub fn len(&self) -> usize {
+		self.records.len()
+	}
+
+	pub fn is_empty(&self) -> bool {
+		self.records.is_empty()
+	}
+
+	/// Forget everything: after the state was changed from outside (`poke`, `set`), stepping back over it would
+	/// mix old and new.
+	pub fn clear(&mut self) {
+		self.records.clear();
+	}
+
+	/// Undo the last instruction on `target`. Err when there is none, or it accessed a device (its record stays).
+	pub fn step_back<T: DebugTarget + ?Sized>(&mut self, target: &mut T) -> Result<(), String> {
+		let record = self.records.back().ok_or("Nothing to step back to: the journal is empty")?;
+		if let Some(access) = record.device_access {
+			return Err(format!("The instruction at ${:04X} accessed ${:04X}, and devices can't step back", record.before.pc, access.addr));
+		}
+		let record = self.records.pop_back().unwrap();
+		for &(addr, old) in record.overwritten.iter().rev() {
+			target.poke(addr, old);
+		}
+		target.set_cpu_state(&record.before);
+		Ok(())
+	}
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+	use crate::bus::FlatBus;
+	use crate::cartridge::{test_rom, Cartridge};
+	use crate::cpu::cpu::CPU;
+	use crate::emulator::Emulator;
+	use crate::program_loader::load_program_tolower;
+
+	fn dump<T: DebugTarget>(target: &T) -> Vec<u8> {
+		(0..=0xFFFF).map(|addr| target.peek(addr)).collect()
+	}
+
+	#[test]
+	fn step_back_test() {
+		let mut memory = [0; 65_536];
+		load_program_tolower(&mut memory);
+		let mut cpu = CPU::new(FlatBus::new(&memory));
+		cpu.reset();
+		let initial = (cpu.state(), dump(&cpu));
+
+		// Through the first bytes the program writes.
+		let mut journal = Journal::new(DEFAULT_JOURNAL_CAPACITY);
+		for _ in 0..100 {
+			let effects = cpu.step_with_effects().unwrap();
+			journal.record(&effects, |_| false);
+		}
+		assert_ne!(dump(&cpu), initial.1);
+		for _ in 0..100 {
+			journal.step_back(&mut cpu).unwrap();
+		}
+		assert_eq!((cpu.state(), dump(&cpu)), initial);
+		assert!(journal.step_back(&mut cpu).is_err());
+
+		// The oldest records go first.
+		let mut journal = Journal::new(3);
+		for _ in 0..5 {
+			journal.record(&cpu.step_with_effects().unwrap(), |_| false);
+		}
+		assert_eq!(journal.len(), 3);
+	}
+
+	#[test]
+	fn device_test() {
+		/*
+		INC $10
+		LDA $2002
+		INC $10
+		*/
+		let mut emulator = Emulator::new(Cartridge::from_ines(&test_rom::nrom("E6 10 AD 02 20 E6 10")).unwrap());
+		let mut journal = Journal::new(DEFAULT_JOURNAL_CAPACITY);
+		for _ in 0..3 {
+			let effects = emulator.step_with_effects().unwrap();
+			journal.record(&effects, |access| emulator.is_device(access));
+		}
+		assert_eq!(emulator.peek(0x10), 2);
+
+		journal.step_back(&mut emulator).unwrap();
+		assert_eq!(emulator.peek(0x10), 1);
+		assert_eq!(emulator.cpu_state().pc, 0x8005);
+		let err = journal.step_back(&mut emulator).unwrap_err();
+		assert_eq!(err, "The instruction at $8002 accessed $2002, and devices can't step back");
+		assert_eq!(emulator.cpu_state().pc, 0x8005);
+		assert_eq!(journal.len(), 2);
+	}
+}
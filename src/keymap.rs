@@ -0,0 +1,147 @@
+// Keyboard to controller mapping.
+//
+// The config file has a `Button = Key` line for every button to remap, with SDL key names. Buttons of player 2
+// start with `P2`, for example:
+//
+// # Player 1
+// A = X
+// B = Z
+// Start = Return
+// Select = Right Shift
+// # Player 2
+// P2 A = G
+// P2 Up = W
+//
+// Buttons that are not in the file keep the default key.
+
+use rust_nes_emulator::controller::{Button, ButtonState};
+
+/// Player 1: arrows = d-pad, Z/X = B/A, Enter = Start, Right Shift = Select.
+/// Player 2: WASD = d-pad, F/G = B/A, E = Start, Q = Select.
+const DEFAULT_KEYS: [[(Button, &str); 8]; 2] = [
+	[
+		(Button::A, "X"),
+		(Button::B, "Z"),
+		(Button::Select, "Right Shift"),
+		(Button::Start, "Return"),
+		(Button::Up, "Up"),
+		(Button::Down, "Down"),
+		(Button::Left, "Left"),
+		(Button::Right, "Right"),
+	],
+	[
+		(Button::A, "G"),
+		(Button::B, "F"),
+		(Button::Select, "Q"),
+		(Button::Start, "E"),
+		(Button::Up, "W"),
+		(Button::Down, "S"),
+		(Button::Left, "A"),
+		(Button::Right, "D"),
+	],
+];
+
+/// Players, one per controller port.
+pub const PLAYERS: usize = 2;
+
+#[derive(Clone, PartialEq, Debug)]
+pub struct KeyMap {
+	/// Key name for every button, of every player.
+	keys: [Vec<(Button, String)>; PLAYERS],
+}
+
+impl Default for KeyMap {
+	fn default() -> Self {
+		KeyMap { keys: DEFAULT_KEYS.map(|keys| keys.iter().map(|(button, key)| (*button, key.to_string())).collect()) }
+	}
+}
+
+impl KeyMap {
+	/// Parse a config file. Empty lines and lines starting with '#' are ignored.
+	pub fn parse(config: &str) -> Result<Self, String> {
+		let mut keymap = KeyMap::default();
+		for (number, line) in config.lines().enumerate() {
+			let line = line.trim();
+			if line.is_empty() || line.starts_with('#') {
+				continue;
+			}
+
+			let (button, key) = line.split_once('=').ok_or_else(|| format!("Line {}: expected 'Button = Key', got '{}'", number + 1, line))?;
+			let (player, button) = match button.trim().strip_prefix("P2") {
+				Some(button) => (1, button.trim()),
+				None => (0, button.trim()),
+			};
+			let button = parse_button(button).ok_or_else(|| format!("Line {}: unknown button '{}'", number + 1, button))?;
+			keymap.set(player, button, key.trim());
+		}
+		Ok(keymap)
+	}
+
+	/// Player 0 is controller 1, player 1 is controller 2.
+	pub fn set(&mut self, player: usize, button: Button, key: &str) {
+		for (mapped_button, mapped_key) in self.keys[player].iter_mut() {
+			if *mapped_button == button {
+				*mapped_key = key.to_string();
+			}
+		}
+	}
+
+	pub fn key(&self, player: usize, button: Button) -> &str {
+		self.keys[player].iter().find(|(mapped_button, _)| *mapped_button == button).map(|(_, key)| key.as_str()).unwrap()
+	}
+
+	/// Buttons state of both controllers from the keyboard state. `is_pressed` gets a key name.
+	/// Opposing directions are filtered, see `ButtonState::without_opposing_directions`.
+	pub fn buttons<F: Fn(&str) -> bool>(&self, is_pressed: F) -> [ButtonState; PLAYERS] {
+		self.keys.each_ref().map(|keys| {
+			let mut buttons = ButtonState::default();
+			for (button, key) in keys {
+				buttons.set(*button, is_pressed(key));
+			}
+			buttons.without_opposing_directions()
+		})
+	}
+}
+
+fn parse_button(name: &str) -> Option<Button> {
+	Button::ALL.iter().copied().find(|button| format!("{:?}", button).eq_ignore_ascii_case(name))
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+
+	#[test]
+	fn parse_test() {
+		let keymap = KeyMap::parse("# Comment\n\na = K\n  Start=Space \nP2 Start = Keypad Enter\n").unwrap();
+		assert_eq!(keymap.key(0, Button::A), "K");
+		assert_eq!(keymap.key(0, Button::Start), "Space");
+		assert_eq!(keymap.key(1, Button::Start), "Keypad Enter");
+		// Not in the file, default.
+		assert_eq!(keymap.key(0, Button::B), "Z");
+		assert_eq!(keymap.key(1, Button::A), "G");
+
+		assert!(KeyMap::parse("Turbo = T").is_err());
+		assert!(KeyMap::parse("P3 A = T").is_err());
+		assert!(KeyMap::parse("A K").is_err());
+	}
+
+	#[test]
+	fn buttons_test() {
+		let keymap = KeyMap::default();
+		let [buttons, buttons2] = keymap.buttons(|key| ["X", "Return", "Left", "Right", "W", "F"].contains(&key));
+
+		assert!(buttons.pressed(Button::A));
+		assert!(buttons.pressed(Button::Start));
+		assert!(!buttons.pressed(Button::B));
+		// Left and Right together are filtered.
+		assert!(!buttons.pressed(Button::Left));
+		assert!(!buttons.pressed(Button::Right));
+
+		// Player 2 has its own keys.
+		assert!(buttons2.pressed(Button::Up));
+		assert!(buttons2.pressed(Button::B));
+		assert!(!buttons2.pressed(Button::A));
+		assert!(!buttons.pressed(Button::Up));
+	}
+}
@@ -0,0 +1,143 @@
+// NES emulator library: the console (CPU, PPU, APU, cartridge, controllers), and the tools around it (test harness,
+// save states, rewind, movies, debugger, trace, benchmark). The binary in main.rs is a command line on top of it.
+//
+// The common types are re-exported here, so most users only need `use rust_nes_emulator::{Cartridge, Emulator};`.
+//
+// Without the `std` feature (on by default), the crate is `no_std` and has only the CPU core: the `cpu` module (minus
+// the disassembler) and the `Bus` trait. The CPU doesn't allocate, so it runs on microcontrollers, on a bus of your own.
+//
+// The browser (wasm32-unknown-unknown) has no files, clock or threads, so the modules that need them (frame pacer,
+// state slots, movies, benchmark) are left out there. The `wasm` feature adds the JavaScript API (wasm.rs).
+#![cfg_attr(not(feature = "std"), no_std)]
+
+// Instructions and addressing modes are named like in 6502 assembly, so they are all uppercase.
+#![allow(clippy::upper_case_acronyms)]
+#![allow(clippy::module_inception)]
+#![allow(clippy::bool_assert_comparison)]
+pub mod cpu;
+pub mod bus;
+#[cfg(feature = "std")]
+pub mod error;
+pub mod irq;
+pub mod log_target;
+#[cfg(feature = "std")]
+pub mod nes_bus;
+#[cfg(feature = "std")]
+pub mod memory;
+#[cfg(feature = "std")]
+pub mod program_loader;
+#[cfg(feature = "std")]
+pub mod easy6502;
+#[cfg(feature = "std")]
+pub mod ppu;
+#[cfg(feature = "std")]
+pub mod apu;
+#[cfg(feature = "std")]
+pub mod wav;
+#[cfg(feature = "std")]
+pub mod png;
+#[cfg(feature = "std")]
+pub mod frame_diff;
+#[cfg(feature = "std")]
+pub mod cartridge;
+#[cfg(feature = "std")]
+pub mod mapper;
+#[cfg(feature = "std")]
+pub mod mmc1;
+#[cfg(feature = "std")]
+pub mod mmc3;
+#[cfg(feature = "std")]
+pub mod mmc5;
+#[cfg(feature = "std")]
+pub mod vrc6;
+#[cfg(feature = "std")]
+pub mod fds;
+#[cfg(feature = "std")]
+pub mod romdb;
+#[cfg(feature = "std")]
+pub mod emulator;
+#[cfg(feature = "std")]
+pub mod controller;
+#[cfg(feature = "std")]
+pub mod zapper;
+#[cfg(feature = "std")]
+pub mod harness;
+#[cfg(feature = "std")]
+pub mod machine;
+#[cfg(feature = "std")]
+pub mod hooks;
+#[cfg(all(feature = "std", not(target_arch = "wasm32")))]
+pub mod frame_pacer;
+#[cfg(feature = "std")]
+pub mod region;
+#[cfg(feature = "std")]
+pub mod ram_init;
+#[cfg(feature = "std")]
+pub mod save_state;
+#[cfg(all(feature = "std", not(target_arch = "wasm32")))]
+pub mod state_slots;
+#[cfg(all(feature = "std", not(target_arch = "wasm32")))]
+pub mod rom_watch;
+#[cfg(feature = "std")]
+pub mod rewind;
+#[cfg(feature = "std")]
+mod hash;
+#[cfg(feature = "std")]
+mod inflate;
+// For movies and serde.
+#[cfg(any(all(feature = "std", not(target_arch = "wasm32")), feature = "serde"))]
+mod base64;
+#[cfg(feature = "serde")]
+mod serde_bytes_base64;
+#[cfg(all(feature = "std", not(target_arch = "wasm32")))]
+pub mod movie;
+#[cfg(feature = "std")]
+pub mod debugger;
+#[cfg(feature = "std")]
+pub mod journal;
+#[cfg(feature = "std")]
+pub mod expression;
+#[cfg(feature = "std")]
+pub mod watch_list;
+#[cfg(feature = "std")]
+pub mod trace;
+#[cfg(feature = "std")]
+pub mod profile;
+#[cfg(feature = "std")]
+pub mod access_trace;
+#[cfg(feature = "std")]
+pub mod symbols;
+#[cfg(all(feature = "std", not(target_arch = "wasm32")))]
+pub mod bench;
+#[cfg(all(feature = "std", not(target_arch = "wasm32")))]
+pub mod batch;
+#[cfg(feature = "wasm")]
+pub mod wasm;
+#[cfg(feature = "ffi")]
+pub mod ffi;
+
+pub use bus::Bus;
+#[cfg(feature = "std")]
+pub use bus::FlatBus;
+#[cfg(feature = "std")]
+pub use cartridge::Cartridge;
+#[cfg(feature = "std")]
+pub use controller::{Button, ButtonState};
+pub use cpu::cpu::{CpuError, CpuState, RunEnd, CPU};
+pub use cpu::effects::{AccessKind, BusAccess, StepEffects};
+pub use cpu::decoder::{decode_opcode, AddressingMode, Instructions};
+pub use cpu::status::{Flag, StatusFlags};
+pub use cpu::stuck::{StuckDetection, StuckDetector};
+pub use irq::{IrqLine, IrqSource};
+#[cfg(feature = "std")]
+pub use emulator::{BudgetResult, Emulator};
+#[cfg(feature = "std")]
+pub use error::NesError;
+#[cfg(feature = "std")]
+pub use nes_bus::NesBus;
+#[cfg(feature = "std")]
+pub use ppu::framebuffer::Framebuffer;
+#[cfg(feature = "std")]
+pub use ram_init::RamInitPattern;
+#[cfg(feature = "std")]
+pub use region::Region;
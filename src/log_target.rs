@@ -0,0 +1,20 @@
+// Targets of the log messages of the library (the `target:` of the log macros), one per part of the console, so they
+// can be turned on one at a time: `--log cpu=debug,ppu=warn` on the command line, or the filter of any logger.
+//
+// The library only logs, it never installs a logger (no `init`): that's for the application. Without one, the
+// messages go nowhere, and cost nothing.
+
+/// Instructions, the stack, interrupts, and the trace.
+pub const CPU: &str = "cpu";
+/// The memory map: internal RAM, open bus, ROM writes, access traces.
+pub const BUS: &str = "bus";
+pub const PPU: &str = "ppu";
+pub const APU: &str = "apu";
+/// Cartridges: headers, the ROM database, and banking.
+pub const MAPPER: &str = "mapper";
+/// Controllers and movies.
+pub const INPUT: &str = "input";
+/// The whole console: power on, save states, and the C API.
+pub const EMULATOR: &str = "emulator";
+
+pub const ALL: [&str; 7] = [CPU, BUS, PPU, APU, MAPPER, INPUT, EMULATOR];
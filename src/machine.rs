@@ -0,0 +1,105 @@
+// The 6502 without the console: the demos and raw binaries on flat memory (`FlatBus`), and easy6502 programs
+// (`Easy6502Bus`). `Emulator` is the console; these machines are just a CPU and its memory, built and reset here, so
+// the frontends, the debugger and the benchmark all start them the same way.
+//
+// They have no PPU, so nothing ends a frame and nothing interrupts: `run_flat` runs them until a BRK (the end of
+// the program, or empty memory), a condition, or a cycle budget.
+
+use std::fmt;
+
+use log::warn;
+
+use crate::bus::{Bus, FlatBus};
+use crate::cpu::cpu::CPU;
+use crate::easy6502::Easy6502Bus;
+use crate::harness::{Condition, Verdict};
+use crate::log_target::EMULATOR;
+use crate::trace::Tracer;
+
+/// A CPU on `image` (program, data, vectors), starting from the reset vector.
+pub fn flat(image: &[u8; 65_536]) -> CPU<FlatBus> {
+	started(FlatBus::new(image))
+}
+
+/// A CPU on the easy6502 machine, with `image` in memory, and random numbers from `seed`. Starts from the reset vector.
+pub fn easy6502(image: &[u8; 65_536], seed: u64) -> CPU<Easy6502Bus> {
+	started(Easy6502Bus::new(image, seed))
+}
+
+fn started<B: Bus>(bus: B) -> CPU<B> {
+	let mut cpu = CPU::new(bus);
+	cpu.reset();
+	cpu
+}
+
+/// How `run_flat` stopped.
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub enum FlatStop {
+	/// At a BRK, the end of the program (or empty memory).
+	Brk(u16),
+	Condition(Condition, Verdict),
+	BudgetExhausted,
+}
+
+impl fmt::Display for FlatStop {
+	fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+		match self {
+			FlatStop::Brk(pc) => write!(f, "Got to BRK at ${:04X}", pc),
+			FlatStop::Condition(condition, verdict) => write!(f, "{:?}: {}", verdict, condition),
+			FlatStop::BudgetExhausted => write!(f, "Budget exhausted"),
+		}
+	}
+}
+
+/// Run a program without the console, until it gets to a BRK, a condition is met (checked before every instruction,
+/// like the harness), or the CPU gets to cycle `max_cycles`. A trace that fails to write is stopped.
+pub fn run_flat<B: Bus>(cpu: &mut CPU<B>, max_cycles: u64, conditions: &[(Condition, Verdict)], trace: &mut Option<Tracer>) -> FlatStop {
+	while cpu.cycles() < max_cycles {
+		let pc = cpu.registers().PC;
+		if let Some(&(condition, verdict)) = conditions.iter().find(|(condition, _)| condition.met_on(pc, |addr| cpu.bus().peek(addr))) {
+			return FlatStop::Condition(condition, verdict);
+		}
+		if cpu.bus().peek(pc) == 0x00 {
+			return FlatStop::Brk(pc);
+		}
+
+		if let Some(tracer) = trace.as_mut() {
+			let bus = cpu.bus();
+			if tracer.instruction(&cpu.state(), None, |addr| bus.peek(addr)).is_err() {
+				warn!(target: EMULATOR, "Failed to write trace, stopping it");
+				*trace = None;
+			}
+		}
+		cpu.clock_tick();
+		if let Some(tracer) = trace.as_mut() {
+			if tracer.executed(cpu.penalty_cycles()).is_err() {
+				warn!(target: EMULATOR, "Failed to write trace, stopping it");
+				*trace = None;
+			}
+		}
+	}
+	FlatStop::BudgetExhausted
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+	use crate::program_loader::{load_program_tolower, TOLOWER_OUTPUT};
+
+	#[test]
+	fn run_flat_test() {
+		let mut memory = [0; 65_536];
+		load_program_tolower(&mut memory);
+		let mut cpu = flat(&memory);
+		assert_eq!(cpu.state().pc, 0x0600);
+
+		let stop = run_flat(&mut cpu, u64::MAX, &[(Condition::PcEquals(0x0615), Verdict::Pass)], &mut None);
+		assert_eq!(stop, FlatStop::Condition(Condition::PcEquals(0x0615), Verdict::Pass));
+		let budget = cpu.cycles() + 10;
+		assert_eq!(run_flat(&mut cpu, budget, &[], &mut None), FlatStop::BudgetExhausted);
+		let stop = run_flat(&mut cpu, u64::MAX, &[], &mut None);
+		assert_eq!(stop, FlatStop::Brk(0x061C));
+		assert_eq!(stop.to_string(), "Got to BRK at $061C");
+		assert_eq!(cpu.bus().peek(TOLOWER_OUTPUT), b'h');
+	}
+}
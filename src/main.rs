@@ -1,35 +1,734 @@
-//#![feature(mixed_integer_ops)]  // stable since 1.67.0-nightly
-mod cpu;
-mod bus;
-mod memory;
-mod program_loader;
-mod ppu;
-
-use log::info;
+// Command line frontend. Everything else is in the library (lib.rs).
+mod cli;
+// Only the window has keys.
+#[cfg_attr(not(feature = "sdl"), allow(dead_code))]
+mod keymap;
+// Only the window pauses.
+#[cfg_attr(not(feature = "sdl"), allow(dead_code))]
+mod pause;
+#[cfg(feature = "sdl")]
+mod sdl_frontend;
+#[cfg(not(feature = "sdl"))]
+mod terminal_frontend;
+
+use std::cell::RefCell;
+use std::fs::File;
+use std::io::{self, BufWriter, Write};
+use std::path::{Path, PathBuf};
+use std::process;
+use std::rc::Rc;
+use std::time::{SystemTime, UNIX_EPOCH};
+
+use log::{error, info, warn};
 use simple_logger::SimpleLogger;
-use bus::Bus;
-use memory::ROM;
-use cpu::cpu::CPU;
-use program_loader::*;
+use rust_nes_emulator::apu::apu::Channel;
+use rust_nes_emulator::apu::sample_buffer::SampleBuffer;
+use rust_nes_emulator::batch;
+use rust_nes_emulator::bench::{self, BenchBudget, FlatBench};
+use rust_nes_emulator::debugger::{DebugTarget, Debugger};
+use rust_nes_emulator::easy6502::{Easy6502Bus, CYCLES_PER_FRAME};
+use rust_nes_emulator::frame_diff::{FrameDiff, Reference};
+use rust_nes_emulator::harness::{hex_dump, BlarggStop, Harness, StopReason, Verdict};
+use rust_nes_emulator::machine::{self, run_flat, FlatStop};
+use rust_nes_emulator::movie::{Movie, MovieMode};
+use rust_nes_emulator::ppu::colors::MasterPalette;
+use rust_nes_emulator::program_loader::*;
+use rust_nes_emulator::profile::Profile;
+use rust_nes_emulator::rom_watch::{CartridgeLoader, RomWatcher, WatchedEmulator};
+use rust_nes_emulator::romdb::RomDb;
+use rust_nes_emulator::state_slots::StateSlots;
+use rust_nes_emulator::symbols::SymbolTable;
+use rust_nes_emulator::trace::Tracer;
+use rust_nes_emulator::wav::WavRecorder;
+use rust_nes_emulator::{Bus, Cartridge, Emulator, NesError, Region, StuckDetection, CPU};
+
+use cli::{CliError, Demo, Machine, Options, Program};
+
+/// Frames to run in headless mode, if not set with --frames.
+const DEFAULT_HEADLESS_FRAMES: u32 = 60;
+/// Frames to run a blargg test ROM, if not set with --frames. Most are done in a few seconds.
+const DEFAULT_BLARGG_FRAMES: u32 = 3600;
+/// Exit code when the harness ran out of frames/cycles before any condition was met.
+const EXIT_BUDGET_EXHAUSTED: i32 = 3;
+
 
 fn main() {
-	SimpleLogger::new().init().unwrap();
+	let options = match cli::parse_args(std::env::args().skip(1)) {
+		Ok(options) => options,
+		Err(CliError::Help) => {
+			println!("{}", cli::USAGE);
+			return;
+		}
+		Err(CliError::Invalid(message)) => {
+			eprintln!("error: {}\n\n{}", message, cli::USAGE);
+			process::exit(2);
+		}
+	};
+
+	if let Err(message) = init_logger(&options) {
+		eprintln!("warning: {}", message);
+	}
+
+	match run(&options) {
+		Ok(0) => info!("Finished running NES"),
+		Ok(code) => process::exit(code),
+		Err(message) => {
+			eprintln!("error: {}", message);
+			process::exit(1);
+		}
+	}
+}
+
+/// Install the logger, with the levels of the options. The library never does it (see log_target.rs), only
+/// applications. There can only be one logger: if one is there already, it stays, and this returns an error.
+fn init_logger(options: &Options) -> Result<(), String> {
+	let mut logger = SimpleLogger::new().with_level(options.log_level);
+	for (target, level) in &options.log_targets {
+		logger = logger.with_module_level(target, *level);
+	}
+	logger.init().map_err(|err| format!("Can't install the logger: {}", err))
+}
+
+/// Returns the exit code.
+fn run(options: &Options) -> Result<i32, String> {
+	if let Some(budget) = options.bench {
+		run_bench(budget, options)?;
+		return Ok(0);
+	}
+
+	let trace = match &options.trace {
+		Some(path) => {
+			let file = File::create(path).map_err(|err| format!("Can't create trace file {}: {}", path.display(), err))?;
+			let tracer = Tracer::new(Box::new(file), options.trace_filter.clone()).with_symbols(load_symbols(options)?);
+			Some(if options.trace_penalties { tracer.with_penalties() } else { tracer })
+		}
+		None => None,
+	};
+
+	match &options.program {
+		Program::Demo(demo) if options.machine == Machine::Easy6502 => run_easy6502(&demo_memory(*demo), options, trace),
+		Program::Demo(demo) => {
+			run_demo(*demo, options, trace)?;
+			Ok(0)
+		}
+		Program::Batch(dir) => run_batch(dir, options),
+		Program::Rom(path) => {
+			let bytes = std::fs::read(path).map_err(|err| NesError::io(path, err))?;
+			if !is_raw(&bytes, options) {
+				return run_rom(&bytes, options, trace);
+			}
+			let image = raw_image(&bytes, options)?;
+			match options.machine {
+				Machine::Easy6502 => run_easy6502(&image, options, trace),
+				Machine::Flat => run_raw(&image, options, trace),
+			}
+		}
+	}
+}
+
+/// Run as fast as possible, without tracing, and print the speed.
+fn run_bench(budget: BenchBudget, options: &Options) -> Result<(), String> {
+	if options.trace.is_some() {
+		warn!("Tracing is disabled in benchmark mode");
+	}
+	// Debug logs are in the hot path of the CPU.
+	log::set_max_level(log::max_level().min(log::LevelFilter::Info));
+
+	let result = match &options.program {
+		Program::Demo(demo) => {
+			let mut target = FlatBench::new(machine::flat(&demo_memory(*demo)));
+			bench::run(&mut target, &format!("{:?}", demo).to_lowercase(), budget)
+		}
+		Program::Rom(path) => {
+			let bytes = std::fs::read(path).map_err(|err| NesError::io(path, err))?;
+			let name = path.file_name().map_or(String::new(), |name| name.to_string_lossy().into_owned());
+			if is_raw(&bytes, options) {
+				let mut target = FlatBench::new(machine::flat(&raw_image(&bytes, options)?));
+				bench::run(&mut target, &name, budget)
+			} else {
+				bench::run(&mut load_emulator(&bytes, options)?, &name, budget)
+			}
+		}
+		Program::Batch(_) => return Err("--bench runs a single ROM".to_string()),
+	};
+	println!("{}", result);
+	Ok(())
+}
+
+/// Makes cartridges of iNES files, with the ROM database and the MMC3 IRQ variant of the options, and of FDS disk
+/// images, with the BIOS of the options. The reloads of --watch use it too.
+fn cartridge_loader(options: &Options) -> Result<CartridgeLoader, String> {
+	let db = match &options.romdb {
+		Some(path) => {
+			let mut db = RomDb::builtin().clone();
+			db.extend(RomDb::load(path)?);
+			Some(db)
+		}
+		None => None,
+	};
+	let fds_bios = match &options.fds_bios {
+		Some(path) => Some(std::fs::read(path).map_err(|err| String::from(NesError::io(path, err)))?),
+		None => None,
+	};
+	let mmc3_irq = options.mmc3_irq;
+	// The ROM database logs what it fixed.
+	Ok(Box::new(move |bytes| {
+		if Cartridge::is_fds(bytes) {
+			let bios = fds_bios.as_ref().ok_or_else(|| NesError::Disk("An FDS disk image needs the BIOS of the RAM adapter: --fds-bios <FILE> (disksys.rom)".to_string()))?;
+			return Cartridge::from_fds(bios, bytes);
+		}
+		let mut cartridge = match &db {
+			Some(db) => Cartridge::from_ines_with_db(bytes, db),
+			None => Cartridge::from_ines(bytes),
+		}?;
+		if let Some(variant) = mmc3_irq {
+			cartridge.set_mmc3_irq(variant);
+		}
+		Ok(cartridge)
+	}))
+}
+
+/// Insert the cartridge in a new console, with the region from the options or the cartridge.
+fn load_emulator(bytes: &[u8], options: &Options) -> Result<Emulator, String> {
+	let cartridge = cartridge_loader(options)?(bytes).map_err(|err| format!("{} (to run it as a raw 6502 binary, use --raw)", err))?;
+	let mut emulator = new_emulator(cartridge, options);
+	if let Some(name) = &options.palette {
+		emulator.set_master_palette(MasterPalette::preset_or_file(name)?);
+	}
+	Ok(emulator)
+}
+
+/// The console of `load_emulator`, with the cartridge in.
+fn new_emulator(cartridge: Cartridge, options: &Options) -> Emulator {
+	let region = options.region.unwrap_or(cartridge.region());
+	info!("Region: {}", region);
+	// A random pattern repeats with its seed.
+	info!("RAM at power on: {}", options.ram_init);
+	let mut emulator = Emulator::with_region(cartridge, region).with_ram_init(options.ram_init);
+	emulator.set_cycle_accurate(options.cycle_accurate);
+	if options.overclock > 0 {
+		info!("Overclock: {} extra scanlines", options.overclock);
+		emulator.set_overclock(options.overclock);
+	}
+	if let Some(percent) = options.expansion_volume {
+		emulator.set_expansion_volume(percent as f32 / 100.0);
+	}
+	if options.warn_ram || options.strict_ram {
+		emulator.set_strict_ram(true);
+	}
+	emulator
+}
+
+/// Run every ROM of `dir` headless, and write the report, see batch.rs. It's a report, not a test: the exit code is 0
+/// whatever the ROMs did.
+fn run_batch(dir: &Path, options: &Options) -> Result<i32, String> {
+	let load = cartridge_loader(options)?;
+	let frames = options.frames.unwrap_or(DEFAULT_HEADLESS_FRAMES) as u64;
+	let rows = batch::run_dir(dir, frames, &|bytes| Ok(new_emulator(load(bytes)?, options)), |row| {
+		info!("{}: {} after {} frames", row.rom, row.result.name(), row.frames);
+	})?;
+	let ran = rows.iter().filter(|row| row.result == batch::BatchResult::Ran).count();
+	info!("{} of {} ROMs ran all {} frames", ran, rows.len(), frames);
+
+	match &options.report {
+		Some(path) => {
+			let mut file = BufWriter::new(File::create(path).map_err(|err| format!("Can't create {}: {}", path.display(), err))?);
+			let json = path.extension().is_some_and(|extension| extension.eq_ignore_ascii_case("json"));
+			let written = if json { batch::write_json(&rows, &mut file) } else { batch::write_csv(&rows, &mut file) };
+			written.and_then(|()| file.flush()).map_err(|err| format!("Can't write {}: {}", path.display(), err))?;
+			info!("Wrote the report to {}", path.display());
+		}
+		None => batch::write_csv(&rows, &mut io::stdout().lock()).map_err(|err| err.to_string())?,
+	}
+	Ok(0)
+}
+
+fn run_rom(bytes: &[u8], options: &Options, trace: Option<Tracer>) -> Result<i32, String> {
+	let mut emulator = load_emulator(bytes, options)?;
+	// Before a loaded slot, which has its own PRG RAM.
+	let sav = sav_path(&emulator, options);
+	if let Some(path) = &sav {
+		load_sav(&mut emulator, path);
+	}
+	if let Some(mut trace) = trace {
+		// The banks at power on, to find the code of the first lines in the ROM.
+		trace.write_header(&emulator.bus().cartridge().debug_state().to_string())
+			.map_err(|err| format!("Can't write the trace: {}", err))?;
+		emulator.set_trace(trace);
+	}
+	if options.profile {
+		emulator.set_profile(Profile::new());
+	}
+
+	if let Some(slot) = options.load_slot {
+		let mut slots = StateSlots::new(&options.state_dir, emulator.rom_hash());
+		slots.select(slot);
+		let path = slots.load(&mut emulator)?;
+		info!("Loaded slot {} from {}", slot, path.display());
+	}
+	let movie = start_movie(&mut emulator, options)?;
+
+	let watcher = match (&options.program, options.watch) {
+		(Program::Rom(path), true) => Some(RomWatcher::new(path, cartridge_loader(options)?)),
+		_ => None,
+	};
+
+	if options.debug {
+		match watcher {
+			Some(watcher) => run_debugger(&mut WatchedEmulator { emulator: &mut emulator, watcher }, options)?,
+			None => run_debugger(&mut emulator, options)?,
+		}
+		return Ok(0);
+	}
+	if let Some(frames) = options.hash_after {
+		return Ok(run_hash(emulator, frames, movie));
+	}
+	if let (Some(frames), Some(path)) = (options.screenshot_after, &options.screenshot_out) {
+		return run_screenshot(emulator, frames, path, options.crop_overscan, movie);
+	}
+
+	if !options.headless {
+		#[cfg(feature = "sdl")]
+		{
+			let result = sdl_frontend::run(&mut emulator, options, &load_keymap(options)?, movie, watcher);
+			// Even when the window failed: the game may have saved before.
+			let saved = sav.as_ref().map_or(Ok(()), |path| write_sav(&emulator, path));
+			return result.and(saved).map(|()| 0);
+		}
+
+		#[cfg(not(feature = "sdl"))]
+		warn!("Built without the 'sdl' feature, so there is no window. Running headless");
+	}
+
+	run_headless(emulator, options, movie)
+}
+
+/// Where the battery backed PRG RAM of the game is kept between runs: `<ROM>.sav`, next to the ROM. Only in the
+/// window, without a movie (which starts from a clean power on), and for a cartridge with a battery.
+fn sav_path(emulator: &Emulator, options: &Options) -> Option<PathBuf> {
+	match &options.program {
+		Program::Rom(path) if cfg!(feature = "sdl") && !options.headless && !options.debug && options.play.is_none() && options.record.is_none() => {
+			emulator.save_ram().map(|_| path.with_extension("sav"))
+		}
+		_ => None,
+	}
+}
+
+/// Put back the PRG RAM of the last run, when there's a .sav file. One that can't be read, or of another size, is only
+/// a warning: the game starts with fresh PRG RAM, and the file is written over on exit.
+fn load_sav(emulator: &mut Emulator, path: &Path) {
+	if !path.exists() {
+		return;
+	}
+	let loaded = std::fs::read(path).map_err(|err| NesError::io(path, err)).and_then(|data| emulator.load_ram(&data));
+	match loaded {
+		Ok(()) => info!("Loaded the battery backed RAM from {}", path.display()),
+		Err(err) => warn!("Can't load {}: {}. Starting with fresh PRG RAM", path.display(), String::from(err)),
+	}
+}
+
+/// Write the battery backed PRG RAM, through a temporary file like the save states, so a failed write keeps the last
+/// save.
+#[cfg(feature = "sdl")]
+fn write_sav(emulator: &Emulator, path: &Path) -> Result<(), String> {
+	let Some(ram) = emulator.save_ram() else {
+		return Ok(());
+	};
+	let temp_path = path.with_extension("sav.tmp");
+	std::fs::write(&temp_path, ram).map_err(|err| format!("Can't write {}: {}", temp_path.display(), err))?;
+	std::fs::rename(&temp_path, path).map_err(|err| format!("Can't write {}: {}", path.display(), err))?;
+	info!("Saved the battery backed RAM to {}", path.display());
+	Ok(())
+}
+
+/// Record or play a movie, as set in the options. A played movie takes the emulator to its start.
+fn start_movie(emulator: &mut Emulator, options: &Options) -> Result<MovieMode, String> {
+	if let Some(path) = &options.play {
+		let movie = Movie::load(path)?;
+		movie.start(emulator)?;
+		info!("Playing {} frames from {}", movie.inputs.len(), path.display());
+		return Ok(MovieMode::Play(movie, 0));
+	}
+	if let Some(path) = &options.record {
+		let rom_filename = match &options.program {
+			Program::Rom(rom) => rom.file_name().map_or(String::new(), |name| name.to_string_lossy().into_owned()),
+			Program::Demo(_) | Program::Batch(_) => String::new(),
+		};
+		// A loaded slot is the start of the movie.
+		return Ok(MovieMode::Record(Movie::new(emulator, &rom_filename, options.load_slot.is_some()), path.clone()));
+	}
+	Ok(MovieMode::Off)
+}
+
+/// Run with the test harness, print the final state, and return the exit code.
+fn run_headless(emulator: Emulator, options: &Options, movie: MovieMode) -> Result<i32, String> {
+	// Before the run: a reference that can't be read is a mistake to tell right away.
+	let reference = options.compare.as_deref().map(Reference::load).transpose()?;
+	// Test ROMs end in a loop, and the NMI may keep running: stop when the loop is all that's left.
+	let mut harness = Harness::new(emulator).detect_stuck_loops(StuckDetection::default());
+	// A movie runs to its end, unless --frames says otherwise.
+	let frames = match movie {
+		MovieMode::Play(movie, _) => {
+			let frames = options.frames.map_or(movie.inputs.len() as u64, |frames| frames as u64);
+			harness.set_inputs(movie.inputs);
+			frames
+		}
+		_ if options.blargg => options.frames.unwrap_or(DEFAULT_BLARGG_FRAMES) as u64,
+		_ => options.frames.unwrap_or(DEFAULT_HEADLESS_FRAMES) as u64,
+	};
+	harness = harness.max_frames(frames);
+	if let Some(cycles) = options.cycles {
+		harness = harness.max_cycles(cycles);
+	}
+	for (condition, verdict) in &options.conditions {
+		harness.add_condition(*condition, *verdict);
+	}
+	if options.strict_rom {
+		harness = harness.stop_on_rom_write();
+	}
+	if options.strict_ram {
+		harness = harness.stop_on_uninitialized_read();
+	}
+	if options.strict_stack {
+		harness = harness.stop_on_stack_fault();
+	}
+	let mut recorders = vec![];
+	if let Some(path) = &options.wav_out {
+		let samples = harness.emulator().bus().apu().sample_buffer();
+		let (recording, wav) = record_wav(harness, path, samples)?;
+		harness = recording;
+		recorders.push(wav);
+		if options.wav_channels {
+			harness.emulator_mut().capture_channels(true);
+			for channel in Channel::ALL {
+				let samples = harness.emulator().bus().apu().channel_sample_buffer(channel).unwrap();
+				let (recording, wav) = record_wav(harness, &channel_wav_path(path, channel), samples)?;
+				harness = recording;
+				recorders.push(wav);
+			}
+		}
+	}
+
+	if options.blargg {
+		let code = run_blargg(&mut harness, options);
+		finish_wav(recorders);
+		print_profile(&harness);
+		return Ok(code);
+	}
+
+	let reason = harness.run();
+	finish_wav(recorders);
+	info!("Stopped after {} frames, {} CPU cycles: {}", harness.emulator().frame(), harness.emulator().cycles(), reason);
+	println!("{}", harness.cpu_state());
+	if let Some((start, end)) = options.dump {
+		print!("{}", harness.dump_memory(start, end));
+	}
+	print_profile(&harness);
+
+	// Without conditions, it's just a headless run, and both ways to stop are fine.
+	let code = match reason {
+		StopReason::Condition(_, Verdict::Pass) => 0,
+		StopReason::Condition(_, Verdict::Fail) | StopReason::RomWrite(_) | StopReason::UninitializedRead(_) | StopReason::StackOverflow { .. } | StopReason::StackUnderflow { .. } => 1,
+		_ if options.conditions.is_empty() => 0,
+		StopReason::Jammed | StopReason::StuckLoop { .. } => 1,
+		StopReason::BudgetExhausted => EXIT_BUDGET_EXHAUSTED,
+	};
+	if let (Some(path), Some(reference)) = (&options.compare, &reference) {
+		let diff = FrameDiff::new(harness.emulator().framebuffer(), reference, options.crop_overscan);
+		println!("{} pixels differ from {}", diff.count(), path.display());
+		if options.compare_verbose {
+			print!("{}", diff);
+		}
+		if diff.count() > options.compare_threshold as usize {
+			return Ok(code.max(1));
+		}
+	}
+	Ok(code)
+}
+
+fn print_profile(harness: &Harness) {
+	if let Some(profile) = harness.emulator().profile() {
+		println!("{}", profile);
+	}
+}
+
+type Recorder = Rc<RefCell<Option<WavRecorder<BufWriter<File>>>>>;
+
+/// Write the audio of every frame the harness runs, the `samples` of the APU, to a WAV file at `path`.
+fn record_wav(harness: Harness, path: &Path, samples: SampleBuffer) -> Result<(Harness, Recorder), String> {
+	let file = File::create(path).map_err(|err| format!("Can't create {}: {}", path.display(), err))?;
+	let apu = harness.emulator().bus().apu();
+	let recorder = WavRecorder::new(BufWriter::new(file), samples, apu.sample_rate())
+		.map_err(|err| format!("Can't write {}: {}", path.display(), err))?;
+	info!("Writing the audio to {} ({} Hz)", path.display(), apu.sample_rate());
+	let recorder = Rc::new(RefCell::new(Some(recorder)));
+
+	let capture = recorder.clone();
+	let path = path.to_path_buf();
+	let harness = harness.on_frame(move |_| {
+		let mut recorder = capture.borrow_mut();
+		if let Some(Err(err)) = recorder.as_mut().map(WavRecorder::capture) {
+			error!("Failed to write {}, stopping the audio: {}", path.display(), err);
+			*recorder = None;
+		}
+	});
+	Ok((harness, recorder))
+}
+
+/// Where `--wav-channels` writes `channel`: game-pulse1.wav for game.wav.
+fn channel_wav_path(path: &Path, channel: Channel) -> PathBuf {
+	let stem = path.file_stem().unwrap_or_default().to_string_lossy();
+	path.with_file_name(format!("{}-{}.wav", stem, channel.name()))
+}
+
+/// Write the rest of the audio, after the last frame.
+fn finish_wav(recorders: Vec<Recorder>) {
+	for recorder in recorders {
+		let Some(mut recorder) = recorder.borrow_mut().take() else {
+			continue;
+		};
+		match recorder.capture() {
+			Ok(()) => info!("Wrote {} samples of audio", recorder.samples()),
+			Err(err) => error!("Failed to write the audio: {}", err),
+		}
+	}
+}
+
+/// Run `frames` frames (with the inputs of the movie, if one plays), print the hashes as a `key=value` line, and
+/// return the exit code.
+fn run_hash(emulator: Emulator, frames: u32, movie: MovieMode) -> i32 {
+	let mut harness = Harness::new(emulator).max_frames(frames as u64);
+	if let MovieMode::Play(movie, _) = movie {
+		harness.set_inputs(movie.inputs);
+	}
+	let reason = harness.run();
+	let emulator = harness.emulator();
+	if reason != StopReason::BudgetExhausted {
+		error!("Stopped after {} of {} frames: {}", emulator.frame(), frames, reason);
+		return 1;
+	}
+	println!("frames={} frame_hash={:016x} state_hash={:016x}", frames, emulator.frame_hash(), emulator.state_hash());
+	0
+}
+
+/// Run `frames` frames (with the inputs of the movie, if one plays), and write the screen to `path` as a PNG. Unlike
+/// the harness, a CPU waiting in a loop doesn't stop it: that's how most games wait for the NMI.
+fn run_screenshot(mut emulator: Emulator, frames: u32, path: &Path, crop_overscan: bool, movie: MovieMode) -> Result<i32, String> {
+	let inputs = match movie {
+		MovieMode::Play(movie, _) => movie.inputs,
+		_ => vec![],
+	};
+	for frame in 0..frames as usize {
+		if let Some(&input) = inputs.get(frame) {
+			emulator.set_controllers(input);
+		}
+		emulator.run_frame();
+	}
+	std::fs::write(path, emulator.framebuffer().to_png(crop_overscan)).map_err(|err| format!("Can't write {}: {}", path.display(), err))?;
+	info!("Wrote the screen after {} frames to {}", frames, path.display());
+	Ok(0)
+}
+
+/// Run a blargg test ROM, print its result, and return the exit code.
+fn run_blargg(harness: &mut Harness, options: &Options) -> i32 {
+	let stop = harness.run_blargg();
+	info!("Stopped after {} frames, {} CPU cycles", harness.emulator().frame(), harness.emulator().cycles());
+	let code = match &stop {
+		BlarggStop::Done(result) => {
+			println!("{}", result);
+			if result.passed() { 0 } else { 1 }
+		}
+		BlarggStop::Stopped(reason, result) => {
+			let status = result.as_ref().map_or("The ROM didn't report anything".to_string(), |result| result.to_string());
+			println!("{}. {}", reason, status);
+			if *reason == StopReason::BudgetExhausted { EXIT_BUDGET_EXHAUSTED } else { 1 }
+		}
+	};
+	if let Some((start, end)) = options.dump {
+		print!("{}", harness.dump_memory(start, end));
+	}
+	code
+}
+
+#[cfg(feature = "sdl")]
+fn load_keymap(options: &Options) -> Result<keymap::KeyMap, String> {
+	match &options.keymap {
+		Some(path) => {
+			let config = std::fs::read_to_string(path).map_err(|err| format!("Can't read keymap {}: {}", path.display(), err))?;
+			keymap::KeyMap::parse(&config).map_err(|err| format!("Keymap {}: {}", path.display(), err))
+		}
+		None => Ok(keymap::KeyMap::default()),
+	}
+}
+
+/// The options ask for a raw binary, or the file is neither an iNES file nor an FDS disk image.
+fn is_raw(bytes: &[u8], options: &Options) -> bool {
+	options.raw || !(Cartridge::is_ines(bytes) || Cartridge::is_fds(bytes))
+}
+
+/// A raw binary in a flat 64KB memory, at the addresses from the options, see `load_raw`.
+fn raw_image(bytes: &[u8], options: &Options) -> Result<[u8; 65_536], String> {
+	// The command line only knows it's raw when the options say so.
+	if options.blargg || options.strict_rom || options.strict_ram || options.strict_stack || options.warn_ram || options.hash_after.is_some() {
+		return Err("Not an iNES file, and --blargg, --strict-rom, --strict-ram, --strict-stack, --warn-ram and --hash-after need one".to_string());
+	}
+	let image = load_raw(bytes, options.load, options.entry)?;
+	info!("Raw binary, {} bytes, starting at ${:04X}", bytes.len(), u16::from_le_bytes([image[0xFFFC], image[0xFFFD]]));
+	Ok(image)
+}
+
+/// Run a raw binary on flat memory. Headless, it stops at the --pass-* and --fail-* conditions, like the harness, and
+/// returns the exit code.
+fn run_raw(image: &[u8; 65_536], options: &Options, mut trace: Option<Tracer>) -> Result<i32, String> {
+	let mut cpu = machine::flat(image);
 
-	// Create ROM and load it with simple program.
+	if options.debug {
+		run_debugger(&mut cpu, options)?;
+		return Ok(0);
+	}
+	let max_cycles = options.cycles.map_or(flat_max_cycles(options), |cycles| cycles.min(flat_max_cycles(options)));
+	let stop = run_flat(&mut cpu, max_cycles, &options.conditions, &mut trace);
+	info!("Stopped after {} CPU cycles: {}", cpu.cycles(), stop);
+	println!("{}", cpu.state());
+	if let Some((start, end)) = options.dump {
+		print!("{}", hex_dump(start, end, |addr| cpu.bus().peek(addr)));
+	}
+
+	let code = match stop {
+		FlatStop::Condition(_, Verdict::Pass) => 0,
+		FlatStop::Condition(_, Verdict::Fail) => 1,
+		_ if options.conditions.is_empty() => 0,
+		FlatStop::Brk(_) => 1,
+		FlatStop::BudgetExhausted => EXIT_BUDGET_EXHAUSTED,
+	};
+	Ok(code)
+}
+
+/// Memory loaded with the demo program.
+fn demo_memory(demo: Demo) -> [u8; 65_536] {
 	let mut rom_memory: [u8; 65_536] = [0;65_536];
-	let assembly_lines_amount = load_program_zeropage_x(&mut rom_memory);
-	let rom: ROM = ROM {
-		rom: Box::new(rom_memory)
+	match demo {
+		Demo::Adc => load_program_adc(&mut rom_memory),
+		Demo::ToLower => load_program_tolower(&mut rom_memory),
+		Demo::HelloWorld => load_program_helloworld(&mut rom_memory),
+		Demo::Snake => load_program_snake(&mut rom_memory),
 	};
-	
-	// Create CPU.
-	let bus = Box::new(Bus::new(rom));
-	let mut cpu = CPU::new(bus);
+	rom_memory
+}
+
+fn run_demo(demo: Demo, options: &Options, mut trace: Option<Tracer>) -> Result<(), String> {
+	let mut cpu = machine::flat(&demo_memory(demo));
+
+	if options.debug {
+		run_debugger(&mut cpu, options)?;
+	} else {
+		let max_cycles = flat_max_cycles(options);
+		info!("{}", run_flat(&mut cpu, max_cycles, &[], &mut trace));
+	}
+
+	info!("{}", cpu.registers());
+	match demo {
+		Demo::Adc | Demo::Snake => {}
+		Demo::ToLower => {
+			let output: Vec<u8> = (TOLOWER_OUTPUT..).map(|addr| cpu.bus_mut().read(addr)).take_while(|&c| c != 0).collect();
+			info!("Output string: {}", String::from_utf8_lossy(&output));
+		}
+		Demo::HelloWorld => {
+			let pixels: Vec<u8> = (0x0200..0x0203).map(|addr| cpu.bus_mut().read(addr)).collect();
+			info!("Display $0200-$0202: {:02X?}", pixels);
+		}
+	}
+	Ok(())
+}
+
+/// Run the debugger on stdin and stdout, until 'q' (or the end of stdin).
+fn run_debugger<T: DebugTarget>(target: &mut T, options: &Options) -> Result<(), String> {
+	let stdin = std::io::stdin();
+	let mut debugger = Debugger::new();
+	debugger.set_symbols(load_symbols(options)?);
+	debugger.repl(target, stdin.lock(), std::io::stdout()).map_err(|err| format!("Debugger: {}", err))
+}
+
+/// All the --symbols files in one table. The later files win.
+fn load_symbols(options: &Options) -> Result<SymbolTable, String> {
+	let mut symbols = SymbolTable::new();
+	for path in &options.symbols {
+		symbols.extend(SymbolTable::load(path)?);
+	}
+	Ok(symbols)
+}
+
+/// Programs that run without the PPU (demos and raw binaries) are limited by the cycles of --frames frames.
+fn flat_max_cycles(options: &Options) -> u64 {
+	let frames = options.frames.unwrap_or(DEFAULT_HEADLESS_FRAMES) as f64;
+	(frames * Region::default().cpu_cycles_per_frame()).ceil() as u64
+}
+
+/// Run on the easy6502 machine: in the window (or in the terminal, without the `sdl` feature), a frame of
+/// `CYCLES_PER_FRAME` cycles at a time, until the program gets to a BRK. Headless, it's like the other flat programs.
+fn run_easy6502(image: &[u8; 65_536], options: &Options, mut trace: Option<Tracer>) -> Result<i32, String> {
+	let seed = options.seed.unwrap_or_else(|| SystemTime::now().duration_since(UNIX_EPOCH).map_or(0, |time| time.as_nanos() as u64));
+	info!("Random seed: {} (--seed repeats the run)", seed);
+	let mut cpu = machine::easy6502(image, seed);
 
-	// Execute clocks.
-	for _ in 0..assembly_lines_amount {
-		cpu.clock_tick();
+	if options.debug {
+		run_debugger(&mut cpu, options)?;
+	} else if options.headless {
+		let max_cycles = flat_max_cycles(options);
+		info!("{}", run_flat(&mut cpu, max_cycles, &[], &mut trace));
+	} else {
+		// Goes on until the BRK.
+		let frame = |cpu: &mut CPU<Easy6502Bus>| {
+			let end = cpu.cycles() + CYCLES_PER_FRAME;
+			match run_flat(cpu, end, &[], &mut trace) {
+				FlatStop::BudgetExhausted => true,
+				stop => {
+					info!("{}", stop);
+					false
+				}
+			}
+		};
+		#[cfg(feature = "sdl")]
+		sdl_frontend::run_easy6502(&mut cpu, options, frame)?;
+		#[cfg(not(feature = "sdl"))]
+		terminal_frontend::run_easy6502(&mut cpu, options, frame)?;
 	}
 
-	info!("Finished running NES");
+	info!("{}", cpu.registers());
+	Ok(0)
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+
+	#[test]
+	fn init_logger_test() {
+		let options = cli::parse_args(["game.nes", "--log", "off,cpu=off"].map(String::from)).unwrap();
+		assert_eq!(init_logger(&options), Ok(()));
+		// A second logger is an error, not a panic.
+		assert!(init_logger(&options).is_err());
+	}
+
+	#[test]
+	fn load_sav_test() {
+		// NROM with a battery: 8KB of PRG RAM.
+		let mut rom = vec![0x4E, 0x45, 0x53, 0x1A, 1, 1, 0x02, 0, 0, 0, 0, 0, 0, 0, 0, 0];
+		rom.resize(16 + 0x4000 + 0x2000, 0xEA);
+		let mut emulator = Emulator::new(Cartridge::from_ines(&rom).unwrap());
+		let path = std::env::temp_dir().join(format!("nes-load-sav-{}.sav", process::id()));
+
+		// Of another size: the game starts anyway, without it.
+		std::fs::write(&path, [0x12; 0x1000]).unwrap();
+		load_sav(&mut emulator, &path);
+		assert_ne!(emulator.peek(0x6000), 0x12);
+
+		std::fs::write(&path, [0x12; 0x2000]).unwrap();
+		load_sav(&mut emulator, &path);
+		std::fs::remove_file(&path).unwrap();
+		assert_eq!(emulator.peek(0x6000), 0x12);
+
+		// None yet.
+		load_sav(&mut emulator, &path);
+	}
 }
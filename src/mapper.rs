@@ -0,0 +1,572 @@
+// Mappers: https://www.nesdev.org/wiki/Mapper
+// The chips of the cartridge board between the buses and the ROMs (or their absence, for NROM). The cartridge has
+// the memory: PRG ROM, PRG RAM and CHR. A mapper has the registers, and says where its banks are: where each 8KB
+// window of $8000-$FFFF starts in PRG ROM, and each 1KB window of the pattern tables in CHR. The cartridge asks after
+// every write to the registers, so a read of the CPU or the PPU only adds and indexes. See cartridge.rs.
+//
+// `Board` has the mappers here, by their iNES number. Each is a `Mapper`:
+//
+// | Mapper | Board | See |
+// |---|---|---|
+// | 0 | NROM | `Nrom` |
+// | 1 | MMC1 | mmc1.rs |
+// | 2 | UxROM | `Uxrom` |
+// | 3 | CNROM | `Cnrom` |
+// | 4 | MMC3 | mmc3.rs |
+// | 5 | MMC5 | mmc5.rs |
+// | 7 | AxROM | `Axrom` |
+// | 11 | Color Dreams | `ColorDreams` |
+// | 20 | FDS RAM adapter | fds/fds.rs, not from an iNES file |
+// | 24, 26 | VRC6 | vrc6.rs |
+// | 66 | GxROM | `Gxrom` |
+
+use crate::cartridge::IrqCounterInfo;
+use crate::fds::fds::Fds;
+use crate::mmc1::Mmc1;
+use crate::mmc3::{IrqVariant, Mmc3};
+use crate::mmc5::Mmc5;
+use crate::ppu::ppu::Mirroring;
+use crate::save_state::{SaveState, StateReader, StateWriter};
+use crate::vrc6::Vrc6;
+
+const KB: usize = 1024;
+const PRG_BANK_SIZE: usize = 8 * KB;
+const PRG_ROM_UNIT: usize = 16 * KB;
+const CHR_BANK_SIZE: usize = KB;
+/// NES 2.0 submapper 2 of mappers 2, 3 and 7: the boards with bus conflicts. 1 is without, and 0 doesn't say, like 1.
+const BUS_CONFLICTS_SUBMAPPER: u8 = 2;
+
+/// The registers of a cartridge board. It doesn't know the sizes of PRG ROM and CHR: the cartridge asks for the banks
+/// with them. The state is only the registers, see `Cartridge::save_state`.
+///
+/// There's `cpu_write` and `mirroring`, but no `cpu_read`, `ppu_read` or `ppu_write`: the memory is the cartridge's,
+/// and the mapper says where its banks are, so the reads of the PPU (one every 2 dots while rendering) only index,
+/// without a call through `Board`. A board that does more than switch banks gets a hook for what it adds, which does nothing by
+/// default: registers elsewhere (`expansion_write`, `ppu_register_write`), memory of its own (`nametable_read`), or
+/// what it sees of the PPU (`ppu_scanline`, `background_tile`). A new one is added only for what the banks can't say.
+pub trait Mapper: SaveState {
+	/// A write to $8000-$FFFF. Returns false when there's no register at `addr`.
+	fn cpu_write(&mut self, addr: u16, data: u8) -> bool;
+
+	/// Where each 8KB window of $8000-$FFFF starts in PRG ROM.
+	fn prg_banks(&self, prg_rom_size: usize) -> [usize; 4];
+
+	/// A write to $4020-$5FFF, the expansion area. Returns false when there's no register at `addr`.
+	fn expansion_write(&mut self, _addr: u16, _data: u8) -> bool {
+		false
+	}
+
+	/// A read of a register at $4020-$5FFF, for the debugger: nothing changes. None when there's none, open bus.
+	fn expansion_peek(&self, _addr: u16) -> Option<u8> {
+		None
+	}
+
+	/// A read of the CPU, like `expansion_peek`, that may acknowledge something.
+	fn expansion_read(&mut self, addr: u16) -> Option<u8> {
+		self.expansion_peek(addr)
+	}
+
+	/// Which 8KB of PRG RAM are at $6000.
+	fn prg_ram_bank(&self) -> usize {
+		0
+	}
+
+	/// The last address of PRG RAM: $7FFF, or more when it goes on in $8000-$FFFF (the 32KB of the FDS).
+	fn prg_ram_end(&self) -> u16 {
+		0x7FFF
+	}
+
+	/// PRG RAM answers at $6000-$7FFF. When it doesn't, it's open bus.
+	fn prg_ram_enabled(&self) -> bool {
+		true
+	}
+
+	/// The game can write PRG RAM, when it's enabled.
+	fn prg_ram_writable(&self) -> bool {
+		true
+	}
+
+	/// Where each 1KB window of the pattern tables starts in CHR. The first 8KB, without CHR banks.
+	fn chr_banks(&self, _chr_size: usize) -> [usize; 8] {
+		core::array::from_fn(|window| window * CHR_BANK_SIZE)
+	}
+
+	/// The same for the fetches of the background, when they have banks of their own. The ones of `chr_banks`
+	/// otherwise.
+	fn background_chr_banks(&self, chr_size: usize) -> [usize; 8] {
+		self.chr_banks(chr_size)
+	}
+
+	/// A write of the CPU to a register of the PPU, $2000-$3FFF. Returns true when the banks may have changed.
+	fn ppu_register_write(&mut self, _addr: u16, _data: u8) -> bool {
+		false
+	}
+
+	/// The board doesn't stop the ROM from driving the data bus during a write: the register gets the AND of the
+	/// value and the byte of ROM at the address. Games write a value that's also in ROM there.
+	fn bus_conflicts(&self) -> bool {
+		false
+	}
+
+	/// The mirroring the game chose, if it can. The one of the header otherwise.
+	fn mirroring(&self) -> Option<Mirroring> {
+		None
+	}
+
+	/// Where each of the 4 nametables is, when it's not one of the mirrorings. None for those, see `mirroring`.
+	fn nametables(&self) -> Option<[Nametable; 4]> {
+		None
+	}
+
+	/// A read of a nametable that is `Nametable::Cartridge`, at `addr` ($2000-$2FFF).
+	fn nametable_read(&self, _addr: u16) -> u8 {
+		0
+	}
+
+	/// A write there.
+	fn nametable_write(&mut self, _addr: u16, _data: u8) {}
+
+	/// A background tile of the mapper, instead of the one of the nametable and the pattern tables. None for the
+	/// usual one.
+	fn background_tile(&self, _fetch: &BackgroundFetch) -> Option<BackgroundTile> {
+		None
+	}
+
+	/// The PPU started a scanline: one with the fetches of rendering, or one without (see `PPU::scanline_fetches`).
+	fn ppu_scanline(&mut self, _fetching: bool) {}
+
+	/// The sizes of the PRG banks, from $8000, for the debugger (see `Cartridge::debug_state`).
+	fn prg_slots(&self) -> &'static [usize] {
+		&[16 * KB; 2]
+	}
+
+	/// The sizes of the CHR banks, from $0000, like `prg_slots`.
+	fn chr_slots(&self) -> &'static [usize] {
+		&[8 * KB]
+	}
+
+	/// The IRQ counter, for the debugger. None without one.
+	fn irq_info(&self) -> Option<IrqCounterInfo> {
+		None
+	}
+
+	/// Holds the IRQ line.
+	fn irq_pending(&self) -> bool {
+		false
+	}
+
+	/// The PPU got to the rise of A12 of a rendering scanline.
+	fn clock_scanline(&mut self) {}
+
+	/// A CPU cycle.
+	fn clock_cpu(&mut self) {}
+
+	/// The sound channels of the board, at the level of the APU channels.
+	fn audio_level(&self) -> f32 {
+		0.0
+	}
+}
+
+/// Where a nametable is, see `Mapper::nametables`.
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub enum Nametable {
+	/// One of the 2 pages of the PPU's VRAM.
+	Vram(u8),
+	/// In the cartridge, see `Mapper::nametable_read`.
+	Cartridge,
+}
+
+/// A fetch of a background tile by the PPU, see `Mapper::background_tile`.
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub struct BackgroundFetch {
+	/// The tile of the scanline, 0-33. The first 2 are fetched at the end of the scanline before.
+	pub column: u8,
+	/// The scanline the tile is on.
+	pub scanline: u16,
+	/// Where the nametable byte is, from the scroll.
+	pub nametable_addr: u16,
+	/// The nametable byte.
+	pub tile: u8,
+	/// The row of the tile, from the scroll.
+	pub fine_y: u16,
+}
+
+/// A background tile of the mapper.
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub struct BackgroundTile {
+	/// The nametable byte.
+	pub tile: u8,
+	/// The 2 bits of the attribute.
+	pub palette: u8,
+	/// Where the low byte of the row is in CHR. The high one is 8 bytes after it.
+	pub pattern: usize,
+}
+
+/// The mapper of the cartridge. An enum, and not a `Box<dyn Mapper>`, so it has the serde derives of the others.
+#[derive(Clone, Debug)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub enum Board {
+	Nrom(Nrom),
+	Mmc1(Mmc1),
+	Uxrom(Uxrom),
+	Cnrom(Cnrom),
+	Mmc3(Mmc3),
+	/// Boxed, for its 1KB of ExRAM.
+	Mmc5(Box<Mmc5>),
+	Axrom(Axrom),
+	ColorDreams(ColorDreams),
+	Vrc6(Vrc6),
+	Gxrom(Gxrom),
+	/// Boxed, for the disk. Never from `new`, see `Cartridge::from_fds`.
+	Fds(Box<Fds>),
+}
+
+impl Board {
+	/// The board of the iNES mapper `number`. None when it's not one of these.
+	pub fn new(number: u8, submapper: u8) -> Option<Self> {
+		Some(match number {
+			0 => Board::Nrom(Nrom),
+			1 => Board::Mmc1(Mmc1::new()),
+			2 => Board::Uxrom(Uxrom { prg_bank: 0, bus_conflicts: submapper == BUS_CONFLICTS_SUBMAPPER }),
+			3 => Board::Cnrom(Cnrom { chr_bank: 0, bus_conflicts: submapper == BUS_CONFLICTS_SUBMAPPER }),
+			4 => Board::Mmc3(Mmc3::new(IrqVariant::from_submapper(submapper))),
+			5 => Board::Mmc5(Box::default()),
+			7 => Board::Axrom(Axrom { register: 0, bus_conflicts: submapper == BUS_CONFLICTS_SUBMAPPER }),
+			11 => Board::ColorDreams(ColorDreams::default()),
+			24 | 26 => Board::Vrc6(Vrc6::new(number)),
+			66 => Board::Gxrom(Gxrom::default()),
+			_ => return None,
+		})
+	}
+
+	pub fn mapper(&self) -> &dyn Mapper {
+		match self {
+			Board::Nrom(nrom) => nrom,
+			Board::Mmc1(mmc1) => mmc1,
+			Board::Uxrom(uxrom) => uxrom,
+			Board::Cnrom(cnrom) => cnrom,
+			Board::Mmc3(mmc3) => mmc3,
+			Board::Mmc5(mmc5) => mmc5.as_ref(),
+			Board::Axrom(axrom) => axrom,
+			Board::ColorDreams(color_dreams) => color_dreams,
+			Board::Vrc6(vrc6) => vrc6,
+			Board::Gxrom(gxrom) => gxrom,
+			Board::Fds(fds) => fds.as_ref(),
+		}
+	}
+
+	pub fn mapper_mut(&mut self) -> &mut dyn Mapper {
+		match self {
+			Board::Nrom(nrom) => nrom,
+			Board::Mmc1(mmc1) => mmc1,
+			Board::Uxrom(uxrom) => uxrom,
+			Board::Cnrom(cnrom) => cnrom,
+			Board::Mmc3(mmc3) => mmc3,
+			Board::Mmc5(mmc5) => mmc5.as_mut(),
+			Board::Axrom(axrom) => axrom,
+			Board::ColorDreams(color_dreams) => color_dreams,
+			Board::Vrc6(vrc6) => vrc6,
+			Board::Gxrom(gxrom) => gxrom,
+			Board::Fds(fds) => fds.as_mut(),
+		}
+	}
+}
+
+/// Mapper 0, no mapper: 32KB of PRG ROM fill the 4 windows, and 16KB (NROM-128) are mirrored at $C000.
+#[derive(Clone, Debug, Default)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct Nrom;
+
+impl Mapper for Nrom {
+	fn cpu_write(&mut self, _addr: u16, _data: u8) -> bool {
+		false
+	}
+
+	fn prg_banks(&self, prg_rom_size: usize) -> [usize; 4] {
+		core::array::from_fn(|window| window * PRG_BANK_SIZE % prg_rom_size)
+	}
+}
+
+/// No registers.
+impl SaveState for Nrom {
+	fn save_state(&self, _out: &mut StateWriter) {}
+
+	fn load_state(&mut self, _input: &mut StateReader) -> Result<(), String> {
+		Ok(())
+	}
+}
+
+/// Mapper 2: a write to $8000-$FFFF selects the 16KB at $8000, and the last 16KB are at $C000. The boards have CHR RAM.
+#[derive(Clone, Debug, Default)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct Uxrom {
+	prg_bank: u8,
+	/// From the header, not in the state.
+	bus_conflicts: bool,
+}
+
+impl Mapper for Uxrom {
+	fn cpu_write(&mut self, _addr: u16, data: u8) -> bool {
+		self.prg_bank = data;
+		true
+	}
+
+	fn prg_banks(&self, prg_rom_size: usize) -> [usize; 4] {
+		let bank = self.prg_bank as usize * PRG_ROM_UNIT % prg_rom_size;
+		let last = prg_rom_size - PRG_ROM_UNIT;
+		[bank, bank + PRG_BANK_SIZE, last, last + PRG_BANK_SIZE]
+	}
+
+	fn bus_conflicts(&self) -> bool {
+		self.bus_conflicts
+	}
+}
+
+impl SaveState for Uxrom {
+	fn save_state(&self, out: &mut StateWriter) {
+		out.u8(self.prg_bank);
+	}
+
+	fn load_state(&mut self, input: &mut StateReader) -> Result<(), String> {
+		self.prg_bank = input.u8()?;
+		Ok(())
+	}
+}
+
+/// Mapper 3: PRG ROM like NROM, and a write to $8000-$FFFF selects the 8KB of CHR ROM.
+#[derive(Clone, Debug, Default)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct Cnrom {
+	chr_bank: u8,
+	/// From the header, not in the state.
+	bus_conflicts: bool,
+}
+
+impl Mapper for Cnrom {
+	fn cpu_write(&mut self, _addr: u16, data: u8) -> bool {
+		self.chr_bank = data;
+		true
+	}
+
+	fn prg_banks(&self, prg_rom_size: usize) -> [usize; 4] {
+		Nrom.prg_banks(prg_rom_size)
+	}
+
+	fn chr_banks(&self, chr_size: usize) -> [usize; 8] {
+		chr_banks_8kb(self.chr_bank, chr_size)
+	}
+
+	fn bus_conflicts(&self) -> bool {
+		self.bus_conflicts
+	}
+}
+
+impl SaveState for Cnrom {
+	fn save_state(&self, out: &mut StateWriter) {
+		out.u8(self.chr_bank);
+	}
+
+	fn load_state(&mut self, input: &mut StateReader) -> Result<(), String> {
+		self.chr_bank = input.u8()?;
+		Ok(())
+	}
+}
+
+/// Mapper 7: a write to $8000-$FFFF selects the 32KB of PRG ROM (bits 0-2), and the nametable of the single screen
+/// mirroring (bit 4). The boards have CHR RAM.
+#[derive(Clone, Debug, Default)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct Axrom {
+	register: u8,
+	/// From the header, not in the state.
+	bus_conflicts: bool,
+}
+
+impl Mapper for Axrom {
+	fn cpu_write(&mut self, _addr: u16, data: u8) -> bool {
+		self.register = data;
+		true
+	}
+
+	fn prg_banks(&self, prg_rom_size: usize) -> [usize; 4] {
+		prg_banks_32kb(self.register & 0b111, prg_rom_size)
+	}
+
+	fn mirroring(&self) -> Option<Mirroring> {
+		Some(if self.register & 0x10 == 0 { Mirroring::SingleScreenLower } else { Mirroring::SingleScreenUpper })
+	}
+
+	fn prg_slots(&self) -> &'static [usize] {
+		&[32 * KB]
+	}
+
+	fn bus_conflicts(&self) -> bool {
+		self.bus_conflicts
+	}
+}
+
+impl SaveState for Axrom {
+	fn save_state(&self, out: &mut StateWriter) {
+		out.u8(self.register);
+	}
+
+	fn load_state(&mut self, input: &mut StateReader) -> Result<(), String> {
+		self.register = input.u8()?;
+		Ok(())
+	}
+}
+
+/// Mapper 11: a write to $8000-$FFFF selects the 32KB of PRG ROM (bits 0-1) and the 8KB of CHR ROM (bits 4-7).
+#[derive(Clone, Debug, Default)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct ColorDreams {
+	register: u8,
+}
+
+impl Mapper for ColorDreams {
+	fn cpu_write(&mut self, _addr: u16, data: u8) -> bool {
+		self.register = data;
+		true
+	}
+
+	fn prg_banks(&self, prg_rom_size: usize) -> [usize; 4] {
+		prg_banks_32kb(self.register & 0b11, prg_rom_size)
+	}
+
+	fn chr_banks(&self, chr_size: usize) -> [usize; 8] {
+		chr_banks_8kb(self.register >> 4, chr_size)
+	}
+
+	fn prg_slots(&self) -> &'static [usize] {
+		&[32 * KB]
+	}
+
+	/// Every board of the mapper has them, unlike mappers 2, 3 and 7: no submapper says otherwise.
+	fn bus_conflicts(&self) -> bool {
+		true
+	}
+}
+
+impl SaveState for ColorDreams {
+	fn save_state(&self, out: &mut StateWriter) {
+		out.u8(self.register);
+	}
+
+	fn load_state(&mut self, input: &mut StateReader) -> Result<(), String> {
+		self.register = input.u8()?;
+		Ok(())
+	}
+}
+
+/// Mapper 66: like Color Dreams, with the PRG bank in bits 4-5, and the CHR bank in bits 0-1.
+#[derive(Clone, Debug, Default)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct Gxrom {
+	register: u8,
+}
+
+impl Mapper for Gxrom {
+	fn cpu_write(&mut self, _addr: u16, data: u8) -> bool {
+		self.register = data;
+		true
+	}
+
+	fn prg_banks(&self, prg_rom_size: usize) -> [usize; 4] {
+		prg_banks_32kb((self.register >> 4) & 0b11, prg_rom_size)
+	}
+
+	fn chr_banks(&self, chr_size: usize) -> [usize; 8] {
+		chr_banks_8kb(self.register & 0b11, chr_size)
+	}
+
+	fn prg_slots(&self) -> &'static [usize] {
+		&[32 * KB]
+	}
+
+	/// Every board of the mapper has them, unlike mappers 2, 3 and 7: no submapper says otherwise.
+	fn bus_conflicts(&self) -> bool {
+		true
+	}
+}
+
+impl SaveState for Gxrom {
+	fn save_state(&self, out: &mut StateWriter) {
+		out.u8(self.register);
+	}
+
+	fn load_state(&mut self, input: &mut StateReader) -> Result<(), String> {
+		self.register = input.u8()?;
+		Ok(())
+	}
+}
+
+/// The 8KB windows of the 32KB PRG bank `bank`.
+fn prg_banks_32kb(bank: u8, prg_rom_size: usize) -> [usize; 4] {
+	let start = bank as usize * 32 * KB;
+	core::array::from_fn(|window| (start + window * PRG_BANK_SIZE) % prg_rom_size)
+}
+
+/// The 1KB windows of the 8KB CHR bank `bank`.
+fn chr_banks_8kb(bank: u8, chr_size: usize) -> [usize; 8] {
+	let start = bank as usize * 8 * KB % chr_size;
+	core::array::from_fn(|window| start + window * CHR_BANK_SIZE)
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+
+	#[test]
+	fn nrom_test() {
+		let mut nrom = Board::new(0, 0).unwrap();
+		assert!(!nrom.mapper_mut().cpu_write(0x8000, 1));
+		// NROM-128 is mirrored.
+		assert_eq!(nrom.mapper().prg_banks(0x4000), [0x0000, 0x2000, 0x0000, 0x2000]);
+		assert_eq!(nrom.mapper().prg_banks(0x8000), [0x0000, 0x2000, 0x4000, 0x6000]);
+		assert_eq!(nrom.mapper().chr_banks(0x2000).map(|start| start / 0x400), [0, 1, 2, 3, 4, 5, 6, 7]);
+		assert_eq!(nrom.mapper().mirroring(), None);
+
+		assert!(Board::new(9, 0).is_none());
+	}
+
+	#[test]
+	fn cnrom_test() {
+		// 4 banks of 8KB of CHR.
+		let mut cnrom = Board::new(3, 0).unwrap();
+		assert!(!cnrom.mapper().bus_conflicts());
+		assert!(cnrom.mapper_mut().cpu_write(0x8000, 2));
+		assert_eq!(cnrom.mapper().chr_banks(0x8000).map(|start| start / 0x400), [16, 17, 18, 19, 20, 21, 22, 23]);
+		assert!(cnrom.mapper_mut().cpu_write(0xFFFF, 5));
+		assert_eq!(cnrom.mapper().chr_banks(0x8000)[0], 0x2000);
+		assert_eq!(cnrom.mapper().prg_banks(0x4000), [0x0000, 0x2000, 0x0000, 0x2000]);
+
+		assert!(Board::new(3, 2).unwrap().mapper().bus_conflicts());
+		assert!(Board::new(2, 2).unwrap().mapper().bus_conflicts());
+	}
+
+	#[test]
+	fn discrete_32kb_test() {
+		// 4 banks of 32KB of PRG, and 4 of 8KB of CHR.
+		let banks = |board: &Board| (board.mapper().prg_banks(0x20000).map(|start| start / 0x8000), board.mapper().chr_banks(0x8000)[0] / 0x2000);
+
+		let mut axrom = Board::new(7, 0).unwrap();
+		assert_eq!(axrom.mapper().mirroring(), Some(Mirroring::SingleScreenLower));
+		axrom.mapper_mut().cpu_write(0x8000, 0x12);
+		assert_eq!(banks(&axrom), ([2; 4], 0));
+		assert_eq!(axrom.mapper().mirroring(), Some(Mirroring::SingleScreenUpper));
+		// 8 banks, in 128KB.
+		axrom.mapper_mut().cpu_write(0x8000, 0x07);
+		assert_eq!(banks(&axrom).0, [3; 4]);
+		assert!(!axrom.mapper().bus_conflicts());
+
+		let mut color_dreams = Board::new(11, 0).unwrap();
+		color_dreams.mapper_mut().cpu_write(0x8000, 0x31);
+		assert_eq!(banks(&color_dreams), ([1; 4], 3));
+
+		let mut gxrom = Board::new(66, 0).unwrap();
+		gxrom.mapper_mut().cpu_write(0x8000, 0x31);
+		assert_eq!(banks(&gxrom), ([3; 4], 1));
+		assert_eq!(gxrom.mapper().prg_slots(), [0x8000]);
+	}
+}
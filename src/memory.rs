@@ -11,17 +11,18 @@
 
 extern crate hex;
 
-use log::debug;
+use log::{debug, log_enabled, Level};
+
+use crate::error::NesError;
+use crate::log_target::BUS;
 
 /// Addressable memory (64kb). Includes zero page, CPU ram, PPU registers, Cartidge memory, basically all available addressable memory.
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
 pub struct MemoryBus {
+	#[cfg_attr(feature = "serde", serde(with = "crate::serde_bytes_base64"))]
 	memory: Box<[u8; 65_536]>
 }
 
-pub struct ROM {
-	pub rom: Box<[u8; 65_536]> 		// NOTE: ROM can be very big (8MB). For now I leave it at 64kb.
-}
-
 enum MemoryMap {
 	ZEROPAGE, 			// 0x0000 - 0x00FF
 	STACK,				// 0x0100 - 0x01FF
@@ -32,19 +33,16 @@ enum MemoryMap {
 	OTHER,  			// everything else (it will be completed when I understand memory better)
 }
 
-/// Read = if you intend to read or write to mm.
-fn get_memory_map(addr: u16, read: bool) -> MemoryMap {
+fn get_memory_map(addr: u16) -> MemoryMap {
 	if addr <= 0x00FF {
 		MemoryMap::ZEROPAGE
-	} else if addr >= 0x100 && addr < 0x200 {
+	} else if (0x100..0x200).contains(&addr) {
 		MemoryMap::STACK
-	} else if addr >= 0x2000 && addr < 0x6000 {
-		if addr == 0x2002 {
-			if read {
-				MemoryMap::PpuStatus
-			} else {
-				panic!("You can't write to PPU Status memory. It's read only.");
-			}
+	} else if (0x2000..0x6000).contains(&addr) {
+		if addr == 0x2001 {
+			MemoryMap::PpuMask
+		} else if addr == 0x2002 {
+			MemoryMap::PpuStatus
 		} else {
 			MemoryMap::MappedIO
 		}
@@ -53,63 +51,90 @@ fn get_memory_map(addr: u16, read: bool) -> MemoryMap {
 	}
 }
 
+impl Default for MemoryBus {
+	fn default() -> Self {
+		Self::new()
+	}
+}
+
 impl MemoryBus {
 	pub fn new() -> Self {
 		MemoryBus { memory: Box::new([0; 65536]) }
 	}
 
 	fn debug_write(&self, addr: u16, data: u8) {
-		let map = get_memory_map(addr, false);
+		let map = get_memory_map(addr);
 		match map {
-			MemoryMap::ZEROPAGE 		=> debug!("Writing to zero page, address: {:#X}, data: {:#X}", addr, data),
-			MemoryMap::STACK 			=> debug!("Writing to stack, address: {:#X}, data: {:#X}", addr, data),
-			MemoryMap::MappedIO			=> debug!("Writing to memory mapped i/o, address: {:#X}, data: {:#X}", addr, data),
-			MemoryMap::PpuStatus 		=> (),
-			MemoryMap::PpuMask 			=> debug!("Writing to PPU mask, address: {:#X}, data: {:#X}", addr, data),
-			MemoryMap::OTHER 			=> debug!("Writing to address: {:#X}, data: {:#X}", addr, data)
+			MemoryMap::ZEROPAGE 		=> debug!(target: BUS, "Writing to zero page, address: {:#X}, data: {:#X}", addr, data),
+			MemoryMap::STACK 			=> debug!(target: BUS, "Writing to stack, address: {:#X}, data: {:#X}", addr, data),
+			MemoryMap::MappedIO			=> debug!(target: BUS, "Writing to memory mapped i/o, address: {:#X}, data: {:#X}", addr, data),
+			// Read only on the NES, but this is just memory, so the write goes through.
+			MemoryMap::PpuStatus 		=> debug!(target: BUS, "Writing to read only PPU status, address: {:#X}, data: {:#X}", addr, data),
+			MemoryMap::PpuMask 			=> debug!(target: BUS, "Writing to PPU mask, address: {:#X}, data: {:#X}", addr, data),
+			MemoryMap::OTHER 			=> debug!(target: BUS, "Writing to address: {:#X}, data: {:#X}", addr, data)
 		}
 	}
 
 	fn debug_read(&self, addr: u16) {
-		let map = get_memory_map(addr, true);
+		let map = get_memory_map(addr);
 		match map {
-			MemoryMap::ZEROPAGE 		=> debug!("Reading from zero page, address: {:#X}", addr),
-			MemoryMap::STACK 			=> debug!("Reading from stack, address: {:#X}", addr),
-			MemoryMap::MappedIO			=> debug!("Reading from memory mapped i/o, address: {:#X}", addr),
-			MemoryMap::PpuStatus 		=> debug!("Reading from PPU status, address: {:#X}", addr),
-			MemoryMap::PpuMask 			=> debug!("Reading from PPU mask, address: {:#X}", addr),
-			MemoryMap::OTHER 			=> debug!("Reading from	address: {:#X}", addr)
+			MemoryMap::ZEROPAGE 		=> debug!(target: BUS, "Reading from zero page, address: {:#X}", addr),
+			MemoryMap::STACK 			=> debug!(target: BUS, "Reading from stack, address: {:#X}", addr),
+			MemoryMap::MappedIO			=> debug!(target: BUS, "Reading from memory mapped i/o, address: {:#X}", addr),
+			MemoryMap::PpuStatus 		=> debug!(target: BUS, "Reading from PPU status, address: {:#X}", addr),
+			MemoryMap::PpuMask 			=> debug!(target: BUS, "Reading from PPU mask, address: {:#X}", addr),
+			MemoryMap::OTHER 			=> debug!(target: BUS, "Reading from	address: {:#X}", addr)
 		}
 	}
 	
+	/// Copy the whole memory image.
+	pub fn load(&mut self, image: &[u8; 65_536]) {
+		self.memory.copy_from_slice(image);
+	}
+
 	/// Write a single byte to memory.
 	pub fn write(&mut self, addr: u16, data: u8) {
-		self.debug_write(addr, data);
+		// Only find where the address is when it's logged. This is on every access.
+		if log_enabled!(target: BUS, Level::Debug) {
+			self.debug_write(addr, data);
+		}
 		self.memory[addr as usize] = data;
 	}
 
 	/// Read a single byte from memory.
 	pub fn read(&self, addr: u16) -> u8 {
-		self.debug_read(addr);
+		if log_enabled!(target: BUS, Level::Debug) {
+			self.debug_read(addr);
+		}
 		self.memory[addr as usize]
 	}
 }
 
-impl ROM {
-	pub fn read(&self, addr: u16) -> u8 {
-		self.rom[addr as usize]
-	}
+/// Programs are loaded here, like in easy6502. It leaves the zero page and the stack free for the program to use.
+pub const PROGRAM_START: u16 = 0x0600;
+
+/// Parse bytes from string, represented by hex with spaces. For example: "A9 FF EA".
+pub fn hex_to_bytes(dump: &str) -> Result<Vec<u8>, NesError> {
+	dump.split_whitespace().map(|s| match hex::decode(s) {
+		Ok(byte) if byte.len() == 1 => Ok(byte[0]),
+		_ => Err(NesError::Decode(format!("Not a hex byte: '{}'", s))),
+	}).collect()
 }
 
-/// Write to array the bytes from string, represented by hex with spaces.
-pub fn write_rom(rom_memory: &mut [u8;65_536], dump: &str) {
-	let split = dump.split(" ");
-	let mut i = 0;
-	for s in split {
-		let z = hex::decode(s).unwrap();
-		rom_memory[i] = z[0];
-		i += 1;
+/// Write to memory image the bytes from string, represented by hex with spaces.
+/// The program is written at `PROGRAM_START`, and the reset vector points to it.
+pub fn write_rom(rom_memory: &mut [u8;65_536], dump: &str) -> Result<(), NesError> {
+	let program = hex_to_bytes(dump)?;
+	let start = PROGRAM_START as usize;
+	// The program can't overwrite the vectors.
+	if program.len() > 0xFFFA - start {
+		return Err(NesError::Bus(format!("Program is too long: {} bytes, up to {} fit", program.len(), 0xFFFA - start)));
 	}
+	rom_memory[start..start + program.len()].copy_from_slice(&program);
+
+	rom_memory[0xFFFC] = (PROGRAM_START & 0xFF) as u8;
+	rom_memory[0xFFFD] = (PROGRAM_START >> 8) as u8;
+	Ok(())
 }
 
 #[cfg(test)]
@@ -129,4 +154,17 @@ mod tests {
 		assert!(ram.read(addr + 1) == 0xCD);
 		assert!(ram.read(addr + 2) == 0x00);
     }
+
+	#[test]
+	fn write_rom_test() {
+		let mut rom = [0; 65_536];
+		write_rom(&mut rom, "A9 ff  EA").unwrap();
+		assert_eq!(rom[0x600..0x603], [0xA9, 0xFF, 0xEA]);
+		assert_eq!(rom[0xFFFC..0xFFFE], [0x00, 0x06]);
+
+		assert!(write_rom(&mut rom, "A9 F").is_err());
+		assert!(write_rom(&mut rom, "A9FF").is_err());
+		assert!(write_rom(&mut rom, "LDA").is_err());
+		assert!(write_rom(&mut rom, &"EA ".repeat(0x10000)).is_err());
+	}
 }
\ No newline at end of file
@@ -0,0 +1,307 @@
+// MMC1 (mapper 1, the SxROM boards): https://www.nesdev.org/wiki/MMC1
+// The registers are written one bit at a time, through a shift register: 5 writes to $8000-$FFFF of bit 0, and the
+// address of the 5th chooses the register. A write with bit 7 set empties the shift register, and sets PRG mode 3.
+//
+// | Address | Register |
+// |---|---|
+// | $8000-$9FFF | Control: mirroring (bits 0-1), PRG mode (bits 2-3), CHR mode (bit 4) |
+// | $A000-$BFFF | CHR bank 0: 4KB at $0000, or 8KB at $0000 (the low bit is ignored) |
+// | $C000-$DFFF | CHR bank 1: 4KB at $1000, ignored in 8KB mode |
+// | $E000-$FFFF | PRG bank: 16KB, or 32KB (the low bit is ignored) |
+//
+// | PRG mode | $8000 | $C000 |
+// |---|---|---|
+// | 0, 1 | 32KB bank | |
+// | 2 | First bank | PRG bank |
+// | 3 | PRG bank | Last bank |
+//
+// The mirroring is 0 single screen lower, 1 single screen upper, 2 vertical, 3 horizontal.
+//
+// SUROM boards have 512KB of PRG ROM: bit 4 of CHR bank 0 chooses the 256KB half, the "first" and "last" banks
+// included (Dragon Warrior III and IV). The boards with 8KB of CHR RAM don't use the CHR banks.
+//
+// The chip ignores a write on the cycle right after another one: of the 2 writes of a read-modify-write instruction,
+// only the first (the value read) counts. Bill & Ted's Excellent Adventure resets the shift register with an INC of a
+// $FF in ROM. The CPU does both writes with `--cycle-accurate` (see `CPU::set_cycle_accurate`), and ticks the bus
+// after the instruction, so here they come on the same cycle. The PRG RAM disable bit (bit 4 of the PRG bank) is ignored, like on MMC1A: PRG RAM is always on.
+
+use crate::mapper::Mapper;
+use crate::ppu::ppu::Mirroring;
+use crate::save_state::{SaveState, StateReader, StateWriter};
+
+const KB: usize = 1024;
+const PRG_BANK_SIZE: usize = 16 * KB;
+const CHR_BANK_SIZE: usize = 4 * KB;
+/// The size of the PRG ROM that the PRG bank register reaches, the 256KB of a half of SUROM.
+const PRG_OUTER_BANK_SIZE: usize = 256 * KB;
+/// The shift register is empty: the 1 gets to bit 0 at the 5th write.
+const SHIFT_EMPTY: u8 = 0x10;
+
+/// The registers of the chip. Like `Mmc3`, the cartridge asks for the banks with its sizes.
+#[derive(Clone, Debug)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct Mmc1 {
+	shift: u8,
+	control: u8,
+	/// The control register was written: the mirroring of the header until then.
+	control_written: bool,
+	chr_bank_0: u8,
+	chr_bank_1: u8,
+	prg_bank: u8,
+	/// The CPU cycles since the last write, up to 2: enough to tell the next cycle. Not in the save state, a state is
+	/// saved between instructions.
+	idle_cycles: u8,
+}
+
+impl Default for Mmc1 {
+	fn default() -> Self {
+		Self::new()
+	}
+}
+
+impl Mmc1 {
+	/// At power on, the last bank is at $C000, so the reset vector is there.
+	pub fn new() -> Self {
+		Mmc1 {
+			shift: SHIFT_EMPTY,
+			control: 0x0C,
+			control_written: false,
+			chr_bank_0: 0,
+			chr_bank_1: 0,
+			prg_bank: 0,
+			idle_cycles: 2,
+		}
+	}
+
+	fn prg_mode(&self) -> u8 {
+		(self.control >> 2) & 0b11
+	}
+
+	fn chr_4kb(&self) -> bool {
+		self.control & 0x10 != 0
+	}
+
+	fn write_register(&mut self, addr: u16, data: u8) {
+		match addr & 0xE000 {
+			0x8000 => {
+				self.control = data;
+				self.control_written = true;
+			}
+			0xA000 => self.chr_bank_0 = data,
+			0xC000 => self.chr_bank_1 = data,
+			_ => self.prg_bank = data & 0x0F,
+		}
+	}
+}
+
+impl Mapper for Mmc1 {
+	/// A write to the shift register. The 5th one goes to the register of `addr`. Ignored on the cycle after another one.
+	fn cpu_write(&mut self, addr: u16, data: u8) -> bool {
+		let consecutive = self.idle_cycles < 2;
+		self.idle_cycles = 0;
+		if consecutive {
+			return true;
+		}
+		if data & 0x80 != 0 {
+			self.shift = SHIFT_EMPTY;
+			self.control |= 0x0C;
+			return true;
+		}
+		let full = self.shift & 1 != 0;
+		self.shift = (self.shift >> 1) | (data & 1) << 4;
+		if full {
+			let value = self.shift;
+			self.shift = SHIFT_EMPTY;
+			self.write_register(addr, value);
+		}
+		true
+	}
+
+	fn mirroring(&self) -> Option<Mirroring> {
+		self.control_written.then_some(match self.control & 0b11 {
+			0 => Mirroring::SingleScreenLower,
+			1 => Mirroring::SingleScreenUpper,
+			2 => Mirroring::Vertical,
+			_ => Mirroring::Horizontal,
+		})
+	}
+
+	/// The 16KB banks of the PRG mode, in the 256KB half of SUROM.
+	fn prg_banks(&self, prg_rom_size: usize) -> [usize; 4] {
+		let outer = if prg_rom_size > PRG_OUTER_BANK_SIZE { (self.chr_bank_0 as usize >> 4 & 1) * PRG_OUTER_BANK_SIZE } else { 0 };
+		let inner_size = prg_rom_size.min(PRG_OUTER_BANK_SIZE);
+		let bank = |number: usize| outer + number * PRG_BANK_SIZE % inner_size;
+		let selected = self.prg_bank as usize;
+		let (low, high) = match self.prg_mode() {
+			0 | 1 => (bank(selected & !1), bank(selected | 1)),
+			2 => (bank(0), bank(selected)),
+			_ => (bank(selected), outer + inner_size - PRG_BANK_SIZE),
+		};
+		const HALF: usize = PRG_BANK_SIZE / 2;
+		[low % prg_rom_size, (low + HALF) % prg_rom_size, high % prg_rom_size, (high + HALF) % prg_rom_size]
+	}
+
+	/// Two 4KB banks, or 8KB from CHR bank 0.
+	fn chr_banks(&self, chr_size: usize) -> [usize; 8] {
+		let (low, high) = if self.chr_4kb() {
+			(self.chr_bank_0 as usize, self.chr_bank_1 as usize)
+		} else {
+			(self.chr_bank_0 as usize & !1, self.chr_bank_0 as usize | 1)
+		};
+		core::array::from_fn(|window| {
+			let bank = if window < 4 { low } else { high };
+			(bank * CHR_BANK_SIZE + (window % 4) * KB) % chr_size
+		})
+	}
+
+	fn prg_slots(&self) -> &'static [usize] {
+		if self.prg_mode() < 2 { &[2 * PRG_BANK_SIZE] } else { &[PRG_BANK_SIZE; 2] }
+	}
+
+	fn chr_slots(&self) -> &'static [usize] {
+		if self.chr_4kb() { &[CHR_BANK_SIZE; 2] } else { &[2 * CHR_BANK_SIZE] }
+	}
+
+	fn clock_cpu(&mut self) {
+		self.idle_cycles = (self.idle_cycles + 1).min(2);
+	}
+}
+
+impl SaveState for Mmc1 {
+	fn save_state(&self, out: &mut StateWriter) {
+		out.u8(self.shift);
+		out.u8(self.control);
+		out.bool(self.control_written);
+		out.u8(self.chr_bank_0);
+		out.u8(self.chr_bank_1);
+		out.u8(self.prg_bank);
+	}
+
+	fn load_state(&mut self, input: &mut StateReader) -> Result<(), String> {
+		self.shift = input.u8()?;
+		self.control = input.u8()?;
+		self.control_written = input.bool()?;
+		self.chr_bank_0 = input.u8()?;
+		self.chr_bank_1 = input.u8()?;
+		self.prg_bank = input.u8()?;
+		Ok(())
+	}
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+	use crate::cartridge::{test_rom, Cartridge};
+	use crate::emulator::Emulator;
+
+	/// A write of an STA, after the cycles of the instruction before.
+	fn store(mmc1: &mut Mmc1, addr: u16, data: u8) {
+		mmc1.clock_cpu();
+		mmc1.clock_cpu();
+		mmc1.cpu_write(addr, data);
+	}
+
+	/// Write `value` to the register at `addr`, a bit at a time.
+	fn write(mmc1: &mut Mmc1, addr: u16, value: u8) {
+		for bit in 0..5 {
+			store(mmc1, addr, value >> bit & 1);
+		}
+	}
+
+	#[test]
+	fn shift_register_test() {
+		let mut mmc1 = Mmc1::new();
+		assert_eq!(mmc1.mirroring(), None);
+		write(&mut mmc1, 0x8000, 0b0_11_10);
+		assert_eq!(mmc1.mirroring(), Some(Mirroring::Vertical));
+
+		// 4 bits, then the reset: nothing is written, and the shift register starts again.
+		for _ in 0..4 {
+			store(&mut mmc1, 0x8000, 1);
+		}
+		store(&mut mmc1, 0x8000, 0x80);
+		assert_eq!(mmc1.mirroring(), Some(Mirroring::Vertical));
+		write(&mut mmc1, 0x9FFF, 0b0_11_11);
+		assert_eq!(mmc1.mirroring(), Some(Mirroring::Horizontal));
+		write(&mut mmc1, 0x8000, 0b0_11_00);
+		assert_eq!(mmc1.mirroring(), Some(Mirroring::SingleScreenLower));
+	}
+
+	#[test]
+	fn prg_banks_test() {
+		// 8 banks of 16KB. Mode 3 at power on: the last bank at $C000.
+		let mut mmc1 = Mmc1::new();
+		let banks = |mmc1: &Mmc1| mmc1.prg_banks(0x20000).map(|start| start / 0x2000);
+		assert_eq!(banks(&mmc1), [0, 1, 14, 15]);
+		write(&mut mmc1, 0xE000, 3);
+		assert_eq!(banks(&mmc1), [6, 7, 14, 15]);
+
+		// Mode 2: the first bank at $8000.
+		write(&mut mmc1, 0x8000, 0b0_10_00);
+		assert_eq!(banks(&mmc1), [0, 1, 6, 7]);
+
+		// 32KB: the low bit is ignored.
+		write(&mut mmc1, 0x8000, 0b0_00_00);
+		assert_eq!(banks(&mmc1), [4, 5, 6, 7]);
+		assert_eq!(mmc1.prg_slots(), [0x8000]);
+
+		// The reset sets mode 3 again.
+		store(&mut mmc1, 0x8000, 0x80);
+		assert_eq!(banks(&mmc1), [6, 7, 14, 15]);
+
+		// SUROM: the second 256KB.
+		write(&mut mmc1, 0xA000, 0x10);
+		write(&mut mmc1, 0xE000, 1);
+		assert_eq!(mmc1.prg_banks(0x80000).map(|start| start / 0x4000), [17, 17, 31, 31]);
+	}
+
+	#[test]
+	fn chr_banks_test() {
+		// 16 banks of 4KB. 8KB mode at power on: the low bit is ignored.
+		let mut mmc1 = Mmc1::new();
+		write(&mut mmc1, 0xA000, 5);
+		write(&mut mmc1, 0xC000, 9);
+		assert_eq!(mmc1.chr_banks(0x10000).map(|start| start / 0x400), [16, 17, 18, 19, 20, 21, 22, 23]);
+
+		write(&mut mmc1, 0x8000, 0b1_11_00);
+		assert_eq!(mmc1.chr_banks(0x10000).map(|start| start / 0x400), [20, 21, 22, 23, 36, 37, 38, 39]);
+		assert_eq!(mmc1.chr_slots(), [0x1000, 0x1000]);
+
+		// 8KB of CHR RAM: the banks wrap.
+		assert_eq!(mmc1.chr_banks(0x2000).map(|start| start / 0x400), [4, 5, 6, 7, 4, 5, 6, 7]);
+	}
+
+	#[test]
+	fn read_modify_write_test() {
+		/*
+		LDA #$01
+		STA $8000	; 2 bits in the shift register
+		STA $8000
+		INC $8000	; Writes the $FF of the ROM, which empties it, then $00, which is ignored
+		LDA #$00	; Control = %01110: vertical mirroring
+		STA $8000
+		LDA #$01
+		STA $8000
+		STA $8000
+		STA $8000
+		LDA #$00
+		STA $8000
+		*/
+		let program = [
+			0xA9, 0x01, 0x8D, 0x00, 0x80, 0x8D, 0x00, 0x80, 0xEE, 0x00, 0x80, 0xA9, 0x00, 0x8D, 0x00, 0x80,
+			0xA9, 0x01, 0x8D, 0x00, 0x80, 0x8D, 0x00, 0x80, 0x8D, 0x00, 0x80, 0xA9, 0x00, 0x8D, 0x00, 0x80,
+		];
+		let mut prg = vec![0xEA; 0x8000];
+		prg[0] = 0xFF;
+		prg[0x4000..0x4000 + program.len()].copy_from_slice(&program);
+		prg[0x7FFC..0x7FFE].copy_from_slice(&[0x00, 0xC0]);
+		let mut emulator = Emulator::new(Cartridge::from_ines(&test_rom::ines(1, &prg, &[0; 0x2000])).unwrap());
+		emulator.set_cycle_accurate(true);
+		for _ in 0..11 {
+			emulator.step_instruction();
+		}
+		assert_eq!(emulator.bus().cartridge().mirroring(), Mirroring::Horizontal);
+		emulator.step_instruction();
+		assert_eq!(emulator.bus().cartridge().mirroring(), Mirroring::Vertical);
+	}
+}
@@ -0,0 +1,323 @@
+// MMC3 (mapper 4, the TxROM boards): https://www.nesdev.org/wiki/MMC3
+// The cartridge maps PRG ROM in 8KB banks and CHR in 1KB banks, from the registers here. See cartridge.rs.
+//
+// | Address (even / odd) | Register |
+// |---|---|
+// | $8000 / $8001 | Bank select: which bank register, PRG mode (bit 6), CHR inversion (bit 7) / the bank number |
+// | $A000 / $A001 | Mirroring: 0 vertical, 1 horizontal / PRG RAM protect, ignored (MMC6 boards use it differently) |
+// | $C000 / $C001 | IRQ latch / IRQ reload: the counter takes the latch at the next clock |
+// | $E000 / $E001 | IRQ disable, and acknowledge / IRQ enable |
+//
+// The counter is clocked by the rises of A12 on the PPU address bus, so once per scanline when the background and the
+// sprites use different pattern tables: at dot 260 when the background uses the one at $0000 and the sprites the one
+// at $1000 (almost every MMC3 game), at dot 324 the other way around. With 8x16 sprites, at the first slot that uses
+// $1000. The PPU doesn't give every address it fetches, so it tells where the rise is, see `PPU::a12_rose`.
+//
+// The revisions of the chip don't agree on when the counter fires, and some games only work with one (Mickey's
+// Safari in Letterland is the usual example):
+//
+// | Variant | Chips | NES 2.0 | Fires when, after a clock |
+// |---|---|---|---|
+// | New | MMC3B and MMC3C (Sharp) | submapper 0 | The counter is 0. A latch of 0 fires on every scanline |
+// | Old | MMC3A (NEC) | submapper 4 | The counter went from 1 to 0, or was reloaded to 0 after $C001. A latch of 0 fires once |
+//
+// iNES files can't say, so they get the new one, like most games need. `--mmc3-irq` chooses for a game.
+
+use std::fmt;
+
+use crate::cartridge::IrqCounterInfo;
+use crate::mapper::Mapper;
+use crate::ppu::ppu::Mirroring;
+use crate::save_state::{SaveState, StateReader, StateWriter};
+
+const PRG_BANK_SIZE: usize = 8 * 1024;
+const CHR_BANK_SIZE: usize = 1024;
+
+/// When the IRQ counter fires, see the top of the file.
+#[derive(Clone, Copy, PartialEq, Eq, Debug, Default)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub enum IrqVariant {
+	#[default]
+	New,
+	Old,
+}
+
+impl IrqVariant {
+	/// The one of an NES 2.0 submapper of mapper 4. The other submappers (MMC6, and rare clones) have the new one.
+	pub fn from_submapper(submapper: u8) -> Self {
+		if submapper == 4 { IrqVariant::Old } else { IrqVariant::New }
+	}
+}
+
+/// `new`, like `--mmc3-irq`.
+impl fmt::Display for IrqVariant {
+	fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+		match self {
+			IrqVariant::New => write!(f, "new"),
+			IrqVariant::Old => write!(f, "old"),
+		}
+	}
+}
+
+/// The registers of the chip. It doesn't know the sizes of PRG ROM and CHR: the cartridge asks for the banks with them.
+#[derive(Clone, Debug, Default)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct Mmc3 {
+	bank_select: u8,
+	/// R0-R5 are CHR banks (R0 and R1 of 2KB), R6 and R7 PRG banks.
+	banks: [u8; 8],
+	/// None until the game writes $A000: the one of the header until then.
+	mirroring: Option<Mirroring>,
+	irq_variant: IrqVariant,
+	irq_latch: u8,
+	irq_counter: u8,
+	/// $C001 was written: the next clock reloads the counter.
+	irq_reload: bool,
+	irq_enabled: bool,
+	irq_pending: bool,
+}
+
+impl Mmc3 {
+	pub fn new(irq_variant: IrqVariant) -> Self {
+		Mmc3 { irq_variant, ..Self::default() }
+	}
+
+	pub fn irq_variant(&self) -> IrqVariant {
+		self.irq_variant
+	}
+
+	pub fn set_irq_variant(&mut self, variant: IrqVariant) {
+		self.irq_variant = variant;
+	}
+
+	/// The 1KB banks are at $0000, and the 2KB ones at $1000.
+	pub fn chr_inverted(&self) -> bool {
+		self.bank_select & 0x80 != 0
+	}
+}
+
+impl Mapper for Mmc3 {
+	/// A write to $8000-$FFFF. The register is chosen by the range and whether the address is even.
+	fn cpu_write(&mut self, addr: u16, data: u8) -> bool {
+		match (addr & 0xE000, addr & 1 == 0) {
+			(0x8000, true) => self.bank_select = data,
+			(0x8000, false) => self.banks[(self.bank_select & 0b111) as usize] = data,
+			(0xA000, true) => self.mirroring = Some(if data & 1 == 0 { Mirroring::Vertical } else { Mirroring::Horizontal }),
+			(0xA000, false) => {}
+			(0xC000, true) => self.irq_latch = data,
+			(0xC000, false) => {
+				self.irq_counter = 0;
+				self.irq_reload = true;
+			}
+			(_, true) => {
+				self.irq_enabled = false;
+				self.irq_pending = false;
+			}
+			(_, false) => self.irq_enabled = true,
+		}
+		true
+	}
+
+	/// The mirroring the game chose, if it did.
+	fn mirroring(&self) -> Option<Mirroring> {
+		self.mirroring
+	}
+
+	/// Where each 8KB window of $8000-$FFFF starts in PRG ROM. The second to last bank is at $8000 or $C000 (the PRG
+	/// mode), R6 at the other one, R7 at $A000 and the last bank at $E000.
+	fn prg_banks(&self, prg_rom_size: usize) -> [usize; 4] {
+		let bank = |number: usize| number * PRG_BANK_SIZE % prg_rom_size;
+		let second_last = prg_rom_size - 2 * PRG_BANK_SIZE;
+		let (r6, r7) = (bank(self.banks[6] as usize), bank(self.banks[7] as usize));
+		let last = prg_rom_size - PRG_BANK_SIZE;
+		if self.bank_select & 0x40 == 0 {
+			[r6, r7, second_last, last]
+		} else {
+			[second_last, r7, r6, last]
+		}
+	}
+
+	/// For the debugger, see `Cartridge::debug_state`.
+	fn irq_info(&self) -> Option<IrqCounterInfo> {
+		Some(IrqCounterInfo { counter: self.irq_counter, latch: self.irq_latch, enabled: self.irq_enabled, pending: self.irq_pending })
+	}
+
+	/// Where each 1KB window of the pattern tables starts in CHR. The 2KB banks (R0 and R1) are at $0000, and the 1KB
+	/// ones (R2-R5) at $1000, or the other way around with the CHR inversion.
+	fn chr_banks(&self, chr_size: usize) -> [usize; 8] {
+		let bank = |number: u8| number as usize * CHR_BANK_SIZE % chr_size;
+		let r = &self.banks;
+		let two_kb = [bank(r[0] & 0xFE), bank(r[0] | 1), bank(r[1] & 0xFE), bank(r[1] | 1)];
+		let one_kb = [bank(r[2]), bank(r[3]), bank(r[4]), bank(r[5])];
+		let (low, high) = if self.bank_select & 0x80 == 0 { (two_kb, one_kb) } else { (one_kb, two_kb) };
+		core::array::from_fn(|window| if window < 4 { low[window] } else { high[window - 4] })
+	}
+
+	fn prg_slots(&self) -> &'static [usize] {
+		&[PRG_BANK_SIZE; 4]
+	}
+
+	/// The 2KB banks first, unless the CHR is inverted.
+	fn chr_slots(&self) -> &'static [usize] {
+		const KB: usize = 1024;
+		if self.chr_inverted() { &[KB, KB, KB, KB, 2 * KB, 2 * KB] } else { &[2 * KB, 2 * KB, KB, KB, KB, KB] }
+	}
+
+	/// A rise of A12: the counter is reloaded when it's 0 (or after $C001), decremented otherwise. Then it may fire.
+	fn clock_scanline(&mut self) {
+		let before = self.irq_counter;
+		let reloaded = self.irq_reload;
+		if self.irq_counter == 0 || self.irq_reload {
+			self.irq_counter = self.irq_latch;
+			self.irq_reload = false;
+		} else {
+			self.irq_counter -= 1;
+		}
+
+		let fires = match self.irq_variant {
+			IrqVariant::New => self.irq_counter == 0,
+			IrqVariant::Old => self.irq_counter == 0 && (before != 0 || reloaded),
+		};
+		if fires && self.irq_enabled {
+			self.irq_pending = true;
+		}
+	}
+
+	/// Holds the IRQ line, until $E000 is written.
+	fn irq_pending(&self) -> bool {
+		self.irq_pending
+	}
+}
+
+/// The variant is not in the state: it's a setting of the game, like the mapper.
+impl SaveState for Mmc3 {
+	fn save_state(&self, out: &mut StateWriter) {
+		out.u8(self.bank_select);
+		out.bytes(&self.banks);
+		out.u8(match self.mirroring {
+			None => 0,
+			Some(Mirroring::Vertical) => 1,
+			Some(Mirroring::Horizontal) => 2,
+			// MMC3 doesn't choose these, they are here for the match.
+			Some(Mirroring::SingleScreenLower) => 3,
+			Some(Mirroring::SingleScreenUpper) => 4,
+		});
+		out.u8(self.irq_latch);
+		out.u8(self.irq_counter);
+		out.bool(self.irq_reload);
+		out.bool(self.irq_enabled);
+		out.bool(self.irq_pending);
+	}
+
+	fn load_state(&mut self, input: &mut StateReader) -> Result<(), String> {
+		self.bank_select = input.u8()?;
+		input.bytes(&mut self.banks)?;
+		self.mirroring = match input.u8()? {
+			0 => None,
+			1 => Some(Mirroring::Vertical),
+			2 => Some(Mirroring::Horizontal),
+			3 => Some(Mirroring::SingleScreenLower),
+			4 => Some(Mirroring::SingleScreenUpper),
+			other => return Err(format!("Unknown MMC3 mirroring {} in the save state", other)),
+		};
+		self.irq_latch = input.u8()?;
+		self.irq_counter = input.u8()?;
+		self.irq_reload = input.bool()?;
+		self.irq_enabled = input.bool()?;
+		self.irq_pending = input.bool()?;
+		Ok(())
+	}
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+
+	/// Latch `latch`, reload, enable, then clock `clocks` times: the clocks after which the IRQ line was held, each
+	/// acknowledged (and enabled again) right away, like an IRQ handler does.
+	fn fired(variant: IrqVariant, latch: u8, clocks: usize) -> Vec<usize> {
+		let mut mmc3 = Mmc3::new(variant);
+		mmc3.cpu_write(0xC000, latch);
+		mmc3.cpu_write(0xC001, 0);
+		mmc3.cpu_write(0xE001, 0);
+		let mut fired = vec![];
+		for clock in 1..=clocks {
+			mmc3.clock_scanline();
+			if mmc3.irq_pending() {
+				fired.push(clock);
+				mmc3.cpu_write(0xE000, 0);
+				mmc3.cpu_write(0xE001, 0);
+			}
+		}
+		fired
+	}
+
+	#[test]
+	fn irq_counter_test() {
+		// The first clock reloads 2, then 1, 0: fires, reloads...
+		for variant in [IrqVariant::New, IrqVariant::Old] {
+			assert_eq!(fired(variant, 2, 9), vec![3, 6, 9], "{}", variant);
+		}
+
+		// A latch of 0: the new one fires at every clock, the old one only after $C001.
+		assert_eq!(fired(IrqVariant::New, 0, 4), vec![1, 2, 3, 4]);
+		assert_eq!(fired(IrqVariant::Old, 0, 4), vec![1]);
+
+		// Disabled, it counts but doesn't fire. Enabled again, it fires at the next 0.
+		let mut mmc3 = Mmc3::new(IrqVariant::New);
+		mmc3.cpu_write(0xC000, 1);
+		mmc3.cpu_write(0xC001, 0);
+		mmc3.clock_scanline();
+		mmc3.clock_scanline();
+		assert!(!mmc3.irq_pending());
+		mmc3.cpu_write(0xE001, 0);
+		mmc3.clock_scanline();
+		mmc3.clock_scanline();
+		assert!(mmc3.irq_pending());
+		// Held until $E000.
+		mmc3.clock_scanline();
+		assert!(mmc3.irq_pending());
+		mmc3.cpu_write(0xE000, 0);
+		assert!(!mmc3.irq_pending());
+
+		// $C001 in the middle of a count starts it again.
+		let mut mmc3 = Mmc3::new(IrqVariant::Old);
+		mmc3.cpu_write(0xC000, 2);
+		mmc3.cpu_write(0xC001, 0);
+		mmc3.cpu_write(0xE001, 0);
+		mmc3.clock_scanline();
+		mmc3.clock_scanline();
+		mmc3.cpu_write(0xC001, 0);
+		mmc3.clock_scanline();
+		mmc3.clock_scanline();
+		assert!(!mmc3.irq_pending());
+		mmc3.clock_scanline();
+		assert!(mmc3.irq_pending());
+	}
+
+	#[test]
+	fn banks_test() {
+		let mut mmc3 = Mmc3::new(IrqVariant::New);
+		// 8 banks of 8KB.
+		for (register, bank) in [(6, 2), (7, 5)] {
+			mmc3.cpu_write(0x8000, register);
+			mmc3.cpu_write(0x8001, bank);
+		}
+		assert_eq!(mmc3.prg_banks(0x10000), [0x4000, 0xA000, 0xC000, 0xE000]);
+		mmc3.cpu_write(0x8000, 0x40);
+		assert_eq!(mmc3.prg_banks(0x10000), [0xC000, 0xA000, 0x4000, 0xE000]);
+
+		for (register, bank) in [(0, 3), (1, 4), (2, 10), (3, 11), (4, 12), (5, 13)] {
+			mmc3.cpu_write(0x8000, register);
+			mmc3.cpu_write(0x8001, bank);
+		}
+		// R0 ignores its low bit.
+		assert_eq!(mmc3.chr_banks(0x4000).map(|start| start / 0x400), [2, 3, 4, 5, 10, 11, 12, 13]);
+		mmc3.cpu_write(0x8000, 0x80);
+		assert_eq!(mmc3.chr_banks(0x4000).map(|start| start / 0x400), [10, 11, 12, 13, 2, 3, 4, 5]);
+
+		assert_eq!(mmc3.mirroring(), None);
+		mmc3.cpu_write(0xA000, 1);
+		assert_eq!(mmc3.mirroring(), Some(Mirroring::Horizontal));
+	}
+}
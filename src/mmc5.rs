@@ -0,0 +1,559 @@
+// MMC5 (mapper 5, the ExROM boards): https://www.nesdev.org/wiki/MMC5
+// Nintendo's biggest mapper, of Castlevania III, the Koei games and Just Breed. Its registers are at $5000-$5FFF (the
+// expansion area, see `Mapper::expansion_write`), and it watches the PPU: the writes to PPUCTRL, and the fetches of
+// the scanlines.
+//
+// | Address | Register |
+// |---|---|
+// | $5100 | PRG mode: 0 one bank of 32KB, 1 two of 16KB, 2 16KB and two of 8KB, 3 four of 8KB |
+// | $5101 | CHR mode: 0 banks of 8KB, 1 of 4KB, 2 of 2KB, 3 of 1KB |
+// | $5102 / $5103 | PRG RAM protect: writes only with 2 and 1 |
+// | $5104 | ExRAM mode: 0 nametable, 1 nametable and extended attributes, 2 RAM, 3 read only RAM |
+// | $5105 | Nametables: 2 bits each, 0 and 1 the pages of the PPU's VRAM, 2 ExRAM, 3 the fill mode |
+// | $5106 / $5107 | Fill mode: the tile / the palette (bits 0-1) |
+// | $5113 | The 8KB of PRG RAM at $6000 |
+// | $5114-$5117 | PRG banks, in 8KB: the last one of the mode is at $E000, $5117 |
+// | $5120-$5127 | CHR banks of the sprites (set A): in 8KB mode $5127, in 4KB $5123 and $5127, and so on |
+// | $5128-$512B | CHR banks of the background (set B), the same for $0000 and $1000 |
+// | $5130 | The high bits (bits 0-1) of the CHR banks written after it |
+// | $5200 / $5201 / $5202 | Split screen: the tiles (bit 7 on, bit 6 right side, bits 0-4 tiles) / scroll / 4KB CHR bank |
+// | $5203 / $5204 | IRQ scanline / IRQ enable (bit 7), and when read, pending (bit 7) and in frame (bit 6) |
+// | $5205 / $5206 | Multiplier: the 2 factors, and when read, the low and high bytes of the product |
+// | $5C00-$5FFF | ExRAM, 1KB |
+//
+// The sets of CHR banks are for 8x16 sprites: those of the sprites use set A, and the background set B. With 8x8
+// sprites, everything uses the set written last. So there are 2 sets of CHR windows in the cartridge, see
+// `Mapper::background_chr_banks`.
+//
+// With extended attributes (ExRAM mode 1), the ExRAM byte of each nametable byte has the palette of the tile (bits
+// 6-7) and its 4KB CHR bank (bits 0-5). The split screen shows ExRAM as a nametable on the left or right tiles, with
+// its own vertical scroll. Both replace background tiles, see `Mapper::background_tile`.
+//
+// The chip counts the scanlines from the fetches of the PPU (`Mapper::ppu_scanline`): the first one of a frame sets
+// "in frame", the next ones count, and the IRQ is pending at the one of $5203. The frame ends with the fetches, at
+// scanline 240 or when rendering is turned off.
+//
+// Not here: the sound channels (2 pulses and the PCM channel of $5000-$5015), and PRG RAM in $8000-$DFFF (bit 7 clear
+// in $5114-$5116): the banks there are always ROM, which is what the games use.
+
+use crate::cartridge::IrqCounterInfo;
+use crate::mapper::{BackgroundFetch, BackgroundTile, Mapper, Nametable};
+use crate::ppu::ppu::Mirroring;
+use crate::save_state::{SaveState, StateReader, StateWriter};
+
+const KB: usize = 1024;
+const PRG_BANK_SIZE: usize = 8 * KB;
+const CHR_BANK_SIZE: usize = KB;
+/// Extended attributes and the split screen have 4KB CHR banks.
+const BACKGROUND_BANK_SIZE: usize = 4 * KB;
+const EXRAM_SIZE: usize = KB;
+/// Where the attributes are in a nametable.
+const ATTRIBUTES: usize = 0x3C0;
+
+const EXRAM_NAMETABLE: u8 = 0;
+const EXRAM_EXTENDED_ATTRIBUTES: u8 = 1;
+const EXRAM_RAM: u8 = 2;
+
+#[derive(Clone, Debug)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct Mmc5 {
+	prg_mode: u8,
+	chr_mode: u8,
+	prg_ram_protect: [u8; 2],
+	exram_mode: u8,
+	/// $5105.
+	nametables: u8,
+	fill_tile: u8,
+	fill_palette: u8,
+	/// $5113-$5117.
+	prg_banks: [u8; 5],
+	/// $5120-$512B, with the high bits of $5130.
+	chr_banks: [u16; 12],
+	chr_high: u8,
+	/// $5128-$512B were written after $5120-$5127.
+	background_set_last: bool,
+	/// PPUCTRL bit 5.
+	sprites_8x16: bool,
+	split_control: u8,
+	split_scroll: u8,
+	split_bank: u8,
+	irq_scanline: u8,
+	irq_enabled: bool,
+	irq_pending: bool,
+	in_frame: bool,
+	/// The scanline of the frame, from the fetches.
+	scanline: u8,
+	factors: [u8; 2],
+	#[cfg_attr(feature = "serde", serde(with = "crate::serde_bytes_base64"))]
+	exram: [u8; EXRAM_SIZE],
+}
+
+impl Default for Mmc5 {
+	fn default() -> Self {
+		Self::new()
+	}
+}
+
+impl Mmc5 {
+	/// At power on, PRG mode 3 with the last bank at $E000, so the reset vector is there.
+	pub fn new() -> Self {
+		Mmc5 {
+			prg_mode: 3,
+			chr_mode: 0,
+			prg_ram_protect: [0; 2],
+			exram_mode: 0,
+			nametables: 0,
+			fill_tile: 0,
+			fill_palette: 0,
+			prg_banks: [0, 0, 0, 0, 0xFF],
+			chr_banks: [0; 12],
+			chr_high: 0,
+			background_set_last: false,
+			sprites_8x16: false,
+			split_control: 0,
+			split_scroll: 0,
+			split_bank: 0,
+			irq_scanline: 0,
+			irq_enabled: false,
+			irq_pending: false,
+			in_frame: false,
+			scanline: 0,
+			factors: [0xFF; 2],
+			exram: [0; EXRAM_SIZE],
+		}
+	}
+
+	/// The 1KB windows of a set of 8 CHR bank registers, in the CHR mode.
+	fn chr_windows(&self, registers: [u16; 8], chr_size: usize) -> [usize; 8] {
+		core::array::from_fn(|window| {
+			let start = match self.chr_mode {
+				0 => registers[7] as usize * 8 * KB + window * KB,
+				1 => registers[window | 3] as usize * 4 * KB + (window & 3) * KB,
+				2 => registers[window | 1] as usize * 2 * KB + (window & 1) * KB,
+				_ => registers[window] as usize * CHR_BANK_SIZE,
+			};
+			start % chr_size
+		})
+	}
+
+	fn sprite_set(&self) -> [u16; 8] {
+		core::array::from_fn(|register| self.chr_banks[register])
+	}
+
+	/// The 4 registers of set B are for both pattern tables.
+	fn background_set(&self) -> [u16; 8] {
+		core::array::from_fn(|register| self.chr_banks[8 + (register & 3)])
+	}
+
+	/// What the nametable `table` (0-3) is, from $5105.
+	fn nametable(&self, table: u16) -> u8 {
+		(self.nametables >> (table * 2)) & 0b11
+	}
+
+	/// The tiles on the side of the split, counted from the first one fetched.
+	fn in_split(&self, column: u8) -> bool {
+		if self.split_control & 0x80 == 0 || self.exram_mode > EXRAM_EXTENDED_ATTRIBUTES {
+			return false;
+		}
+		let tiles = self.split_control & 0x1F;
+		if self.split_control & 0x40 != 0 { column >= tiles } else { column < tiles }
+	}
+}
+
+impl Mapper for Mmc5 {
+	/// No registers above $8000.
+	fn cpu_write(&mut self, _addr: u16, _data: u8) -> bool {
+		false
+	}
+
+	fn expansion_write(&mut self, addr: u16, data: u8) -> bool {
+		match addr {
+			0x5100 => self.prg_mode = data & 0b11,
+			0x5101 => self.chr_mode = data & 0b11,
+			0x5102 | 0x5103 => self.prg_ram_protect[(addr & 1) as usize] = data & 0b11,
+			0x5104 => self.exram_mode = data & 0b11,
+			0x5105 => self.nametables = data,
+			0x5106 => self.fill_tile = data,
+			0x5107 => self.fill_palette = data & 0b11,
+			0x5113..=0x5117 => self.prg_banks[(addr - 0x5113) as usize] = data,
+			0x5120..=0x512B => {
+				self.chr_banks[(addr - 0x5120) as usize] = (self.chr_high as u16) << 8 | data as u16;
+				self.background_set_last = addr >= 0x5128;
+			}
+			0x5130 => self.chr_high = data & 0b11,
+			0x5200 => self.split_control = data,
+			0x5201 => self.split_scroll = data,
+			0x5202 => self.split_bank = data,
+			0x5203 => self.irq_scanline = data,
+			0x5204 => self.irq_enabled = data & 0x80 != 0,
+			0x5205 | 0x5206 => self.factors[(addr - 0x5205) as usize] = data,
+			0x5C00..=0x5FFF => {
+				let index = (addr - 0x5C00) as usize;
+				match self.exram_mode {
+					// The PPU has it: the CPU writes 0 outside of the frame.
+					EXRAM_NAMETABLE | EXRAM_EXTENDED_ATTRIBUTES => self.exram[index] = if self.in_frame { data } else { 0 },
+					EXRAM_RAM => self.exram[index] = data,
+					_ => (),
+				}
+			}
+			_ => return false,
+		}
+		true
+	}
+
+	fn expansion_peek(&self, addr: u16) -> Option<u8> {
+		match addr {
+			0x5204 => Some((self.irq_pending as u8) << 7 | (self.in_frame as u8) << 6),
+			0x5205 | 0x5206 => {
+				let product = self.factors[0] as u16 * self.factors[1] as u16;
+				Some(product.to_le_bytes()[(addr - 0x5205) as usize])
+			}
+			0x5C00..=0x5FFF if self.exram_mode >= EXRAM_RAM => Some(self.exram[(addr - 0x5C00) as usize]),
+			_ => None,
+		}
+	}
+
+	/// Reading $5204 acknowledges the IRQ.
+	fn expansion_read(&mut self, addr: u16) -> Option<u8> {
+		let value = self.expansion_peek(addr);
+		if addr == 0x5204 {
+			self.irq_pending = false;
+		}
+		value
+	}
+
+	fn prg_banks(&self, prg_rom_size: usize) -> [usize; 4] {
+		let bank = |number: usize| number * PRG_BANK_SIZE % prg_rom_size;
+		let register = |index: usize| (self.prg_banks[index] & 0x7F) as usize;
+		match self.prg_mode {
+			0 => core::array::from_fn(|window| bank((register(4) & !3) + window)),
+			1 => [bank(register(2) & !1), bank(register(2) | 1), bank(register(4) & !1), bank(register(4) | 1)],
+			2 => [bank(register(2) & !1), bank(register(2) | 1), bank(register(3)), bank(register(4))],
+			_ => [bank(register(1)), bank(register(2)), bank(register(3)), bank(register(4))],
+		}
+	}
+
+	fn prg_ram_bank(&self) -> usize {
+		(self.prg_banks[0] & 0b111) as usize
+	}
+
+	fn prg_ram_writable(&self) -> bool {
+		self.prg_ram_protect == [0b10, 0b01]
+	}
+
+	/// Set A with 8x16 sprites, the set written last with 8x8 ones.
+	fn chr_banks(&self, chr_size: usize) -> [usize; 8] {
+		let set = if !self.sprites_8x16 && self.background_set_last { self.background_set() } else { self.sprite_set() };
+		self.chr_windows(set, chr_size)
+	}
+
+	fn background_chr_banks(&self, chr_size: usize) -> [usize; 8] {
+		let set = if self.sprites_8x16 || self.background_set_last { self.background_set() } else { self.sprite_set() };
+		self.chr_windows(set, chr_size)
+	}
+
+	/// PPUCTRL says if the sprites are 8x16.
+	fn ppu_register_write(&mut self, addr: u16, data: u8) -> bool {
+		if addr & 7 != 0 {
+			return false;
+		}
+		self.sprites_8x16 = data & 0x20 != 0;
+		true
+	}
+
+	/// The mirroring, when the 4 nametables are in the PPU's VRAM like one. See `nametables`.
+	fn mirroring(&self) -> Option<Mirroring> {
+		match self.nametables {
+			0x44 => Some(Mirroring::Vertical),
+			0x50 => Some(Mirroring::Horizontal),
+			0x00 => Some(Mirroring::SingleScreenLower),
+			0x55 => Some(Mirroring::SingleScreenUpper),
+			_ => None,
+		}
+	}
+
+	fn nametables(&self) -> Option<[Nametable; 4]> {
+		if self.mirroring().is_some() {
+			return None;
+		}
+		Some(core::array::from_fn(|table| match self.nametable(table as u16) {
+			page @ (0 | 1) => Nametable::Vram(page),
+			_ => Nametable::Cartridge,
+		}))
+	}
+
+	/// ExRAM, while the PPU has it (modes 0 and 1), or the fill mode.
+	fn nametable_read(&self, addr: u16) -> u8 {
+		let index = addr as usize & (EXRAM_SIZE - 1);
+		match self.nametable((addr >> 10) & 0b11) {
+			2 if self.exram_mode <= EXRAM_EXTENDED_ATTRIBUTES => self.exram[index],
+			2 => 0,
+			// The palette is the same for the 4 quadrants.
+			_ if index >= ATTRIBUTES => self.fill_palette * 0b0101_0101,
+			_ => self.fill_tile,
+		}
+	}
+
+	fn nametable_write(&mut self, addr: u16, data: u8) {
+		if self.nametable((addr >> 10) & 0b11) == 2 && self.exram_mode <= EXRAM_EXTENDED_ATTRIBUTES {
+			self.exram[addr as usize & (EXRAM_SIZE - 1)] = data;
+		}
+	}
+
+	/// The tiles of the split screen, from ExRAM, or the extended attributes of the others.
+	fn background_tile(&self, fetch: &BackgroundFetch) -> Option<BackgroundTile> {
+		if self.in_split(fetch.column) {
+			// Its own scroll, from $5201 at scanline 0.
+			let y = (self.split_scroll as usize + fetch.scanline as usize) % 240;
+			let (row, column) = (y / 8, fetch.column as usize & 31);
+			let tile = self.exram[row * 32 + column];
+			let attribute = self.exram[ATTRIBUTES + (row / 4) * 8 + column / 4];
+			let palette = (attribute >> (((row & 2) << 1) | (column & 2))) & 0b11;
+			let pattern = self.split_bank as usize * BACKGROUND_BANK_SIZE + tile as usize * 16 + (y & 7);
+			return Some(BackgroundTile { tile, palette, pattern });
+		}
+		if self.exram_mode != EXRAM_EXTENDED_ATTRIBUTES {
+			return None;
+		}
+		let attribute = self.exram[fetch.nametable_addr as usize & (EXRAM_SIZE - 1)];
+		let bank = (self.chr_high as usize) << 6 | (attribute & 0x3F) as usize;
+		let pattern = bank * BACKGROUND_BANK_SIZE + fetch.tile as usize * 16 + fetch.fine_y as usize;
+		Some(BackgroundTile { tile: fetch.tile, palette: attribute >> 6, pattern })
+	}
+
+	fn ppu_scanline(&mut self, fetching: bool) {
+		if !fetching {
+			self.in_frame = false;
+		} else if self.in_frame {
+			self.scanline = self.scanline.wrapping_add(1);
+			if self.scanline == self.irq_scanline {
+				self.irq_pending = true;
+			}
+		} else {
+			self.in_frame = true;
+			self.scanline = 0;
+			self.irq_pending = false;
+		}
+	}
+
+	fn irq_pending(&self) -> bool {
+		self.irq_pending && self.irq_enabled
+	}
+
+	fn irq_info(&self) -> Option<IrqCounterInfo> {
+		Some(IrqCounterInfo { counter: self.scanline, latch: self.irq_scanline, enabled: self.irq_enabled, pending: self.irq_pending })
+	}
+
+	fn prg_slots(&self) -> &'static [usize] {
+		match self.prg_mode {
+			0 => &[32 * KB],
+			1 => &[16 * KB; 2],
+			2 => &[16 * KB, 8 * KB, 8 * KB],
+			_ => &[8 * KB; 4],
+		}
+	}
+
+	fn chr_slots(&self) -> &'static [usize] {
+		match self.chr_mode {
+			0 => &[8 * KB],
+			1 => &[4 * KB; 2],
+			2 => &[2 * KB; 4],
+			_ => &[KB; 8],
+		}
+	}
+}
+
+impl SaveState for Mmc5 {
+	fn save_state(&self, out: &mut StateWriter) {
+		out.u8(self.prg_mode);
+		out.u8(self.chr_mode);
+		out.bytes(&self.prg_ram_protect);
+		out.u8(self.exram_mode);
+		out.u8(self.nametables);
+		out.u8(self.fill_tile);
+		out.u8(self.fill_palette);
+		out.bytes(&self.prg_banks);
+		for bank in self.chr_banks {
+			out.u16(bank);
+		}
+		out.u8(self.chr_high);
+		out.bool(self.background_set_last);
+		out.bool(self.sprites_8x16);
+		out.u8(self.split_control);
+		out.u8(self.split_scroll);
+		out.u8(self.split_bank);
+		out.u8(self.irq_scanline);
+		out.bool(self.irq_enabled);
+		out.bool(self.irq_pending);
+		out.bool(self.in_frame);
+		out.u8(self.scanline);
+		out.bytes(&self.factors);
+		out.bytes(&self.exram);
+	}
+
+	fn load_state(&mut self, input: &mut StateReader) -> Result<(), String> {
+		self.prg_mode = input.u8()?;
+		self.chr_mode = input.u8()?;
+		input.bytes(&mut self.prg_ram_protect)?;
+		self.exram_mode = input.u8()?;
+		self.nametables = input.u8()?;
+		self.fill_tile = input.u8()?;
+		self.fill_palette = input.u8()?;
+		input.bytes(&mut self.prg_banks)?;
+		for bank in &mut self.chr_banks {
+			*bank = input.u16()?;
+		}
+		self.chr_high = input.u8()?;
+		self.background_set_last = input.bool()?;
+		self.sprites_8x16 = input.bool()?;
+		self.split_control = input.u8()?;
+		self.split_scroll = input.u8()?;
+		self.split_bank = input.u8()?;
+		self.irq_scanline = input.u8()?;
+		self.irq_enabled = input.bool()?;
+		self.irq_pending = input.bool()?;
+		self.in_frame = input.bool()?;
+		self.scanline = input.u8()?;
+		input.bytes(&mut self.factors)?;
+		input.bytes(&mut self.exram)?;
+		Ok(())
+	}
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+
+	#[test]
+	fn prg_banks_test() {
+		// 16 banks of 8KB. Mode 3 at power on: the last bank at $E000.
+		let mut mmc5 = Mmc5::new();
+		let banks = |mmc5: &Mmc5| mmc5.prg_banks(0x20000).map(|start| start / PRG_BANK_SIZE);
+		assert_eq!(banks(&mmc5)[3], 15);
+		for (register, bank) in (0x5114..=0x5117).zip([0x81, 0x85, 0x89, 0x8E]) {
+			mmc5.expansion_write(register, bank);
+		}
+		assert_eq!(banks(&mmc5), [1, 5, 9, 14]);
+		mmc5.expansion_write(0x5100, 2);
+		assert_eq!(banks(&mmc5), [4, 5, 9, 14]);
+		mmc5.expansion_write(0x5100, 1);
+		assert_eq!(banks(&mmc5), [4, 5, 14, 15]);
+		mmc5.expansion_write(0x5100, 0);
+		assert_eq!(banks(&mmc5), [12, 13, 14, 15]);
+		assert_eq!(mmc5.prg_slots(), [0x8000]);
+
+		// PRG RAM takes both values of the protect registers.
+		mmc5.expansion_write(0x5113, 3);
+		assert_eq!(mmc5.prg_ram_bank(), 3);
+		mmc5.expansion_write(0x5102, 2);
+		assert!(!mmc5.prg_ram_writable());
+		mmc5.expansion_write(0x5103, 1);
+		assert!(mmc5.prg_ram_writable());
+	}
+
+	#[test]
+	fn chr_banks_test() {
+		// 256 banks of 1KB.
+		let mut mmc5 = Mmc5::new();
+		let kb = |banks: [usize; 8]| banks.map(|start| start / KB);
+		mmc5.expansion_write(0x5101, 3);
+		for register in 0..12 {
+			mmc5.expansion_write(0x5120 + register, register as u8 + 1);
+		}
+		// 8x8 sprites: set B was written last, for both.
+		assert_eq!(kb(mmc5.chr_banks(0x40000)), [9, 10, 11, 12, 9, 10, 11, 12]);
+		assert_eq!(mmc5.chr_banks(0x40000), mmc5.background_chr_banks(0x40000));
+
+		// 8x16 sprites: set A for the sprites.
+		assert!(mmc5.ppu_register_write(0x2000, 0x20));
+		assert_eq!(kb(mmc5.chr_banks(0x40000)), [1, 2, 3, 4, 5, 6, 7, 8]);
+		assert_eq!(kb(mmc5.background_chr_banks(0x40000)), [9, 10, 11, 12, 9, 10, 11, 12]);
+
+		// 2KB banks: the odd registers. The high bits of $5130.
+		mmc5.expansion_write(0x5101, 2);
+		mmc5.expansion_write(0x5130, 1);
+		mmc5.expansion_write(0x5127, 0);
+		assert_eq!(kb(mmc5.chr_banks(0x100000)), [4, 5, 8, 9, 12, 13, 512, 513]);
+		// 8KB: bank 256 wraps in 1MB.
+		mmc5.expansion_write(0x5101, 0);
+		assert_eq!(kb(mmc5.chr_banks(0x100000)), [0, 1, 2, 3, 4, 5, 6, 7]);
+	}
+
+	#[test]
+	fn nametables_test() {
+		let mut mmc5 = Mmc5::new();
+		mmc5.expansion_write(0x5105, 0x44);
+		assert_eq!((mmc5.mirroring(), mmc5.nametables()), (Some(Mirroring::Vertical), None));
+
+		// VRAM page 0, page 1, ExRAM, fill.
+		mmc5.expansion_write(0x5105, 0b11_10_01_00);
+		assert_eq!(mmc5.mirroring(), None);
+		assert_eq!(mmc5.nametables(), Some([Nametable::Vram(0), Nametable::Vram(1), Nametable::Cartridge, Nametable::Cartridge]));
+		mmc5.nametable_write(0x2805, 0x42);
+		assert_eq!(mmc5.nametable_read(0x2805), 0x42);
+		mmc5.expansion_write(0x5106, 0x33);
+		mmc5.expansion_write(0x5107, 2);
+		assert_eq!((mmc5.nametable_read(0x2C05), mmc5.nametable_read(0x2FC5)), (0x33, 0xAA));
+
+		// The CPU writes 0 outside of the frame, unless ExRAM is RAM.
+		mmc5.expansion_write(0x5C05, 0x11);
+		assert_eq!(mmc5.nametable_read(0x2805), 0);
+		assert_eq!(mmc5.expansion_peek(0x5C05), None);
+		mmc5.expansion_write(0x5104, 2);
+		mmc5.expansion_write(0x5C05, 0x11);
+		assert_eq!(mmc5.expansion_peek(0x5C05), Some(0x11));
+	}
+
+	#[test]
+	fn background_tile_test() {
+		let mut mmc5 = Mmc5::new();
+		let fetch = BackgroundFetch { column: 5, scanline: 20, nametable_addr: 0x2000 + 2 * 32 + 3, tile: 0x12, fine_y: 4 };
+		assert!(mmc5.background_tile(&fetch).is_none());
+
+		// Extended attributes: palette 2, 4KB bank 3.
+		mmc5.expansion_write(0x5104, 2);
+		mmc5.expansion_write(0x5C00 + 2 * 32 + 3, 0x83);
+		mmc5.expansion_write(0x5104, 1);
+		let tile = mmc5.background_tile(&fetch).unwrap();
+		assert_eq!((tile.tile, tile.palette, tile.pattern), (0x12, 2, 3 * 0x1000 + 0x124));
+
+		// The split on the 8 tiles on the left, 100 scanlines down in ExRAM: row 12, and the top left of its attribute.
+		mmc5.expansion_write(0x5104, 2);
+		mmc5.expansion_write(0x5C00 + 12 * 32 + 5, 0x77);
+		mmc5.expansion_write(0x5C00 + ATTRIBUTES as u16 + 3 * 8 + 1, 0b00_00_00_01);
+		mmc5.expansion_write(0x5104, 1);
+		mmc5.expansion_write(0x5200, 0x88);
+		mmc5.expansion_write(0x5201, 80);
+		mmc5.expansion_write(0x5202, 2);
+		let tile = mmc5.background_tile(&fetch).unwrap();
+		assert_eq!((tile.tile, tile.palette, tile.pattern), (0x77, 1, 2 * 0x1000 + 0x774));
+		// The right side of it is not in the split.
+		assert_eq!(mmc5.background_tile(&BackgroundFetch { column: 8, ..fetch }).unwrap().pattern, 3 * 0x1000 + 0x124);
+	}
+
+	#[test]
+	fn irq_test() {
+		let mut mmc5 = Mmc5::new();
+		mmc5.expansion_write(0x5203, 3);
+		mmc5.expansion_write(0x5204, 0x80);
+		// The pre-render scanline, then scanline 0 starts the frame.
+		mmc5.ppu_scanline(false);
+		mmc5.ppu_scanline(true);
+		assert_eq!(mmc5.expansion_peek(0x5204), Some(0x40));
+		mmc5.ppu_scanline(true);
+		mmc5.ppu_scanline(true);
+		assert!(!mmc5.irq_pending());
+		mmc5.ppu_scanline(true);
+		assert!(mmc5.irq_pending());
+		assert_eq!(mmc5.expansion_read(0x5204), Some(0xC0));
+		assert!(!mmc5.irq_pending());
+
+		// Scanline 240 ends the frame.
+		mmc5.ppu_scanline(false);
+		assert_eq!(mmc5.expansion_peek(0x5204), Some(0x00));
+
+		mmc5.expansion_write(0x5205, 200);
+		mmc5.expansion_write(0x5206, 100);
+		assert_eq!((mmc5.expansion_peek(0x5205), mmc5.expansion_peek(0x5206)), (Some(0x20), Some(0x4E)));
+	}
+}
@@ -0,0 +1,410 @@
+// Input movies, in FCEUX's FM2 format: https://fceux.com/web/help/fm2.html
+//
+// A text header of `key value` lines, and then a line for every frame:
+//
+// |0|RLDUTSBA|RLDUTSBA||
+//
+// The first field is commands (1 = soft reset, 2 = hard reset), and then a field for every port. Ports 0 and 1 have
+// the 8 buttons of controllers 1 and 2, a '.' for every released button. Port 2 is empty (the Famicom expansion port,
+// not connected).
+//
+// The emulator is deterministic, so the same inputs from the same starting point always produce the same frames.
+// Movies start from power on, or from a save state (the anchor). FCEUX can't load our save states, so the anchor
+// is in its own header key, `anchor`. FCEUX ignores it, and plays the movie from power on.
+
+use std::fs;
+use std::path::{Path, PathBuf};
+
+use log::info;
+
+use crate::base64;
+use crate::controller::{Button, ButtonState};
+use crate::emulator::Emulator;
+use crate::log_target::INPUT;
+use crate::region::Region;
+
+/// Buttons of a port, in the order of the FM2 input log.
+const FM2_BUTTONS: [(Button, char); 8] = [
+	(Button::Right, 'R'),
+	(Button::Left, 'L'),
+	(Button::Down, 'D'),
+	(Button::Up, 'U'),
+	(Button::Start, 'T'),
+	(Button::Select, 'S'),
+	(Button::B, 'B'),
+	(Button::A, 'A'),
+];
+
+#[derive(Clone, PartialEq, Debug)]
+pub struct Movie {
+	pub rom_filename: String,
+	/// MD5 of the ROM, see `Cartridge::md5`.
+	pub rom_checksum: [u8; 16],
+	pub region: Region,
+	pub guid: String,
+	pub rerecord_count: u32,
+	pub comments: Vec<String>,
+	/// The save state the movie starts from. None means power on.
+	pub anchor: Option<Vec<u8>>,
+	/// Buttons of controllers 1 and 2, one per frame.
+	pub inputs: Vec<[ButtonState; 2]>,
+}
+
+impl Movie {
+	/// A new empty movie for the game in `emulator`. If `from_state`, it starts from the current state of the
+	/// emulator, otherwise the emulator should be freshly powered on.
+	pub fn new(emulator: &Emulator, rom_filename: &str, from_state: bool) -> Self {
+		Movie {
+			rom_filename: rom_filename.to_string(),
+			rom_checksum: emulator.rom_md5(),
+			region: emulator.region(),
+			guid: new_guid(emulator.rom_hash()),
+			rerecord_count: 0,
+			comments: vec!["Recorded with rust-nes-emulator".to_string()],
+			anchor: from_state.then(|| emulator.save_state()),
+			inputs: vec![],
+		}
+	}
+
+	pub fn record(&mut self, buttons: [ButtonState; 2]) {
+		self.inputs.push(buttons);
+	}
+
+	/// The buttons of frame `frame`, None after the end of the movie.
+	pub fn input(&self, frame: usize) -> Option<[ButtonState; 2]> {
+		self.inputs.get(frame).copied()
+	}
+
+	/// Get the emulator to the start of the movie. Refuses movies of another game or region.
+	pub fn start(&self, emulator: &mut Emulator) -> Result<(), String> {
+		if self.rom_checksum != emulator.rom_md5() {
+			return Err(format!("Movie is for another ROM ({})", self.rom_filename));
+		}
+		if self.region != emulator.region() {
+			return Err(format!("Movie is for {}, the console is {}", self.region, emulator.region()));
+		}
+		if let Some(anchor) = &self.anchor {
+			emulator.load_state(anchor).map_err(|err| format!("Can't load the movie start state: {}", err))?;
+		}
+		Ok(())
+	}
+
+	pub fn to_fm2(&self) -> String {
+		let mut fm2 = String::new();
+		fm2 += "version 3\n";
+		fm2 += "emuVersion 0\n";
+		fm2 += &format!("rerecordCount {}\n", self.rerecord_count);
+		fm2 += &format!("palFlag {}\n", (self.region == Region::Pal) as u8);
+		fm2 += &format!("romFilename {}\n", self.rom_filename);
+		fm2 += &format!("romChecksum base64:{}\n", base64::encode(&self.rom_checksum));
+		fm2 += &format!("guid {}\n", self.guid);
+		fm2 += "fourscore 0\nmicrophone 0\nport0 1\nport1 1\nport2 0\nFDS 0\nNewPPU 0\n";
+		for comment in &self.comments {
+			fm2 += &format!("comment {}\n", comment);
+		}
+		if let Some(anchor) = &self.anchor {
+			fm2 += &format!("anchor base64:{}\n", base64::encode(anchor));
+		}
+
+		for [buttons1, buttons2] in &self.inputs {
+			fm2 += &format!("|0|{}|{}||\n", fm2_buttons(*buttons1), fm2_buttons(*buttons2));
+		}
+		fm2
+	}
+
+	pub fn parse_fm2(text: &str) -> Result<Self, String> {
+		let mut movie = Movie {
+			rom_filename: String::new(),
+			rom_checksum: [0; 16],
+			region: Region::Ntsc,
+			guid: String::new(),
+			rerecord_count: 0,
+			comments: vec![],
+			anchor: None,
+			inputs: vec![],
+		};
+
+		for (number, line) in text.lines().enumerate() {
+			let line = line.trim_end();
+			if line.is_empty() {
+				continue;
+			}
+			if line.starts_with('|') {
+				movie.inputs.push(parse_input(line).map_err(|err| format!("Line {}: {}", number + 1, err))?);
+				continue;
+			}
+
+			let (key, value) = line.split_once(' ').unwrap_or((line, ""));
+			let invalid = || format!("Line {}: invalid {} '{}'", number + 1, key, value);
+			match key {
+				"version" if value != "3" => return Err(format!("FM2 version {} is not supported", value)),
+				"romFilename" => movie.rom_filename = value.to_string(),
+				"romChecksum" => {
+					let checksum = base64::decode(value.strip_prefix("base64:").unwrap_or(value)).ok_or_else(invalid)?;
+					movie.rom_checksum = checksum.try_into().map_err(|_| invalid())?;
+				}
+				"palFlag" => movie.region = if value == "1" { Region::Pal } else { Region::Ntsc },
+				"guid" => movie.guid = value.to_string(),
+				"rerecordCount" => movie.rerecord_count = value.parse().map_err(|_| invalid())?,
+				"comment" => movie.comments.push(value.to_string()),
+				"anchor" => movie.anchor = Some(base64::decode(value.strip_prefix("base64:").unwrap_or(value)).ok_or_else(invalid)?),
+				"savestate" => return Err("Movies that start from an FCEUX save state are not supported".to_string()),
+				// 1 is a controller, 2 a Zapper, which isn't recorded.
+				"port0" | "port1" if value != "0" && value != "1" => return Err(format!("Movies with {} {} are not supported, only controllers", key, value)),
+				"fourscore" | "port2" if value != "0" => return Err(format!("Movies with {} {} are not supported, only controllers 1 and 2", key, value)),
+				"binary" if value != "0" => return Err("Binary FM2 input logs are not supported".to_string()),
+				// Everything else is information for FCEUX only.
+				_ => {}
+			}
+		}
+		Ok(movie)
+	}
+
+	pub fn save(&self, path: &Path) -> Result<(), String> {
+		fs::write(path, self.to_fm2()).map_err(|err| format!("Can't write movie {}: {}", path.display(), err))
+	}
+
+	pub fn load(path: &Path) -> Result<Self, String> {
+		let text = fs::read_to_string(path).map_err(|err| format!("Can't read movie {}: {}", path.display(), err))?;
+		Self::parse_fm2(&text).map_err(|err| format!("Movie {}: {}", path.display(), err))
+	}
+}
+
+/// What the frontend does with the controller input.
+pub enum MovieMode {
+	Off,
+	/// Add every frame's input to the movie, and write it to the file at the end.
+	Record(Movie, PathBuf),
+	/// Feed the movie input instead of the live input. After the last frame, the live input is back.
+	Play(Movie, usize),
+}
+
+impl MovieMode {
+	/// The buttons for the next frame, given what the player is pressing. Call exactly once per frame.
+	pub fn input(&mut self, live: [ButtonState; 2]) -> [ButtonState; 2] {
+		match self {
+			MovieMode::Off => live,
+			MovieMode::Record(movie, _) => {
+				movie.record(live);
+				live
+			}
+			MovieMode::Play(movie, frame) => match movie.input(*frame) {
+				Some(buttons) => {
+					*frame += 1;
+					buttons
+				}
+				None => {
+					info!(target: INPUT, "Movie finished after {} frames", movie.inputs.len());
+					*self = MovieMode::Off;
+					live
+				}
+			},
+		}
+	}
+
+	/// Loading states or rewinding in the middle of a movie breaks it.
+	pub fn is_active(&self) -> bool {
+		!matches!(self, MovieMode::Off)
+	}
+
+	/// Write the recorded movie, if recording.
+	pub fn finish(&self) -> Result<(), String> {
+		if let MovieMode::Record(movie, path) = self {
+			movie.save(path)?;
+			info!(target: INPUT, "Recorded {} frames to {}", movie.inputs.len(), path.display());
+		}
+		Ok(())
+	}
+}
+
+/// `|commands|port0|port1|port2|`. An empty port is a controller with nothing pressed.
+fn parse_input(line: &str) -> Result<[ButtonState; 2], String> {
+	let fields: Vec<&str> = line.split('|').collect();
+	if fields.len() < 3 {
+		return Err(format!("expected '|commands|port0|...', got '{}'", line));
+	}
+	if fields[1].trim().parse::<u32>().map_err(|_| format!("invalid commands '{}'", fields[1]))? != 0 {
+		return Err("Reset commands are not supported".to_string());
+	}
+
+	let port1 = fields.get(3).copied().unwrap_or("");
+	Ok([parse_buttons(fields[2], 1)?, parse_buttons(port1, 2)?])
+}
+
+/// `RLDUTSBA`, a '.' (or a space) for every released button.
+fn parse_buttons(field: &str, controller: u8) -> Result<ButtonState, String> {
+	let mut buttons = ButtonState::default();
+	if field.is_empty() {
+		return Ok(buttons);
+	}
+	let field: Vec<char> = field.chars().collect();
+	if field.len() != FM2_BUTTONS.len() {
+		return Err(format!("controller {} should be 8 buttons (RLDUTSBA), got '{}'", controller, field.iter().collect::<String>()));
+	}
+	for (&(button, _), &pressed) in FM2_BUTTONS.iter().zip(&field) {
+		buttons.set(button, pressed != '.' && pressed != ' ');
+	}
+	Ok(buttons)
+}
+
+fn fm2_buttons(buttons: ButtonState) -> String {
+	FM2_BUTTONS.iter().map(|&(button, name)| if buttons.pressed(button) { name } else { '.' }).collect()
+}
+
+/// Like FCEUX: 8-4-4-4-12 hex digits. Only needs to be unique, so the time and the ROM are enough.
+fn new_guid(rom_hash: u32) -> String {
+	let nanos = std::time::SystemTime::now().duration_since(std::time::UNIX_EPOCH).map_or(0, |time| time.as_nanos());
+	let hex = format!("{:08X}{:024X}", rom_hash, nanos & ((1 << 96) - 1));
+	format!("{}-{}-{}-{}-{}", &hex[0..8], &hex[8..12], &hex[12..16], &hex[16..20], &hex[20..32])
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+	use crate::cartridge::{test_rom, Cartridge};
+	use crate::harness::{Harness, StopReason};
+	use crate::hash::crc32;
+
+	/// Reads both controllers every frame, and keeps a histogram of the running sum of the reads of each in RAM,
+	/// so the RAM depends on every input of every frame.
+	fn input_rom() -> Cartridge {
+		/*
+		frame:
+		LDA #$01
+		STA $4016
+		LDA #$00
+		STA $4016 	; Strobe the controllers
+		LDY #$00
+		read:
+		LDA $4016
+		CLC
+		ADC $10
+		STA $10 	; Running sum
+		LDX $10
+		INC $0200,X 	; Histogram of the sums
+		LDA $4017
+		CLC
+		ADC $11
+		STA $11 	; The same for controller 2
+		LDX $11
+		INC $0300,X
+		INY
+		CPY #$08
+		BNE read
+		wait:
+		BIT $2002
+		BPL wait 	; Wait for VBlank
+		JMP frame
+		*/
+		let program = "A9 01 8D 16 40 A9 00 8D 16 40 A0 00 AD 16 40 18 65 10 85 10 A6 10 FE 00 02 \
+			AD 17 40 18 65 11 85 11 A6 11 FE 00 03 C8 C0 08 D0 E1 2C 02 20 10 FB 4C 00 80";
+		Cartridge::from_ines(&test_rom::nrom(program)).unwrap()
+	}
+
+	fn ram_hash(emulator: &Emulator) -> u32 {
+		let ram: Vec<u8> = (0..0x800).map(|addr| emulator.peek(addr)).collect();
+		crc32(&ram)
+	}
+
+	/// Some made up input, different every frame, and for each controller.
+	fn scripted_input(frame: usize) -> [ButtonState; 2] {
+		[
+			ButtonState((frame * 37 % 256) as u8 & if frame.is_multiple_of(5) { 0 } else { 0xFF }).without_opposing_directions(),
+			ButtonState((frame * 53 % 256) as u8 & if frame.is_multiple_of(7) { 0 } else { 0xFF }).without_opposing_directions(),
+		]
+	}
+
+	#[test]
+	fn record_and_play_test() {
+		let mut emulator = Emulator::new(input_rom());
+		let mut movie = Movie::new(&emulator, "input.nes", false);
+		for frame in 0..600 {
+			let buttons = scripted_input(frame);
+			movie.record(buttons);
+			emulator.set_controllers(buttons);
+			emulator.run_frame();
+		}
+		let recorded_hash = ram_hash(&emulator);
+
+		let movie = Movie::parse_fm2(&movie.to_fm2()).unwrap();
+		assert_eq!(movie.inputs.len(), 600);
+		let mut player = Emulator::new(input_rom());
+		movie.start(&mut player).unwrap();
+		for frame in 0..600 {
+			player.set_controllers(movie.input(frame).unwrap());
+			player.run_frame();
+		}
+		assert_eq!(ram_hash(&player), recorded_hash);
+
+		// Headless playback, instruction by instruction, feeds the input at the same points.
+		let mut harness = Harness::new(Emulator::new(input_rom())).max_frames(600);
+		harness.set_inputs(movie.inputs.clone());
+		assert_eq!(harness.run(), StopReason::BudgetExhausted);
+		assert_eq!(ram_hash(harness.emulator()), recorded_hash);
+
+		// Make sure the test tests something: other inputs give another RAM.
+		let mut other = Emulator::new(input_rom());
+		for _ in 0..600 {
+			other.run_frame();
+		}
+		assert_ne!(ram_hash(&other), recorded_hash);
+	}
+
+	#[test]
+	fn anchor_test() {
+		let mut emulator = Emulator::new(input_rom());
+		for frame in 0..30 {
+			emulator.set_controllers(scripted_input(frame));
+			emulator.run_frame();
+		}
+
+		let mut movie = Movie::new(&emulator, "input.nes", true);
+		for frame in 30..60 {
+			movie.record(scripted_input(frame));
+			emulator.set_controllers(scripted_input(frame));
+			emulator.run_frame();
+		}
+
+		let movie = Movie::parse_fm2(&movie.to_fm2()).unwrap();
+		let mut player = Emulator::new(input_rom());
+		movie.start(&mut player).unwrap();
+		for frame in 0..30 {
+			player.set_controllers(movie.input(frame).unwrap());
+			player.run_frame();
+		}
+		assert_eq!(ram_hash(&player), ram_hash(&emulator));
+	}
+
+	#[test]
+	fn fm2_test() {
+		let emulator = Emulator::new(input_rom());
+		let mut movie = Movie::new(&emulator, "input.nes", false);
+		let mut buttons = ButtonState::default();
+		buttons.set(Button::A, true);
+		buttons.set(Button::Up, true);
+		let mut buttons2 = ButtonState::default();
+		buttons2.set(Button::Start, true);
+		movie.record([buttons, buttons2]);
+
+		let fm2 = movie.to_fm2();
+		assert!(fm2.contains("\nromFilename input.nes\n"));
+		assert!(fm2.contains("\npalFlag 0\n"));
+		assert!(fm2.contains("\nport1 1\n"));
+		assert!(fm2.ends_with("|0|...U...A|....T...||\n"));
+		assert_eq!(Movie::parse_fm2(&fm2).unwrap(), movie);
+
+		// Movies without controller 2 have an empty port 1.
+		let one_player = Movie::parse_fm2("version 3\nport1 0\n|0|...U...A|||\n").unwrap();
+		assert_eq!(one_player.inputs, vec![[buttons, ButtonState::default()]]);
+
+		assert!(Movie::parse_fm2("version 3\n|0|...U...|||\n").is_err());
+		assert!(Movie::parse_fm2("version 3\n|0|........|...|\n").is_err());
+		assert!(Movie::parse_fm2("version 3\n|1|........|||\n").is_err());
+		// A Zapper.
+		assert!(Movie::parse_fm2("version 3\nport1 2\n").is_err());
+
+		// Another game.
+		let mut other = Emulator::new(Cartridge::from_ines(&test_rom::nrom("4C 00 80")).unwrap());
+		assert!(movie.start(&mut other).is_err());
+	}
+}
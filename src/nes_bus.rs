@@ -0,0 +1,1235 @@
+// CPU memory map: https://www.nesdev.org/wiki/CPU_memory_map
+//
+// | Address range | Size | Device |
+// |---|---|---|
+// | $0000 - $07FF | $0800 | 2KB internal RAM |
+// | $0800 - $1FFF | $1800 | Mirrors of $0000 - $07FF |
+// | $2000 - $2007 | $0008 | PPU registers |
+// | $2008 - $3FFF | $1FF8 | Mirrors of $2000 - $2007 (repeats every 8 bytes) |
+// | $4000 - $4017 | $0018 | APU and I/O registers |
+// | $4018 - $401F | $0008 | APU and I/O functionality that is normally disabled |
+// | $4020 - $FFFF | $BFE0 | Cartridge space: PRG ROM, PRG RAM, and mapper registers |
+
+use core::fmt;
+use core::ops::RangeInclusive;
+
+use log::{debug, warn};
+
+use crate::access_trace::{AccessKind, AccessKinds, AccessSink, AccessTracer};
+use crate::apu::apu::APU;
+use crate::bus::Bus;
+use crate::cartridge::Cartridge;
+use crate::controller::Joypad;
+use crate::error::NesError;
+use crate::irq::IrqLine;
+use crate::log_target::BUS;
+use crate::ppu::ppu::{DOTS_PER_SCANLINE, PPU};
+use crate::ram_init::RamInitPattern;
+use crate::region::Region;
+use crate::save_state::{SaveState, StateReader, StateWriter};
+use crate::zapper::Zapper;
+
+/// CPU cycles the CPU is stalled for, every time the DMC reads a sample byte: a cycle to halt, a dummy cycle, and the
+/// read. One more when it starts on an even cycle, to align the read, like for OAM DMA.
+/// NOTE: The halt also waits for the writes of the CPU (up to 3, for an interrupt), which makes it shorter. Not here.
+const DMC_DMA_STALL_CYCLES: u64 = 3;
+
+/// In the middle of an OAM DMA, the CPU is already halted and aligned.
+const DMC_DMA_STALL_CYCLES_IN_OAM_DMA: u64 = 2;
+
+/// CPU cycles the CPU is stalled for by OAM DMA: a cycle to halt, then a read and a write for each of the 256 bytes.
+/// One more when it starts on an odd cycle, to align the reads.
+const OAM_DMA_STALL_CYCLES: u64 = 1 + 256 * 2;
+
+/// A write to PRG ROM, which is never writable. A bug in the program, usually a stray STA.
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct RomWriteViolation {
+	/// The instruction that wrote.
+	pub pc: u16,
+	pub addr: u16,
+	pub value: u8,
+}
+
+impl fmt::Display for RomWriteViolation {
+	fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+		write!(f, "Write of ${:02X} to ROM at ${:04X}, by the instruction at ${:04X}", self.value, self.addr, self.pc)
+	}
+}
+
+/// A read or write of the CPU test registers, $4018-$401F. They are disabled on retail consoles, so a program that
+/// touches them is usually wrong.
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct UnmappedAccess {
+	/// The instruction that accessed.
+	pub pc: u16,
+	pub addr: u16,
+	/// The value written, or the open bus value the read returned.
+	pub value: u8,
+	pub kind: AccessKind,
+}
+
+impl fmt::Display for UnmappedAccess {
+	fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+		match self.kind {
+			AccessKind::Read => write!(f, "Read of the disabled register ${:04X}", self.addr)?,
+			AccessKind::Write => write!(f, "Write of ${:02X} to the disabled register ${:04X}", self.value, self.addr)?,
+		}
+		write!(f, ", by the instruction at ${:04X}", self.pc)
+	}
+}
+
+/// A read of RAM that was never written, since the strict mode was set (`NesBus::set_strict_ram`). What is there is
+/// whatever the RAM had at power on, which differs between consoles: a bug in the program, usually a missing
+/// initialization.
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct UninitializedRead {
+	/// The instruction that read.
+	pub pc: u16,
+	/// As the CPU read it, before mirroring.
+	pub addr: u16,
+}
+
+impl fmt::Display for UninitializedRead {
+	fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+		write!(f, "Read of ${:04X}, which was never written, by the instruction at ${:04X}", self.addr, self.pc)
+	}
+}
+
+/// A bit for every byte of RAM, set when it's written: the 2KB of internal RAM, then the 8KB of PRG RAM.
+#[derive(Clone)]
+struct WrittenBytes([u64; (0x800 + 0x2000) / 64]);
+
+impl WrittenBytes {
+	/// The bit of `addr`, None when it's not RAM.
+	fn index(addr: u16) -> Option<usize> {
+		match addr {
+			0x0000..=0x1FFF => Some((addr & 0x07FF) as usize),
+			0x6000..=0x7FFF => Some(0x800 + (addr - 0x6000) as usize),
+			_ => None,
+		}
+	}
+
+	fn get(&self, index: usize) -> bool {
+		self.0[index / 64] & (1 << (index % 64)) != 0
+	}
+
+	fn set(&mut self, index: usize) {
+		self.0[index / 64] |= 1 << (index % 64);
+	}
+}
+
+/// The bus of the NES console: internal RAM, PPU, APU, and the cartridge.
+///
+/// The bus is also the master clock. The CPU executes a whole instruction at once, so the other devices are
+/// "caught up" through `tick`: the PPU runs exactly 3 dots for every CPU cycle (3.2 on PAL), and the APU runs 1 cycle.
+/// The CPU ticks the bus until the last cycle of the instruction before executing it, because that's when most
+/// instructions access memory. So a read of $2002 sees the PPU like the real CPU would, give or take a cycle.
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct NesBus {
+	#[cfg_attr(feature = "serde", serde(with = "crate::serde_bytes_base64"))]
+	ram: [u8; 0x800],
+	ppu: PPU,
+	apu: APU,
+	controller1: Joypad,
+	controller2: Joypad,
+	/// In port 2, once the frontend aims it, instead of controller 2. It's input, like the buttons, so it's not in
+	/// save states.
+	zapper: Option<Zapper>,
+	cartridge: Cartridge,
+	region: Region,
+	/// PAL runs 16 dots every 5 CPU cycles. Dots owed to the PPU, times the denominator.
+	dot_remainder: u32,
+	cycles: u64,
+	stall_cycles: u64,
+	/// The page written to $4014. The DMA starts after the cycle of the write, which the CPU ticks right after, so it's
+	/// never pending between instructions and it's not in save states.
+	oam_dma_page: Option<u8>,
+	/// The address of the read of the CPU in this cycle, which it repeats when the DMC halts it. Like `oam_dma_page`,
+	/// it's gone by the end of the instruction.
+	cpu_read: Option<u16>,
+	/// Extra scanlines of CPU time at the end of every VBlank, see `set_overclock`. A setting, not in save states.
+	overclock_scanlines: u16,
+	/// CPU cycles left in the extra scanlines, while the PPU and the APU wait.
+	overclock_left: u32,
+	/// The extra scanlines aren't a whole number of CPU cycles: dots owed, times the denominator, like `dot_remainder`.
+	overclock_remainder: u32,
+	overclock_cycles: u64,
+	/// Record writes to ROM in `rom_write_violations`, instead of just dropping them.
+	strict_rom: bool,
+	rom_write_violations: Vec<RomWriteViolation>,
+	/// Record accesses to $4018-$401F in `unmapped_accesses`.
+	strict_io: bool,
+	unmapped_accesses: Vec<UnmappedAccess>,
+	/// None until `set_strict_ram`, so there's no cost without it. Not in save states.
+	#[cfg_attr(feature = "serde", serde(skip))]
+	ram_written: Option<Box<WrittenBytes>>,
+	uninitialized_reads: Vec<UninitializedRead>,
+	/// The last value on the data bus, which reads of nothing return. Every access sets it again (the CPU fetches
+	/// its operands first), so it's not in save states.
+	open_bus: u8,
+	/// Address of the instruction being executed, for `rom_write_violations`. The CPU sets it.
+	instruction_pc: u16,
+	/// None until `trace_accesses`, so there's no cost without it. Not in save states.
+	#[cfg_attr(feature = "serde", serde(skip))]
+	access_trace: Option<Box<AccessTracer>>,
+}
+
+impl NesBus {
+	/// The region comes from the cartridge header.
+	pub fn new(cartridge: Cartridge) -> Self {
+		let region = cartridge.region();
+		Self::with_region(cartridge, region)
+	}
+
+	pub fn with_region(cartridge: Cartridge, region: Region) -> Self {
+		let mut ppu = PPU::new();
+		ppu.mirroring = cartridge.mirroring();
+		ppu.set_region(region);
+		let mut apu = APU::new();
+		apu.set_region(region);
+
+		NesBus {
+			ram: [0; 0x800],
+			ppu,
+			apu,
+			controller1: Joypad::new(),
+			controller2: Joypad::new(),
+			zapper: None,
+			cartridge,
+			region,
+			dot_remainder: 0,
+			cycles: 0,
+			stall_cycles: 0,
+			oam_dma_page: None,
+			cpu_read: None,
+			overclock_scanlines: 0,
+			overclock_left: 0,
+			overclock_remainder: 0,
+			overclock_cycles: 0,
+			strict_rom: false,
+			rom_write_violations: vec![],
+			strict_io: false,
+			unmapped_accesses: vec![],
+			ram_written: None,
+			uninitialized_reads: vec![],
+			open_bus: 0,
+			instruction_pc: 0,
+			access_trace: None,
+		}
+	}
+
+	/// Fill the internal RAM, and the RAM of the cartridge, with `pattern`, like at power on. The rest of the bus
+	/// keeps its state.
+	pub fn power_on(&mut self, pattern: RamInitPattern) {
+		let mut filler = pattern.filler();
+		filler.fill(&mut self.ram);
+		self.cartridge.power_on(&mut filler);
+		// Nothing was written to the new contents yet.
+		if self.ram_written.is_some() {
+			self.set_strict_ram(true);
+		}
+	}
+
+	/// Amount of CPU cycles since power on, including cycles the CPU was stalled (DMA, for example).
+	pub fn cycles(&self) -> u64 {
+		self.cycles
+	}
+
+	/// Amount of CPU cycles the CPU was stalled by DMA since power on.
+	pub fn stall_cycles(&self) -> u64 {
+		self.stall_cycles
+	}
+
+	pub fn region(&self) -> Region {
+		self.region
+	}
+
+	/// Overclock: at the end of every VBlank, stop the PPU and the APU for `scanlines` scanlines, and let the CPU run
+	/// alone (113.67 cycles a scanline on NTSC), for games that slow down when they have too much to do in a frame.
+	/// The frame, the NMI, sprite 0 and the IRQs keep their timing: nothing happens on the PPU at that point, and
+	/// the APU doesn't see the time go by, so neither the audio nor the DMC fetches change. 0, the default, is off.
+	/// The cycles count in `cycles`, and in `overclock_cycles` too.
+	pub fn set_overclock(&mut self, scanlines: u16) {
+		self.overclock_scanlines = scanlines;
+	}
+
+	pub fn overclock(&self) -> u16 {
+		self.overclock_scanlines
+	}
+
+	/// How loud the sound channels of the cartridge (VRC6, FDS) are in the mix, 1.0 by default. See `APU::set_expansion_volume`.
+	pub fn set_expansion_volume(&mut self, volume: f32) {
+		self.apu.set_expansion_volume(volume);
+	}
+
+	/// The APU channels in the mix, see `APU::set_channel_mask`.
+	pub fn set_channel_mask(&mut self, mask: u8) {
+		self.apu.set_channel_mask(mask);
+	}
+
+	/// Mix every APU channel alone too, see `APU::capture_channels`.
+	pub fn capture_channels(&mut self, capture: bool) {
+		self.apu.capture_channels(capture);
+	}
+
+	/// CPU cycles run in the extra scanlines since power on.
+	pub fn overclock_cycles(&self) -> u64 {
+		self.overclock_cycles
+	}
+
+	/// The 2KB of internal RAM, mirrored at $0000-$1FFF.
+	pub fn ram(&self) -> &[u8; 0x800] {
+		&self.ram
+	}
+
+	pub fn ppu(&self) -> &PPU {
+		&self.ppu
+	}
+
+	pub fn ppu_mut(&mut self) -> &mut PPU {
+		&mut self.ppu
+	}
+
+	pub fn apu(&self) -> &APU {
+		&self.apu
+	}
+
+	pub fn cartridge(&self) -> &Cartridge {
+		&self.cartridge
+	}
+
+	/// Ejects the disk of the FDS, and inserts `side` 2 seconds later. See `Cartridge::insert_disk`.
+	pub fn insert_disk(&mut self, side: Option<usize>) -> Result<(), String> {
+		self.cartridge.insert_disk(side)
+	}
+
+	/// Put back the battery backed PRG RAM, see `Cartridge::load_ram`.
+	pub fn load_ram(&mut self, data: &[u8]) -> Result<(), NesError> {
+		self.cartridge.load_ram(data)
+	}
+
+	pub fn controller1_mut(&mut self) -> &mut Joypad {
+		&mut self.controller1
+	}
+
+	pub fn controller2_mut(&mut self) -> &mut Joypad {
+		&mut self.controller2
+	}
+
+	/// Plug the Zapper in port 2 (or take it out, with None, and controller 2 is back).
+	pub fn set_zapper(&mut self, zapper: Option<Zapper>) {
+		self.zapper = zapper;
+	}
+
+	/// Strict mode, for test programs: writes to ROM are recorded in `rom_write_violations`. They are dropped either way.
+	pub fn set_strict_rom(&mut self, strict: bool) {
+		self.strict_rom = strict;
+	}
+
+	/// The writes to ROM since strict mode was set, oldest first.
+	pub fn rom_write_violations(&self) -> &[RomWriteViolation] {
+		&self.rom_write_violations
+	}
+
+	/// Strict mode for $4018-$401F, the CPU test registers: reads and writes there are recorded in
+	/// `unmapped_accesses`. Either way, writes are dropped and reads return open bus.
+	pub fn set_strict_io(&mut self, strict: bool) {
+		self.strict_io = strict;
+	}
+
+	/// The accesses to $4018-$401F since strict mode was set, oldest first.
+	pub fn unmapped_accesses(&self) -> &[UnmappedAccess] {
+		&self.unmapped_accesses
+	}
+
+	/// Strict mode for RAM, the internal RAM and PRG RAM: a read of a byte that wasn't written since is recorded in
+	/// `uninitialized_reads`, and logged. Only the first read of every byte, and only the reads of the CPU (the
+	/// instructions, and the stack). The fill at power on is not a write, and the stack pushes are. The trainer
+	/// ($7000-$71FF) is part of the ROM file, so it counts as written.
+	/// NOTE: With `CPU::set_cycle_accurate`, the dummy reads of the CPU count too.
+	pub fn set_strict_ram(&mut self, strict: bool) {
+		self.ram_written = strict.then(|| {
+			let mut written = Box::new(WrittenBytes([0; (0x800 + 0x2000) / 64]));
+			if self.cartridge.has_trainer() {
+				for addr in 0x7000..0x7200 {
+					written.set(WrittenBytes::index(addr).unwrap());
+				}
+			}
+			written
+		});
+	}
+
+	/// The reads of RAM that was never written, since strict mode was set, oldest first.
+	pub fn uninitialized_reads(&self) -> &[UninitializedRead] {
+		&self.uninitialized_reads
+	}
+
+	fn check_initialized(&mut self, addr: u16) {
+		let Some(written) = self.ram_written.as_mut() else {
+			return;
+		};
+		if let Some(index) = WrittenBytes::index(addr) {
+			if !written.get(index) {
+				// Once is enough: the next reads are of the same missing write.
+				written.set(index);
+				let read = UninitializedRead { pc: self.instruction_pc, addr };
+				warn!(target: BUS, "{}", read);
+				self.uninitialized_reads.push(read);
+			}
+		}
+	}
+
+	fn mark_written(&mut self, addr: u16) {
+		if let (Some(written), Some(index)) = (self.ram_written.as_mut(), WrittenBytes::index(addr)) {
+			written.set(index);
+		}
+	}
+
+	fn record_unmapped(&mut self, addr: u16, value: u8, kind: AccessKind) {
+		if self.strict_io {
+			debug!(target: BUS, "Access to disabled register {:#X}, {:?}, value: {:#X}, PC: {:#X}", addr, kind, value, self.instruction_pc);
+			self.unmapped_accesses.push(UnmappedAccess { pc: self.instruction_pc, addr, value, kind });
+		}
+	}
+
+	/// Send the reads and/or writes of the CPU in `range` to `sink`, see access_trace.rs. The addresses are the ones
+	/// the CPU used, before mirroring: a trace of $0200-$02FF doesn't see a write to $0A00.
+	pub fn trace_accesses(&mut self, range: RangeInclusive<u16>, kinds: AccessKinds, sink: Box<dyn AccessSink>) {
+		self.access_trace.get_or_insert_with(Default::default).add(range, kinds, sink);
+	}
+
+	/// Stop all the access traces.
+	pub fn stop_access_traces(&mut self) {
+		self.access_trace = None;
+	}
+
+	/// A single CPU cycle of the rest of the machine. In the extra scanlines of the overclock, there's none.
+	fn clock(&mut self) {
+		self.cycles += 1;
+		if self.overclock_left > 0 {
+			self.overclock_left -= 1;
+			self.overclock_cycles += 1;
+			return;
+		}
+
+		let (numerator, denominator) = self.region.ppu_dots_per_cpu_cycle();
+		self.dot_remainder += numerator;
+		while self.dot_remainder >= denominator {
+			self.dot_remainder -= denominator;
+			self.ppu.tick(&self.cartridge);
+			if self.ppu.a12_rose() {
+				self.cartridge.clock_scanline();
+			}
+			if let Some(fetching) = self.ppu.scanline_fetches() {
+				self.cartridge.ppu_scanline(fetching);
+			}
+			if self.overclock_scanlines > 0 && self.ppu.dot() == 0 && self.ppu.scanline() == self.region.prerender_scanline() {
+				self.start_overclock();
+			}
+		}
+		// The cartridge waits with the APU, its sound channels are part of the audio.
+		self.cartridge.clock_cpu();
+		self.apu.set_expansion(self.cartridge.audio_level());
+		self.apu.tick(1);
+	}
+
+	/// Copy the 256 bytes of `page` to OAM, through $2004, so it starts at OAMADDR. The CPU is stalled, so the rest of the
+	/// machine keeps running without it, like for the DMC.
+	fn oam_dma(&mut self, page: u8) {
+		debug!(target: BUS, "OAM DMA from page {:#04X}", page);
+		let stall = OAM_DMA_STALL_CYCLES + self.cycles % 2;
+		for _ in 0..stall - 256 * 2 {
+			self.oam_dma_clock();
+		}
+		for low in 0..=0xFF {
+			let data = self.read_device(u16::from_le_bytes([low, page]));
+			self.open_bus = data;
+			self.oam_dma_clock();
+			self.ppu.cpu_write(0x2004, data, &mut self.cartridge);
+			self.oam_dma_clock();
+		}
+		self.stall_cycles += stall;
+	}
+
+	/// A cycle of OAM DMA. The DMC still gets its samples, in between.
+	fn oam_dma_clock(&mut self) {
+		self.clock();
+		if self.apu.dmc_dma_request().is_some() {
+			self.dmc_dma(DMC_DMA_STALL_CYCLES_IN_OAM_DMA);
+		}
+	}
+
+	/// The DMC reads its next sample byte, and the CPU waits for `stall` cycles.
+	fn dmc_dma(&mut self, stall: u64) {
+		let Some(addr) = self.apu.dmc_dma_request() else {
+			return;
+		};
+		let data = self.read_device(addr);
+		self.open_bus = data;
+		self.apu.dmc_dma_complete(data);
+		for _ in 0..stall {
+			self.clock();
+		}
+		self.stall_cycles += stall;
+	}
+
+	/// VBlank is over: the extra scanlines start with the next CPU cycle.
+	fn start_overclock(&mut self) {
+		let (numerator, denominator) = self.region.ppu_dots_per_cpu_cycle();
+		let dots = self.overclock_remainder + self.overclock_scanlines as u32 * DOTS_PER_SCANLINE as u32 * denominator;
+		self.overclock_left = dots / numerator;
+		self.overclock_remainder = dots % numerator;
+	}
+
+	/// The device behind `addr` for a read of the CPU (or the DMC).
+	fn read_device(&mut self, addr: u16) -> u8 {
+		match addr {
+			0x0000..=0x1FFF => self.ram[(addr & 0x07FF) as usize],
+			0x2000..=0x3FFF => self.ppu.cpu_read(addr, &self.cartridge),
+			0x4015 => self.apu.cpu_read(addr),
+			// The upper bits are open bus, usually the high byte of the address ($40).
+			0x4016 => 0x40 | self.controller1.read(),
+			0x4017 => match &self.zapper {
+				Some(zapper) => 0x40 | zapper.read(&self.ppu),
+				None => 0x40 | self.controller2.read(),
+			},
+			// The APU registers (and OAMDMA) are write only.
+			0x4000..=0x4014 => self.open_bus,
+			0x4018..=0x401F => {
+				self.record_unmapped(addr, self.open_bus, AccessKind::Read);
+				self.open_bus
+			}
+			0x4020..=0x5FFF => self.cartridge.expansion_read(addr).unwrap_or(self.open_bus),
+			// A NES 2.0 cartridge without PRG RAM, or a mapper that turned it off.
+			0x6000..=0x7FFF if !self.cartridge.prg_ram_enabled() => self.open_bus,
+			0x6000..=0xFFFF => self.cartridge.cpu_read(addr),
+		}
+	}
+
+	fn write_device(&mut self, addr: u16, data: u8) {
+		match addr {
+			0x0000..=0x1FFF => self.ram[(addr & 0x07FF) as usize] = data,
+			0x2000..=0x3FFF => {
+				self.ppu.cpu_write(addr, data, &mut self.cartridge);
+				self.cartridge.ppu_register_write(addr, data);
+			}
+			// $4017 is the APU frame counter for writes, and port 2 for reads.
+			0x4000..=0x4013 | 0x4015 | 0x4017 => self.apu.cpu_write(addr, data),
+			// The strobe goes to both ports.
+			0x4016 => {
+				self.controller1.write(data);
+				self.controller2.write(data);
+			}
+			0x4014 => self.oam_dma_page = Some(data),
+			0x4018..=0x401F => self.record_unmapped(addr, data, AccessKind::Write),
+			0x4020..=0xFFFF => {
+				if !self.cartridge.cpu_write(addr, data) && addr >= 0x8000 && self.strict_rom {
+					debug!(target: BUS, "Write to ROM at {:#X}, data: {:#X}, PC: {:#X}", addr, data, self.instruction_pc);
+					self.rom_write_violations.push(RomWriteViolation { pc: self.instruction_pc, addr, value: data });
+				}
+				// MMC3 sets it.
+				self.ppu.mirroring = self.cartridge.mirroring();
+			}
+		}
+	}
+}
+
+impl Bus for NesBus {
+	fn read(&mut self, addr: u16) -> u8 {
+		self.check_initialized(addr);
+		self.cpu_read = Some(addr);
+		let value = self.read_device(addr);
+		self.open_bus = value;
+		if let Some(trace) = &mut self.access_trace {
+			trace.record(addr, value, AccessKind::Read);
+		}
+		value
+	}
+
+	fn peek(&self, addr: u16) -> u8 {
+		match addr {
+			0x0000..=0x1FFF => self.ram[(addr & 0x07FF) as usize],
+			0x4015 => self.apu.status(),
+			0x2000..=0x401F => 0,
+			0x4020..=0xFFFF => self.cartridge.cpu_read(addr),
+		}
+	}
+
+	fn write(&mut self, addr: u16, data: u8) {
+		if let Some(trace) = &mut self.access_trace {
+			trace.record(addr, data, AccessKind::Write);
+		}
+		self.open_bus = data;
+		self.mark_written(addr);
+		self.write_device(addr, data);
+	}
+
+	/// Not traced, and PRG ROM is written without a `RomWriteViolation`, see `Cartridge::poke`. RAM is written for
+	/// `set_strict_ram`.
+	fn poke(&mut self, addr: u16, data: u8) {
+		self.mark_written(addr);
+		match addr {
+			0x4020..=0xFFFF => self.cartridge.poke(addr, data),
+			_ => self.write_device(addr, data),
+		}
+	}
+
+	fn instruction_start(&mut self, pc: u16, _cycles: u64) {
+		self.instruction_pc = pc;
+		// The bus counts the DMA stalls too, so its cycles are the ones of the trace log.
+		if let Some(trace) = &mut self.access_trace {
+			trace.instruction_start(pc, self.cycles);
+		}
+	}
+	/// The PPU is 3 times faster than the CPU (3.2 on PAL).
+	/// The DMC reads its samples here, and OAM DMA copies the sprites: the CPU is stalled, so the rest of the machine
+	/// keeps running without it.
+	fn tick(&mut self, cycles: u8) {
+		for _ in 0..cycles {
+			self.clock();
+			let cpu_read = self.cpu_read.take();
+
+			if let Some(page) = self.oam_dma_page.take() {
+				self.oam_dma(page);
+			}
+
+			if self.apu.dmc_dma_request().is_some() {
+				// The halted CPU reads again: a controller port is clocked twice, and a bit is lost.
+				if let Some(addr @ 0x4016..=0x4017) = cpu_read {
+					self.read_device(addr);
+				}
+				let stall = DMC_DMA_STALL_CYCLES + self.cycles.is_multiple_of(2) as u64;
+				self.dmc_dma(stall);
+			}
+		}
+	}
+
+	/// The APU, and the mapper (MMC3).
+	fn irq_sources(&self) -> IrqLine {
+		self.apu.irq_line().join(self.cartridge.irq_line())
+	}
+
+	/// The PPU, at the start of VBlank, see `PPU::take_nmi`.
+	fn take_nmi(&mut self) -> bool {
+		self.ppu.take_nmi()
+	}
+}
+
+impl SaveState for NesBus {
+	fn save_state(&self, out: &mut StateWriter) {
+		out.u8(self.region as u8);
+		out.bytes(&self.ram);
+		out.u32(self.dot_remainder);
+		out.u64(self.cycles);
+		out.u64(self.stall_cycles);
+		out.u32(self.overclock_left);
+		out.u32(self.overclock_remainder);
+		out.u64(self.overclock_cycles);
+		self.ppu.save_state(out);
+		self.apu.save_state(out);
+		self.controller1.save_state(out);
+		self.controller2.save_state(out);
+		self.cartridge.save_state(out);
+	}
+
+	fn load_state(&mut self, input: &mut StateReader) -> Result<(), String> {
+		// The timing of the whole machine depends on the region, so a state can't move between regions.
+		let region = input.u8()?;
+		if region != self.region as u8 {
+			return Err(format!("Save state is for another region, this console is {}", self.region));
+		}
+		input.bytes(&mut self.ram)?;
+		self.dot_remainder = input.u32()?;
+		self.cycles = input.u64()?;
+		self.stall_cycles = input.u64()?;
+		self.overclock_left = input.u32()?;
+		self.overclock_remainder = input.u32()?;
+		self.overclock_cycles = input.u64()?;
+		self.ppu.load_state(input)?;
+		self.apu.load_state(input)?;
+		self.controller1.load_state(input)?;
+		self.controller2.load_state(input)?;
+		self.cartridge.load_state(input)?;
+		self.ppu.mirroring = self.cartridge.mirroring();
+		Ok(())
+	}
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+	use crate::cartridge::test_rom;
+	use crate::controller::{Button, ButtonState};
+	use crate::irq::IrqSource;
+	use crate::ppu::ppu::Mirroring;
+
+	#[test]
+	fn memory_map_test() {
+		let cartridge = Cartridge::from_ines(&test_rom::nrom("A9 01")).unwrap();
+		let mut bus = NesBus::new(cartridge);
+
+		// RAM is mirrored 4 times.
+		bus.write(0x0012, 0xAB);
+		assert_eq!(bus.read(0x0812), 0xAB);
+		assert_eq!(bus.read(0x1812), 0xAB);
+
+		// PPU registers are mirrored every 8 bytes.
+		bus.write(0x3FFE, 0x3F);
+		bus.write(0x2006, 0x00);
+		assert_eq!(bus.ppu.scroll_registers().v, 0x3F00);
+
+		// PRG ROM.
+		assert_eq!(bus.read(0x8000), 0xA9);
+		bus.write(0x8000, 0x00);
+		assert_eq!(bus.read(0x8000), 0xA9);
+
+		// PRG RAM.
+		bus.write(0x6000, 0x42);
+		assert_eq!(bus.read(0x6000), 0x42);
+	}
+
+	#[test]
+	fn poke_test() {
+		let cartridge = Cartridge::from_ines(&test_rom::nrom("A9 01")).unwrap();
+		let mut bus = NesBus::new(cartridge);
+		bus.set_strict_rom(true);
+
+		// The CPU can't write ROM, a poke can. 16KB of PRG ROM is at $8000 and $C000.
+		bus.write(0x8000, 0xEA);
+		assert_eq!(bus.peek(0x8000), 0xA9);
+		assert_eq!(bus.rom_write_violations().len(), 1);
+		bus.poke(0x8000, 0xEA);
+		assert_eq!(bus.peek(0x8000), 0xEA);
+		assert_eq!(bus.peek(0xC000), 0xEA);
+		assert_eq!(bus.rom_write_violations().len(), 1);
+
+		bus.poke(0x0842, 0x42);
+		bus.poke(0x6000, 0x60);
+		assert_eq!((bus.peek(0x0042), bus.peek(0x6000)), (0x42, 0x60));
+	}
+
+	#[test]
+	fn test_registers_test() {
+		let cartridge = Cartridge::from_ines(&test_rom::nrom("A9 01")).unwrap();
+		let mut bus = NesBus::new(cartridge);
+
+		// Nothing drives the bus: the read is the last value on it.
+		assert_eq!(bus.read(0x8000), 0xA9);
+		assert_eq!(bus.read(0x4018), 0xA9);
+		assert_eq!(bus.read(0x8001), 0x01);
+		assert_eq!(bus.read(0x401F), 0x01);
+		// The write-only APU registers too.
+		assert_eq!(bus.read(0x4000), 0x01);
+		// Writes go nowhere, and don't touch the controllers or the APU.
+		bus.write(0x4018, 0xFF);
+		bus.write(0x401F, 0xFF);
+		assert_eq!(bus.read(0x4016), 0x40);
+		assert_eq!(bus.read(0x4017), 0x40);
+		assert_eq!(bus.read(0x4015), 0x00);
+		assert!(bus.unmapped_accesses().is_empty());
+	}
+
+	/// Write `data` to PPU memory at `addr` through PPUADDR and PPUDATA, like games do.
+	fn write_vram(bus: &mut NesBus, addr: u16, data: &[u8]) {
+		bus.write(0x2006, (addr >> 8) as u8);
+		bus.write(0x2006, addr as u8);
+		for &byte in data {
+			bus.write(0x2007, byte);
+		}
+	}
+
+	/// Read `count` bytes of PPU memory at `addr` through PPUDATA. The first read only fills the buffer.
+	fn read_vram(bus: &mut NesBus, addr: u16, count: usize) -> Vec<u8> {
+		bus.write(0x2006, (addr >> 8) as u8);
+		bus.write(0x2006, addr as u8);
+		bus.read(0x2007);
+		(0..count).map(|_| bus.read(0x2007)).collect()
+	}
+
+	#[test]
+	fn chr_ram_test() {
+		let cartridge = Cartridge::from_ines(&test_rom::ines(0, &[0xEA; 0x4000], &[])).unwrap();
+		assert!(cartridge.has_chr_ram());
+		let mut bus = NesBus::new(cartridge);
+
+		let tile = [0x3C, 0x42, 0x81, 0xFF];
+		write_vram(&mut bus, 0x1230, &tile);
+		assert_eq!(read_vram(&mut bus, 0x1230, 4), tile);
+		let direct: Vec<u8> = (0x1230..0x1234).map(|addr| bus.ppu.ppu_read(addr, &bus.cartridge)).collect();
+		assert_eq!(direct, tile);
+
+		let mut out = StateWriter::default();
+		bus.save_state(&mut out);
+		let state = out.into_bytes();
+		write_vram(&mut bus, 0x1230, &[0; 4]);
+		assert_eq!(read_vram(&mut bus, 0x1230, 4), [0; 4]);
+
+		let mut input = StateReader::new(&state);
+		bus.load_state(&mut input).unwrap();
+		input.finish().unwrap();
+		assert_eq!(read_vram(&mut bus, 0x1230, 4), tile);
+	}
+
+	#[test]
+	fn chr_rom_test() {
+		let mut chr = vec![0; 0x2000];
+		chr[0x0100] = 0x55;
+		let cartridge = Cartridge::from_ines(&test_rom::ines(0, &[0xEA; 0x4000], &chr)).unwrap();
+		assert!(!cartridge.has_chr_ram());
+		let mut bus = NesBus::new(cartridge);
+
+		// Writes to CHR ROM go nowhere.
+		write_vram(&mut bus, 0x0100, &[0xAA, 0xBB]);
+		assert_eq!(read_vram(&mut bus, 0x0100, 2), [0x55, 0x00]);
+		assert_eq!(bus.ppu.ppu_read(0x0100, &bus.cartridge), 0x55);
+	}
+
+	#[test]
+	fn controller_test() {
+		let cartridge = Cartridge::from_ines(&test_rom::nrom("EA")).unwrap();
+		let mut bus = NesBus::new(cartridge);
+
+		let mut buttons = ButtonState::default();
+		buttons.set(Button::B, true);
+		buttons.set(Button::Up, true);
+		bus.controller1.set_buttons(buttons);
+
+		bus.write(0x4016, 1);
+		bus.write(0x4016, 0);
+		let reads: Vec<u8> = (0..8).map(|_| bus.read(0x4016)).collect();
+		assert_eq!(reads, vec![0x40, 0x41, 0x40, 0x40, 0x41, 0x40, 0x40, 0x40]);
+	}
+
+	#[test]
+	fn two_controllers_test() {
+		let cartridge = Cartridge::from_ines(&test_rom::nrom("EA")).unwrap();
+		let mut bus = NesBus::new(cartridge);
+
+		let mut buttons1 = ButtonState::default();
+		buttons1.set(Button::A, true);
+		buttons1.set(Button::Start, true);
+		bus.controller1.set_buttons(buttons1);
+		let mut buttons2 = ButtonState::default();
+		buttons2.set(Button::B, true);
+		buttons2.set(Button::Right, true);
+		bus.controller2.set_buttons(buttons2);
+
+		// A single strobe at $4016 latches both.
+		bus.write(0x4016, 1);
+		bus.write(0x4016, 0);
+		// Reading one port doesn't shift the other.
+		let mut reads1 = vec![];
+		let mut reads2 = vec![];
+		for i in 0..8 {
+			reads1.push(bus.read(0x4016));
+			reads2.push(bus.read(0x4017));
+			if i % 3 == 0 {
+				reads2.push(bus.read(0x4017));
+			}
+		}
+		assert_eq!(reads1, vec![0x41, 0x40, 0x40, 0x41, 0x40, 0x40, 0x40, 0x40]);
+		// Port 2 was read 11 times: its 8 buttons, and then 1s.
+		assert_eq!(reads2, vec![0x40, 0x41, 0x40, 0x40, 0x40, 0x40, 0x40, 0x41, 0x41, 0x41, 0x41]);
+
+		// Writes to $4017 go to the APU frame counter, and don't strobe controller 2.
+		bus.write(0x4016, 1);
+		bus.write(0x4016, 0);
+		bus.write(0x4017, 0x01);
+		assert_eq!(bus.read(0x4017), 0x40);
+		assert_eq!(bus.read(0x4017), 0x41);
+	}
+
+	/// Run the PPU to the start of the scanline, in the frame.
+	fn tick_to(bus: &mut NesBus, frame: u64, scanline: u16) {
+		while bus.ppu.frame() < frame || bus.ppu.scanline() < scanline {
+			bus.tick(1);
+		}
+	}
+
+	#[test]
+	fn zapper_test() {
+		// Tile 1 is all color 3, which is white. The rest is black.
+		let mut chr = vec![0; 0x2000];
+		chr[16..32].copy_from_slice(&[0xFF; 16]);
+		let cartridge = Cartridge::from_ines(&test_rom::ines(0, &[0xEA; 0x4000], &chr)).unwrap();
+		let mut bus = NesBus::new(cartridge);
+		for (addr, data) in [(0x2006, 0x3F), (0x2006, 0x00), (0x2007, 0x0F), (0x2007, 0x0F), (0x2007, 0x0F), (0x2007, 0x30)] {
+			bus.write(addr, data);
+		}
+		// A white square at tile (10, 10): pixels 80-87 in both directions.
+		for (addr, data) in [(0x2006, 0x21), (0x2006, 0x4A), (0x2007, 0x01)] {
+			bus.write(addr, data);
+		}
+		for (addr, data) in [(0x2000, 0x00), (0x2005, 0x00), (0x2005, 0x00), (0x2001, 0x0A)] {
+			bus.write(addr, data);
+		}
+
+		// Controller 2, with nothing pressed.
+		assert_eq!(bus.read(0x4017), 0x40);
+
+		// The beam is a little below the square: the gun sees it.
+		tick_to(&mut bus, 1, 90);
+		assert_eq!(bus.ppu.framebuffer().get(84, 84), 0x30);
+		bus.set_zapper(Some(Zapper::new(84, 84, false)));
+		assert_eq!(bus.read(0x4017), 0x40);
+		bus.set_zapper(Some(Zapper::new(84, 84, true)));
+		assert_eq!(bus.read(0x4017), 0x50);
+		// Next to it, it's black.
+		bus.set_zapper(Some(Zapper::new(20, 84, false)));
+		assert_eq!(bus.read(0x4017), 0x48);
+
+		// The beam didn't get to the square yet, in this frame.
+		bus.set_zapper(Some(Zapper::new(84, 84, false)));
+		tick_to(&mut bus, 2, 50);
+		assert_eq!(bus.read(0x4017), 0x48);
+		tick_to(&mut bus, 2, 90);
+		assert_eq!(bus.read(0x4017), 0x40);
+		// Long after the beam passed.
+		tick_to(&mut bus, 2, 120);
+		assert_eq!(bus.read(0x4017), 0x48);
+
+		// Without rendering, the screen is the backdrop color, black.
+		bus.write(0x2001, 0x00);
+		tick_to(&mut bus, 3, 90);
+		assert_eq!(bus.ppu.framebuffer().get(84, 84), 0x0F);
+		assert_eq!(bus.read(0x4017), 0x48);
+	}
+
+	#[test]
+	fn dmc_dma_test() {
+		let mut prg = vec![0xFF; 0x4000];
+		prg[0x3FFC] = 0x00;
+		prg[0x3FFD] = 0x80;
+		let cartridge = Cartridge::from_ines(&test_rom::ines(0, &prg, &[0; 0x2000])).unwrap();
+		let mut bus = NesBus::new(cartridge);
+
+		bus.write(0x4010, 0x8F);	// IRQ enabled, fastest rate: a byte every 54 * 8 = 432 cycles
+		bus.write(0x4012, 0x00);	// $C000
+		bus.write(0x4013, 0x01);	// 17 bytes
+		bus.write(0x4015, 0x10);
+
+		// The first byte is fetched right away, because the sample buffer is empty. It starts on cycle 1: odd, so the
+		// read is aligned.
+		bus.tick(1);
+		assert_eq!(bus.stall_cycles(), 3);
+		assert_eq!(bus.cycles(), 1 + 3);
+
+		// The next byte is fetched when the previous one starts playing.
+		for _ in 0..(432 - 4) {
+			bus.tick(1);
+		}
+		assert_eq!(bus.stall_cycles(), 3 + 3);
+
+		// 17 bytes in total, then the interrupt.
+		let mut fetches = 2;
+		let mut stall_cycles = bus.stall_cycles();
+		while fetches < 17 {
+			assert!(!bus.irq_pending());
+			bus.tick(1);
+			if bus.stall_cycles() != stall_cycles {
+				assert!((3..=4).contains(&(bus.stall_cycles() - stall_cycles)));
+				stall_cycles = bus.stall_cycles();
+				fetches += 1;
+			}
+		}
+		assert!(bus.irq_pending());
+		assert_eq!(bus.read(0x4015) & 0x90, 0x80);
+		for _ in 0..2000 {
+			bus.tick(1);
+		}
+		assert_eq!(bus.stall_cycles(), stall_cycles);
+
+		// The samples are all 1 bits, so the output goes up.
+		assert!(bus.apu.dmc_output() > 100);
+
+		// Reading $4015 does not acknowledge the DMC interrupt, writing does.
+		assert!(bus.irq_pending());
+		bus.write(0x4015, 0x00);
+		assert!(!bus.irq_pending());
+	}
+
+	#[test]
+	fn oam_dma_test() {
+		let cartridge = Cartridge::from_ines(&test_rom::nrom("A9 01")).unwrap();
+		let mut bus = NesBus::new(cartridge);
+		for i in 0..0x100 {
+			bus.write(0x0300 + i, i as u8);
+		}
+
+		// Starts at OAMADDR, and wraps around.
+		bus.write(0x2003, 0x10);
+		bus.write(0x4014, 0x03);
+		// The cycle of the write, then the DMA starts on cycle 1: odd, so it waits one more to align the reads.
+		assert_eq!(bus.stall_cycles(), 0);
+		bus.tick(1);
+		assert_eq!(bus.cycles(), 1 + 514);
+		assert_eq!(bus.stall_cycles(), 514);
+		for i in 0..0x100u16 {
+			bus.write(0x2003, (i + 0x10) as u8);
+			assert_eq!(bus.read(0x2004), i as u8);
+		}
+
+		// Now on an even cycle.
+		assert_eq!(bus.cycles() % 2, 1);
+		bus.write(0x4014, 0x03);
+		bus.tick(1);
+		assert_eq!(bus.stall_cycles(), 514 + 513);
+	}
+
+	#[test]
+	fn dmc_dma_conflicts_test() {
+		let cartridge = Cartridge::from_ines(&test_rom::ines(0, &[0xFF; 0x4000], &[0; 0x2000])).unwrap();
+		let mut bus = NesBus::new(cartridge);
+		let mut buttons = ButtonState::default();
+		buttons.set(Button::A, true);
+		buttons.set(Button::Select, true);
+		bus.controller1.set_buttons(buttons);
+		bus.write(0x4016, 1);
+		bus.write(0x4016, 0);
+
+		// A DMC fetch on the cycle of a read of $4016: the CPU reads it again, and B is lost.
+		bus.write(0x4010, 0x0F);
+		bus.write(0x4013, 0x00);	// 1 byte
+		bus.write(0x4015, 0x10);
+		assert_eq!(bus.read(0x4016), 0x41);
+		bus.tick(1);
+		assert_eq!(bus.stall_cycles(), 3);
+		assert_eq!(bus.read(0x4016), 0x41);
+
+		// Inside an OAM DMA, only 2.
+		bus.write(0x4015, 0x10);
+		bus.write(0x4014, 0x02);
+		bus.tick(1);
+		assert_eq!(bus.stall_cycles(), 3 + 514 + 2);
+	}
+
+	#[test]
+	fn mmc3_test() {
+		let cartridge = Cartridge::from_ines(&test_rom::ines(4, &[0xEA; 0x8000], &[0; 0x2000])).unwrap();
+		let mut bus = NesBus::new(cartridge);
+		assert_eq!(bus.ppu.mirroring, Mirroring::Horizontal);
+		bus.write(0xA000, 0);
+		assert_eq!(bus.ppu.mirroring, Mirroring::Vertical);
+
+		// Not counting without rendering. The frame counter of the APU holds the line too, so only the mapper is checked.
+		bus.write(0xC000, 9);
+		bus.write(0xC001, 0);
+		bus.write(0xE001, 0);
+		for _ in 0..30_000 {
+			bus.tick(1);
+		}
+		assert!(!bus.irq_sources().holds(IrqSource::MAPPER));
+
+		// A clock on every rendering scanline, with the sprites at $1000: the one of scanline 0 reloads 9, the one of
+		// scanline 9 gets to 0.
+		bus.write(0x2000, 0x08);
+		bus.write(0x2001, 0x18);
+		while bus.ppu.scanline() != 0 {
+			bus.tick(1);
+		}
+		// It may have fired on the way there.
+		bus.write(0xE000, 0);
+		bus.write(0xE001, 0);
+		bus.write(0xC001, 0);
+		while !bus.irq_sources().holds(IrqSource::MAPPER) {
+			bus.tick(1);
+		}
+		assert_eq!(bus.ppu.scanline(), 9);
+		assert!((260..263).contains(&bus.ppu.dot()), "dot {}", bus.ppu.dot());
+		bus.write(0xE000, 0);
+		assert!(!bus.irq_sources().holds(IrqSource::MAPPER));
+	}
+
+	#[test]
+	fn mmc5_test() {
+		let cartridge = Cartridge::from_ines(&test_rom::ines(5, &[0xEA; 0x8000], &[0; 0x2000])).unwrap();
+		let mut bus = NesBus::new(cartridge);
+		bus.write(0x5105, 0x44);
+		assert_eq!(bus.ppu.mirroring, Mirroring::Vertical);
+
+		// PRG RAM is protected until $5102 and $5103 say it isn't.
+		bus.write(0x6000, 0x42);
+		bus.write(0x5102, 2);
+		bus.write(0x5103, 1);
+		bus.write(0x6001, 0x43);
+		assert_eq!(bus.read(0x6001), 0x43);
+		assert_ne!(bus.read(0x6000), 0x42);
+
+		// The multiplier, and open bus where there's no register.
+		bus.write(0x5205, 12);
+		bus.write(0x5206, 34);
+		assert_eq!((bus.read(0x5205), bus.read(0x5206)), (152, 1));
+		assert_eq!(bus.read(0x5800), 1);
+
+		// The 4 nametables in ExRAM, which the CPU reads back in mode 2.
+		bus.write(0x5105, 0b10_10_10_10);
+		bus.write(0x2006, 0x2C);
+		bus.write(0x2006, 0x05);
+		bus.write(0x2007, 0x99);
+		bus.write(0x5104, 2);
+		assert_eq!(bus.read(0x5C05), 0x99);
+
+		// The IRQ at scanline 9 of the frame.
+		bus.write(0x5105, 0x44);
+		bus.write(0x5203, 9);
+		bus.write(0x5204, 0x80);
+		bus.write(0x2001, 0x18);
+		while bus.ppu.scanline() != 0 {
+			bus.tick(1);
+		}
+		bus.read(0x5204);
+		while !bus.irq_sources().holds(IrqSource::MAPPER) {
+			bus.tick(1);
+		}
+		assert_eq!(bus.ppu.scanline(), 9);
+		assert_eq!(bus.read(0x5204), 0xC0);
+		assert!(!bus.irq_sources().holds(IrqSource::MAPPER));
+	}
+
+	#[test]
+	fn vrc6_test() {
+		let cartridge = Cartridge::from_ines(&test_rom::ines(24, &[0xEA; 0x8000], &[0; 0x2000])).unwrap();
+		let mut bus = NesBus::new(cartridge);
+		bus.write(0x6000, 0x12);
+		bus.write(0xB003, 0x84);
+		assert_eq!(bus.ppu.mirroring, Mirroring::Horizontal);
+		// PRG RAM is on with bit 7 of $B003, open bus without it.
+		assert_eq!(bus.read(0x6000), 0x12);
+		bus.write(0xB003, 0x04);
+		bus.write(0x6000, 0x34);
+		assert_eq!(bus.read(0x6000), 0x34);
+		bus.write(0xB003, 0x84);
+		assert_eq!(bus.read(0x6000), 0x12);
+
+		// Cycle mode, from $F0: the IRQ is held 16 cycles later, on the line of the mapper.
+		bus.write(0xF000, 0xF0);
+		bus.write(0xF001, 0b110);
+		bus.tick(15);
+		assert!(!bus.irq_sources().holds(IrqSource::MAPPER));
+		bus.tick(1);
+		assert!(bus.irq_sources().holds(IrqSource::MAPPER));
+		bus.write(0xF002, 0);
+		assert!(!bus.irq_sources().holds(IrqSource::MAPPER));
+
+		// The sawtooth is in the mix, at the expansion volume.
+		bus.write(0xB000, 42);
+		bus.write(0xB002, 0x80);
+		bus.tick(4);
+		let full = bus.apu.output();
+		bus.set_expansion_volume(0.0);
+		let apu = bus.apu.output();
+		assert!(full > apu);
+		bus.set_expansion_volume(0.5);
+		assert!((bus.apu.output() - apu - (full - apu) / 2.0).abs() < 1e-6);
+	}
+
+	#[test]
+	fn fds_test() {
+		let side = crate::fds::disk::test_disk::side(&[0xEA], 0x6000);
+		let cartridge = Cartridge::from_fds(&[0xEA; 0x2000], &crate::fds::disk::test_disk::image(&[side])).unwrap();
+		let mut bus = NesBus::new(cartridge);
+		// 32KB of PRG RAM, and the BIOS at $E000.
+		bus.write(0xDFFF, 0x12);
+		assert_eq!((bus.read(0xDFFF), bus.read(0xE000)), (0x12, 0xEA));
+		bus.write(0xE000, 0x34);
+		assert_eq!(bus.read(0xE000), 0xEA);
+		bus.write(0x4025, 0x08);
+		assert_eq!(bus.ppu.mirroring, Mirroring::Horizontal);
+
+		// The timer IRQ is on the line of the mapper, and $4030 acknowledges it.
+		bus.write(0x4020, 9);
+		bus.write(0x4022, 0b10);
+		bus.tick(9);
+		assert!(!bus.irq_sources().holds(IrqSource::MAPPER));
+		bus.tick(1);
+		assert!(bus.irq_sources().holds(IrqSource::MAPPER));
+		assert_eq!(bus.read(0x4030) & 1, 1);
+		assert!(!bus.irq_sources().holds(IrqSource::MAPPER));
+
+		// The wave is in the mix.
+		let silent = bus.apu.output();
+		bus.write(0x4089, 0x80);
+		for addr in 0x4040..=0x407F {
+			bus.write(addr, 0x3F);
+		}
+		bus.write(0x4089, 0x00);
+		bus.write(0x4080, 0xA0);
+		bus.write(0x4082, 0xFF);
+		bus.tick(1);
+		assert!(bus.apu.output() > silent);
+	}
+
+	#[test]
+	fn vblank_race_test() {
+		let cartridge = Cartridge::from_ines(&test_rom::nrom("EA")).unwrap();
+		let mut bus = NesBus::new(cartridge);
+		bus.write(0x2000, 0x80);
+
+		// Read PPUSTATUS on the first CPU cycle at or after the dot that sets VBlank. A frame is not a whole number of
+		// CPU cycles, so in frames 0, 1 and 2 the read lands 0, 1 and 2 dots after it. In frame 3, a cycle later: 3 dots.
+		let mut reads = vec![];
+		for frame in 0..4 {
+			tick_to(&mut bus, frame, 241);
+			while bus.ppu.dot() < 1 {
+				bus.tick(1);
+			}
+			if frame == 3 {
+				bus.tick(1);
+			}
+			let dots_after = bus.ppu.dot() - 1;
+			let status = bus.read(0x2002);
+			reads.push((dots_after, status & 0x80 != 0, bus.ppu.nmi_pending(), bus.ppu.registers.ppustatus.vertical_blank_started() != 0));
+		}
+		assert_eq!(reads, vec![
+			// On the dot: the flag reads clear, and there's no NMI (or flag) in the frame.
+			(0, false, false, false),
+			// 1 or 2 dots later: the flag reads set, but there's still no NMI.
+			(1, true, false, false),
+			(2, true, false, false),
+			// Later: the NMI has gone.
+			(3, true, true, false),
+		]);
+
+		// The flag was never set in frame 0.
+		tick_to(&mut bus, 4, 0);
+		bus.write(0x2000, 0x00);
+		tick_to(&mut bus, 4, 242);
+		assert!(!bus.ppu.nmi_pending());
+		assert_ne!(bus.read(0x2002) & 0x80, 0);
+	}
+
+	#[test]
+	fn nmi_off_race_test() {
+		let cartridge = Cartridge::from_ines(&test_rom::nrom("EA")).unwrap();
+		let mut bus = NesBus::new(cartridge);
+
+		// Like `vblank_race_test`, with a write that turns the NMI off instead of the read.
+		let mut writes = vec![];
+		for frame in 0..4 {
+			tick_to(&mut bus, frame, 240);
+			bus.write(0x2000, 0x80);
+			tick_to(&mut bus, frame, 241);
+			while bus.ppu.dot() < 1 {
+				bus.tick(1);
+			}
+			if frame == 3 {
+				bus.tick(1);
+			}
+			let dots_after = bus.ppu.dot() - 1;
+			bus.write(0x2000, 0x00);
+			bus.tick(1);
+			writes.push((dots_after, bus.ppu.nmi_pending(), bus.ppu.registers.ppustatus.vertical_blank_started() != 0));
+		}
+		assert_eq!(writes, vec![
+			// Before the flag is set, or 1 or 2 dots after: no NMI, but the flag is set.
+			(0, false, true),
+			(1, false, true),
+			(2, false, true),
+			// Later: the NMI has gone.
+			(3, true, true),
+		]);
+	}
+}
@@ -0,0 +1,85 @@
+// Pause and frame advance of the window, for inspecting graphical glitches.
+//
+// P pauses, and P again resumes. While paused, the period key runs exactly one frame, with the input sampled like any
+// other frame, so gameplay can be stepped frame by frame. The frontend asks `next_frame` what to do every time around
+// its loop, and keeps the last frame on screen while it holds.
+
+/// What the frontend does this time around its loop.
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub enum FrameAction {
+	/// Run a frame, at the pacer's speed.
+	Run,
+	/// Paused, but run a single frame, right away.
+	Step,
+	/// Paused: don't run anything.
+	Hold,
+}
+
+#[derive(Default)]
+pub struct PauseControl {
+	paused: bool,
+	/// Frame advances asked for, and not run yet. Only while paused.
+	steps: u32,
+}
+
+impl PauseControl {
+	/// Pause, or resume. Resuming drops the frame advances not run yet. Returns true if it's paused now.
+	pub fn toggle(&mut self) -> bool {
+		self.paused = !self.paused;
+		self.steps = 0;
+		self.paused
+	}
+
+	/// Run one more frame, if paused. Running, it does nothing.
+	pub fn advance(&mut self) {
+		if self.paused {
+			self.steps += 1;
+		}
+	}
+
+	pub fn next_frame(&mut self) -> FrameAction {
+		if !self.paused {
+			FrameAction::Run
+		} else if self.steps > 0 {
+			self.steps -= 1;
+			FrameAction::Step
+		} else {
+			FrameAction::Hold
+		}
+	}
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+
+	#[test]
+	fn pause_test() {
+		let mut control = PauseControl::default();
+		assert_eq!(control.next_frame(), FrameAction::Run);
+		// Advancing while running is nothing.
+		control.advance();
+		assert_eq!(control.next_frame(), FrameAction::Run);
+
+		assert!(control.toggle());
+		assert_eq!(control.next_frame(), FrameAction::Hold);
+		assert_eq!(control.next_frame(), FrameAction::Hold);
+
+		// One frame per key press, and then it holds again.
+		control.advance();
+		assert_eq!(control.next_frame(), FrameAction::Step);
+		assert_eq!(control.next_frame(), FrameAction::Hold);
+		control.advance();
+		control.advance();
+		assert_eq!(control.next_frame(), FrameAction::Step);
+		assert_eq!(control.next_frame(), FrameAction::Step);
+		assert_eq!(control.next_frame(), FrameAction::Hold);
+
+		// Resuming forgets the advances still waiting.
+		control.advance();
+		assert!(!control.toggle());
+		assert_eq!(control.next_frame(), FrameAction::Run);
+		assert!(control.toggle());
+		assert_eq!(control.next_frame(), FrameAction::Hold);
+	}
+}
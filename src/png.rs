@@ -0,0 +1,336 @@
+// PNG files of the screen, for screenshots and golden images (`--screenshot-out`, F12 in the window).
+// https://www.w3.org/TR/png/
+//
+// The smallest encoder that makes a valid file: 8-bit RGB, no interlacing, and every row with filter 0 (none). The
+// zlib stream uses stored deflate blocks, which are not compressed at all, so there's no deflate to write. A screen is
+// 180KB, about 15 times more than a real encoder makes, which is fine for a few screenshots.
+//
+// | Part | Description |
+// |---|---|
+// | Signature | 8 bytes |
+// | IHDR | Width, height, bit depth 8, color type 2 (RGB) |
+// | IDAT | zlib: a 2 byte header, stored blocks of up to 65535 bytes, and the Adler-32 of the data |
+// | IEND | Empty |
+//
+// Every chunk is its length (big endian), its type, its data, and the CRC-32 of the type and the data.
+//
+// `decode_rgb` reads the PNG files of other programs too, for the reference images of `--compare`: compressed (see
+// inflate.rs), with any of the row filters, and RGB, RGBA, gray or palette colors. Not interlaced, and not 16 bits.
+
+use std::path::{Path, PathBuf};
+
+use crate::hash::crc32;
+use crate::inflate::inflate;
+
+const SIGNATURE: [u8; 8] = [0x89, b'P', b'N', b'G', b'\r', b'\n', 0x1A, b'\n'];
+/// The most a stored deflate block holds.
+const MAX_STORED_BLOCK: usize = 0xFFFF;
+
+/// A PNG of `width` x `height` pixels, from `rgb`: 3 bytes per pixel, row by row.
+pub fn encode_rgb(width: usize, height: usize, rgb: &[u8]) -> Vec<u8> {
+	assert_eq!(rgb.len(), width * height * 3, "{}x{} pixels need {} bytes of RGB", width, height, width * height * 3);
+
+	// Filter type 0 before every row.
+	let mut raw = Vec::with_capacity(height * (1 + width * 3));
+	for row in rgb.chunks_exact(width * 3) {
+		raw.push(0);
+		raw.extend_from_slice(row);
+	}
+
+	let mut png = SIGNATURE.to_vec();
+	let mut header = Vec::with_capacity(13);
+	header.extend_from_slice(&(width as u32).to_be_bytes());
+	header.extend_from_slice(&(height as u32).to_be_bytes());
+	// 8 bits, RGB, deflate, filters of method 0, no interlacing.
+	header.extend_from_slice(&[8, 2, 0, 0, 0]);
+	write_chunk(&mut png, b"IHDR", &header);
+	write_chunk(&mut png, b"IDAT", &zlib_stored(&raw));
+	write_chunk(&mut png, b"IEND", &[]);
+	png
+}
+
+/// The width, the height and the RGB of a PNG, 3 bytes per pixel, row by row like `encode_rgb` takes it. Alpha is
+/// dropped.
+pub fn decode_rgb(png: &[u8]) -> Result<(usize, usize, Vec<u8>), String> {
+	if !png.starts_with(&SIGNATURE) {
+		return Err("Not a PNG file".to_string());
+	}
+	let mut pos = SIGNATURE.len();
+	let mut header = None;
+	let mut palette: &[u8] = &[];
+	let mut zlib = vec![];
+	loop {
+		let length = png.get(pos..pos + 4).ok_or("The PNG file ends too soon")?;
+		let length = u32::from_be_bytes(length.try_into().unwrap()) as usize;
+		let chunk = png.get(pos + 4..pos + 12 + length).ok_or("The PNG file ends too soon")?;
+		let (kind, data) = (&chunk[..4], &chunk[4..4 + length]);
+		if crc32(&chunk[..4 + length]).to_be_bytes() != chunk[4 + length..] {
+			return Err(format!("Wrong CRC in the {} chunk", String::from_utf8_lossy(kind)));
+		}
+		pos += 12 + length;
+		match kind {
+			b"IHDR" if length == 13 => header = Some(data),
+			b"PLTE" => palette = data,
+			b"IDAT" => zlib.extend_from_slice(data),
+			b"IEND" => break,
+			_ => {}
+		}
+	}
+	let header = header.ok_or("A PNG file without a header")?;
+	let width = u32::from_be_bytes(header[0..4].try_into().unwrap()) as usize;
+	let height = u32::from_be_bytes(header[4..8].try_into().unwrap()) as usize;
+	let (depth, color_type) = (header[8], header[9]);
+	let channels = match (color_type, depth) {
+		(0, 8) | (3, 1 | 2 | 4 | 8) => 1,
+		(4, 8) => 2,
+		(2, 8) => 3,
+		(6, 8) => 4,
+		_ => return Err(format!("Unsupported PNG: {}-bit, color type {}", depth, color_type)),
+	};
+	if header[12] != 0 {
+		return Err("Unsupported PNG: interlaced".to_string());
+	}
+
+	let raw = zlib_decompress(&zlib)?;
+	let row_bytes = (width * channels * depth as usize).div_ceil(8);
+	if raw.len() < height * (1 + row_bytes) {
+		return Err("The PNG image data is too short".to_string());
+	}
+	let rows = unfilter(&raw, height, row_bytes, channels)?;
+
+	let mut rgb = Vec::with_capacity(width * height * 3);
+	for row in rows.chunks_exact(row_bytes) {
+		for x in 0..width {
+			match color_type {
+				0 | 4 => rgb.extend_from_slice(&[row[x * channels]; 3]),
+				3 => {
+					// Pixels of less than 8 bits are from the highest bits of the byte.
+					let bit = x * depth as usize;
+					let index = (row[bit / 8] >> (8 - depth as usize - bit % 8)) & ((1u16 << depth) - 1) as u8;
+					let color = palette.get(index as usize * 3..index as usize * 3 + 3).ok_or("A color out of the PNG palette")?;
+					rgb.extend_from_slice(color);
+				}
+				_ => rgb.extend_from_slice(&row[x * channels..x * channels + 3]),
+			}
+		}
+	}
+	Ok((width, height, rgb))
+}
+
+/// The rows of a PNG without their filters: `row_bytes` each, with no filter type byte.
+fn unfilter(raw: &[u8], height: usize, row_bytes: usize, channels: usize) -> Result<Vec<u8>, String> {
+	// The filters use the byte of the pixel to the left, or the byte before when pixels are smaller than a byte.
+	let left = channels;
+	let mut rows = vec![0u8; height * row_bytes];
+	for (y, line) in raw.chunks_exact(1 + row_bytes).take(height).enumerate() {
+		let (filter, line) = (line[0], &line[1..]);
+		let (done, rest) = rows.split_at_mut(y * row_bytes);
+		let above = if y == 0 { None } else { Some(&done[(y - 1) * row_bytes..]) };
+		let row = &mut rest[..row_bytes];
+		for i in 0..row_bytes {
+			let a = if i >= left { row[i - left] } else { 0 };
+			let b = above.map_or(0, |above| above[i]);
+			let c = match above {
+				Some(above) if i >= left => above[i - left],
+				_ => 0,
+			};
+			let predictor = match filter {
+				0 => 0,
+				1 => a,
+				2 => b,
+				3 => ((a as u16 + b as u16) / 2) as u8,
+				4 => paeth(a, b, c),
+				_ => return Err(format!("PNG filter type {}", filter)),
+			};
+			row[i] = line[i].wrapping_add(predictor);
+		}
+	}
+	Ok(rows)
+}
+
+/// Of the pixels to the left, above, and above to the left, the one closest to left + above - above left.
+fn paeth(a: u8, b: u8, c: u8) -> u8 {
+	let p = a as i16 + b as i16 - c as i16;
+	let (pa, pb, pc) = ((p - a as i16).abs(), (p - b as i16).abs(), (p - c as i16).abs());
+	if pa <= pb && pa <= pc {
+		a
+	} else if pb <= pc {
+		b
+	} else {
+		c
+	}
+}
+
+/// The data of a zlib stream, with its header and Adler-32 checked.
+fn zlib_decompress(zlib: &[u8]) -> Result<Vec<u8>, String> {
+	if zlib.len() < 2 || zlib[0] & 0x0F != 8 || !u16::from_be_bytes([zlib[0], zlib[1]]).is_multiple_of(31) || zlib[1] & 0x20 != 0 {
+		return Err("Not a zlib stream of deflate".to_string());
+	}
+	let (data, length) = inflate(&zlib[2..])?;
+	let checksum = zlib.get(2 + length..2 + length + 4).ok_or("The zlib stream ends too soon")?;
+	if adler32(&data).to_be_bytes() != checksum {
+		return Err("Wrong Adler-32 of the PNG image data".to_string());
+	}
+	Ok(data)
+}
+
+fn write_chunk(png: &mut Vec<u8>, kind: &[u8; 4], data: &[u8]) {
+	png.extend_from_slice(&(data.len() as u32).to_be_bytes());
+	let start = png.len();
+	png.extend_from_slice(kind);
+	png.extend_from_slice(data);
+	let crc = crc32(&png[start..]);
+	png.extend_from_slice(&crc.to_be_bytes());
+}
+
+/// A zlib stream of stored (not compressed) deflate blocks.
+fn zlib_stored(data: &[u8]) -> Vec<u8> {
+	// Deflate, 32KB window, no dictionary. The check bits make the header a multiple of 31.
+	let mut out = vec![0x78, 0x01];
+	let mut blocks = data.chunks(MAX_STORED_BLOCK).peekable();
+	if blocks.peek().is_none() {
+		// No data is still a block, the last one.
+		out.extend_from_slice(&[1, 0x00, 0x00, 0xFF, 0xFF]);
+	}
+	while let Some(block) = blocks.next() {
+		let last = blocks.peek().is_none();
+		out.push(last as u8);
+		let len = block.len() as u16;
+		out.extend_from_slice(&len.to_le_bytes());
+		out.extend_from_slice(&(!len).to_le_bytes());
+		out.extend_from_slice(block);
+	}
+	out.extend_from_slice(&adler32(data).to_be_bytes());
+	out
+}
+
+/// Adler-32, the checksum of zlib.
+fn adler32(data: &[u8]) -> u32 {
+	const MODULO: u32 = 65521;
+	let (mut a, mut b) = (1u32, 0u32);
+	for &byte in data {
+		a = (a + byte as u32) % MODULO;
+		b = (b + a) % MODULO;
+	}
+	(b << 16) | a
+}
+
+/// `<dir>/<name>-<N>.png`, with the first N from 1 that is not a file yet. For screenshots of a ROM: `name` is the ROM
+/// file name, without the extension.
+pub fn numbered_path(dir: &Path, name: &str) -> PathBuf {
+	(1..).map(|n| dir.join(format!("{}-{}.png", name, n))).find(|path| !path.exists()).unwrap()
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+	use crate::ppu::framebuffer::{Framebuffer, HEIGHT, OVERSCAN_LINES, WIDTH};
+
+	/// Decode a PNG like the ones `encode_rgb` makes (stored blocks only), checking every CRC and the Adler-32.
+	/// Returns the width, the height, and the RGB.
+	fn decode(png: &[u8]) -> (usize, usize, Vec<u8>) {
+		assert_eq!(png[..8], SIGNATURE);
+		let mut pos = 8;
+		let (mut width, mut height) = (0, 0);
+		let mut zlib = vec![];
+		loop {
+			let len = u32::from_be_bytes(png[pos..pos + 4].try_into().unwrap()) as usize;
+			let kind = &png[pos + 4..pos + 8];
+			let data = &png[pos + 8..pos + 8 + len];
+			let crc = u32::from_be_bytes(png[pos + 8 + len..pos + 12 + len].try_into().unwrap());
+			assert_eq!(crc, crc32(&png[pos + 4..pos + 8 + len]));
+			pos += 12 + len;
+			match kind {
+				b"IHDR" => {
+					width = u32::from_be_bytes(data[0..4].try_into().unwrap()) as usize;
+					height = u32::from_be_bytes(data[4..8].try_into().unwrap()) as usize;
+					assert_eq!(data[8..], [8, 2, 0, 0, 0]);
+				}
+				b"IDAT" => zlib.extend_from_slice(data),
+				b"IEND" => break,
+				_ => panic!("Unexpected chunk {:?}", kind),
+			}
+		}
+		assert_eq!(pos, png.len());
+
+		assert_eq!(u16::from_be_bytes([zlib[0], zlib[1]]) % 31, 0);
+		let mut raw = vec![];
+		let mut pos = 2;
+		loop {
+			let last = zlib[pos] & 1 == 1;
+			assert_eq!(zlib[pos] >> 1, 0, "Not a stored block");
+			let len = u16::from_le_bytes([zlib[pos + 1], zlib[pos + 2]]);
+			assert_eq!(!len, u16::from_le_bytes([zlib[pos + 3], zlib[pos + 4]]));
+			raw.extend_from_slice(&zlib[pos + 5..pos + 5 + len as usize]);
+			pos += 5 + len as usize;
+			if last {
+				break;
+			}
+		}
+		assert_eq!(zlib[pos..], adler32(&raw).to_be_bytes());
+
+		let mut rgb = vec![];
+		for row in raw.chunks_exact(1 + width * 3) {
+			assert_eq!(row[0], 0);
+			rgb.extend_from_slice(&row[1..]);
+		}
+		assert_eq!(rgb.len(), width * height * 3);
+		(width, height, rgb)
+	}
+
+	#[test]
+	fn adler32_test() {
+		assert_eq!(adler32(b""), 1);
+		assert_eq!(adler32(b"Wikipedia"), 0x11E6_0398);
+	}
+
+	#[test]
+	fn encode_test() {
+		let rgb: Vec<u8> = (0..3 * 2 * 3).map(|i| i as u8 * 10).collect();
+		let png = encode_rgb(3, 2, &rgb);
+		assert_eq!(decode(&png), (3, 2, rgb));
+
+		// Bigger than a stored block.
+		let rgb: Vec<u8> = (0..200 * 150 * 3).map(|i| (i % 251) as u8).collect();
+		assert_eq!(decode(&encode_rgb(200, 150, &rgb)), (200, 150, rgb));
+	}
+
+	#[test]
+	fn decode_rgb_test() {
+		let rgb: Vec<u8> = (0..200 * 150 * 3).map(|i| (i % 251) as u8).collect();
+		assert_eq!(decode_rgb(&encode_rgb(200, 150, &rgb)).unwrap(), (200, 150, rgb));
+
+		// From Python's zlib: RGBA, 3x5, every row with another filter (none, sub, up, average, Paeth).
+		let png = hex::decode("89504e470d0a1a0a0000000d4948445200000003000000050806000000807156a20000003a4944415478da6360606038a101c40140ccc86804e2b03380301390c3c068c40ec47c0ccc4c290c29a2927c0ca292420c2c6019a01246063e060015390672a6dbd3430000000049454e44ae426082").unwrap();
+		let rgb: Vec<u8> = (0..5).flat_map(|y| (0..3).flat_map(move |x| [x * 40 + y, y * 50, x * y * 7])).collect();
+		assert_eq!(decode_rgb(&png).unwrap(), (3, 5, rgb));
+
+		// 2-bit palette colors, 5x2: black, red, green and blue.
+		let png = hex::decode("89504e470d0a1a0a0000000d4948445200000005000000020203000000ed04fece0000000c504c5445000000ff000000ff000000ff9bc013dc0000000e4944415478da63907660787200000415020047b53e4c0000000049454e44ae426082").unwrap();
+		let colors = [[0, 0, 0], [255, 0, 0], [0, 255, 0], [0, 0, 255]];
+		let rgb: Vec<u8> = [0, 1, 2, 3, 1, 3, 2, 1, 0, 3].iter().flat_map(|&index| colors[index]).collect();
+		assert_eq!(decode_rgb(&png).unwrap(), (5, 2, rgb));
+
+		let mut png = encode_rgb(2, 2, &[0; 12]);
+		png[20] ^= 1;
+		assert_eq!(decode_rgb(&png), Err("Wrong CRC in the IHDR chunk".to_string()));
+		assert!(decode_rgb(b"GIF89a").is_err());
+	}
+
+	#[test]
+	fn framebuffer_test() {
+		let mut framebuffer = Framebuffer::new();
+		for y in 0..HEIGHT {
+			for x in 0..WIDTH {
+				framebuffer.set(x, y, ((x / 8 + y / 8) % 64) as u8);
+			}
+		}
+		framebuffer.set_emphasis(100, 0b010);
+		let mut rgb = vec![0; WIDTH * HEIGHT * 3];
+		framebuffer.write_rgb24(&mut rgb);
+
+		assert_eq!(decode(&framebuffer.to_png(false)), (WIDTH, HEIGHT, rgb.clone()));
+		let visible = rgb[OVERSCAN_LINES * WIDTH * 3..(HEIGHT - OVERSCAN_LINES) * WIDTH * 3].to_vec();
+		assert_eq!(decode(&framebuffer.to_png(true)), (WIDTH, HEIGHT - 2 * OVERSCAN_LINES, visible));
+	}
+}
@@ -1,5 +1,28 @@
+// The NES master palette, and its 8 variants for the color emphasis bits of PPUMASK:
+// https://www.nesdev.org/wiki/NTSC_video#Color_Tint_Bits
+//
+// Emphasizing a color darkens the other two, so the picture gets a tint of that color. The variants are computed
+// once, at compile time, so converting a pixel to RGB is still a single lookup.
+//
+// | Emphasis bit | Color |
+// |---|---|
+// | 0 | Red |
+// | 1 | Green |
+// | 2 | Blue |
+//
+// These are the bits of the colors, not of PPUMASK: PAL swaps red and green there (see `Region`).
+//
+// The master palette can be replaced (`MasterPalette`, `PPU::set_master_palette`): the NES makes its colors as a
+// video signal, not RGB, so every emulator and TV shows them a bit differently. There are presets, and .pal files:
+// 64 RGB colors (192 bytes), the emphasis variants computed like above, or 512 (1536 bytes), all 8 variants of the
+// 64 colors, one after the other in the order of the emphasis.
 
-pub const palette: [(u8, u8, u8); 64] = [
+use std::path::Path;
+
+/// How much the colors that are not emphasized are darkened, in 1/1000: -1.76 dB.
+const ATTENUATION: u32 = 816;
+
+pub const PALETTE: [(u8, u8, u8); 64] = [
     (0x52, 0x52, 0x52), /* 0x00 */
     (0x01, 0x1a, 0x51), /* 0x01 */
     (0x0f, 0x0f, 0x65), /* 0x02 */
@@ -64,4 +87,145 @@ pub const palette: [(u8, u8, u8); 64] = [
     (0xa9, 0xa9, 0xa9), /* 0x3d */
     (0x00, 0x00, 0x00), /* 0x3e */
     (0x00, 0x00, 0x00), /* 0x3f */
-];
\ No newline at end of file
+];
+
+/// The palette of FCEUX, and of many other emulators.
+pub const FCEUX_PALETTE: [(u8, u8, u8); 64] = [
+    (0x74, 0x74, 0x74), (0x24, 0x18, 0x8c), (0x00, 0x00, 0xa8), (0x44, 0x00, 0x9c),
+    (0x8c, 0x00, 0x74), (0xa8, 0x00, 0x10), (0xa4, 0x00, 0x00), (0x7c, 0x08, 0x00),
+    (0x40, 0x2c, 0x00), (0x00, 0x44, 0x00), (0x00, 0x50, 0x00), (0x00, 0x3c, 0x14),
+    (0x18, 0x3c, 0x5c), (0x00, 0x00, 0x00), (0x00, 0x00, 0x00), (0x00, 0x00, 0x00),
+    (0xbc, 0xbc, 0xbc), (0x00, 0x70, 0xec), (0x20, 0x38, 0xec), (0x80, 0x00, 0xf0),
+    (0xbc, 0x00, 0xbc), (0xe4, 0x00, 0x58), (0xd8, 0x28, 0x00), (0xc8, 0x4c, 0x0c),
+    (0x88, 0x70, 0x00), (0x00, 0x94, 0x00), (0x00, 0xa8, 0x00), (0x00, 0x90, 0x38),
+    (0x00, 0x80, 0x88), (0x00, 0x00, 0x00), (0x00, 0x00, 0x00), (0x00, 0x00, 0x00),
+    (0xfc, 0xfc, 0xfc), (0x3c, 0xbc, 0xfc), (0x5c, 0x94, 0xfc), (0xcc, 0x88, 0xfc),
+    (0xf4, 0x78, 0xfc), (0xfc, 0x74, 0xb4), (0xfc, 0x74, 0x60), (0xfc, 0x98, 0x38),
+    (0xf0, 0xbc, 0x3c), (0x80, 0xd0, 0x10), (0x4c, 0xdc, 0x48), (0x58, 0xf8, 0x98),
+    (0x00, 0xe8, 0xd8), (0x78, 0x78, 0x78), (0x00, 0x00, 0x00), (0x00, 0x00, 0x00),
+    (0xfc, 0xfc, 0xfc), (0xa8, 0xe4, 0xfc), (0xc4, 0xd4, 0xfc), (0xd4, 0xc8, 0xfc),
+    (0xfc, 0xc4, 0xfc), (0xfc, 0xc4, 0xd8), (0xfc, 0xbc, 0xb0), (0xfc, 0xd8, 0xa8),
+    (0xfc, 0xe4, 0xa0), (0xe0, 0xfc, 0xa0), (0xa8, 0xf0, 0xbc), (0xb0, 0xfc, 0xcc),
+    (0x9c, 0xfc, 0xf0), (0xc4, 0xc4, 0xc4), (0x00, 0x00, 0x00), (0x00, 0x00, 0x00),
+];
+
+/// The names of the palettes `MasterPalette::preset` knows, the default first.
+pub const PRESETS: [&str; 2] = ["default", "fceux"];
+
+/// `PALETTE` with every emphasis (0-7, see the top of the file), indexed by the emphasis and then the color.
+pub const EMPHASIS_PALETTES: [[(u8, u8, u8); 64]; 8] = emphasis_palettes(PALETTE);
+
+const fn emphasis_palettes(palette: [(u8, u8, u8); 64]) -> [[(u8, u8, u8); 64]; 8] {
+    let mut palettes = [palette; 8];
+    let mut emphasis = 1;
+    while emphasis < 8 {
+        let mut color = 0;
+        while color < 64 {
+            let (r, g, b) = palette[color];
+            palettes[emphasis][color] = (
+                attenuate(r, emphasis, 0b001),
+                attenuate(g, emphasis, 0b010),
+                attenuate(b, emphasis, 0b100),
+            );
+            color += 1;
+        }
+        emphasis += 1;
+    }
+    palettes
+}
+
+/// A channel keeps its value when it's emphasized, unless all three are: then they are all darkened.
+const fn attenuate(value: u8, emphasis: usize, channel: usize) -> u8 {
+    if emphasis & channel != 0 && emphasis != 0b111 {
+        value
+    } else {
+        (value as u32 * ATTENUATION / 1000) as u8
+    }
+}
+
+/// The colors the PPU's color indexes are shown with, for every emphasis. See the top of the file.
+#[derive(Clone, PartialEq, Eq, Debug)]
+pub struct MasterPalette {
+    colors: Box<[[(u8, u8, u8); 64]; 8]>,
+}
+
+impl Default for MasterPalette {
+    fn default() -> Self {
+        MasterPalette { colors: Box::new(EMPHASIS_PALETTES) }
+    }
+}
+
+impl MasterPalette {
+    /// 64 colors, with the emphasis variants computed from them.
+    pub fn from_colors(colors: [(u8, u8, u8); 64]) -> Self {
+        MasterPalette { colors: Box::new(emphasis_palettes(colors)) }
+    }
+
+    /// One of `PRESETS`, by name (in any case).
+    pub fn preset(name: &str) -> Option<Self> {
+        match name.to_ascii_lowercase().as_str() {
+            "default" => Some(Self::default()),
+            "fceux" => Some(Self::from_colors(FCEUX_PALETTE)),
+            _ => None,
+        }
+    }
+
+    /// The bytes of a .pal file: 64 or 512 RGB colors.
+    pub fn from_pal(bytes: &[u8]) -> Result<Self, String> {
+        let rgb = |i: usize| (bytes[i * 3], bytes[i * 3 + 1], bytes[i * 3 + 2]);
+        match bytes.len() {
+            192 => Ok(Self::from_colors(core::array::from_fn(rgb))),
+            1536 => Ok(MasterPalette { colors: Box::new(core::array::from_fn(|emphasis| core::array::from_fn(|color| rgb(emphasis * 64 + color)))) }),
+            len => Err(format!("A palette is 192 bytes (64 colors) or 1536 bytes (512, with the emphasis), not {}", len)),
+        }
+    }
+
+    pub fn load(path: &Path) -> Result<Self, String> {
+        let bytes = std::fs::read(path).map_err(|err| format!("Can't read {}: {}", path.display(), err))?;
+        Self::from_pal(&bytes).map_err(|err| format!("{}: {}", path.display(), err))
+    }
+
+    /// A preset, or else a .pal file: for `--palette`.
+    pub fn preset_or_file(name: &str) -> Result<Self, String> {
+        match Self::preset(name) {
+            Some(palette) => Ok(palette),
+            None => Self::load(Path::new(name)).map_err(|err| format!("{} (or a preset: {})", err, PRESETS.join(", "))),
+        }
+    }
+
+    /// The color of `index` (0x00-0x3F) with `emphasis` (0-7).
+    pub fn color(&self, emphasis: u8, index: u8) -> (u8, u8, u8) {
+        self.colors[(emphasis & 0b111) as usize][(index & 0x3F) as usize]
+    }
+
+    /// The 64 colors with `emphasis`.
+    pub fn colors(&self, emphasis: u8) -> &[(u8, u8, u8); 64] {
+        &self.colors[(emphasis & 0b111) as usize]
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn pal_test() {
+        let mut pal: Vec<u8> = (0..64).flat_map(|i| [i as u8, 0x80, 0xFF]).collect();
+        let palette = MasterPalette::from_pal(&pal).unwrap();
+        assert_eq!(palette.color(0, 5), (5, 0x80, 0xFF));
+        // Green emphasized: red and blue darker.
+        assert_eq!(palette.color(0b010, 5), (4, 0x80, 0xD0));
+        assert_eq!(palette.colors(0b010)[5], palette.color(0b010, 5));
+
+        // 8 variants, as they are.
+        pal.extend(std::iter::repeat_n(0x11, 1536 - 192));
+        let palette = MasterPalette::from_pal(&pal).unwrap();
+        assert_eq!(palette.color(0, 63), (63, 0x80, 0xFF));
+        assert_eq!(palette.color(0b111, 63), (0x11, 0x11, 0x11));
+
+        assert!(MasterPalette::from_pal(&pal[..300]).is_err());
+        assert_eq!(MasterPalette::preset("FCEUX").unwrap().color(0, 0x30), (0xfc, 0xfc, 0xfc));
+        assert_eq!(MasterPalette::preset("default"), Some(MasterPalette::default()));
+        assert!(MasterPalette::preset_or_file("no-such-palette.pal").unwrap_err().contains("default, fceux"));
+    }
+}
@@ -0,0 +1,201 @@
+use super::colors::MasterPalette;
+use crate::png;
+use crate::save_state::{SaveState, StateReader, StateWriter};
+
+pub const WIDTH: usize = 256;
+pub const HEIGHT: usize = 240;
+/// Most TVs don't show the top and bottom 8 lines, and games often have garbage there.
+pub const OVERSCAN_LINES: usize = 8;
+
+/// The picture the PPU outputs. Each pixel is an index into the NES master palette (0x00 - 0x3F), not an RGB color.
+/// Converting to RGB is the job of whoever displays the frame, and the framebuffer has the palette for it (a setting,
+/// not in save states).
+///
+/// The color emphasis (see `colors.rs`) is kept for every scanline, not every pixel. Games change it between frames,
+/// or between scanlines, so it's the same for the whole scanline anyway.
+#[derive(Clone, PartialEq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct Framebuffer {
+    #[cfg_attr(feature = "serde", serde(with = "crate::serde_bytes_base64"))]
+    pixels: Box<[u8; WIDTH * HEIGHT]>,
+    #[cfg_attr(feature = "serde", serde(with = "crate::serde_bytes_base64"))]
+    emphasis: [u8; HEIGHT],
+    #[cfg_attr(feature = "serde", serde(skip))]
+    palette: MasterPalette,
+}
+
+impl Framebuffer {
+    pub fn new() -> Self {
+        Framebuffer { pixels: Box::new([0; WIDTH * HEIGHT]), emphasis: [0; HEIGHT], palette: MasterPalette::default() }
+    }
+
+    /// The colors of the RGB conversions.
+    pub fn palette(&self) -> &MasterPalette {
+        &self.palette
+    }
+
+    pub fn set_palette(&mut self, palette: MasterPalette) {
+        self.palette = palette;
+    }
+
+    pub fn get(&self, x: usize, y: usize) -> u8 {
+        self.pixels[y * WIDTH + x]
+    }
+
+    pub fn set(&mut self, x: usize, y: usize, color: u8) {
+        self.pixels[y * WIDTH + x] = color;
+    }
+
+    /// Row by row, left to right.
+    pub fn pixels(&self) -> &[u8] {
+        &self.pixels[..]
+    }
+
+    /// The color emphasis of scanline `y`, 0-7: bit 0 is red, bit 1 green, and bit 2 blue.
+    pub fn emphasis(&self, y: usize) -> u8 {
+        self.emphasis[y]
+    }
+
+    pub fn set_emphasis(&mut self, y: usize, emphasis: u8) {
+        self.emphasis[y] = emphasis & 0b111;
+    }
+
+    /// RGB of every pixel, row by row.
+    fn rgb_pixels(&self) -> impl Iterator<Item = (u8, u8, u8)> + '_ {
+        self.pixels.chunks_exact(WIDTH).zip(self.emphasis.iter()).flat_map(|(row, &emphasis)| {
+            let palette = self.palette.colors(emphasis);
+            row.iter().map(move |pixel| palette[(pixel & 0x3F) as usize])
+        })
+    }
+
+    /// Convert to RGB, 3 bytes per pixel, row by row. `out` must be `WIDTH * HEIGHT * 3` bytes long.
+    pub fn write_rgb24(&self, out: &mut [u8]) {
+        for ((r, g, b), rgb) in self.rgb_pixels().zip(out.chunks_exact_mut(3)) {
+            rgb.copy_from_slice(&[r, g, b]);
+        }
+    }
+
+    /// Convert to RGBA (opaque), 4 bytes per pixel, row by row, like a canvas `ImageData`. `out` must be
+    /// `WIDTH * HEIGHT * 4` bytes long.
+    pub fn write_rgba32(&self, out: &mut [u8]) {
+        for ((r, g, b), rgba) in self.rgb_pixels().zip(out.chunks_exact_mut(4)) {
+            rgba.copy_from_slice(&[r, g, b, 0xFF]);
+        }
+    }
+
+    /// Convert to 0x00RRGGBB (XRGB8888, like libretro), a `u32` per pixel, row by row. `out` must be
+    /// `WIDTH * HEIGHT` long.
+    pub fn write_xrgb32(&self, out: &mut [u32]) {
+        for ((r, g, b), xrgb) in self.rgb_pixels().zip(out.iter_mut()) {
+            *xrgb = (r as u32) << 16 | (g as u32) << 8 | b as u32;
+        }
+    }
+
+    /// A binary PPM (P6) image: the simplest file any image viewer opens, for the debugger.
+    pub fn to_ppm(&self) -> Vec<u8> {
+        let mut ppm = format!("P6\n{} {}\n255\n", WIDTH, HEIGHT).into_bytes();
+        let header = ppm.len();
+        ppm.resize(header + WIDTH * HEIGHT * 3, 0);
+        self.write_rgb24(&mut ppm[header..]);
+        ppm
+    }
+
+    /// A PNG image (see png.rs), 256x240, or 256x224 without the overscan lines. For screenshots and golden images.
+    pub fn to_png(&self, crop_overscan: bool) -> Vec<u8> {
+        let mut rgb = vec![0; WIDTH * HEIGHT * 3];
+        self.write_rgb24(&mut rgb);
+        let lines = if crop_overscan { OVERSCAN_LINES..HEIGHT - OVERSCAN_LINES } else { 0..HEIGHT };
+        png::encode_rgb(WIDTH, lines.len(), &rgb[lines.start * WIDTH * 3..lines.end * WIDTH * 3])
+    }
+}
+
+impl Default for Framebuffer {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl SaveState for Framebuffer {
+    fn save_state(&self, out: &mut StateWriter) {
+        out.bytes(&self.pixels[..]);
+        out.bytes(&self.emphasis);
+    }
+
+    fn load_state(&mut self, input: &mut StateReader) -> Result<(), String> {
+        input.bytes(&mut self.pixels[..])?;
+        input.bytes(&mut self.emphasis)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::ppu::colors::PALETTE;
+
+    #[test]
+    fn write_rgb24_test() {
+        let mut framebuffer = Framebuffer::new();
+        framebuffer.set(1, 0, 0x30);
+        framebuffer.set(0, 1, 0x01);
+
+        let mut rgb = vec![0; WIDTH * HEIGHT * 3];
+        framebuffer.write_rgb24(&mut rgb);
+
+        assert_eq!(rgb[0..3], [PALETTE[0].0, PALETTE[0].1, PALETTE[0].2]);
+        assert_eq!(rgb[3..6], [PALETTE[0x30].0, PALETTE[0x30].1, PALETTE[0x30].2]);
+        let second_row = WIDTH * 3;
+        assert_eq!(rgb[second_row..second_row + 3], [PALETTE[1].0, PALETTE[1].1, PALETTE[1].2]);
+    }
+
+    #[test]
+    fn write_rgba32_test() {
+        let mut framebuffer = Framebuffer::new();
+        framebuffer.set(1, 0, 0x30);
+
+        let mut rgba = vec![0; WIDTH * HEIGHT * 4];
+        framebuffer.write_rgba32(&mut rgba);
+
+        assert_eq!(rgba[0..4], [PALETTE[0].0, PALETTE[0].1, PALETTE[0].2, 0xFF]);
+        assert_eq!(rgba[4..8], [PALETTE[0x30].0, PALETTE[0x30].1, PALETTE[0x30].2, 0xFF]);
+        assert!(rgba.chunks_exact(4).all(|pixel| pixel[3] == 0xFF));
+    }
+
+    #[test]
+    fn write_xrgb32_test() {
+        let mut framebuffer = Framebuffer::new();
+        framebuffer.set(1, 0, 0x30);
+
+        let mut xrgb = vec![0; WIDTH * HEIGHT];
+        framebuffer.write_xrgb32(&mut xrgb);
+
+        let (r, g, b) = PALETTE[0x30];
+        assert_eq!(xrgb[1], (r as u32) << 16 | (g as u32) << 8 | b as u32);
+        assert!(xrgb.iter().all(|pixel| pixel >> 24 == 0));
+    }
+
+    #[test]
+    fn to_ppm_test() {
+        let mut framebuffer = Framebuffer::new();
+        framebuffer.set(0, 0, 0x30);
+        let ppm = framebuffer.to_ppm();
+        assert!(ppm.starts_with(b"P6\n256 240\n255\n"));
+        assert_eq!(ppm.len(), 15 + WIDTH * HEIGHT * 3);
+        assert_eq!(ppm[15..18], [PALETTE[0x30].0, PALETTE[0x30].1, PALETTE[0x30].2]);
+    }
+
+    #[test]
+    fn emphasis_test() {
+        let mut framebuffer = Framebuffer::new();
+        framebuffer.set(0, 0, 0x30);
+        framebuffer.set(0, 1, 0x30);
+        framebuffer.set_emphasis(1, 0b001);
+
+        let mut rgb = vec![0; WIDTH * HEIGHT * 3];
+        framebuffer.write_rgb24(&mut rgb);
+        // Only the second row, with red emphasized: green and blue are darker.
+        let white = PALETTE[0x30];
+        assert_eq!(rgb[0..3], [white.0, white.1, white.2]);
+        let second_row = WIDTH * 3;
+        assert_eq!(rgb[second_row..second_row + 3], [white.0, (white.1 as u32 * 816 / 1000) as u8, (white.2 as u32 * 816 / 1000) as u8]);
+    }
+}
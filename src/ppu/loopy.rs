@@ -0,0 +1,242 @@
+// The PPU internal scroll registers, named after "loopy" who documented them.
+// https://www.nesdev.org/wiki/PPU_scrolling
+//
+// v and t are 15 bits wide, and are laid out like this:
+//
+// yyy NN YYYYY XXXXX
+// ||| || ||||| +++++-- coarse X scroll
+// ||| || +++++-------- coarse Y scroll
+// ||| ++-------------- nametable select
+// +++----------------- fine Y scroll
+//
+// Binary literals in this file are grouped by these fields, not by nibbles.
+#![allow(clippy::unusual_byte_groupings)]
+
+const COARSE_X: u16 = 0b000_00_00000_11111;
+const COARSE_Y: u16 = 0b000_00_11111_00000;
+const NAMETABLE_X: u16 = 0b000_01_00000_00000;
+const NAMETABLE_Y: u16 = 0b000_10_00000_00000;
+const FINE_Y: u16 = 0b111_00_00000_00000;
+
+/// Bits that are copied from t to v at dot 257 of each rendering scanline.
+const HORIZONTAL_BITS: u16 = COARSE_X | NAMETABLE_X;
+/// Bits that are copied from t to v at dots 280-304 of the pre-render scanline.
+const VERTICAL_BITS: u16 = COARSE_Y | NAMETABLE_Y | FINE_Y;
+
+/// # Loopy registers
+///
+/// | Register | Size | Description |
+/// |---|---|---|
+/// | v | 15 bits | Current VRAM address. While rendering, its also the scroll position of the current tile. |
+/// | t | 15 bits | Temporary VRAM address. Holds the scroll position of the top left onscreen tile until its copied to v. |
+/// | x | 3 bits | Fine X scroll. |
+/// | w | 1 bit | First or second write toggle, shared by $2005 and $2006. |
+#[derive(Default, Clone, Copy, PartialEq, Debug)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct LoopyRegisters {
+    pub v: u16,
+    pub t: u16,
+    pub x: u8,
+    pub w: bool,
+}
+
+impl LoopyRegisters {
+    /// Write to PPUCTRL ($2000). Only the nametable select bits matter here.
+    pub fn write_ctrl(&mut self, data: u8) {
+        // t: ...GH.. ........ <- d: ......GH
+        self.t = (self.t & !(NAMETABLE_X | NAMETABLE_Y)) | (((data & 0b11) as u16) << 10);
+    }
+
+    /// Read from PPUSTATUS ($2002) resets the write toggle.
+    pub fn read_status(&mut self) {
+        self.w = false;
+    }
+
+    /// Write to PPUSCROLL ($2005). First write is X scroll, second write is Y scroll.
+    pub fn write_scroll(&mut self, data: u8) {
+        if !self.w {
+            // t: ....... ...ABCDE <- d: ABCDE...
+            // x:              FGH <- d: .....FGH
+            self.t = (self.t & !COARSE_X) | ((data >> 3) as u16);
+            self.x = data & 0b111;
+        } else {
+            // t: FGH..AB CDE..... <- d: ABCDEFGH
+            self.t = (self.t & !(COARSE_Y | FINE_Y))
+                | (((data & 0b111) as u16) << 12)
+                | (((data & 0b1111_1000) as u16) << 2);
+        }
+        self.w = !self.w;
+    }
+
+    /// Write to PPUADDR ($2006). First write is the high byte, second write is the low byte and also sets v.
+    pub fn write_addr(&mut self, data: u8) {
+        if !self.w {
+            // t: .CDEFGH ........ <- d: ..CDEFGH
+            // t: Z...... ........ <- 0 (bit 14 is cleared)
+            self.t = (self.t & 0x00FF) | (((data & 0b0011_1111) as u16) << 8);
+        } else {
+            // t: ....... ABCDEFGH <- d: ABCDEFGH
+            self.t = (self.t & 0xFF00) | data as u16;
+            self.v = self.t;
+        }
+        self.w = !self.w;
+    }
+
+    /// Increment coarse X, and wrap into the horizontally neighbouring nametable. Happens every 8 dots while rendering.
+    pub fn increment_coarse_x(&mut self) {
+        if self.v & COARSE_X == 31 {
+            self.v &= !COARSE_X;
+            self.v ^= NAMETABLE_X;
+        } else {
+            self.v += 1;
+        }
+    }
+
+    /// Increment fine Y, overflowing into coarse Y, and wrap into the vertically neighbouring nametable. Happens at dot 256.
+    pub fn increment_y(&mut self) {
+        if self.v & FINE_Y != FINE_Y {
+            self.v += 0x1000;
+            return;
+        }
+
+        self.v &= !FINE_Y;
+        let mut coarse_y = (self.v & COARSE_Y) >> 5;
+        if coarse_y == 29 {
+            // Row 29 is the last row of tiles in a nametable; the next rows are the attribute table.
+            coarse_y = 0;
+            self.v ^= NAMETABLE_Y;
+        } else if coarse_y == 31 {
+            // Coarse Y can be set out of bounds (into the attribute table). It wraps without switching nametable.
+            coarse_y = 0;
+        } else {
+            coarse_y += 1;
+        }
+        self.v = (self.v & !COARSE_Y) | (coarse_y << 5);
+    }
+
+    /// Copy all horizontal position bits from t to v.
+    pub fn copy_horizontal(&mut self) {
+        self.v = (self.v & !HORIZONTAL_BITS) | (self.t & HORIZONTAL_BITS);
+    }
+
+    /// Copy all vertical position bits from t to v.
+    pub fn copy_vertical(&mut self) {
+        self.v = (self.v & !VERTICAL_BITS) | (self.t & VERTICAL_BITS);
+    }
+
+    /// Address of the nametable byte of the tile v points to.
+    pub fn tile_address(&self) -> u16 {
+        0x2000 | (self.v & 0x0FFF)
+    }
+
+    /// Address of the attribute byte of the tile v points to.
+    pub fn attribute_address(&self) -> u16 {
+        0x23C0 | (self.v & (NAMETABLE_X | NAMETABLE_Y)) | ((self.v >> 4) & 0x38) | ((self.v >> 2) & 0x07)
+    }
+
+    pub fn coarse_x(&self) -> u16 {
+        self.v & COARSE_X
+    }
+
+    pub fn coarse_y(&self) -> u16 {
+        (self.v & COARSE_Y) >> 5
+    }
+
+    pub fn fine_y(&self) -> u16 {
+        (self.v & FINE_Y) >> 12
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn register_writes_test() {
+        // The example from: https://www.nesdev.org/wiki/PPU_scrolling#Summary
+        let mut loopy = LoopyRegisters::default();
+
+        loopy.write_ctrl(0x00);
+        assert_eq!(loopy.t & 0x0C00, 0);
+
+        loopy.read_status();
+        assert!(!loopy.w);
+
+        loopy.write_scroll(0x7D);
+        assert_eq!(loopy.t, 0b000_00_00000_01111);
+        assert_eq!(loopy.x, 0b101);
+        assert!(loopy.w);
+
+        loopy.write_scroll(0x5E);
+        assert_eq!(loopy.t, 0b110_00_01011_01111);
+        assert!(!loopy.w);
+
+        loopy.write_addr(0x3D);
+        assert_eq!(loopy.t, 0b011_11_01011_01111);
+        assert!(loopy.w);
+
+        loopy.write_addr(0xF0);
+        assert_eq!(loopy.t, 0b011_11_01111_10000);
+        assert_eq!(loopy.v, loopy.t);
+        assert!(!loopy.w);
+    }
+
+    #[test]
+    fn increment_coarse_x_test() {
+        let mut loopy = LoopyRegisters { v: 30, ..Default::default() };
+
+        loopy.increment_coarse_x();
+        assert_eq!(loopy.v, 31);
+
+        // Wrap to the next horizontal nametable.
+        loopy.increment_coarse_x();
+        assert_eq!(loopy.v, NAMETABLE_X);
+
+        // And back.
+        loopy.v |= 31;
+        loopy.increment_coarse_x();
+        assert_eq!(loopy.v, 0);
+    }
+
+    #[test]
+    fn increment_y_test() {
+        // Fine Y is incremented first.
+        let mut loopy = LoopyRegisters { v: 0x6000, ..Default::default() };
+        loopy.increment_y();
+        assert_eq!(loopy.v, 0x7000);
+
+        // Fine Y overflows into coarse Y.
+        loopy.increment_y();
+        assert_eq!(loopy.fine_y(), 0);
+        assert_eq!(loopy.coarse_y(), 1);
+
+        // Coarse Y 29 wraps into the next vertical nametable.
+        loopy.v = FINE_Y | (29 << 5);
+        loopy.increment_y();
+        assert_eq!(loopy.v, NAMETABLE_Y);
+
+        // Coarse Y 31 wraps, but stays in the same nametable.
+        loopy.v = FINE_Y | (31 << 5);
+        loopy.increment_y();
+        assert_eq!(loopy.v, 0);
+    }
+
+    #[test]
+    fn copy_test() {
+        let mut loopy = LoopyRegisters { v: 0, t: 0x7FFF, ..Default::default() };
+
+        loopy.copy_horizontal();
+        assert_eq!(loopy.v, 0b000_01_00000_11111);
+
+        loopy.copy_vertical();
+        assert_eq!(loopy.v, 0x7FFF);
+    }
+
+    #[test]
+    fn attribute_address_test() {
+        // Coarse X = 5, coarse Y = 9, nametable 1.
+        let loopy = LoopyRegisters { v: NAMETABLE_X | (9 << 5) | 5, ..Default::default() };
+        assert_eq!(loopy.tile_address(), 0x2400 + 9 * 32 + 5);
+        assert_eq!(loopy.attribute_address(), 0x27C0 + (9 / 4) * 8 + 5 / 4);
+    }
+}
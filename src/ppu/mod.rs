@@ -1,7 +1,10 @@
 mod ppuctrl;
-mod colors;
 mod ppumask;
 mod ppustatus;
 mod registers;
 
-pub mod ppu;
\ No newline at end of file
+pub mod colors;
+pub mod framebuffer;
+pub mod loopy;
+pub mod ppu;
+pub mod scanline;
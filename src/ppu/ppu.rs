@@ -1,14 +1,1285 @@
+use log::debug;
+
+use super::colors::MasterPalette;
+use super::framebuffer::Framebuffer;
+use super::loopy::LoopyRegisters;
 use super::registers::Registers;
+use super::scanline::ScanlineState;
+use crate::cartridge::Cartridge;
+use crate::log_target::PPU;
+use crate::mapper::{BackgroundFetch, BackgroundTile, Nametable};
+use crate::region::Region;
+use crate::save_state::{SaveState, StateReader, StateWriter};
+
+// https://www.nesdev.org/wiki/PPU_rendering
+// An NTSC frame is 262 scanlines, each 341 dots (PPU cycles) long.
+// Scanlines 0-239 are visible, 240 is idle (post-render), 241-260 are vertical blank, and 261 is the pre-render scanline.
+// PAL has 50 more VBlank scanlines (241-310), so the pre-render scanline is 311. See `region.rs`.
+//
+// Reading PPUSTATUS races with the VBlank flag being set (dot 1 of the first VBlank scanline):
+// https://www.nesdev.org/wiki/PPU_frame_timing#VBL_Flag_Timing
+// - A read on the very dot the flag is set (the CPU read and the PPU dot coincide) returns it clear, and it stays clear:
+//   the frame has no VBlank flag and no NMI.
+// - A read 1 or 2 dots later returns it set, and clears it like any read, but the NMI of the frame is suppressed too.
+// - Later reads don't change the NMI: it has already gone to the CPU.
+// The CPU reads on the last cycle of the instruction (see nes_bus.rs), so which of them a read lands on depends on
+// how the CPU cycles line up with the dots in that frame, like on the console. ppu_vbl_nmi tests 02 to 06 time these.
+//
+// The NMI line is down while the VBlank flag and PPUCTRL bit 7 are both set, and the CPU takes an NMI when it goes
+// down (`take_nmi`, which the bus gives the CPU, see `Bus::take_nmi`): at the start of VBlank, or when bit 7 is turned
+// on during VBlank, before PPUSTATUS is read. The CPU polls it before the last cycle of every instruction.
+// Turning bit 7 off brings the line up again. 1 or 2 dots after the flag is set, like the PPUSTATUS read, that's
+// before the CPU saw it go down: no NMI. Later, it's already on its way. ppu_vbl_nmi tests 04 to 08 time these.
+//
+// Sprites: https://www.nesdev.org/wiki/PPU_sprite_evaluation
+// At dot 257 of a rendering scanline, the PPU picks the sprites of the next scanline from OAM: the first 8 in OAM
+// order whose Y (the scanline above the sprite) puts them on it, see scanline.rs. More than 8 set the sprite overflow
+// flag (without the bug of the console, which misses some and finds others that aren't there). Their pattern bytes
+// are fetched at dot 320, with the banks of then, and flipped already. The pre-render scanline finds none: no sprite
+// is drawn on scanline 0.
+// Each pixel is the first opaque sprite of the 8 (the lowest in OAM), over the background, or behind it when the
+// priority bit of the sprite says so: then it shows only where the background is transparent. Sprite 0 hit is set
+// where an opaque pixel of sprite 0 meets an opaque pixel of the background, but never at x = 255, and not in the
+// leftmost 8 pixels when either of them is clipped there.
+pub const DOTS_PER_SCANLINE: u16 = 341;
 
+/// Called at the start of every visible scanline, see scanline.rs.
+pub type ScanlineCallback = Box<dyn FnMut(u16, &ScanlineState)>;
+
+/// Nametable mirroring, set by the cartridge. The PPU has only 2KB of VRAM, which is enough for 2 nametables out of 4.
+/// Some mappers (VRC6) can also show the same 1KB in all 4: the first one (lower) or the second one (upper).
+#[derive(Clone, Copy, PartialEq, Debug)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub enum Mirroring {
+    Horizontal,
+    Vertical,
+    SingleScreenLower,
+    SingleScreenUpper,
+}
+
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
 pub struct PPU {
-    pub registers: Registers
+    pub registers: Registers,
+    region: Region,
+    loopy: LoopyRegisters,
+
+    #[cfg_attr(feature = "serde", serde(with = "crate::serde_bytes_base64"))]
+    // 0x0000 - 0x1FFF: pattern tables, in the cartridge (CHR ROM or CHR RAM). The methods that touch them take it.
+    vram: [u8; 0x800],          /* 0x2000 - 0x2FFF: nametables (mirrored) */
+    #[cfg_attr(feature = "serde", serde(with = "crate::serde_bytes_base64"))]
+    palette: [u8; 32],          /* 0x3F00 - 0x3F1F: palette RAM */
+    #[cfg_attr(feature = "serde", serde(with = "crate::serde_bytes_base64"))]
+    oam: [u8; 256],
+    oam_addr: u8,
+    pub mirroring: Mirroring,
+
+    data_buffer: u8,            // PPUDATA reads are delayed by one read, except for palette.
+    io_latch: u8,               // The last value written to any register; unused bits of PPUSTATUS read it back.
+
+    scanline: u16,
+    dot: u16,
+    frame: u64,
+
+    // Background pipeline: latches are filled during the 8 dots of a tile fetch, then loaded to the low byte of the shifters.
+    nametable_latch: u8,
+    attribute_latch: u8,
+    pattern_lo_latch: u8,
+    pattern_hi_latch: u8,
+    pattern_lo_shifter: u16,
+    pattern_hi_shifter: u16,
+    attribute_lo_shifter: u16,
+    attribute_hi_shifter: u16,
+
+    // Sprites of the next scanline, from the evaluation at dot 257: their 4 bytes of OAM.
+    #[cfg_attr(feature = "serde", serde(with = "crate::serde_bytes_base64"))]
+    secondary_oam: [u8; 32],
+    secondary_count: u8,
+    secondary_has_sprite_0: bool,
+    // Sprites of this scanline, fetched at dot 320 of the one before: pattern bytes (flipped), attributes and X.
+    sprite_count: u8,
+    sprite_pattern_lo: [u8; 8],
+    sprite_pattern_hi: [u8; 8],
+    sprite_attributes: [u8; 8],
+    sprite_x: [u8; 8],
+    /// The first of them is sprite 0, for the sprite 0 hit.
+    sprite_0_on_scanline: bool,
+
+    framebuffer: Framebuffer,
+    frame_complete: bool,
+    /// The VBlank flag went up with the NMI on: the NMI the CPU has to take, until `take_nmi`.
+    nmi_pending: bool,
+    /// A PPUSTATUS read raced the VBlank flag, see the top of the file: no NMI until the next frame.
+    nmi_suppressed: bool,
+
+    // Not part of the state, like the callbacks of the emulator.
+    #[cfg_attr(feature = "serde", serde(skip))]
+    scanline_callback: Option<ScanlineCallback>,
 }
 
 impl PPU {
     pub fn new() -> Self {
-        let ppu = PPU {
+        PPU {
             registers: Registers::new(),
+            region: Region::Ntsc,
+            loopy: LoopyRegisters::default(),
+            vram: [0; 0x800],
+            palette: [0; 32],
+            oam: [0; 256],
+            oam_addr: 0,
+            mirroring: Mirroring::Horizontal,
+            data_buffer: 0,
+            io_latch: 0,
+            scanline: 0,
+            dot: 0,
+            frame: 0,
+            nametable_latch: 0,
+            attribute_latch: 0,
+            pattern_lo_latch: 0,
+            pattern_hi_latch: 0,
+            pattern_lo_shifter: 0,
+            pattern_hi_shifter: 0,
+            attribute_lo_shifter: 0,
+            attribute_hi_shifter: 0,
+            secondary_oam: [0xFF; 32],
+            secondary_count: 0,
+            secondary_has_sprite_0: false,
+            sprite_count: 0,
+            sprite_pattern_lo: [0; 8],
+            sprite_pattern_hi: [0; 8],
+            sprite_attributes: [0; 8],
+            sprite_x: [0; 8],
+            sprite_0_on_scanline: false,
+            framebuffer: Framebuffer::new(),
+            frame_complete: false,
+            nmi_pending: false,
+            nmi_suppressed: false,
+            scanline_callback: None,
+        }
+    }
+
+    pub fn set_region(&mut self, region: Region) {
+        self.region = region;
+    }
+
+    /// The internal scroll registers (v, t, x, w). Read only, for debugging.
+    pub fn scroll_registers(&self) -> LoopyRegisters {
+        self.loopy
+    }
+
+    pub fn scanline(&self) -> u16 {
+        self.scanline
+    }
+
+    pub fn dot(&self) -> u16 {
+        self.dot
+    }
+
+    pub fn frame(&self) -> u64 {
+        self.frame
+    }
+
+    pub fn framebuffer(&self) -> &Framebuffer {
+        &self.framebuffer
+    }
+
+    /// The colors the frames are shown with, see colors.rs.
+    pub fn set_master_palette(&mut self, palette: MasterPalette) {
+        self.framebuffer.set_palette(palette);
+    }
+
+    /// Call `callback` at the start (dot 0) of every visible scanline, with what the scanline is drawn with.
+    pub fn set_scanline_callback<F: FnMut(u16, &ScanlineState) + 'static>(&mut self, callback: F) {
+        self.scanline_callback = Some(Box::new(callback));
+    }
+
+    pub fn clear_scanline_callback(&mut self) {
+        self.scanline_callback = None;
+    }
+
+    /// The scroll, registers and sprites of the current scanline, see scanline.rs.
+    pub fn scanline_state(&self) -> ScanlineState {
+        ScanlineState::new(self.scanline, self.loopy, self.registers.ppuctrl.register, self.registers.ppumask.register, &self.oam)
+    }
+
+    /// Returns true once per frame, when the last visible scanline was drawn. Reading it clears it.
+    pub fn take_frame_complete(&mut self) -> bool {
+        let res = self.frame_complete;
+        self.frame_complete = false;
+        res
+    }
+
+    /// The VBlank NMI of this frame is waiting for the CPU. Reading PPUSTATUS right after the flag is set cancels it,
+    /// see the top of the file.
+    pub fn nmi_pending(&self) -> bool {
+        self.nmi_pending
+    }
+
+    /// Whether the VBlank NMI is waiting, and it's no longer waiting: the CPU takes it.
+    pub fn take_nmi(&mut self) -> bool {
+        std::mem::take(&mut self.nmi_pending)
+    }
+
+    /// A12 of the PPU address bus just rose, which MMC3 counts scanlines with, see mmc3.rs. Once per rendering
+    /// scanline at most, at `a12_rise_dot`.
+    pub fn a12_rose(&self) -> bool {
+        (self.scanline < 240 || self.scanline == self.region.prerender_scanline())
+            && self.rendering_enabled()
+            && self.a12_rise_dot() == Some(self.dot)
+    }
+
+    /// The dot where A12 rises on this scanline, from the pattern tables of the fetches: the background (dots 1-256),
+    /// the 8 sprite slots (dots 257-320, the empty ones fetch tile $FF) and the background of the next scanline (dots
+    /// 321-336). MMC3 ignores A12 going low for a few dots, so only a change of pattern table counts, and only the
+    /// first one: None when the fetches never leave the table at $0000, or never leave the one at $1000.
+    fn a12_rise_dot(&self) -> Option<u16> {
+        let ppuctrl = &self.registers.ppuctrl;
+        let background_high = ppuctrl.bg_pattern_table() == 0x1000;
+        let mut high = background_high;
+        for slot in 0..8 {
+            let sprite_high = if ppuctrl.sprite_height() == 16 {
+                self.secondary_oam[slot * 4 + 1] & 1 != 0
+            } else {
+                ppuctrl.sprite_pattern_table() == 0x1000
+            };
+            if sprite_high && !high {
+                return Some(260 + 8 * slot as u16);
+            }
+            high = sprite_high;
+        }
+        (background_high && !high).then_some(324)
+    }
+
+    /// MMC5 counts the scanlines from the fetches, and sees the frame end when they stop: on dot 3 of each scanline,
+    /// whether it's a visible one with rendering on. None on the other dots.
+    pub fn scanline_fetches(&self) -> Option<bool> {
+        (self.dot == 3).then(|| self.scanline < 240 && self.rendering_enabled())
+    }
+
+    fn rendering_enabled(&self) -> bool {
+        self.registers.ppumask.show_bg() != 0 || self.registers.ppumask.show_sprites() != 0
+    }
+
+    /// Read PPU register, mapped to CPU memory at $2000 - $2007 (and mirrored up to $3FFF).
+    pub fn cpu_read(&mut self, addr: u16, cartridge: &Cartridge) -> u8 {
+        match addr & 7 {
+            2 => {
+                // PPUSTATUS: Only the top 3 bits are real, the rest are whatever was on the bus.
+                let res = (self.registers.ppustatus.register & 0b1110_0000) | (self.io_latch & 0b0001_1111);
+                if self.scanline == self.region.vblank_scanline() && (1..=3).contains(&self.dot) {
+                    // The race with the VBlank flag: `dot` is the next one to run, so 1 is the dot that sets it.
+                    debug!(target: PPU, "PPUSTATUS read {} dots after VBlank, the NMI is suppressed", self.dot as i32 - 1);
+                    self.nmi_suppressed = true;
+                    self.nmi_pending = false;
+                }
+                self.registers.ppustatus.set_vertical_blank_started(false);
+                self.loopy.read_status();
+                res
+            }
+            4 => self.oam[self.oam_addr as usize],
+            7 => {
+                // PPUDATA
+                let addr = self.loopy.v & 0x3FFF;
+                let res = if addr >= 0x3F00 {
+                    // Palette is returned immediately, but the buffer is still filled with the nametable 'under' it.
+                    self.data_buffer = self.ppu_read(addr - 0x1000, cartridge);
+                    self.ppu_read(addr, cartridge)
+                } else {
+                    let res = self.data_buffer;
+                    self.data_buffer = self.ppu_read(addr, cartridge);
+                    res
+                };
+                self.increment_vram_addr();
+                res
+            }
+            _ => {
+                // Write only registers.
+                self.io_latch
+            }
+        }
+    }
+
+    /// Write PPU register, mapped to CPU memory at $2000 - $2007 (and mirrored up to $3FFF).
+    pub fn cpu_write(&mut self, addr: u16, data: u8, cartridge: &mut Cartridge) {
+        self.io_latch = data;
+        match addr & 7 {
+            0 => {
+                // NMI is the VBlank flag and bit 7, so turning bit 7 on during VBlank (before PPUSTATUS is read) brings
+                // the line down: an NMI right away. Off and on again, another one.
+                let enabled = self.registers.ppuctrl.generate_nmi() != 0;
+                self.registers.ppuctrl.register = data;
+                self.loopy.write_ctrl(data);
+                if !enabled && self.registers.ppuctrl.generate_nmi() != 0 && self.registers.ppustatus.vertical_blank_started() != 0 {
+                    self.nmi_pending = true;
+                }
+                // Off right after the flag is set, see the top of the file.
+                if enabled && self.registers.ppuctrl.generate_nmi() == 0 && self.scanline == self.region.vblank_scanline() && (2..=3).contains(&self.dot) {
+                    debug!(target: PPU, "NMI turned off {} dots after VBlank, the NMI is cancelled", self.dot - 1);
+                    self.nmi_pending = false;
+                }
+            }
+            1 => self.registers.ppumask.register = data,
+            2 => debug!(target: PPU, "Ignoring write to read only PPU status"),
+            3 => self.oam_addr = data,
+            4 => {
+                self.oam[self.oam_addr as usize] = data;
+                self.oam_addr = self.oam_addr.wrapping_add(1);
+            }
+            5 => self.loopy.write_scroll(data),
+            6 => self.loopy.write_addr(data),
+            7 => {
+                self.ppu_write(self.loopy.v & 0x3FFF, data, cartridge);
+                self.increment_vram_addr();
+            }
+            _ => unreachable!()
+        }
+    }
+
+    /// After a PPUDATA access. While the PPU renders, v is the scroll it fetches tiles with, and the access moves it
+    /// like the fetches do: a coarse X and a Y increment at once, and not by 1 or 32.
+    /// https://www.nesdev.org/wiki/PPU_scrolling#$2007_reads_and_writes
+    fn increment_vram_addr(&mut self) {
+        let rendering_scanline = self.scanline < 240 || self.scanline == self.region.prerender_scanline();
+        if self.rendering_enabled() && rendering_scanline {
+            debug!(target: PPU, "PPUDATA accessed while rendering, at scanline {} dot {}", self.scanline, self.dot);
+            self.loopy.increment_coarse_x();
+            self.loopy.increment_y();
+            return;
+        }
+        let amount = self.registers.ppuctrl.vram_addr_increment_amount();
+        self.loopy.v = self.loopy.v.wrapping_add(amount) & 0x7FFF;
+    }
+
+    /// Read from the PPU's own address space ($0000 - $3FFF).
+    pub fn ppu_read(&self, addr: u16, cartridge: &Cartridge) -> u8 {
+        let addr = addr & 0x3FFF;
+        match addr {
+            0x0000..=0x1FFF => cartridge.ppu_read(addr),
+            0x2000..=0x3EFF => match self.nametable_index(addr, cartridge) {
+                Some(index) => self.vram[index],
+                None => cartridge.nametable_read(addr),
+            },
+            _ => self.palette[Self::palette_index(addr)],
+        }
+    }
+
+    /// Write to the PPU's own address space ($0000 - $3FFF). Writes to CHR ROM are dropped by the cartridge.
+    pub fn ppu_write(&mut self, addr: u16, data: u8, cartridge: &mut Cartridge) {
+        let addr = addr & 0x3FFF;
+        match addr {
+            0x0000..=0x1FFF => {
+                if !cartridge.ppu_write(addr, data) {
+                    debug!(target: PPU, "Ignoring write to CHR ROM at {:#X}, data: {:#X}", addr, data);
+                }
+            }
+            0x2000..=0x3EFF => match self.nametable_index(addr, cartridge) {
+                Some(index) => self.vram[index] = data,
+                None => cartridge.nametable_write(addr, data),
+            },
+            _ => self.palette[Self::palette_index(addr)] = data,
+        }
+    }
+
+    /// Map nametable address ($2000 - $2FFF, and mirror $3000 - $3EFF) to index in the 2KB VRAM. None when the
+    /// nametable is in the cartridge (MMC5's ExRAM).
+    fn nametable_index(&self, addr: u16, cartridge: &Cartridge) -> Option<usize> {
+        let addr = (addr - 0x2000) & 0x0FFF;
+        let table = addr / 0x400;
+        let offset = addr % 0x400;
+        let physical_table = match cartridge.nametables() {
+            Some(nametables) => match nametables[table as usize] {
+                Nametable::Vram(page) => page as u16,
+                Nametable::Cartridge => return None,
+            },
+            None => match self.mirroring {
+                Mirroring::Vertical => table & 1,
+                Mirroring::Horizontal => table >> 1,
+                Mirroring::SingleScreenLower => 0,
+                Mirroring::SingleScreenUpper => 1,
+            },
+        };
+        Some((physical_table * 0x400 + offset) as usize)
+    }
+
+    /// $3F10, $3F14, $3F18, $3F1C are mirrors of $3F00, $3F04, $3F08, $3F0C.
+    fn palette_index(addr: u16) -> usize {
+        let mut index = addr & 0x1F;
+        if index & 0x13 == 0x10 {
+            index &= !0x10;
+        }
+        index as usize
+    }
+
+    /// A single PPU cycle (dot). The cartridge has the pattern tables.
+    pub fn tick(&mut self, cartridge: &Cartridge) {
+        let visible_scanline = self.scanline < 240;
+        let prerender_scanline = self.scanline == self.region.prerender_scanline();
+
+        if visible_scanline && self.dot == 0 && self.scanline_callback.is_some() {
+            let state = self.scanline_state();
+            if let Some(callback) = self.scanline_callback.as_mut() {
+                callback(self.scanline, &state);
+            }
+        }
+
+        if self.rendering_enabled() && (visible_scanline || prerender_scanline) {
+            self.background_step(prerender_scanline, cartridge);
+            self.sprite_step(prerender_scanline, cartridge);
+        }
+
+        if visible_scanline && (1..=256).contains(&self.dot) {
+            self.render_pixel();
+        }
+
+        if self.scanline == self.region.vblank_scanline() && self.dot == 1 {
+            // Unless a read on this very dot got there first.
+            if !self.nmi_suppressed {
+                self.registers.ppustatus.set_vertical_blank_started(true);
+                self.nmi_pending = self.registers.ppuctrl.generate_nmi() != 0;
+            }
+            self.frame_complete = true;
+        }
+
+        if prerender_scanline && self.dot == 1 {
+            self.nmi_suppressed = false;
+            self.nmi_pending = false;
+            self.registers.ppustatus.set_vertical_blank_started(false);
+            self.registers.ppustatus.set_sprite_0_hit(false);
+            self.registers.ppustatus.set_sprite_overflow(false);
+        }
+
+        // On odd frames, when rendering is enabled, the last dot of the pre-render scanline is skipped (NTSC only).
+        if prerender_scanline && self.dot == 339 && self.frame % 2 == 1 && self.rendering_enabled() && self.region.skips_odd_frame_dot() {
+            self.dot += 1;
+        }
+
+        self.dot += 1;
+        if self.dot == DOTS_PER_SCANLINE {
+            self.dot = 0;
+            self.scanline += 1;
+            if self.scanline == self.region.scanlines_per_frame() {
+                self.scanline = 0;
+                self.frame += 1;
+            }
+        }
+    }
+
+    /// Fetch tiles and move the scroll registers, like the real PPU does on rendering scanlines.
+    /// https://www.nesdev.org/w/images/default/4/4f/Ppu.svg
+    fn background_step(&mut self, prerender_scanline: bool, cartridge: &Cartridge) {
+        let dot = self.dot;
+
+        if (2..=257).contains(&dot) || (321..=337).contains(&dot) {
+            self.shift_background();
+
+            match (dot - 1) % 8 {
+                0 => {
+                    self.load_background_shifters();
+                    let tile = self.ppu_read(self.loopy.tile_address(), cartridge);
+                    self.nametable_latch = self.mapper_tile(tile, cartridge).map_or(tile, |mapper_tile| mapper_tile.tile);
+                }
+                2 => {
+                    let attribute = self.ppu_read(self.loopy.attribute_address(), cartridge);
+                    // Each attribute byte covers 4x4 tiles, 2 bits for each 2x2 quadrant.
+                    let shift = ((self.loopy.coarse_y() & 2) << 1) | (self.loopy.coarse_x() & 2);
+                    self.attribute_latch = match self.mapper_tile(self.nametable_latch, cartridge) {
+                        Some(mapper_tile) => mapper_tile.palette,
+                        None => (attribute >> shift) & 0b11,
+                    };
+                }
+                4 => {
+                    self.pattern_lo_latch = match self.mapper_tile(self.nametable_latch, cartridge) {
+                        Some(mapper_tile) => cartridge.chr_byte(mapper_tile.pattern),
+                        None => cartridge.ppu_read_background(self.pattern_address()),
+                    };
+                }
+                6 => {
+                    self.pattern_hi_latch = match self.mapper_tile(self.nametable_latch, cartridge) {
+                        Some(mapper_tile) => cartridge.chr_byte(mapper_tile.pattern + 8),
+                        None => cartridge.ppu_read_background(self.pattern_address() + 8),
+                    };
+                }
+                7 => self.loopy.increment_coarse_x(),
+                _ => ()
+            }
+        }
+
+        if dot == 256 {
+            self.loopy.increment_y();
+        }
+
+        if dot == 257 {
+            self.load_background_shifters();
+            self.loopy.copy_horizontal();
+        }
+
+        if prerender_scanline && (280..=304).contains(&dot) {
+            self.loopy.copy_vertical();
+        }
+    }
+
+    /// Evaluate the sprites of the next scanline, and fetch them, see the top of the file.
+    fn sprite_step(&mut self, prerender_scanline: bool, cartridge: &Cartridge) {
+        match self.dot {
+            257 => {
+                // OAMADDR is used by the fetches, and left at 0.
+                self.oam_addr = 0;
+                self.secondary_oam = [0xFF; 32];
+                self.secondary_count = 0;
+                self.secondary_has_sprite_0 = false;
+                if prerender_scanline {
+                    return;
+                }
+                let next = ScanlineState::new(self.scanline + 1, self.loopy, self.registers.ppuctrl.register, self.registers.ppumask.register, &self.oam);
+                for (slot, &index) in next.sprites().iter().enumerate() {
+                    let index = index as usize * 4;
+                    self.secondary_oam[slot * 4..slot * 4 + 4].copy_from_slice(&self.oam[index..index + 4]);
+                    self.secondary_has_sprite_0 |= index == 0;
+                }
+                self.secondary_count = next.sprites().len() as u8;
+                if next.sprite_overflow() {
+                    self.registers.ppustatus.set_sprite_overflow(true);
+                }
+            }
+            320 => {
+                let height = self.registers.ppuctrl.sprite_height();
+                for slot in 0..self.secondary_count as usize {
+                    let [y, tile, attributes, x] = self.secondary_oam[slot * 4..slot * 4 + 4] else { unreachable!() };
+                    let mut row = self.scanline.wrapping_sub(y as u16) & (height - 1);
+                    if attributes & 0x80 != 0 {
+                        row = height - 1 - row;
+                    }
+                    let addr = if height == 16 {
+                        // Bit 0 of the tile is the pattern table, and the sprite is the tile and the next one.
+                        (tile as u16 & 1) * 0x1000 + ((tile & 0xFE) as u16 + row / 8) * 16 + row % 8
+                    } else {
+                        self.registers.ppuctrl.sprite_pattern_table() + tile as u16 * 16 + row
+                    };
+                    let (mut lo, mut hi) = (self.ppu_read(addr, cartridge), self.ppu_read(addr + 8, cartridge));
+                    if attributes & 0x40 != 0 {
+                        lo = lo.reverse_bits();
+                        hi = hi.reverse_bits();
+                    }
+                    self.sprite_pattern_lo[slot] = lo;
+                    self.sprite_pattern_hi[slot] = hi;
+                    self.sprite_attributes[slot] = attributes;
+                    self.sprite_x[slot] = x;
+                }
+                self.sprite_count = self.secondary_count;
+                self.sprite_0_on_scanline = self.secondary_has_sprite_0;
+            }
+            _ => (),
+        }
+    }
+
+    /// The sprite pixel at `x` of the scanline: its color (1-3, 0 is transparent), palette (4-7), whether it's behind
+    /// the background, and whether it's sprite 0.
+    fn sprite_pixel(&self, x: usize) -> (u8, u8, bool, bool) {
+        for slot in 0..self.sprite_count as usize {
+            let offset = x.wrapping_sub(self.sprite_x[slot] as usize);
+            if offset >= 8 {
+                continue;
+            }
+            let bit = 7 - offset;
+            let pixel = ((self.sprite_pattern_hi[slot] >> bit) & 1) << 1 | ((self.sprite_pattern_lo[slot] >> bit) & 1);
+            if pixel != 0 {
+                let attributes = self.sprite_attributes[slot];
+                return (pixel, 4 + (attributes & 0b11), attributes & 0x20 != 0, slot == 0 && self.sprite_0_on_scanline);
+            }
+        }
+        (0, 0, false, false)
+    }
+
+    /// The tile the mapper has instead of the one of the nametable, for the fetches of the tile (`tile` is the
+    /// nametable byte). The fetches of dots 321-336 are the first 2 tiles of the next scanline.
+    fn mapper_tile(&self, tile: u8, cartridge: &Cartridge) -> Option<BackgroundTile> {
+        let (column, scanline) = if self.dot >= 321 {
+            let next = if self.scanline == self.region.prerender_scanline() { 0 } else { self.scanline + 1 };
+            ((self.dot - 321) / 8, next)
+        } else {
+            ((self.dot - 1) / 8 + 2, self.scanline)
+        };
+        let fetch = BackgroundFetch {
+            column: column as u8,
+            scanline,
+            nametable_addr: self.loopy.tile_address(),
+            tile,
+            fine_y: self.loopy.fine_y(),
+        };
+        cartridge.background_tile(&fetch)
+    }
+
+    fn pattern_address(&self) -> u16 {
+        self.registers.ppuctrl.bg_pattern_table() + (self.nametable_latch as u16) * 16 + self.loopy.fine_y()
+    }
+
+    /// Whenever the PPU renders, even with the background hidden: it's only hidden when drawn, see `render_pixel`.
+    fn shift_background(&mut self) {
+        self.pattern_lo_shifter <<= 1;
+        self.pattern_hi_shifter <<= 1;
+        self.attribute_lo_shifter <<= 1;
+        self.attribute_hi_shifter <<= 1;
+    }
+
+    fn load_background_shifters(&mut self) {
+        self.pattern_lo_shifter = (self.pattern_lo_shifter & 0xFF00) | self.pattern_lo_latch as u16;
+        self.pattern_hi_shifter = (self.pattern_hi_shifter & 0xFF00) | self.pattern_hi_latch as u16;
+        // The attribute is the same for all 8 pixels of the tile.
+        let attribute_lo = if self.attribute_latch & 0b01 != 0 { 0xFF } else { 0x00 };
+        let attribute_hi = if self.attribute_latch & 0b10 != 0 { 0xFF } else { 0x00 };
+        self.attribute_lo_shifter = (self.attribute_lo_shifter & 0xFF00) | attribute_lo;
+        self.attribute_hi_shifter = (self.attribute_hi_shifter & 0xFF00) | attribute_hi;
+    }
+
+    fn render_pixel(&mut self) {
+        let x = (self.dot - 1) as usize;
+        let y = self.scanline as usize;
+
+        let mut pixel = 0;
+        let mut palette = 0;
+
+        let show_bg = self.registers.ppumask.show_bg() != 0;
+        let show_leftmost = self.registers.ppumask.show_bg_leftmost_8() != 0;
+        if show_bg && (x >= 8 || show_leftmost) {
+            let bit = 0x8000 >> self.loopy.x;
+            let lo = (self.pattern_lo_shifter & bit != 0) as u8;
+            let hi = (self.pattern_hi_shifter & bit != 0) as u8;
+            pixel = (hi << 1) | lo;
+
+            let attribute_lo = (self.attribute_lo_shifter & bit != 0) as u8;
+            let attribute_hi = (self.attribute_hi_shifter & bit != 0) as u8;
+            palette = (attribute_hi << 1) | attribute_lo;
+        }
+
+        let show_sprites = self.registers.ppumask.show_sprites() != 0;
+        let show_sprites_leftmost = self.registers.ppumask.show_sprites_leftmost_8() != 0;
+        if show_sprites && (x >= 8 || show_sprites_leftmost) {
+            let (sprite, sprite_palette, behind, sprite_0) = self.sprite_pixel(x);
+            // `pixel` is 0 where the background is hidden or clipped, so those never hit.
+            if sprite_0 && sprite != 0 && pixel != 0 && x != 255 {
+                self.registers.ppustatus.set_sprite_0_hit(true);
+            }
+            if sprite != 0 && (pixel == 0 || !behind) {
+                pixel = sprite;
+                palette = sprite_palette;
+            }
+        }
+
+        // Pixel 0 of every palette is transparent, and shows the universal background color ($3F00).
+        let color = if pixel == 0 {
+            self.palette[0]
+        } else {
+            self.palette[Self::palette_index(((palette as u16) << 2) + pixel as u16)]
+        };
+        // Grayscale keeps only the brightness (the row of the palette): the colors of column 0 are the grays.
+        let mask = if self.registers.ppumask.greyscale() != 0 { 0x30 } else { 0x3F };
+        self.framebuffer.set(x, y, color & mask);
+        if x == 0 {
+            self.framebuffer.set_emphasis(y, self.emphasis());
+        }
+    }
+
+    /// The color emphasis bits of PPUMASK, as the bits of the colors (see `colors.rs`).
+    fn emphasis(&self) -> u8 {
+        let emphasis = self.registers.ppumask.register >> 5;
+        if self.region.swaps_red_green_emphasis() {
+            (emphasis & 0b100) | (emphasis & 0b010) >> 1 | (emphasis & 0b001) << 1
+        } else {
+            emphasis
+        }
+    }
+}
+
+impl Default for PPU {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl SaveState for PPU {
+    fn save_state(&self, out: &mut StateWriter) {
+        out.u8(self.registers.ppuctrl.register);
+        out.u8(self.registers.ppumask.register);
+        out.u8(self.registers.ppustatus.register);
+        out.u16(self.loopy.v);
+        out.u16(self.loopy.t);
+        out.u8(self.loopy.x);
+        out.bool(self.loopy.w);
+        out.bytes(&self.vram);
+        out.bytes(&self.palette);
+        out.bytes(&self.oam);
+        out.u8(self.oam_addr);
+        out.u8(self.data_buffer);
+        out.u8(self.io_latch);
+        out.u16(self.scanline);
+        out.u16(self.dot);
+        out.u64(self.frame);
+        out.u8(self.nametable_latch);
+        out.u8(self.attribute_latch);
+        out.u8(self.pattern_lo_latch);
+        out.u8(self.pattern_hi_latch);
+        out.u16(self.pattern_lo_shifter);
+        out.u16(self.pattern_hi_shifter);
+        out.u16(self.attribute_lo_shifter);
+        out.u16(self.attribute_hi_shifter);
+        out.bytes(&self.secondary_oam);
+        out.u8(self.secondary_count);
+        out.bool(self.secondary_has_sprite_0);
+        out.u8(self.sprite_count);
+        out.bytes(&self.sprite_pattern_lo);
+        out.bytes(&self.sprite_pattern_hi);
+        out.bytes(&self.sprite_attributes);
+        out.bytes(&self.sprite_x);
+        out.bool(self.sprite_0_on_scanline);
+        self.framebuffer.save_state(out);
+        out.bool(self.frame_complete);
+        out.bool(self.nmi_pending);
+        out.bool(self.nmi_suppressed);
+    }
+
+    fn load_state(&mut self, input: &mut StateReader) -> Result<(), String> {
+        self.registers.ppuctrl.register = input.u8()?;
+        self.registers.ppumask.register = input.u8()?;
+        self.registers.ppustatus.register = input.u8()?;
+        self.loopy.v = input.u16()?;
+        self.loopy.t = input.u16()?;
+        self.loopy.x = input.u8()?;
+        self.loopy.w = input.bool()?;
+        input.bytes(&mut self.vram)?;
+        input.bytes(&mut self.palette)?;
+        input.bytes(&mut self.oam)?;
+        self.oam_addr = input.u8()?;
+        self.data_buffer = input.u8()?;
+        self.io_latch = input.u8()?;
+        self.scanline = input.u16()?;
+        self.dot = input.u16()?;
+        self.frame = input.u64()?;
+        self.nametable_latch = input.u8()?;
+        self.attribute_latch = input.u8()?;
+        self.pattern_lo_latch = input.u8()?;
+        self.pattern_hi_latch = input.u8()?;
+        self.pattern_lo_shifter = input.u16()?;
+        self.pattern_hi_shifter = input.u16()?;
+        self.attribute_lo_shifter = input.u16()?;
+        self.attribute_hi_shifter = input.u16()?;
+        input.bytes(&mut self.secondary_oam)?;
+        self.secondary_count = input.u8()?;
+        self.secondary_has_sprite_0 = input.bool()?;
+        self.sprite_count = input.u8()?;
+        input.bytes(&mut self.sprite_pattern_lo)?;
+        input.bytes(&mut self.sprite_pattern_hi)?;
+        input.bytes(&mut self.sprite_attributes)?;
+        input.bytes(&mut self.sprite_x)?;
+        self.sprite_0_on_scanline = input.bool()?;
+        self.framebuffer.load_state(input)?;
+        self.frame_complete = input.bool()?;
+        self.nmi_pending = input.bool()?;
+        self.nmi_suppressed = input.bool()?;
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::ppu::colors::PALETTE;
+    use crate::cartridge::test_rom;
+    use crate::ppu::framebuffer::{HEIGHT, WIDTH};
+
+    const VBLANK_SCANLINE: u16 = 241;
+    const PRERENDER_SCANLINE: u16 = 261;
+
+    /// A cartridge without CHR ROM, so the pattern tables are RAM that the tests can fill.
+    fn chr_ram_cartridge() -> Cartridge {
+        Cartridge::from_ines(&test_rom::ines(0, &[0xEA; 0x4000], &[])).unwrap()
+    }
+
+    /// Tick the PPU until it reaches the given position.
+    fn run_until(ppu: &mut PPU, cartridge: &Cartridge, scanline: u16, dot: u16) {
+        while ppu.scanline() != scanline || ppu.dot() != dot {
+            ppu.tick(cartridge);
+        }
+    }
+
+    /// Tile 1 is solid (color 1), tile 0 is empty. The nametable alternates columns: tile 1, tile 0, tile 1...
+    fn striped_ppu() -> (PPU, Cartridge) {
+        let mut ppu = PPU::new();
+        let mut cartridge = chr_ram_cartridge();
+        for row in 0..8 {
+            ppu.ppu_write(0x0010 + row, 0xFF, &mut cartridge);
+        }
+        for i in 0..960 {
+            ppu.ppu_write(0x2000 + i, if i % 2 == 0 { 1 } else { 0 }, &mut cartridge);
+        }
+        ppu.ppu_write(0x3F00, 0x0F, &mut cartridge);
+        ppu.ppu_write(0x3F01, 0x30, &mut cartridge);
+        (ppu, cartridge)
+    }
+
+    #[test]
+    fn register_write_test() {
+        let mut ppu = PPU::new();
+        let mut cartridge = chr_ram_cartridge();
+
+        ppu.cpu_write(0x2000, 0b0000_0011, &mut cartridge);
+        assert_eq!(ppu.scroll_registers().t, 0x0C00);
+
+        ppu.cpu_write(0x2006, 0x21, &mut cartridge);
+        ppu.cpu_write(0x2006, 0x08, &mut cartridge);
+        assert_eq!(ppu.scroll_registers().v, 0x2108);
+
+        // Reading status resets the write toggle.
+        ppu.cpu_write(0x2005, 0xFF, &mut cartridge);
+        assert!(ppu.scroll_registers().w);
+        ppu.cpu_read(0x2002, &cartridge);
+        assert!(!ppu.scroll_registers().w);
+
+        // Registers are mirrored every 8 bytes.
+        ppu.cpu_write(0x3FFD, 0x08, &mut cartridge);
+        assert_eq!(ppu.scroll_registers().t & 0x1F, 1);
+    }
+
+    #[test]
+    fn ppudata_test() {
+        let mut ppu = PPU::new();
+        let mut cartridge = chr_ram_cartridge();
+
+        ppu.cpu_write(0x2006, 0x20, &mut cartridge);
+        ppu.cpu_write(0x2006, 0x00, &mut cartridge);
+        ppu.cpu_write(0x2007, 0xAB, &mut cartridge);
+        ppu.cpu_write(0x2007, 0xCD, &mut cartridge);
+
+        ppu.cpu_write(0x2006, 0x20, &mut cartridge);
+        ppu.cpu_write(0x2006, 0x00, &mut cartridge);
+        ppu.cpu_read(0x2007, &cartridge); // Dummy read, fills the buffer.
+        assert_eq!(ppu.cpu_read(0x2007, &cartridge), 0xAB);
+        assert_eq!(ppu.cpu_read(0x2007, &cartridge), 0xCD);
+
+        // Increment by 32.
+        ppu.cpu_write(0x2000, 0b0000_0100, &mut cartridge);
+        ppu.cpu_write(0x2006, 0x20, &mut cartridge);
+        ppu.cpu_write(0x2006, 0x00, &mut cartridge);
+        ppu.cpu_write(0x2007, 0x11, &mut cartridge);
+        assert_eq!(ppu.scroll_registers().v, 0x2020);
+
+        // Palette reads are not buffered, and $3F10 mirrors $3F00.
+        ppu.cpu_write(0x2000, 0, &mut cartridge);
+        ppu.cpu_write(0x2006, 0x3F, &mut cartridge);
+        ppu.cpu_write(0x2006, 0x10, &mut cartridge);
+        ppu.cpu_write(0x2007, 0x2A, &mut cartridge);
+        ppu.cpu_write(0x2006, 0x3F, &mut cartridge);
+        ppu.cpu_write(0x2006, 0x00, &mut cartridge);
+        assert_eq!(ppu.cpu_read(0x2007, &cartridge), 0x2A);
+    }
+
+    #[test]
+    fn nametable_mirroring_test() {
+        let mut ppu = PPU::new();
+        let mut cartridge = chr_ram_cartridge();
+
+        ppu.mirroring = Mirroring::Vertical;
+        ppu.ppu_write(0x2005, 0x12, &mut cartridge);
+        assert_eq!(ppu.ppu_read(0x2805, &cartridge), 0x12);
+        assert_eq!(ppu.ppu_read(0x2405, &cartridge), 0x00);
+
+        ppu.mirroring = Mirroring::Horizontal;
+        ppu.ppu_write(0x2005, 0x34, &mut cartridge);
+        assert_eq!(ppu.ppu_read(0x2405, &cartridge), 0x34);
+        assert_eq!(ppu.ppu_read(0x2805, &cartridge), 0x00);
+
+        ppu.mirroring = Mirroring::SingleScreenUpper;
+        ppu.ppu_write(0x2C05, 0x56, &mut cartridge);
+        assert_eq!(ppu.ppu_read(0x2005, &cartridge), 0x56);
+        assert_eq!(ppu.ppu_read(0x2405, &cartridge), 0x56);
+        ppu.mirroring = Mirroring::SingleScreenLower;
+        assert_eq!(ppu.ppu_read(0x2805, &cartridge), 0x34);
+    }
+
+    #[test]
+    fn vblank_test() {
+        let mut ppu = PPU::new();
+        let cartridge = chr_ram_cartridge();
+
+        run_until(&mut ppu, &cartridge, VBLANK_SCANLINE, 1);
+        assert_eq!(ppu.registers.ppustatus.vertical_blank_started(), 0);
+        ppu.tick(&cartridge);
+        assert_ne!(ppu.registers.ppustatus.vertical_blank_started(), 0);
+        assert!(ppu.take_frame_complete());
+        assert!(!ppu.take_frame_complete());
+
+        // Reading status clears vblank.
+        assert_ne!(ppu.cpu_read(0x2002, &cartridge) & 0x80, 0);
+        assert_eq!(ppu.cpu_read(0x2002, &cartridge) & 0x80, 0);
+    }
+
+    #[test]
+    fn scroll_copy_during_rendering_test() {
+        let (mut ppu, mut cartridge) = striped_ppu();
+        ppu.cpu_write(0x2001, 0b0000_1010, &mut cartridge);
+
+        // Coarse X = 3, fine X = 5.
+        ppu.cpu_write(0x2005, 3 * 8 + 5, &mut cartridge);
+        // Coarse Y = 2, fine Y = 1.
+        ppu.cpu_write(0x2005, 2 * 8 + 1, &mut cartridge);
+        ppu.cpu_write(0x2000, 0b01, &mut cartridge);
+
+        // t is copied to v during the pre-render scanline.
+        run_until(&mut ppu, &cartridge, PRERENDER_SCANLINE, 305);
+        let loopy = ppu.scroll_registers();
+        assert_eq!(loopy.fine_y(), 1);
+        assert_eq!(loopy.coarse_y(), 2);
+        assert_eq!(loopy.x, 5);
+
+        // At the end of the pre-render scanline, coarse x was incremented twice (two tiles prefetched for scanline 0).
+        run_until(&mut ppu, &cartridge, 0, 0);
+        let loopy = ppu.scroll_registers();
+        assert_eq!(loopy.coarse_x(), 5);
+        assert_eq!(loopy.v & 0x0C00, 0x0400);
+
+        // Y is incremented at dot 256 of each scanline.
+        run_until(&mut ppu, &cartridge, 0, 257);
+        assert_eq!(ppu.scroll_registers().fine_y(), 2);
+    }
+
+    #[test]
+    fn odd_frame_skip_test() {
+        let mut ppu = PPU::new();
+        let mut cartridge = chr_ram_cartridge();
+        ppu.cpu_write(0x2001, 0b0000_1000, &mut cartridge);
+
+        let mut dots_per_frame = vec![];
+        for _ in 0..4 {
+            let mut dots = 0;
+            let frame = ppu.frame();
+            while ppu.frame() == frame {
+                ppu.tick(&cartridge);
+                dots += 1;
+            }
+            dots_per_frame.push(dots);
+        }
+        assert_eq!(dots_per_frame, vec![89342, 89341, 89342, 89341]);
+
+        // Without rendering, all frames are the same length.
+        ppu.cpu_write(0x2001, 0, &mut cartridge);
+        let frame = ppu.frame();
+        let mut dots = 0;
+        while ppu.frame() < frame + 2 {
+            ppu.tick(&cartridge);
+            dots += 1;
+        }
+        assert_eq!(dots, 89342 * 2);
+    }
+
+    #[test]
+    fn ppudata_during_rendering_test() {
+        let (mut ppu, mut cartridge) = striped_ppu();
+        ppu.cpu_write(0x2001, 0b0000_1010, &mut cartridge);
+        run_until(&mut ppu, &cartridge, 50, 100);
+
+        // The scroll moves to the next tile and the next line, and the write goes to where v was.
+        let before = ppu.scroll_registers();
+        let mut expected = before;
+        expected.increment_coarse_x();
+        expected.increment_y();
+        ppu.cpu_write(0x2007, 0x55, &mut cartridge);
+        assert_eq!(ppu.scroll_registers(), expected);
+        assert_eq!(ppu.ppu_read(before.v, &cartridge), 0x55);
+
+        // In VBlank, it's the usual increment again.
+        run_until(&mut ppu, &cartridge, VBLANK_SCANLINE, 10);
+        let v = ppu.scroll_registers().v;
+        ppu.cpu_write(0x2007, 0x55, &mut cartridge);
+        assert_eq!(ppu.scroll_registers().v, v + 1);
+    }
+
+    #[test]
+    fn hidden_background_shift_test() {
+        // Sprites on and the background hidden, then the background shown in the middle of the scanline: the
+        // shifters kept moving, so the stripes are right from the first pixel.
+        let (mut ppu, mut cartridge) = striped_ppu();
+        ppu.cpu_write(0x2001, 0b0001_0110, &mut cartridge);
+        run_until(&mut ppu, &cartridge, 10, 100);
+        ppu.cpu_write(0x2001, 0b0001_1110, &mut cartridge);
+        run_until(&mut ppu, &cartridge, 11, 0);
+
+        let frame = ppu.framebuffer();
+        assert_eq!(frame.get(50, 10), 0x0F);
+        for x in 100..256 {
+            let expected = if x / 8 % 2 == 0 { 0x30 } else { 0x0F };
+            assert_eq!(frame.get(x, 10), expected, "x = {}", x);
+        }
+    }
+
+    #[test]
+    fn mid_frame_scroll_test() {
+        let (mut ppu, mut cartridge) = striped_ppu();
+
+        ppu.cpu_write(0x2005, 0, &mut cartridge);
+        ppu.cpu_write(0x2005, 0, &mut cartridge);
+        ppu.cpu_write(0x2001, 0b0000_1010, &mut cartridge);
+
+        // Start from the pre-render scanline, so the first tiles of the frame are prefetched.
+        run_until(&mut ppu, &cartridge, PRERENDER_SCANLINE, 0);
+
+        // Render the top of the frame without scroll, then scroll by one tile (8 pixels) in the middle of scanline 100.
+        run_until(&mut ppu, &cartridge, 100, 100);
+        ppu.cpu_read(0x2002, &cartridge);
+        ppu.cpu_write(0x2005, 8, &mut cartridge);
+        ppu.cpu_write(0x2005, 0, &mut cartridge);
+        run_until(&mut ppu, &cartridge, VBLANK_SCANLINE, 0);
+
+        let frame = ppu.framebuffer();
+        for y in 0..=100 {
+            assert_eq!(frame.get(0, y), 0x30, "scanline {} should not be scrolled", y);
+            assert_eq!(frame.get(8, y), 0x0F, "scanline {} should not be scrolled", y);
+        }
+        for y in 101..240 {
+            assert_eq!(frame.get(0, y), 0x0F, "scanline {} should be scrolled", y);
+            assert_eq!(frame.get(8, y), 0x30, "scanline {} should be scrolled", y);
+        }
+    }
+
+    /// `striped_ppu`, with tile 2 for sprites: an arrow, its first row the leftmost pixel only (color 1), its last row
+    /// all 8 pixels (color 2). Sprite palette 0 is $16 $27 $18.
+    fn sprite_ppu() -> (PPU, Cartridge) {
+        let (mut ppu, mut cartridge) = striped_ppu();
+        for row in 0..7 {
+            ppu.ppu_write(0x0020 + row, 0x80, &mut cartridge);
+        }
+        ppu.ppu_write(0x0027 + 8, 0xFF, &mut cartridge);
+        for (i, color) in [0x16, 0x27, 0x18].into_iter().enumerate() {
+            ppu.ppu_write(0x3F11 + i as u16, color, &mut cartridge);
+        }
+        for i in 0..256 {
+            ppu.oam[i] = 0xFF;
+        }
+        (ppu, cartridge)
+    }
+
+    fn set_sprite(ppu: &mut PPU, index: usize, y: u8, tile: u8, attributes: u8, x: u8) {
+        ppu.oam[index * 4..index * 4 + 4].copy_from_slice(&[y, tile, attributes, x]);
+    }
+
+    /// Render a whole frame, from the pre-render scanline.
+    fn render_frame(ppu: &mut PPU, cartridge: &Cartridge) {
+        run_until(ppu, cartridge, PRERENDER_SCANLINE, 0);
+        run_until(ppu, cartridge, VBLANK_SCANLINE, 0);
+    }
+
+    #[test]
+    fn sprite_test() {
+        let (mut ppu, mut cartridge) = sprite_ppu();
+        // Sprites only, on the empty column at x = 8-15 (over the stripe, x = 16-23, it's the same).
+        ppu.cpu_write(0x2001, 0b0001_0110, &mut cartridge);
+        set_sprite(&mut ppu, 0, 19, 2, 0, 8);
+        // Flipped both ways.
+        set_sprite(&mut ppu, 1, 39, 2, 0xC0, 24);
+        render_frame(&mut ppu, &cartridge);
+
+        let frame = ppu.framebuffer();
+        // Drawn on scanlines 20-27, one below its Y.
+        assert_eq!(frame.get(8, 19), 0x0F);
+        assert_eq!((frame.get(8, 20), frame.get(9, 20)), (0x16, 0x0F));
+        assert_eq!((frame.get(8, 27), frame.get(15, 27), frame.get(16, 27)), (0x27, 0x27, 0x0F));
+        assert_eq!(frame.get(8, 28), 0x0F);
+        // The full row on top, and the pixel on the right.
+        assert_eq!((frame.get(24, 40), frame.get(31, 40)), (0x27, 0x27));
+        assert_eq!((frame.get(30, 47), frame.get(31, 47)), (0x0F, 0x16));
+        // No sprite is drawn on scanline 0.
+        set_sprite(&mut ppu, 0, 0xFF, 2, 0, 8);
+        render_frame(&mut ppu, &cartridge);
+        assert_eq!(ppu.framebuffer().get(8, 0), 0x0F);
+    }
+
+    #[test]
+    fn sprite_priority_test() {
+        let (mut ppu, mut cartridge) = sprite_ppu();
+        ppu.cpu_write(0x2001, 0b0001_1110, &mut cartridge);
+        // The last row of sprite 0 over x = 4-11: half on the stripe at 0-7, half on the empty column. Sprite 1 is
+        // behind the background at x = 20-27, and sprite 2 under it (OAM order wins, not priority).
+        set_sprite(&mut ppu, 0, 19, 2, 0, 4);
+        set_sprite(&mut ppu, 1, 19, 2, 0x21, 20);
+        set_sprite(&mut ppu, 2, 19, 2, 0x00, 20);
+        ppu.ppu_write(0x3F16, 0x2A, &mut cartridge);
+        render_frame(&mut ppu, &cartridge);
+
+        let frame = ppu.framebuffer();
+        assert_eq!((frame.get(4, 27), frame.get(8, 27)), (0x27, 0x27));
+        // Behind: the stripe at 20-23, and sprite 1 at 24-27, where the background is empty.
+        assert_eq!((frame.get(20, 27), frame.get(24, 27)), (0x30, 0x2A));
+    }
+
+    #[test]
+    fn sprite_0_hit_test() {
+        let (mut ppu, mut cartridge) = sprite_ppu();
+        ppu.cpu_write(0x2001, 0b0001_1110, &mut cartridge);
+
+        // Over the empty column: no hit.
+        set_sprite(&mut ppu, 0, 19, 2, 0, 8);
+        render_frame(&mut ppu, &cartridge);
+        assert_eq!(ppu.registers.ppustatus.sprite_0_hit(), 0);
+
+        // Over a stripe: the hit is at the pixel, and stays until the pre-render scanline.
+        set_sprite(&mut ppu, 0, 19, 2, 0, 16);
+        run_until(&mut ppu, &cartridge, PRERENDER_SCANLINE, 0);
+        run_until(&mut ppu, &cartridge, 20, 17);
+        assert_eq!(ppu.registers.ppustatus.sprite_0_hit(), 0);
+        ppu.tick(&cartridge);
+        assert_ne!(ppu.registers.ppustatus.sprite_0_hit(), 0);
+        run_until(&mut ppu, &cartridge, PRERENDER_SCANLINE, 2);
+        assert_eq!(ppu.registers.ppustatus.sprite_0_hit(), 0);
+
+        // Another sprite doesn't hit.
+        set_sprite(&mut ppu, 0, 0xFF, 2, 0, 8);
+        set_sprite(&mut ppu, 1, 19, 2, 0, 16);
+        render_frame(&mut ppu, &cartridge);
+        assert_eq!(ppu.registers.ppustatus.sprite_0_hit(), 0);
+
+        // Not in the leftmost 8 pixels when the sprites are clipped there, and never at x = 255.
+        set_sprite(&mut ppu, 0, 19, 2, 0, 0);
+        set_sprite(&mut ppu, 1, 0xFF, 2, 0, 0);
+        ppu.cpu_write(0x2001, 0b0001_1010, &mut cartridge);
+        render_frame(&mut ppu, &cartridge);
+        assert_eq!(ppu.registers.ppustatus.sprite_0_hit(), 0);
+        ppu.cpu_write(0x2001, 0b0001_1110, &mut cartridge);
+        render_frame(&mut ppu, &cartridge);
+        assert_ne!(ppu.registers.ppustatus.sprite_0_hit(), 0);
+        for i in 0..960 {
+            ppu.ppu_write(0x2000 + i, 1, &mut cartridge);
+        }
+        set_sprite(&mut ppu, 0, 19, 2, 0x40, 255);
+        render_frame(&mut ppu, &cartridge);
+        assert_eq!(ppu.registers.ppustatus.sprite_0_hit(), 0);
+    }
+
+    #[test]
+    fn sprite_8x16_and_overflow_test() {
+        let (mut ppu, mut cartridge) = sprite_ppu();
+        // 8x16: tile 2 is the top, tile 3 the bottom, in the table of bit 0 ($0000 here).
+        ppu.ppu_write(0x0030, 0xFF, &mut cartridge);
+        ppu.cpu_write(0x2000, 0b0010_0000, &mut cartridge);
+        ppu.cpu_write(0x2001, 0b0001_0110, &mut cartridge);
+        set_sprite(&mut ppu, 0, 19, 2, 0, 8);
+        // Flipped vertically, the bottom tile is on top, upside down: its full row is at the bottom of it.
+        set_sprite(&mut ppu, 1, 59, 2, 0x80, 8);
+        render_frame(&mut ppu, &cartridge);
+        let frame = ppu.framebuffer();
+        assert_eq!((frame.get(15, 27), frame.get(15, 28), frame.get(15, 29)), (0x27, 0x16, 0x0F));
+        assert_eq!((frame.get(15, 60), frame.get(8, 67), frame.get(15, 68)), (0x0F, 0x16, 0x27));
+        assert_eq!(ppu.registers.ppustatus.sprite_overflow(), 0);
+
+        // 9 sprites on a scanline: the 9th isn't drawn, and the flag is set.
+        for i in 0..9 {
+            set_sprite(&mut ppu, i, 99, 2, 0, 8 + 16 * i as u8);
+        }
+        render_frame(&mut ppu, &cartridge);
+        assert_ne!(ppu.registers.ppustatus.sprite_overflow(), 0);
+        assert_eq!((ppu.framebuffer().get(120, 100), ppu.framebuffer().get(136, 100)), (0x16, 0x0F));
+    }
+
+    #[test]
+    fn a12_rise_test() {
+        let (mut ppu, mut cartridge) = sprite_ppu();
+        ppu.cpu_write(0x2001, 0b0001_1000, &mut cartridge);
+        let rises = |ppu: &mut PPU, cartridge: &Cartridge| {
+            run_until(ppu, cartridge, 10, 0);
+            let mut dots = Vec::new();
+            while ppu.scanline() == 10 {
+                if ppu.a12_rose() {
+                    dots.push(ppu.dot());
+                }
+                ppu.tick(cartridge);
+            }
+            dots
         };
-        ppu
+
+        // Background at $0000 and sprites at $1000, like most MMC3 games: the first sprite fetch.
+        ppu.cpu_write(0x2000, 0b0000_1000, &mut cartridge);
+        assert_eq!(rises(&mut ppu, &cartridge), [260]);
+        // Background at $1000 and sprites at $0000: the fetch of the next scanline.
+        ppu.cpu_write(0x2000, 0b0001_0000, &mut cartridge);
+        assert_eq!(rises(&mut ppu, &cartridge), [324]);
+        // One table for both: A12 doesn't change.
+        ppu.cpu_write(0x2000, 0b0000_0000, &mut cartridge);
+        assert_eq!(rises(&mut ppu, &cartridge), Vec::<u16>::new());
+        ppu.cpu_write(0x2000, 0b0001_1000, &mut cartridge);
+        assert_eq!(rises(&mut ppu, &cartridge), Vec::<u16>::new());
+
+        // 8x16: two sprites of the table at $0000 on scanline 11, the 3rd slot is the first at $1000.
+        ppu.cpu_write(0x2000, 0b0010_0000, &mut cartridge);
+        set_sprite(&mut ppu, 0, 5, 2, 0, 8);
+        set_sprite(&mut ppu, 1, 5, 2, 0, 24);
+        assert_eq!(rises(&mut ppu, &cartridge), [276]);
+    }
+
+    /// A frame of a single color: rendering is off, so it's all the backdrop color.
+    fn solid_frame(ppu: &mut PPU, cartridge: &mut Cartridge, color: u8, ppumask: u8) -> Vec<u8> {
+        ppu.ppu_write(0x3F00, color, cartridge);
+        ppu.cpu_write(0x2001, ppumask, cartridge);
+        run_until(ppu, cartridge, VBLANK_SCANLINE, 0);
+        let mut rgb = vec![0; WIDTH * HEIGHT * 3];
+        ppu.framebuffer().write_rgb24(&mut rgb);
+        run_until(ppu, cartridge, 0, 0);
+        rgb
+    }
+
+    #[test]
+    fn emphasis_test() {
+        let attenuate = |value: u8| (value as u32 * 816 / 1000) as u8;
+        let (r, g, b) = PALETTE[0x20];
+
+        let mut ppu = PPU::new();
+        let mut cartridge = chr_ram_cartridge();
+        assert_eq!(solid_frame(&mut ppu, &mut cartridge, 0x20, 0)[..3], [r, g, b]);
+        // The emphasized color stays, the others are darker.
+        let cases = [
+            (0b0010_0000, [r, attenuate(g), attenuate(b)]),
+            (0b0100_0000, [attenuate(r), g, attenuate(b)]),
+            (0b1000_0000, [attenuate(r), attenuate(g), b]),
+            (0b0110_0000, [r, g, attenuate(b)]),
+            (0b1110_0000, [attenuate(r), attenuate(g), attenuate(b)]),
+        ];
+        for (ppumask, expected) in cases {
+            let rgb = solid_frame(&mut ppu, &mut cartridge, 0x20, ppumask);
+            assert_eq!(rgb[..3], expected, "PPUMASK {:#010b}", ppumask);
+            // The whole frame.
+            assert!(rgb.chunks_exact(3).all(|pixel| pixel == expected), "PPUMASK {:#010b}", ppumask);
+        }
+
+        // PAL swaps the red and green bits.
+        let mut ppu = PPU::new();
+        let mut cartridge = chr_ram_cartridge();
+        ppu.set_region(Region::Pal);
+        assert_eq!(solid_frame(&mut ppu, &mut cartridge, 0x20, 0b0010_0000)[..3], [attenuate(r), g, attenuate(b)]);
+        assert_eq!(solid_frame(&mut ppu, &mut cartridge, 0x20, 0b0100_0000)[..3], [r, attenuate(g), attenuate(b)]);
+    }
+
+    #[test]
+    fn grayscale_test() {
+        let mut ppu = PPU::new();
+        let mut cartridge = chr_ram_cartridge();
+        for color in 0x01..=0x0C {
+            solid_frame(&mut ppu, &mut cartridge, color, 0b0000_0001);
+            assert_eq!(ppu.framebuffer().get(100, 100), 0x00, "color {:#04X}", color);
+        }
+        // The brightness stays.
+        solid_frame(&mut ppu, &mut cartridge, 0x16, 0b0000_0001);
+        assert_eq!(ppu.framebuffer().get(100, 100), 0x10);
+        solid_frame(&mut ppu, &mut cartridge, 0x16, 0);
+        assert_eq!(ppu.framebuffer().get(100, 100), 0x16);
     }
-}
\ No newline at end of file
+
+    #[test]
+    fn master_palette_test() {
+        // Color $00 is red, the rest black.
+        let mut pal = vec![0; 192];
+        pal[0] = 0xFF;
+        let mut ppu = PPU::new();
+        let mut cartridge = chr_ram_cartridge();
+        ppu.set_master_palette(MasterPalette::from_pal(&pal).unwrap());
+        let rgb = solid_frame(&mut ppu, &mut cartridge, 0x00, 0);
+        assert!(rgb.chunks_exact(3).all(|pixel| pixel == [0xFF, 0, 0]));
+        // Red emphasized: it stays.
+        assert_eq!(solid_frame(&mut ppu, &mut cartridge, 0x00, 0b0010_0000)[..3], [0xFF, 0, 0]);
+        assert_eq!(solid_frame(&mut ppu, &mut cartridge, 0x00, 0b0100_0000)[..3], [0xD0, 0, 0]);
+    }
+}
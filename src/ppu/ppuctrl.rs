@@ -1,4 +1,5 @@
 
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
 pub struct PPUCtrl {
     pub register: u8
 }
@@ -8,31 +9,51 @@ impl PPUCtrl {
         Self { register: 0 }
     }
     
-    pub fn nametable(&mut self) -> u8 {
+    pub fn nametable(&self) -> u8 {
         (self.register & 1) | (self.register & (1 << 1))
     }
 
-    pub fn vram_addr_inc(&mut self) -> u8 {
+    pub fn vram_addr_inc(&self) -> u8 {
         self.register & (1 << 2)
     }
 
-    pub fn sprite_pattern_address(&mut self) -> u8 {
+    pub fn sprite_pattern_address(&self) -> u8 {
         self.register & (1 << 3)
     }
 
-    pub fn bg_pattern_address(&mut self) -> u8 {
+    pub fn bg_pattern_address(&self) -> u8 {
         self.register & (1 << 4)
     }
 
-    pub fn sprite_size(&mut self) -> u8 {
+    pub fn sprite_size(&self) -> u8 {
         self.register & (1 << 5)
     }
 
-    pub fn ppu_master_slave(&mut self) -> u8 {
+    pub fn ppu_master_slave(&self) -> u8 {
         self.register & (1 << 6)
     }
 
-    pub fn generate_NMI(&mut self) -> u8 {
+    pub fn generate_nmi(&self) -> u8 {
         self.register & (1 << 7)
     }
+
+    /// How much to add to the VRAM address after every PPUDATA access: 1 (going across) or 32 (going down).
+    pub fn vram_addr_increment_amount(&self) -> u16 {
+        if self.vram_addr_inc() == 0 { 1 } else { 32 }
+    }
+
+    /// Base address of the background pattern table: $0000 or $1000.
+    pub fn bg_pattern_table(&self) -> u16 {
+        if self.bg_pattern_address() == 0 { 0x0000 } else { 0x1000 }
+    }
+
+    /// Base address of the pattern table of 8x8 sprites: $0000 or $1000. 8x16 sprites say it in their tile number.
+    pub fn sprite_pattern_table(&self) -> u16 {
+        if self.sprite_pattern_address() == 0 { 0x0000 } else { 0x1000 }
+    }
+
+    /// 8 or 16 rows.
+    pub fn sprite_height(&self) -> u16 {
+        if self.sprite_size() == 0 { 8 } else { 16 }
+    }
 }
\ No newline at end of file
@@ -1,4 +1,5 @@
 
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
 pub struct PPUMask {
     pub register: u8
 }
@@ -8,35 +9,35 @@ impl PPUMask {
         Self { register: 0 }
     }
     
-    pub fn greyscale(&mut self) -> u8 {
+    pub fn greyscale(&self) -> u8 {
         self.register & 1
     }
 
-    pub fn show_bg_leftmost_8(&mut self) -> u8 {
+    pub fn show_bg_leftmost_8(&self) -> u8 {
         self.register & (1 << 1)
     }
 
-    pub fn show_sprites_leftmost_8(&mut self) -> u8 {
+    pub fn show_sprites_leftmost_8(&self) -> u8 {
         self.register & (1 << 2)
     }
 
-    pub fn show_bg(&mut self) -> u8 {
+    pub fn show_bg(&self) -> u8 {
         self.register & (1 << 3)
     }
 
-    pub fn show_sprites(&mut self) -> u8 {
+    pub fn show_sprites(&self) -> u8 {
         self.register & (1 << 4)
     }
 
-    pub fn emphasize_red(&mut self) -> u8 {
+    pub fn emphasize_red(&self) -> u8 {
         self.register & (1 << 5)
     }
 
-    pub fn emphasize_green(&mut self) -> u8 {
+    pub fn emphasize_green(&self) -> u8 {
         self.register & (1 << 6)
     }
 
-    pub fn emphasize_blue(&mut self) -> u8 {
+    pub fn emphasize_blue(&self) -> u8 {
         self.register & (1 << 7)
     }
 }
\ No newline at end of file
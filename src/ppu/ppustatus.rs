@@ -1,4 +1,5 @@
 
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
 pub struct PPUStatus {
     pub register: u8
 }
@@ -8,15 +9,35 @@ impl PPUStatus {
         Self { register: 0 }
     }
     
-    pub fn sprite_overflow(&mut self) -> u8 {
+    pub fn sprite_overflow(&self) -> u8 {
         self.register & (1 << 5)
     }
 
-    pub fn sprite_0_hit(&mut self) -> u8 {
+    pub fn sprite_0_hit(&self) -> u8 {
         self.register & (1 << 6)
     }
 
-    pub fn vertical_blank_started(&mut self) -> u8 {
+    pub fn vertical_blank_started(&self) -> u8 {
         self.register & (1 << 7)
     }
+
+    pub fn set_vertical_blank_started(&mut self, value: bool) {
+        self.set(7, value);
+    }
+
+    pub fn set_sprite_0_hit(&mut self, value: bool) {
+        self.set(6, value);
+    }
+
+    pub fn set_sprite_overflow(&mut self, value: bool) {
+        self.set(5, value);
+    }
+
+    fn set(&mut self, bit: u8, value: bool) {
+        if value {
+            self.register |= 1 << bit;
+        } else {
+            self.register &= !(1 << bit);
+        }
+    }
 }
\ No newline at end of file
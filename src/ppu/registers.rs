@@ -3,6 +3,7 @@ use super::ppumask::PPUMask;
 use super::ppustatus::PPUStatus;
 
 
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
 pub struct Registers {
     pub ppuctrl: PPUCtrl,       /* 0x2000 */
     pub ppumask: PPUMask,       /* 0x2001 */
@@ -11,11 +12,10 @@ pub struct Registers {
 
 impl Registers {
     pub fn new() -> Self {
-        let r = Registers {
+        Registers {
             ppuctrl: PPUCtrl::new(),
             ppumask: PPUMask::new(),
             ppustatus: PPUStatus::new(),
-        };
-        r
+        }
     }
 }
\ No newline at end of file
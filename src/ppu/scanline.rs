@@ -0,0 +1,133 @@
+// The state of the PPU at the start of a visible scanline, for debugging raster effects (split screens, status bars,
+// parallax). Games change the scroll and PPUCTRL/PPUMASK in the middle of the frame, so the whole frame doesn't tell
+// what a scanline was drawn with. `PPU::set_scanline_callback` gets this at dot 0 of every visible scanline:
+//
+// | Field | Description |
+// |---|---|
+// | `scroll` | v, t, x and w (see loopy.rs). v is where the scanline starts fetching tiles |
+// | `scroll_x()`, `scroll_y()` | The scroll the scanline is drawn with, from v |
+// | `ppuctrl`, `ppumask` | The registers, as last written |
+// | `sprites()` | The sprites on the scanline, like the sprite evaluation selects them: the first 8 in OAM order |
+// | `sprite_overflow()` | There were more than 8 |
+//
+// The PPU draws the scanline with the same selection, see ppu.rs.
+
+use std::fmt;
+
+use super::loopy::LoopyRegisters;
+
+/// The PPU draws at most this many sprites on a scanline. The rest are dropped (and set the sprite overflow flag).
+pub const MAX_SPRITES_PER_SCANLINE: usize = 8;
+
+#[derive(Clone, Copy, PartialEq, Debug)]
+pub struct ScanlineState {
+    pub scanline: u16,
+    pub scroll: LoopyRegisters,
+    pub ppuctrl: u8,
+    pub ppumask: u8,
+    sprites: [u8; MAX_SPRITES_PER_SCANLINE],
+    sprite_count: u8,
+    overflow: bool,
+}
+
+impl ScanlineState {
+    /// Select the sprites of `scanline` from `oam`. Sprites are 8x16 when bit 5 of `ppuctrl` is set.
+    pub fn new(scanline: u16, scroll: LoopyRegisters, ppuctrl: u8, ppumask: u8, oam: &[u8; 256]) -> Self {
+        let height = if ppuctrl & 0b0010_0000 != 0 { 16 } else { 8 };
+        let mut state = ScanlineState { scanline, scroll, ppuctrl, ppumask, sprites: [0; MAX_SPRITES_PER_SCANLINE], sprite_count: 0, overflow: false };
+        for (index, sprite) in oam.chunks_exact(4).enumerate() {
+            // The Y in OAM is the scanline above the sprite.
+            let row = scanline.wrapping_sub(sprite[0] as u16 + 1);
+            if row >= height {
+                continue;
+            }
+            if (state.sprite_count as usize) < MAX_SPRITES_PER_SCANLINE {
+                state.sprites[state.sprite_count as usize] = index as u8;
+                state.sprite_count += 1;
+            } else {
+                state.overflow = true;
+                break;
+            }
+        }
+        state
+    }
+
+    /// The OAM indexes (0-63) of the sprites on the scanline, at most 8.
+    pub fn sprites(&self) -> &[u8] {
+        &self.sprites[..self.sprite_count as usize]
+    }
+
+    /// More than 8 sprites are on the scanline: the PPU sets the sprite overflow flag.
+    pub fn sprite_overflow(&self) -> bool {
+        self.overflow
+    }
+
+    /// Horizontal scroll of the scanline, 0-511: the left of the first nametable is 0, of the one on its right 256.
+    pub fn scroll_x(&self) -> u16 {
+        let nametable_x = (self.scroll.v >> 10) & 1;
+        let fetched = nametable_x * 256 + self.scroll.coarse_x() * 8 + self.scroll.x as u16;
+        // With rendering on, the end of the previous scanline already fetched the first 2 tiles, and v moved past them.
+        if self.ppumask & 0b0001_1000 != 0 {
+            fetched.wrapping_sub(16) % 512
+        } else {
+            fetched
+        }
+    }
+
+    /// Vertical scroll of the scanline, 0-479: the nametable row v points to. It's the Y of the screen, plus the
+    /// scanline (v moves down with every scanline).
+    pub fn scroll_y(&self) -> u16 {
+        let nametable_y = (self.scroll.v >> 11) & 1;
+        nametable_y * 240 + self.scroll.coarse_y() * 8 + self.scroll.fine_y()
+    }
+}
+
+/// `Scanline 100: scroll 8,100 (v=$0C8D t=$0001 x=0) PPUCTRL=$80 PPUMASK=$1E sprites: 0 3`
+impl fmt::Display for ScanlineState {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "Scanline {}: scroll {},{} (v=${:04X} t=${:04X} x={}) PPUCTRL=${:02X} PPUMASK=${:02X} sprites:",
+            self.scanline, self.scroll_x(), self.scroll_y(), self.scroll.v, self.scroll.t, self.scroll.x, self.ppuctrl, self.ppumask)?;
+        if self.sprites().is_empty() {
+            return write!(f, " none");
+        }
+        for sprite in self.sprites() {
+            write!(f, " {}", sprite)?;
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn sprite_selection_test() {
+        let mut oam = [0xFF; 256];
+        // Sprite 1 is at the top of scanlines 10-17, sprite 5 on 17-24.
+        oam[4] = 9;
+        oam[20] = 16;
+        let state = |scanline, ppuctrl| ScanlineState::new(scanline, LoopyRegisters::default(), ppuctrl, 0, &oam);
+        assert!(state(9, 0).sprites().is_empty());
+        assert_eq!(state(10, 0).sprites(), &[1]);
+        assert_eq!(state(17, 0).sprites(), &[1, 5]);
+        assert_eq!(state(18, 0).sprites(), &[5]);
+        // 8x16 sprites.
+        assert_eq!(state(25, 0b0010_0000).sprites(), &[1, 5]);
+        assert!(!state(17, 0).sprite_overflow());
+
+        // Only the first 8.
+        let oam = [20; 256];
+        let state = ScanlineState::new(25, LoopyRegisters::default(), 0, 0, &oam);
+        assert_eq!(state.sprites(), &[0, 1, 2, 3, 4, 5, 6, 7]);
+        assert!(state.sprite_overflow());
+        assert_eq!(state.to_string(), "Scanline 25: scroll 0,0 (v=$0000 t=$0000 x=0) PPUCTRL=$00 PPUMASK=$00 sprites: 0 1 2 3 4 5 6 7");
+
+        // v is 2 tiles ahead when rendering.
+        let scroll = LoopyRegisters { v: 0x0002, ..LoopyRegisters::default() };
+        assert_eq!(ScanlineState::new(0, scroll, 0, 0x08, &oam).scroll_x(), 0);
+        assert_eq!(ScanlineState::new(0, scroll, 0, 0x00, &oam).scroll_x(), 16);
+        let scroll = LoopyRegisters { v: 0x0001, ..LoopyRegisters::default() };
+        assert_eq!(ScanlineState::new(0, scroll, 0, 0x08, &oam).scroll_x(), 504);
+    }
+}
@@ -0,0 +1,135 @@
+// Instruction profile: for every opcode, how many times it ran, the cycles it took, and how many of them were
+// penalties (the oops cycles of a page crossed by an indexed read, or of a branch taken, see `CPU::penalty_cycles`).
+// `Emulator::set_profile` fills it as the emulator runs, and `--profile` prints it after a headless run:
+//
+// Opcode  Instruction        Executions      Cycles   Penalties
+// $B1     LDA INDIRECTY            1000        5500         500
+// $D0     BNE RELATIVE             1000        2999         999
+//
+// Penalty cycles are the ones that moving data (or code) on the real console can save: a table that starts at $80F0
+// costs an extra cycle for every read past $8100, and the same table at $8000 doesn't.
+
+use std::fmt;
+
+use crate::cpu::decoder::decode_opcode;
+
+#[derive(Clone, Copy, Default, PartialEq, Eq, Debug)]
+pub struct OpcodeStats {
+	pub executions: u64,
+	/// All of them, with the penalties.
+	pub cycles: u64,
+	pub penalty_cycles: u64,
+}
+
+/// See the top of the file.
+#[derive(Clone)]
+pub struct Profile {
+	opcodes: [OpcodeStats; 256],
+}
+
+impl Default for Profile {
+	fn default() -> Self {
+		Self::new()
+	}
+}
+
+impl Profile {
+	pub fn new() -> Self {
+		Profile { opcodes: [OpcodeStats::default(); 256] }
+	}
+
+	/// An instruction ran: `cycles` in all, `penalty_cycles` of them over its base count.
+	pub fn record(&mut self, opcode: u8, cycles: u8, penalty_cycles: u8) {
+		let stats = &mut self.opcodes[opcode as usize];
+		stats.executions += 1;
+		stats.cycles += cycles as u64;
+		stats.penalty_cycles += penalty_cycles as u64;
+	}
+
+	pub fn opcode(&self, opcode: u8) -> OpcodeStats {
+		self.opcodes[opcode as usize]
+	}
+
+	/// The sums of all the opcodes.
+	pub fn total(&self) -> OpcodeStats {
+		self.opcodes.iter().fold(OpcodeStats::default(), |total, stats| OpcodeStats {
+			executions: total.executions + stats.executions,
+			cycles: total.cycles + stats.cycles,
+			penalty_cycles: total.penalty_cycles + stats.penalty_cycles,
+		})
+	}
+
+	/// The opcodes that ran, the most cycles first (then by opcode, so the order is always the same).
+	pub fn hottest(&self) -> Vec<(u8, OpcodeStats)> {
+		let mut hottest: Vec<(u8, OpcodeStats)> = (0..=255).map(|opcode| (opcode, self.opcodes[opcode as usize]))
+			.filter(|(_, stats)| stats.executions > 0)
+			.collect();
+		hottest.sort_by_key(|&(opcode, stats)| (std::cmp::Reverse(stats.cycles), opcode));
+		hottest
+	}
+}
+
+/// A line for every opcode that ran, like at the top of the file, and the total.
+impl fmt::Display for Profile {
+	fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+		writeln!(f, "Opcode  Instruction        Executions      Cycles   Penalties")?;
+		for (opcode, stats) in self.hottest() {
+			let name = match decode_opcode(opcode) {
+				Some((instruction, addrmode, ..)) => format!("{:?} {:?}", instruction, addrmode),
+				None => "???".to_string(),
+			};
+			writeln!(f, "${:02X}     {:<19}{:>10} {:>11} {:>11}", opcode, name, stats.executions, stats.cycles, stats.penalty_cycles)?;
+		}
+		let total = self.total();
+		write!(f, "Total   {:<19}{:>10} {:>11} {:>11}", "", total.executions, total.cycles, total.penalty_cycles)
+	}
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+	use crate::cartridge::{test_rom, Cartridge};
+	use crate::emulator::Emulator;
+
+	/// Reads 32 bytes of a table at `table`, then loops on itself.
+	fn profile_table_loop(table: u16) -> Profile {
+		/*
+		LDX #$00
+		loop:
+		LDA table,X
+		INX
+		CPX #$20
+		BNE loop
+		end:
+		JMP end
+		*/
+		let program = format!("A2 00 BD {:02X} {:02X} E8 E0 20 D0 F8 4C 0A 80", table & 0xFF, table >> 8);
+		let mut emulator = Emulator::new(Cartridge::from_ines(&test_rom::nrom(&program)).unwrap());
+		emulator.set_profile(Profile::new());
+		while emulator.cpu_state().pc != 0x800A {
+			emulator.step_instruction();
+		}
+		emulator.profile().unwrap().clone()
+	}
+
+	#[test]
+	fn profile_test() {
+		// Past $8100 from X = $10: 16 reads cross the page. The branch is taken 31 times, always within the page.
+		let profile = profile_table_loop(0x80F0);
+		assert_eq!(profile.opcode(0xBD), OpcodeStats { executions: 32, cycles: 32 * 4 + 16, penalty_cycles: 16 });
+		assert_eq!(profile.opcode(0xD0), OpcodeStats { executions: 32, cycles: 32 * 2 + 31, penalty_cycles: 31 });
+		assert_eq!(profile.opcode(0xA2), OpcodeStats { executions: 1, cycles: 2, penalty_cycles: 0 });
+		assert_eq!(profile.total(), OpcodeStats { executions: 129, cycles: 2 + 144 + 64 + 64 + 95, penalty_cycles: 47 });
+		assert_eq!(profile.hottest()[0].0, 0xBD);
+
+		// The same loop over a table that starts a page crosses nothing.
+		let profile = profile_table_loop(0x8000);
+		assert_eq!(profile.opcode(0xBD), OpcodeStats { executions: 32, cycles: 32 * 4, penalty_cycles: 0 });
+		assert_eq!(profile.total().penalty_cycles, 31);
+
+		let lines: Vec<String> = profile.to_string().lines().map(str::to_string).collect();
+		assert_eq!(lines.len(), 7);
+		assert_eq!(lines[1], "$BD     LDA ABSOLUTEX              32         128           0");
+		assert!(lines[6].starts_with("Total"));
+	}
+}
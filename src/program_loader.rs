@@ -1,5 +1,49 @@
+use crate::error::NesError;
 use crate::memory::write_rom;
 
+/// Where the reset vector is.
+const RESET_VECTOR: usize = 0xFFFC;
+
+/// A raw 6502 binary (no header, like ca65 or xa output) in a flat 64KB memory, for a bare 6502: no PPU, APU or
+/// mirrors. The binary goes at `load`, and the CPU starts at `entry`:
+///
+/// | `load` | `entry` | Binary at | Reset vector |
+/// |---|---|---|---|
+/// | Some | Some | `load` | `entry` |
+/// | Some | None | `load` | the binary's own if it covers $FFFC-$FFFD, else `load` |
+/// | None | Some | `entry` | `entry` |
+/// | None | None | the end of memory, so its last byte is at $FFFF | the binary's own, if it's long enough |
+///
+/// So `entry` always wins over the binary's vector, and a binary that doesn't have one starts where it was loaded.
+pub fn load_raw(bytes: &[u8], load: Option<u16>, entry: Option<u16>) -> Result<[u8; 65_536], NesError> {
+	if bytes.len() > 0x10000 {
+		return Err(NesError::Bus(format!("Binary is {} bytes, more than the 64KB of memory", bytes.len())));
+	}
+	let start = load.or(entry).map_or(0x10000 - bytes.len(), |addr| addr as usize);
+	let end = start + bytes.len();
+	if end > 0x10000 {
+		return Err(NesError::Bus(format!("Binary is {} bytes, it doesn't fit in memory at {:#06X}", bytes.len(), start)));
+	}
+
+	let mut image = [0; 65_536];
+	image[start..end].copy_from_slice(bytes);
+	let has_vector = start <= RESET_VECTOR && end >= RESET_VECTOR + 2;
+	let vector = match entry {
+		Some(entry) => Some(entry),
+		None if has_vector => None,
+		None => Some(start as u16),
+	};
+	if let Some(vector) = vector {
+		image[RESET_VECTOR..RESET_VECTOR + 2].copy_from_slice(&vector.to_le_bytes());
+	}
+	Ok(image)
+}
+
+/// The demos are fixed programs, so a parse error is a bug here.
+fn write_program(rom: &mut [u8;65_536], dump: &str) {
+	write_rom(rom, dump).expect("Demo program is not valid hex");
+}
+
 // Each function loads a program to memory, and returns amount of assembly lines used.
 
 /// Basic stack operations; Push A, pull A.
@@ -15,7 +59,7 @@ pub fn load_program_stack(rom: &mut [u8;65_536]) -> u8 {
 		PLA ; This will overflow the stack pointer
 		NOP
 	*/
-	write_rom(rom, "A9 8C 48 A9 AB 48 68 68 68 EA");
+	write_program(rom, "A9 8C 48 A9 AB 48 68 68 68 EA");
 	8
 }
 
@@ -26,7 +70,7 @@ pub fn load_program_lda(rom: &mut [u8;65_536]) -> u8 {
 	LDA #$00
 	NOP
 	*/
-	write_rom(rom, "A9 FF A9 00 EA");
+	write_program(rom, "A9 FF A9 00 EA");
 	3
 }
 
@@ -58,7 +102,7 @@ pub fn load_program_adc(rom: &mut [u8;65_536]) -> u8 {
 
 	NOP
 	*/
-	write_rom(rom, "d8 a9 09 18 69 02 f8 a9 09 18 69 02 d8 a9 ff 69 81 18 a9 80 69 ff b8 18 a9 7f 69 01 ea");
+	write_program(rom, "d8 a9 09 18 69 02 f8 a9 09 18 69 02 d8 a9 ff 69 81 18 a9 80 69 ff b8 18 a9 7f 69 01 ea");
 	19
 }
 
@@ -71,7 +115,7 @@ pub fn load_program_absolute_store(rom: &mut [u8;65_536]) -> u8 {
 	STX $2001
 	NOP
 	*/
-	write_rom(rom, "78 d8 a2 ab 8e 00 20 8e 01 20 ea");
+	write_program(rom, "78 d8 a2 ab 8e 00 20 8e 01 20 ea");
 	6
 }
 
@@ -82,7 +126,7 @@ pub fn load_program_index_increment(rom: &mut [u8;65_536]) -> u8 {
 	INX
 	NOP
 	*/
-	write_rom(rom, "a2 fe e8 e8 ea");
+	write_program(rom, "a2 fe e8 e8 ea");
 	4
 }
 
@@ -94,7 +138,7 @@ pub fn load_program_zeropage_store_load_and_memory_increment(rom: &mut [u8;65_53
 	INC $0A
 	NOP
 	*/
-	write_rom(rom, "a2 fe 86 0a e6 0a e6 0a ea");
+	write_program(rom, "a2 fe 86 0a e6 0a e6 0a ea");
 	5
 }
 
@@ -113,7 +157,7 @@ pub fn load_program_zeropage_x(rom: &mut [u8;65_536]) -> u8 {
 
 	NOP
 	*/
-	write_rom(rom, "a2 fe 86 0a a5 0a a2 ff a9 00 b5 0b a2 0b 75 ff ea");
+	write_program(rom, "a2 fe 86 0a a5 0a a2 ff a9 00 b5 0b a2 0b 75 ff ea");
 	9
 }
 
@@ -130,7 +174,7 @@ pub fn load_program_absolute_indexed(rom: &mut [u8;65_536]) -> u8 {
 
 	NOP
 	*/
-	write_rom(rom, "a9 0a 8d cd ab a2 0d bc c0 ab a9 00 a0 ff b9 ce aa ea");
+	write_program(rom, "a9 0a 8d cd ab a2 0d bc c0 ab a9 00 a0 ff b9 ce aa ea");
 	8
 }
 
@@ -144,7 +188,7 @@ pub fn load_program_jmp_absolute(rom: &mut [u8;65_536]) -> u8 {
 	JMP 0001 	; Jump to $0001
 	- 			; Execute the instruction in $0001 , DECIMAL bitflag is set. Note: We don't add assembly instruction here, because its out of reach. The PC changed.
 	*/
-	write_rom(rom, "a2 f8 8e 01 00 4c 01 00");
+	write_program(rom, "a2 f8 8e 01 00 4c 01 00");
 	4  	// 4 instructions, the last instruction should be executed (0xF8 = SED).
 }
 
@@ -158,7 +202,7 @@ pub fn load_program_jmp_indirect(rom: &mut [u8;65_536]) -> u8 {
 
 	JMP ($00AB)
 	*/
-	write_rom(rom, "a9 05 8d ab 00 a9 ff 8d ac 00 6c ab 00");
+	write_program(rom, "a9 05 8d ab 00 a9 ff 8d ac 00 6c ab 00");
 	5
 }
 
@@ -178,7 +222,7 @@ pub fn load_program_cmp(rom: &mut [u8;65_536]) -> u8 {
 
 	NOP
 	*/
-	write_rom(rom, "a9 05 c9 01 c9 05 c9 06 a9 aa c9 22 a9 00 c9 ff ea");
+	write_program(rom, "a9 05 c9 01 c9 05 c9 06 a9 aa c9 22 a9 00 c9 ff ea");
 	9
 }
 
@@ -198,6 +242,356 @@ pub fn load_program_cpx(rom: &mut [u8;65_536]) -> u8 {
 
 	NOP
 	*/
-	write_rom(rom, "a9 05 85 0a a2 04 e4 0a a2 ff e4 0a a2 05 e4 0a ea");
+	write_program(rom, "a9 05 85 0a a2 04 e4 0a a2 ff e4 0a a2 05 e4 0a ea");
 	9
 }
+
+/// Branch backwards until X wraps around to zero.
+pub fn load_program_branch(rom: &mut [u8;65_536]) -> u8 {
+	/*
+	LDX #$FD
+	loop:
+	INX
+	BNE loop 	; Taken twice (X = 0xFE, 0xFF), not taken when X = 0x00
+	NOP
+	*/
+	write_program(rom, "A2 FD E8 D0 FD EA");
+	4
+}
+
+/// The easy6502 "hello world": draw 3 pixels (white, green, orange) on the top left of the display at $0200.
+pub fn load_program_helloworld(rom: &mut [u8;65_536]) -> u8 {
+	/*
+	LDA #$01
+	STA $0200
+	LDA #$05
+	STA $0201
+	LDA #$08
+	STA $0202
+	*/
+	write_program(rom, "a9 01 8d 00 02 a9 05 8d 01 02 a9 08 8d 02 02");
+	6
+}
+
+/// Address of the null terminated string `load_program_tolower` converts.
+pub const TOLOWER_INPUT: u16 = 0x0640;
+/// Address the lowercase string is written to.
+pub const TOLOWER_OUTPUT: u16 = 0x0680;
+
+/// Convert a null terminated string to lowercase.
+pub fn load_program_tolower(rom: &mut [u8;65_536]) -> u8 {
+	/*
+		LDX #$00
+	loop:
+		LDA $0640,X
+		BEQ done 		; End of string
+		CMP #$41
+		BCC store 		; Below 'A'
+		CMP #$5B
+		BCS store 		; Above 'Z'
+		CLC
+		ADC #$20 		; To lowercase
+	store:
+		STA $0680,X
+		INX
+		JMP loop
+	done:
+		STA $0680,X 	; Null terminator
+	*/
+	write_program(rom, "a2 00 bd 40 06 f0 12 c9 41 90 07 c9 5b b0 03 18 69 20 9d 80 06 e8 4c 02 06 9d 80 06");
+
+	let input = b"Hello, World! 6502\0";
+	rom[TOLOWER_INPUT as usize..TOLOWER_INPUT as usize + input.len()].copy_from_slice(input);
+	14
+}
+
+/// Where the apple is: a pointer to the display, at $00-$01.
+pub const SNAKE_APPLE: u16 = 0x0000;
+/// The head of the snake, a pointer to the display, at $10-$11. The body follows it, a pointer per segment.
+pub const SNAKE_HEAD: u16 = 0x0010;
+/// Length of the snake, in bytes: 2 per segment.
+pub const SNAKE_LENGTH: u16 = 0x0003;
+/// Where the snake goes: 1 up, 2 right, 4 down, 8 left.
+pub const SNAKE_DIRECTION: u16 = 0x0002;
+
+/// easy6502's snake game, by Nick Morgan. It needs the easy6502 machine (`easy6502::Easy6502Bus`): random numbers at
+/// $FE, the last key at $FF (W, A, S, D), and the display at $0200. The game ends with a BRK at $0735, right after it.
+pub fn load_program_snake(rom: &mut [u8;65_536]) -> u8 {
+	/*
+	define appleL          $00
+	define appleH          $01
+	define snakeHeadL      $10
+	define snakeHeadH      $11
+	define snakeBodyStart  $12
+	define snakeDirection  $02
+	define snakeLength     $03
+	define movingUp        1
+	define movingRight     2
+	define movingDown      4
+	define movingLeft      8
+	define ASCII_w         $77
+	define ASCII_a         $61
+	define ASCII_s         $73
+	define ASCII_d         $64
+	define sysRandom       $fe
+	define sysLastKey      $ff
+
+		JSR init
+		JSR loop
+	init:
+		JSR initSnake
+		JSR generateApplePosition
+		RTS
+	initSnake:
+		LDA #movingRight
+		STA snakeDirection
+		LDA #4
+		STA snakeLength
+		LDA #$11
+		STA snakeHeadL
+		LDA #$10
+		STA snakeBodyStart
+		LDA #$0f
+		STA $14 		; Body segment 2, low byte
+		LDA #$04
+		STA snakeHeadH
+		STA $13
+		STA $15 		; Body segment 2, high byte
+		RTS
+	generateApplePosition:
+		LDA sysRandom
+		STA appleL
+		LDA sysRandom
+		AND #$03
+		CLC
+		ADC #2
+		STA appleH
+		RTS
+	loop:
+		JSR readKeys
+		JSR checkCollision
+		JSR updateSnake
+		JSR drawApple
+		JSR drawSnake
+		JSR spinWheels
+		JMP loop
+	readKeys:
+		LDA sysLastKey
+		CMP #ASCII_w
+		BEQ upKey
+		CMP #ASCII_d
+		BEQ rightKey
+		CMP #ASCII_s
+		BEQ downKey
+		CMP #ASCII_a
+		BEQ leftKey
+		RTS
+	upKey:
+		LDA #movingDown
+		BIT snakeDirection
+		BNE illegalMove
+		LDA #movingUp
+		STA snakeDirection
+		RTS
+	rightKey:
+		LDA #movingLeft
+		BIT snakeDirection
+		BNE illegalMove
+		LDA #movingRight
+		STA snakeDirection
+		RTS
+	downKey:
+		LDA #movingUp
+		BIT snakeDirection
+		BNE illegalMove
+		LDA #movingDown
+		STA snakeDirection
+		RTS
+	leftKey:
+		LDA #movingRight
+		BIT snakeDirection
+		BNE illegalMove
+		LDA #movingLeft
+		STA snakeDirection
+		RTS
+	illegalMove:
+		RTS
+	checkCollision:
+		JSR checkAppleCollision
+		JSR checkSnakeCollision
+		RTS
+	checkAppleCollision:
+		LDA appleL
+		CMP snakeHeadL
+		BNE doneCheckingAppleCollision
+		LDA appleH
+		CMP snakeHeadH
+		BNE doneCheckingAppleCollision
+		INC snakeLength
+		INC snakeLength
+		JSR generateApplePosition
+	doneCheckingAppleCollision:
+		RTS
+	checkSnakeCollision:
+		LDX #2
+	snakeCollisionLoop:
+		LDA snakeHeadL,x
+		CMP snakeHeadL
+		BNE continueCollisionLoop
+	maybeCollided:
+		LDA snakeHeadH,x
+		CMP snakeHeadH
+		BEQ didCollide
+	continueCollisionLoop:
+		INX
+		INX
+		CPX snakeLength
+		BEQ didntCollide
+		JMP snakeCollisionLoop
+	didCollide:
+		JMP gameOver
+	didntCollide:
+		RTS
+	updateSnake:
+		LDX snakeLength
+		DEX
+		TXA
+	updateloop:
+		LDA snakeHeadL,x
+		STA snakeBodyStart,x
+		DEX
+		BPL updateloop
+		LDA snakeDirection
+		LSR
+		BCS up
+		LSR
+		BCS right
+		LSR
+		BCS down
+		LSR
+		BCS left
+	up:
+		LDA snakeHeadL
+		SEC
+		SBC #$20
+		STA snakeHeadL
+		BCC upup
+		RTS
+	upup:
+		DEC snakeHeadH
+		LDA #$1
+		CMP snakeHeadH
+		BEQ collision
+		RTS
+	right:
+		INC snakeHeadL
+		LDA #$1f
+		BIT snakeHeadL
+		BEQ collision
+		RTS
+	down:
+		LDA snakeHeadL
+		CLC
+		ADC #$20
+		STA snakeHeadL
+		BCS downdown
+		RTS
+	downdown:
+		INC snakeHeadH
+		LDA #$6
+		CMP snakeHeadH
+		BEQ collision
+		RTS
+	left:
+		DEC snakeHeadL
+		LDA snakeHeadL
+		AND #$1f
+		CMP #$1f
+		BEQ collision
+		RTS
+	collision:
+		JMP gameOver
+	drawApple:
+		LDY #0
+		LDA sysRandom
+		STA (appleL),y
+		RTS
+	drawSnake:
+		LDX snakeLength
+		LDA #0
+		STA (snakeHeadL,x)
+		LDX #0
+		LDA #1
+		STA (snakeHeadL,x)
+		RTS
+	spinWheels:
+		LDX #0
+	spinloop:
+		NOP
+		NOP
+		DEX
+		BNE spinloop
+		RTS
+	gameOver:
+	*/
+	write_program(rom, concat!(
+		"20 06 06 20 38 06 20 0d 06 20 2a 06 60 a9 02 85 02 a9 04 85 03 a9 11 85 ",
+		"10 a9 10 85 12 a9 0f 85 14 a9 04 85 11 85 13 85 15 60 a5 fe 85 00 a5 fe ",
+		"29 03 18 69 02 85 01 60 20 4d 06 20 8d 06 20 c3 06 20 19 07 20 20 07 20 ",
+		"2d 07 4c 38 06 a5 ff c9 77 f0 0d c9 64 f0 14 c9 73 f0 1b c9 61 f0 22 60 ",
+		"a9 04 24 02 d0 26 a9 01 85 02 60 a9 08 24 02 d0 1b a9 02 85 02 60 a9 01 ",
+		"24 02 d0 10 a9 04 85 02 60 a9 02 24 02 d0 05 a9 08 85 02 60 60 20 94 06 ",
+		"20 a8 06 60 a5 00 c5 10 d0 0d a5 01 c5 11 d0 07 e6 03 e6 03 20 2a 06 60 ",
+		"a2 02 b5 10 c5 10 d0 06 b5 11 c5 11 f0 09 e8 e8 e4 03 f0 06 4c aa 06 4c ",
+		"35 07 60 a6 03 ca 8a b5 10 95 12 ca 10 f9 a5 02 4a b0 09 4a b0 19 4a b0 ",
+		"1f 4a b0 2f a5 10 38 e9 20 85 10 90 01 60 c6 11 a9 01 c5 11 f0 28 60 e6 ",
+		"10 a9 1f 24 10 f0 1f 60 a5 10 18 69 20 85 10 b0 01 60 e6 11 a9 06 c5 11 ",
+		"f0 0c 60 c6 10 a5 10 29 1f c9 1f f0 01 60 4c 35 07 a0 00 a5 fe 91 00 60 ",
+		"a6 03 a9 00 81 10 a2 00 a9 01 81 10 60 a2 00 ea ea ca d0 fb 60",
+	));
+	164
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+	use crate::bus::{Bus, FlatBus};
+	use crate::cpu::cpu::CPU;
+
+	fn reset_pc(image: &[u8; 65_536]) -> u16 {
+		let mut cpu = CPU::new(FlatBus::new(image));
+		cpu.reset();
+		cpu.state().pc
+	}
+
+	#[test]
+	fn load_raw_test() {
+		// LDA #$42, STA $10
+		let program = [0xA9, 0x42, 0x85, 0x10];
+		let image = load_raw(&program, Some(0x0600), Some(0x0600)).unwrap();
+		assert_eq!(image[0x0600..0x0604], program);
+		let mut cpu = CPU::new(FlatBus::new(&image));
+		cpu.reset();
+		assert_eq!(cpu.state().pc, 0x0600);
+		cpu.clock_tick();
+		cpu.clock_tick();
+		assert_eq!(cpu.bus().peek(0x10), 0x42);
+
+		// The entry alone is the load address too.
+		assert_eq!(load_raw(&program, None, Some(0x0600)).unwrap(), image);
+		assert_eq!(reset_pc(&load_raw(&program, Some(0x0600), Some(0x0602)).unwrap()), 0x0602);
+
+		// A binary with its own vectors: at the end of memory, starting where its vector says.
+		let mut rom = vec![0xEA; 0x100];
+		rom[0xFC..0xFE].copy_from_slice(&[0x10, 0xFF]);
+		assert_eq!(reset_pc(&load_raw(&rom, None, None).unwrap()), 0xFF10);
+		assert_eq!(reset_pc(&load_raw(&rom, Some(0xFF00), None).unwrap()), 0xFF10);
+		assert_eq!(reset_pc(&load_raw(&rom, Some(0xFF00), Some(0xFF20)).unwrap()), 0xFF20);
+		// Without them, where it was loaded.
+		assert_eq!(reset_pc(&load_raw(&rom, Some(0x8000), None).unwrap()), 0x8000);
+
+		assert!(load_raw(&program, Some(0xFFFE), None).is_err());
+		assert!(load_raw(&[0; 0x10001], None, None).is_err());
+		assert_eq!(reset_pc(&load_raw(&[0; 0x10000], Some(0x0000), Some(0x0400)).unwrap()), 0x0400);
+	}
+}
@@ -0,0 +1,164 @@
+// What RAM holds at power on. The console doesn't clear it: it powers on with whatever the chips settle to, which
+// changes from console to console (and from one power on to the next). Most games clear it first, but some read it
+// before, and some bugs only show with some contents.
+//
+// | Pattern | RAM |
+// |---|---|
+// | `AllZero` | $00 everywhere, the default |
+// | `AllFF` | $FF everywhere |
+// | `Alternating` | 4 bytes of $00, then 4 bytes of $FF, and so on. Common on Famicoms |
+// | `Random { seed }` | Random bytes, the same ones for the same seed |
+//
+// It goes to the internal RAM, PRG RAM and CHR RAM, at power on (`Emulator::power_on`), and not at reset. The
+// pattern is in save states, so a run that failed with a random pattern can be replayed with its seed.
+
+use std::fmt;
+use std::str::FromStr;
+
+#[derive(Clone, Copy, PartialEq, Eq, Debug, Default)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub enum RamInitPattern {
+	#[default]
+	AllZero,
+	AllFF,
+	Alternating,
+	Random { seed: u64 },
+}
+
+impl RamInitPattern {
+	/// Fills memories, one after the other. A random pattern goes on where the last memory ended, so the memories
+	/// are not copies of each other.
+	pub fn filler(self) -> RamFiller {
+		RamFiller { pattern: self, random: match self { RamInitPattern::Random { seed } => seed, _ => 0 } }
+	}
+
+	/// For save states.
+	pub(crate) fn to_parts(self) -> (u8, u64) {
+		match self {
+			RamInitPattern::AllZero => (0, 0),
+			RamInitPattern::AllFF => (1, 0),
+			RamInitPattern::Alternating => (2, 0),
+			RamInitPattern::Random { seed } => (3, seed),
+		}
+	}
+
+	pub(crate) fn from_parts(kind: u8, seed: u64) -> Result<Self, String> {
+		match kind {
+			0 => Ok(RamInitPattern::AllZero),
+			1 => Ok(RamInitPattern::AllFF),
+			2 => Ok(RamInitPattern::Alternating),
+			3 => Ok(RamInitPattern::Random { seed }),
+			_ => Err(format!("Unknown RAM pattern {} in the save state", kind)),
+		}
+	}
+}
+
+/// `random:1234`
+impl fmt::Display for RamInitPattern {
+	fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+		match self {
+			RamInitPattern::AllZero => write!(f, "zero"),
+			RamInitPattern::AllFF => write!(f, "ff"),
+			RamInitPattern::Alternating => write!(f, "alternating"),
+			RamInitPattern::Random { seed } => write!(f, "random:{}", seed),
+		}
+	}
+}
+
+/// Parses what `Display` writes.
+impl FromStr for RamInitPattern {
+	type Err = String;
+
+	fn from_str(text: &str) -> Result<Self, Self::Err> {
+		match text.to_lowercase().as_str() {
+			"zero" => Ok(RamInitPattern::AllZero),
+			"ff" => Ok(RamInitPattern::AllFF),
+			"alternating" => Ok(RamInitPattern::Alternating),
+			other => {
+				let seed = other.strip_prefix("random:")
+					.ok_or_else(|| format!("Unknown RAM pattern '{}', expected zero, ff, alternating or random:SEED", text))?;
+				seed.parse().map(|seed| RamInitPattern::Random { seed })
+					.map_err(|_| format!("The seed of the RAM pattern is not a number: '{}'", seed))
+			}
+		}
+	}
+}
+
+/// See `RamInitPattern::filler`.
+pub struct RamFiller {
+	pattern: RamInitPattern,
+	/// State of the random numbers.
+	random: u64,
+}
+
+impl RamFiller {
+	pub fn fill(&mut self, memory: &mut [u8]) {
+		match self.pattern {
+			RamInitPattern::AllZero => memory.fill(0x00),
+			RamInitPattern::AllFF => memory.fill(0xFF),
+			RamInitPattern::Alternating => {
+				for (i, byte) in memory.iter_mut().enumerate() {
+					*byte = if i & 4 == 0 { 0x00 } else { 0xFF };
+				}
+			}
+			RamInitPattern::Random { .. } => {
+				for byte in memory.iter_mut() {
+					*byte = self.next_random();
+				}
+			}
+		}
+	}
+
+	/// SplitMix64: good numbers from any seed, 0 too.
+	fn next_random(&mut self) -> u8 {
+		self.random = self.random.wrapping_add(0x9E37_79B9_7F4A_7C15);
+		let mut z = self.random;
+		z = (z ^ (z >> 30)).wrapping_mul(0xBF58_476D_1CE4_E5B9);
+		z = (z ^ (z >> 27)).wrapping_mul(0x94D0_49BB_1331_11EB);
+		(z ^ (z >> 31)) as u8
+	}
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+
+	fn filled(pattern: RamInitPattern) -> [u8; 12] {
+		let mut memory = [0x55; 12];
+		pattern.filler().fill(&mut memory);
+		memory
+	}
+
+	#[test]
+	fn fill_test() {
+		assert_eq!(filled(RamInitPattern::AllZero), [0; 12]);
+		assert_eq!(filled(RamInitPattern::AllFF), [0xFF; 12]);
+		assert_eq!(filled(RamInitPattern::Alternating), [0, 0, 0, 0, 0xFF, 0xFF, 0xFF, 0xFF, 0, 0, 0, 0]);
+
+		let random = filled(RamInitPattern::Random { seed: 1 });
+		assert_eq!(filled(RamInitPattern::Random { seed: 1 }), random);
+		assert_ne!(filled(RamInitPattern::Random { seed: 2 }), random);
+		assert!(random.iter().any(|&byte| byte != random[0]));
+
+		// The next memory goes on with other numbers.
+		let mut filler = RamInitPattern::Random { seed: 1 }.filler();
+		let (mut first, mut second) = ([0; 12], [0; 12]);
+		filler.fill(&mut first);
+		filler.fill(&mut second);
+		assert_eq!(first, random);
+		assert_ne!(second, random);
+	}
+
+	#[test]
+	fn parse_test() {
+		for pattern in [RamInitPattern::AllZero, RamInitPattern::AllFF, RamInitPattern::Alternating, RamInitPattern::Random { seed: 1234 }] {
+			assert_eq!(pattern.to_string().parse(), Ok(pattern));
+			let (kind, seed) = pattern.to_parts();
+			assert_eq!(RamInitPattern::from_parts(kind, seed), Ok(pattern));
+		}
+		assert_eq!("FF".parse(), Ok(RamInitPattern::AllFF));
+		assert!("random".parse::<RamInitPattern>().is_err());
+		assert!("random:x".parse::<RamInitPattern>().is_err());
+		assert!("ones".parse::<RamInitPattern>().is_err());
+	}
+}
@@ -0,0 +1,179 @@
+// TV system of the console, and all the timing that depends on it: https://www.nesdev.org/wiki/Cycle_reference_chart
+//
+// | | NTSC | PAL |
+// |---|---|---|
+// | Master clock | 21.477272 MHz | 26.601712 MHz |
+// | CPU clock | Master / 12 = 1.789773 MHz | Master / 16 = 1.662607 MHz |
+// | PPU dots per CPU cycle | 3 | 3.2 |
+// | Scanlines per frame | 262 | 312 |
+// | VBlank scanlines | 20 | 70 |
+// | Skipped dot on odd frames | Yes | No |
+// | Frame rate | 60.0988 Hz | 50.0070 Hz |
+// | PPUMASK bits 5, 6 emphasize | Red, green | Green, red |
+//
+// Every timing constant that depends on the region lives here, so other regions (Dendy, for example) need only
+// a new row in every table.
+
+use std::fmt;
+
+#[derive(Default, Clone, Copy, PartialEq, Eq, Debug)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub enum Region {
+	#[default]
+	Ntsc,
+	Pal,
+}
+
+/// Frame counter sequencer steps, in CPU cycles since the sequence started. See `frame_counter.rs`.
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub struct FrameCounterSteps {
+	pub step1: u32,
+	pub step2: u32,
+	pub step3: u32,
+	/// Last step of the 4-step sequence. The frame IRQ is raised from the cycle before it, to the end of the sequence.
+	pub step4: u32,
+	/// Last step of the 5-step sequence.
+	pub step5: u32,
+	pub four_step_length: u32,
+	pub five_step_length: u32,
+}
+
+const NTSC_FRAME_COUNTER_STEPS: FrameCounterSteps = FrameCounterSteps {
+	step1: 7457,
+	step2: 14913,
+	step3: 22371,
+	step4: 29829,
+	step5: 37281,
+	four_step_length: 29830,
+	five_step_length: 37282,
+};
+
+const PAL_FRAME_COUNTER_STEPS: FrameCounterSteps = FrameCounterSteps {
+	step1: 8313,
+	step2: 16627,
+	step3: 24939,
+	step4: 33253,
+	step5: 41565,
+	four_step_length: 33254,
+	five_step_length: 41566,
+};
+
+/// Noise timer periods, in CPU cycles.
+const NTSC_NOISE_PERIODS: [u16; 16] = [4, 8, 16, 32, 64, 96, 128, 160, 202, 254, 380, 508, 762, 1016, 2034, 4068];
+const PAL_NOISE_PERIODS: [u16; 16] = [4, 8, 14, 30, 60, 88, 118, 148, 188, 236, 354, 472, 708, 944, 1890, 3778];
+
+/// DMC timer periods, in CPU cycles.
+const NTSC_DMC_RATES: [u16; 16] = [428, 380, 340, 320, 286, 254, 226, 214, 190, 160, 142, 128, 106, 84, 72, 54];
+const PAL_DMC_RATES: [u16; 16] = [398, 354, 316, 298, 276, 236, 210, 198, 176, 148, 132, 118, 98, 78, 66, 50];
+
+impl Region {
+	/// CPU clock rate, in Hz.
+	pub fn cpu_clock_rate(self) -> u64 {
+		match self {
+			Region::Ntsc => 1_789_773,
+			Region::Pal => 1_662_607,
+		}
+	}
+
+	/// PPU dots per CPU cycle, as a fraction (numerator, denominator), so PAL's 3.2 is exact.
+	pub fn ppu_dots_per_cpu_cycle(self) -> (u32, u32) {
+		match self {
+			Region::Ntsc => (3, 1),
+			Region::Pal => (16, 5),
+		}
+	}
+
+	pub fn scanlines_per_frame(self) -> u16 {
+		match self {
+			Region::Ntsc => 262,
+			Region::Pal => 312,
+		}
+	}
+
+	/// The first scanline of vertical blank. The same in both regions: PAL just has a longer VBlank.
+	pub fn vblank_scanline(self) -> u16 {
+		241
+	}
+
+	/// The last scanline of the frame.
+	pub fn prerender_scanline(self) -> u16 {
+		self.scanlines_per_frame() - 1
+	}
+
+	/// NTSC skips the last dot of the pre-render scanline on odd frames, when rendering is enabled.
+	pub fn skips_odd_frame_dot(self) -> bool {
+		self == Region::Ntsc
+	}
+
+	/// PAL swaps the red and green emphasis bits of PPUMASK.
+	pub fn swaps_red_green_emphasis(self) -> bool {
+		self == Region::Pal
+	}
+
+	/// Average CPU cycles per frame: 341 dots * scanlines (minus half the skipped dots) / dots per CPU cycle.
+	pub fn cpu_cycles_per_frame(self) -> f64 {
+		let dots = 341.0 * self.scanlines_per_frame() as f64 - if self.skips_odd_frame_dot() { 0.5 } else { 0.0 };
+		let (numerator, denominator) = self.ppu_dots_per_cpu_cycle();
+		dots * denominator as f64 / numerator as f64
+	}
+
+	/// Frames per second.
+	pub fn frame_rate(self) -> f64 {
+		let master_clock = match self {
+			Region::Ntsc => 236.25e6 / 11.0,
+			Region::Pal => 26.601712e6,
+		};
+		let cpu_divider = match self {
+			Region::Ntsc => 12.0,
+			Region::Pal => 16.0,
+		};
+		master_clock / cpu_divider / self.cpu_cycles_per_frame()
+	}
+
+	pub fn frame_nanos(self) -> f64 {
+		1e9 / self.frame_rate()
+	}
+
+	pub fn frame_counter_steps(self) -> &'static FrameCounterSteps {
+		match self {
+			Region::Ntsc => &NTSC_FRAME_COUNTER_STEPS,
+			Region::Pal => &PAL_FRAME_COUNTER_STEPS,
+		}
+	}
+
+	pub fn noise_periods(self) -> &'static [u16; 16] {
+		match self {
+			Region::Ntsc => &NTSC_NOISE_PERIODS,
+			Region::Pal => &PAL_NOISE_PERIODS,
+		}
+	}
+
+	pub fn dmc_rates(self) -> &'static [u16; 16] {
+		match self {
+			Region::Ntsc => &NTSC_DMC_RATES,
+			Region::Pal => &PAL_DMC_RATES,
+		}
+	}
+}
+
+impl fmt::Display for Region {
+	fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+		match self {
+			Region::Ntsc => write!(f, "NTSC"),
+			Region::Pal => write!(f, "PAL"),
+		}
+	}
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+
+	#[test]
+	fn frame_rate_test() {
+		assert!((Region::Ntsc.frame_rate() - 60.0988).abs() < 0.0001);
+		assert!((Region::Pal.frame_rate() - 50.0070).abs() < 0.0001);
+		assert_eq!(Region::Ntsc.cpu_cycles_per_frame(), 29_780.5);
+		assert_eq!(Region::Pal.cpu_cycles_per_frame(), 33_247.5);
+	}
+}
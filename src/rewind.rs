@@ -0,0 +1,256 @@
+// Rewind: a ring buffer of save states, taken every few frames.
+//
+// Consecutive states are almost the same, so only the newest state is kept whole. Every older state is kept as
+// the difference (XOR) from the state after it, with the runs of zeros (unchanged bytes) compressed:
+//
+// | Oldest | ... | | Newest |
+// |---|---|---|---|
+// | S0 ^ S1 | ... | S(n-1) ^ Sn | Sn |
+//
+// Rewinding pops the newest state, and rebuilds the one before it from its difference. When the buffer is too big,
+// the oldest difference is dropped, and nothing else has to change.
+
+use std::collections::VecDeque;
+
+use crate::emulator::Emulator;
+
+pub const DEFAULT_REWIND_INTERVAL: u32 = 3;
+pub const DEFAULT_REWIND_MEMORY: usize = 64 * 1024 * 1024;
+
+pub struct Rewind {
+	/// Take a state every `interval` frames.
+	interval: u32,
+	frames: u32,
+	max_bytes: usize,
+	/// Differences, oldest first. `deltas[i]` rebuilds state i from state i + 1.
+	deltas: VecDeque<Vec<u8>>,
+	delta_bytes: usize,
+	latest: Option<Vec<u8>>,
+}
+
+impl Rewind {
+	/// `max_bytes` caps the memory of all the states together.
+	pub fn new(interval: u32, max_bytes: usize) -> Self {
+		assert!(interval > 0, "Rewind interval must be at least 1 frame");
+		Rewind {
+			interval,
+			frames: 0,
+			max_bytes,
+			deltas: VecDeque::new(),
+			delta_bytes: 0,
+			latest: None,
+		}
+	}
+
+	/// Call after every emulated frame (not rewound). Takes a state every `interval` frames.
+	pub fn frame(&mut self, emulator: &Emulator) {
+		self.frames += 1;
+		if self.frames >= self.interval {
+			self.frames = 0;
+			self.push(emulator.save_state());
+		}
+	}
+
+	pub fn push(&mut self, state: Vec<u8>) {
+		if let Some(latest) = self.latest.take() {
+			if latest.len() == state.len() {
+				let delta = compress_delta(&latest, &state);
+				self.delta_bytes += delta.len();
+				self.deltas.push_back(delta);
+			} else {
+				// Can't happen with the same emulator, but a difference can't be made anyway.
+				self.clear();
+			}
+		}
+		self.latest = Some(state);
+
+		while self.memory() > self.max_bytes && !self.deltas.is_empty() {
+			let oldest = self.deltas.pop_front().unwrap();
+			self.delta_bytes -= oldest.len();
+		}
+	}
+
+	/// The newest state, removed from the buffer. None when there is nothing left to rewind.
+	pub fn pop(&mut self) -> Option<Vec<u8>> {
+		let state = self.latest.take()?;
+		if let Some(delta) = self.deltas.pop_back() {
+			self.delta_bytes -= delta.len();
+			self.latest = Some(apply_delta(&state, &delta));
+		}
+		self.frames = 0;
+		Some(state)
+	}
+
+	/// Pop the newest state and load it. Returns false when there is nothing left to rewind, and then the
+	/// emulator stays where it is.
+	pub fn rewind(&mut self, emulator: &mut Emulator) -> bool {
+		match self.pop() {
+			Some(state) => emulator.load_state(&state).is_ok(),
+			None => false,
+		}
+	}
+
+	pub fn len(&self) -> usize {
+		self.deltas.len() + self.latest.is_some() as usize
+	}
+
+	pub fn is_empty(&self) -> bool {
+		self.latest.is_none()
+	}
+
+	/// Bytes used by the states.
+	pub fn memory(&self) -> usize {
+		self.delta_bytes + self.latest.as_ref().map_or(0, |latest| latest.len())
+	}
+
+	pub fn clear(&mut self) {
+		self.deltas.clear();
+		self.delta_bytes = 0;
+		self.latest = None;
+		self.frames = 0;
+	}
+}
+
+/// `newer ^ older`, as runs: (count of unchanged bytes, count of changed bytes, the changed bytes XORed).
+/// Counts are LEB128 varints, because most runs are short.
+fn compress_delta(older: &[u8], newer: &[u8]) -> Vec<u8> {
+	let mut out = vec![];
+	let mut i = 0;
+	while i < older.len() {
+		let unchanged_start = i;
+		while i < older.len() && older[i] == newer[i] {
+			i += 1;
+		}
+		let changed_start = i;
+		while i < older.len() && older[i] != newer[i] {
+			i += 1;
+		}
+		write_varint(&mut out, changed_start - unchanged_start);
+		write_varint(&mut out, i - changed_start);
+		out.extend((changed_start..i).map(|j| older[j] ^ newer[j]));
+	}
+	out
+}
+
+fn apply_delta(newer: &[u8], delta: &[u8]) -> Vec<u8> {
+	let mut older = newer.to_vec();
+	let mut position = 0;
+	let mut i = 0;
+	while position < delta.len() {
+		i += read_varint(delta, &mut position);
+		let changed = read_varint(delta, &mut position);
+		for byte in &delta[position..position + changed] {
+			older[i] ^= byte;
+			i += 1;
+		}
+		position += changed;
+	}
+	older
+}
+
+fn write_varint(out: &mut Vec<u8>, mut value: usize) {
+	while value >= 0x80 {
+		out.push((value as u8 & 0x7F) | 0x80);
+		value >>= 7;
+	}
+	out.push(value as u8);
+}
+
+fn read_varint(data: &[u8], position: &mut usize) -> usize {
+	let mut value = 0;
+	let mut shift = 0;
+	loop {
+		let byte = data[*position];
+		*position += 1;
+		value |= ((byte & 0x7F) as usize) << shift;
+		if byte & 0x80 == 0 {
+			return value;
+		}
+		shift += 7;
+	}
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+	use crate::cartridge::{test_rom, Cartridge};
+
+	#[test]
+	fn delta_test() {
+		let older: Vec<u8> = (0..1000).map(|i| (i % 256) as u8).collect();
+		let mut newer = older.clone();
+		newer[0] = 0xFF;
+		newer[500..520].fill(0);
+		newer[999] = 1;
+
+		let delta = compress_delta(&older, &newer);
+		assert!(delta.len() < 40, "delta is {} bytes", delta.len());
+		assert_eq!(apply_delta(&newer, &delta), older);
+		assert_eq!(apply_delta(&older, &compress_delta(&older, &older)), older);
+	}
+
+	#[test]
+	fn ring_buffer_test() {
+		let mut rewind = Rewind::new(1, 1024 * 1024);
+		let states: Vec<Vec<u8>> = (0..10u8).map(|i| vec![i; 100]).collect();
+		for state in &states {
+			rewind.push(state.clone());
+		}
+		assert_eq!(rewind.len(), 10);
+
+		// Newest first, all the way to the oldest, and then it stops.
+		for state in states.iter().rev() {
+			assert_eq!(rewind.pop().as_ref(), Some(state));
+		}
+		assert_eq!(rewind.pop(), None);
+		assert!(rewind.is_empty());
+	}
+
+	#[test]
+	fn memory_cap_test() {
+		// Every state is completely different from the one before it, so every difference is about 100 bytes.
+		let mut rewind = Rewind::new(1, 1000);
+		for i in 0..50u8 {
+			rewind.push(vec![i; 100]);
+		}
+		assert!(rewind.memory() <= 1000, "memory: {}", rewind.memory());
+
+		// The newest states are kept, the oldest are dropped.
+		let mut last = None;
+		while let Some(state) = rewind.pop() {
+			last = Some(state[0]);
+		}
+		assert!(last.unwrap() > 40, "oldest state: {:?}", last);
+	}
+
+	#[test]
+	fn resume_determinism_test() {
+		// Keeps changing the background color, so every frame is different.
+		let rom = test_rom::nrom("A9 3F 8D 06 20 A9 00 8D 06 20 E8 8E 07 20 4C 00 80");
+		let mut emulator = Emulator::new(Cartridge::from_ines(&rom).unwrap());
+		let mut rewind = Rewind::new(2, DEFAULT_REWIND_MEMORY);
+
+		let mut frames = vec![];
+		for _ in 0..20 {
+			frames.push(emulator.run_frame().clone());
+			rewind.frame(&emulator);
+		}
+
+		// States were taken after frames 2, 4, ..., 20. Rewind 3 states: to the state after frame 16.
+		for _ in 0..3 {
+			assert!(rewind.rewind(&mut emulator));
+		}
+		assert!(*emulator.framebuffer() == frames[15]);
+
+		// Going forward again replays exactly the same frames.
+		for frame in &frames[16..20] {
+			assert!(emulator.run_frame() == frame);
+		}
+
+		// Rewinding to the start stops there.
+		while rewind.rewind(&mut emulator) {}
+		assert!(*emulator.framebuffer() == frames[1]);
+		assert!(!rewind.rewind(&mut emulator));
+		assert!(*emulator.framebuffer() == frames[1]);
+	}
+}
@@ -0,0 +1,243 @@
+// Reloading the ROM while it's being developed (`--watch`): rebuild it, and the emulator runs the new one, without
+// restarting the window or the debugger.
+//
+// The file is polled: its modification time and size, no file system notifications. A changed file is loaded again,
+// and the console powers on with it (`Emulator::insert_cartridge`). The assembler may still be writing it, so a file
+// that doesn't load keeps the old cartridge running, and is tried again when it changes again (the rest of the write).
+// The debugger's breakpoints, watchpoints and symbols are not in the emulator, so they stay. So does the battery
+// backed PRG RAM, like on a power cycle: the new cartridge gets it when it's the same size, else its .sav would be
+// overwritten by the blank RAM on exit.
+
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::time::SystemTime;
+
+use log::warn;
+
+use crate::cartridge::{Cartridge, MapperDebugInfo, PrgLocation};
+use crate::cpu::cpu::CpuState;
+use crate::cpu::effects::{BusAccess, StepEffects};
+use crate::cpu::registers::Register;
+use crate::cpu::status::Flag;
+use crate::debugger::DebugTarget;
+use crate::emulator::Emulator;
+use crate::error::NesError;
+use crate::irq::IrqLine;
+use crate::ppu::framebuffer::Framebuffer;
+use crate::ppu::scanline::ScanlineState;
+
+/// Makes a cartridge of the bytes of the file: `Cartridge::from_ines`, or with a ROM database.
+pub type CartridgeLoader = Box<dyn Fn(&[u8]) -> Result<Cartridge, NesError>>;
+
+/// What tells a file changed: its modification time and its size.
+type Stamp = (SystemTime, u64);
+
+pub struct RomWatcher {
+	path: PathBuf,
+	load: CartridgeLoader,
+	/// The file as it was when it was last loaded, or tried to.
+	seen: Option<Stamp>,
+}
+
+impl RomWatcher {
+	/// Watch `path`, which the emulator is running now: only changes from now on reload it.
+	pub fn new(path: &Path, load: CartridgeLoader) -> Self {
+		RomWatcher { path: path.to_path_buf(), load, seen: stamp(path) }
+	}
+
+	pub fn path(&self) -> &Path {
+		&self.path
+	}
+
+	/// The file changed since it was last loaded (or tried to). A file that can't be read (it's being replaced) didn't
+	/// change yet.
+	pub fn changed(&self) -> bool {
+		stamp(&self.path).is_some_and(|stamp| Some(stamp) != self.seen)
+	}
+
+	/// Load the file, and insert it in `emulator`, even if it didn't change (the R key of the window), with the battery
+	/// backed PRG RAM of the old one. On error, the emulator keeps the cartridge it has.
+	pub fn reload(&mut self, emulator: &mut Emulator) -> Result<(), String> {
+		// Before reading: if the file changes while it's read, that's a change for the next poll.
+		self.seen = stamp(&self.path);
+		let bytes = fs::read(&self.path).map_err(|err| NesError::io(&self.path, err))?;
+		let cartridge = (self.load)(&bytes).map_err(|err| format!("Can't load {}: {}", self.path.display(), err))?;
+		let save = emulator.save_ram().map(<[u8]>::to_vec);
+		emulator.insert_cartridge(cartridge);
+		if let Some(save) = save {
+			// A new size, or no battery anymore: the new cartridge starts without it.
+			if let Err(err) = emulator.load_ram(&save) {
+				warn!("{}. The battery backed RAM of the old cartridge is lost", String::from(err));
+			}
+		}
+		Ok(())
+	}
+
+	/// Reload if the file changed. None if it didn't.
+	pub fn poll(&mut self, emulator: &mut Emulator) -> Option<Result<(), String>> {
+		self.changed().then(|| self.reload(emulator))
+	}
+}
+
+fn stamp(path: &Path) -> Option<Stamp> {
+	let metadata = fs::metadata(path).ok()?;
+	Some((metadata.modified().ok()?, metadata.len()))
+}
+
+/// The emulator, for the debugger, reloading its ROM before every command when the file changed.
+pub struct WatchedEmulator<'a> {
+	pub emulator: &'a mut Emulator,
+	pub watcher: RomWatcher,
+}
+
+impl DebugTarget for WatchedEmulator<'_> {
+	fn cpu_state(&self) -> CpuState {
+		self.emulator.cpu_state()
+	}
+
+	fn peek(&self, addr: u16) -> u8 {
+		self.emulator.peek(addr)
+	}
+
+	fn step(&mut self) {
+		DebugTarget::step(self.emulator);
+	}
+
+	fn step_with_effects(&mut self) -> StepEffects {
+		DebugTarget::step_with_effects(self.emulator)
+	}
+
+	fn is_device(&self, access: &BusAccess) -> bool {
+		DebugTarget::is_device(self.emulator, access)
+	}
+
+	fn irq_sources(&self) -> IrqLine {
+		DebugTarget::irq_sources(self.emulator)
+	}
+
+	fn set_cpu_state(&mut self, state: &CpuState) {
+		self.emulator.set_cpu_state(state);
+	}
+
+	fn set_register(&mut self, register: Register, value: u16) {
+		self.emulator.set_register(register, value);
+	}
+
+	fn set_flag(&mut self, flag: Flag, value: bool) {
+		self.emulator.set_flag(flag, value);
+	}
+
+	fn poke(&mut self, addr: u16, data: u8) {
+		self.emulator.poke(addr, data);
+	}
+
+	fn render_until_scanline(&mut self, scanline: u16) -> Result<(ScanlineState, &Framebuffer), String> {
+		DebugTarget::render_until_scanline(self.emulator, scanline)
+	}
+
+	fn mapper_state(&self) -> Option<MapperDebugInfo> {
+		DebugTarget::mapper_state(self.emulator)
+	}
+
+	fn prg_location(&self, addr: u16) -> Option<PrgLocation> {
+		DebugTarget::prg_location(self.emulator, addr)
+	}
+
+	fn poll_reload(&mut self) -> Option<String> {
+		let result = self.watcher.poll(self.emulator)?;
+		Some(match result {
+			Ok(()) => format!("Reloaded {}, and powered on", self.watcher.path().display()),
+			Err(err) => format!("{}. Still running the old one, it's tried again when the file changes", err),
+		})
+	}
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+	use crate::cartridge::test_rom;
+	use crate::debugger::{Debugger, Reply};
+	use crate::symbols::SymbolTable;
+
+	fn temp_file(name: &str) -> PathBuf {
+		std::env::temp_dir().join(format!("nes-{}-{}.nes", name, std::process::id()))
+	}
+
+	#[test]
+	fn reload_test() {
+		let path = temp_file("watch");
+		/*
+		Reset:
+		INC $10
+		JMP Reset
+		*/
+		fs::write(&path, test_rom::nrom("E6 10 4C 00 80")).unwrap();
+		let mut emulator = Emulator::new(Cartridge::from_ines(&fs::read(&path).unwrap()).unwrap());
+		let mut target = WatchedEmulator { emulator: &mut emulator, watcher: RomWatcher::new(&path, Box::new(Cartridge::from_ines)) };
+		let mut debugger = Debugger::new();
+		let mut symbols = SymbolTable::new();
+		symbols.insert(0x8000, "Reset", 1);
+		debugger.set_symbols(symbols);
+		debugger.execute(&mut target, "b Reset").unwrap();
+		debugger.execute(&mut target, "w 10").unwrap();
+		debugger.execute(&mut target, "watch counter = [$10]").unwrap();
+		debugger.execute(&mut target, "s 5").unwrap();
+		assert_ne!(target.peek(0x0010), 0);
+		assert!(!target.watcher.changed());
+		assert_eq!(target.poll_reload(), None);
+
+		// Half written: the old program keeps running.
+		fs::write(&path, &test_rom::nrom("E6 10 4C 00 80")[..100]).unwrap();
+		let message = target.poll_reload().unwrap();
+		assert!(message.contains("Still running the old one"), "{}", message);
+		assert_eq!(target.peek(0x8000), 0xE6);
+		assert_ne!(target.peek(0x0010), 0);
+		// It's not tried again until the file changes again.
+		assert_eq!(target.poll_reload(), None);
+
+		/*
+		Reset:
+		INC $11
+		JMP Reset
+		*/
+		fs::write(&path, test_rom::nrom("E6 11 4C 00 80")).unwrap();
+		assert!(target.poll_reload().unwrap().starts_with("Reloaded"));
+		debugger.program_reloaded(&target);
+		fs::remove_file(&path).unwrap();
+		// RAM is back to its power on state, and the new program starts from the reset vector.
+		assert_eq!((target.peek(0x0010), target.peek(0x0011)), (0, 0));
+		assert_eq!(target.peek(0x8001), 0x11);
+		assert_eq!(target.cpu_state().pc, 0x8000);
+
+		// The breakpoint, the watchpoint, the watch and the symbols are still there.
+		let Ok(Reply::Print(list)) = debugger.execute(&mut target, "b") else { panic!() };
+		assert!(list.contains("$8000") && list.contains("$0010"), "{}", list);
+		assert_eq!(debugger.execute(&mut target, "watches"), Ok(Reply::Print("counter = [$10]".to_string())));
+		assert!(debugger.execute(&mut target, "u Reset 1").is_ok());
+		let Ok(Reply::Print(stop)) = debugger.execute(&mut target, "c") else { panic!() };
+		assert!(stop.contains("Breakpoint 1"), "{}", stop);
+		// From the value after the reload, not the old program's.
+		assert!(stop.ends_with("  counter = $00 (0)"), "{}", stop);
+		assert_eq!(target.peek(0x0011), 1);
+	}
+
+	#[test]
+	fn battery_test() {
+		let path = temp_file("battery");
+		let mut rom = test_rom::nrom("EA");
+		rom[6] |= 0x02;
+		fs::write(&path, &rom).unwrap();
+		let mut emulator = Emulator::new(Cartridge::from_ines(&rom).unwrap());
+		let mut watcher = RomWatcher::new(&path, Box::new(Cartridge::from_ines));
+		emulator.poke(0x6000, 0x12);
+		watcher.reload(&mut emulator).unwrap();
+		assert_eq!(emulator.peek(0x6000), 0x12);
+		assert_eq!(emulator.save_ram().unwrap()[0], 0x12);
+
+		// Without a battery, it's gone, but the new one runs.
+		fs::write(&path, test_rom::nrom("A9 01")).unwrap();
+		watcher.reload(&mut emulator).unwrap();
+		fs::remove_file(&path).unwrap();
+		assert_eq!(emulator.peek(0x8000), 0xA9);
+	}
+}
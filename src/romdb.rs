@@ -0,0 +1,207 @@
+// ROM database: what the header of known dumps should say, for the ones with a wrong iNES header (a wrong mapper
+// number, mirroring or region). `Cartridge::from_ines_with_db` looks the ROM up by its hashes, and overrides the
+// header with what it finds, logging every change. `Cartridge::header` still has what the file says.
+//
+// The database is CSV, a ROM a line, and `#` starts a comment:
+//
+// | Column | Description |
+// |---|---|
+// | `crc32` | CRC32 of PRG ROM and CHR ROM, 8 hex digits |
+// | `sha1` | SHA-1 of PRG ROM and CHR ROM, 40 hex digits |
+// | `mapper` | iNES mapper number |
+// | `mirroring` | `horizontal` or `vertical` |
+// | `region` | `ntsc` or `pal` |
+// | `name` | For the log. It's the last column, so it can have commas |
+//
+// A line needs one of the hashes, and when it has both, both must match. An empty field keeps the header value.
+// The built-in database is romdb.csv, and `--romdb` adds a file on top of it: its lines win.
+
+use std::fs;
+use std::path::Path;
+use std::sync::OnceLock;
+
+use log::info;
+
+use crate::cartridge::HeaderValues;
+use crate::log_target::MAPPER;
+use crate::ppu::ppu::Mirroring;
+use crate::region::Region;
+
+/// A known ROM, and what its header should say.
+#[derive(Clone, PartialEq, Debug)]
+pub struct RomDbEntry {
+	pub crc32: Option<u32>,
+	pub sha1: Option<[u8; 20]>,
+	pub mapper: Option<u8>,
+	pub mirroring: Option<Mirroring>,
+	pub region: Option<Region>,
+	pub name: String,
+}
+
+impl RomDbEntry {
+	fn matches(&self, crc32: u32, sha1: &[u8; 20]) -> bool {
+		self.crc32.is_none_or(|hash| hash == crc32) && self.sha1.is_none_or(|hash| hash == *sha1)
+	}
+
+	/// The header values, with the ones of the entry instead. Every value that changes is logged.
+	pub fn fix(&self, header: HeaderValues) -> HeaderValues {
+		let fixed = HeaderValues {
+			mapper: self.mapper.unwrap_or(header.mapper),
+			mirroring: self.mirroring.unwrap_or(header.mirroring),
+			region: self.region.unwrap_or(header.region),
+		};
+		if fixed.mapper != header.mapper {
+			info!(target: MAPPER, "ROM database, {}: mapper {}, the header says {}", self.name, fixed.mapper, header.mapper);
+		}
+		if fixed.mirroring != header.mirroring {
+			info!(target: MAPPER, "ROM database, {}: {:?} mirroring, the header says {:?}", self.name, fixed.mirroring, header.mirroring);
+		}
+		if fixed.region != header.region {
+			info!(target: MAPPER, "ROM database, {}: {}, the header says {}", self.name, fixed.region, header.region);
+		}
+		if fixed == header {
+			info!(target: MAPPER, "ROM database, {}: the header is right", self.name);
+		}
+		fixed
+	}
+}
+
+/// Known ROMs, looked up by hash.
+#[derive(Clone, Default, Debug)]
+pub struct RomDb {
+	entries: Vec<RomDbEntry>,
+}
+
+impl RomDb {
+	pub fn new() -> Self {
+		Self::default()
+	}
+
+	/// romdb.csv, in the binary. `Cartridge::from_ines` uses it.
+	pub fn builtin() -> &'static RomDb {
+		static BUILTIN: OnceLock<RomDb> = OnceLock::new();
+		BUILTIN.get_or_init(|| RomDb::parse(include_str!("romdb.csv")).expect("romdb.csv is valid"))
+	}
+
+	/// Load a CSV file (see the top of the file). Only the file: `extend` the built-in database with it.
+	pub fn load(path: &Path) -> Result<Self, String> {
+		let bytes = fs::read(path).map_err(|err| format!("Can't read ROM database {}: {}", path.display(), err))?;
+		Self::parse(&String::from_utf8_lossy(&bytes)).map_err(|err| format!("{}: {}", path.display(), err))
+	}
+
+	pub fn parse(text: &str) -> Result<Self, String> {
+		let mut db = RomDb::new();
+		for (number, line) in text.lines().enumerate() {
+			let line = line.split('#').next().unwrap_or_default().trim();
+			if line.is_empty() {
+				continue;
+			}
+			db.entries.push(parse_entry(line).map_err(|err| format!("Line {}: {}", number + 1, err))?);
+		}
+		Ok(db)
+	}
+
+	/// Add the entries of `other`. They win over the ones already here.
+	pub fn extend(&mut self, other: RomDb) {
+		self.entries.extend(other.entries);
+	}
+
+	/// The entry of the ROM with these hashes. When more than one match, the last added.
+	pub fn lookup(&self, crc32: u32, sha1: &[u8; 20]) -> Option<&RomDbEntry> {
+		self.entries.iter().rev().find(|entry| entry.matches(crc32, sha1))
+	}
+
+	pub fn is_empty(&self) -> bool {
+		self.entries.is_empty()
+	}
+
+	pub fn len(&self) -> usize {
+		self.entries.len()
+	}
+}
+
+/// `crc32,sha1,mapper,mirroring,region,name`
+fn parse_entry(line: &str) -> Result<RomDbEntry, String> {
+	let fields: Vec<&str> = line.splitn(6, ',').map(str::trim).collect();
+	let [crc32, sha1, mapper, mirroring, region, name] = fields[..] else {
+		return Err(format!("expected crc32,sha1,mapper,mirroring,region,name, got '{}'", line));
+	};
+	let optional = |field: &str| if field.is_empty() { None } else { Some(field.to_string()) };
+
+	let crc32 = optional(crc32)
+		.map(|crc32| u32::from_str_radix(&crc32, 16).map_err(|_| format!("bad CRC32 '{}'", crc32)))
+		.transpose()?;
+	let sha1 = optional(sha1)
+		.map(|sha1| hex::decode(&sha1).ok().and_then(|bytes| bytes.try_into().ok()).ok_or_else(|| format!("bad SHA-1 '{}'", sha1)))
+		.transpose()?;
+	if crc32.is_none() && sha1.is_none() {
+		return Err("needs a CRC32 or a SHA-1".to_string());
+	}
+	let mapper = optional(mapper)
+		.map(|mapper| mapper.parse().map_err(|_| format!("bad mapper '{}'", mapper)))
+		.transpose()?;
+	let mirroring = optional(mirroring)
+		.map(|mirroring| match mirroring.to_lowercase().as_str() {
+			"horizontal" => Ok(Mirroring::Horizontal),
+			"vertical" => Ok(Mirroring::Vertical),
+			_ => Err(format!("unknown mirroring '{}', expected horizontal or vertical", mirroring)),
+		})
+		.transpose()?;
+	let region = optional(region)
+		.map(|region| match region.to_lowercase().as_str() {
+			"ntsc" => Ok(Region::Ntsc),
+			"pal" => Ok(Region::Pal),
+			_ => Err(format!("unknown region '{}', expected ntsc or pal", region)),
+		})
+		.transpose()?;
+	Ok(RomDbEntry { crc32, sha1, mapper, mirroring, region, name: name.to_string() })
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+
+	const CSV: &str = "\
+# A comment
+0BADF00D,,2,vertical,,Bad mapper, and mirroring
+
+,da39a3ee5e6b4b0d3255bfef95601890afd80709,,,pal,Only SHA-1 # and a comment
+12345678,0000000000000000000000000000000000000000,3,,,Both
+";
+
+	#[test]
+	fn parse_test() {
+		let db = RomDb::parse(CSV).unwrap();
+		assert_eq!(db.len(), 3);
+		let sha1 = [0; 20];
+
+		let entry = db.lookup(0x0BADF00D, &sha1).unwrap();
+		assert_eq!(entry.mapper, Some(2));
+		assert_eq!(entry.mirroring, Some(Mirroring::Vertical));
+		assert_eq!(entry.region, None);
+		assert_eq!(entry.name, "Bad mapper, and mirroring");
+
+		let empty = crate::hash::sha1(b"");
+		assert_eq!(db.lookup(0, &empty).unwrap().region, Some(Region::Pal));
+		// Both hashes must match.
+		assert_eq!(db.lookup(0x12345678, &sha1).unwrap().mapper, Some(3));
+		assert!(db.lookup(0x12345678, &[1; 20]).is_none());
+
+		let fixed = db.lookup(0x0BADF00D, &sha1).unwrap().fix(HeaderValues { mapper: 0, mirroring: Mirroring::Horizontal, region: Region::Pal });
+		assert_eq!(fixed, HeaderValues { mapper: 2, mirroring: Mirroring::Vertical, region: Region::Pal });
+
+		assert!(RomDb::parse(",,2,,,No hash").is_err());
+		assert!(RomDb::parse("0BADF00D,,2,sideways,,Bad mirroring").is_err());
+		assert!(RomDb::parse("0BADF00D,,2").is_err());
+		assert!(RomDb::parse("0BADF00D,1234,,,,Short SHA-1").is_err());
+		// The built-in one parses.
+		RomDb::builtin();
+	}
+
+	#[test]
+	fn extend_test() {
+		let mut db = RomDb::parse("0BADF00D,,2,,,Built in").unwrap();
+		db.extend(RomDb::parse("0BADF00D,,3,,,Mine").unwrap());
+		assert_eq!(db.lookup(0x0BADF00D, &[0; 20]).unwrap().name, "Mine");
+	}
+}
@@ -0,0 +1,183 @@
+// Save states: the whole state of the console, as bytes.
+//
+// | Bytes | Description |
+// |---|---|
+// | 0-3 | "NESS" |
+// | 4-7 | State format version (little endian) |
+// | 8-11 | CRC32 of the ROM (PRG and CHR), so a state is never loaded into another game |
+// | 12 | The power on RAM pattern, see `ram_init.rs` |
+// | 13-20 | Its seed, if it's random |
+// | 21- | The state of every component, in a fixed order, see `SaveState` |
+//
+// There are no field names or lengths in the state, every component just writes its fields in order, and reads
+// them back in the same order. So any change to what a component saves must bump `STATE_FORMAT_VERSION`.
+
+pub const STATE_FORMAT_VERSION: u32 = 9;
+const MAGIC: [u8; 4] = *b"NESS";
+
+/// A component that can save its state, and load it back.
+pub trait SaveState {
+	fn save_state(&self, out: &mut StateWriter);
+	/// On error the component may be left half loaded. The caller should restore a backup.
+	fn load_state(&mut self, input: &mut StateReader) -> Result<(), String>;
+}
+
+#[derive(Default)]
+pub struct StateWriter {
+	data: Vec<u8>,
+}
+
+impl StateWriter {
+	/// A writer with the state header.
+	pub fn with_header(rom_hash: u32) -> Self {
+		let mut writer = StateWriter::default();
+		writer.bytes(&MAGIC);
+		writer.u32(STATE_FORMAT_VERSION);
+		writer.u32(rom_hash);
+		writer
+	}
+
+	pub fn u8(&mut self, value: u8) {
+		self.data.push(value);
+	}
+
+	pub fn bool(&mut self, value: bool) {
+		self.u8(value as u8);
+	}
+
+	pub fn u16(&mut self, value: u16) {
+		self.bytes(&value.to_le_bytes());
+	}
+
+	pub fn u32(&mut self, value: u32) {
+		self.bytes(&value.to_le_bytes());
+	}
+
+	pub fn u64(&mut self, value: u64) {
+		self.bytes(&value.to_le_bytes());
+	}
+
+	pub fn f32(&mut self, value: f32) {
+		self.bytes(&value.to_le_bytes());
+	}
+
+	pub fn bytes(&mut self, bytes: &[u8]) {
+		self.data.extend_from_slice(bytes);
+	}
+
+	pub fn into_bytes(self) -> Vec<u8> {
+		self.data
+	}
+}
+
+#[derive(Debug)]
+pub struct StateReader<'a> {
+	data: &'a [u8],
+	position: usize,
+}
+
+impl<'a> StateReader<'a> {
+	pub fn new(data: &'a [u8]) -> Self {
+		StateReader { data, position: 0 }
+	}
+
+	/// Read and check the state header.
+	pub fn with_header(data: &'a [u8], rom_hash: u32) -> Result<Self, String> {
+		let mut reader = StateReader::new(data);
+		let mut magic = [0; 4];
+		reader.bytes(&mut magic).map_err(|_| "Not a save state: the file is too short".to_string())?;
+		if magic != MAGIC {
+			return Err("Not a save state: missing 'NESS' header".to_string());
+		}
+
+		let version = reader.u32()?;
+		if version != STATE_FORMAT_VERSION {
+			return Err(format!("Save state format version {} is not supported, expected version {}", version, STATE_FORMAT_VERSION));
+		}
+
+		let state_hash = reader.u32()?;
+		if state_hash != rom_hash {
+			return Err(format!("Save state is for another ROM (CRC32 {:08X}, this ROM is {:08X})", state_hash, rom_hash));
+		}
+		Ok(reader)
+	}
+
+	pub fn u8(&mut self) -> Result<u8, String> {
+		let mut byte = [0];
+		self.bytes(&mut byte)?;
+		Ok(byte[0])
+	}
+
+	pub fn bool(&mut self) -> Result<bool, String> {
+		match self.u8()? {
+			0 => Ok(false),
+			1 => Ok(true),
+			other => Err(format!("Save state is corrupt: {} at offset {} should be a bool", other, self.position - 1)),
+		}
+	}
+
+	pub fn u16(&mut self) -> Result<u16, String> {
+		let mut bytes = [0; 2];
+		self.bytes(&mut bytes)?;
+		Ok(u16::from_le_bytes(bytes))
+	}
+
+	pub fn u32(&mut self) -> Result<u32, String> {
+		let mut bytes = [0; 4];
+		self.bytes(&mut bytes)?;
+		Ok(u32::from_le_bytes(bytes))
+	}
+
+	pub fn u64(&mut self) -> Result<u64, String> {
+		let mut bytes = [0; 8];
+		self.bytes(&mut bytes)?;
+		Ok(u64::from_le_bytes(bytes))
+	}
+
+	pub fn f32(&mut self) -> Result<f32, String> {
+		let mut bytes = [0; 4];
+		self.bytes(&mut bytes)?;
+		Ok(f32::from_le_bytes(bytes))
+	}
+
+	/// Fill `out` completely.
+	pub fn bytes(&mut self, out: &mut [u8]) -> Result<(), String> {
+		let end = self.position + out.len();
+		if end > self.data.len() {
+			return Err("Save state is corrupt: it ends too early".to_string());
+		}
+		out.copy_from_slice(&self.data[self.position..end]);
+		self.position = end;
+		Ok(())
+	}
+
+	/// Everything was read. Leftover bytes mean the state doesn't match what the components expect.
+	pub fn finish(self) -> Result<(), String> {
+		if self.position != self.data.len() {
+			return Err(format!("Save state is corrupt: {} unexpected bytes at the end", self.data.len() - self.position));
+		}
+		Ok(())
+	}
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+
+	#[test]
+	fn header_test() {
+		let mut writer = StateWriter::with_header(0x1234_5678);
+		writer.u16(0xABCD);
+		writer.bool(true);
+		let bytes = writer.into_bytes();
+
+		let mut reader = StateReader::with_header(&bytes, 0x1234_5678).unwrap();
+		assert_eq!(reader.u16(), Ok(0xABCD));
+		assert_eq!(reader.bool(), Ok(true));
+		assert!(reader.u8().is_err());
+
+		assert!(StateReader::with_header(&bytes, 0x8765_4321).unwrap_err().contains("another ROM"));
+		assert!(StateReader::with_header(&bytes[..6], 0x1234_5678).is_err());
+		assert!(StateReader::with_header(b"NES\x1a and more bytes", 0x1234_5678).is_err());
+	}
+}
@@ -0,0 +1,356 @@
+// Window frontend, on top of SDL2. Only built with `--features sdl`.
+
+use std::path::{Path, PathBuf};
+use std::thread;
+use std::time::Duration;
+
+use log::{error, info};
+use sdl2::event::Event;
+use sdl2::keyboard::{Keycode, Mod, Scancode};
+use sdl2::pixels::PixelFormatEnum;
+
+use crate::cli::{Options, Program};
+use rust_nes_emulator::apu::apu::Channel;
+use rust_nes_emulator::controller::Button;
+use rust_nes_emulator::easy6502::{Easy6502Bus, DISPLAY_SIZE};
+use rust_nes_emulator::emulator::Emulator;
+use rust_nes_emulator::frame_diff::{FrameDiff, Reference};
+use rust_nes_emulator::frame_pacer::FramePacer;
+use crate::keymap::{KeyMap, PLAYERS};
+use crate::pause::{FrameAction, PauseControl};
+use rust_nes_emulator::movie::MovieMode;
+use rust_nes_emulator::png;
+use rust_nes_emulator::ppu::colors::{MasterPalette, PRESETS};
+use rust_nes_emulator::rewind::Rewind;
+use rust_nes_emulator::rom_watch::RomWatcher;
+use rust_nes_emulator::state_slots::StateSlots;
+use rust_nes_emulator::ppu::framebuffer::{HEIGHT, OVERSCAN_LINES, WIDTH};
+use rust_nes_emulator::{Region, CPU};
+
+/// How often the keys are checked while paused. The frame pacer is stopped then.
+const PAUSED_POLL: Duration = Duration::from_millis(10);
+/// Frames between checks of the ROM file, with --watch.
+const WATCH_INTERVAL: u64 = 30;
+
+/// Open a window, and run the emulator in it until the window is closed, Escape is pressed, or `--frames` frames ran.
+/// Tab toggles turbo: running as fast as possible. Holding Backspace rewinds. F5 saves the state to the current slot, F8 loads it, and 0-9 select the slot.
+/// P pauses and resumes, and the period key runs a single frame while paused, see pause.rs. F12 writes a screenshot,
+/// `<ROM>-<N>.png` in the current directory. With a `watcher` (--watch), the ROM is reloaded when its file changes, or
+/// when R is pressed. F9 switches to the next palette: the presets, and the --palette file. Ctrl+1-5 mutes or unmutes
+/// an APU channel (pulse 1, pulse 2, triangle, noise, DMC), and Ctrl+Shift+1-5 plays it alone, or all of them again.
+/// With an FDS disk, F6 flips it to the next side (or the next disk), which goes in 2 seconds after the eject.
+/// With --compare, the window shows the heatmap of the pixels that differ from the reference, and their count in the
+/// title. A recorded movie is written when the window closes. Loading and rewinding are disabled while a movie records or plays.
+pub fn run(emulator: &mut Emulator, options: &Options, keymap: &KeyMap, mut movie: MovieMode, mut watcher: Option<RomWatcher>) -> Result<(), String> {
+	for player in 0..PLAYERS {
+		for button in Button::ALL {
+			if Scancode::from_name(keymap.key(player, button)).is_none() {
+				return Err(format!("Unknown key '{}' for button {:?} of player {}", keymap.key(player, button), button, player + 1));
+			}
+		}
+	}
+
+	let visible_lines = if options.crop_overscan { HEIGHT - 2 * OVERSCAN_LINES } else { HEIGHT };
+	let first_line = if options.crop_overscan { OVERSCAN_LINES } else { 0 };
+
+	let sdl = sdl2::init()?;
+	let video = sdl.video()?;
+	let window = video
+		.window("NES", WIDTH as u32 * options.scale, visible_lines as u32 * options.scale)
+		.position_centered()
+		.build()
+		.map_err(|err| err.to_string())?;
+	// No vsync: the frame pacer sets the speed. Vsync would lock it to the display rate, and break --speed and turbo.
+	let mut canvas = window.into_canvas().build().map_err(|err| err.to_string())?;
+	let texture_creator = canvas.texture_creator();
+	let mut texture = texture_creator
+		.create_texture_streaming(PixelFormatEnum::RGB24, WIDTH as u32, visible_lines as u32)
+		.map_err(|err| err.to_string())?;
+	let mut event_pump = sdl.event_pump()?;
+
+	let mut rgb = vec![0; WIDTH * HEIGHT * 3];
+	let reference = options.compare.as_deref().map(Reference::load).transpose()?;
+	let mut differing = None;
+	let mut frames = 0;
+	let mut rewind = (options.rewind_interval > 0).then(|| Rewind::new(options.rewind_interval, options.rewind_memory));
+	let mut slots = StateSlots::new(&options.state_dir, emulator.rom_hash());
+	let mut pacer = FramePacer::new(emulator.region().frame_nanos());
+	pacer.set_speed(options.speed);
+	let mut pause = PauseControl::default();
+	let mut polls = 0u64;
+	// The --palette file is one more to switch to, after the presets.
+	let mut palettes: Vec<String> = PRESETS.iter().map(|name| name.to_string()).collect();
+	let mut palette = 0;
+	if let Some(name) = &options.palette {
+		palette = palettes.iter().position(|preset| preset.eq_ignore_ascii_case(name)).unwrap_or_else(|| {
+			palettes.push(name.clone());
+			palettes.len() - 1
+		});
+	}
+
+	'running: loop {
+		// The file is checked every few frames, and R reloads it anyway.
+		let mut reload = false;
+		if let Some(watcher) = &watcher {
+			polls += 1;
+			reload = polls.is_multiple_of(WATCH_INTERVAL) && watcher.changed();
+		}
+		for event in event_pump.poll_iter() {
+			match event {
+				Event::Quit { .. } | Event::KeyDown { keycode: Some(Keycode::Escape), .. } => break 'running,
+				Event::KeyDown { keycode: Some(Keycode::Tab), repeat: false, .. } => {
+					pacer.set_turbo(!pacer.turbo());
+					info!("Turbo {}", if pacer.turbo() { "on" } else { "off" });
+				}
+				Event::KeyDown { keycode: Some(Keycode::P), repeat: false, .. } => {
+					if pause.toggle() {
+						show_message(canvas.window_mut(), Ok(format!("Paused at frame {}", emulator.frame())));
+					} else {
+						// The time paused is not time to catch up on.
+						pacer.restart();
+						show_message(canvas.window_mut(), Ok("Resumed".to_string()));
+					}
+				}
+				Event::KeyDown { keycode: Some(Keycode::F12), repeat: false, .. } => {
+					let result = screenshot(emulator, options)
+						.map(|path| format!("Screenshot saved to {}", path.display()))
+						.map_err(|err| format!("Screenshot failed: {}", err));
+					show_message(canvas.window_mut(), result);
+				}
+				Event::KeyDown { keycode: Some(Keycode::F9), repeat: false, .. } => {
+					palette = (palette + 1) % palettes.len();
+					let result = MasterPalette::preset_or_file(&palettes[palette])
+						.map(|colors| {
+							emulator.set_master_palette(colors);
+							format!("Palette: {}", palettes[palette])
+						})
+						.map_err(|err| format!("Palette failed: {}", err));
+					show_message(canvas.window_mut(), result);
+				}
+				Event::KeyDown { keycode: Some(Keycode::F6), repeat: false, .. } if emulator.bus().cartridge().disk_sides() > 0 && movie.is_active() => {
+					show_message(canvas.window_mut(), Err("Can't change the disk while a movie records or plays".to_string()));
+				}
+				Event::KeyDown { keycode: Some(Keycode::F6), repeat: false, .. } if emulator.bus().cartridge().disk_sides() > 0 => {
+					let sides = emulator.bus().cartridge().disk_sides();
+					let side = emulator.bus().cartridge().disk_side().map_or(0, |side| (side + 1) % sides);
+					let result = emulator.insert_disk(Some(side))
+						.map(|()| format!("Disk {} side {}", side / 2 + 1, if side.is_multiple_of(2) { 'A' } else { 'B' }));
+					show_message(canvas.window_mut(), result);
+				}
+				Event::KeyDown { keycode: Some(Keycode::R), repeat: false, .. } if watcher.is_some() => reload = true,
+				// Repeats too: holding it steps continuously.
+				Event::KeyDown { keycode: Some(Keycode::Period), .. } => pause.advance(),
+				Event::KeyDown { keycode: Some(Keycode::F5), repeat: false, .. } => {
+					let result = slots.save(emulator)
+						.map(|path| format!("Saved slot {} to {}", slots.slot(), path.display()))
+						.map_err(|err| format!("Save failed: {}", err));
+					show_message(canvas.window_mut(), result);
+				}
+				Event::KeyDown { keycode: Some(Keycode::F8), repeat: false, .. } if movie.is_active() => {
+					show_message(canvas.window_mut(), Err("Can't load a state while a movie records or plays".to_string()));
+				}
+				Event::KeyDown { keycode: Some(Keycode::F8), repeat: false, .. } => {
+					let result = slots.load(emulator)
+						.map(|path| format!("Loaded slot {} from {}", slots.slot(), path.display()))
+						.map_err(|err| format!("Load failed: {}", err));
+					// Rewinding from the loaded state should not jump back to before it was loaded.
+					if let (Ok(_), Some(rewind)) = (&result, rewind.as_mut()) {
+						rewind.clear();
+					}
+					show_message(canvas.window_mut(), result);
+				}
+				Event::KeyDown { keycode: Some(keycode), keymod, repeat: false, .. } if keymod.intersects(Mod::LCTRLMOD | Mod::RCTRLMOD) && (Keycode::Num1 as i32..=Keycode::Num5 as i32).contains(&(keycode as i32)) => {
+					let channel = Channel::ALL[(keycode as i32 - Keycode::Num1 as i32) as usize];
+					let mask = emulator.bus().apu().channel_mask();
+					let mask = if !keymod.intersects(Mod::LSHIFTMOD | Mod::RSHIFTMOD) {
+						mask ^ channel.mask()
+					} else if mask == channel.mask() {
+						Channel::ALL_MASK
+					} else {
+						channel.mask()
+					};
+					emulator.set_channel_mask(mask);
+					show_message(canvas.window_mut(), Ok(channels_message(mask)));
+				}
+				Event::KeyDown { keycode: Some(keycode), repeat: false, .. } if (Keycode::Num0 as i32..=Keycode::Num9 as i32).contains(&(keycode as i32)) => {
+					slots.select((keycode as i32 - Keycode::Num0 as i32) as u8);
+					show_message(canvas.window_mut(), Ok(format!("Slot {}", slots.slot())));
+				}
+				_ => {}
+			}
+		}
+
+		if let (true, Some(watcher)) = (reload, watcher.as_mut()) {
+			let result = watcher.reload(emulator)
+				.map(|()| format!("Reloaded {}", watcher.path().display()))
+				.map_err(|err| format!("{}, still running the old one", err));
+			if result.is_ok() {
+				// A new ROM: its own save states, and nothing to rewind to.
+				let slot = slots.slot();
+				slots = StateSlots::new(&options.state_dir, emulator.rom_hash());
+				slots.select(slot);
+				if let Some(rewind) = rewind.as_mut() {
+					rewind.clear();
+				}
+				pacer.restart();
+			}
+			show_message(canvas.window_mut(), result);
+		}
+
+		let action = pause.next_frame();
+		if action == FrameAction::Hold {
+			// The last frame stays on screen.
+			canvas.clear();
+			canvas.copy(&texture, None, None)?;
+			canvas.present();
+			thread::sleep(PAUSED_POLL);
+			continue;
+		}
+
+		// Sample the keyboard once per frame, before the frame runs, so the game sees a single state per frame.
+		let keyboard = event_pump.keyboard_state();
+		let rewinding = rewind.is_some() && !movie.is_active() && keyboard.is_scancode_pressed(Scancode::Backspace);
+		if rewinding {
+			// Show the states backwards, one per frame. The controller is ignored, the states have their own.
+			// At the oldest state it just stays there, and the emulation resumes from wherever Backspace is released.
+			rewind.as_mut().unwrap().rewind(emulator);
+		} else {
+			let buttons = keymap.buttons(|key| Scancode::from_name(key).is_some_and(|scancode| keyboard.is_scancode_pressed(scancode)));
+			emulator.set_controllers(movie.input(buttons));
+			if options.zapper {
+				// The window is the screen, scaled.
+				let mouse = event_pump.mouse_state();
+				let x = mouse.x().max(0) as u32 / options.scale;
+				let y = mouse.y().max(0) as u32 / options.scale + first_line as u32;
+				emulator.set_zapper(x as u16, y as u16, mouse.left());
+			}
+			emulator.run_frame();
+			if let Some(rewind) = rewind.as_mut() {
+				rewind.frame(emulator);
+			}
+		}
+
+		emulator.framebuffer().write_rgb24(&mut rgb);
+		if let Some(reference) = &reference {
+			let diff = FrameDiff::new(emulator.framebuffer(), reference, options.crop_overscan);
+			diff.write_heatmap(&mut rgb);
+			if differing != Some(diff.count()) {
+				differing = Some(diff.count());
+				show_message(canvas.window_mut(), Ok(format!("{} pixels differ from the reference", diff.count())));
+			}
+		}
+		let visible = &rgb[first_line * WIDTH * 3..(first_line + visible_lines) * WIDTH * 3];
+		texture.update(None, visible, WIDTH * 3).map_err(|err| err.to_string())?;
+		canvas.clear();
+		canvas.copy(&texture, None, None)?;
+		canvas.present();
+
+		frames += 1;
+		if options.frames == Some(frames) {
+			break;
+		}
+
+		if action == FrameAction::Step {
+			show_message(canvas.window_mut(), Ok(format!("Paused at frame {}", emulator.frame())));
+		} else {
+			pacer.wait_for_next_frame();
+		}
+	}
+
+	info!("Window closed after {} frames", frames);
+	movie.finish()
+}
+
+/// Write the screen as a PNG, not scaled (and without the overscan lines, like the window, with --crop-overscan).
+/// Returns its path.
+fn screenshot(emulator: &Emulator, options: &Options) -> Result<PathBuf, String> {
+	let name = match &options.program {
+		Program::Rom(rom) => rom.file_stem().map_or("screenshot".to_string(), |stem| stem.to_string_lossy().into_owned()),
+		Program::Demo(_) | Program::Batch(_) => "screenshot".to_string(),
+	};
+	let path = png::numbered_path(Path::new("."), &name);
+	std::fs::write(&path, emulator.framebuffer().to_png(options.crop_overscan)).map_err(|err| format!("{}: {}", path.display(), err))?;
+	Ok(path)
+}
+
+/// There is no text rendering, so messages go to the window title (and to the log). Errors are messages too.
+/// Like `Channels: pulse1 triangle (muted: pulse2 noise dmc)`.
+fn channels_message(mask: u8) -> String {
+	let names = |heard: bool| Channel::ALL.iter().filter(|channel| (mask & channel.mask() != 0) == heard).map(|channel| channel.name()).collect::<Vec<_>>().join(" ");
+	match mask {
+		Channel::ALL_MASK => "Channels: all".to_string(),
+		0 => "Channels: all muted".to_string(),
+		_ => format!("Channels: {} (muted: {})", names(true), names(false)),
+	}
+}
+
+fn show_message(window: &mut sdl2::video::Window, message: Result<String, String>) {
+	let message = match message {
+		Ok(message) => {
+			info!("{}", message);
+			message
+		}
+		Err(message) => {
+			error!("{}", message);
+			message
+		}
+	};
+	// Only fails if the title has a nul byte.
+	let _ = window.set_title(&format!("NES - {}", message));
+}
+
+/// Open a window with the easy6502 display, and run `frame` once per frame (it returns false when the program ended),
+/// until the program ends, the window is closed, Escape is pressed, or `--frames` frames ran. The keys go to $FF.
+pub fn run_easy6502(cpu: &mut CPU<Easy6502Bus>, options: &Options, mut frame: impl FnMut(&mut CPU<Easy6502Bus>) -> bool) -> Result<(), String> {
+	// The same width as the NES window.
+	let pixel_size = (WIDTH / DISPLAY_SIZE) as u32 * options.scale;
+
+	let sdl = sdl2::init()?;
+	let video = sdl.video()?;
+	let window = video
+		.window("easy6502", DISPLAY_SIZE as u32 * pixel_size, DISPLAY_SIZE as u32 * pixel_size)
+		.position_centered()
+		.build()
+		.map_err(|err| err.to_string())?;
+	let mut canvas = window.into_canvas().build().map_err(|err| err.to_string())?;
+	let texture_creator = canvas.texture_creator();
+	let mut texture = texture_creator
+		.create_texture_streaming(PixelFormatEnum::RGB24, DISPLAY_SIZE as u32, DISPLAY_SIZE as u32)
+		.map_err(|err| err.to_string())?;
+	let mut event_pump = sdl.event_pump()?;
+
+	let mut rgb = vec![0; DISPLAY_SIZE * DISPLAY_SIZE * 3];
+	let mut frames = 0;
+	let mut pacer = FramePacer::new(Region::default().frame_nanos());
+	pacer.set_speed(options.speed);
+
+	'running: loop {
+		for event in event_pump.poll_iter() {
+			match event {
+				Event::Quit { .. } | Event::KeyDown { keycode: Some(Keycode::Escape), .. } => break 'running,
+				// Keycodes of the printable keys are their ASCII codes.
+				Event::KeyDown { keycode: Some(keycode), repeat: false, .. } if (0x21..0x7F).contains(&(keycode as i32)) => {
+					cpu.bus_mut().set_key(keycode as i32 as u8);
+				}
+				_ => {}
+			}
+		}
+
+		let running = frame(cpu);
+
+		cpu.bus().write_rgb24(&mut rgb);
+		texture.update(None, &rgb, DISPLAY_SIZE * 3).map_err(|err| err.to_string())?;
+		canvas.clear();
+		canvas.copy(&texture, None, None)?;
+		canvas.present();
+
+		frames += 1;
+		if !running || options.frames == Some(frames) {
+			break;
+		}
+		pacer.wait_for_next_frame();
+	}
+
+	info!("Window closed after {} frames", frames);
+	Ok(())
+}
@@ -0,0 +1,66 @@
+// Serde for the big byte arrays of the state (RAM, VRAM, CHR, the framebuffer...), with `#[serde(with = ...)]`.
+//
+// Binary formats (bincode, CBOR) get them as bytes, through serde_bytes. Text formats (JSON) get a base64 string,
+// instead of thousands of numbers, one per line when pretty printed.
+
+use serde::de::Error;
+use serde::{Deserialize, Deserializer, Serializer};
+use serde_bytes::ByteBuf;
+
+use crate::base64;
+
+/// The types of the byte arrays in the state.
+pub trait Bytes: Sized {
+	fn as_bytes(&self) -> &[u8];
+	/// None when the length is wrong.
+	fn from_bytes(bytes: Vec<u8>) -> Option<Self>;
+}
+
+impl Bytes for Vec<u8> {
+	fn as_bytes(&self) -> &[u8] {
+		self
+	}
+
+	fn from_bytes(bytes: Vec<u8>) -> Option<Self> {
+		Some(bytes)
+	}
+}
+
+impl<const N: usize> Bytes for [u8; N] {
+	fn as_bytes(&self) -> &[u8] {
+		self
+	}
+
+	fn from_bytes(bytes: Vec<u8>) -> Option<Self> {
+		bytes.try_into().ok()
+	}
+}
+
+impl<const N: usize> Bytes for Box<[u8; N]> {
+	fn as_bytes(&self) -> &[u8] {
+		&self[..]
+	}
+
+	fn from_bytes(bytes: Vec<u8>) -> Option<Self> {
+		bytes.into_boxed_slice().try_into().ok()
+	}
+}
+
+pub fn serialize<T: Bytes, S: Serializer>(bytes: &T, serializer: S) -> Result<S::Ok, S::Error> {
+	if serializer.is_human_readable() {
+		serializer.serialize_str(&base64::encode(bytes.as_bytes()))
+	} else {
+		serializer.serialize_bytes(bytes.as_bytes())
+	}
+}
+
+pub fn deserialize<'de, T: Bytes, D: Deserializer<'de>>(deserializer: D) -> Result<T, D::Error> {
+	let bytes = if deserializer.is_human_readable() {
+		let text = String::deserialize(deserializer)?;
+		base64::decode(&text).ok_or_else(|| D::Error::custom("invalid base64"))?
+	} else {
+		ByteBuf::deserialize(deserializer)?.into_vec()
+	};
+	let length = bytes.len();
+	T::from_bytes(bytes).ok_or_else(|| D::Error::invalid_length(length, &"the length of the array"))
+}
@@ -0,0 +1,159 @@
+// Save state files, 10 slots per game.
+//
+// Files are named after the ROM hash and the slot, like `states/1A2B3C4D.ss0`, so every game has its own slots.
+// The hash is also inside the file, so a state that was renamed or copied is still never loaded into another game.
+
+use std::fs;
+use std::path::{Path, PathBuf};
+
+use crate::emulator::Emulator;
+
+pub const SLOTS: u8 = 10;
+/// Used when --state-dir is not set.
+pub const DEFAULT_STATE_DIR: &str = "states";
+
+pub struct StateSlots {
+	dir: PathBuf,
+	rom_hash: u32,
+	slot: u8,
+}
+
+impl StateSlots {
+	pub fn new(dir: &Path, rom_hash: u32) -> Self {
+		StateSlots { dir: dir.to_path_buf(), rom_hash, slot: 0 }
+	}
+
+	pub fn slot(&self) -> u8 {
+		self.slot
+	}
+
+	/// Slots are 0-9.
+	pub fn select(&mut self, slot: u8) {
+		assert!(slot < SLOTS, "There are only {} slots, got slot {}", SLOTS, slot);
+		self.slot = slot;
+	}
+
+	pub fn path(&self, slot: u8) -> PathBuf {
+		self.dir.join(format!("{:08X}.ss{}", self.rom_hash, slot))
+	}
+
+	/// Save to the current slot. Writes a temporary file and renames it, so a failed save (disk full, for example)
+	/// doesn't destroy the state that was in the slot.
+	pub fn save(&self, emulator: &Emulator) -> Result<PathBuf, String> {
+		let path = self.path(self.slot);
+		let temp_path = path.with_extension("tmp");
+		fs::create_dir_all(&self.dir).map_err(|err| format!("Can't create {}: {}", self.dir.display(), err))?;
+		fs::write(&temp_path, emulator.save_state()).map_err(|err| format!("Can't write {}: {}", temp_path.display(), err))?;
+		fs::rename(&temp_path, &path).map_err(|err| format!("Can't write {}: {}", path.display(), err))?;
+		Ok(path)
+	}
+
+	/// Load the current slot.
+	pub fn load(&self, emulator: &mut Emulator) -> Result<PathBuf, String> {
+		let path = self.path(self.slot);
+		if !path.exists() {
+			return Err(format!("Slot {} is empty", self.slot));
+		}
+		let state = fs::read(&path).map_err(|err| format!("Can't read {}: {}", path.display(), err))?;
+		emulator.load_state(&state).map_err(|err| format!("Can't load {}: {}", path.display(), err))?;
+		Ok(path)
+	}
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+	use crate::cartridge::{test_rom, Cartridge};
+	use crate::cpu::cpu::CpuState;
+
+	/// Draws, plays a sound, and keeps changing the RAM, so every part of the state matters.
+	fn busy_emulator() -> Emulator {
+		/*
+		LDA #$08
+		STA $2001 	; Enable background rendering
+		LDA #$0F
+		STA $4015 	; Enable the sound channels
+		LDA #$BF
+		STA $400C 	; Noise, constant volume 15
+		STA $400F
+		loop:
+		LDA #$3F
+		STA $2006
+		LDA #$00
+		STA $2006 	; PPU address = $3F00
+		INX
+		STX $2007 	; Background color = X
+		INC $10,X
+		STX $400E 	; Noise period
+		JMP loop
+		*/
+		let program = "A9 08 8D 01 20 A9 0F 8D 15 40 A9 BF 8D 0C 40 8D 0F 40 \
+			A9 3F 8D 06 20 A9 00 8D 06 20 E8 8E 07 20 F6 10 8E 0E 40 4C 12 80";
+		Emulator::new(Cartridge::from_ines(&test_rom::nrom(program)).unwrap())
+	}
+
+	fn run(emulator: &mut Emulator, frames: usize) -> Vec<(CpuState, Vec<u8>)> {
+		(0..frames).map(|_| {
+			let pixels = emulator.run_frame().pixels().to_vec();
+			(emulator.cpu_state(), pixels)
+		}).collect()
+	}
+
+	fn temp_dir(name: &str) -> PathBuf {
+		let dir = std::env::temp_dir().join(format!("nes-{}-{}", name, std::process::id()));
+		let _ = fs::remove_dir_all(&dir);
+		dir
+	}
+
+	#[test]
+	fn round_trip_test() {
+		let dir = temp_dir("state-round-trip");
+		let mut emulator = busy_emulator();
+		let mut slots = StateSlots::new(&dir, emulator.rom_hash());
+		slots.select(3);
+
+		run(&mut emulator, 5);
+		let path = slots.save(&emulator).unwrap();
+		assert!(path.starts_with(&dir));
+		let expected = run(&mut emulator, 5);
+
+		// A freshly powered on console resumes exactly where the state was saved.
+		let mut resumed = busy_emulator();
+		slots.load(&mut resumed).unwrap();
+		assert!(run(&mut resumed, 5) == expected);
+		assert_eq!(resumed.cycles(), emulator.cycles());
+
+		fs::remove_dir_all(&dir).unwrap();
+	}
+
+	#[test]
+	fn load_errors_test() {
+		let dir = temp_dir("state-errors");
+		let mut emulator = busy_emulator();
+		let mut slots = StateSlots::new(&dir, emulator.rom_hash());
+		assert!(slots.load(&mut emulator).unwrap_err().contains("empty"));
+
+		slots.save(&emulator).unwrap();
+		run(&mut emulator, 1);
+		let before = emulator.save_state();
+
+		// Another game, with a state file renamed to this game's name.
+		let mut other = Emulator::new(Cartridge::from_ines(&test_rom::nrom("4C 00 80")).unwrap());
+		let other_slots = StateSlots::new(&dir, other.rom_hash());
+		other_slots.save(&other).unwrap();
+		fs::rename(other_slots.path(0), slots.path(1)).unwrap();
+		slots.select(1);
+		assert!(slots.load(&mut emulator).unwrap_err().contains("another ROM"));
+		assert!(emulator.save_state() == before);
+
+		// Corrupt file: the state is cut in the middle.
+		slots.select(0);
+		let state = fs::read(slots.path(0)).unwrap();
+		fs::write(slots.path(0), &state[..state.len() / 2]).unwrap();
+		assert!(slots.load(&mut emulator).unwrap_err().contains("corrupt"));
+		assert!(emulator.save_state() == before);
+		assert!(other.load_state(&before).is_err());
+
+		fs::remove_dir_all(&dir).unwrap();
+	}
+}
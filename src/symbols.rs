@@ -0,0 +1,276 @@
+// Symbols: names for addresses, from the label files of debuggers and assemblers. The disassembler, the trace and
+// the debugger show them instead of the addresses, and the debugger accepts them instead of addresses.
+//
+// | Format | Extension | Lines |
+// |---|---|---|
+// | FCEUX | `.nl` | `$C123#Reset#comment`, or `$0300/10#Buffer#` for 16 bytes (the size is hex) |
+// | cc65 debug info (`ld65 --dbgfile`) | `.dbg` | `sym id=0,name="Reset",addrsize=absolute,size=1,...,val=0xC123,type=lab` |
+// | VICE labels (`ld65 -Ln`) | `.sym`, `.lbl` | `al 00C123 .Reset` |
+//
+// A symbol with a size (arrays in .nl files, `size=` in .dbg files) covers the whole range: an address inside it is
+// shown as `Buffer+3`. Banks aren't known, so with more than one name for an address (FCEUX has a .nl file for each
+// bank), the last one loaded wins.
+
+use std::collections::{BTreeMap, HashMap};
+use std::fmt;
+use std::fs;
+use std::path::Path;
+
+#[derive(Clone, PartialEq, Eq, Debug)]
+struct Symbol {
+	name: String,
+	/// In bytes, at least 1.
+	size: u16,
+}
+
+/// Names of addresses, and the other way around.
+#[derive(Clone, Default, Debug)]
+pub struct SymbolTable {
+	symbols: BTreeMap<u16, Symbol>,
+	addresses: HashMap<String, u16>,
+	/// The size of the largest symbol, so `lookup` knows how far back to search.
+	max_size: u16,
+}
+
+impl SymbolTable {
+	pub fn new() -> Self {
+		Self::default()
+	}
+
+	/// Load a file, in the format of its extension (see the table at the top).
+	pub fn load(path: &Path) -> Result<Self, String> {
+		match path.extension().and_then(|extension| extension.to_str()).map(|extension| extension.to_ascii_lowercase()).as_deref() {
+			Some("nl") => Self::load_nl(path),
+			Some("dbg") => Self::load_dbg(path),
+			Some("sym" | "lbl") => Self::load_vice(path),
+			_ => Err(format!("Unknown symbol file {}: expected .nl, .dbg, .sym or .lbl", path.display())),
+		}
+	}
+
+	/// FCEUX .nl file.
+	pub fn load_nl(path: &Path) -> Result<Self, String> {
+		Self::parse_nl(&read(path)?).map_err(|err| format!("{}: {}", path.display(), err))
+	}
+
+	/// cc65 debug info file.
+	pub fn load_dbg(path: &Path) -> Result<Self, String> {
+		Self::parse_dbg(&read(path)?).map_err(|err| format!("{}: {}", path.display(), err))
+	}
+
+	/// VICE label file.
+	pub fn load_vice(path: &Path) -> Result<Self, String> {
+		Self::parse_vice(&read(path)?).map_err(|err| format!("{}: {}", path.display(), err))
+	}
+
+	/// `$C123#Reset#comment`. Lines without a name (just a comment) are skipped.
+	pub fn parse_nl(text: &str) -> Result<Self, String> {
+		let mut table = SymbolTable::new();
+		for (number, line) in numbered_lines(text) {
+			let mut fields = line.splitn(3, '#');
+			let addr = fields.next().unwrap_or_default();
+			let name = fields.next().ok_or_else(|| format!("Line {}: expected $ADDRESS#NAME#, got '{}'", number, line))?;
+			let (addr, size) = match addr.split_once('/') {
+				Some((addr, size)) => (addr, parse_hex(size).ok_or_else(|| format!("Line {}: bad size '{}'", number, size))?),
+				None => (addr, 1),
+			};
+			let addr = addr.strip_prefix('$').and_then(parse_hex).ok_or_else(|| format!("Line {}: bad address '{}'", number, addr))?;
+			if !name.is_empty() {
+				table.insert(addr, name, size);
+			}
+		}
+		Ok(table)
+	}
+
+	/// The `sym` lines: `sym id=0,name="Reset",addrsize=absolute,size=1,scope=0,def=0,val=0xC123,seg=0,type=lab`.
+	/// Imports (without a `val`) and values that aren't addresses are skipped.
+	pub fn parse_dbg(text: &str) -> Result<Self, String> {
+		let mut table = SymbolTable::new();
+		for (number, line) in numbered_lines(text) {
+			let Some(attributes) = line.strip_prefix("sym") else {
+				continue;
+			};
+			let mut name = None;
+			let mut addr = None;
+			let mut size = 1;
+			for attribute in attributes.trim().split(',') {
+				match attribute.split_once('=') {
+					Some(("name", value)) => name = Some(value.trim_matches('"')),
+					Some(("val", value)) => addr = value.strip_prefix("0x").and_then(parse_hex),
+					Some(("size", value)) => size = value.parse().map_err(|_| format!("Line {}: bad size '{}'", number, value))?,
+					_ => {}
+				}
+			}
+			let name = name.ok_or_else(|| format!("Line {}: sym without a name", number))?;
+			if let Some(addr) = addr {
+				table.insert(addr, name, size);
+			}
+		}
+		Ok(table)
+	}
+
+	/// `al 00C123 .Reset`. The dot is optional.
+	pub fn parse_vice(text: &str) -> Result<Self, String> {
+		let mut table = SymbolTable::new();
+		for (number, line) in numbered_lines(text) {
+			let [kind, addr, name] = line.split_whitespace().collect::<Vec<_>>()[..] else {
+				return Err(format!("Line {}: expected 'al ADDRESS .NAME', got '{}'", number, line));
+			};
+			let addr = parse_hex(addr).filter(|_| kind == "al").ok_or_else(|| format!("Line {}: expected 'al ADDRESS .NAME', got '{}'", number, line))?;
+			table.insert(addr, name.strip_prefix('.').unwrap_or(name), 1);
+		}
+		Ok(table)
+	}
+
+	/// Name `size` bytes from `addr`. Sizes under 1 are 1.
+	pub fn insert(&mut self, addr: u16, name: &str, size: u16) {
+		let size = size.max(1);
+		if let Some(old) = self.symbols.insert(addr, Symbol { name: name.to_string(), size }) {
+			self.addresses.remove(&old.name);
+		}
+		self.addresses.insert(name.to_string(), addr);
+		self.max_size = self.max_size.max(size);
+	}
+
+	/// Add all the symbols of `other`. Its names win.
+	pub fn extend(&mut self, other: SymbolTable) {
+		for (addr, symbol) in other.symbols {
+			self.insert(addr, &symbol.name, symbol.size);
+		}
+	}
+
+	pub fn is_empty(&self) -> bool {
+		self.symbols.is_empty()
+	}
+
+	pub fn len(&self) -> usize {
+		self.symbols.len()
+	}
+
+	/// The address of a name.
+	pub fn resolve(&self, name: &str) -> Option<u16> {
+		self.addresses.get(name).copied()
+	}
+
+	/// The symbol at `addr`, or the one with a range around it: the name, and the offset of `addr` from its start.
+	pub fn lookup(&self, addr: u16) -> Option<(&str, u16)> {
+		let first = addr.saturating_sub(self.max_size.saturating_sub(1));
+		self.symbols.range(first..=addr).rev()
+			.find(|(&start, symbol)| (addr - start) < symbol.size)
+			.map(|(&start, symbol)| (symbol.name.as_str(), addr - start))
+	}
+
+	/// Write the name of `addr` (`Reset`, or `Buffer+3` inside a range). Returns false, and writes nothing, if it
+	/// has no name.
+	pub fn write_name(&self, out: &mut impl fmt::Write, addr: u16) -> Result<bool, fmt::Error> {
+		match self.lookup(addr) {
+			Some((name, 0)) => write!(out, "{}", name)?,
+			Some((name, offset)) => write!(out, "{}+{}", name, offset)?,
+			None => return Ok(false),
+		}
+		Ok(true)
+	}
+
+	/// The name of `addr`, like `write_name`.
+	pub fn name(&self, addr: u16) -> Option<String> {
+		let mut name = String::new();
+		self.write_name(&mut name, addr).expect("Writing to a String can't fail").then_some(name)
+	}
+}
+
+fn read(path: &Path) -> Result<String, String> {
+	let bytes = fs::read(path).map_err(|err| format!("Can't read symbol file {}: {}", path.display(), err))?;
+	// Label files come from all sorts of tools on all sorts of systems, so don't insist on UTF-8.
+	Ok(String::from_utf8_lossy(&bytes).into_owned())
+}
+
+/// The lines that aren't empty, trimmed, with their line numbers (from 1).
+fn numbered_lines(text: &str) -> impl Iterator<Item = (usize, &str)> {
+	text.lines().map(str::trim).enumerate().map(|(i, line)| (i + 1, line)).filter(|(_, line)| !line.is_empty())
+}
+
+/// Hex, up to $FFFF. The VICE files have 6 digits, like `00C123`.
+fn parse_hex(value: &str) -> Option<u16> {
+	u32::from_str_radix(value, 16).ok().and_then(|value| u16::try_from(value).ok())
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+	use crate::cpu::disassembler::disassemble_with_symbols;
+
+	const NL: &str = "\
+$C000#Reset#Power on and reset
+$C010##Just a comment
+$0300/10#Buffer#
+$2002#PPUSTATUS#
+";
+
+	#[test]
+	fn parse_nl_test() {
+		let symbols = SymbolTable::parse_nl(NL).unwrap();
+		assert_eq!(symbols.len(), 3);
+		assert_eq!(symbols.resolve("Reset"), Some(0xC000));
+		assert_eq!(symbols.name(0xC000).as_deref(), Some("Reset"));
+		assert_eq!(symbols.name(0xC010), None);
+
+		// Inside the range of the array, and just after it.
+		assert_eq!(symbols.name(0x0300).as_deref(), Some("Buffer"));
+		assert_eq!(symbols.name(0x030F).as_deref(), Some("Buffer+15"));
+		assert_eq!(symbols.name(0x0310), None);
+
+		assert!(SymbolTable::parse_nl("C000#Reset#").is_err());
+		assert!(SymbolTable::parse_nl("$C000").is_err());
+	}
+
+	#[test]
+	fn disassemble_symbols_test() {
+		let symbols = SymbolTable::parse_nl(NL).unwrap();
+		// JSR Reset, LDA PPUSTATUS, STA Buffer+2,X, LDA $0400, BNE Reset
+		let program = [0x20, 0x00, 0xC0, 0xAD, 0x02, 0x20, 0x9D, 0x02, 0x03, 0xAD, 0x00, 0x04, 0xD0, 0xE6];
+		let peek = |addr: u16| program.get(addr.wrapping_sub(0xC00C) as usize).copied().unwrap_or(0);
+
+		let mut addr = 0xC00C;
+		let mut lines = vec![];
+		for _ in 0..5 {
+			let disassembly = disassemble_with_symbols(addr, peek, Some(&symbols));
+			addr = disassembly.next_addr();
+			lines.push(disassembly.text);
+		}
+		assert_eq!(lines, ["JSR Reset", "LDA PPUSTATUS", "STA Buffer+2,X", "LDA $0400", "BNE Reset"]);
+	}
+
+	#[test]
+	fn parse_cc65_test() {
+		let dbg = "\
+version\tmajor=2,minor=0
+file\tid=0,name=\"game.s\",size=100,mtime=0x00000000,mod=0
+sym\tid=0,name=\"Reset\",addrsize=absolute,scope=0,def=1,ref=5,val=0xC000,seg=0,type=lab
+sym\tid=1,name=\"buffer\",addrsize=absolute,size=16,scope=0,def=2,val=0x300,seg=1,type=lab
+sym\tid=2,name=\"ppu_init\",addrsize=absolute,scope=0,def=3,type=imp
+";
+		let symbols = SymbolTable::parse_dbg(dbg).unwrap();
+		assert_eq!(symbols.len(), 2);
+		assert_eq!(symbols.resolve("Reset"), Some(0xC000));
+		assert_eq!(symbols.name(0x0305).as_deref(), Some("buffer+5"));
+
+		let symbols = SymbolTable::parse_vice("al 00C000 .Reset\nal 000300 .buffer\n").unwrap();
+		assert_eq!(symbols.resolve("buffer"), Some(0x0300));
+		assert_eq!(symbols.name(0xC000).as_deref(), Some("Reset"));
+		assert!(SymbolTable::parse_vice("Reset = $C000").is_err());
+	}
+
+	#[test]
+	fn insert_test() {
+		// A new name for an address replaces the old one, in both directions.
+		let mut symbols = SymbolTable::parse_nl(NL).unwrap();
+		symbols.extend(SymbolTable::parse_vice("al C000 .Start").unwrap());
+		assert_eq!(symbols.name(0xC000).as_deref(), Some("Start"));
+		assert_eq!(symbols.resolve("Start"), Some(0xC000));
+		assert_eq!(symbols.resolve("Reset"), None);
+
+		// The label at the address wins over a range around it.
+		symbols.insert(0x0304, "Middle", 1);
+		assert_eq!(symbols.name(0x0304).as_deref(), Some("Middle"));
+		assert_eq!(symbols.name(0x0305).as_deref(), Some("Buffer+5"));
+	}
+}
@@ -0,0 +1,66 @@
+// Terminal frontend for the easy6502 machine, when there is no window (built without `--features sdl`). The display is
+// drawn with colored characters, and the keys are read from stdin.
+
+use std::io::{self, Read, Write};
+use std::sync::mpsc::{self, Receiver};
+use std::thread;
+
+use log::info;
+
+use crate::cli::Options;
+use rust_nes_emulator::easy6502::Easy6502Bus;
+use rust_nes_emulator::frame_pacer::FramePacer;
+use rust_nes_emulator::{Region, CPU};
+
+/// Draw the display, and run `frame` once per frame (it returns false when the program ended), until the program ends,
+/// or `--frames` frames ran. The terminal sends the keys when Enter is pressed: type `w` and Enter to go up in snake.
+pub fn run_easy6502(cpu: &mut CPU<Easy6502Bus>, options: &Options, mut frame: impl FnMut(&mut CPU<Easy6502Bus>) -> bool) -> Result<(), String> {
+	let keys = read_keys();
+	let mut pacer = FramePacer::new(Region::default().frame_nanos());
+	pacer.set_speed(options.speed);
+	let mut stdout = io::stdout();
+	let mut shown = None;
+	let mut frames = 0;
+
+	// Clear the screen. The display is drawn over itself from now on.
+	print!("\x1b[2J");
+	loop {
+		// Only the last one counts, like in easy6502.
+		while let Ok(key) = keys.try_recv() {
+			if key.is_ascii_graphic() {
+				cpu.bus_mut().set_key(key);
+			}
+		}
+
+		let running = frame(cpu);
+		// Redraw only when something changed, the terminal is slow.
+		let display = cpu.bus().display();
+		if shown != Some(display) {
+			write!(stdout, "{}", cpu.bus().render_ansi()).and_then(|_| stdout.flush()).map_err(|err| format!("Can't draw: {}", err))?;
+			shown = Some(display);
+		}
+
+		frames += 1;
+		if !running || options.frames == Some(frames) {
+			break;
+		}
+		pacer.wait_for_next_frame();
+	}
+
+	info!("Stopped after {} frames", frames);
+	Ok(())
+}
+
+/// The bytes of stdin, from a thread, so reading them doesn't wait.
+fn read_keys() -> Receiver<u8> {
+	let (sender, receiver) = mpsc::channel();
+	thread::spawn(move || {
+		for byte in io::stdin().lock().bytes() {
+			let Ok(byte) = byte else { break };
+			if sender.send(byte).is_err() {
+				break;
+			}
+		}
+	});
+	receiver
+}
@@ -0,0 +1,309 @@
+// Execution trace: a line for every instruction, in the format of nestest.log, so traces can be diffed with it
+// (and with other emulators):
+//
+// C000  4C F5 C5  JMP $C5F5                       A:00 X:00 Y:00 P:24 SP:FD PPU:  0, 21 CYC:7
+//
+// The registers are before the instruction executes. P stays hex (not `nv-bdIzc`), like in nestest.log. PPU is the
+// scanline and dot, and only the NES has it (not the demos on flat memory). CYC is the CPU cycles since power on.
+//
+// Traces of long runs are huge, so there are filters:
+//
+// | Filter | Description |
+// |---|---|
+// | PC range | Only instructions inside the range, like the code of a single routine |
+// | Start address | Nothing until the instruction at the address runs for the first time |
+// | Last N | Keep only the last N lines in memory, and write them at the end (end of the run, jam, or crash) |
+//
+// With a symbol table (`with_symbols`), the operands show the names of the addresses, like `JSR Reset`. That's easier
+// to read, but it no longer diffs with nestest.log.
+//
+// With `with_penalties`, an instruction that took oops cycles (a page crossed by an indexed read, a branch taken) has
+// them after CYC, like `CYC:9 +1`. The line is then written after the instruction ran (`executed`), not before.
+//
+// A header (`write_header`) goes before the lines, every line of it starting with `; `. --trace writes the banks of
+// the mapper there (see `Cartridge::debug_state`), so the addresses of the lines can be found in the ROM. Drop the
+// `;` lines before diffing with nestest.log.
+//
+// Tracing runs before every instruction, so it must be fast: a line is formatted into a buffer that is reused,
+// and the ring of the last lines reuses the buffers of the lines it drops. Nothing is allocated per instruction.
+
+use std::collections::VecDeque;
+use std::fmt::Write as _;
+use std::io::{self, BufWriter, Write};
+
+use crate::cpu::cpu::CpuState;
+use crate::cpu::disassembler::{instruction_length, write_text};
+use crate::symbols::SymbolTable;
+
+#[derive(Clone, Default, PartialEq, Eq, Debug)]
+pub struct TraceFilter {
+	/// Only trace instructions with PC in `start..=end`.
+	pub pc_range: Option<(u16, u16)>,
+	/// Start tracing when the instruction at this address runs for the first time.
+	pub start_at: Option<u16>,
+	/// Keep only the last N lines, and write them when the trace ends.
+	pub last: Option<usize>,
+}
+
+pub struct Tracer {
+	out: BufWriter<Box<dyn Write>>,
+	filter: TraceFilter,
+	started: bool,
+	/// Formatting buffer, reused for every line.
+	line: String,
+	/// The last lines, oldest first, when the filter has `last`.
+	ring: VecDeque<String>,
+	symbols: Option<SymbolTable>,
+	penalties: bool,
+	/// With `penalties`: `line` is waiting for `executed`.
+	pending: bool,
+}
+
+impl Tracer {
+	pub fn new(out: Box<dyn Write>, filter: TraceFilter) -> Self {
+		Tracer {
+			out: BufWriter::new(out),
+			started: filter.start_at.is_none(),
+			ring: VecDeque::with_capacity(filter.last.unwrap_or(0)),
+			filter,
+			line: String::with_capacity(128),
+			symbols: None,
+			penalties: false,
+			pending: false,
+		}
+	}
+
+	/// Show the names of the addresses in the operands.
+	pub fn with_symbols(mut self, symbols: SymbolTable) -> Self {
+		self.symbols = Some(symbols);
+		self
+	}
+
+	/// Add the oops cycles of every instruction to its line, see the top of the file.
+	pub fn with_penalties(mut self) -> Self {
+		self.penalties = true;
+		self
+	}
+
+	/// Write `text` before the lines, see the top of the file.
+	pub fn write_header(&mut self, text: &str) -> io::Result<()> {
+		for line in text.lines() {
+			writeln!(self.out, "; {}", line)?;
+		}
+		Ok(())
+	}
+
+	/// Call before every instruction. `ppu` is the PPU position (scanline, dot), if there is a PPU.
+	/// `peek` reads memory without side effects, for the instruction bytes.
+	pub fn instruction(&mut self, state: &CpuState, ppu: Option<(u16, u16)>, peek: impl Fn(u16) -> u8) -> io::Result<()> {
+		// The last instruction didn't finish (or `executed` wasn't called): its line goes as it is.
+		if self.pending {
+			self.emit()?;
+		}
+		if !self.started {
+			if Some(state.pc) != self.filter.start_at {
+				return Ok(());
+			}
+			self.started = true;
+		}
+		if let Some((start, end)) = self.filter.pc_range {
+			if !(start..=end).contains(&state.pc) {
+				return Ok(());
+			}
+		}
+
+		self.line.clear();
+		format_line(&mut self.line, state, ppu, &peek, self.symbols.as_ref()).expect("Writing to a String can't fail");
+		if self.penalties {
+			self.pending = true;
+			return Ok(());
+		}
+		self.emit()
+	}
+
+	/// Call after every instruction, with its `CPU::penalty_cycles`. Only needed `with_penalties`.
+	pub fn executed(&mut self, penalty_cycles: u8) -> io::Result<()> {
+		if !self.pending {
+			return Ok(());
+		}
+		if penalty_cycles > 0 {
+			write!(self.line, " +{}", penalty_cycles).expect("Writing to a String can't fail");
+		}
+		self.emit()
+	}
+
+	/// Write `line`, or keep it in the ring.
+	fn emit(&mut self) -> io::Result<()> {
+		self.pending = false;
+		match self.filter.last {
+			Some(0) => {}
+			Some(last) => {
+				let mut line = if self.ring.len() == last { self.ring.pop_front().unwrap() } else { String::with_capacity(128) };
+				line.clear();
+				line.push_str(&self.line);
+				self.ring.push_back(line);
+			}
+			None => {
+				self.out.write_all(self.line.as_bytes())?;
+				self.out.write_all(b"\n")?;
+			}
+		}
+		Ok(())
+	}
+
+	/// Write the kept lines (with `last`), and flush. Also called when the tracer is dropped, so the file is complete
+	/// even when the emulator panics.
+	pub fn finish(&mut self) -> io::Result<()> {
+		if self.pending {
+			self.emit()?;
+		}
+		for line in self.ring.drain(..) {
+			self.out.write_all(line.as_bytes())?;
+			self.out.write_all(b"\n")?;
+		}
+		self.out.flush()
+	}
+}
+
+impl Drop for Tracer {
+	fn drop(&mut self) {
+		// Nowhere to report it. Most likely the same error already stopped the trace.
+		let _ = self.finish();
+	}
+}
+
+/// `C000  4C F5 C5  JMP $C5F5                       A:00 X:00 Y:00 P:24 SP:FD PPU:  0, 21 CYC:7`
+fn format_line(line: &mut String, state: &CpuState, ppu: Option<(u16, u16)>, peek: &impl Fn(u16) -> u8, symbols: Option<&SymbolTable>) -> std::fmt::Result {
+	write!(line, "{:04X}  ", state.pc)?;
+	let bytes_start = line.len();
+	for i in 0..instruction_length(peek(state.pc)) {
+		write!(line, "{:02X} ", peek(state.pc.wrapping_add(i as u16)))?;
+	}
+	pad(line, bytes_start + 10);
+
+	let text_start = line.len();
+	write_text(line, state.pc, peek, symbols)?;
+	pad(line, text_start + 32);
+
+	write!(line, "A:{:02X} X:{:02X} Y:{:02X} P:{:02X} SP:{:02X} ", state.a, state.x, state.y, state.p, state.s)?;
+	if let Some((scanline, dot)) = ppu {
+		write!(line, "PPU:{:>3},{:>3} ", scanline, dot)?;
+	}
+	write!(line, "CYC:{}", state.cycles)
+}
+
+fn pad(line: &mut String, length: usize) {
+	while line.len() < length {
+		line.push(' ');
+	}
+}
+
+#[cfg(test)]
+mod tests {
+	use std::fs;
+
+	use super::*;
+	use crate::cartridge::{test_rom, Cartridge};
+	use crate::emulator::Emulator;
+
+	/*
+	LDX #$00
+	loop:
+	INX
+	CPX #$03
+	BNE loop
+	end:
+	JMP end
+	*/
+	const COUNT_TO_3: &str = "A2 00 E8 E0 03 D0 FB 4C 07 80";
+
+	/// Run `program` for `instructions` instructions, tracing to a file, and return the lines of the file.
+	fn trace(name: &str, program: &str, instructions: usize, filter: TraceFilter) -> Vec<String> {
+		trace_with_symbols(name, program, instructions, filter, SymbolTable::new())
+	}
+
+	fn trace_with_symbols(name: &str, program: &str, instructions: usize, filter: TraceFilter, symbols: SymbolTable) -> Vec<String> {
+		let path = std::env::temp_dir().join(format!("nes-trace-{}-{}.log", name, std::process::id()));
+		let mut emulator = Emulator::new(Cartridge::from_ines(&test_rom::nrom(program)).unwrap());
+		emulator.set_trace(Tracer::new(Box::new(fs::File::create(&path).unwrap()), filter).with_symbols(symbols));
+		for _ in 0..instructions {
+			emulator.step_instruction();
+		}
+		// Dropping the emulator finishes the trace.
+		drop(emulator);
+
+		let text = fs::read_to_string(&path).unwrap();
+		fs::remove_file(&path).unwrap();
+		text.lines().map(String::from).collect()
+	}
+
+	#[test]
+	fn pc_range_test() {
+		// Only INX and CPX. The reset took the first 7 cycles, like in nestest.log.
+		let lines = trace("pc-range", COUNT_TO_3, 20, TraceFilter { pc_range: Some((0x8002, 0x8004)), ..Default::default() });
+		assert_eq!(lines, [
+			"8002  E8        INX                             A:00 X:00 Y:00 P:26 SP:FF PPU:  0, 27 CYC:9",
+			"8003  E0 03     CPX #$03                        A:00 X:01 Y:00 P:24 SP:FF PPU:  0, 33 CYC:11",
+			"8002  E8        INX                             A:00 X:01 Y:00 P:A4 SP:FF PPU:  0, 48 CYC:16",
+			"8003  E0 03     CPX #$03                        A:00 X:02 Y:00 P:24 SP:FF PPU:  0, 54 CYC:18",
+			"8002  E8        INX                             A:00 X:02 Y:00 P:A4 SP:FF PPU:  0, 69 CYC:23",
+			"8003  E0 03     CPX #$03                        A:00 X:03 Y:00 P:24 SP:FF PPU:  0, 75 CYC:25",
+		]);
+	}
+
+	#[test]
+	fn start_and_last_test() {
+		// Starts at the JMP, so only the end of the program is traced.
+		let lines = trace("start", COUNT_TO_3, 20, TraceFilter { start_at: Some(0x8007), ..Default::default() });
+		assert_eq!(lines.len(), 20 - 10);
+		assert!(lines.iter().all(|line| line.starts_with("8007  4C 07 80  JMP $8007")));
+
+		// The last 3 lines.
+		let lines = trace("last", COUNT_TO_3, 10, TraceFilter { last: Some(3), ..Default::default() });
+		let pcs: Vec<&str> = lines.iter().map(|line| &line[..4]).collect();
+		assert_eq!(pcs, ["8002", "8003", "8005"]);
+	}
+
+	#[test]
+	fn penalties_test() {
+		let path = std::env::temp_dir().join(format!("nes-trace-penalties-{}.log", std::process::id()));
+		let mut emulator = Emulator::new(Cartridge::from_ines(&test_rom::nrom(COUNT_TO_3)).unwrap());
+		let filter = TraceFilter { pc_range: Some((0x8005, 0x8007)), ..Default::default() };
+		emulator.set_trace(Tracer::new(Box::new(fs::File::create(&path).unwrap()), filter).with_penalties());
+		for _ in 0..12 {
+			emulator.step_instruction();
+		}
+		drop(emulator);
+		let text = fs::read_to_string(&path).unwrap();
+		fs::remove_file(&path).unwrap();
+
+		// The BNE is taken twice (to the same page), and then not. The JMP never has one.
+		let ends: Vec<&str> = text.lines().map(|line| &line[line.find("CYC").unwrap()..]).collect();
+		assert_eq!(ends, ["CYC:13 +1", "CYC:20 +1", "CYC:27", "CYC:29", "CYC:32"]);
+	}
+
+	#[test]
+	fn symbols_test() {
+		let symbols = SymbolTable::parse_nl("$8002#loop#\n$8007#end#").unwrap();
+		let lines = trace_with_symbols("symbols", COUNT_TO_3, 12, TraceFilter { pc_range: Some((0x8005, 0x8007)), ..Default::default() }, symbols);
+		assert!(lines[0].starts_with("8005  D0 FB     BNE loop                        A:00"), "{}", lines[0]);
+		assert!(lines.last().unwrap().starts_with("8007  4C 07 80  JMP end "));
+	}
+
+	#[test]
+	fn header_test() {
+		let path = std::env::temp_dir().join(format!("nes-trace-header-{}.log", std::process::id()));
+		let mut emulator = Emulator::new(Cartridge::from_ines(&test_rom::nrom(COUNT_TO_3)).unwrap());
+		let mut tracer = Tracer::new(Box::new(fs::File::create(&path).unwrap()), TraceFilter::default());
+		tracer.write_header(&emulator.bus().cartridge().debug_state().to_string()).unwrap();
+		emulator.set_trace(tracer);
+		emulator.step_instruction();
+		drop(emulator);
+		let text = fs::read_to_string(&path).unwrap();
+		fs::remove_file(&path).unwrap();
+
+		let lines: Vec<&str> = text.lines().collect();
+		assert_eq!(lines[..2], ["; Mapper 0", "; PRG: $8000 16KB bank 0, $C000 16KB bank 0"]);
+		assert!(lines[4].starts_with("8000  A2 00     LDX #$00"), "{}", lines[4]);
+	}
+}
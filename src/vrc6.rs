@@ -0,0 +1,533 @@
+// VRC6 (mappers 24 and 26): https://www.nesdev.org/wiki/VRC6
+// Konami's chip of Akumajou Densetsu (mapper 24), Madara and Esper Dream 2 (mapper 26). The cartridge maps PRG ROM
+// and CHR from the registers here, like for MMC3, see cartridge.rs. It also has an IRQ counter, and 3 more sound
+// channels, that the APU mixes with its own (`APU::set_expansion`).
+//
+// | Address | Register |
+// |---|---|
+// | $8000-$8003 | 16KB PRG bank at $8000 |
+// | $9000 / $9001 / $9002 | Pulse 1: mode, duty and volume / period low / enable, period high |
+// | $9003 | Audio control: halt (bit 0), periods / 16 (bit 1) or / 256 (bit 2) |
+// | $A000-$A002 | Pulse 2, like pulse 1 |
+// | $B000 / $B001 / $B002 | Sawtooth: accumulator rate / period low / enable, period high |
+// | $B003 | Mirroring (bits 2-3): vertical, horizontal, single screen lower, single screen upper. PRG RAM on (bit 7) |
+// | $C000-$C003 | 8KB PRG bank at $C000 |
+// | $D000-$D003 | CHR banks of 1KB at $0000-$0FFF |
+// | $E000-$E003 | CHR banks of 1KB at $1000-$1FFF |
+// | $F000 / $F001 / $F002 | IRQ latch / IRQ control / IRQ acknowledge |
+//
+// The last 8KB of PRG ROM are at $E000. Mapper 26 is the same chip, on a board with the A0 and A1 lines swapped: its
+// games write $x002 for what is $x001 on mapper 24, and the other way around. `write` swaps them back.
+//
+// Only the PPU banking mode 0 of $B003 (8 banks of 1KB) is there, the one the games use. PRG RAM is on until the
+// game writes $B003, and then with bit 7: open bus without it.
+//
+// The IRQ counter (https://www.nesdev.org/wiki/VRC_IRQ) counts up from the latch, and fires when it overflows from
+// $FF, which reloads it. It's clocked every CPU cycle in cycle mode (bit 2 of $F001), or every scanline in scanline
+// mode, which a prescaler counts on its own: 341 PPU dots, 3 every CPU cycle. So it doesn't need the PPU.
+//
+// The channels are clocked every CPU cycle, when their timer gets to 0. A pulse counts its duty step down from 15,
+// and outputs its volume while the step is at most its duty (bits 4-6), or always in mode 1 (bit 7). The sawtooth
+// adds its rate to an 8 bit accumulator every other step, and clears it at the 14th: 7 levels (the high 5 bits of
+// the accumulator), and then from 0 again.
+
+use crate::cartridge::IrqCounterInfo;
+use crate::mapper::Mapper;
+use crate::ppu::ppu::Mirroring;
+use crate::save_state::{SaveState, StateReader, StateWriter};
+
+const PRG_BANK_SIZE: usize = 8 * 1024;
+const CHR_BANK_SIZE: usize = 1024;
+/// The prescaler of the scanline mode, in PPU dots.
+const SCANLINE_DOTS: i16 = 341;
+/// A step of a channel is about as loud as a step of an APU pulse at full volume, see `APU::output`.
+const STEP_LEVEL: f32 = 95.88 / (8128.0 / 15.0 + 100.0) / 15.0;
+
+#[derive(Clone, Debug, Default)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+struct Pulse {
+	volume: u8,
+	duty: u8,
+	/// Mode 1: the volume, whatever the step.
+	ignore_duty: bool,
+	period: u16,
+	enabled: bool,
+	timer: u16,
+	step: u8,
+}
+
+impl Pulse {
+	fn new() -> Self {
+		Pulse { step: 15, ..Self::default() }
+	}
+
+	fn write(&mut self, register: u16, data: u8) {
+		match register {
+			0 => {
+				self.ignore_duty = data & 0x80 != 0;
+				self.duty = (data >> 4) & 0b111;
+				self.volume = data & 0x0F;
+			}
+			1 => self.period = (self.period & 0x0F00) | data as u16,
+			_ => {
+				self.period = (self.period & 0x00FF) | (data as u16 & 0x0F) << 8;
+				self.enabled = data & 0x80 != 0;
+				if !self.enabled {
+					self.step = 15;
+				}
+			}
+		}
+	}
+
+	fn clock(&mut self, shift: u8) {
+		if !self.enabled {
+			return;
+		}
+		if self.timer == 0 {
+			self.timer = self.period >> shift;
+			self.step = self.step.wrapping_sub(1) & 0x0F;
+		} else {
+			self.timer -= 1;
+		}
+	}
+
+	fn output(&self) -> u8 {
+		if self.enabled && (self.ignore_duty || self.step <= self.duty) { self.volume } else { 0 }
+	}
+}
+
+impl SaveState for Pulse {
+	fn save_state(&self, out: &mut StateWriter) {
+		out.u8(self.volume);
+		out.u8(self.duty);
+		out.bool(self.ignore_duty);
+		out.u16(self.period);
+		out.bool(self.enabled);
+		out.u16(self.timer);
+		out.u8(self.step);
+	}
+
+	fn load_state(&mut self, input: &mut StateReader) -> Result<(), String> {
+		self.volume = input.u8()?;
+		self.duty = input.u8()?;
+		self.ignore_duty = input.bool()?;
+		self.period = input.u16()?;
+		self.enabled = input.bool()?;
+		self.timer = input.u16()?;
+		self.step = input.u8()?;
+		Ok(())
+	}
+}
+
+#[derive(Clone, Debug, Default)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+struct Sawtooth {
+	rate: u8,
+	period: u16,
+	enabled: bool,
+	timer: u16,
+	/// 0-13: the accumulator is cleared when it gets to 14.
+	step: u8,
+	accumulator: u8,
+}
+
+impl Sawtooth {
+	fn write(&mut self, register: u16, data: u8) {
+		match register {
+			0 => self.rate = data & 0x3F,
+			1 => self.period = (self.period & 0x0F00) | data as u16,
+			_ => {
+				self.period = (self.period & 0x00FF) | (data as u16 & 0x0F) << 8;
+				self.enabled = data & 0x80 != 0;
+				if !self.enabled {
+					self.step = 0;
+					self.accumulator = 0;
+				}
+			}
+		}
+	}
+
+	fn clock(&mut self, shift: u8) {
+		if !self.enabled {
+			return;
+		}
+		if self.timer > 0 {
+			self.timer -= 1;
+			return;
+		}
+		self.timer = self.period >> shift;
+		self.step += 1;
+		if self.step == 14 {
+			self.step = 0;
+			self.accumulator = 0;
+		} else if self.step.is_multiple_of(2) {
+			// A rate above 42 overflows, and the real chip distorts the same way.
+			self.accumulator = self.accumulator.wrapping_add(self.rate);
+		}
+	}
+
+	fn output(&self) -> u8 {
+		self.accumulator >> 3
+	}
+}
+
+impl SaveState for Sawtooth {
+	fn save_state(&self, out: &mut StateWriter) {
+		out.u8(self.rate);
+		out.u16(self.period);
+		out.bool(self.enabled);
+		out.u16(self.timer);
+		out.u8(self.step);
+		out.u8(self.accumulator);
+	}
+
+	fn load_state(&mut self, input: &mut StateReader) -> Result<(), String> {
+		self.rate = input.u8()?;
+		self.period = input.u16()?;
+		self.enabled = input.bool()?;
+		self.timer = input.u16()?;
+		self.step = input.u8()?;
+		self.accumulator = input.u8()?;
+		Ok(())
+	}
+}
+
+/// The registers and the sound channels of the chip. Like `Mmc3`, the cartridge asks for the banks with its sizes.
+#[derive(Clone, Debug)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct Vrc6 {
+	/// Mapper 26: A0 and A1 are swapped.
+	swapped: bool,
+	prg_16k: u8,
+	prg_8k: u8,
+	chr: [u8; 8],
+	/// $B003. None until the game writes it: the mirroring of the header until then.
+	control: Option<u8>,
+	irq_latch: u8,
+	irq_counter: u8,
+	irq_prescaler: i16,
+	irq_enabled: bool,
+	/// Bit 0 of $F001: `irq_enabled` after an acknowledge.
+	irq_enabled_after_ack: bool,
+	irq_cycle_mode: bool,
+	irq_pending: bool,
+	/// $9003.
+	audio_control: u8,
+	pulse1: Pulse,
+	pulse2: Pulse,
+	sawtooth: Sawtooth,
+}
+
+impl Vrc6 {
+	/// `mapper` is 24 or 26, which differ by the address lines.
+	pub fn new(mapper: u8) -> Self {
+		Vrc6 {
+			swapped: mapper == 26,
+			prg_16k: 0,
+			prg_8k: 0,
+			chr: [0; 8],
+			control: None,
+			irq_latch: 0,
+			irq_counter: 0,
+			irq_prescaler: SCANLINE_DOTS,
+			irq_enabled: false,
+			irq_enabled_after_ack: false,
+			irq_cycle_mode: false,
+			irq_pending: false,
+			audio_control: 0,
+			pulse1: Pulse::new(),
+			pulse2: Pulse::new(),
+			sawtooth: Sawtooth::default(),
+		}
+	}
+
+	fn clock_irq_counter(&mut self) {
+		if self.irq_counter == 0xFF {
+			self.irq_counter = self.irq_latch;
+			self.irq_pending = true;
+		} else {
+			self.irq_counter += 1;
+		}
+	}
+
+	/// The sum of the channels, 0-61: 15 for each pulse, and 31 for the sawtooth.
+	pub fn output(&self) -> u8 {
+		self.pulse1.output() + self.pulse2.output() + self.sawtooth.output()
+	}
+}
+
+impl Mapper for Vrc6 {
+	/// A write to $8000-$FFFF. The register is the range, and the low 2 bits of the address (swapped for mapper 26).
+	fn cpu_write(&mut self, addr: u16, data: u8) -> bool {
+		let addr = if self.swapped { (addr & !0b11) | (addr & 1) << 1 | (addr & 2) >> 1 } else { addr };
+		let register = addr & 0b11;
+		match addr & 0xF003 {
+			0x8000..=0x8003 => self.prg_16k = data & 0x0F,
+			0x9003 => self.audio_control = data,
+			0x9000..=0x9002 => self.pulse1.write(register, data),
+			0xA000..=0xA002 => self.pulse2.write(register, data),
+			0xB003 => self.control = Some(data),
+			0xB000..=0xB002 => self.sawtooth.write(register, data),
+			0xC000..=0xC003 => self.prg_8k = data & 0x1F,
+			0xD000..=0xD003 => self.chr[register as usize] = data,
+			0xE000..=0xE003 => self.chr[4 + register as usize] = data,
+			0xF000 => self.irq_latch = data,
+			0xF001 => {
+				self.irq_enabled_after_ack = data & 0b001 != 0;
+				self.irq_enabled = data & 0b010 != 0;
+				self.irq_cycle_mode = data & 0b100 != 0;
+				if self.irq_enabled {
+					self.irq_counter = self.irq_latch;
+					self.irq_prescaler = SCANLINE_DOTS;
+				}
+				self.irq_pending = false;
+			}
+			0xF002 => {
+				self.irq_pending = false;
+				self.irq_enabled = self.irq_enabled_after_ack;
+			}
+			_ => {}
+		}
+		true
+	}
+
+	/// The mirroring the game chose, if it did.
+	fn mirroring(&self) -> Option<Mirroring> {
+		self.control.map(|control| match (control >> 2) & 0b11 {
+			0 => Mirroring::Vertical,
+			1 => Mirroring::Horizontal,
+			2 => Mirroring::SingleScreenLower,
+			_ => Mirroring::SingleScreenUpper,
+		})
+	}
+
+	fn prg_ram_enabled(&self) -> bool {
+		self.control.is_none_or(|control| control & 0x80 != 0)
+	}
+
+	/// Where each 8KB window of $8000-$FFFF starts in PRG ROM: the 16KB bank, the 8KB bank and the last 8KB.
+	fn prg_banks(&self, prg_rom_size: usize) -> [usize; 4] {
+		let bank = |number: usize| number * PRG_BANK_SIZE % prg_rom_size;
+		let first = self.prg_16k as usize * 2;
+		[bank(first), bank(first + 1), bank(self.prg_8k as usize), prg_rom_size - PRG_BANK_SIZE]
+	}
+
+	/// Where each 1KB window of the pattern tables starts in CHR.
+	fn chr_banks(&self, chr_size: usize) -> [usize; 8] {
+		self.chr.map(|number| number as usize * CHR_BANK_SIZE % chr_size)
+	}
+
+	fn prg_slots(&self) -> &'static [usize] {
+		&[2 * PRG_BANK_SIZE, PRG_BANK_SIZE, PRG_BANK_SIZE]
+	}
+
+	fn chr_slots(&self) -> &'static [usize] {
+		&[CHR_BANK_SIZE; 8]
+	}
+
+	/// For the debugger, see `Cartridge::debug_state`.
+	fn irq_info(&self) -> Option<IrqCounterInfo> {
+		Some(IrqCounterInfo { counter: self.irq_counter, latch: self.irq_latch, enabled: self.irq_enabled, pending: self.irq_pending })
+	}
+
+	/// A CPU cycle: the IRQ counter, and the timers of the channels.
+	fn clock_cpu(&mut self) {
+		if self.irq_enabled {
+			if self.irq_cycle_mode {
+				self.clock_irq_counter();
+			} else {
+				self.irq_prescaler -= 3;
+				if self.irq_prescaler <= 0 {
+					self.irq_prescaler += SCANLINE_DOTS;
+					self.clock_irq_counter();
+				}
+			}
+		}
+
+		if self.audio_control & 1 == 0 {
+			let shift = if self.audio_control & 0b100 != 0 { 8 } else if self.audio_control & 0b010 != 0 { 4 } else { 0 };
+			self.pulse1.clock(shift);
+			self.pulse2.clock(shift);
+			self.sawtooth.clock(shift);
+		}
+	}
+
+	/// Holds the IRQ line, until $F001 or $F002 is written.
+	fn irq_pending(&self) -> bool {
+		self.irq_pending
+	}
+
+	/// `output`, at the level of the APU channels.
+	fn audio_level(&self) -> f32 {
+		self.output() as f32 * STEP_LEVEL
+	}
+}
+
+/// The mapper number is not in the state, like the MMC3 variant.
+impl SaveState for Vrc6 {
+	fn save_state(&self, out: &mut StateWriter) {
+		out.u8(self.prg_16k);
+		out.u8(self.prg_8k);
+		out.bytes(&self.chr);
+		out.bool(self.control.is_some());
+		out.u8(self.control.unwrap_or(0));
+		out.u8(self.irq_latch);
+		out.u8(self.irq_counter);
+		out.u16(self.irq_prescaler as u16);
+		out.bool(self.irq_enabled);
+		out.bool(self.irq_enabled_after_ack);
+		out.bool(self.irq_cycle_mode);
+		out.bool(self.irq_pending);
+		out.u8(self.audio_control);
+		self.pulse1.save_state(out);
+		self.pulse2.save_state(out);
+		self.sawtooth.save_state(out);
+	}
+
+	fn load_state(&mut self, input: &mut StateReader) -> Result<(), String> {
+		self.prg_16k = input.u8()?;
+		self.prg_8k = input.u8()?;
+		input.bytes(&mut self.chr)?;
+		let written = input.bool()?;
+		let control = input.u8()?;
+		self.control = written.then_some(control);
+		self.irq_latch = input.u8()?;
+		self.irq_counter = input.u8()?;
+		self.irq_prescaler = input.u16()? as i16;
+		self.irq_enabled = input.bool()?;
+		self.irq_enabled_after_ack = input.bool()?;
+		self.irq_cycle_mode = input.bool()?;
+		self.irq_pending = input.bool()?;
+		self.audio_control = input.u8()?;
+		self.pulse1.load_state(input)?;
+		self.pulse2.load_state(input)?;
+		self.sawtooth.load_state(input)
+	}
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+
+	#[test]
+	fn banks_test() {
+		// 16 banks of 8KB.
+		for mapper in [24, 26] {
+			let mut vrc6 = Vrc6::new(mapper);
+			vrc6.cpu_write(0x8000, 3);
+			vrc6.cpu_write(0xC003, 9);
+			assert_eq!(vrc6.prg_banks(0x20000).map(|start| start / 0x2000), [6, 7, 9, 15], "mapper {}", mapper);
+			// Out of range banks wrap.
+			vrc6.cpu_write(0x8001, 9);
+			assert_eq!(vrc6.prg_banks(0x20000)[0], 2 * 0x2000);
+		}
+
+		// $D001 on mapper 24 is $D002 on mapper 26.
+		let (mut vrc6a, mut vrc6b) = (Vrc6::new(24), Vrc6::new(26));
+		for (register, bank) in [(0xD000, 10), (0xD001, 11), (0xD002, 12), (0xE003, 13)] {
+			vrc6a.cpu_write(register, bank);
+			vrc6b.cpu_write(register, bank);
+		}
+		assert_eq!(vrc6a.chr_banks(0x8000).map(|start| start / 0x400), [10, 11, 12, 0, 0, 0, 0, 13]);
+		assert_eq!(vrc6b.chr_banks(0x8000).map(|start| start / 0x400), [10, 12, 11, 0, 0, 0, 0, 13]);
+
+		assert_eq!(vrc6a.mirroring(), None);
+		assert!(vrc6a.prg_ram_enabled());
+		vrc6a.cpu_write(0xB003, 0x04);
+		assert!(!vrc6a.prg_ram_enabled());
+		for (control, mirroring) in [(0x80, Mirroring::Vertical), (0x84, Mirroring::Horizontal), (0x88, Mirroring::SingleScreenLower), (0x8C, Mirroring::SingleScreenUpper)] {
+			vrc6a.cpu_write(0xB003, control);
+			assert_eq!(vrc6a.mirroring(), Some(mirroring));
+		}
+	}
+
+	/// Latch `latch`, enable with `control` (and bit 0, enabled again after the acknowledge), then clock `cycles`
+	/// times: the cycles after which the IRQ line was held, each acknowledged right away.
+	fn fired(latch: u8, control: u8, cycles: usize) -> Vec<usize> {
+		let mut vrc6 = Vrc6::new(24);
+		vrc6.cpu_write(0xF000, latch);
+		vrc6.cpu_write(0xF001, control | 0b011);
+		let mut fired = vec![];
+		for cycle in 1..=cycles {
+			vrc6.clock_cpu();
+			if vrc6.irq_pending() {
+				fired.push(cycle);
+				vrc6.cpu_write(0xF002, 0);
+			}
+		}
+		fired
+	}
+
+	#[test]
+	fn irq_test() {
+		// Cycle mode: $F0 to $FF, and the overflow, every 16 cycles.
+		assert_eq!(fired(0xF0, 0b100, 50), [16, 32, 48]);
+		assert_eq!(fired(0xFF, 0b100, 3), [1, 2, 3]);
+
+		// Scanline mode: 341 dots are 113.67 cycles, so the counter is clocked at 114, 228, 341 and 455.
+		assert_eq!(fired(0xFE, 0, 500), [228, 455]);
+		// Every 131 scanlines, half of an NTSC frame.
+		assert_eq!(fired((0x100u16 - 131) as u8, 0, 30_000), [14_891, 29_781]);
+
+		// Disabled, it doesn't count.
+		let mut vrc6 = Vrc6::new(24);
+		vrc6.cpu_write(0xF000, 0xFF);
+		vrc6.cpu_write(0xF001, 0b100);
+		for _ in 0..10 {
+			vrc6.clock_cpu();
+		}
+		assert!(!vrc6.irq_pending());
+		// Without bit 0, the acknowledge disables it.
+		vrc6.cpu_write(0xF001, 0b110);
+		vrc6.clock_cpu();
+		assert!(vrc6.irq_pending());
+		vrc6.cpu_write(0xF002, 0);
+		vrc6.clock_cpu();
+		assert!(!vrc6.irq_pending());
+	}
+
+	#[test]
+	fn audio_test() {
+		// The sawtooth with a rate of 42 and a period of 0 (a step every cycle): 7 levels of 2 steps, then again.
+		let mut vrc6 = Vrc6::new(24);
+		vrc6.cpu_write(0xB000, 42);
+		vrc6.cpu_write(0xB002, 0x80);
+		let levels: Vec<u8> = (0..28).map(|_| {
+			vrc6.clock_cpu();
+			vrc6.output()
+		}).collect();
+		let pattern = [0, 5, 5, 10, 10, 15, 15, 21, 21, 26, 26, 31, 31, 0];
+		assert_eq!(levels, [pattern, pattern].concat());
+
+		// Halted, it keeps its level.
+		vrc6.clock_cpu();
+		vrc6.clock_cpu();
+		vrc6.cpu_write(0x9003, 1);
+		vrc6.clock_cpu();
+		vrc6.clock_cpu();
+		assert_eq!(vrc6.output(), 5);
+		vrc6.cpu_write(0x9003, 0);
+		vrc6.clock_cpu();
+		vrc6.clock_cpu();
+		assert_eq!(vrc6.output(), 10);
+		// Disabled, it's cleared.
+		vrc6.cpu_write(0xB002, 0);
+		assert_eq!(vrc6.output(), 0);
+
+		// Pulse 1 with a duty of 3 (4/16) and a volume of 9, mapper 26 registers: on for 4 steps out of 16.
+		let mut vrc6 = Vrc6::new(26);
+		vrc6.cpu_write(0x9000, 0x39);
+		vrc6.cpu_write(0x9001, 0x80);
+		let levels: Vec<u8> = (0..32).map(|_| {
+			vrc6.clock_cpu();
+			vrc6.output()
+		}).collect();
+		assert_eq!(levels.iter().filter(|&&level| level == 9).count(), 8);
+		assert_eq!(&levels[..16], [0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 9, 9, 9, 9, 0]);
+		// Mode 1: always the volume.
+		vrc6.cpu_write(0x9000, 0xB9);
+		vrc6.clock_cpu();
+		assert_eq!(vrc6.output(), 9);
+		assert!(vrc6.audio_level() > 0.0);
+	}
+}
@@ -0,0 +1,58 @@
+// JavaScript API, for running in the browser (feature `wasm`, target wasm32-unknown-unknown). See examples/web.
+//
+// const nes = new WasmNes(romBytes);         // Uint8Array of an iNES file, throws if it's not valid
+// nes.set_buttons(0x01);                     // A pressed on controller 1, see below
+// nes.set_buttons2(0x08);                    // Start pressed on controller 2
+// const pixels = nes.run_frame();            // Uint8ClampedArray, 256x240 RGBA, ready for ImageData
+// const state = nes.save_state();            // Uint8Array
+// nes.load_state(state);                     // throws if the state is not valid
+//
+// The page paces the frames (requestAnimationFrame), and reads the keyboard or gamepad.
+
+use wasm_bindgen::prelude::*;
+use wasm_bindgen::Clamped;
+
+use crate::cartridge::Cartridge;
+use crate::controller::ButtonState;
+use crate::emulator::Emulator;
+use crate::ppu::framebuffer::{HEIGHT, WIDTH};
+
+#[wasm_bindgen]
+pub struct WasmNes {
+	emulator: Emulator,
+}
+
+#[wasm_bindgen]
+impl WasmNes {
+	/// Insert the cartridge (the bytes of an iNES file) and power on.
+	#[wasm_bindgen(constructor)]
+	pub fn new(rom_bytes: &[u8]) -> Result<WasmNes, JsError> {
+		let cartridge = Cartridge::from_ines(rom_bytes).map_err(|err| JsError::new(&err.to_string()))?;
+		Ok(WasmNes { emulator: Emulator::new(cartridge) })
+	}
+
+	/// Run a frame, and return it as RGBA pixels (256 * 240 * 4 bytes).
+	pub fn run_frame(&mut self) -> Clamped<Vec<u8>> {
+		let mut rgba = vec![0; WIDTH * HEIGHT * 4];
+		self.emulator.run_frame().write_rgba32(&mut rgba);
+		Clamped(rgba)
+	}
+
+	/// Buttons of controller 1, a bit each: A, B, Select, Start, Up, Down, Left, Right, from bit 0 to bit 7.
+	pub fn set_buttons(&mut self, buttons: u8) {
+		self.emulator.set_controller1(ButtonState(buttons).without_opposing_directions());
+	}
+
+	/// Buttons of controller 2, like `set_buttons`.
+	pub fn set_buttons2(&mut self, buttons: u8) {
+		self.emulator.set_controller2(ButtonState(buttons).without_opposing_directions());
+	}
+
+	pub fn save_state(&self) -> Vec<u8> {
+		self.emulator.save_state()
+	}
+
+	pub fn load_state(&mut self, state: &[u8]) -> Result<(), JsError> {
+		self.emulator.load_state(state).map_err(|err| JsError::new(&err.to_string()))
+	}
+}
@@ -0,0 +1,159 @@
+// The debugger's watch list: named expressions, printed every time the program stops (a step, a breakpoint, a
+// watchpoint). `watch lives = [$075A]`, or 16 bits with `watch scroll = [$FD]:[$FC]`. The grammar is the one of the
+// breakpoint conditions, see expression.rs.
+//
+//   lives = $03 (3)
+// * scroll = $0120 (288), was $0118
+//
+// Every watch keeps its value at the last stop, so the ones that changed since then are marked with `*`. They belong
+// to the debugger, not to the emulator, so a reloaded ROM (`--watch`) keeps them.
+
+use std::fmt;
+
+use crate::cpu::cpu::CpuState;
+use crate::expression::Expression;
+use crate::symbols::SymbolTable;
+
+struct Watch {
+	name: String,
+	/// As it was typed, to list it.
+	text: String,
+	expression: Expression,
+	value: i64,
+	/// The value at the stop before the last one, if it's not the same.
+	previous: Option<i64>,
+}
+
+#[derive(Default)]
+pub struct WatchList {
+	watches: Vec<Watch>,
+}
+
+impl WatchList {
+	pub fn new() -> Self {
+		Self::default()
+	}
+
+	/// Watch `text` as `name`, with its value now. A watch with the same name is replaced, in its place.
+	pub fn add(&mut self, name: &str, text: &str, symbols: &SymbolTable, state: &CpuState, peek: &dyn Fn(u16) -> u8) -> Result<(), String> {
+		let mut chars = name.chars();
+		let valid = chars.next().is_some_and(|c| c.is_ascii_alphabetic() || c == '_') && chars.all(|c| c.is_ascii_alphanumeric() || c == '_');
+		if !valid {
+			return Err(format!("A watch name is letters, digits and _, like lives, got '{}'", name));
+		}
+		let expression = Expression::parse(text, symbols)?;
+		let value = expression.evaluate(state, peek);
+		let watch = Watch { name: name.to_string(), text: text.to_string(), expression, value, previous: None };
+		match self.watches.iter_mut().find(|watch| watch.name == name) {
+			Some(old) => *old = watch,
+			None => self.watches.push(watch),
+		}
+		Ok(())
+	}
+
+	/// False if there's no watch called `name`.
+	pub fn remove(&mut self, name: &str) -> bool {
+		let count = self.watches.len();
+		self.watches.retain(|watch| watch.name != name);
+		self.watches.len() != count
+	}
+
+	pub fn is_empty(&self) -> bool {
+		self.watches.is_empty()
+	}
+
+	/// The program stopped: evaluate every watch again, and mark the ones that changed since the last stop.
+	pub fn update(&mut self, state: &CpuState, peek: &dyn Fn(u16) -> u8) {
+		for watch in &mut self.watches {
+			let value = watch.expression.evaluate(state, peek);
+			watch.previous = (value != watch.value).then_some(watch.value);
+			watch.value = value;
+		}
+	}
+
+	/// Evaluate every watch again, without marking anything: after the machine changed under them (a reloaded ROM).
+	pub fn refresh(&mut self, state: &CpuState, peek: &dyn Fn(u16) -> u8) {
+		self.update(state, peek);
+		for watch in &mut self.watches {
+			watch.previous = None;
+		}
+	}
+
+	/// Whether the watch called `name` changed at the last stop. None if there's no such watch.
+	pub fn changed(&self, name: &str) -> Option<bool> {
+		self.watches.iter().find(|watch| watch.name == name).map(|watch| watch.previous.is_some())
+	}
+
+	/// The expressions, as they were typed: `lives = [$075A]`, a line each.
+	pub fn definitions(&self) -> String {
+		let lines: Vec<String> = self.watches.iter().map(|watch| format!("{} = {}", watch.name, watch.text)).collect();
+		lines.join("\n")
+	}
+}
+
+/// The values at the last stop, a line each, see the top of the file.
+impl fmt::Display for WatchList {
+	fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+		for (i, watch) in self.watches.iter().enumerate() {
+			if i > 0 {
+				writeln!(f)?;
+			}
+			let mark = if watch.previous.is_some() { '*' } else { ' ' };
+			write!(f, "{} {} = {} ({})", mark, watch.name, hex(watch.value), watch.value)?;
+			if let Some(previous) = watch.previous {
+				write!(f, ", was {}", hex(previous))?;
+			}
+		}
+		Ok(())
+	}
+}
+
+/// `$03` for bytes, `$0120` for 16 bits, and the plain number for anything else.
+fn hex(value: i64) -> String {
+	match value {
+		0..=0xFF => format!("${:02X}", value),
+		0x100..=0xFFFF => format!("${:04X}", value),
+		_ => value.to_string(),
+	}
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+
+	#[test]
+	fn watch_list_test() {
+		let mut memory = [0u8; 0x100];
+		memory[0xFC] = 0x18;
+		memory[0xFD] = 0x01;
+		memory[0x10] = 3;
+		let state = CpuState { pc: 0x8000, a: 0, x: 0, y: 0, p: 0x24, s: 0xFD, cycles: 0 };
+		let symbols = SymbolTable::new();
+		let mut watches = WatchList::new();
+		assert!(watches.is_empty());
+
+		watches.add("lives", "[$10]", &symbols, &state, &|addr| memory[addr as usize]).unwrap();
+		watches.add("scroll", "[$FD]:[$FC]", &symbols, &state, &|addr| memory[addr as usize]).unwrap();
+		assert_eq!(watches.to_string(), "  lives = $03 (3)\n  scroll = $0118 (280)");
+
+		memory[0xFC] = 0x20;
+		watches.update(&state, &|addr| memory[addr as usize]);
+		assert_eq!(watches.changed("scroll"), Some(true));
+		assert_eq!(watches.changed("lives"), Some(false));
+		assert_eq!(watches.to_string(), "  lives = $03 (3)\n* scroll = $0120 (288), was $0118");
+		// Only until the next stop.
+		watches.update(&state, &|addr| memory[addr as usize]);
+		assert_eq!(watches.changed("scroll"), Some(false));
+
+		// Replaced in its place.
+		watches.add("lives", "[$10] - 1", &symbols, &state, &|addr| memory[addr as usize]).unwrap();
+		assert_eq!(watches.definitions(), "lives = [$10] - 1\nscroll = [$FD]:[$FC]");
+		assert!(watches.remove("lives"));
+		assert!(!watches.remove("lives"));
+		assert_eq!(watches.changed("lives"), None);
+
+		assert!(watches.add("2lives", "[$10]", &symbols, &state, &|addr| memory[addr as usize]).is_err());
+		assert!(watches.add("lives", "[$10", &symbols, &state, &|addr| memory[addr as usize]).is_err());
+		assert_eq!(watches.definitions(), "scroll = [$FD]:[$FC]");
+	}
+}
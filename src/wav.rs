@@ -0,0 +1,230 @@
+// WAV files of the audio, for listening to headless runs and diffing them (`--wav-out`). Only what the APU makes:
+// 16-bit PCM, mono. http://soundfile.sapp.org/doc/WaveFormat/
+//
+// | Offset | Bytes | Description |
+// |---|---|---|
+// | 0 | 4 | "RIFF" |
+// | 4 | 4 | Size of the rest of the file: 36 + the data |
+// | 8 | 4 | "WAVE" |
+// | 12 | 4 | "fmt " |
+// | 16 | 4 | 16, the size of the format |
+// | 20 | 2 | 1, PCM |
+// | 22 | 2 | 1 channel |
+// | 24 | 4 | Sample rate |
+// | 28 | 4 | Bytes a second: sample rate * 2 |
+// | 32 | 2 | Bytes a sample: 2 |
+// | 34 | 2 | Bits a sample: 16 |
+// | 36 | 4 | "data" |
+// | 40 | 4 | Size of the data |
+// | 44 | | The samples, little endian |
+//
+// The sizes are written again after every `write`, so the file is complete even if the run never ends cleanly
+// (Ctrl-C, a crash): it has everything up to the last frame.
+
+use std::io::{self, Seek, SeekFrom, Write};
+
+use crate::apu::filter::HighPass;
+use crate::apu::sample_buffer::SampleBuffer;
+
+const HEADER_SIZE: usize = 44;
+/// Like the high-pass of the console's output. It takes away the level of the silent APU.
+const HIGH_PASS_CUTOFF: f32 = 90.0;
+
+pub struct WavWriter<W: Write + Seek> {
+	out: W,
+	sample_rate: u32,
+	samples: u32,
+}
+
+impl<W: Write + Seek> WavWriter<W> {
+	/// Write the header, without samples yet.
+	pub fn new(out: W, sample_rate: u32) -> io::Result<Self> {
+		let mut writer = WavWriter { out, sample_rate, samples: 0 };
+		writer.write_header()?;
+		Ok(writer)
+	}
+
+	/// Add samples, from -1.0 to 1.0 (louder ones are clipped).
+	pub fn write(&mut self, samples: &[f32]) -> io::Result<()> {
+		let mut data = Vec::with_capacity(samples.len() * 2);
+		for &sample in samples {
+			data.extend_from_slice(&to_pcm16(sample).to_le_bytes());
+		}
+		self.out.seek(SeekFrom::End(0))?;
+		self.out.write_all(&data)?;
+		self.samples += samples.len() as u32;
+		self.write_header()?;
+		self.out.flush()
+	}
+
+	pub fn samples(&self) -> u32 {
+		self.samples
+	}
+
+	/// Where the file went, complete.
+	pub fn into_inner(self) -> W {
+		self.out
+	}
+
+	fn write_header(&mut self) -> io::Result<()> {
+		let data_size = self.samples * 2;
+		let mut header = Vec::with_capacity(HEADER_SIZE);
+		header.extend_from_slice(b"RIFF");
+		header.extend_from_slice(&(36 + data_size).to_le_bytes());
+		header.extend_from_slice(b"WAVEfmt ");
+		header.extend_from_slice(&16u32.to_le_bytes());
+		header.extend_from_slice(&1u16.to_le_bytes());
+		header.extend_from_slice(&1u16.to_le_bytes());
+		header.extend_from_slice(&self.sample_rate.to_le_bytes());
+		header.extend_from_slice(&(self.sample_rate * 2).to_le_bytes());
+		header.extend_from_slice(&2u16.to_le_bytes());
+		header.extend_from_slice(&16u16.to_le_bytes());
+		header.extend_from_slice(b"data");
+		header.extend_from_slice(&data_size.to_le_bytes());
+		self.out.seek(SeekFrom::Start(0))?;
+		self.out.write_all(&header)
+	}
+}
+
+/// -1.0 to 1.0, to a 16-bit sample.
+pub fn to_pcm16(sample: f32) -> i16 {
+	(sample.clamp(-1.0, 1.0) * i16::MAX as f32).round() as i16
+}
+
+/// Read a file `WavWriter` wrote: the sample rate, and the samples. For tests and tools, it doesn't know other WAVs.
+pub fn read_pcm16(bytes: &[u8]) -> Result<(u32, Vec<i16>), String> {
+	if bytes.len() < HEADER_SIZE || &bytes[0..4] != b"RIFF" || &bytes[8..16] != b"WAVEfmt " || &bytes[36..40] != b"data" {
+		return Err("Not a WAV file".to_string());
+	}
+	let u16_at = |offset: usize| u16::from_le_bytes([bytes[offset], bytes[offset + 1]]);
+	let u32_at = |offset: usize| u32::from_le_bytes([bytes[offset], bytes[offset + 1], bytes[offset + 2], bytes[offset + 3]]);
+	if u16_at(20) != 1 || u16_at(22) != 1 || u16_at(34) != 16 {
+		return Err("Only 16-bit PCM mono".to_string());
+	}
+	let data_size = u32_at(40) as usize;
+	if u32_at(4) as usize != 36 + data_size || bytes.len() != HEADER_SIZE + data_size {
+		return Err(format!("The header says {} bytes of samples, the file has {}", data_size, bytes.len() - HEADER_SIZE));
+	}
+	let samples = bytes[HEADER_SIZE..].chunks_exact(2).map(|sample| i16::from_le_bytes([sample[0], sample[1]])).collect();
+	Ok((u32_at(24), samples))
+}
+
+/// Writes the samples of the APU to a WAV file, through the high-pass. `capture` after every frame, before the
+/// buffer of the APU fills up.
+pub struct WavRecorder<W: Write + Seek> {
+	samples: SampleBuffer,
+	filter: HighPass,
+	writer: WavWriter<W>,
+	buffer: Vec<f32>,
+}
+
+impl<W: Write + Seek> WavRecorder<W> {
+	/// Record from now: the samples already in the buffer are dropped.
+	pub fn new(out: W, samples: SampleBuffer, sample_rate: u32) -> io::Result<Self> {
+		let mut buffer = Vec::new();
+		samples.take(&mut buffer);
+		buffer.clear();
+		Ok(WavRecorder { samples, filter: HighPass::new(sample_rate, HIGH_PASS_CUTOFF), writer: WavWriter::new(out, sample_rate)?, buffer })
+	}
+
+	/// Write the samples made since the last call.
+	pub fn capture(&mut self) -> io::Result<()> {
+		self.buffer.clear();
+		self.samples.take(&mut self.buffer);
+		for sample in self.buffer.iter_mut() {
+			*sample = self.filter.apply(*sample);
+		}
+		self.writer.write(&self.buffer)
+	}
+
+	pub fn samples(&self) -> u32 {
+		self.writer.samples()
+	}
+
+	pub fn into_inner(self) -> W {
+		self.writer.into_inner()
+	}
+}
+
+#[cfg(test)]
+mod tests {
+	use std::cell::RefCell;
+	use std::io::Cursor;
+	use std::rc::Rc;
+
+	use super::*;
+	use crate::cartridge::{test_rom, Cartridge};
+	use crate::emulator::Emulator;
+	use crate::harness::Harness;
+
+	#[test]
+	fn wav_writer_test() {
+		let mut writer = WavWriter::new(Cursor::new(Vec::new()), 48_000).unwrap();
+		assert_eq!(read_pcm16(writer.out.get_ref()).unwrap(), (48_000, vec![]));
+		writer.write(&[0.0, 1.0, -1.0]).unwrap();
+		// The header is right after every write.
+		assert_eq!(read_pcm16(writer.out.get_ref()).unwrap(), (48_000, vec![0, 32767, -32767]));
+		writer.write(&[2.0, 0.5]).unwrap();
+		let bytes = writer.into_inner().into_inner();
+		assert_eq!(bytes.len(), HEADER_SIZE + 10);
+		assert_eq!(read_pcm16(&bytes).unwrap(), (48_000, vec![0, 32767, -32767, 32767, 16384]));
+
+		assert!(read_pcm16(&bytes[..50]).is_err());
+		assert!(read_pcm16(b"RIFF").is_err());
+	}
+
+	/// Run `program` for `frames` frames, and record it.
+	fn record(program: &str, frames: u64) -> (Emulator, Vec<i16>) {
+		let emulator = Emulator::new(Cartridge::from_ines(&test_rom::nrom(program)).unwrap());
+		let sample_rate = emulator.bus().apu().sample_rate();
+		let recorder = WavRecorder::new(Cursor::new(Vec::new()), emulator.bus().apu().sample_buffer(), sample_rate).unwrap();
+		let recorder = Rc::new(RefCell::new(Some(recorder)));
+		let capture = recorder.clone();
+		let mut harness = Harness::new(emulator).max_frames(frames).on_frame(move |_| {
+			capture.borrow_mut().as_mut().unwrap().capture().unwrap();
+		});
+		harness.run();
+		let bytes = recorder.borrow_mut().take().unwrap().into_inner().into_inner();
+		let (rate, samples) = read_pcm16(&bytes).unwrap();
+		assert_eq!(rate, sample_rate);
+		(harness.into_emulator(), samples)
+	}
+
+	fn rms(samples: &[i16]) -> f64 {
+		(samples.iter().map(|&sample| (sample as f64).powi(2)).sum::<f64>() / samples.len() as f64).sqrt()
+	}
+
+	#[test]
+	fn record_test() {
+		/*
+		LDA #$01
+		STA $4015 	; Pulse 1 on
+		LDA #$BF
+		STA $4000 	; Duty 50%, no length counter, volume 15
+		LDA #$FD
+		STA $4002
+		LDA #$00
+		STA $4003 	; About 440 Hz
+		loop:
+		INX 		; Not a jam for the harness
+		JMP loop
+		*/
+		let (emulator, samples) = record("A9 01 8D 15 40 A9 BF 8D 00 40 A9 FD 8D 02 40 A9 00 8D 03 40 E8 4C 14 80", 30);
+		// A sample every 1/48000 s: the frames, times the samples of a frame.
+		let region = emulator.region();
+		let expected = 30.0 * 48_000.0 / region.frame_rate();
+		assert!((samples.len() as f64 - expected).abs() < 48_000.0 / region.frame_rate(), "{} samples, expected {}", samples.len(), expected);
+		assert!(rms(&samples) > 1000.0, "RMS {}", rms(&samples));
+
+		/*
+		LDA #$00
+		STA $4015 	; Everything off
+		loop:
+		INX
+		JMP loop
+		*/
+		let (_, samples) = record("A9 00 8D 15 40 E8 4C 05 80", 30);
+		assert!(!samples.is_empty());
+		assert!(samples.iter().all(|&sample| sample == 0));
+	}
+}
@@ -0,0 +1,85 @@
+// Zapper light gun: https://www.nesdev.org/wiki/Zapper
+//
+// It plugs in controller port 2, and the game reads it at $4017:
+//
+// | Bit | Meaning |
+// |---|---|
+// | 4 | Trigger: 1 while it's pulled |
+// | 3 | Light sense: 0 when the gun sees light, 1 when it doesn't |
+//
+// The gun doesn't see the picture, only a single photodiode. It sees light when the beam draws something bright where
+// the gun points, and stays on for a while after the beam passed. So games (like Duck Hunt) draw white boxes for a
+// frame, and read the gun while the beam is a little below them. It's what the light depends on here: the pixels
+// under the gun, if the PPU drew them in the last `LIGHT_SCANLINES` scanlines.
+
+use crate::ppu::colors::PALETTE;
+use crate::ppu::framebuffer::{HEIGHT, WIDTH};
+use crate::ppu::ppu::{PPU, DOTS_PER_SCANLINE};
+
+/// How long the light sensor stays on after the beam passed, in scanlines. It's a little longer on the real gun
+/// (depends on the gun, and on how bright), but games read it right after the beam passes.
+const LIGHT_SCANLINES: u32 = 20;
+
+/// A pixel is bright enough for the sensor when the sum of its RGB is at least this.
+const BRIGHTNESS_THRESHOLD: u32 = 0x55 * 3;
+
+/// Where the gun points, and if the trigger is pulled. The frontend sets it, like the buttons of a controller.
+#[derive(Default, Clone, Copy, PartialEq, Eq, Debug)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct Zapper {
+	/// Pixel on the screen (0-255, 0-239). Anything else points away from the screen.
+	pub x: u16,
+	pub y: u16,
+	pub trigger: bool,
+}
+
+impl Zapper {
+	pub fn new(x: u16, y: u16, trigger: bool) -> Self {
+		Zapper { x, y, trigger }
+	}
+
+	/// Read $4017. Only bits 3 and 4 are returned; the caller should fill the rest (open bus).
+	pub fn read(&self, ppu: &PPU) -> u8 {
+		let trigger = if self.trigger { 0x10 } else { 0 };
+		let light = if self.sees_light(ppu) { 0 } else { 0x08 };
+		trigger | light
+	}
+
+	/// The beam drew a bright pixel under the gun, a short while ago.
+	pub fn sees_light(&self, ppu: &PPU) -> bool {
+		let (x, y) = (self.x as usize, self.y as usize);
+		if x >= WIDTH || y >= HEIGHT {
+			return false;
+		}
+
+		// How long ago the beam drew the pixel, in dots. Pixel x is drawn at dot x + 1. Rows below the beam are still
+		// the last frame, which the sensor stopped seeing long ago.
+		let beam = ppu.scanline() as u32 * DOTS_PER_SCANLINE as u32 + ppu.dot() as u32;
+		let pixel = y as u32 * DOTS_PER_SCANLINE as u32 + x as u32 + 1;
+		if beam < pixel || beam - pixel >= LIGHT_SCANLINES * DOTS_PER_SCANLINE as u32 {
+			return false;
+		}
+
+		// The sensor sees a few pixels around where the gun points, not a single one.
+		let framebuffer = ppu.framebuffer();
+		(x.saturating_sub(1)..=(x + 1).min(WIDTH - 1)).any(|x| {
+			let (r, g, b) = PALETTE[(framebuffer.get(x, y) & 0x3F) as usize];
+			r as u32 + g as u32 + b as u32 >= BRIGHTNESS_THRESHOLD
+		})
+	}
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+
+	#[test]
+	fn zapper_read_test() {
+		// A black screen, at the top of the frame.
+		let ppu = PPU::new();
+		assert_eq!(Zapper::new(10, 10, false).read(&ppu), 0x08);
+		assert_eq!(Zapper::new(10, 10, true).read(&ppu), 0x18);
+		// Away from the screen.
+		assert_eq!(Zapper::new(300, 10, true).read(&ppu), 0x18);
+	}
+}
@@ -0,0 +1,92 @@
+// The hot path (CPU step, bus reads and writes, PPU and APU ticks) never allocates. This counts the allocations of
+// the test's thread with a global allocator, so it's a test binary of its own.
+
+use std::alloc::{GlobalAlloc, Layout, System};
+use std::cell::Cell;
+use std::sync::atomic::{AtomicUsize, Ordering};
+
+use rust_nes_emulator::{Cartridge, Emulator};
+
+struct CountingAllocator;
+
+static ALLOCATIONS: AtomicUsize = AtomicUsize::new(0);
+
+thread_local! {
+	// Const, so reading it doesn't allocate.
+	static COUNTING: Cell<bool> = const { Cell::new(false) };
+}
+
+unsafe impl GlobalAlloc for CountingAllocator {
+	unsafe fn alloc(&self, layout: Layout) -> *mut u8 {
+		if COUNTING.with(|counting| counting.get()) {
+			ALLOCATIONS.fetch_add(1, Ordering::Relaxed);
+		}
+		System.alloc(layout)
+	}
+
+	unsafe fn dealloc(&self, ptr: *mut u8, layout: Layout) {
+		System.dealloc(ptr, layout)
+	}
+
+	unsafe fn realloc(&self, ptr: *mut u8, layout: Layout, new_size: usize) -> *mut u8 {
+		if COUNTING.with(|counting| counting.get()) {
+			ALLOCATIONS.fetch_add(1, Ordering::Relaxed);
+		}
+		System.realloc(ptr, layout, new_size)
+	}
+}
+
+#[global_allocator]
+static ALLOCATOR: CountingAllocator = CountingAllocator;
+
+/// Count the allocations `f` makes on this thread.
+fn allocations(f: impl FnOnce()) -> usize {
+	let before = ALLOCATIONS.load(Ordering::Relaxed);
+	COUNTING.with(|counting| counting.set(true));
+	f();
+	COUNTING.with(|counting| counting.set(false));
+	ALLOCATIONS.load(Ordering::Relaxed) - before
+}
+
+/// NROM, with a program that keeps the whole machine busy.
+fn busy_rom() -> Vec<u8> {
+	/*
+	LDA #$1E
+	STA $2001	; Show the background and the sprites
+	LDA #$0F
+	STA $4015	; Enable the sound channels
+	loop:
+	INC $10
+	LDA $10
+	STA $4000	; Pulse 1
+	LDA $2002
+	LDA $8000,X
+	INX
+	JMP loop
+	*/
+	let program = [
+		0xA9, 0x1E, 0x8D, 0x01, 0x20, 0xA9, 0x0F, 0x8D, 0x15, 0x40,
+		0xE6, 0x10, 0xA5, 0x10, 0x8D, 0x00, 0x40, 0xAD, 0x02, 0x20, 0xBD, 0x00, 0x80, 0xE8, 0x4C, 0x0A, 0x80,
+	];
+	let mut prg = vec![0xEA; 0x4000];
+	prg[..program.len()].copy_from_slice(&program);
+	prg[0x3FFC] = 0x00;
+	prg[0x3FFD] = 0x80;
+	let mut rom = vec![0x4E, 0x45, 0x53, 0x1A, 1, 1, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0];
+	rom.extend_from_slice(&prg);
+	rom.extend_from_slice(&[0; 0x2000]);
+	rom
+}
+
+#[test]
+fn no_allocations_test() {
+	let mut emulator = Emulator::new(Cartridge::from_ines(&busy_rom()).unwrap());
+	emulator.run_frame();
+
+	let count = allocations(|| {
+		for _ in 0..10 {
+			emulator.run_frame();
+		}
+	});
+	assert_eq!(count, 0, "{} allocations in 10 frames", count);
+}
@@ -0,0 +1,14 @@
+// Fixtures shared by the test crates.
+
+/// An NROM iNES file with `program` at $8000, the reset vector pointing to it, and empty CHR.
+pub fn nrom(program: &[u8]) -> Vec<u8> {
+	let mut prg = vec![0xEA; 0x4000];
+	prg[..program.len()].copy_from_slice(program);
+	prg[0x3FFC] = 0x00;
+	prg[0x3FFD] = 0x80;
+
+	let mut rom = vec![0x4E, 0x45, 0x53, 0x1A, 1, 1, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0];
+	rom.extend_from_slice(&prg);
+	rom.extend_from_slice(&[0; 0x2000]);
+	rom
+}
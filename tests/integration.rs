@@ -0,0 +1,124 @@
+// Tests of the library from the outside: only the public API, like any other crate would use it.
+
+mod common;
+
+use rust_nes_emulator::{Button, ButtonState, Cartridge, Emulator, Region};
+
+use common::nrom;
+
+#[test]
+fn run_frames_test() {
+	/*
+	LDA #$42
+	STA $10
+	loop:
+	INC $11
+	JMP loop
+	*/
+	let program = [0xA9, 0x42, 0x85, 0x10, 0xE6, 0x11, 0x4C, 0x04, 0x80];
+	let mut emulator = Emulator::new(Cartridge::from_ines(&nrom(&program)).unwrap());
+	assert_eq!(emulator.region(), Region::Ntsc);
+
+	emulator.run_frame();
+	emulator.run_frame();
+	assert_eq!(emulator.peek(0x0010), 0x42);
+	assert_eq!(emulator.bus().ram()[0x10], 0x42);
+	// Mirrored every 2KB.
+	assert_eq!(emulator.peek(0x0810), 0x42);
+	assert_ne!(emulator.peek(0x0011), 0);
+
+	let state = emulator.cpu_state();
+	assert_eq!(state.a, 0x42);
+	assert!((0x8004..0x8009).contains(&state.pc));
+	assert_eq!(emulator.cycles(), state.cycles);
+}
+
+#[test]
+fn controller_test() {
+	/*
+	LDA #$01
+	STA $4016
+	LDA #$00
+	STA $4016
+	LDA $4016
+	STA $00
+	loop:
+	JMP loop
+	*/
+	let program = [0xA9, 0x01, 0x8D, 0x16, 0x40, 0xA9, 0x00, 0x8D, 0x16, 0x40, 0xAD, 0x16, 0x40, 0x85, 0x00, 0x4C, 0x0F, 0x80];
+	let mut emulator = Emulator::new(Cartridge::from_ines(&nrom(&program)).unwrap());
+	let mut buttons = ButtonState::default();
+	buttons.set(Button::A, true);
+	emulator.set_controller1(buttons);
+	emulator.run_frame();
+
+	// A is the first button read.
+	assert_eq!(emulator.peek(0x0000) & 0x01, 0x01);
+}
+
+#[test]
+fn save_state_test() {
+	let program = [0xE6, 0x10, 0x4C, 0x00, 0x80];
+	let mut emulator = Emulator::new(Cartridge::from_ines(&nrom(&program)).unwrap());
+	emulator.run_frame();
+	let state = emulator.save_state();
+	let counter = emulator.peek(0x0010);
+
+	emulator.run_frame();
+	assert_ne!(emulator.peek(0x0010), counter);
+	emulator.load_state(&state).unwrap();
+	assert_eq!(emulator.peek(0x0010), counter);
+}
+
+#[test]
+fn no_logger_test() {
+	// Nothing installs a logger in this test, and the library never does: its messages go nowhere.
+	let mut emulator = Emulator::new(Cartridge::from_ines(&nrom(&[0xE6, 0x10, 0x4C, 0x00, 0x80])).unwrap());
+	emulator.power_on();
+	// Logged, on the mapper target.
+	emulator.poke(0x8001, 0x11);
+	emulator.run_frame();
+	assert!(emulator.load_state(&[0; 4]).is_err());
+	assert_ne!(emulator.peek(0x0011), 0);
+	assert_eq!(log::max_level(), log::LevelFilter::Off);
+}
+
+#[test]
+fn raw_program_test() {
+	/*
+	* = $C000
+	LDX #$00
+	loop:
+	INX
+	STX $10
+	BIT $2002
+	BPL loop 	; Not in VBlank yet
+	INC $11 	; Frames
+	JMP loop
+	*/
+	let mut binary = vec![0; 0x4000];
+	binary[..0x0F].copy_from_slice(&[0xA2, 0x00, 0xE8, 0x86, 0x10, 0x2C, 0x02, 0x20, 0x10, 0xF8, 0xE6, 0x11, 0x4C, 0x02, 0xC0]);
+	// The reset vector.
+	binary[0x3FFC..0x3FFE].copy_from_slice(&[0x00, 0xC0]);
+	let mut emulator = Emulator::with_raw_program(&binary, 0xC000).unwrap();
+	assert_eq!(emulator.cpu_state().pc, 0xC000);
+
+	for _ in 0..3 {
+		emulator.step_instruction();
+	}
+	assert_eq!(emulator.cpu_state().pc, 0xC005);
+	assert_eq!(emulator.cpu_state().x, 1);
+
+	// A slice of a frame, and then whole frames, each with its VBlank.
+	let result = emulator.run_budget(100);
+	assert!(result.cycles_run >= 100 && !result.frame_completed && result.stop.is_none(), "{:?}", result);
+	assert_ne!(emulator.peek(0x0010), 0);
+	assert_eq!(emulator.peek(0x0011), 0);
+	for _ in 0..4 {
+		emulator.run_frame();
+	}
+	assert!((2..=4).contains(&emulator.peek(0x0011)), "Frames: {}", emulator.peek(0x0011));
+
+	assert!(Emulator::with_raw_program(&binary, 0x8000).is_ok());
+	assert!(Emulator::with_raw_program(&binary, 0xD000).is_err());
+}
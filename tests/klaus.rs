@@ -0,0 +1,77 @@
+// Klaus Dormann's 6502 functional test (https://github.com/Klaus2m5/6502_functional_tests): a 64KB image that tests
+// every documented instruction by itself. It starts at $0400, and ends in a `JMP *` (or a branch to itself): at the
+// success address when all the tests passed, and anywhere else (a trap) when one failed. The number of the test that ran is at $0200.
+//
+// It's not ours to keep here, so the test runs only with it:
+// KLAUS_FUNCTIONAL_TEST=path/to/6502_functional_test.bin cargo test --release --test klaus -- --nocapture
+//
+// | Variable | Description |
+// |---|---|
+// | KLAUS_FUNCTIONAL_TEST | The binary |
+// | KLAUS_SUCCESS_PC | The success address, $3469 by default (the binary in the repository). Other builds have it in their listing |
+// | KLAUS_MAX_CYCLES | The budget, 200 million cycles by default |
+//
+// The NES CPU has no decimal mode, and the decimal tests are the last ones: a binary built with `disable_decimal = 1`
+// skips them (and has another success address). With the standard binary, the listing tells if the trap is in one.
+
+use std::env;
+use std::fs;
+
+use rust_nes_emulator::{Bus, CpuState, RunEnd, StuckDetection, CPU};
+
+const ENTRY: u16 = 0x0400;
+const DEFAULT_SUCCESS_PC: u16 = 0x3469;
+const TEST_CASE: u16 = 0x0200;
+const DEFAULT_MAX_CYCLES: u64 = 200_000_000;
+
+/// 64KB of RAM. The binary is the whole memory, vectors included.
+struct FlatMemory(Box<[u8; 65_536]>);
+
+impl Bus for FlatMemory {
+	fn read(&mut self, addr: u16) -> u8 {
+		self.0[addr as usize]
+	}
+
+	fn peek(&self, addr: u16) -> u8 {
+		self.0[addr as usize]
+	}
+
+	fn write(&mut self, addr: u16, data: u8) {
+		self.0[addr as usize] = data;
+	}
+}
+
+/// An address like `$3469`, `0x3469` or `3469`, always hex.
+fn parse_addr(text: &str) -> u16 {
+	let digits = text.trim_start_matches('$').trim_start_matches("0x");
+	u16::from_str_radix(digits, 16).unwrap_or_else(|_| panic!("Not an address: '{}'", text))
+}
+
+#[test]
+fn klaus_functional_test() {
+	let Ok(path) = env::var("KLAUS_FUNCTIONAL_TEST") else {
+		println!("KLAUS_FUNCTIONAL_TEST is not set, skipping. See tests/klaus.rs.");
+		return;
+	};
+	let success_pc = env::var("KLAUS_SUCCESS_PC").map_or(DEFAULT_SUCCESS_PC, |addr| parse_addr(&addr));
+	let max_cycles = env::var("KLAUS_MAX_CYCLES").map_or(DEFAULT_MAX_CYCLES, |cycles| cycles.parse().expect("KLAUS_MAX_CYCLES is not a number"));
+
+	let image = fs::read(&path).unwrap_or_else(|err| panic!("Can't read {}: {}", path, err));
+	assert_eq!(image.len(), 0x10000, "{} is not a 64KB image", path);
+	let mut memory = Box::new([0; 65_536]);
+	memory.copy_from_slice(&image);
+
+	let mut cpu = CPU::new(FlatMemory(memory));
+	cpu.set_state(&CpuState { pc: ENTRY, ..cpu.state() });
+	// A trap is a jump to itself. When the I flag is clear, it runs a little longer first, in case an IRQ comes.
+	let end = cpu.run_until_stuck(max_cycles, StuckDetection::SelfJump);
+
+	let state = cpu.state();
+	let test_case = cpu.bus().peek(TEST_CASE);
+	match end {
+		Ok(RunEnd::StuckLoop { pc }) if pc == success_pc => println!("Passed in {} cycles", state.cycles),
+		Ok(RunEnd::StuckLoop { .. } | RunEnd::Jammed) => panic!("Trapped in test ${:02X}: {}", test_case, state),
+		Ok(RunEnd::BudgetExhausted) => panic!("Neither passed nor trapped in {} cycles, in test ${:02X}: {}", max_cycles, test_case, state),
+		Err(err) => panic!("{}, in test ${:02X}: {}", err, test_case, state),
+	}
+}
@@ -0,0 +1,166 @@
+// nestest (https://www.nesdev.org/wiki/Emulator_tests): runs from $C000 without a PPU, and its log (nestest.log) is
+// the trace of a correct CPU. This runs the ROM with the trace on, and diffs every line with the log, up to the first
+// difference. The ROM and the log are not ours to keep here, so the test runs only with them:
+// NESTEST_ROM=path/to/nestest.nes cargo test --release --test nestest -- --nocapture
+//
+// | Variable | Description |
+// |---|---|
+// | NESTEST_ROM | The ROM |
+// | NESTEST_LOG | The log. By default nestest.log next to the ROM |
+// | NESTEST_UNOFFICIAL | `1` to go on into the unofficial opcodes (`*NOP` in the log). By default it stops before them |
+// | NESTEST_SKIP_TEXT | `1` to compare only the address, registers, PPU and cycles, and not the instruction text. The log also shows the memory operands (`LDA $00 = 00`) |
+//
+// When the whole log matches, nestest's own results are checked too: it writes an error code to $02 when an official
+// opcode fails, and to $03 when an unofficial one does.
+
+use std::cell::RefCell;
+use std::env;
+use std::fmt::Write as _;
+use std::fs;
+use std::io::{self, Write};
+use std::path::{Path, PathBuf};
+use std::rc::Rc;
+
+use rust_nes_emulator::trace::{TraceFilter, Tracer};
+use rust_nes_emulator::{Cartridge, CpuState, Emulator};
+
+/// Lines shown before the first difference.
+const CONTEXT: usize = 5;
+/// The fields after the instruction text, in the order they appear in a line.
+const FIELDS: [&str; 7] = ["A:", "X:", "Y:", "P:", "SP:", "PPU:", "CYC:"];
+
+/// The trace goes here, so the test can read it back.
+#[derive(Clone, Default)]
+struct SharedBuffer(Rc<RefCell<Vec<u8>>>);
+
+impl Write for SharedBuffer {
+	fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+		self.0.borrow_mut().extend_from_slice(buf);
+		Ok(buf.len())
+	}
+
+	fn flush(&mut self) -> io::Result<()> {
+		Ok(())
+	}
+}
+
+/// Unofficial opcodes are marked with a `*` before the instruction: `C6BD  04 A9    *NOP $A9`.
+fn is_unofficial(line: &str) -> bool {
+	line.as_bytes().get(15) == Some(&b'*')
+}
+
+/// The fields of a line, and their values: `("A:", "00")`.
+fn fields(line: &str) -> Vec<(&'static str, &str)> {
+	let Some(start) = line.find(" A:") else {
+		return Vec::new();
+	};
+	let rest = &line[start + 1..];
+	let positions: Vec<(&'static str, usize)> = FIELDS.iter().filter_map(|field| Some((*field, rest.find(field)?))).collect();
+	positions.iter().enumerate().map(|(i, &(field, position))| {
+		let end = positions.get(i + 1).map_or(rest.len(), |&(_, next)| next);
+		(field, rest[position + field.len()..end].trim())
+	}).collect()
+}
+
+/// The line without the instruction text: the address, and the fields.
+fn without_text(line: &str) -> String {
+	let address = line.get(..4).unwrap_or(line);
+	let fields: Vec<String> = fields(line).iter().map(|(field, value)| format!("{}{}", field, value)).collect();
+	format!("{} {}", address, fields.join(" "))
+}
+
+/// Describe the first difference: the lines, the fields that differ, and the lines before.
+fn report(line: usize, expected: &[&str], actual: &[&str], stopped: &Option<String>) -> String {
+	let mut report = String::new();
+	writeln!(report, "nestest differs at line {} (of {}):", line + 1, expected.len()).unwrap();
+	writeln!(report, "  expected: {}", expected[line]).unwrap();
+	match actual.get(line) {
+		Some(actual) => {
+			writeln!(report, "  actual:   {}", actual).unwrap();
+			let actual_fields = fields(actual);
+			for (field, value) in fields(expected[line]) {
+				match actual_fields.iter().find(|(actual_field, _)| *actual_field == field) {
+					Some((_, actual_value)) if *actual_value == value => {}
+					Some((_, actual_value)) => writeln!(report, "  {} expected {}, got {}", field, value, actual_value).unwrap(),
+					None => writeln!(report, "  {} expected {}, got nothing", field, value).unwrap(),
+				}
+			}
+		}
+		None => writeln!(report, "  actual:   nothing, {}", stopped.as_deref().unwrap_or("the trace ended")).unwrap(),
+	}
+	let before = &actual[line.saturating_sub(CONTEXT)..line.min(actual.len())];
+	if !before.is_empty() {
+		writeln!(report, "The lines before:").unwrap();
+		for before in before {
+			writeln!(report, "  {}", before).unwrap();
+		}
+	}
+	report
+}
+
+struct Options {
+	rom: PathBuf,
+	log: PathBuf,
+	unofficial: bool,
+	skip_text: bool,
+}
+
+impl Options {
+	/// None if NESTEST_ROM is not set.
+	fn from_env() -> Option<Options> {
+		let rom = PathBuf::from(env::var_os("NESTEST_ROM")?);
+		let log = env::var_os("NESTEST_LOG").map_or_else(|| rom.with_file_name("nestest.log"), PathBuf::from);
+		let flag = |name: &str| env::var(name).is_ok_and(|value| value == "1");
+		Some(Options { rom, log, unofficial: flag("NESTEST_UNOFFICIAL"), skip_text: flag("NESTEST_SKIP_TEXT") })
+	}
+}
+
+fn read(path: &Path) -> Vec<u8> {
+	fs::read(path).unwrap_or_else(|err| panic!("Can't read {}: {}", path.display(), err))
+}
+
+#[test]
+fn nestest() {
+	let Some(options) = Options::from_env() else {
+		println!("NESTEST_ROM is not set, skipping. See tests/nestest.rs.");
+		return;
+	};
+
+	let log = String::from_utf8(read(&options.log)).expect("The log is not text");
+	let expected: Vec<&str> = log.lines()
+		.map(|line| line.trim_end())
+		.take_while(|line| options.unofficial || !is_unofficial(line))
+		.collect();
+
+	let mut emulator = Emulator::new(Cartridge::from_ines(&read(&options.rom)).unwrap());
+	emulator.set_cpu_state(&CpuState { pc: 0xC000, ..emulator.cpu_state() });
+	let buffer = SharedBuffer::default();
+	emulator.set_trace(Tracer::new(Box::new(buffer.clone()), TraceFilter::default()));
+
+	// A line for every instruction, so it runs as many instructions as the log has lines.
+	let mut stopped = None;
+	for _ in 0..expected.len() {
+		if let Err(err) = emulator.try_step_instruction() {
+			stopped = Some(format!("the CPU stopped: {}", err));
+			break;
+		}
+	}
+	let results = (emulator.peek(0x02), emulator.peek(0x03));
+	// The trace is written when it's dropped.
+	drop(emulator);
+
+	let trace = String::from_utf8(buffer.0.borrow().clone()).unwrap();
+	let actual: Vec<&str> = trace.lines().collect();
+	let same = |expected: &str, actual: &str| {
+		if options.skip_text { without_text(expected) == without_text(actual) } else { expected == actual }
+	};
+	if let Some(line) = (0..expected.len()).find(|&line| !actual.get(line).is_some_and(|actual| same(expected[line], actual))) {
+		panic!("{}", report(line, &expected, &actual, &stopped));
+	}
+
+	println!("{} lines match", expected.len());
+	assert_eq!(results.0, 0, "nestest failed an official opcode, the error code is at $02");
+	if options.unofficial {
+		assert_eq!(results.1, 0, "nestest failed an unofficial opcode, the error code is at $03");
+	}
+}
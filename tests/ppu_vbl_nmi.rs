@@ -0,0 +1,49 @@
+// Blargg's ppu_vbl_nmi (https://github.com/christopherpow/nes-test-roms/tree/master/ppu_vbl_nmi): the timing of the
+// VBlank flag, to the PPU dot, and of the NMI. 02-vbl_set_time reads PPUSTATUS around the dot the flag is set, which
+// is the race in ppu.rs. 04 to 08 time the NMI: turned on and off in VBlank, suppressed by the read, and taken by the
+// CPU, polled before the last cycle of the instruction.
+//
+// The ROMs aren't ours to keep here, so the test runs only with them:
+// PPU_VBL_NMI_DIR=path/to/ppu_vbl_nmi/rom_singles cargo test --release --test ppu_vbl_nmi -- --nocapture
+
+use std::env;
+use std::fs;
+use std::path::PathBuf;
+
+use rust_nes_emulator::harness::{BlarggStop, Harness};
+use rust_nes_emulator::{Cartridge, Emulator, StuckDetection};
+
+const ROMS: [&str; 8] = [
+	"01-vbl_basics.nes",
+	"02-vbl_set_time.nes",
+	"03-vbl_clear_time.nes",
+	"04-nmi_control.nes",
+	"05-nmi_timing.nes",
+	"06-suppression.nes",
+	"07-nmi_on_timing.nes",
+	"08-nmi_off_timing.nes",
+];
+/// The ROMs take a few seconds of emulated time each.
+const MAX_FRAMES: u64 = 60 * 60;
+
+#[test]
+fn ppu_vbl_nmi_test() {
+	let Some(dir) = env::var_os("PPU_VBL_NMI_DIR").map(PathBuf::from) else {
+		println!("PPU_VBL_NMI_DIR is not set, skipping. See tests/ppu_vbl_nmi.rs.");
+		return;
+	};
+
+	let mut failed = vec![];
+	for name in ROMS {
+		let path = dir.join(name);
+		let rom = fs::read(&path).unwrap_or_else(|err| panic!("Can't read {}: {}", path.display(), err));
+		let emulator = Emulator::new(Cartridge::from_ines(&rom).unwrap_or_else(|err| panic!("{}: {}", path.display(), err)));
+		let mut harness = Harness::new(emulator).max_frames(MAX_FRAMES).detect_stuck_loops(StuckDetection::default());
+		match harness.run_blargg() {
+			BlarggStop::Done(result) if result.passed() => println!("{}: {}", name, result),
+			BlarggStop::Done(result) => failed.push(format!("{}: {}", name, result)),
+			BlarggStop::Stopped(reason, result) => failed.push(format!("{}: {} ({:?})", name, reason, result)),
+		}
+	}
+	assert!(failed.is_empty(), "{}", failed.join("\n"));
+}
@@ -0,0 +1,247 @@
+// The SingleStepTests 6502 vectors (https://github.com/SingleStepTests/65x02): for every opcode, a JSON file with 10000
+// cases of a single instruction. Each case has the registers and memory before and after, and every bus access.
+//
+// The files are too big to keep here, so the test runs only with a checkout:
+// git clone https://github.com/SingleStepTests/65x02
+// SINGLE_STEP_TESTS=65x02/6502/v1 cargo test --release --test single_step -- --nocapture
+//
+// | Variable | Description |
+// |---|---|
+// | SINGLE_STEP_TESTS | The directory with the JSON files (00.json to ff.json), or the root of the checkout |
+// | SINGLE_STEP_OPCODES | Only these opcodes, in hex: `a9,69` |
+// | SINGLE_STEP_CYCLES | `1` to compare the bus accesses too, one by one. Otherwise only their count (the cycles) |
+//
+// Illegal opcodes are skipped, and so are opcodes the CPU doesn't implement yet.
+
+use std::env;
+use std::fmt::Write;
+use std::fs;
+use std::path::{Path, PathBuf};
+
+use rust_nes_emulator::cpu::disassembler::disassemble;
+use rust_nes_emulator::{decode_opcode, Bus, CpuError, CpuState, CPU};
+use serde_json::Value;
+
+/// Failures printed for each opcode. The rest are only counted.
+const PRINTED_FAILURES: usize = 5;
+
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+enum Access {
+	Read,
+	Write,
+}
+
+/// 64KB of RAM, and a log of every access.
+struct TestBus {
+	memory: Box<[u8; 65_536]>,
+	accesses: Vec<(u16, u8, Access)>,
+}
+
+impl Bus for TestBus {
+	fn read(&mut self, addr: u16) -> u8 {
+		let data = self.memory[addr as usize];
+		self.accesses.push((addr, data, Access::Read));
+		data
+	}
+
+	fn peek(&self, addr: u16) -> u8 {
+		self.memory[addr as usize]
+	}
+
+	fn write(&mut self, addr: u16, data: u8) {
+		self.memory[addr as usize] = data;
+		self.accesses.push((addr, data, Access::Write));
+	}
+}
+
+/// Registers and memory, before or after the instruction.
+struct Snapshot {
+	state: CpuState,
+	ram: Vec<(u16, u8)>,
+}
+
+impl Snapshot {
+	fn parse(json: &Value) -> Snapshot {
+		let register = |name: &str| json[name].as_u64().unwrap_or_else(|| panic!("Missing register {}", name));
+		Snapshot {
+			state: CpuState {
+				pc: register("pc") as u16,
+				a: register("a") as u8,
+				x: register("x") as u8,
+				y: register("y") as u8,
+				p: register("p") as u8,
+				s: register("s") as u8,
+				cycles: 0,
+			},
+			ram: json["ram"].as_array().expect("Missing ram").iter()
+				.map(|pair| (pair[0].as_u64().unwrap() as u16, pair[1].as_u64().unwrap() as u8))
+				.collect(),
+		}
+	}
+}
+
+struct Options {
+	dir: PathBuf,
+	opcodes: Vec<u8>,
+	compare_accesses: bool,
+}
+
+impl Options {
+	/// None if SINGLE_STEP_TESTS is not set.
+	fn from_env() -> Option<Options> {
+		let dir = PathBuf::from(env::var_os("SINGLE_STEP_TESTS")?);
+		// The root of the checkout, or the directory of the files.
+		let dir = if dir.join("6502/v1").is_dir() { dir.join("6502/v1") } else { dir };
+		let opcodes = match env::var("SINGLE_STEP_OPCODES") {
+			Ok(list) => list.split(',')
+				.map(|opcode| u8::from_str_radix(opcode.trim(), 16).unwrap_or_else(|_| panic!("Not an opcode: '{}'", opcode)))
+				.collect(),
+			Err(_) => (0..=255).collect(),
+		};
+		let compare_accesses = env::var("SINGLE_STEP_CYCLES").is_ok_and(|value| value == "1");
+		Some(Options { dir, opcodes, compare_accesses })
+	}
+}
+
+#[derive(Default)]
+struct Results {
+	passed: usize,
+	failed: usize,
+	/// Opcodes that were skipped, and why.
+	skipped: Vec<(u8, &'static str)>,
+}
+
+enum Outcome {
+	Passed,
+	/// The instruction, and what's different.
+	Failed(String),
+	Unimplemented,
+}
+
+/// Run a single case, and compare the CPU and memory after it.
+fn run_case(cpu: &mut CPU<TestBus>, case: &Value, options: &Options) -> Outcome {
+	let initial = Snapshot::parse(&case["initial"]);
+	let expected = Snapshot::parse(&case["final"]);
+	let expected_accesses: Vec<(u16, u8, Access)> = case["cycles"].as_array().expect("Missing cycles").iter()
+		.map(|cycle| {
+			let access = if cycle[2] == "write" { Access::Write } else { Access::Read };
+			(cycle[0].as_u64().unwrap() as u16, cycle[1].as_u64().unwrap() as u8, access)
+		})
+		.collect();
+
+	let bus = cpu.bus_mut();
+	bus.accesses.clear();
+	for &(addr, data) in &initial.ram {
+		bus.memory[addr as usize] = data;
+	}
+	cpu.set_state(&initial.state);
+	let instruction = disassemble(initial.state.pc, |addr| cpu.bus().peek(addr));
+
+	let cycles = match cpu.step() {
+		Ok(cycles) => cycles,
+		Err(CpuError::Unimplemented { .. }) => return Outcome::Unimplemented,
+		Err(err) => {
+			// Nothing ran, so there's nothing to compare.
+			clear(cpu.bus_mut(), &initial, &expected);
+			return Outcome::Failed(format!("{}\n  {}", instruction, err));
+		}
+	};
+
+	let mut diff = String::new();
+	let mut actual = cpu.state();
+	// B and the unused bit are not in the register, see `ProcessorStatusRegister::set_bits`.
+	actual.p |= 0b0011_0000;
+	let expected_state = CpuState { p: expected.state.p | 0b0011_0000, cycles: actual.cycles, ..expected.state };
+	if !actual.same_registers(&expected_state) {
+		writeln!(diff, "  registers: expected {}", expected_state).unwrap();
+		writeln!(diff, "             got      {}", actual).unwrap();
+	}
+	for &(addr, data) in &expected.ram {
+		let actual = cpu.bus().peek(addr);
+		if actual != data {
+			writeln!(diff, "  ${:04X}: expected {:02X}, got {:02X}", addr, data, actual).unwrap();
+		}
+	}
+	if cycles as usize != expected_accesses.len() {
+		writeln!(diff, "  cycles: expected {}, got {}", expected_accesses.len(), cycles).unwrap();
+	}
+	let accesses = &cpu.bus().accesses;
+	if options.compare_accesses && *accesses != expected_accesses {
+		writeln!(diff, "  bus: expected {:02X?}", expected_accesses).unwrap();
+		writeln!(diff, "       got      {:02X?}", accesses).unwrap();
+	}
+
+	clear(cpu.bus_mut(), &initial, &expected);
+	if diff.is_empty() {
+		Outcome::Passed
+	} else {
+		Outcome::Failed(format!("{}\n{}", instruction, diff.trim_end()))
+	}
+}
+
+/// Zero every address the case touched, for the next one. Clearing the whole 64KB for each case is too slow.
+fn clear(bus: &mut TestBus, initial: &Snapshot, expected: &Snapshot) {
+	let accessed = bus.accesses.iter().map(|&(addr, _, _)| addr);
+	let listed = initial.ram.iter().chain(&expected.ram).map(|&(addr, _)| addr);
+	for addr in accessed.chain(listed).collect::<Vec<u16>>() {
+		bus.memory[addr as usize] = 0;
+	}
+}
+
+fn run_file(path: &Path, opcode: u8, options: &Options, results: &mut Results) {
+	let json: Value = serde_json::from_str(&fs::read_to_string(path).unwrap()).unwrap();
+	let cases = json.as_array().expect("The file is not a list of cases");
+
+	let mut cpu = CPU::new(TestBus { memory: Box::new([0; 65_536]), accesses: Vec::new() });
+	let mut failed = 0;
+	for case in cases {
+		match run_case(&mut cpu, case, options) {
+			Outcome::Passed => results.passed += 1,
+			Outcome::Unimplemented => {
+				results.skipped.push((opcode, "not implemented"));
+				return;
+			}
+			Outcome::Failed(diff) => {
+				failed += 1;
+				if failed <= PRINTED_FAILURES {
+					println!("FAILED {}: {}", case["name"].as_str().unwrap_or("?"), diff);
+				}
+			}
+		}
+	}
+	if failed > PRINTED_FAILURES {
+		println!("... and {} more failures of opcode {:02X}", failed - PRINTED_FAILURES, opcode);
+	}
+	results.failed += failed;
+}
+
+#[test]
+fn single_step_tests() {
+	let Some(options) = Options::from_env() else {
+		println!("SINGLE_STEP_TESTS is not set, skipping. See tests/single_step.rs.");
+		return;
+	};
+
+	let mut results = Results::default();
+	for &opcode in &options.opcodes {
+		if decode_opcode(opcode).is_none() {
+			results.skipped.push((opcode, "illegal"));
+			continue;
+		}
+		let path = options.dir.join(format!("{:02x}.json", opcode));
+		if !path.is_file() {
+			results.skipped.push((opcode, "no file"));
+			continue;
+		}
+		run_file(&path, opcode, &options, &mut results);
+	}
+
+	for reason in ["not implemented", "no file"] {
+		let opcodes: Vec<String> = results.skipped.iter().filter(|(_, why)| *why == reason).map(|(opcode, _)| format!("{:02X}", opcode)).collect();
+		if !opcodes.is_empty() {
+			println!("Skipped, {}: {}", reason, opcodes.join(" "));
+		}
+	}
+	println!("{} passed, {} failed", results.passed, results.failed);
+	assert_eq!(results.failed, 0, "{} cases failed, see above", results.failed);
+}
@@ -0,0 +1,34 @@
+// The JavaScript API, in a JavaScript engine:
+// wasm-pack test --node -- --features wasm
+
+#![cfg(target_arch = "wasm32")]
+
+mod common;
+
+use rust_nes_emulator::wasm::WasmNes;
+use wasm_bindgen_test::wasm_bindgen_test;
+
+use common::nrom;
+
+#[wasm_bindgen_test]
+fn run_frame_test() {
+	// INC $10, JMP $8000
+	let mut nes = WasmNes::new(&nrom(&[0xE6, 0x10, 0x4C, 0x00, 0x80])).unwrap();
+	nes.set_buttons(0x01);
+
+	let pixels = nes.run_frame();
+	assert_eq!(pixels.0.len(), 256 * 240 * 4);
+	// Opaque.
+	assert!(pixels.0.chunks_exact(4).all(|pixel| pixel[3] == 0xFF));
+
+	let state = nes.save_state();
+	let after_save = nes.run_frame();
+	nes.load_state(&state).unwrap();
+	assert_eq!(nes.run_frame().0, after_save.0);
+	assert!(nes.load_state(&state[..10]).is_err());
+}
+
+#[wasm_bindgen_test]
+fn invalid_rom_test() {
+	assert!(WasmNes::new(b"not a rom").is_err());
+}